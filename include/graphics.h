@@ -0,0 +1,64 @@
+/* The C API of the `graphics` crate, built with its `capi` feature. See `src/capi.rs`. */
+
+#ifndef GRAPHICS_H
+#define GRAPHICS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct GraphicsScene GraphicsScene;
+
+/* Flags returned from the frame callback, and `graphics_scene_apply_patch`. */
+#define GRAPHICS_UPDATE_ENTITIES (1u << 0)
+#define GRAPHICS_UPDATE_CAMERA (1u << 1)
+#define GRAPHICS_UPDATE_LIGHTING (1u << 2)
+#define GRAPHICS_UPDATE_MESHES (1u << 3)
+#define GRAPHICS_UPDATE_EXIT (1u << 4)
+
+typedef uint32_t (*GraphicsFrameCallback)(GraphicsScene *scene, float dt, void *user_data);
+
+GraphicsScene *graphics_scene_new(void);
+void graphics_scene_free(GraphicsScene *scene);
+void graphics_scene_set_window(GraphicsScene *scene, const char *title, float width, float height);
+void graphics_scene_set_background(GraphicsScene *scene, float r, float g, float b);
+
+int64_t graphics_scene_add_mesh(GraphicsScene *scene, const float *positions, size_t num_vertices,
+                                const uint32_t *indices, size_t num_indices, bool smooth);
+int64_t graphics_scene_add_obj(GraphicsScene *scene, const uint8_t *bytes, size_t len);
+int64_t graphics_scene_add_sphere(GraphicsScene *scene, float radius);
+int64_t graphics_scene_add_box(GraphicsScene *scene, float len_x, float len_y, float len_z);
+
+int64_t graphics_scene_add_entity(GraphicsScene *scene, size_t mesh, float x, float y, float z,
+                                  float scale, float r, float g, float b);
+size_t graphics_scene_entity_count(const GraphicsScene *scene);
+void graphics_scene_remove_entity(GraphicsScene *scene, size_t entity);
+void graphics_scene_clear_entities(GraphicsScene *scene);
+void graphics_entity_set_position(GraphicsScene *scene, size_t entity, float x, float y, float z);
+void graphics_entity_set_orientation(GraphicsScene *scene, size_t entity, float w, float x,
+                                     float y, float z);
+void graphics_entity_set_scale(GraphicsScene *scene, size_t entity, float scale);
+void graphics_entity_set_color(GraphicsScene *scene, size_t entity, float r, float g, float b,
+                               float opacity);
+
+int64_t graphics_scene_add_light(GraphicsScene *scene, float x, float y, float z, float r,
+                                 float g, float b, float intensity);
+void graphics_light_set_position(GraphicsScene *scene, size_t light, float x, float y, float z);
+
+void graphics_camera_set_position(GraphicsScene *scene, float x, float y, float z);
+void graphics_camera_set_orientation(GraphicsScene *scene, float w, float x, float y, float z);
+void graphics_camera_set_fov(GraphicsScene *scene, float fov_y);
+
+int64_t graphics_scene_apply_patch(GraphicsScene *scene, const uint8_t *bytes, size_t len);
+
+void graphics_run(GraphicsScene *scene, GraphicsFrameCallback on_frame, void *user_data);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
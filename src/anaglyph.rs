@@ -0,0 +1,200 @@
+//! Red/cyan anaglyph stereo, for depth perception with cheap glasses, eg for outreach demos
+//! without VR hardware. When `Scene::anaglyph` is set, we render the scene once for each eye, to
+//! separate textures, then composite them: red from the left eye, and green and blue from the
+//! right.
+//!
+//! Eyes are offset along the camera's right axis, with asymmetric (off-axis) frustums, so objects
+//! at the convergence distance appear at the screen's depth. Only perspective projections are
+//! supported. Culling, shadows, and clustered lighting use the center camera. TAA history is
+//! shared between the eyes, which causes ghosting, so disable TAA while using this.
+
+use wgpu::{
+    BindGroup, BindingType, CommandEncoder, Device, FragmentState, RenderPassDescriptor,
+    RenderPipeline, ShaderStages, StoreOp, TextureFormat, TextureUsages, TextureView, VertexState,
+};
+
+use crate::{camera::Camera, graphics::RIGHT_VEC};
+
+#[derive(Clone, Debug)]
+pub struct Anaglyph {
+    /// The distance between the eyes, in world units.
+    pub eye_separation: f32,
+    /// The distance from the camera, in world units, at which objects appear at the screen's
+    /// depth. Closer objects appear in front of the screen, and farther ones behind it.
+    pub convergence: f32,
+}
+
+impl Default for Anaglyph {
+    fn default() -> Self {
+        Self {
+            eye_separation: 0.065,
+            convergence: 2.,
+        }
+    }
+}
+
+impl Anaglyph {
+    /// The camera for the left eye if `left`, or the right eye otherwise.
+    pub(crate) fn eye_camera(&self, camera: &Camera, left: bool) -> Camera {
+        let offset = if left { -0.5 } else { 0.5 } * self.eye_separation;
+
+        let mut result = camera.clone();
+        result.position += camera.orientation.rotate_vec(RIGHT_VEC) * offset;
+
+        // Shift the frustum so it converges with the other eye's at `convergence`. This adds a
+        // term to clip-space x proportional to view-space z, ie w.
+        if result.ortho_height.is_none() && self.convergence > 0. {
+            result.proj_mat.data[8] += result.proj_mat.data[0] * offset / self.convergence;
+        }
+
+        result
+    }
+}
+
+/// Textures for each eye, and the compositing pass.
+pub(crate) struct AnaglyphRenderer {
+    pub width: u32,
+    pub height: u32,
+    pub left: TextureView,
+    pub right: TextureView,
+    pipeline: RenderPipeline,
+    bind_group: BindGroup,
+}
+
+fn create_eye_view(device: &Device, format: TextureFormat, width: u32, height: u32) -> TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Anaglyph eye texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+
+    texture.create_view(&Default::default())
+}
+
+impl AnaglyphRenderer {
+    /// `format` is that of the main pass's color target; `width` and `height` are its size.
+    pub fn new(device: &Device, format: TextureFormat, width: u32, height: u32) -> Self {
+        let width = width.max(1);
+        let height = height.max(1);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Anaglyph shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("anaglyph.wgsl").into()),
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(0), texture_entry(1)],
+            label: Some("Anaglyph bind group layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Anaglyph pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Anaglyph pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let left = create_eye_view(device, format, width, height);
+        let right = create_eye_view(device, format, width, height);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&left),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&right),
+                },
+            ],
+            label: Some("Anaglyph bind group"),
+        });
+
+        Self {
+            width,
+            height,
+            left,
+            right,
+            pipeline,
+            bind_group,
+        }
+    }
+
+    /// Composite the eye views over the 3D viewport of `target`. `viewport` is (x, y, width,
+    /// height), in pixels.
+    pub fn encode(
+        &self,
+        encoder: &mut CommandEncoder,
+        target: &TextureView,
+        viewport: (f32, f32, f32, f32),
+    ) {
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Anaglyph render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        let (x, y, width, height) = viewport;
+        rpass.set_viewport(x, y, width, height, 0., 1.);
+
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
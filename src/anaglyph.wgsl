@@ -0,0 +1,33 @@
+// Anaglyph compositing: takes red from the left eye's view, and green and blue from the right
+// eye's, for viewing with red/cyan glasses.
+
+@group(0) @binding(0)
+var left_tex: texture_2d<f32>;
+@group(0) @binding(1)
+var right_tex: texture_2d<f32>;
+
+struct VertexOut {
+    @builtin(position) posit: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> VertexOut {
+    // A single triangle that covers the viewport.
+    var uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+
+    var result: VertexOut;
+    result.posit = vec4<f32>(uv * vec2<f32>(2., -2.) + vec2<f32>(-1., 1.), 0., 1.);
+
+    return result;
+}
+
+@fragment
+fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+    // Eye views are rendered to the same viewport as the output, so pixels correspond.
+    let pixel = vec2<i32>(in.posit.xy);
+
+    let left = textureLoad(left_tex, pixel, 0);
+    let right = textureLoad(right_tex, pixel, 0);
+
+    return vec4<f32>(left.r, right.g, right.b, 1.);
+}
@@ -0,0 +1,258 @@
+//! Keyframe animation of the camera and point lights, eg for flythroughs or presentations. The
+//! engine plays back `Scene::timeline` each frame, interpolating between keyframes. `timeline_ui`
+//! is an optional egui widget for playing, scrubbing, and recording keyframes, so applications
+//! get basic authoring without writing GUI code. It requires the `gui` feature.
+
+#[cfg(feature = "gui")]
+use egui::Ui;
+use lin_alg::f32::{Quaternion, Vec3};
+
+#[cfg(feature = "gui")]
+use crate::types::Scene;
+use crate::{camera::Camera, lighting::PointLight, math};
+
+/// Keyframes closer together than this, in seconds, are considered to be at the same time.
+const TIME_EPS: f32 = 0.001;
+
+#[derive(Clone, Debug)]
+pub struct CameraKeyframe {
+    /// In seconds, from the start of the timeline.
+    pub time: f32,
+    pub position: Vec3,
+    pub orientation: Quaternion,
+}
+
+#[derive(Clone, Debug)]
+pub struct LightKeyframe {
+    /// In seconds, from the start of the timeline.
+    pub time: f32,
+    pub position: Vec3,
+    pub diffuse_intensity: f32,
+    pub specular_intensity: f32,
+}
+
+#[derive(Clone, Debug)]
+/// Camera and light keyframes, and the playback state. Between keyframes, we interpolate
+/// linearly, and orientations spherically; before the first and after the last, we hold the
+/// nearest one. The camera and lights follow the timeline while it's playing, or when `time`
+/// changes, eg from scrubbing; otherwise they can be moved freely.
+pub struct Timeline {
+    /// Sorted by time.
+    pub camera: Vec<CameraKeyframe>,
+    /// Keyframes for each point light, by index in `Lighting::point_lights`, each sorted by time.
+    /// Lights without keyframes aren't animated.
+    pub lights: Vec<Vec<LightKeyframe>>,
+    /// The playback position, in seconds.
+    pub time: f32,
+    /// The timeline's length, in seconds. Playback stops, or loops, here.
+    pub length: f32,
+    pub playing: bool,
+    pub looping: bool,
+    /// The time the camera and lights were last set from.
+    applied_time: Option<f32>,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self {
+            camera: Vec::new(),
+            lights: Vec::new(),
+            time: 0.,
+            length: 10.,
+            playing: false,
+            looping: false,
+            applied_time: None,
+        }
+    }
+}
+
+/// The keyframes on either side of `time`, and the interpolation amount between them.
+fn surrounding<T>(
+    keyframes: &[T],
+    time: f32,
+    key_time: impl Fn(&T) -> f32,
+) -> Option<(&T, &T, f32)> {
+    let first = keyframes.first()?;
+    let last = keyframes.last()?;
+
+    if time <= key_time(first) {
+        return Some((first, first, 0.));
+    }
+    if time >= key_time(last) {
+        return Some((last, last, 0.));
+    }
+
+    let i = keyframes.partition_point(|k| key_time(k) <= time);
+    let (a, b) = (&keyframes[i - 1], &keyframes[i]);
+
+    let span = key_time(b) - key_time(a);
+    let amount = if span > 0. {
+        (time - key_time(a)) / span
+    } else {
+        0.
+    };
+
+    Some((a, b, amount))
+}
+
+/// Insert a keyframe in time order, replacing one at the same time.
+fn insert_keyframe<T>(keyframes: &mut Vec<T>, keyframe: T, key_time: impl Fn(&T) -> f32) {
+    let time = key_time(&keyframe);
+
+    match keyframes
+        .iter()
+        .position(|k| (key_time(k) - time).abs() < TIME_EPS)
+    {
+        Some(i) => keyframes[i] = keyframe,
+        None => {
+            let i = keyframes.partition_point(|k| key_time(k) < time);
+            keyframes.insert(i, keyframe);
+        }
+    }
+}
+
+impl Timeline {
+    /// The time of the last keyframe, in seconds.
+    pub fn end(&self) -> f32 {
+        let camera = self.camera.last().map(|k| k.time);
+        let lights = self.lights.iter().filter_map(|l| l.last().map(|k| k.time));
+
+        camera.into_iter().chain(lights).fold(0., f32::max)
+    }
+
+    /// Add a keyframe of the camera at the current time, replacing one there.
+    pub fn key_camera(&mut self, camera: &Camera) {
+        let keyframe = CameraKeyframe {
+            time: self.time,
+            position: camera.position,
+            orientation: camera.orientation,
+        };
+
+        insert_keyframe(&mut self.camera, keyframe, |k| k.time);
+    }
+
+    /// Add a keyframe of each light at the current time, replacing ones there.
+    pub fn key_lights(&mut self, lights: &[PointLight]) {
+        if self.lights.len() < lights.len() {
+            self.lights.resize(lights.len(), Vec::new());
+        }
+
+        for (keyframes, light) in self.lights.iter_mut().zip(lights) {
+            let keyframe = LightKeyframe {
+                time: self.time,
+                position: light.position,
+                diffuse_intensity: light.diffuse_intensity,
+                specular_intensity: light.specular_intensity,
+            };
+
+            insert_keyframe(keyframes, keyframe, |k| k.time);
+        }
+    }
+
+    /// Remove camera and light keyframes at the current time.
+    pub fn remove_keys(&mut self) {
+        let time = self.time;
+        self.camera.retain(|k| (k.time - time).abs() >= TIME_EPS);
+        for keyframes in &mut self.lights {
+            keyframes.retain(|k| (k.time - time).abs() >= TIME_EPS);
+        }
+    }
+
+    /// Advance playback by `dt` seconds, and set the camera and lights from keyframes if the time
+    /// changed. Returns true if they were set, in which case their GPU data must be updated.
+    pub(crate) fn update(
+        &mut self,
+        dt: f32,
+        camera: &mut Camera,
+        lights: &mut [PointLight],
+    ) -> bool {
+        if self.playing {
+            self.time += dt;
+
+            if self.time >= self.length {
+                if self.looping && self.length > 0. {
+                    self.time %= self.length;
+                } else {
+                    self.time = self.length;
+                    self.playing = false;
+                }
+            }
+        }
+
+        if self.applied_time == Some(self.time) {
+            return false;
+        }
+        self.applied_time = Some(self.time);
+
+        if let Some((a, b, amount)) = surrounding(&self.camera, self.time, |k| k.time) {
+            camera.position = a.position + (b.position - a.position) * amount;
+            camera.orientation = math::slerp(a.orientation, b.orientation, amount);
+        }
+
+        for (keyframes, light) in self.lights.iter().zip(lights) {
+            if let Some((a, b, amount)) = surrounding(keyframes, self.time, |k| k.time) {
+                light.position = a.position + (b.position - a.position) * amount;
+                light.diffuse_intensity =
+                    a.diffuse_intensity + (b.diffuse_intensity - a.diffuse_intensity) * amount;
+                light.specular_intensity =
+                    a.specular_intensity + (b.specular_intensity - a.specular_intensity) * amount;
+            }
+        }
+
+        !self.camera.is_empty() || self.lights.iter().any(|l| !l.is_empty())
+    }
+}
+
+/// A timeline widget for `Scene::timeline`: play and pause, a slider to scrub, and buttons to
+/// record or remove keyframes of the current camera and lights. Call this from the GUI handler, eg
+/// in a panel. Changes apply from the next frame, without setting `EngineUpdates`.
+#[cfg(feature = "gui")]
+pub fn timeline_ui(ui: &mut Ui, scene: &mut Scene) {
+    let timeline = &mut scene.timeline;
+
+    ui.horizontal(|ui| {
+        let label = if timeline.playing { "Pause" } else { "Play" };
+        if ui.button(label).clicked() {
+            // Restart from the beginning if we're at the end.
+            if !timeline.playing && timeline.time >= timeline.length {
+                timeline.time = 0.;
+            }
+            timeline.playing = !timeline.playing;
+        }
+
+        ui.add(
+            egui::Slider::new(&mut timeline.time, 0.0..=timeline.length)
+                .suffix(" s")
+                .text("Time"),
+        );
+
+        ui.label("Length:");
+        ui.add(
+            egui::DragValue::new(&mut timeline.length)
+                .range(0.0..=f32::MAX)
+                .speed(0.1)
+                .suffix(" s"),
+        );
+
+        ui.checkbox(&mut timeline.looping, "Loop");
+    });
+
+    ui.horizontal(|ui| {
+        if ui.button("Key camera").clicked() {
+            timeline.key_camera(&scene.camera);
+        }
+        if ui.button("Key lights").clicked() {
+            timeline.key_lights(&scene.lighting.point_lights);
+        }
+        if ui.button("Remove keys").clicked() {
+            timeline.remove_keys();
+        }
+
+        let num_light_keys: usize = timeline.lights.iter().map(|l| l.len()).sum();
+        ui.label(format!(
+            "{} camera, {} light keyframes",
+            timeline.camera.len(),
+            num_light_keys
+        ));
+    });
+}
@@ -0,0 +1,100 @@
+//! Area light proxies: entities that light their surroundings, eg lamps, or screens, including ones
+//! that move with other objects. Add proxies to `Scene::area_lights`. Each frame, the engine
+//! approximates each proxy with a grid of point lights over its entity's bounds, including group
+//! transforms, and uploads them after `Lighting::point_lights`.
+//!
+//! These lights don't cast shadows, and aren't added to `point_lights`. They're inside the entity,
+//! so light it from behind its surfaces; it's usually best lit mostly by ambient light, using
+//! `Entity::lighting_factors`.
+
+use lin_alg::f32::Vec3;
+
+use crate::{
+    lighting::{LightType, PointLight},
+    types::Scene,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct AreaLight {
+    /// Index into `Scene::entities`. Proxies of entities that don't exist, or are hidden by a
+    /// group, are ignored.
+    pub entity: usize,
+    /// Interpreted like `PointLight::diffuse_color`. If `None`, the entity's color is used,
+    /// including group tints.
+    pub color: Option<(f32, f32, f32)>,
+    /// The total intensity, split between the proxy's lights. This is used for both diffuse and
+    /// specular lighting.
+    pub intensity: f32,
+    /// The number of lights along the longest, and second longest axes of the entity's bounds,
+    /// evenly spaced. Eg (1, 1) for a single light at its center, or (4, 2) for a wide screen.
+    pub grid: (u32, u32),
+}
+
+impl AreaLight {
+    pub fn new(entity: usize, intensity: f32, grid: (u32, u32)) -> Self {
+        Self {
+            entity,
+            color: None,
+            intensity,
+            grid,
+        }
+    }
+}
+
+/// Point lights approximating the scene's area light proxies, in order.
+pub(crate) fn lights(scene: &mut Scene) -> Vec<PointLight> {
+    let mut result = Vec::new();
+
+    for i in 0..scene.area_lights.len() {
+        let proxy = scene.area_lights[i];
+
+        let Some(entity) = scene.entity_in_world(proxy.entity) else {
+            continue;
+        };
+        let Some(bounds) = scene.mesh_bounds(entity.mesh) else {
+            continue;
+        };
+
+        let (nx, ny) = (proxy.grid.0.max(1), proxy.grid.1.max(1));
+        let (r, g, b) = proxy.color.unwrap_or(entity.color);
+        let color = [r, g, b, 1.];
+        let intensity = proxy.intensity / (nx * ny) as f32;
+
+        // Local axes of the bounds, longest first.
+        let extent = bounds.max - bounds.min;
+        let mut axes = [
+            (extent.x, Vec3::new(extent.x, 0., 0.)),
+            (extent.y, Vec3::new(0., extent.y, 0.)),
+            (extent.z, Vec3::new(0., 0., extent.z)),
+        ];
+        axes.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let center = bounds.center();
+
+        for ix in 0..nx {
+            for iy in 0..ny {
+                // From -0.5 to 0.5 across the bounds, at the center of each grid cell.
+                let fx = (ix as f32 + 0.5) / nx as f32 - 0.5;
+                let fy = (iy as f32 + 0.5) / ny as f32 - 0.5;
+                let local = center + axes[0].1 * fx + axes[1].1 * fy;
+
+                result.push(PointLight {
+                    type_: LightType::Omnidirectional,
+                    position: entity.orientation.rotate_vec(local * entity.scale) + entity.position,
+                    diffuse_color: color,
+                    specular_color: color,
+                    diffuse_intensity: intensity,
+                    specular_intensity: intensity,
+                    casts_shadow: false,
+                });
+            }
+        }
+    }
+
+    result
+}
+
+/// True if these lights serialize the same, so don't need to be uploaded again.
+pub(crate) fn same(a: &[PointLight], b: &[PointLight]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.to_bytes() == b.to_bytes())
+}
@@ -0,0 +1,179 @@
+//! Gradient backgrounds, which read better in screenshots than a flat color. When
+//! `Scene::background_gradient` is set, we draw it over the 3D viewport before the main pass, as a
+//! fullscreen triangle, and the main pass draws over it instead of clearing.
+//!
+//! Environment probes still capture `Scene::background_color`.
+
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindingType, Buffer, BufferBindingType, BufferUsages, CommandEncoder, Device,
+    FragmentState, RenderPassDescriptor, RenderPipeline, ShaderStages, StoreOp,
+    SurfaceConfiguration, VertexState,
+};
+
+use crate::{
+    pass::PassContext,
+    types::{ColorSettings, F32_SIZE, VEC4_SIZE},
+};
+
+/// Top and bottom colors; the vignette is stored in the top color's W.
+const BACKGROUND_PARAMS_SIZE: usize = 2 * VEC4_SIZE;
+
+#[derive(Clone, Debug, PartialEq)]
+/// A vertical gradient, drawn behind the scene in place of `Scene::background_color`. Colors are
+/// interpreted according to `ColorSettings::input_space`, as with entity colors.
+pub struct BackgroundGradient {
+    /// The color at the top of the 3D viewport.
+    pub top: (f32, f32, f32),
+    /// The color at the bottom of the 3D viewport.
+    pub bottom: (f32, f32, f32),
+    /// How much the corners are darkened, from 0 (none) to 1 (black). Eg 0.3 for a subtle
+    /// vignette.
+    pub vignette: f32,
+}
+
+impl Default for BackgroundGradient {
+    fn default() -> Self {
+        Self {
+            top: (0.85, 0.85, 0.85),
+            bottom: (0.55, 0.55, 0.55),
+            vignette: 0.,
+        }
+    }
+}
+
+impl BackgroundGradient {
+    /// `encode_srgb` is true if colors must be encoded, since the surface isn't sRGB.
+    fn to_bytes(&self, color: &ColorSettings, encode_srgb: bool) -> [u8; BACKGROUND_PARAMS_SIZE] {
+        let mut result = [0; BACKGROUND_PARAMS_SIZE];
+
+        let [r0, g0, b0] = color.unlit_color([self.top.0, self.top.1, self.top.2], encode_srgb);
+        let [r1, g1, b1] =
+            color.unlit_color([self.bottom.0, self.bottom.1, self.bottom.2], encode_srgb);
+
+        let values = [r0, g0, b0, self.vignette.clamp(0., 1.), r1, g1, b1, 1.];
+        for (i, v) in values.iter().enumerate() {
+            result[i * F32_SIZE..(i + 1) * F32_SIZE].clone_from_slice(&v.to_ne_bytes());
+        }
+
+        result
+    }
+}
+
+/// Draws the gradient background.
+pub(crate) struct BackgroundRenderer {
+    pipeline: RenderPipeline,
+    params_buf: Buffer,
+    bind_group: BindGroup,
+}
+
+impl BackgroundRenderer {
+    pub fn new(device: &Device, surface_cfg: &SurfaceConfiguration) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Background shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("background.wgsl").into()),
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("Background bind group layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Background pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Background pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_cfg.format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let params_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Background params buffer"),
+            contents: &[0; BACKGROUND_PARAMS_SIZE],
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params_buf.as_entire_binding(),
+            }],
+            label: Some("Background bind group"),
+        });
+
+        Self {
+            pipeline,
+            params_buf,
+            bind_group,
+        }
+    }
+
+    /// Draw the gradient over the 3D viewport of `ctx`'s output texture.
+    pub fn encode(
+        &self,
+        ctx: &PassContext,
+        encoder: &mut CommandEncoder,
+        gradient: &BackgroundGradient,
+        color: &ColorSettings,
+        encode_srgb: bool,
+    ) {
+        ctx.queue
+            .write_buffer(&self.params_buf, 0, &gradient.to_bytes(color, encode_srgb));
+
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Background render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: ctx.output_texture,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        let (x, y, width, height) = ctx.viewport;
+        rpass.set_viewport(x, y, width, height, 0., 1.);
+
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,47 @@
+// A vertical gradient background, with an optional vignette, drawn over the 3D viewport before
+// the main pass.
+
+struct BackgroundParams {
+    // Converted to the surface's encoding. W of `top` is the vignette strength.
+    top: vec4<f32>,
+    bottom: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> params: BackgroundParams;
+
+struct VertexOut {
+    @builtin(position) posit: vec4<f32>,
+    // 0 at the top left of the viewport, and 1 at the bottom right.
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> VertexOut {
+    // A single triangle that covers the viewport.
+    var uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+
+    var result: VertexOut;
+    result.posit = vec4<f32>(uv * vec2<f32>(2., -2.) + vec2<f32>(-1., 1.), 0., 1.);
+    result.uv = uv;
+
+    return result;
+}
+
+// Noise in [0, 1), to break up banding in smooth gradients.
+fn hash(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2<f32>(12.9898, 78.233))) * 43758.547);
+}
+
+@fragment
+fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+    var color = mix(params.top.rgb, params.bottom.rgb, in.uv.y);
+
+    // 0 at the center, and 1 at the corners.
+    let dist = length(in.uv - 0.5) * sqrt(2.);
+    color *= 1. - params.top.w * dist * dist;
+
+    color += (hash(in.posit.xy) - 0.5) / 255.;
+
+    return vec4<f32>(color, 1.);
+}
@@ -0,0 +1,136 @@
+//! Reading and writing the little-endian binary encodings of scene patches and serialized BVHs.
+//! This data may come from another process, or a file, so reads return an error instead of
+//! panicking if it ends early.
+
+use lin_alg::f32::{Quaternion, Vec3};
+
+/// Appends little-endian values to a buffer.
+#[derive(Default)]
+pub(crate) struct Writer(pub Vec<u8>);
+
+impl Writer {
+    pub fn bytes(&mut self, v: &[u8]) {
+        self.0.extend_from_slice(v);
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    pub fn i32(&mut self, v: i32) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, v: f32) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, v: bool) {
+        self.u8(v as u8);
+    }
+
+    pub fn option_u32(&mut self, v: Option<u32>) {
+        self.bool(v.is_some());
+        if let Some(v) = v {
+            self.u32(v);
+        }
+    }
+
+    pub fn vec3(&mut self, v: Vec3) {
+        for c in [v.x, v.y, v.z] {
+            self.f32(c);
+        }
+    }
+
+    pub fn quaternion(&mut self, v: Quaternion) {
+        for c in [v.w, v.x, v.y, v.z] {
+            self.f32(c);
+        }
+    }
+}
+
+/// Reads little-endian values from a buffer.
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// What the data is, for errors, eg "Patch".
+    name: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8], name: &'static str) -> Self {
+        Self { data, pos: 0, name }
+    }
+
+    /// The number of bytes not yet read.
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos + len;
+        let Some(result) = self.data.get(self.pos..end) else {
+            return Err(format!("{} ended unexpectedly", self.name));
+        };
+        self.pos = end;
+
+        Ok(result)
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.take()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn option_u32(&mut self) -> Result<Option<u32>, String> {
+        Ok(if self.bool()? {
+            Some(self.u32()?)
+        } else {
+            None
+        })
+    }
+
+    pub fn vec3(&mut self) -> Result<Vec3, String> {
+        Ok(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    pub fn quaternion(&mut self) -> Result<Quaternion, String> {
+        Ok(Quaternion::new(
+            self.f32()?,
+            self.f32()?,
+            self.f32()?,
+            self.f32()?,
+        ))
+    }
+}
@@ -0,0 +1,107 @@
+//! A pool of GPU buffers, for buffers that are rebuilt often with varying sizes, eg the instance
+//! buffer when entities are added. Sizes are rounded up to powers of two, within the device's
+//! limits, so a rebuilt buffer
+//! usually fits in the existing one, and is written in place; when it doesn't, a freed buffer of
+//! the right size class is reused if available. This reduces allocation churn and VRAM
+//! fragmentation, at the cost of up to twice the memory per buffer.
+//!
+//! Pooled buffers may be larger than their contents, so their size can't be used to infer how many
+//! items they hold.
+
+use std::collections::HashMap;
+
+use wgpu::{Buffer, BufferUsages, Device, Queue};
+
+use crate::limits;
+
+/// The smallest size class, in bytes.
+const MIN_SIZE: u64 = 256;
+
+/// Freed buffers kept for each size class and usage. Others are dropped.
+const MAX_FREE_PER_CLASS: usize = 2;
+
+#[derive(Default)]
+pub(crate) struct BufferPool {
+    /// Freed buffers, by usage and size.
+    free: HashMap<(BufferUsages, u64), Vec<Buffer>>,
+}
+
+impl BufferPool {
+    /// The size of buffers allocated to hold `size` bytes.
+    pub fn size_class(size: u64) -> u64 {
+        size.max(MIN_SIZE).next_power_of_two()
+    }
+
+    /// A buffer with room for at least `size` bytes; a freed one if available.
+    pub fn acquire(
+        &mut self,
+        device: &Device,
+        size: u64,
+        usage: BufferUsages,
+        label: &str,
+    ) -> Buffer {
+        // Don't round up past the device's limits. Storage buffers are usually bound whole, so
+        // shouldn't be rounded past the storage binding limit either.
+        let limit = if usage.contains(BufferUsages::STORAGE) {
+            limits::max_storage_size(device)
+        } else {
+            device.limits().max_buffer_size
+        };
+        let size = Self::size_class(size).min(limit.max(size));
+
+        if let Some(buf) = self
+            .free
+            .get_mut(&(usage, size))
+            .and_then(|bufs| bufs.pop())
+        {
+            return buf;
+        }
+
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Return a buffer to the pool, for reuse. Buffers not allocated by the pool, and ones past
+    /// the limit for their size class, are dropped.
+    pub fn release(&mut self, buf: Buffer) {
+        let size = buf.size();
+        if size != Self::size_class(size) {
+            return;
+        }
+
+        let bufs = self.free.entry((buf.usage(), size)).or_default();
+        if bufs.len() < MAX_FREE_PER_CLASS {
+            bufs.push(buf);
+        }
+    }
+
+    /// Write `data` to the start of `buf`, replacing it with a larger buffer from the pool if it
+    /// doesn't fit. `usage` must include `COPY_DST`. Returns true if the buffer was replaced, eg so
+    /// bind groups using it can be recreated.
+    pub fn write(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        buf: &mut Buffer,
+        data: &[u8],
+        usage: BufferUsages,
+        label: &str,
+    ) -> bool {
+        let fits = data.len() as u64 <= buf.size() && buf.usage() == usage;
+
+        if !fits {
+            let new = self.acquire(device, data.len() as u64, usage, label);
+            self.release(std::mem::replace(buf, new));
+        }
+
+        if !data.is_empty() {
+            queue.write_buffer(buf, 0, data);
+        }
+
+        !fits
+    }
+}
@@ -4,11 +4,29 @@ use core::f32::consts::TAU;
 
 use lin_alg::f32::{Mat4, Quaternion, Vec3};
 
-use crate::types::{MAT4_SIZE, VEC3_UNIFORM_SIZE};
+use crate::{
+    graphics::{FWD_VEC, RIGHT_VEC, UP_VEC},
+    types::{F32_SIZE, MAT4_SIZE, VEC3_UNIFORM_SIZE, VEC4_SIZE},
+};
 
 // cam size is only the parts we pass to the shader.
-// For each of the 4 matrices in the camera, plus a padded vec3 for position.
-pub const CAMERA_SIZE: usize = MAT4_SIZE + VEC3_UNIFORM_SIZE;
+// The projection-view matrix, plus padded vec3s for position, and the forward, right, and up
+// vectors.
+pub const CAMERA_SIZE: usize = MAT4_SIZE + 4 * VEC3_UNIFORM_SIZE;
+
+/// The time portion of the camera uniform, following the clip plane.
+pub(crate) const TIME_SIZE: usize = VEC4_SIZE;
+
+/// Seconds since the engine started, and the frame's duration in seconds, for the camera uniform.
+/// Eg for animated materials in shader extensions.
+pub(crate) fn time_bytes(time: f32, dt: f32) -> [u8; TIME_SIZE] {
+    let mut result = [0; TIME_SIZE];
+
+    result[0..F32_SIZE].clone_from_slice(&time.to_ne_bytes());
+    result[F32_SIZE..2 * F32_SIZE].clone_from_slice(&dt.to_ne_bytes());
+
+    result
+}
 
 #[derive(Clone, Debug)]
 pub struct Camera {
@@ -20,8 +38,16 @@ pub struct Camera {
     /// we adjust with move keys.
     pub position: Vec3,
     pub orientation: Quaternion,
+    /// If set, use an orthographic projection with this view height, in world units, instead of
+    /// a perspective one; `fov_y` is unused. Eg for 2D; see `Camera::new_2d`. Clustered lighting
+    /// and deferred shading assume a perspective projection.
+    pub ortho_height: Option<f32>,
     /// We store the projection matrix here since it only changes when we change the camera cfg.
     pub proj_mat: Mat4,
+    /// A sub-pixel offset of the projection, in pixels, with +y down, eg for supersampling by
+    /// accumulating frames rendered with different offsets. This is applied when writing the camera
+    /// uniform, so `proj_mat`, picking, and culling are unaffected. TAA adds its own jitter to it.
+    pub jitter: (f32, f32),
 }
 
 impl Camera {
@@ -31,7 +57,17 @@ impl Camera {
         let proj_view = self.proj_mat.clone() * self.view_mat();
 
         result[0..MAT4_SIZE].clone_from_slice(&proj_view.to_bytes());
-        result[MAT4_SIZE..CAMERA_SIZE].clone_from_slice(&self.position.to_bytes_uniform());
+
+        let vecs = [
+            self.position,
+            self.orientation.rotate_vec(FWD_VEC),
+            self.orientation.rotate_vec(RIGHT_VEC),
+            self.orientation.rotate_vec(UP_VEC),
+        ];
+        for (i, v) in vecs.iter().enumerate() {
+            let start = MAT4_SIZE + i * VEC3_UNIFORM_SIZE;
+            result[start..start + VEC3_UNIFORM_SIZE].clone_from_slice(&v.to_bytes_uniform());
+        }
 
         result
     }
@@ -39,7 +75,10 @@ impl Camera {
     /// Updates the projection matrix based on the projection parameters.
     /// Run this after updating the parameters.
     pub fn update_proj_mat(&mut self) {
-        self.proj_mat = Mat4::new_perspective_lh(self.fov_y, self.aspect, self.near, self.far);
+        self.proj_mat = match self.ortho_height {
+            Some(height) => orthographic_lh(height * self.aspect, height, self.near, self.far),
+            None => Mat4::new_perspective_lh(self.fov_y, self.aspect, self.near, self.far),
+        };
     }
 
     /// Calculate the view matrix: This is a translation of the negative coordinates of the camera's
@@ -50,6 +89,10 @@ impl Camera {
     }
 
     pub fn view_size(&self, far: bool) -> (f32, f32) {
+        if let Some(height) = self.ortho_height {
+            return (height * self.aspect, height);
+        }
+
         // Calculate the projected window width and height, using basic trig.
         let dist = if far { self.far } else { self.near };
 
@@ -68,10 +111,41 @@ impl Default for Camera {
             aspect: 4. / 3., // width / height.
             near: 0.5,
             far: 60.,
+            ortho_height: None,
             proj_mat: Mat4::new_identity(),
+            jitter: (0., 0.),
         };
 
         result.update_proj_mat();
         result
     }
 }
+
+/// An orthographic projection centered on the view axis, mapping depths from `near` to `far` to
+/// 0 to 1, as with `new_perspective_lh`. Matrices are column-major.
+fn orthographic_lh(width: f32, height: f32, near: f32, far: f32) -> Mat4 {
+    let depth = far - near;
+
+    let mut result = Mat4::new_identity();
+    result.data[0] = 2. / width;
+    result.data[5] = 2. / height;
+    result.data[10] = 1. / depth;
+    result.data[14] = -near / depth;
+
+    result
+}
+
+/// Project a point in world space to clip space, returning its normalized device coordinates,
+/// and W. Returns `None` if it's behind the camera.
+pub(crate) fn project(proj_view: &Mat4, posit: Vec3) -> Option<(f32, f32, f32)> {
+    // Matrix data is column-major.
+    let d = &proj_view.data;
+    let row = |i: usize| d[i] * posit.x + d[4 + i] * posit.y + d[8 + i] * posit.z + d[12 + i];
+
+    let w = row(3);
+    if w <= 0. {
+        return None;
+    }
+
+    Some((row(0) / w, row(1) / w, w))
+}
@@ -0,0 +1,22 @@
+// The camera uniform, bound at `@group(0) @binding(0)` in the main shader. Written from
+// `Camera::to_bytes`, followed by the TAA data, the clip plane, and the time.
+
+struct Camera {
+    proj_view: mat4x4<f32>,
+    position: vec4<f32>,
+    // The camera's basis vectors, in world space; w is unused.
+    forward: vec4<f32>,
+    right: vec4<f32>,
+    up: vec4<f32>,
+    // The fields below are used for temporal anti-aliasing. When it's disabled, jitter is only
+    // `Camera::jitter`, and we don't use the velocity we compute from `prev_proj_view`.
+    prev_proj_view: mat4x4<f32>,
+    // In NDC; only x and y are used.
+    jitter: vec4<f32>,
+    // Fragments in front of this plane, ie where dot(xyz, position) + w > 0, are clipped, eg by a
+    // slice plane. All zero if nothing is clipped.
+    clip_plane: vec4<f32>,
+    // x: seconds since the engine started. y: the frame's duration, in seconds. Eg for animated
+    // materials in shader extensions. Zero in environment probe captures.
+    time: vec4<f32>,
+}
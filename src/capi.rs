@@ -0,0 +1,501 @@
+//! A C API, for driving the engine from other languages, eg Python with cffi or ctypes, without
+//! writing Rust. This requires the `capi` feature. Build a shared library with
+//! `cargo rustc --release --features capi --crate-type cdylib`; `include/graphics.h` declares
+//! these functions.
+//!
+//! A scene is created with `graphics_scene_new`, and populated with meshes, entities, and lights.
+//! `graphics_run` opens a window, and takes ownership of the scene. Each frame, it calls a
+//! callback with the live scene, which it may modify with the same functions; the callback
+//! returns which parts of the scene changed, as `GRAPHICS_UPDATE_` flags.
+//!
+//! Entities and lights are identified by index. Out-of-range indices are ignored. Vectors are
+//! passed as components; orientations are quaternions, as (w, x, y, z). For other changes,
+//! `graphics_scene_apply_patch` applies a `ScenePatch` encoded with `ScenePatch::to_bytes`.
+
+use core::f32::consts::TAU;
+use std::{
+    ffi::{c_char, c_void, CStr},
+    slice,
+};
+
+use lin_alg::f32::{Quaternion, Vec3};
+
+use crate::{
+    engine::Engine,
+    lighting::{LightType, PointLight},
+    meshes::NormalMode,
+    patch::ScenePatch,
+    types::{EngineUpdates, Entity, Mesh, Scene, Vertex},
+};
+
+/// Entities were added, removed, or changed.
+pub const GRAPHICS_UPDATE_ENTITIES: u32 = 1;
+/// The camera's position, orientation, or field of view changed.
+pub const GRAPHICS_UPDATE_CAMERA: u32 = 1 << 1;
+/// Lights were added, removed, or changed.
+pub const GRAPHICS_UPDATE_LIGHTING: u32 = 1 << 2;
+/// Meshes were added or changed.
+pub const GRAPHICS_UPDATE_MESHES: u32 = 1 << 3;
+/// Close the window, and return from `graphics_run`.
+pub const GRAPHICS_UPDATE_EXIT: u32 = 1 << 4;
+
+/// Called each frame with the scene, the time since the previous frame in seconds, and the
+/// `user_data` passed to `graphics_run`. Returns `GRAPHICS_UPDATE_` flags.
+pub type FrameCallback = extern "C" fn(*mut Scene, f32, *mut c_void) -> u32;
+
+fn updates_from_flags(flags: u32) -> EngineUpdates {
+    EngineUpdates {
+        entities: flags & GRAPHICS_UPDATE_ENTITIES != 0,
+        camera: flags & GRAPHICS_UPDATE_CAMERA != 0,
+        camera_projection: flags & GRAPHICS_UPDATE_CAMERA != 0,
+        lighting: flags & GRAPHICS_UPDATE_LIGHTING != 0,
+        meshes: flags & GRAPHICS_UPDATE_MESHES != 0,
+        exit: flags & GRAPHICS_UPDATE_EXIT != 0,
+        ..Default::default()
+    }
+}
+
+fn flags_from_updates(updates: &EngineUpdates) -> u32 {
+    let mut result = 0;
+
+    if updates.entities || updates.static_entities || !updates.changed_entities.is_empty() {
+        result |= GRAPHICS_UPDATE_ENTITIES;
+    }
+    if updates.camera || updates.camera_view || updates.camera_projection {
+        result |= GRAPHICS_UPDATE_CAMERA;
+    }
+    if updates.lighting || !updates.changed_lights.is_empty() {
+        result |= GRAPHICS_UPDATE_LIGHTING;
+    }
+
+    result
+}
+
+/// Create an empty scene. Free it with `graphics_scene_free`, unless it's passed to
+/// `graphics_run`.
+#[no_mangle]
+pub extern "C" fn graphics_scene_new() -> *mut Scene {
+    Box::into_raw(Box::default())
+}
+
+/// # Safety
+/// `scene` must be from `graphics_scene_new`, and not already freed or passed to `graphics_run`.
+/// Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_scene_free(scene: *mut Scene) {
+    if !scene.is_null() {
+        drop(Box::from_raw(scene));
+    }
+}
+
+/// Set the window's title, and initial size in pixels.
+///
+/// # Safety
+/// `scene` must be valid or null. `title` must be a null-terminated UTF-8 string, or null to keep
+/// the current title.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_scene_set_window(
+    scene: *mut Scene,
+    title: *const c_char,
+    width: f32,
+    height: f32,
+) {
+    let Some(scene) = scene.as_mut() else {
+        return;
+    };
+
+    if !title.is_null() {
+        scene.window_title = CStr::from_ptr(title).to_string_lossy().into_owned();
+    }
+    scene.window_size = (width, height);
+}
+
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_scene_set_background(scene: *mut Scene, r: f32, g: f32, b: f32) {
+    if let Some(scene) = scene.as_mut() {
+        scene.background_color = (r, g, b);
+    }
+}
+
+/// Add a mesh from vertex positions, as x, y, z triples, and triangle indices. Normals are
+/// generated: smooth across all edges if `smooth` is true, and flat otherwise. Returns the mesh's
+/// index, for use with `graphics_scene_add_entity`, or -1 if a pointer is null.
+///
+/// # Safety
+/// `scene` must be valid or null. `positions` must point to `3 * num_vertices` floats, and
+/// `indices` to `num_indices` integers, each less than `num_vertices`.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_scene_add_mesh(
+    scene: *mut Scene,
+    positions: *const f32,
+    num_vertices: usize,
+    indices: *const u32,
+    num_indices: usize,
+    smooth: bool,
+) -> i64 {
+    let Some(scene) = scene.as_mut() else {
+        return -1;
+    };
+    if positions.is_null() || indices.is_null() {
+        return -1;
+    }
+
+    let positions = slice::from_raw_parts(positions, 3 * num_vertices);
+    let indices = slice::from_raw_parts(indices, num_indices);
+
+    let mut mesh = Mesh {
+        vertices: positions
+            .chunks_exact(3)
+            .map(|p| Vertex::new([p[0], p[1], p[2]], Vec3::new(0., 0., 0.)))
+            .collect(),
+        indices: indices.iter().map(|i| *i as usize).collect(),
+        material: None,
+        impostor: None,
+        culling: Default::default(),
+    };
+
+    mesh.generate_normals(if smooth {
+        NormalMode::Smooth {
+            angle_threshold: TAU / 2.,
+        }
+    } else {
+        NormalMode::Flat
+    });
+
+    add_mesh(scene, mesh) as i64
+}
+
+/// Add a mesh from the contents of an OBJ file. Returns the mesh's index, or -1 if the data is
+/// invalid.
+///
+/// # Safety
+/// `scene` must be valid or null. `bytes` must point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_scene_add_obj(
+    scene: *mut Scene,
+    bytes: *const u8,
+    len: usize,
+) -> i64 {
+    let Some(scene) = scene.as_mut() else {
+        return -1;
+    };
+    if bytes.is_null() {
+        return -1;
+    }
+
+    match Mesh::from_obj_bytes(slice::from_raw_parts(bytes, len)) {
+        Ok(mesh) => add_mesh(scene, mesh) as i64,
+        Err(_) => -1,
+    }
+}
+
+/// Add a sphere mesh. Returns its index, or -1 if `scene` is null.
+///
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_scene_add_sphere(scene: *mut Scene, radius: f32) -> i64 {
+    match scene.as_mut() {
+        Some(scene) => add_mesh(scene, Mesh::new_sphere(radius, 20, 20)) as i64,
+        None => -1,
+    }
+}
+
+/// Add a box mesh. Returns its index, or -1 if `scene` is null.
+///
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_scene_add_box(
+    scene: *mut Scene,
+    len_x: f32,
+    len_y: f32,
+    len_z: f32,
+) -> i64 {
+    match scene.as_mut() {
+        Some(scene) => add_mesh(scene, Mesh::new_box(len_x, len_y, len_z)) as i64,
+        None => -1,
+    }
+}
+
+fn add_mesh(scene: &mut Scene, mesh: Mesh) -> usize {
+    scene.meshes.push(mesh);
+    scene.meshes.len() - 1
+}
+
+/// Add an entity using a mesh, with no rotation. Returns its index, or -1 if `scene` is null, or
+/// `mesh` is out of range.
+///
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_scene_add_entity(
+    scene: *mut Scene,
+    mesh: usize,
+    x: f32,
+    y: f32,
+    z: f32,
+    scale: f32,
+    r: f32,
+    g: f32,
+    b: f32,
+) -> i64 {
+    let Some(scene) = scene.as_mut() else {
+        return -1;
+    };
+    if mesh >= scene.meshes.len() {
+        return -1;
+    }
+
+    scene.entities.push(Entity::new(
+        mesh,
+        Vec3::new(x, y, z),
+        Quaternion::new_identity(),
+        scale,
+        (r, g, b),
+        0.5,
+    ));
+    (scene.entities.len() - 1) as i64
+}
+
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_scene_entity_count(scene: *const Scene) -> usize {
+    scene.as_ref().map_or(0, |s| s.entities.len())
+}
+
+/// Remove an entity. The last entity takes its index.
+///
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_scene_remove_entity(scene: *mut Scene, entity: usize) {
+    if let Some(scene) = scene.as_mut() {
+        if entity < scene.entities.len() {
+            scene.entities.swap_remove(entity);
+        }
+    }
+}
+
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_scene_clear_entities(scene: *mut Scene) {
+    if let Some(scene) = scene.as_mut() {
+        scene.entities.clear();
+    }
+}
+
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_entity_set_position(
+    scene: *mut Scene,
+    entity: usize,
+    x: f32,
+    y: f32,
+    z: f32,
+) {
+    if let Some(entity) = scene.as_mut().and_then(|s| s.entities.get_mut(entity)) {
+        entity.position = Vec3::new(x, y, z);
+    }
+}
+
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_entity_set_orientation(
+    scene: *mut Scene,
+    entity: usize,
+    w: f32,
+    x: f32,
+    y: f32,
+    z: f32,
+) {
+    if let Some(entity) = scene.as_mut().and_then(|s| s.entities.get_mut(entity)) {
+        entity.orientation = Quaternion::new(w, x, y, z);
+    }
+}
+
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_entity_set_scale(scene: *mut Scene, entity: usize, scale: f32) {
+    if let Some(entity) = scene.as_mut().and_then(|s| s.entities.get_mut(entity)) {
+        entity.scale = scale;
+    }
+}
+
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_entity_set_color(
+    scene: *mut Scene,
+    entity: usize,
+    r: f32,
+    g: f32,
+    b: f32,
+    opacity: f32,
+) {
+    if let Some(entity) = scene.as_mut().and_then(|s| s.entities.get_mut(entity)) {
+        entity.color = (r, g, b);
+        entity.opacity = opacity;
+    }
+}
+
+/// Add a white-specular point light. Returns its index, or -1 if `scene` is null.
+///
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_scene_add_light(
+    scene: *mut Scene,
+    x: f32,
+    y: f32,
+    z: f32,
+    r: f32,
+    g: f32,
+    b: f32,
+    intensity: f32,
+) -> i64 {
+    let Some(scene) = scene.as_mut() else {
+        return -1;
+    };
+
+    let lights = &mut scene.lighting.point_lights;
+    lights.push(PointLight {
+        type_: LightType::Omnidirectional,
+        position: Vec3::new(x, y, z),
+        diffuse_color: [r, g, b, 1.],
+        specular_color: [1., 1., 1., 1.],
+        diffuse_intensity: intensity,
+        specular_intensity: intensity,
+        casts_shadow: false,
+    });
+    (lights.len() - 1) as i64
+}
+
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_light_set_position(
+    scene: *mut Scene,
+    light: usize,
+    x: f32,
+    y: f32,
+    z: f32,
+) {
+    if let Some(light) = scene
+        .as_mut()
+        .and_then(|s| s.lighting.point_lights.get_mut(light))
+    {
+        light.position = Vec3::new(x, y, z);
+    }
+}
+
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_camera_set_position(scene: *mut Scene, x: f32, y: f32, z: f32) {
+    if let Some(scene) = scene.as_mut() {
+        scene.camera.position = Vec3::new(x, y, z);
+    }
+}
+
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_camera_set_orientation(
+    scene: *mut Scene,
+    w: f32,
+    x: f32,
+    y: f32,
+    z: f32,
+) {
+    if let Some(scene) = scene.as_mut() {
+        scene.camera.orientation = Quaternion::new(w, x, y, z);
+    }
+}
+
+/// Set the vertical field of view, in radians.
+///
+/// # Safety
+/// `scene` must be valid or null.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_camera_set_fov(scene: *mut Scene, fov_y: f32) {
+    if let Some(scene) = scene.as_mut() {
+        scene.camera.fov_y = fov_y;
+    }
+}
+
+/// Apply a `ScenePatch`, encoded with `ScenePatch::to_bytes`. Returns the `GRAPHICS_UPDATE_` flags
+/// for the changes it made, or -1 if the patch is invalid, in which case nothing is changed.
+///
+/// # Safety
+/// `scene` must be valid or null. `bytes` must point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_scene_apply_patch(
+    scene: *mut Scene,
+    bytes: *const u8,
+    len: usize,
+) -> i64 {
+    let Some(scene) = scene.as_mut() else {
+        return -1;
+    };
+    if bytes.is_null() {
+        return -1;
+    }
+
+    let patch = match ScenePatch::from_bytes(slice::from_raw_parts(bytes, len)) {
+        Ok(p) => p,
+        Err(_) => return -1,
+    };
+
+    match scene.apply_patch(&patch) {
+        Ok(updates) => flags_from_updates(&updates) as i64,
+        Err(_) => -1,
+    }
+}
+
+/// The callback and its data, passed to the render handler.
+struct CallbackState {
+    on_frame: Option<FrameCallback>,
+    user_data: *mut c_void,
+}
+
+/// Open a window, and render the scene until it's closed, or the callback returns
+/// `GRAPHICS_UPDATE_EXIT`. This takes ownership of the scene; don't use or free it afterwards,
+/// except through the callback's pointer while running. `on_frame` may be null.
+///
+/// # Safety
+/// `scene` must be from `graphics_scene_new`, or null, in which case this returns immediately.
+/// `user_data` is passed to `on_frame` unchanged.
+#[no_mangle]
+pub unsafe extern "C" fn graphics_run(
+    scene: *mut Scene,
+    on_frame: Option<FrameCallback>,
+    user_data: *mut c_void,
+) {
+    if scene.is_null() {
+        return;
+    }
+    let scene = *Box::from_raw(scene);
+
+    let state = CallbackState {
+        on_frame,
+        user_data,
+    };
+
+    Engine::builder(state)
+        .scene(scene)
+        .on_render(
+            |state: &mut CallbackState, scene: &mut Scene, dt: f32| match state.on_frame {
+                Some(on_frame) => updates_from_flags(on_frame(scene, dt, state.user_data)),
+                None => Default::default(),
+            },
+        )
+        .run();
+}
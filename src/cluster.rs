@@ -0,0 +1,185 @@
+//! Clustered forward lighting, for scenes with many point lights. We split the view frustum into
+//! a grid of clusters: tiles of the viewport, each split into slices by depth. Each frame, a
+//! compute pass assigns each cluster the lights whose range overlaps it. The main shader looks up
+//! the cluster of each fragment, and loops over only its lights, instead of all of them.
+//!
+//! Point lights use inverse square falloff, which never reaches 0, so we give each light a range
+//! where its contribution drops below a small fraction of full brightness. Lights with lower
+//! intensities have smaller ranges, and are assigned to fewer clusters. Each cluster holds up to
+//! `MAX_CLUSTER_LIGHTS`; additional lights overlapping it are ignored there.
+//!
+//! Environment probe captures use their own cameras, so they loop over all lights.
+
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindingType, Buffer, BufferBindingType, BufferUsages, CommandEncoder,
+    ComputePipeline, Device, Queue, ShaderStages,
+};
+
+use crate::{
+    camera::Camera,
+    types::{F32_SIZE, MAT4_SIZE},
+};
+
+/// Tiles across and down the viewport, and depth slices.
+const GRID: (u32, u32, u32) = (16, 9, 24);
+
+const MAX_CLUSTER_LIGHTS: u32 = 128;
+
+const CLUSTER_WORKGROUP_SIZE: u32 = 64;
+
+/// The view matrix, field of view tangents, near and far planes, grid size, and light limit.
+const CLUSTER_PARAMS_SIZE: usize = MAT4_SIZE + 8 * F32_SIZE;
+
+/// The cluster light lists, and the pass that builds them.
+pub(crate) struct ClusterState {
+    /// If false, the main shader loops over all lights, and we don't run the compute pass.
+    enabled: bool,
+    pipeline: ComputePipeline,
+    bind_group: BindGroup,
+    /// Bound to the main shader.
+    pub params_buf: Buffer,
+    /// Zeroed params, which disable clustering; bound when capturing environment probes.
+    pub params_disabled_buf: Buffer,
+    /// Bound to the main shader. A placeholder if disabled.
+    pub lists_buf: Buffer,
+}
+
+impl ClusterState {
+    pub fn new(device: &Device, lighting_buf: &Buffer, enabled: bool) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Light cluster shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("cluster.wgsl").into()),
+        });
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, false),
+            ],
+            label: Some("Light cluster bind group layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light cluster pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Light cluster pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("assign_lights"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let params = |label| {
+            device.create_buffer_init(&BufferInitDescriptor {
+                label: Some(label),
+                contents: &[0; CLUSTER_PARAMS_SIZE],
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            })
+        };
+
+        let params_buf = params("Light cluster params buffer");
+        let params_disabled_buf = params("Light cluster disabled params buffer");
+
+        // Each cluster's light count, followed by its light indices.
+        let lists_len = if enabled {
+            GRID.0 * GRID.1 * GRID.2 * (MAX_CLUSTER_LIGHTS + 1)
+        } else {
+            1
+        };
+
+        let lists_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light cluster buffer"),
+            size: (lists_len as usize * F32_SIZE) as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: lighting_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: lists_buf.as_entire_binding(),
+                },
+            ],
+            label: Some("Light cluster bind group"),
+        });
+
+        Self {
+            enabled,
+            pipeline,
+            bind_group,
+            params_buf,
+            params_disabled_buf,
+            lists_buf,
+        }
+    }
+
+    /// Assign lights to clusters, for the camera's current view. Run this each frame, before the
+    /// main pass.
+    pub fn encode(&self, queue: &Queue, encoder: &mut CommandEncoder, camera: &Camera) {
+        if !self.enabled {
+            return;
+        }
+
+        let tan_y = (camera.fov_y / 2.).tan();
+        let tan_x = tan_y * camera.aspect;
+
+        let mut params = Vec::with_capacity(CLUSTER_PARAMS_SIZE);
+        params.extend_from_slice(&camera.view_mat().to_bytes());
+        for v in [tan_x, tan_y, camera.near, camera.far] {
+            params.extend_from_slice(&v.to_ne_bytes());
+        }
+        for v in [GRID.0, GRID.1, GRID.2, MAX_CLUSTER_LIGHTS] {
+            params.extend_from_slice(&v.to_ne_bytes());
+        }
+
+        queue.write_buffer(&self.params_buf, 0, &params);
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Light cluster pass"),
+            timestamp_writes: None,
+        });
+
+        let num_clusters = GRID.0 * GRID.1 * GRID.2;
+
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.dispatch_workgroups(num_clusters.div_ceil(CLUSTER_WORKGROUP_SIZE), 1, 1);
+    }
+}
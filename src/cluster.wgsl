@@ -0,0 +1,96 @@
+// Clustered light culling: assigns point lights to clusters of the view frustum, so the main
+// shader only loops over lights near each fragment.
+
+struct PointLight {
+    position: vec4<f32>,
+    diffuse_color: vec4<f32>,
+    specular_color: vec4<f32>,
+    diffuse_intensity: f32,
+    specular_intensity: f32,
+    shadow_i: i32,
+}
+
+struct Lighting {
+    ambient_color: vec4<f32>,
+    ambient_intensity: f32,
+    lights_len: i32,
+    falloff_scale: f32,
+    point_lights: array<PointLight>
+}
+
+struct ClusterParams {
+    view: mat4x4<f32>,
+    // Tangents of half the horizontal and vertical fields of view.
+    tan_x: f32,
+    tan_y: f32,
+    near: f32,
+    far: f32,
+    // The size of the grid of clusters.
+    grid_x: u32,
+    grid_y: u32,
+    grid_z: u32,
+    max_lights: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: ClusterParams;
+@group(0) @binding(1)
+var<storage> lighting: Lighting;
+@group(0) @binding(2)
+// For each cluster, its light count, followed by up to `max_lights` light indices.
+var<storage, read_write> cluster_lights: array<u32>;
+
+// Lights don't affect surfaces where they'd contribute less than this fraction of full brightness.
+const LIGHT_CUTOFF: f32 = 0.005;
+
+// The view space depth where slice `k` starts. Slices are spaced exponentially, so near ones are
+// thinner.
+fn slice_depth(k: u32) -> f32 {
+    return params.near * pow(params.far / params.near, f32(k) / f32(params.grid_z));
+}
+
+@compute
+@workgroup_size(64)
+fn assign_lights(@builtin(global_invocation_id) id: vec3<u32>) {
+    var cluster = id.x;
+    if (cluster >= params.grid_x * params.grid_y * params.grid_z) {
+        return;
+    }
+
+    var x = cluster % params.grid_x;
+    var y = (cluster / params.grid_x) % params.grid_y;
+    var z = cluster / (params.grid_x * params.grid_y);
+
+    // The tile's edges, at unit distance from the camera.
+    var grid = vec2<f32>(f32(params.grid_x), f32(params.grid_y));
+    var scale = vec2<f32>(params.tan_x, params.tan_y);
+    var edge_min = (vec2<f32>(f32(x), f32(y)) / grid * 2. - 1.) * scale;
+    var edge_max = (vec2<f32>(f32(x + 1u), f32(y + 1u)) / grid * 2. - 1.) * scale;
+
+    // The cluster's bounding box in view space.
+    var z_near = slice_depth(z);
+    var z_far = slice_depth(z + 1u);
+    var bb_min = vec3<f32>(min(edge_min * z_near, edge_min * z_far), z_near);
+    var bb_max = vec3<f32>(max(edge_max * z_near, edge_max * z_far), z_far);
+
+    var start = cluster * (params.max_lights + 1u);
+    var count = 0u;
+
+    for (var i = 0; i < lighting.lights_len; i++) {
+        var light = lighting.point_lights[i];
+
+        // The distance at which inverse square falloff reaches the cutoff.
+        var intensity = max(max(light.diffuse_intensity, light.specular_intensity), 0.);
+        var radius = lighting.falloff_scale * sqrt(intensity / LIGHT_CUTOFF);
+
+        var center = (params.view * vec4<f32>(light.position.xyz, 1.)).xyz;
+        var nearest = clamp(center, bb_min, bb_max);
+
+        if (distance(center, nearest) <= radius && count < params.max_lights) {
+            cluster_lights[start + 1u + count] = u32(i);
+            count++;
+        }
+    }
+
+    cluster_lights[start] = count;
+}
@@ -0,0 +1,256 @@
+//! Overlap queries between entities, and between entities and volumes, eg for hover volumes or
+//! proximity highlighting. Entities are approximated by world-space bounding boxes, or
+//! bounding spheres; this isn't a physics engine.
+//!
+//! Pairwise queries use sweep-and-prune along the X axis as a broad phase, followed by box
+//! overlap tests, then bounding sphere tests, which reject entities whose boxes overlap only near
+//! their corners.
+
+use std::collections::HashMap;
+
+use lin_alg::f32::Vec3;
+
+use crate::{
+    raycast::{self, Bvh},
+    types::{Entity, Mesh, Scene},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// An axis-aligned bounding box.
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// A box that contains nothing; growing it by a point results in a box containing only that
+    /// point.
+    pub fn empty() -> Self {
+        Self {
+            min: Vec3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Vec3::new(f32::MIN, f32::MIN, f32::MIN),
+        }
+    }
+
+    /// Expand the box to contain a point.
+    pub fn grow(&mut self, p: Vec3) {
+        self.min = Vec3::new(
+            self.min.x.min(p.x),
+            self.min.y.min(p.y),
+            self.min.z.min(p.z),
+        );
+        self.max = Vec3::new(
+            self.max.x.max(p.x),
+            self.max.y.max(p.y),
+            self.max.z.max(p.z),
+        );
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn contains(&self, p: Vec3) -> bool {
+        p.x >= self.min.x
+            && p.x <= self.max.x
+            && p.y >= self.min.y
+            && p.y <= self.max.y
+            && p.z >= self.min.z
+            && p.z <= self.max.z
+    }
+
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.min.x <= other.max.x
+            && self.max.x >= other.min.x
+            && self.min.y <= other.max.y
+            && self.max.y >= other.min.y
+            && self.min.z <= other.max.z
+            && self.max.z >= other.min.z
+    }
+
+    pub fn overlaps_sphere(&self, center: Vec3, radius: f32) -> bool {
+        // The closest point in the box to the sphere's center.
+        let closest = Vec3::new(
+            center.x.clamp(self.min.x, self.max.x),
+            center.y.clamp(self.min.y, self.max.y),
+            center.z.clamp(self.min.z, self.max.z),
+        );
+
+        (closest - center).magnitude_squared() <= radius.powi(2)
+    }
+
+    /// Bounds of a mesh's vertices, in its local space.
+    pub fn from_mesh(mesh: &Mesh) -> Self {
+        let mut result = Self::empty();
+        for vertex in &mesh.vertices {
+            let p = vertex.position;
+            result.grow(Vec3::new(p[0], p[1], p[2]));
+        }
+        result
+    }
+
+    /// Bounds of this box, after applying an entity's scale, orientation, and position.
+    pub fn transformed(&self, entity: &Entity) -> Self {
+        let mut result = Self::empty();
+
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            );
+            result.grow(entity.orientation.rotate_vec(corner * entity.scale) + entity.position);
+        }
+
+        result
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// Per-mesh data used by spatial queries, eg `Scene::raycast`, built as needed. This is managed
+/// by the engine, and updated when meshes change. BVHs may be saved and loaded with
+/// `Scene::serialize_bvhs` and `Scene::load_bvhs`.
+pub struct SpatialCache {
+    pub(crate) bvhs: Vec<Option<Bvh>>,
+    mesh_bounds: Vec<Option<Aabb>>,
+}
+
+impl SpatialCache {
+    /// Discard cached data, eg after meshes change.
+    pub fn clear(&mut self) {
+        self.bvhs.clear();
+        self.mesh_bounds.clear();
+    }
+
+    /// Keep BVHs of meshes whose geometry didn't change, eg after meshes are added or reordered,
+    /// and discard everything else.
+    pub(crate) fn retain_unchanged(&mut self, meshes: &[Mesh]) {
+        let built: HashMap<u64, Bvh> = self
+            .bvhs
+            .drain(..)
+            .flatten()
+            .map(|b| (b.geometry_hash, b))
+            .collect();
+        self.mesh_bounds.clear();
+
+        if built.is_empty() {
+            return;
+        }
+
+        self.bvhs = meshes
+            .iter()
+            .map(|m| built.get(&raycast::geometry_hash(m)).cloned())
+            .collect();
+    }
+
+    /// Discard cached data for specific meshes, eg after their vertices change.
+    pub(crate) fn invalidate(&mut self, meshes: &[usize]) {
+        for &i in meshes {
+            if let Some(bvh) = self.bvhs.get_mut(i) {
+                *bvh = None;
+            }
+            if let Some(bounds) = self.mesh_bounds.get_mut(i) {
+                *bounds = None;
+            }
+        }
+    }
+
+    /// Make sure there's a slot for each mesh.
+    pub(crate) fn resize(&mut self, num_meshes: usize) {
+        if self.bvhs.len() != num_meshes {
+            self.bvhs = vec![None; num_meshes];
+        }
+        if self.mesh_bounds.len() != num_meshes {
+            self.mesh_bounds = vec![None; num_meshes];
+        }
+    }
+}
+
+impl Scene {
+    /// World-space bounding box of an entity, with group transforms applied. Returns `None` if
+    /// it's hidden, or its mesh doesn't exist.
+    pub fn entity_aabb(&mut self, i: usize) -> Option<Aabb> {
+        let entity = self.entity_in_world(i)?;
+        let local = self.mesh_bounds(entity.mesh)?;
+
+        Some(local.transformed(&entity))
+    }
+
+    /// World-space bounding sphere of an entity, as (center, radius). This encloses the mesh's
+    /// local bounding box, so it may not be minimal.
+    pub fn entity_bounding_sphere(&mut self, i: usize) -> Option<(Vec3, f32)> {
+        let entity = self.entity_in_world(i)?;
+        let local = self.mesh_bounds(entity.mesh)?;
+
+        let center = entity.orientation.rotate_vec(local.center() * entity.scale) + entity.position;
+        let radius = (local.max - local.min).magnitude() * 0.5 * entity.scale;
+
+        Some((center, radius))
+    }
+
+    /// Pairs of entities whose bounding boxes and bounding spheres both overlap. Each pair is
+    /// listed once, with the lower index first.
+    pub fn overlapping_pairs(&mut self) -> Vec<(usize, usize)> {
+        let mut bounds: Vec<(usize, Aabb, (Vec3, f32))> = (0..self.entities.len())
+            .filter_map(|i| Some((i, self.entity_aabb(i)?, self.entity_bounding_sphere(i)?)))
+            .collect();
+
+        // Sweep and prune: after sorting by min X, each box can only overlap boxes that start
+        // before it ends.
+        bounds.sort_unstable_by(|a, b| a.1.min.x.total_cmp(&b.1.min.x));
+
+        let mut result = Vec::new();
+
+        for (j, (i_a, a, sphere_a)) in bounds.iter().enumerate() {
+            for (i_b, b, sphere_b) in &bounds[j + 1..] {
+                if b.min.x > a.max.x {
+                    break;
+                }
+                if a.overlaps(b) && spheres_overlap(*sphere_a, *sphere_b) {
+                    result.push((*i_a.min(i_b), *i_a.max(i_b)));
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Entities whose bounding boxes overlap a box.
+    pub fn entities_in_aabb(&mut self, aabb: &Aabb) -> Vec<usize> {
+        (0..self.entities.len())
+            .filter(|&i| self.entity_aabb(i).map(|b| b.overlaps(aabb)) == Some(true))
+            .collect()
+    }
+
+    /// Entities whose bounding boxes overlap a sphere.
+    pub fn entities_in_sphere(&mut self, center: Vec3, radius: f32) -> Vec<usize> {
+        (0..self.entities.len())
+            .filter(|&i| {
+                self.entity_aabb(i)
+                    .map(|b| b.overlaps_sphere(center, radius))
+                    == Some(true)
+            })
+            .collect()
+    }
+
+    /// Local-space bounds of a mesh, computed once and cached.
+    pub(crate) fn mesh_bounds(&mut self, mesh_i: usize) -> Option<Aabb> {
+        let mesh = self.meshes.get(mesh_i)?;
+        if mesh.vertices.is_empty() {
+            return None;
+        }
+
+        self.spatial_cache.resize(self.meshes.len());
+
+        Some(*self.spatial_cache.mesh_bounds[mesh_i].get_or_insert_with(|| Aabb::from_mesh(mesh)))
+    }
+}
+
+/// Whether two spheres, each (center, radius), overlap.
+fn spheres_overlap(a: (Vec3, f32), b: (Vec3, f32)) -> bool {
+    (a.0 - b.0).magnitude_squared() <= (a.1 + b.1).powi(2)
+}
@@ -0,0 +1,70 @@
+//! Conversions between 8-bit sRGB colors, eg from egui color pickers or image editors, and the
+//! linear floats used for entity, palette, light, and background colors. Use these when
+//! `ColorSettings::input_space` is `ColorSpace::Linear`, so picked colors match rendered output.
+//! With `ColorSpace::Srgb`, divide 8-bit components by 255 instead; the engine converts them.
+
+#[cfg(feature = "gui")]
+use egui::Color32;
+
+/// Convert an sRGB-encoded component, from 0 to 1, to linear.
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Convert a linear component, from 0 to 1, to sRGB encoding.
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1. / 2.4) - 0.055
+    }
+}
+
+fn component_from_srgb8(v: u8) -> f32 {
+    srgb_to_linear(v as f32 / 255.)
+}
+
+fn component_to_srgb8(v: f32) -> u8 {
+    (linear_to_srgb(v.clamp(0., 1.)) * 255.).round() as u8
+}
+
+/// Convert an 8-bit sRGB color to linear, eg for `Entity::color` or `Scene::background_color`.
+pub fn color_from_srgb8(color: [u8; 3]) -> (f32, f32, f32) {
+    let [r, g, b] = color.map(component_from_srgb8);
+    (r, g, b)
+}
+
+/// Convert a linear color to 8-bit sRGB, eg to initialize a color picker.
+pub fn color_to_srgb8(color: (f32, f32, f32)) -> [u8; 3] {
+    [color.0, color.1, color.2].map(component_to_srgb8)
+}
+
+/// Convert an 8-bit sRGB color with alpha to linear, eg for light and palette colors. Alpha is
+/// not sRGB-encoded, so is only scaled.
+pub fn rgba_from_srgb8(color: [u8; 4]) -> [f32; 4] {
+    let (r, g, b) = color_from_srgb8([color[0], color[1], color[2]]);
+    [r, g, b, color[3] as f32 / 255.]
+}
+
+/// Convert a linear color with alpha to 8-bit sRGB.
+pub fn rgba_to_srgb8(color: [f32; 4]) -> [u8; 4] {
+    let [r, g, b] = color_to_srgb8((color[0], color[1], color[2]));
+    [r, g, b, (color[3].clamp(0., 1.) * 255.).round() as u8]
+}
+
+/// Convert an egui color, eg from `egui::color_picker`, to linear.
+#[cfg(feature = "gui")]
+pub fn color_from_egui(color: Color32) -> (f32, f32, f32) {
+    color_from_srgb8([color.r(), color.g(), color.b()])
+}
+
+/// Convert a linear color to an egui color, eg for a color picker.
+#[cfg(feature = "gui")]
+pub fn color_to_egui(color: (f32, f32, f32)) -> Color32 {
+    let [r, g, b] = color_to_srgb8(color);
+    Color32::from_rgb(r, g, b)
+}
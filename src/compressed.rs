@@ -0,0 +1,610 @@
+//! Block-compressed (BCn) textures, loaded from KTX2 or DDS files with their mip chains. These
+//! use a quarter to an eighth of the GPU memory of uncompressed RGBA, so are suitable for large
+//! texture sets. They're uploaded as-is if the device supports BC compression, which most desktop
+//! GPUs do. Otherwise, we decompress them on the CPU.
+
+use ddsfile::{Dds, DxgiFormat};
+use wgpu::TextureFormat;
+
+use crate::color::linear_to_srgb;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A block compression format. Each encodes 4x4 pixel blocks.
+pub enum BcFormat {
+    /// RGB, with optional 1-bit alpha. 8 bytes per block. AKA DXT1.
+    Bc1,
+    /// RGBA. 16 bytes per block. AKA DXT5.
+    Bc3,
+    /// Two channels, eg for normal maps. 16 bytes per block.
+    Bc5,
+    /// RGBA, at higher quality than BC1 or BC3. 16 bytes per block.
+    Bc7,
+}
+
+impl BcFormat {
+    pub(crate) fn block_size(self) -> usize {
+        match self {
+            Self::Bc1 => 8,
+            _ => 16,
+        }
+    }
+
+    pub(crate) fn texture_format(self, srgb: bool) -> TextureFormat {
+        match (self, srgb) {
+            (Self::Bc1, false) => TextureFormat::Bc1RgbaUnorm,
+            (Self::Bc1, true) => TextureFormat::Bc1RgbaUnormSrgb,
+            (Self::Bc3, false) => TextureFormat::Bc3RgbaUnorm,
+            (Self::Bc3, true) => TextureFormat::Bc3RgbaUnormSrgb,
+            (Self::Bc5, _) => TextureFormat::Bc5RgUnorm,
+            (Self::Bc7, false) => TextureFormat::Bc7RgbaUnorm,
+            (Self::Bc7, true) => TextureFormat::Bc7RgbaUnormSrgb,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// A block-compressed image, and its mip chain.
+pub struct CompressedImage {
+    pub format: BcFormat,
+    /// If set, colors are sRGB-encoded. This is always false for BC5.
+    pub srgb: bool,
+    pub width: u32,
+    pub height: u32,
+    /// Mip levels, starting at full size. Each is a sequence of blocks, row by row from the top
+    /// left.
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedImage {
+    /// Load from a KTX2 file's contents. Supercompressed files, eg using Basis Universal, aren't
+    /// supported. For arrays and cube maps, we use the first image.
+    pub fn from_ktx2(bytes: &[u8]) -> Result<Self, String> {
+        use ktx2::Format;
+
+        let reader = ktx2::Reader::new(bytes).map_err(|e| e.to_string())?;
+        let header = reader.header();
+
+        if header.supercompression_scheme.is_some() {
+            return Err("Supercompressed KTX2 files aren't supported".to_owned());
+        }
+
+        let (format, srgb) = match header.format {
+            Some(Format::BC1_RGB_UNORM_BLOCK | Format::BC1_RGBA_UNORM_BLOCK) => {
+                (BcFormat::Bc1, false)
+            }
+            Some(Format::BC1_RGB_SRGB_BLOCK | Format::BC1_RGBA_SRGB_BLOCK) => (BcFormat::Bc1, true),
+            Some(Format::BC3_UNORM_BLOCK) => (BcFormat::Bc3, false),
+            Some(Format::BC3_SRGB_BLOCK) => (BcFormat::Bc3, true),
+            Some(Format::BC5_UNORM_BLOCK) => (BcFormat::Bc5, false),
+            Some(Format::BC7_UNORM_BLOCK) => (BcFormat::Bc7, false),
+            Some(Format::BC7_SRGB_BLOCK) => (BcFormat::Bc7, true),
+            f => return Err(format!("Unsupported KTX2 format: {f:?}")),
+        };
+
+        let levels = reader.levels().map(|level| level.data);
+
+        Self::from_levels(
+            format,
+            srgb,
+            header.pixel_width,
+            header.pixel_height,
+            levels,
+        )
+    }
+
+    /// Load from a DDS file's contents. For arrays and cube maps, we use the first image.
+    pub fn from_dds(bytes: &[u8]) -> Result<Self, String> {
+        let dds = Dds::read(bytes).map_err(|e| e.to_string())?;
+
+        let (format, srgb) = match dds.get_dxgi_format() {
+            Some(DxgiFormat::BC1_UNorm) => (BcFormat::Bc1, false),
+            Some(DxgiFormat::BC1_UNorm_sRGB) => (BcFormat::Bc1, true),
+            Some(DxgiFormat::BC3_UNorm) => (BcFormat::Bc3, false),
+            Some(DxgiFormat::BC3_UNorm_sRGB) => (BcFormat::Bc3, true),
+            Some(DxgiFormat::BC5_UNorm) => (BcFormat::Bc5, false),
+            Some(DxgiFormat::BC7_UNorm) => (BcFormat::Bc7, false),
+            Some(DxgiFormat::BC7_UNorm_sRGB) => (BcFormat::Bc7, true),
+            f => return Err(format!("Unsupported DDS format: {f:?}")),
+        };
+
+        let (width, height) = (dds.get_width(), dds.get_height());
+        let mut data = dds.get_data(0).map_err(|e| e.to_string())?;
+
+        // Levels are stored consecutively.
+        let mut levels = Vec::new();
+        for level in 0..dds.get_num_mipmap_levels().max(1) {
+            let size = level_size(format, width, height, level).min(data.len());
+            let (level_data, rest) = data.split_at(size);
+            levels.push(level_data);
+            data = rest;
+        }
+
+        Self::from_levels(format, srgb, width, height, levels.into_iter())
+    }
+
+    /// Validates level sizes, and copies the first image of each.
+    fn from_levels<'a>(
+        format: BcFormat,
+        srgb: bool,
+        width: u32,
+        height: u32,
+        levels: impl Iterator<Item = &'a [u8]>,
+    ) -> Result<Self, String> {
+        if width == 0 || height == 0 {
+            return Err("Compressed image has no pixels".to_owned());
+        }
+
+        let mut result = Self {
+            format,
+            srgb,
+            width,
+            height,
+            levels: Vec::new(),
+        };
+
+        for (i, data) in levels.enumerate() {
+            let size = level_size(format, width, height, i as u32);
+            if data.len() < size {
+                return Err(format!("Compressed image level {i} is truncated"));
+            }
+            result.levels.push(data[..size].to_vec());
+        }
+
+        if result.levels.is_empty() {
+            return Err("Compressed image has no levels".to_owned());
+        }
+
+        Ok(result)
+    }
+
+    /// The number of blocks wide and high of a mip level.
+    pub(crate) fn level_blocks(&self, level: u32) -> (u32, u32) {
+        level_blocks(self.width, self.height, level)
+    }
+
+    /// Decompress the full-size level to RGBA, with 8 bits per channel, row by row from the top
+    /// left. Colors are sRGB-encoded, as with uncompressed materials.
+    pub(crate) fn decompress(&self) -> Vec<u8> {
+        let (blocks_x, blocks_y) = self.level_blocks(0);
+        let (w, h) = (self.width as usize, self.height as usize);
+
+        let mut result = vec![0; w * h * 4];
+        let mut pixels = [[0; 4]; 16];
+
+        for (i, block) in self.levels[0]
+            .chunks_exact(self.format.block_size())
+            .take((blocks_x * blocks_y) as usize)
+            .enumerate()
+        {
+            match self.format {
+                BcFormat::Bc1 => decode_bc1(block, &mut pixels, true),
+                BcFormat::Bc3 => {
+                    decode_bc1(&block[8..], &mut pixels, false);
+                    let alpha = decode_bc4(&block[..8]);
+                    for (p, a) in pixels.iter_mut().zip(alpha) {
+                        p[3] = a;
+                    }
+                }
+                BcFormat::Bc5 => {
+                    let red = decode_bc4(&block[..8]);
+                    let green = decode_bc4(&block[8..]);
+                    for (j, p) in pixels.iter_mut().enumerate() {
+                        *p = [red[j], green[j], 0, 255];
+                    }
+                }
+                BcFormat::Bc7 => decode_bc7(block, &mut pixels),
+            }
+
+            let bx = i % blocks_x as usize * 4;
+            let by = i / blocks_x as usize * 4;
+
+            // Blocks at the right and bottom edges may extend past the image.
+            for (j, p) in pixels.iter().enumerate() {
+                let (x, y) = (bx + j % 4, by + j / 4);
+                if x < w && y < h {
+                    let start = (y * w + x) * 4;
+                    result[start..start + 4].copy_from_slice(p);
+                }
+            }
+        }
+
+        if !self.srgb {
+            for p in result.chunks_exact_mut(4) {
+                for c in &mut p[..3] {
+                    *c = (linear_to_srgb(*c as f32 / 255.) * 255.).round() as u8;
+                }
+            }
+        }
+
+        result
+    }
+}
+
+fn level_blocks(width: u32, height: u32, level: u32) -> (u32, u32) {
+    let w = (width >> level).max(1);
+    let h = (height >> level).max(1);
+    (w.div_ceil(4), h.div_ceil(4))
+}
+
+fn level_size(format: BcFormat, width: u32, height: u32, level: u32) -> usize {
+    let (x, y) = level_blocks(width, height, level);
+    (x * y) as usize * format.block_size()
+}
+
+fn rgb565(c: u16) -> [u8; 4] {
+    let r = ((c >> 11) & 31) as u8;
+    let g = ((c >> 5) & 63) as u8;
+    let b = (c & 31) as u8;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+        255,
+    ]
+}
+
+/// Decode a BC1 color block. BC3 color blocks are the same, but always use 4 colors.
+fn decode_bc1(block: &[u8], pixels: &mut [[u8; 4]; 16], allow_alpha: bool) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |wa: u16, wb: u16, d: u16| {
+        let mut result = [0, 0, 0, 255];
+        for i in 0..3 {
+            result[i] = ((a[i] as u16 * wa + b[i] as u16 * wb) / d) as u8;
+        }
+        result
+    };
+
+    let colors = if c0 > c1 || !allow_alpha {
+        [a, b, mix(2, 1, 3), mix(1, 2, 3)]
+    } else {
+        [a, b, mix(1, 1, 2), [0; 4]]
+    };
+
+    for (i, p) in pixels.iter_mut().enumerate() {
+        *p = colors[(indices >> (2 * i) & 3) as usize];
+    }
+}
+
+/// Decode a single-channel block, as used for BC3 alpha, and each BC5 channel.
+fn decode_bc4(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+
+    let mut index_bytes = [0; 8];
+    index_bytes[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(index_bytes);
+
+    let mut values = [0; 8];
+    values[0] = a0;
+    values[1] = a1;
+    if a0 > a1 {
+        for (i, v) in values.iter_mut().enumerate().skip(2) {
+            *v = ((8 - i as u32) * a0 + (i as u32 - 1) * a1) / 7;
+        }
+    } else {
+        for (i, v) in values.iter_mut().enumerate().take(6).skip(2) {
+            *v = ((6 - i as u32) * a0 + (i as u32 - 1) * a1) / 5;
+        }
+        values[7] = 255;
+    }
+
+    let mut result = [0; 16];
+    for (i, v) in result.iter_mut().enumerate() {
+        *v = values[(indices >> (3 * i) & 7) as usize] as u8;
+    }
+    result
+}
+
+/// The layout of a BC7 mode.
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_select_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    /// A P-bit for each endpoint.
+    endpoint_pbits: bool,
+    /// A P-bit for each subset, shared by its endpoints.
+    shared_pbits: bool,
+    index_bits: u32,
+    /// For modes with separate color and alpha indices.
+    index2_bits: u32,
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode {
+        subsets: 3,
+        partition_bits: 4,
+        rotation_bits: 0,
+        index_select_bits: 0,
+        color_bits: 4,
+        alpha_bits: 0,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 3,
+        index2_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_select_bits: 0,
+        color_bits: 6,
+        alpha_bits: 0,
+        endpoint_pbits: false,
+        shared_pbits: true,
+        index_bits: 3,
+        index2_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 3,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_select_bits: 0,
+        color_bits: 5,
+        alpha_bits: 0,
+        endpoint_pbits: false,
+        shared_pbits: false,
+        index_bits: 2,
+        index2_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_select_bits: 0,
+        color_bits: 7,
+        alpha_bits: 0,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 2,
+        index2_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 2,
+        index_select_bits: 1,
+        color_bits: 5,
+        alpha_bits: 6,
+        endpoint_pbits: false,
+        shared_pbits: false,
+        index_bits: 2,
+        index2_bits: 3,
+    },
+    Bc7Mode {
+        subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 2,
+        index_select_bits: 0,
+        color_bits: 7,
+        alpha_bits: 8,
+        endpoint_pbits: false,
+        shared_pbits: false,
+        index_bits: 2,
+        index2_bits: 2,
+    },
+    Bc7Mode {
+        subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 0,
+        index_select_bits: 0,
+        color_bits: 7,
+        alpha_bits: 7,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 4,
+        index2_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_select_bits: 0,
+        color_bits: 5,
+        alpha_bits: 5,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 2,
+        index2_bits: 0,
+    },
+];
+
+/// Two-subset partitions. Bit `i` is set if pixel `i` is in the second subset.
+const BC7_PARTITIONS_2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80, 0xC800, 0xFFEC, 0xFE80, 0xE800,
+    0xFFE8, 0xFF00, 0xFFF0, 0xF000, 0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C, 0xAAAA, 0xF0F0, 0x5A5A, 0x33CC,
+    0x3C3C, 0x55AA, 0x9696, 0xA55A, 0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C, 0x9336, 0x9CC6, 0x817E, 0xE718,
+    0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+/// Three-subset partitions. Bits `2i` and `2i + 1` are the subset of pixel `i`.
+const BC7_PARTITIONS_3: [u32; 64] = [
+    0xAA685050, 0x6A5A5040, 0x5A5A4200, 0x5450A0A8, 0xA5A50000, 0xA0A05050, 0x5555A0A0, 0x5A5A5050,
+    0xAA550000, 0xAA555500, 0xAAAA5500, 0x90909090, 0x94949494, 0xA4A4A4A4, 0xA9A59450, 0x2A0A4250,
+    0xA5945040, 0x0A425054, 0xA5A5A500, 0x55A0A0A0, 0xA8A85454, 0x6A6A4040, 0xA4A45000, 0x1A1A0500,
+    0x0050A4A4, 0xAAA59090, 0x14696914, 0x69691400, 0xA08585A0, 0xAA821414, 0x50A4A450, 0x6A5A0200,
+    0xA9A58000, 0x5090A0A8, 0xA8A09050, 0x24242424, 0x00AA5500, 0x24924924, 0x24499224, 0x50A50A50,
+    0x500AA550, 0xAAAA4444, 0x66660000, 0xA5A0A5A0, 0x50A050A0, 0x69286928, 0x44AAAA44, 0x66666600,
+    0xAA444444, 0x54A854A8, 0x95809580, 0x96969600, 0xA85454A8, 0x80959580, 0xAA141414, 0x96960000,
+    0xAAAA1414, 0xA05050A0, 0xA0A5A5A0, 0x96000000, 0x40804080, 0xA9A8A9A8, 0xAAAAAA44, 0x2A4A5254,
+];
+
+/// The anchor pixel of the second subset, for two-subset partitions. The first subset's is 0.
+const BC7_ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+/// The anchor pixels of the second and third subsets, for three-subset partitions.
+const BC7_ANCHORS_3: [[u8; 64]; 2] = [
+    [
+        3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6,
+        8, 5, 15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8,
+        5, 10, 5, 10, 8, 13, 15, 12, 3, 3,
+    ],
+    [
+        15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3,
+        15, 6, 10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15,
+        15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+    ],
+];
+
+const BC7_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Reads a block's bits, from least significant.
+struct BitReader {
+    data: u128,
+    pos: u32,
+}
+
+impl BitReader {
+    fn read(&mut self, bits: u32) -> u8 {
+        let result = (self.data >> self.pos) as u32 & ((1 << bits) - 1);
+        self.pos += bits;
+        result as u8
+    }
+}
+
+/// Decode a BC7 block. Invalid blocks decode to transparent black.
+/// https://learn.microsoft.com/en-us/windows/win32/direct3d11/bc7-format
+fn decode_bc7(block: &[u8], pixels: &mut [[u8; 4]; 16]) {
+    let data = u128::from_le_bytes(block.try_into().unwrap());
+
+    // The mode is the position of the lowest set bit.
+    let mode_i = data.trailing_zeros();
+    if mode_i >= 8 {
+        *pixels = [[0; 4]; 16];
+        return;
+    }
+    let mode = &BC7_MODES[mode_i as usize];
+
+    let mut bits = BitReader {
+        data,
+        pos: mode_i + 1,
+    };
+
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_select = bits.read(mode.index_select_bits);
+
+    // Indexed by subset, endpoint, and channel.
+    let mut endpoints = [[[0_u32; 4]; 2]; 3];
+    for c in 0..3 {
+        for subset in &mut endpoints[..mode.subsets] {
+            for endpoint in subset {
+                endpoint[c] = bits.read(mode.color_bits) as u32;
+            }
+        }
+    }
+    for subset in &mut endpoints[..mode.subsets] {
+        for endpoint in subset {
+            endpoint[3] = bits.read(mode.alpha_bits) as u32;
+        }
+    }
+
+    let mut pbits = [[0; 2]; 3];
+    for subset in &mut pbits[..mode.subsets] {
+        if mode.endpoint_pbits {
+            *subset = [bits.read(1) as u32, bits.read(1) as u32];
+        } else if mode.shared_pbits {
+            let p = bits.read(1) as u32;
+            *subset = [p, p];
+        }
+    }
+    let has_pbits = mode.endpoint_pbits || mode.shared_pbits;
+
+    // Expand endpoints to 8 bits, replicating their high bits.
+    for (subset, subset_pbits) in endpoints[..mode.subsets].iter_mut().zip(pbits) {
+        for (endpoint, pbit) in subset.iter_mut().zip(subset_pbits) {
+            for (c, v) in endpoint.iter_mut().enumerate() {
+                let mut n = if c == 3 {
+                    mode.alpha_bits
+                } else {
+                    mode.color_bits
+                };
+                if n == 0 {
+                    *v = 255;
+                    continue;
+                }
+                if has_pbits {
+                    *v = (*v << 1) | pbit;
+                    n += 1;
+                }
+                *v = (*v << (8 - n)) | (*v >> (2 * n - 8));
+            }
+        }
+    }
+
+    let subset_of = |i: usize| match mode.subsets {
+        1 => 0,
+        2 => (BC7_PARTITIONS_2[partition] >> i & 1) as usize,
+        _ => (BC7_PARTITIONS_3[partition] >> (2 * i) & 3) as usize,
+    };
+
+    // Anchor pixels' indices have an implicit leading 0 bit.
+    let is_anchor = |i: usize| {
+        i == 0
+            || match mode.subsets {
+                2 => i == BC7_ANCHORS_2[partition] as usize,
+                3 => BC7_ANCHORS_3.iter().any(|a| i == a[partition] as usize),
+                _ => false,
+            }
+    };
+
+    let mut indices = [0; 16];
+    for (i, index) in indices.iter_mut().enumerate() {
+        *index = bits.read(mode.index_bits - is_anchor(i) as u32) as usize;
+    }
+
+    let mut indices2 = [0; 16];
+    if mode.index2_bits > 0 {
+        for (i, index) in indices2.iter_mut().enumerate() {
+            *index = bits.read(mode.index2_bits - (i == 0) as u32) as usize;
+        }
+    }
+
+    // Color and alpha indices, and their bit counts.
+    let ((color_i, color_bits), (alpha_i, alpha_bits)) = if mode.index2_bits == 0 {
+        ((indices, mode.index_bits), (indices, mode.index_bits))
+    } else if index_select == 0 {
+        ((indices, mode.index_bits), (indices2, mode.index2_bits))
+    } else {
+        ((indices2, mode.index2_bits), (indices, mode.index_bits))
+    };
+
+    let weight = |bits: u32, i: usize| match bits {
+        2 => BC7_WEIGHTS_2[i],
+        3 => BC7_WEIGHTS_3[i],
+        _ => BC7_WEIGHTS_4[i],
+    };
+
+    for (i, p) in pixels.iter_mut().enumerate() {
+        let [e0, e1] = endpoints[subset_of(i)];
+
+        for c in 0..4 {
+            let w = if c == 3 {
+                weight(alpha_bits, alpha_i[i])
+            } else {
+                weight(color_bits, color_i[i])
+            };
+            p[c] = (((64 - w) * e0[c] + w * e1[c] + 32) >> 6) as u8;
+        }
+
+        match rotation {
+            1 => p.swap(0, 3),
+            2 => p.swap(1, 3),
+            3 => p.swap(2, 3),
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! User-defined compute passes. These are encoded into the same command encoder as the
+//! render pass, either before or after it, so a compute shader can write data (eg instance
+//! transforms, or deformed vertices) that the render pass consumes the same frame.
+//!
+//! WGPU tracks buffer usage between passes within an encoder, so ordering passes in the encoder
+//! is sufficient to express the dependency; no explicit barriers are needed.
+//!
+//! `ComputePass::new_mesh_deform` sets up a pass that deforms a single mesh's vertices in place
+//! each frame, eg for wave or field visualizations, without round-tripping vertices through the CPU.
+//!
+//! This requires the `compute` feature. Applications that don't use compute passes can disable it
+//! to skip building this module; the engine's own compute passes, eg for culling, are unaffected.
+
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferUsages, CommandEncoder,
+    ComputePassDescriptor, ComputePipeline, Device, ShaderStages,
+};
+
+use crate::{
+    limits,
+    pass::{MeshBuffers, PassContext},
+    shader_interface::preprocess_wgsl,
+    timing::GpuTimer,
+    types::{Mesh, F32_SIZE},
+};
+
+/// The workgroup size mesh deformation shaders must use, ie `@workgroup_size(64)`.
+pub const DEFORM_WORKGROUP_SIZE: u32 = 64;
+
+const MESH_PARAMS_SIZE: usize = 4 * F32_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Where in the frame's command encoder a compute pass runs, relative to the render pass.
+pub enum ComputeStage {
+    /// Runs before the render pass; use this for data the render pass reads, eg instance
+    /// transforms or vertex positions.
+    PreRender,
+    /// Runs after the render pass (and GUI), eg for reading back or post-processing data.
+    PostRender,
+}
+
+#[derive(Clone, Debug)]
+/// A buffer bound to a compute pass. Bindings are assigned to group 0, in the order they're
+/// listed in `ComputePass::bindings`; ie the first is `@binding(0)`.
+pub enum ComputeBinding {
+    /// The engine's instance buffer, as `var<storage, read_write>`. Each instance is
+    /// `INSTANCE_SIZE` bytes; bind it as `array<f32>` and index accordingly. The buffer may have
+    /// unused space after the last instance, so `arrayLength` doesn't give the instance count.
+    Instances,
+    /// The engine's vertex buffer, as `var<storage, read_write>`. Each vertex is `VERTEX_SIZE`
+    /// bytes; bind it as `array<f32>` and index accordingly. As with `Instances`, the buffer may
+    /// have unused space at its end.
+    Vertices,
+    /// A uniform buffer with user data, eg time, or simulation parameters. This is written to
+    /// the GPU each frame, so it may be changed from the render handler without a rebuild.
+    Uniform(Vec<u8>),
+    /// A `var<storage, read_write>` buffer with user data. This is uploaded when the pass is
+    /// built, and persists on the GPU between frames.
+    Storage(Vec<u8>),
+    /// A uniform describing a mesh, by its index in `Scene::meshes`, and timing. This is updated
+    /// by the engine each frame. Its WGSL layout is:
+    ///
+    /// `struct MeshParams { vertex_start: u32, vertex_count: u32, time: f32, dt: f32 }`
+    ///
+    /// `vertex_start` is the index of the mesh's first vertex in the vertex buffer. `time` is
+    /// seconds since the engine started, and `dt` is seconds since the previous frame.
+    Mesh(usize),
+}
+
+#[derive(Clone, Debug)]
+/// A compute shader, and how to dispatch it each frame. Add these to `Scene::compute_passes`,
+/// and set `EngineUpdates::compute` when adding, removing, or changing their bindings.
+pub struct ComputePass {
+    pub label: String,
+    /// WGSL source code. This may include the engine's shader chunks, eg `#include "instance"`;
+    /// see the `shader_interface` module.
+    pub shader: String,
+    pub entry_point: String,
+    pub stage: ComputeStage,
+    pub bindings: Vec<ComputeBinding>,
+    /// Number of workgroups dispatched in x, y, and z.
+    pub workgroups: (u32, u32, u32),
+}
+
+/// GPU state for a single compute pass. We keep the pipeline and user buffers; the bind group is
+/// created at dispatch, since the vertex and instance buffers are recreated when they change size.
+pub(crate) struct ComputePipelineData {
+    pub pipeline: ComputePipeline,
+    pub layout: BindGroupLayout,
+    /// Indices correspond to the pass's bindings; `None` for engine-owned buffers.
+    pub user_bufs: Vec<Option<Buffer>>,
+}
+
+impl ComputePass {
+    /// Create a pass that deforms a mesh's vertices in place each frame. Bindings are:
+    ///
+    /// - 0: `Vertices`; the vertex buffer
+    /// - 1: `Mesh`; the mesh's location in the vertex buffer, and timing
+    /// - 2: `Storage`; the mesh's undeformed vertices, in the same format as the vertex buffer
+    /// - 3: `Uniform`; `params`, user data which may be updated each frame
+    ///
+    /// The shader must use a workgroup size of `DEFORM_WORKGROUP_SIZE`, and is dispatched with
+    /// one invocation per vertex, rounded up. Note that meshes with identical contents share
+    /// vertex buffer space; deform a mesh that's unique in the scene.
+    pub fn new_mesh_deform(
+        label: &str,
+        shader: &str,
+        entry_point: &str,
+        meshes: &[Mesh],
+        mesh_i: usize,
+        params: Vec<u8>,
+    ) -> Self {
+        let mut rest_vertices = Vec::new();
+        let mut vertex_count = 0;
+
+        if let Some(mesh) = meshes.get(mesh_i) {
+            for vertex in &mesh.vertices {
+                rest_vertices.extend_from_slice(&vertex.to_bytes());
+            }
+            vertex_count = mesh.vertices.len() as u32;
+        }
+
+        Self {
+            label: label.to_owned(),
+            shader: shader.to_owned(),
+            entry_point: entry_point.to_owned(),
+            stage: ComputeStage::PreRender,
+            bindings: vec![
+                ComputeBinding::Vertices,
+                ComputeBinding::Mesh(mesh_i),
+                ComputeBinding::Storage(rest_vertices),
+                ComputeBinding::Uniform(params),
+            ],
+            workgroups: (vertex_count.div_ceil(DEFORM_WORKGROUP_SIZE), 1, 1),
+        }
+    }
+}
+
+impl ComputePipelineData {
+    /// Fails if the shader's directives are invalid, eg if it requires a different interface
+    /// version.
+    pub fn new(device: &Device, pass: &ComputePass) -> Result<Self, String> {
+        let source = preprocess_wgsl(&pass.shader)
+            .map_err(|e| format!("Compute pass \"{}\": {e}", pass.label))?;
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(&pass.label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        let mut layout_entries = Vec::new();
+        let mut user_bufs = Vec::new();
+
+        for (i, binding) in pass.bindings.iter().enumerate() {
+            let (ty, buf) = match binding {
+                ComputeBinding::Instances | ComputeBinding::Vertices => {
+                    (BufferBindingType::Storage { read_only: false }, None)
+                }
+                ComputeBinding::Uniform(data) => (
+                    BufferBindingType::Uniform,
+                    Some(device.create_buffer_init(&BufferInitDescriptor {
+                        label: Some("Compute uniform buffer"),
+                        contents: data,
+                        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                    })),
+                ),
+                ComputeBinding::Storage(data) => (
+                    BufferBindingType::Storage { read_only: false },
+                    Some(device.create_buffer_init(&BufferInitDescriptor {
+                        label: Some("Compute storage buffer"),
+                        contents: data,
+                        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                    })),
+                ),
+                ComputeBinding::Mesh(_) => (
+                    BufferBindingType::Uniform,
+                    Some(device.create_buffer_init(&BufferInitDescriptor {
+                        label: Some("Compute mesh params buffer"),
+                        contents: &[0; MESH_PARAMS_SIZE],
+                        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                    })),
+                ),
+            };
+
+            layout_entries.push(wgpu::BindGroupLayoutEntry {
+                binding: i as u32,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            });
+            user_bufs.push(buf);
+        }
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &layout_entries,
+            label: Some("Compute bind group layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Compute pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&pass.label),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some(&pass.entry_point),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        Ok(Self {
+            pipeline,
+            layout,
+            user_bufs,
+        })
+    }
+}
+
+/// Encode all compute passes for a given stage; `passes` pairs each with its pipeline, and those
+/// that failed to build are skipped. The vertex and instance buffers of `buffers` are bound where
+/// the pass requests them. Their mesh ranges, `time`, and the context's `dt` are used for `Mesh`
+/// bindings. If `timer` is present, each pass writes timestamps at its start and end.
+pub(crate) fn encode_passes<'a>(
+    passes: impl Iterator<Item = (&'a ComputePass, &'a Option<ComputePipelineData>)>,
+    stage: ComputeStage,
+    ctx: &PassContext,
+    encoder: &mut CommandEncoder,
+    buffers: &MeshBuffers,
+    time: f32,
+    timer: Option<&GpuTimer>,
+) {
+    let PassContext {
+        device, queue, dt, ..
+    } = *ctx;
+
+    for (i_pass, (pass, data)) in passes.enumerate() {
+        let Some(data) = data.as_ref().filter(|_| pass.stage == stage) else {
+            continue;
+        };
+
+        let mut entries = Vec::new();
+        let mut mesh_missing = false;
+
+        for (i, binding) in pass.bindings.iter().enumerate() {
+            let buf = match binding {
+                ComputeBinding::Instances => buffers.instance_buf,
+                ComputeBinding::Vertices => buffers.vertex_buf,
+                ComputeBinding::Uniform(bytes) => {
+                    let buf = data.user_bufs[i].as_ref().unwrap();
+                    // Skip the write if the size changed; that requires a rebuild.
+                    if bytes.len() as u64 == buf.size() {
+                        queue.write_buffer(buf, 0, bytes);
+                    }
+                    buf
+                }
+                ComputeBinding::Storage(_) => data.user_bufs[i].as_ref().unwrap(),
+                ComputeBinding::Mesh(mesh_i) => {
+                    let buf = data.user_bufs[i].as_ref().unwrap();
+                    match buffers.mesh_ranges.get(*mesh_i) {
+                        Some(range) => {
+                            let mut params = [0; MESH_PARAMS_SIZE];
+                            let vertex_start = range.vertex_start as u32;
+                            params[0..4].clone_from_slice(&vertex_start.to_ne_bytes());
+                            params[4..8].clone_from_slice(&range.vertex_count.to_ne_bytes());
+                            params[8..12].clone_from_slice(&time.to_ne_bytes());
+                            params[12..16].clone_from_slice(&dt.to_ne_bytes());
+                            queue.write_buffer(buf, 0, &params);
+                        }
+                        None => mesh_missing = true,
+                    }
+                    buf
+                }
+            };
+
+            // The engine's buffers may be larger than a storage binding.
+            entries.push(wgpu::BindGroupEntry {
+                binding: i as u32,
+                resource: limits::storage_binding(device, buf),
+            });
+        }
+
+        // Binding an empty buffer is a validation error; this occurs eg prior to adding entities.
+        let any_empty = entries.iter().any(|e| match &e.resource {
+            wgpu::BindingResource::Buffer(b) => b.buffer.size() == 0,
+            _ => false,
+        });
+        // We also skip passes that refer to a mesh that doesn't exist.
+        if any_empty || mesh_missing {
+            continue;
+        }
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &data.layout,
+            entries: &entries,
+            label: Some("Compute bind group"),
+        });
+
+        let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some(&pass.label),
+            timestamp_writes: timer.map(|t| t.compute_writes(i_pass)),
+        });
+
+        cpass.set_pipeline(&data.pipeline);
+        cpass.set_bind_group(0, &bind_group, &[]);
+        cpass.dispatch_workgroups(pass.workgroups.0, pass.workgroups.1, pass.workgroups.2);
+    }
+}
@@ -0,0 +1,547 @@
+//! GPU occlusion culling, for dense scenes where most geometry is hidden behind other geometry.
+//! After the main pass, we build a depth pyramid (hierarchical Z) from the depth buffer: each
+//! level halves the size of the previous, and stores the farthest depth of the texels it covers.
+//! Before the next main pass, a compute pass tests each instance's bounding sphere against the
+//! view frustum, and against the pyramid, then copies visible instances to a compacted buffer.
+//! Meshes are drawn from that buffer using indirect draws, with instance counts written by the
+//! compute pass, so no data is read back to the CPU.
+//!
+//! Occlusion is tested against the previous frame's depth, so an object that becomes visible
+//! because the geometry in front of it moved may appear a frame late. Bounds are computed on the
+//! CPU from mesh vertices; meshes deformed by compute passes may be culled incorrectly if they
+//! grow past their original bounds. Shadow maps draw all instances.
+
+use lin_alg::f32::{Mat4, Vec3};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferUsages,
+    CommandEncoder, ComputePipeline, Device, ShaderStages, TextureFormat, TextureView,
+};
+
+use crate::{
+    camera::Camera,
+    limits,
+    pass::{MeshBuffers, PassContext},
+    types::{Scene, F32_SIZE, INSTANCE_SIZE, MAT4_SIZE, VEC4_SIZE},
+};
+
+/// The size of `DrawIndexedIndirectArgs`: index count, instance count, first index, base vertex,
+/// and first instance.
+pub(crate) const DRAW_ARGS_SIZE: usize = 5 * 4;
+
+/// Projection-view matrices for this and the previous frame, the previous frame's viewport,
+/// instance count and stride, the pyramid's level count, and if the pyramid is valid.
+const CULL_PARAMS_SIZE: usize = 2 * MAT4_SIZE + VEC4_SIZE + 4 * 4;
+
+/// Center, and radius.
+const MESH_BOUNDS_SIZE: usize = VEC4_SIZE;
+
+const CULL_WORKGROUP_SIZE: u32 = 64;
+const PYRAMID_WORKGROUP_SIZE: u32 = 8;
+
+const PYRAMID_FORMAT: TextureFormat = TextureFormat::R32Float;
+
+/// Local-space bounding spheres of each mesh, as (center, radius). Empty meshes have a radius
+/// of 0.
+pub(crate) fn mesh_bounds(scene: &mut Scene) -> Vec<(Vec3, f32)> {
+    (0..scene.meshes.len())
+        .map(|i| match scene.mesh_bounds(i) {
+            Some(b) => (b.center(), (b.max - b.min).magnitude() * 0.5),
+            None => (Vec3::new_zero(), 0.),
+        })
+        .collect()
+}
+
+/// Mesh bounds, as the `MeshBounds` array in `culling.wgsl` and `gpu_pick.wgsl`.
+pub(crate) fn bounds_data(mesh_bounds: &[(Vec3, f32)]) -> Vec<u8> {
+    // Storage bindings can't be empty.
+    let mut data = Vec::with_capacity(mesh_bounds.len().max(1) * MESH_BOUNDS_SIZE);
+    for (center, radius) in mesh_bounds {
+        data.extend_from_slice(&center.to_bytes_vertex());
+        data.extend_from_slice(&radius.to_ne_bytes());
+    }
+    data.resize(data.len().max(MESH_BOUNDS_SIZE), 0);
+    data
+}
+
+pub(crate) fn buf_entry(binding: u32, buf: &Buffer) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding,
+        resource: buf.as_entire_binding(),
+    }
+}
+
+/// The depth pyramid, and views used to build it.
+struct Pyramid {
+    width: u32,
+    height: u32,
+    mip_count: u32,
+    /// All levels; used for culling.
+    view: TextureView,
+    /// The first level; written from the depth buffer.
+    view_first: TextureView,
+    /// Reads each level, and writes the next one.
+    downsample_bind_groups: Vec<BindGroup>,
+}
+
+impl Pyramid {
+    fn new(device: &Device, layout_downsample: &BindGroupLayout, width: u32, height: u32) -> Self {
+        let width = width.max(1);
+        let height = height.max(1);
+        let mip_count = 32 - width.max(height).leading_zeros();
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Depth pyramid"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: mip_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: PYRAMID_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let mip_view = |level| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("Depth pyramid level"),
+                base_mip_level: level,
+                mip_level_count: Some(1),
+                ..Default::default()
+            })
+        };
+
+        let downsample_bind_groups = (1..mip_count)
+            .map(|level| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: layout_downsample,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&mip_view(level - 1)),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(&mip_view(level)),
+                        },
+                    ],
+                    label: Some("Depth pyramid downsample bind group"),
+                })
+            })
+            .collect();
+
+        Self {
+            width,
+            height,
+            mip_count,
+            view: texture.create_view(&Default::default()),
+            view_first: mip_view(0),
+            downsample_bind_groups,
+        }
+    }
+}
+
+/// Pipelines, buffers, and per-frame state used by occlusion culling.
+pub(crate) struct CullState {
+    pipeline_cull: ComputePipeline,
+    pipeline_copy: ComputePipeline,
+    pipeline_downsample: ComputePipeline,
+    layout_cull: BindGroupLayout,
+    layout_copy: BindGroupLayout,
+    layout_downsample: BindGroupLayout,
+    params_buf: Buffer,
+    /// The mesh each instance uses.
+    instance_meshes_buf: Buffer,
+    mesh_bounds_buf: Buffer,
+    /// Visible instances. Each mesh's instances start at the same index as in the instance buffer,
+    /// so this has the same size.
+    pub culled_buf: Buffer,
+    /// Indirect draw arguments for each mesh.
+    pub indirect_buf: Buffer,
+    /// Rebuilt when buffers it references change.
+    bind_group_cull: Option<BindGroup>,
+    pyramid: Pyramid,
+    instance_count: u32,
+    /// The projection-view matrix used for culling this frame; the pyramid is built with it.
+    proj_view: Mat4,
+    prev_proj_view: Mat4,
+    /// x, y, width, and height, in pixels.
+    prev_viewport: (f32, f32, f32, f32),
+    /// False until the pyramid is first built.
+    pyramid_valid: bool,
+}
+
+impl CullState {
+    pub fn new(device: &Device, width: u32, height: u32) -> Self {
+        let shader_cull = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Culling shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("culling.wgsl").into()),
+        });
+
+        let shader_pyramid = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Depth pyramid shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("pyramid.wgsl").into()),
+        });
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let pyramid_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+
+        let pyramid_out_entry = wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: PYRAMID_FORMAT,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+
+        let layout_cull = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, true),
+                storage_entry(3, true),
+                storage_entry(4, false),
+                storage_entry(5, false),
+                pyramid_entry(6),
+            ],
+            label: Some("Culling bind group layout"),
+        });
+
+        let layout_copy = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                pyramid_out_entry,
+            ],
+            label: Some("Depth pyramid copy bind group layout"),
+        });
+
+        let layout_downsample = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[pyramid_entry(1), pyramid_out_entry],
+            label: Some("Depth pyramid downsample bind group layout"),
+        });
+
+        let create_pipeline = |label, layout: &BindGroupLayout, shader, entry_point| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: shader,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+
+        let pipeline_cull = create_pipeline("Culling pipeline", &layout_cull, &shader_cull, "cull");
+        let pipeline_copy = create_pipeline(
+            "Depth pyramid copy pipeline",
+            &layout_copy,
+            &shader_pyramid,
+            "copy_depth",
+        );
+        let pipeline_downsample = create_pipeline(
+            "Depth pyramid downsample pipeline",
+            &layout_downsample,
+            &shader_pyramid,
+            "downsample",
+        );
+
+        let params_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Culling params buffer"),
+            contents: &[0; CULL_PARAMS_SIZE],
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let pyramid = Pyramid::new(device, &layout_downsample, width, height);
+
+        // Placeholders; these are replaced in `update_instances`.
+        let placeholder = |label, usage| {
+            device.create_buffer_init(&BufferInitDescriptor {
+                label: Some(label),
+                contents: &[0; DRAW_ARGS_SIZE],
+                usage,
+            })
+        };
+
+        Self {
+            pipeline_cull,
+            pipeline_copy,
+            pipeline_downsample,
+            layout_cull,
+            layout_copy,
+            layout_downsample,
+            params_buf,
+            instance_meshes_buf: placeholder("Instance mesh buffer", BufferUsages::STORAGE),
+            mesh_bounds_buf: placeholder("Mesh bounds buffer", BufferUsages::STORAGE),
+            culled_buf: placeholder("Culled instance buffer", BufferUsages::STORAGE),
+            indirect_buf: placeholder("Indirect draw buffer", BufferUsages::INDIRECT),
+            bind_group_cull: None,
+            pyramid,
+            instance_count: 0,
+            proj_view: Mat4::new_identity(),
+            prev_proj_view: Mat4::new_identity(),
+            prev_viewport: (0., 0., 0., 0.),
+            pyramid_valid: false,
+        }
+    }
+
+    /// If there are instances to cull. If not, draw without culling.
+    pub fn active(&self) -> bool {
+        self.instance_count > 0
+    }
+
+    /// Rebuild buffers after instances change. `instance_meshes` is the mesh each instance uses.
+    pub fn update_instances(
+        &mut self,
+        device: &Device,
+        instance_meshes: &[u32],
+        mesh_bounds: &[(Vec3, f32)],
+    ) {
+        // We bind the instance buffer, and the culled copy, as storage. If they're larger than a
+        // storage binding, we draw without culling.
+        let instance_size = (instance_meshes.len() * INSTANCE_SIZE) as u64;
+        if instance_size > limits::max_storage_size(device) {
+            self.instance_count = 0;
+            self.bind_group_cull = None;
+            return;
+        }
+
+        self.instance_count = instance_meshes.len() as u32;
+
+        // Storage bindings can't be empty.
+        let mut mesh_data = Vec::with_capacity(instance_meshes.len().max(1) * F32_SIZE);
+        for mesh in instance_meshes {
+            mesh_data.extend_from_slice(&mesh.to_ne_bytes());
+        }
+        mesh_data.resize(mesh_data.len().max(F32_SIZE), 0);
+
+        self.instance_meshes_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Instance mesh buffer"),
+            contents: &mesh_data,
+            usage: BufferUsages::STORAGE,
+        });
+
+        self.culled_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Culled instance buffer"),
+            size: (instance_meshes.len().max(1) * INSTANCE_SIZE) as u64,
+            usage: BufferUsages::VERTEX | BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        self.indirect_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Indirect draw buffer"),
+            size: (mesh_bounds.len().max(1) * DRAW_ARGS_SIZE) as u64,
+            usage: BufferUsages::INDIRECT | BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        self.update_bounds(device, mesh_bounds);
+    }
+
+    /// Rebuild the mesh bounds buffer, eg after mesh vertices change.
+    pub fn update_bounds(&mut self, device: &Device, mesh_bounds: &[(Vec3, f32)]) {
+        self.mesh_bounds_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Mesh bounds buffer"),
+            contents: &bounds_data(mesh_bounds),
+            usage: BufferUsages::STORAGE,
+        });
+
+        self.bind_group_cull = None;
+    }
+
+    /// Cull instances, writing visible ones to `culled_buf`, and their counts to `indirect_buf`.
+    /// Run this each frame, before the main pass.
+    pub fn encode_cull(
+        &mut self,
+        ctx: &PassContext,
+        encoder: &mut CommandEncoder,
+        camera: &Camera,
+        buffers: &MeshBuffers,
+    ) {
+        let PassContext { device, queue, .. } = *ctx;
+        let MeshBuffers {
+            instance_buf,
+            mesh_ranges,
+            mesh_mappings,
+            ..
+        } = *buffers;
+
+        // Reset instance counts to 0; the compute pass increments them.
+        let mut draw_data = Vec::with_capacity(mesh_mappings.len() * DRAW_ARGS_SIZE);
+        for (range, (instance_start, _)) in mesh_ranges.iter().zip(mesh_mappings) {
+            draw_data.extend_from_slice(&range.index_count.to_ne_bytes());
+            draw_data.extend_from_slice(&0_u32.to_ne_bytes());
+            draw_data.extend_from_slice(&range.index_start.to_ne_bytes());
+            draw_data.extend_from_slice(&range.vertex_start.to_ne_bytes());
+            draw_data.extend_from_slice(&instance_start.to_ne_bytes());
+        }
+        queue.write_buffer(&self.indirect_buf, 0, &draw_data);
+
+        self.proj_view = camera.proj_mat.clone() * camera.view_mat();
+
+        let (x, y, width, height) = self.prev_viewport;
+
+        let mut params = Vec::with_capacity(CULL_PARAMS_SIZE);
+        params.extend_from_slice(&self.proj_view.to_bytes());
+        params.extend_from_slice(&self.prev_proj_view.to_bytes());
+        for v in [x, y, width, height] {
+            params.extend_from_slice(&v.to_ne_bytes());
+        }
+        params.extend_from_slice(&self.instance_count.to_ne_bytes());
+        params.extend_from_slice(&((INSTANCE_SIZE / F32_SIZE) as u32).to_ne_bytes());
+        params.extend_from_slice(&self.pyramid.mip_count.to_ne_bytes());
+        params.extend_from_slice(&(self.pyramid_valid as u32).to_ne_bytes());
+
+        queue.write_buffer(&self.params_buf, 0, &params);
+
+        let bind_group = self.bind_group_cull.get_or_insert_with(|| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.layout_cull,
+                entries: &[
+                    buf_entry(0, &self.params_buf),
+                    // The buffer may have room for more instances than fit in a binding.
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: limits::storage_binding(device, instance_buf),
+                    },
+                    buf_entry(2, &self.instance_meshes_buf),
+                    buf_entry(3, &self.mesh_bounds_buf),
+                    buf_entry(4, &self.indirect_buf),
+                    buf_entry(5, &self.culled_buf),
+                    wgpu::BindGroupEntry {
+                        binding: 6,
+                        resource: wgpu::BindingResource::TextureView(&self.pyramid.view),
+                    },
+                ],
+                label: Some("Culling bind group"),
+            })
+        });
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Culling pass"),
+            timestamp_writes: None,
+        });
+
+        // Split workgroups over 2 dimensions if there are too many for one.
+        let groups = self.instance_count.div_ceil(CULL_WORKGROUP_SIZE);
+        let groups_x = groups.min(device.limits().max_compute_workgroups_per_dimension);
+        let groups_y = groups.div_ceil(groups_x);
+
+        pass.set_pipeline(&self.pipeline_cull);
+        pass.set_bind_group(0, &*bind_group, &[]);
+        pass.dispatch_workgroups(groups_x, groups_y, 1);
+    }
+
+    /// Build the depth pyramid from the main pass's depth buffer, for culling the next frame.
+    /// `width` and `height` are the size of the depth buffer; `viewport` is the portion of it used
+    /// for 3D rendering: x, y, width, and height, in pixels.
+    pub fn encode_pyramid(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        depth_view: &TextureView,
+        width: u32,
+        height: u32,
+        viewport: (f32, f32, f32, f32),
+    ) {
+        if width != self.pyramid.width || height != self.pyramid.height {
+            self.pyramid = Pyramid::new(device, &self.layout_downsample, width, height);
+            self.bind_group_cull = None;
+        }
+
+        // The depth texture is recreated on resize, so we don't keep this bind group.
+        let bind_group_copy = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.layout_copy,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(depth_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&self.pyramid.view_first),
+                },
+            ],
+            label: Some("Depth pyramid copy bind group"),
+        });
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Depth pyramid pass"),
+            timestamp_writes: None,
+        });
+
+        pass.set_pipeline(&self.pipeline_copy);
+        pass.set_bind_group(0, &bind_group_copy, &[]);
+        pass.dispatch_workgroups(
+            self.pyramid.width.div_ceil(PYRAMID_WORKGROUP_SIZE),
+            self.pyramid.height.div_ceil(PYRAMID_WORKGROUP_SIZE),
+            1,
+        );
+
+        pass.set_pipeline(&self.pipeline_downsample);
+        for (i, bind_group) in self.pyramid.downsample_bind_groups.iter().enumerate() {
+            let level = i as u32 + 1;
+            let level_width = (self.pyramid.width >> level).max(1);
+            let level_height = (self.pyramid.height >> level).max(1);
+
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(
+                level_width.div_ceil(PYRAMID_WORKGROUP_SIZE),
+                level_height.div_ceil(PYRAMID_WORKGROUP_SIZE),
+                1,
+            );
+        }
+
+        self.prev_proj_view = self.proj_view.clone();
+        self.prev_viewport = viewport;
+        self.pyramid_valid = true;
+    }
+}
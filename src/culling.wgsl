@@ -0,0 +1,181 @@
+// GPU occlusion culling. Tests each instance's bounding sphere against the view frustum, and
+// against a depth pyramid built from the previous frame (see `pyramid.wgsl`), then writes visible
+// instances to a compacted buffer, and counts them in each mesh's indirect draw arguments.
+
+const WORKGROUP_SIZE: u32 = 64u;
+
+struct CullParams {
+    proj_view: mat4x4<f32>,
+    // The camera used to render the depth pyramid.
+    prev_proj_view: mat4x4<f32>,
+    // The 3D viewport of the previous frame, in pixels: x, y, width, height.
+    prev_viewport: vec4<f32>,
+    instance_count: u32,
+    // In f32s.
+    instance_stride: u32,
+    mip_count: u32,
+    // 0 if the pyramid doesn't contain a previous frame, ie on the first frame. Only frustum
+    // culling is performed in that case.
+    pyramid_valid: u32,
+}
+
+// Matches `DrawIndexedIndirectArgs`.
+struct DrawArgs {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+// A bounding sphere, in the mesh's local space.
+struct MeshBounds {
+    center: vec3<f32>,
+    radius: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: CullParams;
+@group(0) @binding(1)
+var<storage, read> instances: array<f32>;
+// The mesh each instance uses.
+@group(0) @binding(2)
+var<storage, read> instance_meshes: array<u32>;
+@group(0) @binding(3)
+var<storage, read> mesh_bounds: array<MeshBounds>;
+@group(0) @binding(4)
+var<storage, read_write> draws: array<DrawArgs>;
+@group(0) @binding(5)
+var<storage, read_write> culled: array<f32>;
+@group(0) @binding(6)
+var pyramid: texture_2d<f32>;
+
+fn load_vec4(i: u32) -> vec4<f32> {
+    return vec4<f32>(instances[i], instances[i + 1u], instances[i + 2u], instances[i + 3u]);
+}
+
+// A box around the sphere is outside the frustum if all its corners are outside the same plane.
+// This is conservative; boxes near the frustum's edges may pass.
+fn in_frustum(center: vec3<f32>, radius: f32) -> bool {
+    var outside = array<u32, 6>(0u, 0u, 0u, 0u, 0u, 0u);
+    for (var i = 0u; i < 8u; i++) {
+        let corner = center + vec3<f32>(
+            select(-radius, radius, (i & 1u) != 0u),
+            select(-radius, radius, (i & 2u) != 0u),
+            select(-radius, radius, (i & 4u) != 0u),
+        );
+        let clip = params.proj_view * vec4<f32>(corner, 1.);
+
+        outside[0] += u32(clip.x < -clip.w);
+        outside[1] += u32(clip.x > clip.w);
+        outside[2] += u32(clip.y < -clip.w);
+        outside[3] += u32(clip.y > clip.w);
+        outside[4] += u32(clip.z < 0.);
+        outside[5] += u32(clip.z > clip.w);
+    }
+
+    for (var i = 0u; i < 6u; i++) {
+        if (outside[i] == 8u) {
+            return false;
+        }
+    }
+    return true;
+}
+
+fn occluded(center: vec3<f32>, radius: f32) -> bool {
+    var uv_min = vec2<f32>(1.);
+    var uv_max = vec2<f32>(0.);
+    var depth_nearest = 1.;
+
+    for (var i = 0u; i < 8u; i++) {
+        let corner = center + vec3<f32>(
+            select(-radius, radius, (i & 1u) != 0u),
+            select(-radius, radius, (i & 2u) != 0u),
+            select(-radius, radius, (i & 4u) != 0u),
+        );
+        let clip = params.prev_proj_view * vec4<f32>(corner, 1.);
+
+        // Crosses the near plane.
+        if (clip.w <= 0.) {
+            return false;
+        }
+
+        let ndc = clip.xyz / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+
+        uv_min = min(uv_min, uv);
+        uv_max = max(uv_max, uv);
+        depth_nearest = min(depth_nearest, ndc.z);
+    }
+
+    uv_min = clamp(uv_min, vec2<f32>(0.), vec2<f32>(1.));
+    uv_max = clamp(uv_max, vec2<f32>(0.), vec2<f32>(1.));
+
+    let px_min = params.prev_viewport.xy + uv_min * params.prev_viewport.zw;
+    let px_max = params.prev_viewport.xy + uv_max * params.prev_viewport.zw;
+
+    // Choose the level where the box covers at most 2x2 texels.
+    let size = max(px_max.x - px_min.x, px_max.y - px_min.y);
+    let level = min(u32(ceil(log2(max(size, 1.)))), params.mip_count - 1u);
+
+    let dims = vec2<i32>(textureDimensions(pyramid, level)) - 1;
+    let t_min = min(vec2<i32>(px_min) >> vec2<u32>(level), dims);
+    let t_max = min(vec2<i32>(px_max) >> vec2<u32>(level), dims);
+    let lod = i32(level);
+
+    let depth_farthest = max(
+        max(
+            textureLoad(pyramid, t_min, lod).r,
+            textureLoad(pyramid, vec2<i32>(t_max.x, t_min.y), lod).r,
+        ),
+        max(
+            textureLoad(pyramid, vec2<i32>(t_min.x, t_max.y), lod).r,
+            textureLoad(pyramid, t_max, lod).r,
+        ),
+    );
+
+    return depth_nearest > depth_farthest;
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE)
+fn cull(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+) {
+    // Workgroups may be split over 2 dimensions.
+    let i = id.x + id.y * groups.x * WORKGROUP_SIZE;
+    if (i >= params.instance_count) {
+        return;
+    }
+
+    let start = i * params.instance_stride;
+    // The model matrix is at the start of each instance.
+    let model = mat4x4<f32>(
+        load_vec4(start),
+        load_vec4(start + 4u),
+        load_vec4(start + 8u),
+        load_vec4(start + 12u),
+    );
+
+    let mesh = instance_meshes[i];
+    let bounds = mesh_bounds[mesh];
+
+    // Entities are uniformly scaled.
+    let center = (model * vec4<f32>(bounds.center, 1.)).xyz;
+    let radius = bounds.radius * length(model[0].xyz);
+
+    if (!in_frustum(center, radius)) {
+        return;
+    }
+    if (params.pyramid_valid != 0u && occluded(center, radius)) {
+        return;
+    }
+
+    let slot = draws[mesh].first_instance + atomicAdd(&draws[mesh].instance_count, 1u);
+    let dest = slot * params.instance_stride;
+
+    for (var j = 0u; j < params.instance_stride; j++) {
+        culled[dest + j] = instances[start + j];
+    }
+}
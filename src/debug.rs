@@ -0,0 +1,638 @@
+//! Debug visualization, drawn as lines over the scene. This includes gizmos that show the position,
+//! color, and range of lights, and the extent of their shadow maps, and entity shapes: vertex
+//! normals, bounding boxes and spheres, and wireframes. Line geometry is generated on the CPU,
+//! and rendered in the main pass with a separate pipeline.
+//!
+//! `DebugDraw`, in `Scene::debug_draw`, lets the application draw lines, spheres, and text for a
+//! single frame, eg from the render handler, without adding entities.
+
+use core::f32::consts::TAU;
+
+#[cfg(feature = "gui")]
+use egui::{Align2, Color32, Context, FontId, Id, LayerId, Order, Pos2};
+use lin_alg::f32::Vec3;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroupLayout, Buffer, BufferUsages, Device, FragmentState, RenderPass, RenderPipeline,
+    SurfaceConfiguration, TextureFormat, VertexState,
+};
+
+#[cfg(feature = "gui")]
+use crate::{
+    camera::{self, Camera},
+    color::rgba_to_srgb8,
+};
+use crate::{
+    graphics::{FWD_VEC, RIGHT_VEC, UP_VEC},
+    lighting::{LightType, PointLight},
+    shader_interface::engine_wgsl,
+    taa::VELOCITY_FORMAT,
+    types::{Entity, Mesh, F32_SIZE, VEC3_SIZE, VEC4_SIZE},
+};
+
+/// Position, and color.
+pub const LINE_VERTEX_SIZE: usize = VEC3_SIZE + VEC4_SIZE;
+
+/// Number of segments used to approximate circles.
+const CIRCLE_SEGMENTS: usize = 24;
+
+/// The size of the marker drawn at each light's position.
+const LIGHT_MARKER_SIZE: f32 = 0.5;
+
+/// The length of arrows showing the direction of directional lights.
+const LIGHT_ARROW_LEN: f32 = 3.;
+
+/// The length of vertex normal lines, relative to entity scale.
+const NORMAL_LEN: f32 = 0.2;
+
+const NORMAL_COLOR: [f32; 4] = [0.2, 0.4, 1., 1.];
+const AABB_COLOR: [f32; 4] = [0.2, 1., 0.2, 1.];
+const BOUNDING_SPHERE_COLOR: [f32; 4] = [1., 0.8, 0.2, 0.6];
+const WIREFRAME_COLOR: [f32; 4] = [1., 1., 1., 0.6];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+/// Shapes drawn over entities, to visualize their geometry. Set these globally in
+/// `DebugSettings::shapes`, or per entity in `Entity::debug`; an entity draws the shapes enabled
+/// in either.
+pub struct DebugShapes {
+    /// A line along each vertex's normal.
+    pub normals: bool,
+    /// The entity's axis-aligned bounding box, in world space.
+    pub aabb: bool,
+    pub bounding_sphere: bool,
+    /// Triangle edges.
+    pub wireframe: bool,
+}
+
+impl DebugShapes {
+    /// Shapes enabled in either.
+    pub fn combine(self, other: Self) -> Self {
+        Self {
+            normals: self.normals || other.normals,
+            aabb: self.aabb || other.aabb,
+            bounding_sphere: self.bounding_sphere || other.bounding_sphere,
+            wireframe: self.wireframe || other.wireframe,
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        self.normals || self.aabb || self.bounding_sphere || self.wireframe
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// Debug visualizations. These may be toggled at runtime by changing `Scene::debug`; no
+/// `EngineUpdates` flag is required.
+pub struct DebugSettings {
+    /// Draw a marker at each point light's position, in its diffuse color, a sphere showing its
+    /// range, and for directional lights, an arrow in the direction it points. The range is the
+    /// distance at which diffuse intensity, after inverse-square falloff, drops below 1.
+    pub light_gizmos: bool,
+    /// Draw the extent of shadow maps for shadow-casting lights.
+    pub shadow_frusta: bool,
+    /// Shapes drawn over all entities. Since these are generated along with instances,
+    /// changing an entity's own `debug` field requires setting `EngineUpdates::entities`.
+    pub shapes: DebugShapes,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct LineVertex {
+    pub position: Vec3,
+    pub color: [f32; 4],
+}
+
+impl LineVertex {
+    pub fn to_bytes(self) -> [u8; LINE_VERTEX_SIZE] {
+        let mut result = [0; LINE_VERTEX_SIZE];
+
+        result[0..VEC3_SIZE].clone_from_slice(&self.position.to_bytes_vertex());
+        for (i, c) in self.color.iter().enumerate() {
+            let start = VEC3_SIZE + i * F32_SIZE;
+            result[start..start + F32_SIZE].clone_from_slice(&c.to_ne_bytes());
+        }
+
+        result
+    }
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: LINE_VERTEX_SIZE as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: VEC3_SIZE as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Line list geometry, built up over a frame.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Lines {
+    pub vertices: Vec<LineVertex>,
+}
+
+impl Lines {
+    pub fn line(&mut self, a: Vec3, b: Vec3, color: [f32; 4]) {
+        self.vertices.push(LineVertex { position: a, color });
+        self.vertices.push(LineVertex { position: b, color });
+    }
+
+    /// A circle, in the plane normal to `axis`.
+    pub fn circle(&mut self, center: Vec3, axis: Vec3, radius: f32, color: [f32; 4]) {
+        // Two unit vectors spanning the circle's plane.
+        let reference = if axis.dot(UP_VEC).abs() < 0.9 {
+            UP_VEC
+        } else {
+            RIGHT_VEC
+        };
+        let u = axis.cross(reference).to_normalized();
+        let v = axis.cross(u).to_normalized();
+
+        let point = |i: usize| {
+            let θ = i as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+            center + (u * θ.cos() + v * θ.sin()) * radius
+        };
+
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+
+    /// An arc around `center`, from direction `from` to direction `to`, along the shorter path.
+    pub fn arc(&mut self, center: Vec3, from: Vec3, to: Vec3, radius: f32, color: [f32; 4]) {
+        if from.magnitude() == 0. || to.magnitude() == 0. {
+            return;
+        }
+        let u = from.to_normalized();
+        let to = to.to_normalized();
+
+        // A unit vector normal to `u`, in the plane of the arc.
+        let v = to - u * to.dot(u);
+        if v.magnitude() < 1e-6 {
+            return; // The directions are parallel.
+        }
+        let v = v.to_normalized();
+
+        let angle = to.dot(u).clamp(-1., 1.).acos();
+        let num_segments = ((angle / TAU * CIRCLE_SEGMENTS as f32).ceil() as usize).max(1);
+
+        let point = |i: usize| {
+            let θ = i as f32 / num_segments as f32 * angle;
+            center + (u * θ.cos() + v * θ.sin()) * radius
+        };
+
+        for i in 0..num_segments {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+
+    /// A sphere, approximated by circles around each axis.
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: [f32; 4]) {
+        for axis in [RIGHT_VEC, UP_VEC, FWD_VEC] {
+            self.circle(center, axis, radius, color);
+        }
+    }
+
+    /// An axis-aligned box.
+    pub fn aabb(&mut self, min: Vec3, max: Vec3, color: [f32; 4]) {
+        let corner = |i: usize| {
+            Vec3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+
+        // Connect corners that differ by a single axis.
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corner(i), corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    /// An arrow from `start` to `end`, with a head at `end`.
+    pub fn arrow(&mut self, start: Vec3, end: Vec3, color: [f32; 4]) {
+        self.line(start, end, color);
+
+        let diff = end - start;
+        let len = diff.magnitude();
+        if len == 0. {
+            return;
+        }
+        let dir = diff * (1. / len);
+
+        let reference = if dir.dot(UP_VEC).abs() < 0.9 {
+            UP_VEC
+        } else {
+            RIGHT_VEC
+        };
+        let side = dir.cross(reference).to_normalized();
+        let side_b = dir.cross(side).to_normalized();
+
+        let head_len = len * 0.2;
+        let head_base = end - dir * head_len;
+
+        for s in [side, side * -1., side_b, side_b * -1.] {
+            self.line(end, head_base + s * head_len * 0.5, color);
+        }
+    }
+
+    /// Add shapes for an entity; `entity` has any group transforms applied.
+    pub fn entity_shapes(&mut self, entity: &Entity, mesh: &Mesh, shapes: DebugShapes) {
+        if mesh.vertices.is_empty() {
+            return;
+        }
+
+        let to_world = |posit: &[f32; 3]| {
+            let v = Vec3::new(posit[0], posit[1], posit[2]) * entity.scale;
+            entity.orientation.rotate_vec(v) + entity.position
+        };
+
+        let world: Vec<Vec3> = mesh
+            .vertices
+            .iter()
+            .map(|v| to_world(&v.position))
+            .collect();
+
+        if shapes.normals {
+            for (vertex, posit) in mesh.vertices.iter().zip(&world) {
+                let normal = entity.orientation.rotate_vec(vertex.normal);
+                self.line(
+                    *posit,
+                    *posit + normal * NORMAL_LEN * entity.scale,
+                    NORMAL_COLOR,
+                );
+            }
+        }
+
+        if shapes.aabb {
+            let mut min = world[0];
+            let mut max = world[0];
+            for p in &world {
+                min = Vec3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+                max = Vec3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+            }
+            self.aabb(min, max, AABB_COLOR);
+        }
+
+        if shapes.bounding_sphere {
+            // Center on the mesh's local bounding box; this is a reasonable, if not minimal fit.
+            let first = mesh.vertices[0].position;
+            let (mut min, mut max) = (first, first);
+            for vertex in &mesh.vertices {
+                for i in 0..3 {
+                    min[i] = min[i].min(vertex.position[i]);
+                    max[i] = max[i].max(vertex.position[i]);
+                }
+            }
+            let center = [
+                (min[0] + max[0]) / 2.,
+                (min[1] + max[1]) / 2.,
+                (min[2] + max[2]) / 2.,
+            ];
+            let center_world = to_world(&center);
+
+            let radius = world
+                .iter()
+                .map(|p| (*p - center_world).magnitude())
+                .fold(0., f32::max);
+
+            self.sphere(center_world, radius, BOUNDING_SPHERE_COLOR);
+        }
+
+        if shapes.wireframe {
+            for tri in mesh.indices.chunks_exact(3) {
+                for (a, b) in [(tri[0], tri[1]), (tri[1], tri[2]), (tri[2], tri[0])] {
+                    if a < world.len() && b < world.len() {
+                        self.line(world[a], world[b], WIREFRAME_COLOR);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Add gizmos for each light. `max_shadow_lights` and `shadow_far` correspond to the shadow
+    /// maps' settings.
+    pub fn light_gizmos(
+        &mut self,
+        lights: &[PointLight],
+        settings: &DebugSettings,
+        max_shadow_lights: usize,
+        shadow_far: f32,
+    ) {
+        if settings.light_gizmos {
+            for light in lights {
+                let color = [
+                    light.diffuse_color[0],
+                    light.diffuse_color[1],
+                    light.diffuse_color[2],
+                    1.,
+                ];
+                let p = light.position;
+
+                for axis in [RIGHT_VEC, UP_VEC, FWD_VEC] {
+                    let offset = axis * LIGHT_MARKER_SIZE;
+                    self.line(p - offset, p + offset, color);
+                }
+
+                let range = light.diffuse_intensity.max(0.).sqrt();
+                if range > 0. {
+                    self.sphere(p, range, [color[0], color[1], color[2], 0.3]);
+                }
+
+                if let LightType::Directional(dir) = &light.type_ {
+                    self.arrow(p, p + dir.to_normalized() * LIGHT_ARROW_LEN, color);
+                }
+            }
+        }
+
+        if settings.shadow_frusta {
+            let color = [1., 1., 0., 0.5];
+            let shadow_lights = lights
+                .iter()
+                .filter(|l| l.casts_shadow)
+                .take(max_shadow_lights);
+
+            // Each cube face's frustum extends from the light to a face of a cube; together,
+            // they form a cube, with lines from the light to its corners.
+            for light in shadow_lights {
+                let p = light.position;
+                let extent = Vec3::new(shadow_far, shadow_far, shadow_far);
+                self.aabb(p - extent, p + extent, color);
+
+                for x in [-1., 1.] {
+                    for y in [-1., 1.] {
+                        for z in [-1., 1.] {
+                            self.line(p, p + Vec3::new(x, y, z) * shadow_far, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+struct DebugText {
+    posit: Vec3,
+    text: String,
+    color: [f32; 4],
+}
+
+#[derive(Clone, Debug, Default)]
+/// Immediate-mode debug drawing. Add shapes and text from a handler, eg the render handler; these
+/// are drawn that frame, then cleared. Colors are RGBA, from 0 to 1.
+pub struct DebugDraw {
+    lines: Lines,
+    texts: Vec<DebugText>,
+}
+
+impl DebugDraw {
+    pub fn line(&mut self, a: Vec3, b: Vec3, color: [f32; 4]) {
+        self.lines.line(a, b, color);
+    }
+
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: [f32; 4]) {
+        self.lines.sphere(center, radius, color);
+    }
+
+    pub fn arrow(&mut self, start: Vec3, end: Vec3, color: [f32; 4]) {
+        self.lines.arrow(start, end, color);
+    }
+
+    pub fn aabb(&mut self, min: Vec3, max: Vec3, color: [f32; 4]) {
+        self.lines.aabb(min, max, color);
+    }
+
+    /// An arc around `center`, from direction `from` to direction `to`, eg to show an angle.
+    pub fn arc(&mut self, center: Vec3, from: Vec3, to: Vec3, radius: f32, color: [f32; 4]) {
+        self.lines.arc(center, from, to, radius, color);
+    }
+
+    /// Text, centered on a position in world space. It's drawn over the scene, and isn't hidden
+    /// by geometry in front of it. Text is painted with the GUI, so it isn't drawn without the
+    /// `gui` feature.
+    pub fn text(&mut self, posit: Vec3, text: &str) {
+        self.text_color(posit, text, [1., 1., 1., 1.]);
+    }
+
+    pub fn text_color(&mut self, posit: Vec3, text: &str, color: [f32; 4]) {
+        self.texts.push(DebugText {
+            posit,
+            text: text.to_owned(),
+            color,
+        });
+    }
+
+    /// Remove everything drawn. The engine does this after each frame.
+    pub fn clear(&mut self) {
+        self.lines.vertices.clear();
+        self.texts.clear();
+    }
+
+    pub(crate) fn lines(&self) -> &Lines {
+        &self.lines
+    }
+
+    /// Paint text using the GUI. `viewport` is the (x, y, width, height) of the 3D viewport,
+    /// in pixels.
+    #[cfg(feature = "gui")]
+    pub(crate) fn paint_text(
+        &self,
+        ctx: &Context,
+        camera: &Camera,
+        viewport: (f32, f32, f32, f32),
+        pixels_per_point: f32,
+    ) {
+        if self.texts.is_empty() {
+            return;
+        }
+
+        let painter = ctx.layer_painter(LayerId::new(Order::Foreground, Id::new("debug_text")));
+        let proj_view = camera.proj_mat.clone() * camera.view_mat();
+        let (x, y, width, height) = viewport;
+
+        for text in &self.texts {
+            let Some((ndc_x, ndc_y, _)) = camera::project(&proj_view, text.posit) else {
+                continue;
+            };
+
+            let screen_x = x + (ndc_x * 0.5 + 0.5) * width;
+            let screen_y = y + (0.5 - ndc_y * 0.5) * height;
+
+            let c = rgba_to_srgb8(text.color);
+
+            painter.text(
+                Pos2::new(screen_x / pixels_per_point, screen_y / pixels_per_point),
+                Align2::CENTER_CENTER,
+                &text.text,
+                FontId::monospace(14.),
+                Color32::from_rgba_unmultiplied(c[0], c[1], c[2], c[3]),
+            );
+        }
+    }
+}
+
+/// The debug line pipelines, and the vertex buffer for this frame's lines.
+pub(crate) struct LineRenderer {
+    pipeline: RenderPipeline,
+    /// Used when TAA is enabled; this has an additional velocity target.
+    pipeline_taa: RenderPipeline,
+    buf: Buffer,
+    num_vertices: u32,
+}
+
+impl LineRenderer {
+    pub fn new(
+        device: &Device,
+        surface_cfg: &SurfaceConfiguration,
+        depth_format: TextureFormat,
+        layout_cam: &BindGroupLayout,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Line shader"),
+            source: wgpu::ShaderSource::Wgsl(engine_wgsl(include_str!("lines.wgsl")).into()),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Line pipeline layout"),
+            bind_group_layouts: &[layout_cam],
+            push_constant_ranges: &[],
+        });
+
+        let buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Line vertex buffer"),
+            contents: &[],
+            usage: BufferUsages::VERTEX,
+        });
+
+        Self {
+            pipeline: create_pipeline(
+                device,
+                &pipeline_layout,
+                &shader,
+                surface_cfg,
+                depth_format,
+                false,
+            ),
+            pipeline_taa: create_pipeline(
+                device,
+                &pipeline_layout,
+                &shader,
+                surface_cfg,
+                depth_format,
+                true,
+            ),
+            buf,
+            num_vertices: 0,
+        }
+    }
+
+    /// Upload this frame's lines.
+    pub fn update(&mut self, device: &Device, lines: &Lines) {
+        self.num_vertices = lines.vertices.len() as u32;
+        if lines.vertices.is_empty() {
+            return;
+        }
+
+        let mut data = Vec::with_capacity(lines.vertices.len() * LINE_VERTEX_SIZE);
+        for vertex in &lines.vertices {
+            data.extend_from_slice(&vertex.to_bytes());
+        }
+
+        // We can't update using a queue due to buffer size mismatches.
+        self.buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Line vertex buffer"),
+            contents: &data,
+            usage: BufferUsages::VERTEX,
+        });
+    }
+
+    /// Draw lines in the main render pass. The camera bind group must be set.
+    pub fn draw(&self, rpass: &mut RenderPass, taa: bool) {
+        if self.num_vertices == 0 {
+            return;
+        }
+
+        if taa {
+            rpass.set_pipeline(&self.pipeline_taa);
+        } else {
+            rpass.set_pipeline(&self.pipeline);
+        }
+
+        rpass.set_vertex_buffer(0, self.buf.slice(..));
+        rpass.draw(0..self.num_vertices, 0..1);
+    }
+}
+
+fn create_pipeline(
+    device: &Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    config: &SurfaceConfiguration,
+    depth_format: TextureFormat,
+    taa: bool,
+) -> RenderPipeline {
+    let color_target = Some(wgpu::ColorTargetState {
+        format: config.format,
+        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+        write_mask: wgpu::ColorWrites::ALL,
+    });
+
+    let velocity_target = Some(wgpu::ColorTargetState {
+        format: VELOCITY_FORMAT,
+        blend: None,
+        write_mask: wgpu::ColorWrites::ALL,
+    });
+
+    let (fs_entry_point, targets) = if taa {
+        ("fs_main_taa", vec![color_target, velocity_target])
+    } else {
+        ("fs_main", vec![color_target])
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Line pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[LineVertex::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: Some(fs_entry_point),
+            compilation_options: Default::default(),
+            targets: &targets,
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::LineList,
+            ..Default::default()
+        },
+        // Lines are hidden by geometry in front of them, but don't occlude anything themselves.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
@@ -0,0 +1,435 @@
+//! Debug views of intermediate render targets, eg the depth buffer, or a light's shadow map. When
+//! `Scene::debug_view` is set, the engine draws its target in a corner of the 3D viewport, after
+//! the scene, under the HUD and GUI; toggle this at runtime with `DebugView::show`, eg from a
+//! shortcut. Setting `DebugView::save_path` writes the target to an image file at its own
+//! resolution, at the end of the next frame.
+//!
+//! Values are mapped to colors: depth and distance to gray, from black near the camera, normals
+//! from -1 to 1 to RGB, and velocities from -1 to 1 to red and green. Targets are shown as they
+//! were left by the frame's last pass to write them; with extra views or an inset, this is the
+//! last view's. Targets only exist with the settings that use them; eg velocity with TAA. If the
+//! target doesn't exist, nothing is shown. There's no SSAO, or ID buffer to view; GPU picking
+//! renders IDs on demand, for a single pixel.
+
+use std::path::PathBuf;
+
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferUsages,
+    CommandEncoder, Device, FragmentState, Queue, RenderPassDescriptor, RenderPipeline,
+    ShaderModule, ShaderStages, StoreOp, TextureFormat, TextureUsages, TextureView, VertexState,
+};
+
+use crate::{
+    camera::Camera,
+    headless::read_texture,
+    inset::{corner_rect, InsetCorner},
+    texture::Texture,
+    types::{F32_SIZE, VEC4_SIZE},
+};
+
+/// Saved images use this format, so they can be read back as they're rendered.
+const SAVE_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+
+/// The target rectangle, mode, sRGB and orthographic flags, scale, and near and far planes,
+/// padded.
+const PARAMS_SIZE: usize = 3 * VEC4_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+/// An engine texture to view.
+pub enum DebugTarget {
+    /// The main depth buffer, as distance from the camera, normalized to its far plane.
+    #[default]
+    Depth,
+    /// One cube face of a shadow-casting light's shadow map, as distance from the light. `light`
+    /// indexes shadow-casting lights, in order; `face` is from 0 to 5, in the order +X, -X, +Y, -Y,
+    /// +Z, -Z.
+    ShadowMap { light: usize, face: usize },
+    /// Screen-space motion since the previous frame. Requires TAA.
+    Velocity,
+    /// Base color. Requires deferred shading.
+    GBufferAlbedo,
+    /// World space normals. Requires deferred shading.
+    GBufferNormal,
+    /// Lighting factors, and reflectivity. Requires deferred shading.
+    GBufferMaterial,
+}
+
+impl DebugTarget {
+    /// How the shader maps values to colors; see `debug_view.wgsl`.
+    fn mode(self) -> u32 {
+        match self {
+            Self::GBufferAlbedo | Self::GBufferMaterial => 0,
+            Self::GBufferNormal => 1,
+            Self::Velocity => 2,
+            Self::Depth => 3,
+            Self::ShadowMap { .. } => 4,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DebugView {
+    pub target: DebugTarget,
+    /// Draw the target in a corner of the 3D viewport.
+    pub show: bool,
+    pub corner: InsetCorner,
+    /// Width and height, in pixels. This is clipped to the 3D viewport. The target is stretched to
+    /// fit.
+    pub size: (u32, u32),
+    /// The distance from the edges of the 3D viewport, in pixels.
+    pub margin: u32,
+    /// Multiplies values before they're mapped to colors, eg to see small velocities, or depth
+    /// near the camera.
+    pub scale: f32,
+    /// If set, the engine saves the target to this path at the end of the next frame, and clears
+    /// it. The format is inferred from the extension, eg `.png`.
+    pub save_path: Option<PathBuf>,
+}
+
+impl Default for DebugView {
+    fn default() -> Self {
+        Self {
+            target: Default::default(),
+            show: true,
+            corner: InsetCorner::BottomRight,
+            size: (320, 180),
+            margin: 16,
+            scale: 1.,
+            save_path: None,
+        }
+    }
+}
+
+impl DebugView {
+    pub fn new(target: DebugTarget) -> Self {
+        Self {
+            target,
+            ..Default::default()
+        }
+    }
+}
+
+/// A texture to view, its width and height, and whether it's a depth texture, vice color.
+pub(crate) struct DebugSource<'a> {
+    pub view: &'a TextureView,
+    pub size: (u32, u32),
+    pub depth: bool,
+}
+
+impl<'a> DebugSource<'a> {
+    /// A color texture.
+    pub fn color(texture: &'a Texture) -> Self {
+        Self {
+            view: &texture.view,
+            size: (texture.texture.width(), texture.texture.height()),
+            depth: false,
+        }
+    }
+}
+
+fn params_bytes(
+    rect: (u32, u32, u32, u32),
+    srgb_target: bool,
+    view: &DebugView,
+    camera: &Camera,
+) -> [u8; PARAMS_SIZE] {
+    let mut result = [0; PARAMS_SIZE];
+
+    let (x, y, width, height) = rect;
+    let values = [
+        (x as f32).to_ne_bytes(),
+        (y as f32).to_ne_bytes(),
+        (width as f32).to_ne_bytes(),
+        (height as f32).to_ne_bytes(),
+        view.target.mode().to_ne_bytes(),
+        (srgb_target as u32).to_ne_bytes(),
+        (camera.ortho_height.is_some() as u32).to_ne_bytes(),
+        view.scale.to_ne_bytes(),
+        camera.near.to_ne_bytes(),
+        camera.far.to_ne_bytes(),
+    ];
+
+    for (i, v) in values.iter().enumerate() {
+        result[i * F32_SIZE..(i + 1) * F32_SIZE].clone_from_slice(v);
+    }
+
+    result
+}
+
+/// Pipelines for color and depth sources, rendering to one format.
+struct DebugPipelines {
+    color: RenderPipeline,
+    depth: RenderPipeline,
+    params_buf: Buffer,
+    params_bind_group: BindGroup,
+}
+
+impl DebugPipelines {
+    fn new(
+        device: &Device,
+        shader: &ShaderModule,
+        layouts: [&BindGroupLayout; 3],
+        format: TextureFormat,
+    ) -> Self {
+        let [layout_params, layout_color, layout_depth] = layouts;
+
+        let create_pipeline = |layout_source, entry_point| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Debug view pipeline layout"),
+                bind_group_layouts: &[layout_params, layout_source],
+                push_constant_ranges: &[],
+            });
+
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Debug view pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: VertexState {
+                    module: shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[],
+                },
+                fragment: Some(FragmentState {
+                    module: shader,
+                    entry_point: Some(entry_point),
+                    compilation_options: Default::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        let params_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Debug view params buffer"),
+            contents: &[0; PARAMS_SIZE],
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let params_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: layout_params,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params_buf.as_entire_binding(),
+            }],
+            label: Some("Debug view params bind group"),
+        });
+
+        Self {
+            color: create_pipeline(layout_color, "fs_color"),
+            depth: create_pipeline(layout_depth, "fs_depth"),
+            params_buf,
+            params_bind_group,
+        }
+    }
+}
+
+/// Draws debug views to the output, and renders them to textures for saving.
+pub(crate) struct DebugViewRenderer {
+    /// The format of the output we draw to.
+    pub format: TextureFormat,
+    layout_color: BindGroupLayout,
+    layout_depth: BindGroupLayout,
+    display: DebugPipelines,
+    save: DebugPipelines,
+    /// Rendered this frame, and saved once it's submitted.
+    pending_save: Option<(wgpu::Texture, PathBuf)>,
+}
+
+impl DebugViewRenderer {
+    /// `format` is that of the output.
+    pub fn new(device: &Device, format: TextureFormat) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Debug view shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("debug_view.wgsl").into()),
+        });
+
+        let layout_params = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("Debug view params bind group layout"),
+        });
+
+        let texture_entry = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type,
+            },
+            count: None,
+        };
+
+        let layout_color = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(
+                0,
+                wgpu::TextureSampleType::Float { filterable: false },
+            )],
+            label: Some("Debug view color bind group layout"),
+        });
+
+        let layout_depth = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(1, wgpu::TextureSampleType::Depth)],
+            label: Some("Debug view depth bind group layout"),
+        });
+
+        let layouts = [&layout_params, &layout_color, &layout_depth];
+
+        Self {
+            format,
+            display: DebugPipelines::new(device, &shader, layouts, format),
+            save: DebugPipelines::new(device, &shader, layouts, SAVE_FORMAT),
+            layout_color,
+            layout_depth,
+            pending_save: None,
+        }
+    }
+
+    /// Draw `source` over `rect` of `target`, which is (x, y, width, height) in pixels. Write
+    /// `pipelines`' params first.
+    fn encode_pass(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        pipelines: &DebugPipelines,
+        target: &TextureView,
+        source: &DebugSource,
+        rect: (u32, u32, u32, u32),
+    ) {
+        let (layout, binding, pipeline) = if source.depth {
+            (&self.layout_depth, 1, &pipelines.depth)
+        } else {
+            (&self.layout_color, 0, &pipelines.color)
+        };
+
+        let source_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(source.view),
+            }],
+            label: Some("Debug view source bind group"),
+        });
+
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Debug view render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        let (x, y, width, height) = rect;
+        rpass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0., 1.);
+        rpass.set_scissor_rect(x, y, width, height);
+
+        rpass.set_pipeline(pipeline);
+        rpass.set_bind_group(0, &pipelines.params_bind_group, &[]);
+        rpass.set_bind_group(1, &source_bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+
+    /// Draw the view in its corner of the 3D viewport of `output`, which has this renderer's
+    /// format. `viewport` is (x, y, width, height), in pixels. `camera` is the one the main depth
+    /// buffer was rendered with.
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode_display(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        output: &TextureView,
+        viewport: (f32, f32, f32, f32),
+        view: &DebugView,
+        source: &DebugSource,
+        camera: &Camera,
+    ) {
+        let Some(rect) = corner_rect(view.corner, view.size, view.margin, viewport) else {
+            return;
+        };
+
+        let params = params_bytes(rect, self.format.is_srgb(), view, camera);
+        queue.write_buffer(&self.display.params_buf, 0, &params);
+
+        self.encode_pass(device, encoder, &self.display, output, source, rect);
+    }
+
+    /// Render the view to a texture the size of its source, to save to `path` with `finish_save`
+    /// once the frame is submitted.
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode_save(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        view: &DebugView,
+        source: &DebugSource,
+        camera: &Camera,
+        path: PathBuf,
+    ) {
+        let (width, height) = source.size;
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Debug view save texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SAVE_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let target = texture.create_view(&Default::default());
+
+        let rect = (0, 0, width, height);
+        let params = params_bytes(rect, false, view, camera);
+        queue.write_buffer(&self.save.params_buf, 0, &params);
+
+        self.encode_pass(device, encoder, &self.save, &target, source, rect);
+
+        self.pending_save = Some((texture, path));
+    }
+
+    /// Save the view rendered by `encode_save`, if any. Run this after submitting the frame. This
+    /// blocks until the GPU is done.
+    pub fn finish_save(&mut self, device: &Device, queue: &Queue) {
+        let Some((texture, path)) = self.pending_save.take() else {
+            return;
+        };
+
+        let result = read_texture(device, queue, &texture)
+            .and_then(|image| image.save(&path).map_err(|e| e.to_string()));
+
+        if let Err(e) = result {
+            println!("Error saving the debug view to {path:?}: {e}");
+        }
+    }
+}
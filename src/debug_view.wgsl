@@ -0,0 +1,109 @@
+// Displays an engine texture, eg the depth buffer or a shadow map, for debugging; see
+// `debug_view.rs`. Values are mapped to display colors, and scaled to the target rectangle.
+
+struct DebugViewParams {
+    // The target rectangle: x, y, width, height, in pixels.
+    rect: vec4<f32>,
+    // See `DebugTarget::mode`.
+    mode: u32,
+    // 1 if the target has an sRGB format, which encodes our output.
+    srgb_target: u32,
+    // 1 if the depth buffer is from an orthographic projection, so is already linear.
+    ortho: u32,
+    scale: f32,
+    near: f32,
+    far: f32,
+}
+
+const MODE_COLOR: u32 = 0u;
+const MODE_NORMAL: u32 = 1u;
+const MODE_VELOCITY: u32 = 2u;
+const MODE_DEPTH: u32 = 3u;
+const MODE_DISTANCE: u32 = 4u;
+
+@group(0) @binding(0)
+var<uniform> params: DebugViewParams;
+// Only one of these is bound, depending on the source's format.
+@group(1) @binding(0)
+var color_tex: texture_2d<f32>;
+@group(1) @binding(1)
+var depth_tex: texture_depth_2d;
+
+struct VertexOut {
+    @builtin(position) posit: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> VertexOut {
+    // A single triangle that covers the viewport, which is set to the target rectangle.
+    var uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+
+    var result: VertexOut;
+    result.posit = vec4<f32>(uv * vec2<f32>(2., -2.) + vec2<f32>(-1., 1.), 0., 1.);
+
+    return result;
+}
+
+// The source pixel under a target pixel, stretching the source over the target rectangle.
+fn source_pixel(posit: vec2<f32>, dims: vec2<u32>) -> vec2<i32> {
+    var uv = (posit - params.rect.xy) / params.rect.zw;
+    var pixel = vec2<i32>(uv * vec2<f32>(dims));
+
+    return clamp(pixel, vec2<i32>(0, 0), vec2<i32>(dims) - 1);
+}
+
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    return select(pow((c + 0.055) / 1.055, vec3<f32>(2.4)), c / 12.92, c <= vec3<f32>(0.04045));
+}
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    var encoded = 1.055 * pow(c, vec3<f32>(1. / 2.4)) - 0.055;
+    return select(encoded, c * 12.92, c <= vec3<f32>(0.0031308));
+}
+
+// Values are displayed as-is, eg 0.5 as mid-gray, so undo the target's encoding. Colors are
+// linear, so display them encoded.
+fn output(value: vec3<f32>, is_color: bool) -> vec4<f32> {
+    var v = clamp(value, vec3<f32>(0.), vec3<f32>(1.));
+
+    if is_color && params.srgb_target == 0u {
+        v = linear_to_srgb(v);
+    } else if !is_color && params.srgb_target == 1u {
+        v = srgb_to_linear(v);
+    }
+
+    return vec4<f32>(v, 1.);
+}
+
+@fragment
+fn fs_color(vertex: VertexOut) -> @location(0) vec4<f32> {
+    var pixel = source_pixel(vertex.posit.xy, textureDimensions(color_tex));
+    var value = textureLoad(color_tex, pixel, 0) * params.scale;
+
+    switch params.mode {
+        case MODE_NORMAL: {
+            return output(value.xyz * 0.5 + 0.5, false);
+        }
+        case MODE_VELOCITY: {
+            return output(vec3<f32>(value.xy * 0.5 + 0.5, 0.5), false);
+        }
+        default: {
+            return output(value.rgb, true);
+        }
+    }
+}
+
+@fragment
+fn fs_depth(vertex: VertexOut) -> @location(0) vec4<f32> {
+    var pixel = source_pixel(vertex.posit.xy, textureDimensions(depth_tex));
+    var depth = textureLoad(depth_tex, pixel, 0);
+
+    // Shadow maps store distance, normalized to their far distance.
+    var dist = depth;
+    if params.mode == MODE_DEPTH && params.ortho == 0u {
+        var linear = params.near * params.far / (params.far - depth * (params.far - params.near));
+        dist = linear / params.far;
+    }
+
+    return output(vec3<f32>(dist * params.scale), false);
+}
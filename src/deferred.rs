@@ -0,0 +1,301 @@
+//! Deferred shading, for scenes with many point lights. Instead of lighting each fragment as it's
+//! drawn, the main pass writes surface properties to a G-buffer: base color, normal and shinyness,
+//! and lighting factors and reflectivity. A lighting pass then shades each pixel of the viewport
+//! once, reading these, and reconstructing position from depth. Lighting cost no longer scales
+//! with overdraw, so it's proportional to the viewport's size, times the number of lights.
+//!
+//! The G-buffer stores one surface per pixel, so translucency isn't supported; entities are drawn
+//! opaque. Lines and SDF elements are drawn over the lit result, in a separate pass.
+
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferUsages,
+    CommandEncoder, Device, FragmentState, Queue, RenderPassDescriptor, RenderPipeline,
+    ShaderModule, ShaderStages, StoreOp, SurfaceConfiguration, TextureFormat, TextureView,
+    VertexState,
+};
+
+use crate::{
+    camera::Camera,
+    graphics::{FWD_VEC, RIGHT_VEC, UP_VEC},
+    texture::Texture,
+    types::{F32_SIZE, VEC4_SIZE},
+};
+
+/// Base color; normal and shinyness; lighting factors and reflectivity. Base color is linear, so
+/// we use an sRGB format to preserve precision in dark colors.
+pub const GBUFFER_FORMATS: [TextureFormat; 3] = [
+    TextureFormat::Rgba8UnormSrgb,
+    TextureFormat::Rgba16Float,
+    TextureFormat::Rgba16Float,
+];
+
+/// The camera's right, up, and forward vectors, the viewport, and the near and far planes, padded.
+const DEFERRED_PARAMS_SIZE: usize = 5 * VEC4_SIZE;
+
+/// G-buffer textures, and the lighting pass.
+pub(crate) struct DeferredState {
+    gbuffer: [Texture; 3],
+    pipeline_lighting: RenderPipeline,
+    layout: BindGroupLayout,
+    params_buf: Buffer,
+    /// G-buffer textures, depth, and params. Recreated with the G-buffer.
+    bind_group: BindGroup,
+}
+
+impl DeferredState {
+    /// `layouts` are the camera, lighting, and shadow bind group layouts of the main shader, which
+    /// the lighting pass shares.
+    pub fn new(
+        device: &Device,
+        surface_cfg: &SurfaceConfiguration,
+        shader: &ShaderModule,
+        layouts: [&BindGroupLayout; 3],
+        depth_view: &TextureView,
+    ) -> Self {
+        let texture_entry = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type,
+            },
+            count: None,
+        };
+
+        let unfiltered = wgpu::TextureSampleType::Float { filterable: false };
+
+        // These bindings follow the instance data ones in the main shader, which share the group.
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(4, unfiltered),
+                texture_entry(5, unfiltered),
+                texture_entry(6, unfiltered),
+                texture_entry(7, wgpu::TextureSampleType::Depth),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Deferred lighting bind group layout"),
+        });
+
+        let [layout_cam, layout_lighting, layout_shadows] = layouts;
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Deferred lighting pipeline layout"),
+            bind_group_layouts: &[layout_cam, layout_lighting, &layout, layout_shadows],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline_lighting = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Deferred lighting pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: shader,
+                entry_point: Some("vs_fullscreen"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: shader,
+                entry_point: Some("fs_deferred"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_cfg.format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let params_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Deferred lighting params buffer"),
+            contents: &[0; DEFERRED_PARAMS_SIZE],
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let gbuffer = create_gbuffer(device, surface_cfg);
+        let bind_group = create_bind_group(device, &layout, &gbuffer, depth_view, &params_buf);
+
+        Self {
+            gbuffer,
+            pipeline_lighting,
+            layout,
+            params_buf,
+            bind_group,
+        }
+    }
+
+    /// Recreate the G-buffer to match a new surface size. `depth_view` is the new depth texture's.
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        surface_cfg: &SurfaceConfiguration,
+        depth_view: &TextureView,
+    ) {
+        self.gbuffer = create_gbuffer(device, surface_cfg);
+        self.bind_group = create_bind_group(
+            device,
+            &self.layout,
+            &self.gbuffer,
+            depth_view,
+            &self.params_buf,
+        );
+    }
+
+    /// One of the G-buffer textures, in the order of `GBUFFER_FORMATS`, for debug views.
+    pub fn gbuffer(&self, i: usize) -> &Texture {
+        &self.gbuffer[i]
+    }
+
+    /// Color attachments for the main pass, cleared.
+    pub fn attachments(&self) -> Vec<Option<wgpu::RenderPassColorAttachment<'_>>> {
+        self.gbuffer
+            .iter()
+            .map(|tex| {
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &tex.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: StoreOp::Store,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    /// Write the camera and viewport used to reconstruct positions. Run this once per frame.
+    /// `viewport` is (x, y, width, height), in pixels.
+    pub fn update_params(&self, queue: &Queue, camera: &Camera, viewport: (f32, f32, f32, f32)) {
+        queue.write_buffer(&self.params_buf, 0, &params_bytes(camera, viewport));
+    }
+
+    /// Light the G-buffer, writing to the 3D viewport of `output_view`, which is loaded with
+    /// `load`; background pixels are left as loaded. `bind_groups` are the camera, lighting, and
+    /// shadow bind groups.
+    pub fn encode_lighting(
+        &self,
+        encoder: &mut CommandEncoder,
+        output_view: &TextureView,
+        bind_groups: [&BindGroup; 3],
+        viewport: (f32, f32, f32, f32),
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) {
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Deferred lighting render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        let (x, y, width, height) = viewport;
+        rpass.set_viewport(x, y, width, height, 0., 1.);
+
+        let [cam, lighting, shadows] = bind_groups;
+
+        rpass.set_pipeline(&self.pipeline_lighting);
+        rpass.set_bind_group(0, cam, &[]);
+        rpass.set_bind_group(1, lighting, &[]);
+        rpass.set_bind_group(2, &self.bind_group, &[]);
+        rpass.set_bind_group(3, shadows, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
+
+/// We reconstruct positions along view rays through each pixel, so we pass the camera's basis,
+/// with right and up scaled to the edges of the view at unit distance.
+fn params_bytes(camera: &Camera, viewport: (f32, f32, f32, f32)) -> [u8; DEFERRED_PARAMS_SIZE] {
+    let mut result = [0; DEFERRED_PARAMS_SIZE];
+
+    let tan_y = (camera.fov_y / 2.).tan();
+    let tan_x = tan_y * camera.aspect;
+
+    let right = camera.orientation.rotate_vec(RIGHT_VEC) * tan_x;
+    let up = camera.orientation.rotate_vec(UP_VEC) * tan_y;
+    let fwd = camera.orientation.rotate_vec(FWD_VEC);
+
+    let (x, y, width, height) = viewport;
+
+    let values = [
+        [right.x, right.y, right.z, 0.],
+        [up.x, up.y, up.z, 0.],
+        [fwd.x, fwd.y, fwd.z, 0.],
+        [x, y, width, height],
+        [camera.near, camera.far, 0., 0.],
+    ]
+    .concat();
+
+    for (i, v) in values.iter().enumerate() {
+        result[i * F32_SIZE..(i + 1) * F32_SIZE].clone_from_slice(&v.to_ne_bytes());
+    }
+
+    result
+}
+
+fn create_gbuffer(device: &Device, surface_cfg: &SurfaceConfiguration) -> [Texture; 3] {
+    let labels = [
+        "G-buffer base color texture",
+        "G-buffer normal texture",
+        "G-buffer material texture",
+    ];
+
+    [0, 1, 2]
+        .map(|i| Texture::create_render_target(device, surface_cfg, GBUFFER_FORMATS[i], labels[i]))
+}
+
+fn create_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    gbuffer: &[Texture; 3],
+    depth_view: &TextureView,
+    params_buf: &Buffer,
+) -> BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&gbuffer[0].view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(&gbuffer[1].view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::TextureView(&gbuffer[2].view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::TextureView(depth_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: params_buf.as_entire_binding(),
+            },
+        ],
+        label: Some("Deferred lighting bind group"),
+    })
+}
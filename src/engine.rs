@@ -0,0 +1,295 @@
+//! A builder for configuring and running the engine, as an alternative to `run`'s positional
+//! arguments. Settings and handlers not set use their defaults; handlers default to doing nothing.
+//! Without the `gui` feature, there's no `on_gui`, so this is the way to run without a GUI handler.
+//!
+//! ```ignore
+//! Engine::builder(state)
+//!     .scene(scene)
+//!     .graphics_settings(GraphicsSettings { taa: true, ..Default::default() })
+//!     .on_render(render_handler)
+//!     .on_event(event_handler)
+//!     .on_gui(gui_handler)
+//!     .on_fixed_update(120., physics_handler)
+//!     .run();
+//! ```
+
+use winit::event::DeviceEvent;
+
+#[cfg(feature = "compute")]
+use crate::compute::ComputePass;
+#[cfg(not(feature = "gui"))]
+use crate::system::GuiContext;
+use crate::{
+    fixed_step::FixedUpdate,
+    system::{Settings, State},
+    types::{EngineUpdates, GraphicsSettings, InputSettings, Scene, UiSettings},
+};
+
+/// The render handler's type, before one is set.
+pub type DefaultRenderHandler<T> = fn(&mut T, &mut Scene, f32) -> EngineUpdates;
+
+/// The event handler's type, before one is set.
+pub type DefaultEventHandler<T> = fn(&mut T, DeviceEvent, &mut Scene, f32) -> EngineUpdates;
+
+/// The GUI handler's type, before one is set.
+#[cfg(feature = "gui")]
+pub type DefaultGuiHandler<T> = fn(&mut T, &egui::Context, &mut Scene) -> EngineUpdates;
+
+/// Without the `gui` feature, there's no GUI handler.
+#[cfg(not(feature = "gui"))]
+pub type DefaultGuiHandler<T> = std::marker::PhantomData<T>;
+
+fn no_render<T>(_: &mut T, _: &mut Scene, _: f32) -> EngineUpdates {
+    Default::default()
+}
+
+fn no_event<T>(_: &mut T, _: DeviceEvent, _: &mut Scene, _: f32) -> EngineUpdates {
+    Default::default()
+}
+
+#[cfg(feature = "gui")]
+fn no_gui<T>(_: &mut T, _: &egui::Context, _: &mut Scene) -> EngineUpdates {
+    Default::default()
+}
+
+/// Without the `gui` feature, this is never called.
+#[cfg(not(feature = "gui"))]
+fn no_gui<T>(_: &mut T, _: &GuiContext, _: &mut Scene) -> EngineUpdates {
+    Default::default()
+}
+
+/// The entry point for the builder API. See the `engine` module.
+pub struct Engine;
+
+impl Engine {
+    /// Start configuring the engine. `user_state` is arbitrary application state, passed to the
+    /// handlers; use `()` if there is none.
+    pub fn builder<T: 'static>(
+        user_state: T,
+    ) -> EngineBuilder<T, DefaultRenderHandler<T>, DefaultEventHandler<T>, DefaultGuiHandler<T>>
+    {
+        EngineBuilder {
+            user_state,
+            scene: Default::default(),
+            input_settings: Default::default(),
+            ui_settings: Default::default(),
+            graphics_settings: Default::default(),
+            #[cfg(feature = "compute")]
+            compute_passes: None,
+            render_handler: no_render::<T>,
+            event_handler: no_event::<T>,
+            #[cfg(feature = "gui")]
+            gui_handler: no_gui::<T>,
+            #[cfg(not(feature = "gui"))]
+            gui_handler: Default::default(),
+            fixed_update: None,
+        }
+    }
+}
+
+/// Engine configuration, created by `Engine::builder`. Finish with `run`.
+pub struct EngineBuilder<T, FRender, FEvent, FGui> {
+    user_state: T,
+    scene: Scene,
+    input_settings: InputSettings,
+    ui_settings: UiSettings,
+    graphics_settings: GraphicsSettings,
+    /// If set, these replace the scene's compute passes.
+    #[cfg(feature = "compute")]
+    compute_passes: Option<Vec<ComputePass>>,
+    render_handler: FRender,
+    event_handler: FEvent,
+    gui_handler: FGui,
+    fixed_update: Option<FixedUpdate<T>>,
+}
+
+impl<T: 'static, FRender, FEvent, FGui> EngineBuilder<T, FRender, FEvent, FGui> {
+    /// The initial scene.
+    pub fn scene(mut self, scene: Scene) -> Self {
+        self.scene = scene;
+        self
+    }
+
+    pub fn input_settings(mut self, input_settings: InputSettings) -> Self {
+        self.input_settings = input_settings;
+        self
+    }
+
+    pub fn ui_settings(mut self, ui_settings: UiSettings) -> Self {
+        self.ui_settings = ui_settings;
+        self
+    }
+
+    pub fn graphics_settings(mut self, graphics_settings: GraphicsSettings) -> Self {
+        self.graphics_settings = graphics_settings;
+        self
+    }
+
+    /// Compute passes to run each frame. These replace `Scene::compute_passes`, regardless of
+    /// whether `scene` is set before or after.
+    #[cfg(feature = "compute")]
+    pub fn compute(mut self, passes: Vec<ComputePass>) -> Self {
+        self.compute_passes = Some(passes);
+        self
+    }
+
+    /// Code to run each frame. It's passed the time since the previous frame, in seconds.
+    pub fn on_render<F>(self, handler: F) -> EngineBuilder<T, F, FEvent, FGui>
+    where
+        F: FnMut(&mut T, &mut Scene, f32) -> EngineUpdates + 'static,
+    {
+        EngineBuilder {
+            user_state: self.user_state,
+            scene: self.scene,
+            input_settings: self.input_settings,
+            ui_settings: self.ui_settings,
+            graphics_settings: self.graphics_settings,
+            #[cfg(feature = "compute")]
+            compute_passes: self.compute_passes,
+            render_handler: handler,
+            event_handler: self.event_handler,
+            gui_handler: self.gui_handler,
+            fixed_update: self.fixed_update,
+        }
+    }
+
+    /// Code to handle device events, such as user input, that the GUI, shortcuts, and camera
+    /// controls don't consume.
+    pub fn on_event<F>(self, handler: F) -> EngineBuilder<T, FRender, F, FGui>
+    where
+        F: FnMut(&mut T, DeviceEvent, &mut Scene, f32) -> EngineUpdates + 'static,
+    {
+        EngineBuilder {
+            user_state: self.user_state,
+            scene: self.scene,
+            input_settings: self.input_settings,
+            ui_settings: self.ui_settings,
+            graphics_settings: self.graphics_settings,
+            #[cfg(feature = "compute")]
+            compute_passes: self.compute_passes,
+            render_handler: self.render_handler,
+            event_handler: handler,
+            gui_handler: self.gui_handler,
+            fixed_update: self.fixed_update,
+        }
+    }
+
+    /// EGUI code that describes the UI. Requires the `gui` feature.
+    #[cfg(feature = "gui")]
+    pub fn on_gui<F>(self, handler: F) -> EngineBuilder<T, FRender, FEvent, F>
+    where
+        F: FnMut(&mut T, &egui::Context, &mut Scene) -> EngineUpdates + 'static,
+    {
+        EngineBuilder {
+            user_state: self.user_state,
+            scene: self.scene,
+            input_settings: self.input_settings,
+            ui_settings: self.ui_settings,
+            graphics_settings: self.graphics_settings,
+            #[cfg(feature = "compute")]
+            compute_passes: self.compute_passes,
+            render_handler: self.render_handler,
+            event_handler: self.event_handler,
+            gui_handler: handler,
+            fixed_update: self.fixed_update,
+        }
+    }
+
+    /// Code to run at a fixed rate, `hz` times per second, eg 120, for deterministic simulation,
+    /// eg physics. It's passed the step duration, in seconds, which is constant. It runs before
+    /// the render handler, as many times as needed each frame to keep up; see the `fixed_step`
+    /// module. Panics if `hz` isn't finite and positive.
+    pub fn on_fixed_update<F>(mut self, hz: f32, handler: F) -> Self
+    where
+        F: FnMut(&mut T, &mut Scene, f32) -> EngineUpdates + 'static,
+    {
+        assert!(
+            hz.is_finite() && hz > 0.,
+            "Fixed update rate must be finite and positive; got {hz}"
+        );
+        self.fixed_update = Some(FixedUpdate::new(hz, Box::new(handler)));
+        self
+    }
+
+    /// The scene to start with, with compute passes from `compute` applied.
+    fn initial_scene(&mut self) -> Scene {
+        let scene = std::mem::take(&mut self.scene);
+
+        #[cfg(feature = "compute")]
+        if let Some(passes) = self.compute_passes.take() {
+            return Scene {
+                compute_passes: passes,
+                ..scene
+            };
+        }
+
+        scene
+    }
+}
+
+#[cfg(feature = "gui")]
+impl<T: 'static, FRender, FEvent, FGui> EngineBuilder<T, FRender, FEvent, FGui>
+where
+    FRender: FnMut(&mut T, &mut Scene, f32) -> EngineUpdates + 'static,
+    FEvent: FnMut(&mut T, DeviceEvent, &mut Scene, f32) -> EngineUpdates + 'static,
+    FGui: FnMut(&mut T, &egui::Context, &mut Scene) -> EngineUpdates + 'static,
+{
+    /// Start the event loop. This is the same as calling `run` with the builder's settings, plus
+    /// the fixed update handler, if set. Returns the user state once the loop ends.
+    pub fn run(mut self) -> T {
+        let scene = self.initial_scene();
+
+        let settings = Settings {
+            input: self.input_settings,
+            ui: self.ui_settings,
+            graphics: self.graphics_settings,
+        };
+
+        let mut state = State::new(
+            scene,
+            settings,
+            self.user_state,
+            self.render_handler,
+            self.event_handler,
+            self.gui_handler,
+        );
+        state.fixed_update = self.fixed_update;
+
+        state.run_event_loop();
+
+        state.user_state
+    }
+}
+
+#[cfg(not(feature = "gui"))]
+impl<T: 'static, FRender, FEvent> EngineBuilder<T, FRender, FEvent, DefaultGuiHandler<T>>
+where
+    FRender: FnMut(&mut T, &mut Scene, f32) -> EngineUpdates + 'static,
+    FEvent: FnMut(&mut T, DeviceEvent, &mut Scene, f32) -> EngineUpdates + 'static,
+{
+    /// Start the event loop. This is the same as calling `run` with the builder's settings, plus
+    /// the fixed update handler, if set. Returns the user state once the loop ends.
+    pub fn run(mut self) -> T {
+        let scene = self.initial_scene();
+
+        let settings = Settings {
+            input: self.input_settings,
+            ui: self.ui_settings,
+            graphics: self.graphics_settings,
+        };
+
+        let mut state = State::new(
+            scene,
+            settings,
+            self.user_state,
+            self.render_handler,
+            self.event_handler,
+            no_gui::<T>,
+        );
+        state.fixed_update = self.fixed_update;
+
+        state.run_event_loop();
+
+        state.user_state
+    }
+}
@@ -0,0 +1,62 @@
+//! Entity indices, bucketed by the mesh they use. Instances are drawn one mesh at a time, so we
+//! order them by mesh. We keep buckets between instance rebuilds, and update them only for
+//! entities whose mesh changed, or that were added or removed; this includes removal with
+//! `Vec::swap_remove`, where only the last entity's index changes.
+
+use crate::types::Entity;
+
+#[derive(Default)]
+pub(crate) struct EntityBuckets {
+    /// Indices of entities using each mesh, in ascending order.
+    pub by_mesh: Vec<Vec<usize>>,
+    /// The mesh of each entity, as of the last sync.
+    entity_meshes: Vec<usize>,
+}
+
+impl EntityBuckets {
+    /// Update buckets to match the current entities.
+    pub fn sync(&mut self, entities: &[Entity], num_meshes: usize) {
+        // Entities using meshes out of range aren't in a bucket, so we can't update
+        // incrementally if the mesh count changes.
+        if num_meshes != self.by_mesh.len() {
+            self.by_mesh = vec![Vec::new(); num_meshes];
+            self.entity_meshes.clear();
+        }
+
+        for i in (entities.len()..self.entity_meshes.len()).rev() {
+            self.remove(i, self.entity_meshes[i]);
+        }
+        self.entity_meshes.truncate(entities.len());
+
+        for (i, entity) in entities.iter().enumerate() {
+            match self.entity_meshes.get(i) {
+                Some(&mesh) if mesh == entity.mesh => (),
+                Some(&mesh) => {
+                    self.remove(i, mesh);
+                    self.insert(i, entity.mesh);
+                    self.entity_meshes[i] = entity.mesh;
+                }
+                None => {
+                    self.insert(i, entity.mesh);
+                    self.entity_meshes.push(entity.mesh);
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, entity: usize, mesh: usize) {
+        if let Some(bucket) = self.by_mesh.get_mut(mesh) {
+            if let Err(pos) = bucket.binary_search(&entity) {
+                bucket.insert(pos, entity);
+            }
+        }
+    }
+
+    fn remove(&mut self, entity: usize, mesh: usize) {
+        if let Some(bucket) = self.by_mesh.get_mut(mesh) {
+            if let Ok(pos) = bucket.binary_search(&entity) {
+                bucket.remove(pos);
+            }
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! Application-defined extensions to the main shader: WGSL appended to it, and bind groups with
+//! the data it uses, eg textures or storage buffers for custom shader effects. These are bound
+//! after the engine's groups, starting at `@group(4)`, in the main pass and probe captures.
+//!
+//! An extension defines `extend_surface`, which is called for each mesh and impostor fragment,
+//! after texturing, and before lighting:
+//!
+//! `fn extend_surface(surface: Surface, vertex: VertexOut) -> Surface`
+//!
+//! `Surface` and `VertexOut` are defined in `shader.wgsl`; `surface.base_color` is linear. With
+//! deferred shading, this runs before the surface is written to the G-buffer.
+//!
+//! The `camera` uniform is available too, including its position, basis vectors, and the time.
+//! The main shader includes the engine's shader chunks; see the `shader_interface` module. An
+//! extension may declare the interface version it was written for with `#interface_version`.
+
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt, TextureDataOrder},
+    BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferUsages, Device,
+    Extent3d, Queue, RenderPass, ShaderStages, TextureFormat, TextureUsages,
+};
+
+use crate::shader_interface::preprocess_wgsl;
+
+/// Used when there's no extension, or it hasn't been built yet.
+const DEFAULT_WGSL: &str = "fn extend_surface(surface: Surface, vertex: VertexOut) -> Surface {
+    return surface;
+}
+";
+
+/// The first bind group index available to extensions.
+pub(crate) const EXTENSION_GROUP_START: u32 = 4;
+
+#[derive(Clone, Debug)]
+/// A resource bound to an extension's bind group. Bindings are assigned in the order they're
+/// listed in `ExtensionBindGroup::bindings`; ie the first is `@binding(0)`. All are visible to
+/// the vertex and fragment stages.
+pub enum ExtensionBinding {
+    /// A uniform buffer with user data. This is written to the GPU each frame, so it may be
+    /// changed from the render handler without a rebuild, as long as its size doesn't change.
+    Uniform(Vec<u8>),
+    /// A `var<storage, read>` buffer with user data, uploaded when the extension is built.
+    Storage(Vec<u8>),
+    /// A `texture_2d<f32>`, uploaded when the extension is built. `data` is 8-bit sRGB RGBA,
+    /// row by row, and is sampled as linear. It's padded or truncated to `width` x `height`.
+    Texture {
+        width: u32,
+        height: u32,
+        data: Vec<u8>,
+    },
+    /// A filtering `sampler`, with linear filtering, and repeating addressing.
+    Sampler,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExtensionBindGroup {
+    pub label: String,
+    pub bindings: Vec<ExtensionBinding>,
+}
+
+#[derive(Clone, Debug)]
+/// WGSL appended to the main shader, and the bind groups it uses; see the module documentation.
+/// Set `EngineUpdates::shader_extension` after changing this, other than uniform contents.
+pub struct ShaderExtension {
+    /// WGSL declaring the bindings of `bind_groups`, and defining `extend_surface`. The first
+    /// group is `@group(4)`.
+    pub wgsl: String,
+    /// Groups past the device's limit, usually 4 of these, aren't bound.
+    pub bind_groups: Vec<ExtensionBindGroup>,
+}
+
+/// GPU resources of the scene's shader extension.
+pub(crate) struct ExtensionState {
+    /// Appended to the main shader: the extension's, or a default `extend_surface`.
+    pub wgsl: String,
+    pub layouts: Vec<BindGroupLayout>,
+    pub bind_groups: Vec<BindGroup>,
+    /// Uniform buffers, with their group and binding indices.
+    uniform_bufs: Vec<(usize, usize, Buffer)>,
+    /// Set if `Scene::shader_extension` hasn't been built yet.
+    pub stale: bool,
+    /// Set if the extension's directives are invalid, eg it requires a different interface
+    /// version. The default extension is used instead.
+    pub error: Option<String>,
+}
+
+impl Default for ExtensionState {
+    fn default() -> Self {
+        Self {
+            wgsl: DEFAULT_WGSL.to_owned(),
+            layouts: Vec::new(),
+            bind_groups: Vec::new(),
+            uniform_bufs: Vec::new(),
+            stale: false,
+            error: None,
+        }
+    }
+}
+
+impl ExtensionState {
+    /// Create bind groups for an extension, up to `max_groups` of them.
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        extension: Option<&ShaderExtension>,
+        max_groups: usize,
+    ) -> Self {
+        let Some(extension) = extension else {
+            return Self::default();
+        };
+
+        // Chunks are resolved along with the main shader, which includes them already.
+        if let Err(e) = preprocess_wgsl(&extension.wgsl) {
+            return Self {
+                error: Some(format!("Shader extension: {e}")),
+                ..Default::default()
+            };
+        }
+
+        let mut result = Self {
+            wgsl: extension.wgsl.clone(),
+            ..Default::default()
+        };
+
+        for (i_group, group) in extension.bind_groups.iter().take(max_groups).enumerate() {
+            let mut layout_entries = Vec::new();
+            let mut resources = Vec::new();
+
+            for (i, binding) in group.bindings.iter().enumerate() {
+                let (ty, resource) = match binding {
+                    ExtensionBinding::Uniform(data) => {
+                        let buf = device.create_buffer_init(&BufferInitDescriptor {
+                            label: Some("Extension uniform buffer"),
+                            contents: data,
+                            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                        });
+                        result.uniform_bufs.push((i_group, i, buf));
+                        let i_buf = result.uniform_bufs.len() - 1;
+                        (
+                            buffer_type(BufferBindingType::Uniform),
+                            Resource::Uniform(i_buf),
+                        )
+                    }
+                    ExtensionBinding::Storage(data) => {
+                        let buf = device.create_buffer_init(&BufferInitDescriptor {
+                            label: Some("Extension storage buffer"),
+                            contents: data,
+                            usage: BufferUsages::STORAGE,
+                        });
+                        let ty = BufferBindingType::Storage { read_only: true };
+                        (buffer_type(ty), Resource::Buffer(buf))
+                    }
+                    ExtensionBinding::Texture {
+                        width,
+                        height,
+                        data,
+                    } => {
+                        let ty = BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        };
+                        let view = create_texture(device, queue, *width, *height, data);
+                        (ty, Resource::Texture(view))
+                    }
+                    ExtensionBinding::Sampler => {
+                        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+                            label: Some("Extension sampler"),
+                            address_mode_u: wgpu::AddressMode::Repeat,
+                            address_mode_v: wgpu::AddressMode::Repeat,
+                            mag_filter: wgpu::FilterMode::Linear,
+                            min_filter: wgpu::FilterMode::Linear,
+                            ..Default::default()
+                        });
+                        let ty = BindingType::Sampler(wgpu::SamplerBindingType::Filtering);
+                        (ty, Resource::Sampler(sampler))
+                    }
+                };
+
+                layout_entries.push(wgpu::BindGroupLayoutEntry {
+                    binding: i as u32,
+                    visibility: ShaderStages::VERTEX_FRAGMENT,
+                    ty,
+                    count: None,
+                });
+                resources.push(resource);
+            }
+
+            let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &layout_entries,
+                label: Some(&group.label),
+            });
+
+            let entries: Vec<_> = resources
+                .iter()
+                .enumerate()
+                .map(|(i, resource)| wgpu::BindGroupEntry {
+                    binding: i as u32,
+                    resource: match resource {
+                        Resource::Uniform(i_buf) => {
+                            result.uniform_bufs[*i_buf].2.as_entire_binding()
+                        }
+                        Resource::Buffer(buf) => buf.as_entire_binding(),
+                        Resource::Texture(view) => wgpu::BindingResource::TextureView(view),
+                        Resource::Sampler(sampler) => wgpu::BindingResource::Sampler(sampler),
+                    },
+                })
+                .collect();
+
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &layout,
+                entries: &entries,
+                label: Some(&group.label),
+            });
+
+            result.layouts.push(layout);
+            result.bind_groups.push(bind_group);
+        }
+
+        result
+    }
+
+    /// Set the bind groups in a pass using the main pipeline layout.
+    pub fn set_bind_groups(&self, rpass: &mut RenderPass) {
+        for (i, bind_group) in self.bind_groups.iter().enumerate() {
+            rpass.set_bind_group(EXTENSION_GROUP_START + i as u32, bind_group, &[]);
+        }
+    }
+
+    /// Write uniform buffers from the extension's current data.
+    pub fn write_uniforms(&self, queue: &Queue, extension: Option<&ShaderExtension>) {
+        let Some(extension) = extension else {
+            return;
+        };
+
+        for (i_group, i, buf) in &self.uniform_bufs {
+            let binding = extension
+                .bind_groups
+                .get(*i_group)
+                .and_then(|g| g.bindings.get(*i));
+
+            // Skip the write if the size changed; that requires a rebuild.
+            if let Some(ExtensionBinding::Uniform(bytes)) = binding {
+                if bytes.len() as u64 == buf.size() {
+                    queue.write_buffer(buf, 0, bytes);
+                }
+            }
+        }
+    }
+}
+
+/// A resource, kept until its bind group is created.
+enum Resource {
+    /// An index into `ExtensionState::uniform_bufs`.
+    Uniform(usize),
+    Buffer(Buffer),
+    Texture(wgpu::TextureView),
+    Sampler(wgpu::Sampler),
+}
+
+fn buffer_type(ty: BufferBindingType) -> BindingType {
+    BindingType::Buffer {
+        ty,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    }
+}
+
+fn create_texture(
+    device: &Device,
+    queue: &Queue,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> wgpu::TextureView {
+    let (width, height) = (width.max(1), height.max(1));
+
+    let mut data = data.to_vec();
+    data.resize((width * height * 4) as usize, 0);
+
+    let texture = device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some("Extension texture"),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        },
+        TextureDataOrder::LayerMajor,
+        &data,
+    );
+
+    texture.create_view(&Default::default())
+}
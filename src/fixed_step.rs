@@ -0,0 +1,62 @@
+//! A fixed-timestep update handler, for simulation code that needs deterministic steps, eg
+//! physics, independent of the frame rate. Set it with `EngineBuilder::on_fixed_update`.
+//!
+//! Each frame, before the render handler, the frame's time is added to an accumulator, and the
+//! handler runs once for each whole step it contains; this may be zero, or several times per
+//! frame. The remainder carries over to the next frame. The fraction of a step left over is
+//! available as `FrameStats::fixed_step_alpha`, for interpolating between the previous and current
+//! simulation states when rendering.
+
+use std::time::Duration;
+
+use crate::types::{EngineUpdates, Scene};
+
+/// Frame times longer than this, eg after the window was dragged, or the application paused in a
+/// debugger, are clamped to it. Otherwise, we'd run many steps at once to catch up, making that
+/// frame slow too.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// Durations round down to whole nanoseconds; a zero step would never be consumed.
+const MIN_STEP: Duration = Duration::from_nanos(1);
+
+/// The handler's type. It's passed the step duration, in seconds, which is the same each call.
+pub(crate) type FixedUpdateHandler<T> = Box<dyn FnMut(&mut T, &mut Scene, f32) -> EngineUpdates>;
+
+pub(crate) struct FixedUpdate<T> {
+    pub handler: FixedUpdateHandler<T>,
+    step: Duration,
+    /// Time not yet simulated.
+    accumulator: Duration,
+}
+
+impl<T> FixedUpdate<T> {
+    /// `hz` is the number of steps per second, eg 120. Steps are at least 1ns, so very high rates
+    /// can't make `advance` loop indefinitely.
+    pub fn new(hz: f32, handler: FixedUpdateHandler<T>) -> Self {
+        Self {
+            handler,
+            step: Duration::from_secs_f64(1. / hz.max(f32::EPSILON) as f64).max(MIN_STEP),
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    /// The duration of each step, in seconds.
+    pub fn step_secs(&self) -> f32 {
+        self.step.as_secs_f32()
+    }
+
+    /// Add a frame's time, and return the number of whole steps to run.
+    pub fn advance(&mut self, dt: Duration) -> u32 {
+        self.accumulator += dt.min(MAX_FRAME_TIME);
+
+        // This fits in a `u32`, since the accumulator is under `MAX_FRAME_TIME` plus a step.
+        let steps = (self.accumulator.as_nanos() / self.step.as_nanos()) as u32;
+        self.accumulator -= self.step * steps;
+        steps
+    }
+
+    /// The fraction of a step accumulated, but not yet run, from 0 to 1.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+    }
+}
@@ -0,0 +1,293 @@
+//! Picking on the GPU, for scenes with too many instances to test on the CPU each click. A compute
+//! pass tests a ray against the bounding sphere of every instance in the instance buffer, and the
+//! nearest hit is read back. This uses the same bounds as occlusion culling, so it's coarser than
+//! `Scene::raycast`: the sphere around an entity may be hit where the entity isn't. For exact hits,
+//! refine the result with `Scene::raycast`, or test the returned entity's triangles.
+//!
+//! Request a pick with `EngineUpdates::gpu_pick`; the result is in `Scene::gpu_pick_hit` when the
+//! next handler runs. Reading back blocks until the pass completes. Raw instances aren't tested.
+
+use std::sync::mpsc;
+
+use lin_alg::f32::Vec3;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferUsages, ComputePipeline,
+    Device, MapMode, Queue, ShaderStages,
+};
+
+use crate::{
+    culling::{self, buf_entry},
+    limits,
+    types::{F32_SIZE, INSTANCE_SIZE, VEC4_SIZE},
+};
+
+/// Ray origin and direction, instance count, stride, and offset, and padding.
+const PICK_PARAMS_SIZE: usize = 2 * VEC4_SIZE + 4 * 4;
+
+/// The distance of the nearest hit, and its instance.
+const RESULT_SIZE: usize = 2 * 4;
+
+const PICK_WORKGROUP_SIZE: u32 = 64;
+/// The most workgroups allowed in one dimension of a dispatch.
+const MAX_WORKGROUPS: u32 = 65_535;
+
+/// Marks instances that can't be picked, and is the result if nothing is hit.
+pub(crate) const PICK_NONE: u32 = u32::MAX;
+
+#[derive(Clone, Debug)]
+/// The nearest entity whose bounding sphere a ray hits, from `EngineUpdates::gpu_pick`.
+pub struct GpuHit {
+    /// Index into `Scene::entities`.
+    pub entity: usize,
+    /// Distance from the ray's origin to the bounding sphere, in world units. 0 if the origin is
+    /// inside it.
+    pub distance: f32,
+}
+
+pub(crate) struct GpuPicker {
+    pipeline_nearest: ComputePipeline,
+    pipeline_resolve: ComputePipeline,
+    layout: BindGroupLayout,
+    result_buf: Buffer,
+    readback_buf: Buffer,
+}
+
+impl GpuPicker {
+    pub fn new(device: &Device) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("GPU picking shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gpu_pick.wgsl").into()),
+        });
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, true),
+                storage_entry(3, true),
+                storage_entry(4, true),
+                storage_entry(5, false),
+            ],
+            label: Some("GPU picking bind group layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GPU picking pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let create_pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+
+        let pipeline_nearest = create_pipeline("GPU picking nearest pipeline", "nearest");
+        let pipeline_resolve = create_pipeline("GPU picking resolve pipeline", "resolve");
+
+        let result_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU picking result buffer"),
+            size: RESULT_SIZE as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let readback_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU picking readback buffer"),
+            size: RESULT_SIZE as u64,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline_nearest,
+            pipeline_resolve,
+            layout,
+            result_buf,
+            readback_buf,
+        }
+    }
+
+    /// Find the nearest instance whose bounding sphere the ray hits, and the distance to it.
+    /// `instance_meshes` and `instance_entities` have an entry for each instance in
+    /// `instance_buf`; entities are `PICK_NONE` for instances that can't be picked. `dir` must be
+    /// normalized. Blocks until the result is read back.
+    #[allow(clippy::too_many_arguments)]
+    pub fn pick(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        instance_buf: &Buffer,
+        instance_meshes: &[u32],
+        instance_entities: &[u32],
+        mesh_bounds: &[(Vec3, f32)],
+        origin: Vec3,
+        dir: Vec3,
+    ) -> Option<(u32, f32)> {
+        if instance_meshes.is_empty() {
+            return None;
+        }
+
+        let u32_buf = |label, values: &[u32]| {
+            let mut data = Vec::with_capacity(values.len() * F32_SIZE);
+            for v in values {
+                data.extend_from_slice(&v.to_ne_bytes());
+            }
+            device.create_buffer_init(&BufferInitDescriptor {
+                label: Some(label),
+                contents: &data,
+                usage: BufferUsages::STORAGE,
+            })
+        };
+
+        let meshes_buf = u32_buf("GPU picking instance mesh buffer", instance_meshes);
+        let entities_buf = u32_buf("GPU picking instance entity buffer", instance_entities);
+        let bounds_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("GPU picking mesh bounds buffer"),
+            contents: &culling::bounds_data(mesh_bounds),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let mut result = Vec::with_capacity(RESULT_SIZE);
+        result.extend_from_slice(&PICK_NONE.to_ne_bytes());
+        result.extend_from_slice(&PICK_NONE.to_ne_bytes());
+        queue.write_buffer(&self.result_buf, 0, &result);
+
+        // The instance buffer may be larger than a storage binding, so we bind it, and the other
+        // per-instance buffers, in ranges; see the `limits` module.
+        let ranges = limits::binding_ranges(device, instance_meshes.len(), INSTANCE_SIZE);
+
+        let bind_groups: Vec<_> = ranges
+            .iter()
+            .map(|range| {
+                let mut params = Vec::with_capacity(PICK_PARAMS_SIZE);
+                for v in [origin, dir] {
+                    params.extend_from_slice(&v.to_bytes_vertex());
+                    params.extend_from_slice(&0_f32.to_ne_bytes());
+                }
+                params.extend_from_slice(&(range.len() as u32).to_ne_bytes());
+                params.extend_from_slice(&((INSTANCE_SIZE / F32_SIZE) as u32).to_ne_bytes());
+                params.extend_from_slice(&(range.start as u32).to_ne_bytes());
+                params.resize(PICK_PARAMS_SIZE, 0);
+
+                let params_buf = device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some("GPU picking params buffer"),
+                    contents: &params,
+                    usage: BufferUsages::UNIFORM,
+                });
+
+                let bytes = |buf, size: usize| {
+                    let range = (range.start * size) as u64..(range.end * size) as u64;
+                    limits::storage_range(buf, range)
+                };
+
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.layout,
+                    entries: &[
+                        buf_entry(0, &params_buf),
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: bytes(instance_buf, INSTANCE_SIZE),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: bytes(&meshes_buf, F32_SIZE),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: bytes(&entities_buf, F32_SIZE),
+                        },
+                        buf_entry(4, &bounds_buf),
+                        buf_entry(5, &self.result_buf),
+                    ],
+                    label: Some("GPU picking bind group"),
+                })
+            })
+            .collect();
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("GPU picking encoder"),
+        });
+
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("GPU picking pass"),
+                timestamp_writes: None,
+            });
+
+            // Storage writes from the first dispatches are visible to the later ones, so all
+            // ranges find their nearest hits before any resolves.
+            for pipeline in [&self.pipeline_nearest, &self.pipeline_resolve] {
+                pass.set_pipeline(pipeline);
+
+                for (range, bind_group) in ranges.iter().zip(&bind_groups) {
+                    // Split workgroups over 2 dimensions if there are too many for one.
+                    let groups = (range.len() as u32).div_ceil(PICK_WORKGROUP_SIZE);
+                    let groups_x = groups.min(MAX_WORKGROUPS);
+                    let groups_y = groups.div_ceil(groups_x);
+
+                    pass.set_bind_group(0, bind_group, &[]);
+                    pass.dispatch_workgroups(groups_x, groups_y, 1);
+                }
+            }
+        }
+
+        encoder.copy_buffer_to_buffer(
+            &self.result_buf,
+            0,
+            &self.readback_buf,
+            0,
+            RESULT_SIZE as u64,
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = self.readback_buf.slice(..);
+        let (tx, rx) = mpsc::channel();
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+
+        rx.recv().ok()?.ok()?;
+
+        let (distance, instance) = {
+            let data = slice.get_mapped_range();
+            let read = |i: usize| u32::from_ne_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+            (read(0), read(1))
+        };
+        self.readback_buf.unmap();
+
+        if instance == PICK_NONE {
+            return None;
+        }
+
+        Some((instance, f32::from_bits(distance)))
+    }
+}
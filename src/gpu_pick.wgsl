@@ -0,0 +1,129 @@
+// GPU picking. Tests a ray against each instance's bounding sphere, and finds the nearest hit.
+// `nearest` finds the smallest hit distance; `resolve` then finds the first instance at that
+// distance. Non-negative floats order the same as their bits, so we compare them as u32s.
+//
+// Workgroups are dispatched in 2D if there are more than fit in one dimension; see `instance_i`.
+// Instances may be split over several dispatches, if they don't fit in one storage binding; each
+// binds a range of the per-instance buffers.
+
+const WORKGROUP_SIZE: u32 = 64u;
+
+// Marks instances that can't be picked, and is the result if nothing is hit.
+const NONE: u32 = 0xffffffffu;
+
+struct PickParams {
+    // The ray's origin, and normalized direction, in world space. W is unused.
+    origin: vec4<f32>,
+    dir: vec4<f32>,
+    // The number of instances in this dispatch's range.
+    instance_count: u32,
+    // In f32s.
+    instance_stride: u32,
+    // The index of the first instance in the range.
+    instance_offset: u32,
+}
+
+// A bounding sphere, in the mesh's local space.
+struct MeshBounds {
+    center: vec3<f32>,
+    radius: f32,
+}
+
+struct PickResult {
+    // The bits of the nearest hit distance.
+    distance: atomic<u32>,
+    instance: atomic<u32>,
+}
+
+@group(0) @binding(0)
+var<uniform> params: PickParams;
+@group(0) @binding(1)
+var<storage, read> instances: array<f32>;
+// The mesh each instance uses.
+@group(0) @binding(2)
+var<storage, read> instance_meshes: array<u32>;
+// The entity each instance draws; `NONE` if it can't be picked.
+@group(0) @binding(3)
+var<storage, read> instance_entities: array<u32>;
+@group(0) @binding(4)
+var<storage, read> mesh_bounds: array<MeshBounds>;
+@group(0) @binding(5)
+var<storage, read_write> result: PickResult;
+
+fn instance_i(id: vec3<u32>, groups: vec3<u32>) -> u32 {
+    return id.x + id.y * groups.x * WORKGROUP_SIZE;
+}
+
+fn load_vec4(i: u32) -> vec4<f32> {
+    return vec4<f32>(instances[i], instances[i + 1u], instances[i + 2u], instances[i + 3u]);
+}
+
+// The distance along the ray to the instance's bounding sphere; negative if it misses, or the
+// instance can't be picked. 0 if the ray starts inside the sphere.
+fn hit_distance(i: u32) -> f32 {
+    if (instance_entities[i] == NONE) {
+        return -1.;
+    }
+
+    // The model matrix is the first field of each instance.
+    let base = i * params.instance_stride;
+    let model = mat4x4<f32>(
+        load_vec4(base),
+        load_vec4(base + 4u),
+        load_vec4(base + 8u),
+        load_vec4(base + 12u),
+    );
+
+    let bounds = mesh_bounds[instance_meshes[i]];
+    let center = (model * vec4<f32>(bounds.center, 1.)).xyz;
+    // Scale is uniform.
+    let radius = bounds.radius * length(model[0].xyz);
+
+    let oc = params.origin.xyz - center;
+    let b = dot(oc, params.dir.xyz);
+    let c = dot(oc, oc) - radius * radius;
+
+    if (c <= 0.) {
+        return 0.;
+    }
+
+    let discriminant = b * b - c;
+    if (discriminant < 0.) {
+        return -1.;
+    }
+
+    // Negative if the sphere is behind the origin.
+    return -b - sqrt(discriminant);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn nearest(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+) {
+    let i = instance_i(id, groups);
+    if (i >= params.instance_count) {
+        return;
+    }
+
+    let t = hit_distance(i);
+    if (t >= 0.) {
+        atomicMin(&result.distance, bitcast<u32>(t));
+    }
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn resolve(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+) {
+    let i = instance_i(id, groups);
+    if (i >= params.instance_count) {
+        return;
+    }
+
+    let t = hit_distance(i);
+    if (t >= 0. && bitcast<u32>(t) == atomicLoad(&result.distance)) {
+        atomicMin(&result.instance, params.instance_offset + i);
+    }
+}
@@ -9,31 +9,99 @@
 //!
 //! 2022-08-21: https://github.com/gfx-rs/wgpu/blob/master/wgpu/examples/cube/main.rs
 
-use std::{sync::Arc, time::Duration};
+use std::{mem, ops::Range, time::Duration};
 
+#[cfg(feature = "gui")]
 use egui::Context;
-use lin_alg::f32::Vec3;
+#[cfg(feature = "remote")]
+use image::RgbaImage;
+use lin_alg::f32::{Mat4, Vec3};
 use wgpu::{
     self,
     util::{BufferInitDescriptor, DeviceExt},
     BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferUsages,
-    CommandEncoder, CommandEncoderDescriptor, Device, FragmentState, Queue, RenderPass,
-    RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline, ShaderStages, StoreOp,
-    SurfaceConfiguration, SurfaceTexture,  TextureView, VertexState,
+    CommandEncoder, CommandEncoderDescriptor, Device, Queue, RenderBundle, RenderPass,
+    RenderPassDepthStencilAttachment, RenderPassDescriptor, ShaderStages, StoreOp,
+    SurfaceConfiguration, SurfaceTexture, TextureFormat, TextureView,
 };
-use winit::{event::DeviceEvent, window::Window};
+use winit::event::DeviceEvent;
+#[cfg(feature = "gui")]
+use winit::window::Window;
+
+#[cfg(feature = "compute")]
+use crate::compute::{self, ComputePipelineData, ComputeStage};
+#[cfg(feature = "gui")]
+use crate::{gui::GuiState, system::process_engine_updates, types::EngineUpdates};
+#[cfg(feature = "remote")]
+use crate::headless;
 
 use crate::{
-    gui,
-    gui::GuiState,
+    anaglyph::{Anaglyph, AnaglyphRenderer},
+    area_light,
+    background::BackgroundRenderer,
+    buffer_pool::BufferPool,
+    camera::{self, CAMERA_SIZE},
+    cluster::ClusterState,
+    culling::{self, CullState, DRAW_ARGS_SIZE},
+    debug::{DebugShapes, LineRenderer, Lines},
+    debug_view::{DebugSource, DebugTarget, DebugViewRenderer},
+    deferred::DeferredState,
+    entity_buckets::EntityBuckets,
+    extension::{ExtensionState, EXTENSION_GROUP_START},
+    gpu_pick::{GpuHit, GpuPicker, PICK_NONE},
+    ground::GroundRenderer,
+    heatmap::Colormap,
+    hud::HudRenderer,
+    impostor::{Impostor, ImpostorDraw, ImpostorRenderer},
     input::{self, InputsCommanded},
-    system::{process_engine_updates, DEPTH_FORMAT},
+    labels,
+    layers::RenderLayer,
+    letterbox::{self, LetterboxRenderer},
+    light_path,
+    lighting::{PointLight, LIGHTING_SIZE_FIXED, POINT_LIGHT_SIZE},
+    limits,
+    loader::AssetLoader,
+    mesh_cache::{MeshCache, MeshRange},
+    packed::PackedInstances,
+    material::MaterialTextures,
+    parallel::{self, BundleCache, BundleKey, DrawInputs, InstanceChunk, InstanceInputs},
+    pass::{MeshBuffers, PassContext},
+    pipeline_cache::{MainTargets, MeshPipelines, PipelineCache, PipelineKey},
+    probe::{CaptureInputs, ProbeState},
+    raw_instances::RawInstanceState,
+    redraw::{self, RedrawMode, RedrawRegion, SceneCache},
+    sdf::SdfRenderer,
+    shader_interface::engine_wgsl,
+    shadow::ShadowState,
+    slice::{clip_plane_bytes, CLIP_PLANE_SIZE},
+    sub_range::{self, SubRangeDraw},
+    taa::{self, TaaState, TAA_CAMERA_SIZE},
     texture::Texture,
+    timing::GpuTimer,
+    toon::ToonRenderer,
     types::{
-        ControlScheme, EngineUpdates, InputSettings, Instance, Scene, UiLayout, UiSettings, Vertex,
+        ColorSpace, ControlScheme, Entity, FaceCulling, GraphicsSettings, InputSettings, Instance,
+        Mesh, Scene, UiLayout, UiSettings, INSTANCE_SIZE, MAT4_SIZE,
     },
+    upload::BufferUpload,
+    views::{ExtraView, ViewRenderer},
 };
 
+// Storage usage allows binding vertices to user compute passes. Copy dest allows updating meshes
+// in place, and copy source allows growing the buffers when replacing meshes.
+const VERTEX_BUF_USAGE: BufferUsages = BufferUsages::VERTEX
+    .union(BufferUsages::STORAGE)
+    .union(BufferUsages::COPY_DST)
+    .union(BufferUsages::COPY_SRC);
+const INDEX_BUF_USAGE: BufferUsages = BufferUsages::INDEX
+    .union(BufferUsages::COPY_DST)
+    .union(BufferUsages::COPY_SRC);
+// Storage usage allows binding to user compute passes. Copy dest allows updating individual
+// instances, and rebuilding the buffer in place.
+const INSTANCE_BUF_USAGE: BufferUsages = BufferUsages::VERTEX
+    .union(BufferUsages::STORAGE)
+    .union(BufferUsages::COPY_DST);
+
 pub(crate) const UP_VEC: Vec3 = Vec3 {
     x: 0.,
     y: 1.,
@@ -50,315 +118,2820 @@ pub(crate) const FWD_VEC: Vec3 = Vec3 {
     z: 1.,
 };
 
+/// Instances of static entities, built once and reused when rebuilding instances, until entities
+/// marked static change.
+pub(crate) struct StaticBatch {
+    instances: InstanceChunk,
+    /// Indices of visible static entities using each mesh.
+    by_mesh: Vec<Vec<usize>>,
+    /// The scene's entity count when this was built. Entity indices may change with it.
+    entity_count: usize,
+    debug_shapes: DebugShapes,
+}
+
 /// Code related to our specific engine. Buffers, texture data etc.
 pub(crate) struct GraphicsState {
     pub vertex_buf: Buffer,
     pub index_buf: Buffer,
     instance_buf: Buffer,
+    /// The number of instances in `instance_buf`, which may have room for more.
+    instance_count: usize,
+    /// Each instance's model matrix from the previous frame, in the same order as the instance
+    /// buffer. Only populated when TAA is enabled.
+    prev_models_buf: Buffer,
+    /// Colors of the active palette; see `Scene::palettes`.
+    palette_buf: Buffer,
+    /// Point lights from `Scene::area_lights`, as last uploaded. These follow `point_lights` in
+    /// the lighting buffer.
+    area_lights: Vec<PointLight>,
+    /// See `Scene::materials`.
+    materials: MaterialTextures,
     pub bind_groups: BindGroupData,
     pub camera_buf: Buffer,
     lighting_buf: Buffer,
-    pub pipeline: RenderPipeline, // todo: Move to renderer.
+    /// Lights near each cluster of the view, for clustered lighting.
+    clusters: ClusterState,
+    /// Exposure, gamma, and color space settings for the main shader.
+    color_buf: Buffer,
+    /// Main pass pipelines, by feature; created as they're needed.
+    pipelines: PipelineCache,
+    /// Bind groups of `Scene::shader_extension`, bound after the engine's in the main pass.
+    extension: ExtensionState,
+    /// The format of the surface, and the main pass's color target.
+    color_format: TextureFormat,
+    /// The format of `depth_texture`; see `GraphicsSettings::depth_format`.
+    pub depth_format: TextureFormat,
     pub depth_texture: Texture,
     // pub input_settings: InputSettings,
     // pub ui_settings: UiSettings,
     pub inputs_commanded: InputsCommanded,
     // staging_belt: wgpu::util::StagingBelt, // todo: Do we want this? Probably in sys, not here.
     pub scene: Scene,
-    mesh_mappings: Vec<(i32, u32, u32)>,
-    pub window: Arc<Window>,
+    /// Instance start and count, for each mesh.
+    mesh_mappings: Vec<(u32, u32)>,
+    /// Entities using each mesh, kept between instance rebuilds.
+    entity_buckets: EntityBuckets,
+    /// The index in the instance buffer of each entity; `None` if hidden.
+    entity_instances: Vec<Option<usize>>,
+    mesh_cache: MeshCache,
+    /// The location of each mesh in the vertex and index buffers. Empty while an upload is
+    /// pending.
+    mesh_ranges: Vec<MeshRange>,
+    /// Present while large vertex and index buffers are uploaded over several frames.
+    pending_upload: Option<BufferUpload>,
+    /// Reused buffers, for the instance, vertex and index buffers, which are rebuilt often.
+    buffer_pool: BufferPool,
+    /// Indices correspond to `scene.compute_passes`.
+    #[cfg(feature = "compute")]
+    compute_pipelines: Vec<Option<ComputePipelineData>>,
+    /// Errors from building compute passes; see `Scene::shader_errors`.
+    #[cfg(feature = "compute")]
+    compute_errors: Vec<String>,
+    /// Errors from the last upload of instances and lights; see `Scene::buffer_errors`.
+    instance_limit_error: Option<String>,
+    light_limit_error: Option<String>,
+    /// Seconds since the engine started; passed to compute passes that deform meshes, and written
+    /// to the camera uniform.
+    compute_time: f32,
+    /// Present if GPU timing is enabled, and supported by the device.
+    gpu_timer: Option<GpuTimer>,
+    /// Present if temporal anti-aliasing is enabled.
+    pub taa: Option<TaaState>,
+    /// Present if occlusion culling is enabled, and supported.
+    culling: Option<CullState>,
+    /// Present if deferred shading is enabled, and TAA isn't.
+    pub deferred: Option<DeferredState>,
+    /// Created on the first `EngineUpdates::gpu_pick`.
+    gpu_picker: Option<GpuPicker>,
+    /// Created when `Scene::anaglyph` is first set, and when the output size changes.
+    anaglyph: Option<AnaglyphRenderer>,
+    /// Created when `Scene::extra_views` or `Scene::inset` is first set, and when the output size
+    /// changes.
+    views: Option<ViewRenderer>,
+    /// Created when `Scene::debug_view` is first shown or saved, and when the output format
+    /// changes.
+    debug_view: Option<DebugViewRenderer>,
+    /// Present with `RedrawMode::OnChange`; recreated when the output size changes.
+    scene_cache: Option<SceneCache>,
+    /// The part of the scene to render next frame, with `RedrawMode::OnChange`.
+    redraw: Option<RedrawRegion>,
+    /// True if the scene was rendered this frame, vice reusing the previous frame.
+    scene_rendered: bool,
+    /// How long until the GUI needs repainting, eg for an animation, or to blink a text cursor.
+    /// `Duration::MAX` if it doesn't.
+    pub gui_repaint_delay: Duration,
+    /// The background color last rendered. The scene is redrawn when `Scene::background_color`
+    /// changes, eg from a transition.
+    background_color: (f32, f32, f32),
+    /// The scalar colormap, range, and input color space entities were last colored with. They're
+    /// rebuilt when these change; see `Entity::scalar`.
+    scalar_colors: (Colormap, (f32, f32), ColorSpace),
+    /// The settings resources were created with.
+    settings: GraphicsSettings,
+    /// Set from `EngineUpdates::graphics_settings`; applied with `apply_settings` before the next
+    /// frame, since this may require reconfiguring the surface.
+    pub pending_settings: Option<GraphicsSettings>,
+    /// Runs loads from `EngineUpdates::load_assets`; polled each frame.
+    pub loader: AssetLoader,
+    /// Set from `EngineUpdates::exit`; the event loop exits when it's next idle.
+    pub exit_requested: bool,
+    /// If set, `render` reads back the frame before presenting it, into `captured`. Used for
+    /// remote screenshots.
+    #[cfg(feature = "remote")]
+    pub capture_requested: bool,
+    #[cfg(feature = "remote")]
+    pub captured: Option<Result<RgbaImage, String>>,
+    shadows: ShadowState,
+    raw_instances: RawInstanceState,
+    pub probes: ProbeState,
+    impostors: ImpostorRenderer,
+    /// Entities drawn individually, by render layer: those in the background and overlay layers,
+    /// and world entities drawing part of their mesh; see `Entity::index_range`.
+    layer_draws: [Vec<SubRangeDraw>; 3],
+    /// Debug lines, eg light gizmos.
+    lines: LineRenderer,
+    pub sdf: SdfRenderer,
+    pub hud: HudRenderer,
+    /// Bars around the 3D viewport, with `UiSettings::viewport_aspect`.
+    letterbox: LetterboxRenderer,
+    /// Outlines, for toon rendering.
+    toon: ToonRenderer,
+    /// Draws `Scene::background_gradient`.
+    background: BackgroundRenderer,
+    /// Draws `Scene::ground`.
+    ground: GroundRenderer,
+    /// Debug shapes for entities. We build these with instances, since they may be expensive.
+    entity_debug_lines: Lines,
+    /// The global debug shapes `entity_debug_lines` was built with.
+    entity_debug_shapes: DebugShapes,
+    /// Instances of static entities; `None` if these need to be rebuilt.
+    pub static_batch: Option<StaticBatch>,
+    /// Render bundles of the main pass's mesh draws, when these are split across threads.
+    bundles: BundleCache,
 }
 
 impl GraphicsState {
     pub(crate) fn new(
         device: &Device,
+        queue: &Queue,
         surface_cfg: &SurfaceConfiguration,
         mut scene: Scene,
-        window: Arc<Window>,
+        graphics_settings: &GraphicsSettings,
     ) -> Self {
         let vertex_buf = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Vertex buffer"),
             contents: &[], // Populated later.
-            usage: BufferUsages::VERTEX,
+            usage: VERTEX_BUF_USAGE,
         });
 
         let index_buf = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Index buffer"),
             contents: &[], // Populated later.
-            usage: BufferUsages::INDEX,
+            usage: INDEX_BUF_USAGE,
         });
 
+        scene.scale_default_camera();
         scene.camera.update_proj_mat();
 
+        // The TAA portion of the camera uniform follows the camera data; it's zero unless TAA
+        // is enabled. The slice plane's clip plane follows that, then the time, which is written
+        // each frame.
+        let mut cam_data = scene.camera.to_bytes().to_vec();
+        cam_data.extend_from_slice(&[0; TAA_CAMERA_SIZE]);
+        cam_data.extend_from_slice(&clip_plane_bytes(scene.slice_plane.as_ref()));
+        cam_data.extend_from_slice(&[0; camera::TIME_SIZE]);
+
         let cam_buf = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Camera buffer"),
-            contents: &scene.camera.to_bytes(),
+            contents: &cam_data,
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
 
+        // Lights are uploaded in `update_lighting`, once the state is built, since they may not
+        // fit in the device's limits. Until then, this holds no lights.
         let lighting_buf = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Lighting buffer"),
-            contents: &scene.lighting.to_bytes(),
+            contents: &[0; LIGHTING_SIZE_FIXED + POINT_LIGHT_SIZE],
             // We use a storage buffer, since our lighting size is unknown by the shader;
             // this is due to the dynamic-sized point light array.
             usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
         });
         //
 
-        let bind_groups = create_bindgroups(device, &cam_buf, &lighting_buf);
+        // A placeholder; this is replaced in `setup_entities` if TAA is enabled. Out-of-bounds
+        // storage reads are clamped, so a single matrix is sufficient otherwise.
+        let prev_models_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Previous model matrix buffer"),
+            contents: &Mat4::new_identity().to_bytes(),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let raw_instances = RawInstanceState::new(device);
+        let probes = ProbeState::new(device, surface_cfg.format, graphics_settings.max_env_probes);
 
-        let depth_texture = Texture::create_depth_texture(device, surface_cfg, "Depth texture");
+        let palette_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Palette buffer"),
+            contents: &scene.palette_bytes(),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
 
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Graphics shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+        // Uploaded on the first render.
+        let materials = MaterialTextures::placeholder(device, &graphics_settings.material_sampler);
+
+        let color_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Color settings buffer"),
+            contents: &scene
+                .color
+                .to_bytes(!surface_cfg.format.is_srgb(), scene.toon.cel_bands),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
 
-        let pipeline_layout_graphics =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render pipeline layout"),
-                bind_group_layouts: &[&bind_groups.layout_cam, &bind_groups.layout_lighting],
-                push_constant_ranges: &[],
-            });
+        let clusters =
+            ClusterState::new(device, &lighting_buf, graphics_settings.clustered_lighting);
+
+        let bind_groups = create_bindgroups(
+            device,
+            &BindGroupResources {
+                cam_buf: &cam_buf,
+                lighting_buf: &lighting_buf,
+                clusters: &clusters,
+                prev_models_buf: &prev_models_buf,
+                palette_buf: &palette_buf,
+                materials: &materials,
+                color_buf: &color_buf,
+                probes: &probes,
+            },
+        );
+
+        let depth_format = graphics_settings
+            .depth_format
+            .to_wgpu(graphics_settings.stencil, device.features());
+        let depth_texture =
+            Texture::create_depth_texture(device, surface_cfg, depth_format, "Depth texture");
+
+        let shadows = ShadowState::new(
+            device,
+            graphics_settings.max_shadow_lights,
+            graphics_settings.shadow_resolution,
+        );
+        let lines = LineRenderer::new(device, surface_cfg, depth_format, &bind_groups.layout_cam);
+        let sdf = SdfRenderer::new(device, surface_cfg, depth_format, &bind_groups.layout_cam);
+        let hud = HudRenderer::new(device, surface_cfg, depth_format);
+        let letterbox = LetterboxRenderer::new(device, surface_cfg.format, depth_format);
+        let toon = ToonRenderer::new(device, surface_cfg);
+        let background = BackgroundRenderer::new(device, surface_cfg);
+        let ground = GroundRenderer::new(
+            device,
+            surface_cfg,
+            depth_format,
+            [&bind_groups.layout_cam, &bind_groups.layout_lighting, &shadows.layout],
+        );
+
+        // The scene's shader extension is built on the first frame, since that requires a queue.
+        let mut extension = ExtensionState::default();
+        extension.stale = scene.shader_extension.is_some();
+
+        // Pipelines are created the first time they're drawn with; see `encode_scene`.
+        let pipelines = create_pipeline_cache(
+            device,
+            surface_cfg.format,
+            depth_format,
+            [
+                &bind_groups.layout_cam,
+                &bind_groups.layout_lighting,
+                &bind_groups.layout_instance_data,
+                &shadows.layout,
+            ],
+            &extension,
+        );
+
+        let main_targets = settings_targets(graphics_settings);
+
+        let impostors = ImpostorRenderer::new(device);
+
+        let taa = if main_targets == MainTargets::Taa {
+            Some(TaaState::new(device, surface_cfg))
+        } else {
+            None
+        };
+
+        let deferred = if main_targets == MainTargets::GBuffer {
+            Some(DeferredState::new(
+                device,
+                surface_cfg,
+                pipelines.shader(),
+                [
+                    &bind_groups.layout_cam,
+                    &bind_groups.layout_lighting,
+                    &shadows.layout,
+                ],
+                &depth_texture.depth_view(),
+            ))
+        } else {
+            None
+        };
 
-        let pipeline_graphics =
-            create_render_pipeline(device, &pipeline_layout_graphics, shader, surface_cfg);
+        let culling = create_culling(device, surface_cfg, graphics_settings);
 
         // We initialize instances, the instance buffer and mesh mappings in `setup_entities`.
         // let instances = Vec::new();
         let instance_buf = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Instance buffer"),
             contents: &[], // empty on init
-            usage: BufferUsages::VERTEX,
+            usage: INSTANCE_BUF_USAGE,
         });
 
         // Placeholder value
         let mesh_mappings = Vec::new();
+        let background_color = scene.background_color;
+        let scalar_colors = (
+            scene.scalar_colormap,
+            scene.scalar_range,
+            scene.color.input_space,
+        );
 
-        // todo: Logical (scaling by device?) vs physical pixels
-        // let window_size = winit::dpi::LogicalSize::new(scene.window_size.0, scene.window_size.1);
-        window.set_title(&scene.window_title);
+        // We create the timer in `setup_compute`, since its query count depends on the number
+        // of compute passes.
+        let gpu_timing = graphics_settings.gpu_timing
+            && device.features().contains(wgpu::Features::TIMESTAMP_QUERY);
 
         let mut result = Self {
             vertex_buf,
             index_buf,
             instance_buf,
+            instance_count: 0,
+            prev_models_buf,
+            palette_buf,
+            materials,
             bind_groups,
             camera_buf: cam_buf,
             lighting_buf,
-            pipeline: pipeline_graphics,
+            area_lights: Vec::new(),
+            clusters,
+            color_buf,
+            pipelines,
+            extension,
+            color_format: surface_cfg.format,
+            depth_format,
             depth_texture,
             // staging_belt: wgpu::util::StagingBelt::new(0x100),
             scene,
             inputs_commanded: Default::default(),
             mesh_mappings,
-            window,
+            entity_buckets: Default::default(),
+            entity_instances: Vec::new(),
+            mesh_cache: Default::default(),
+            mesh_ranges: Vec::new(),
+            pending_upload: None,
+            buffer_pool: Default::default(),
+            #[cfg(feature = "compute")]
+            compute_pipelines: Vec::new(),
+            #[cfg(feature = "compute")]
+            compute_errors: Vec::new(),
+            instance_limit_error: None,
+            light_limit_error: None,
+            compute_time: 0.,
+            gpu_timer: None,
+            taa,
+            culling,
+            deferred,
+            settings: graphics_settings.clone(),
+            pending_settings: None,
+            loader: Default::default(),
+            exit_requested: false,
+            #[cfg(feature = "remote")]
+            capture_requested: false,
+            #[cfg(feature = "remote")]
+            captured: None,
+            shadows,
+            raw_instances,
+            probes,
+            impostors,
+            layer_draws: Default::default(),
+            lines,
+            sdf,
+            hud,
+            letterbox,
+            toon,
+            background,
+            ground,
+            entity_debug_lines: Default::default(),
+            entity_debug_shapes: Default::default(),
+            static_batch: None,
+            bundles: Default::default(),
+            gpu_picker: None,
+            anaglyph: None,
+            views: None,
+            debug_view: None,
+            scene_cache: None,
+            background_color,
+            scalar_colors,
+            redraw: None,
+            scene_rendered: false,
+            gui_repaint_delay: Duration::MAX,
         };
 
-        result.setup_vertices_indices(device);
-        result.setup_entities(device);
+        if gpu_timing {
+            result.gpu_timer = Some(GpuTimer::new(device, 0));
+        }
+
+        result.setup_vertices_indices(device, queue);
+        result.setup_entities(device, queue);
+        result.update_lighting(device, queue);
+        result.update_raw_instances(device);
+        #[cfg(feature = "compute")]
+        result.setup_compute(device);
 
         result
     }
 
+    /// Apply an event to the camera controls.
     pub(crate) fn handle_input(&mut self, event: DeviceEvent, input_settings: &InputSettings) {
         match input_settings.initial_controls {
-            ControlScheme::FreeCamera => input::add_input_cmd(event, &mut self.inputs_commanded),
+            ControlScheme::FreeCamera => input::add_input_cmd(
+                event,
+                &mut self.inputs_commanded,
+                &input_settings.key_bindings,
+            ),
+            ControlScheme::Arc => input::add_input_cmd_arc(
+                event,
+                &mut self.inputs_commanded,
+                &input_settings.key_bindings,
+            ),
             // todo: Handle the others.
             _ => (),
         }
     }
 
-    /// todo: WIP to update meshes.
-    pub(crate) fn setup_vertices_indices(&mut self, device: &Device) {
-        let mut vertices = Vec::new();
-        let mut indices = Vec::new();
+    /// Update the vertex and index buffers from the scene's meshes. Meshes already on the GPU
+    /// (by content) aren't re-uploaded, and identical meshes share buffer ranges.
+    /// If the data is larger than `GraphicsSettings::upload_chunk_size`, it's uploaded over several
+    /// frames; see the `upload` module.
+    pub(crate) fn setup_vertices_indices(&mut self, device: &Device, queue: &Queue) {
+        self.bundles.invalidate();
+        let (mesh_ranges, data) = self.mesh_cache.update(&self.scene.meshes);
+
+        self.impostors.update_vertices(device, &mut self.scene);
+
+        let Some((vertex_data, index_data)) = data else {
+            // The meshes are in the buffers being uploaded, if any.
+            match &mut self.pending_upload {
+                Some(upload) => upload.mesh_ranges = mesh_ranges,
+                None => self.mesh_ranges = mesh_ranges,
+            }
+            return;
+        };
+
+        // This replaces any upload in progress.
+        self.pending_upload = None;
+        self.scene.upload_progress = None;
+
+        let chunk_size = self.settings.upload_chunk_size;
+        if chunk_size > 0 && vertex_data.len() + index_data.len() > chunk_size {
+            self.pending_upload = Some(BufferUpload::new(
+                device,
+                vertex_data,
+                index_data,
+                mesh_ranges,
+                VERTEX_BUF_USAGE,
+                INDEX_BUF_USAGE,
+                chunk_size,
+            ));
+            // Meshes aren't drawn until the upload completes.
+            self.mesh_ranges = Vec::new();
+            self.scene.upload_progress = Some(0.);
+            return;
+        }
+
+        self.mesh_ranges = mesh_ranges;
+
+        let pool = &mut self.buffer_pool;
+        pool.write(
+            device,
+            queue,
+            &mut self.vertex_buf,
+            &vertex_data,
+            VERTEX_BUF_USAGE,
+            "Vertex buffer",
+        );
+        pool.write(
+            device,
+            queue,
+            &mut self.index_buf,
+            &index_data,
+            INDEX_BUF_USAGE,
+            "Index buffer",
+        );
+    }
+
+    /// Copy chunks of a pending vertex and index buffer upload, and swap in the new buffers once
+    /// it completes. If `block` is true, waits for the whole upload.
+    fn advance_upload(&mut self, device: &Device, encoder: &mut CommandEncoder, block: bool) {
+        if self.pending_upload.is_some() {
+            // Placeholders are drawn until the upload completes, and meshes after.
+            self.request_redraw(RedrawRegion::All);
+        }
+
+        let Some(upload) = &mut self.pending_upload else {
+            return;
+        };
+
+        let done = loop {
+            let done = upload.advance(device, encoder, block);
+            if done || !block {
+                break done;
+            }
+        };
+
+        if !done {
+            self.scene.upload_progress = Some(upload.progress());
+            return;
+        }
+
+        let upload = self.pending_upload.take().unwrap();
+        let vertex_buf = std::mem::replace(&mut self.vertex_buf, upload.vertex_buf);
+        let index_buf = std::mem::replace(&mut self.index_buf, upload.index_buf);
+        self.buffer_pool.release(vertex_buf);
+        self.buffer_pool.release(index_buf);
+        self.mesh_ranges = upload.mesh_ranges;
+        self.bundles.invalidate();
+        self.scene.upload_progress = None;
+    }
+
+    /// Complete a pending upload immediately, eg before writing to the buffers in place.
+    fn finish_upload(&mut self, device: &Device, queue: &Queue) {
+        if self.pending_upload.is_none() {
+            return;
+        }
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Upload encoder"),
+        });
+        self.advance_upload(device, &mut encoder, true);
+        queue.submit(Some(encoder.finish()));
+    }
+
+    /// Write vertices of meshes that have changed in place (ie with the same vertex count and
+    /// indices) to the vertex buffer, without recreating it. Falls back to rebuilding the vertex and
+    /// index buffers if a mesh can't be updated in place. Returns an error, without updating any
+    /// meshes, if a mesh doesn't exist, or has indices past the end of its vertices.
+    pub(crate) fn update_mesh_vertices(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        meshes: &[usize],
+    ) -> Result<(), String> {
+        // Indices past the end of a mesh whose vertices shrunk would read other meshes' vertices,
+        // or past the end of the buffer.
+        for &mesh_i in meshes {
+            let Some(mesh) = self.scene.meshes.get(mesh_i) else {
+                return Err(format!("Mesh {mesh_i} doesn't exist"));
+            };
+            if let Some(i) = mesh.indices.iter().find(|&&i| i >= mesh.vertices.len()) {
+                return Err(format!(
+                    "Mesh {mesh_i} has index {i}, but only {} vertices",
+                    mesh.vertices.len()
+                ));
+            }
+        }
+
+        self.finish_upload(device, queue);
+
+        for &mesh_i in meshes {
+            match self.mesh_cache.update_vertices(&self.scene.meshes, mesh_i) {
+                Some((offset, data)) => queue.write_buffer(&self.vertex_buf, offset, &data),
+                None => {
+                    self.setup_vertices_indices(device, queue);
+                    break;
+                }
+            }
+        }
+
+        if let Some(culling) = &mut self.culling {
+            culling.update_bounds(device, &culling::mesh_bounds(&mut self.scene));
+        }
+        self.impostors.update_vertices(device, &mut self.scene);
+
+        Ok(())
+    }
+
+    /// Write meshes replaced using `Scene::replace_mesh` to the vertex and index buffers, growing
+    /// them if needed. Entities keep referencing them by index. Falls back to rebuilding the
+    /// buffers if a mesh's range is shared with others.
+    pub(crate) fn replace_meshes(&mut self, device: &Device, queue: &Queue, meshes: &[usize]) {
+        self.finish_upload(device, queue);
+        self.bundles.invalidate();
+
+        for &mesh_i in meshes {
+            let Some(write) = self.mesh_cache.replace(&self.scene.meshes, mesh_i) else {
+                self.setup_vertices_indices(device, queue);
+                break;
+            };
+
+            if self.vertex_buf.size() < write.vertex_buf_size {
+                self.vertex_buf = grow_buffer(
+                    device,
+                    queue,
+                    &self.vertex_buf,
+                    write.vertex_buf_size,
+                    "Vertex buffer",
+                    VERTEX_BUF_USAGE,
+                );
+            }
+            if self.index_buf.size() < write.index_buf_size {
+                self.index_buf = grow_buffer(
+                    device,
+                    queue,
+                    &self.index_buf,
+                    write.index_buf_size,
+                    "Index buffer",
+                    INDEX_BUF_USAGE,
+                );
+            }
+
+            queue.write_buffer(&self.vertex_buf, write.vertex_offset, &write.vertex_data);
+            queue.write_buffer(&self.index_buf, write.index_offset, &write.index_data);
+
+            if let Some(range) = self.mesh_ranges.get_mut(mesh_i) {
+                *range = write.range;
+            }
+        }
+
+        if let Some(culling) = &mut self.culling {
+            culling.update_bounds(device, &culling::mesh_bounds(&mut self.scene));
+        }
+        self.impostors.update_vertices(device, &mut self.scene);
+    }
+
+    /// Currently, sets up entities (And the associated instance buf), but doesn't change
+    /// meshes, lights, or the camera. The vertex and index buffers aren't changed; only the instances.
+    pub(crate) fn setup_entities(&mut self, device: &Device, queue: &Queue) {
+        self.request_redraw(RedrawRegion::All);
+        self.bundles.invalidate();
+
+        // Apply colors from scalars, then group transforms, tints, and visibility. We only clone
+        // entities that have a scalar, or are in a group; these are empty if there are neither.
+        let mut grouped: Vec<Option<Entity>> = Vec::new();
+        let debug_shapes = self.scene.debug.shapes;
+        let mut hidden = Vec::new();
+        let mut group_impostors = Vec::new();
+
+        if self.scene.entities.iter().any(|e| e.scalar.is_some()) {
+            grouped = self
+                .scene
+                .entities
+                .iter()
+                .map(|entity| {
+                    let scalar = entity.scalar?;
+                    let mut entity = entity.clone();
+                    entity.color = self.scene.scalar_color(scalar);
+                    Some(entity)
+                })
+                .collect();
+        }
+
+        if !self.scene.groups.is_empty() {
+            grouped.resize(self.scene.entities.len(), None);
+            hidden = vec![false; self.scene.entities.len()];
+            group_impostors = vec![None; self.scene.entities.len()];
+
+            for group in &self.scene.groups {
+                for &i_ent in &group.entities {
+                    if i_ent >= self.scene.entities.len() {
+                        continue;
+                    }
+                    if !group.visible {
+                        hidden[i_ent] = true;
+                    }
+                    if group.impostor.is_some() {
+                        group_impostors[i_ent] = group.impostor;
+                    }
+
+                    let entity =
+                        grouped[i_ent].get_or_insert_with(|| self.scene.entities[i_ent].clone());
+                    group.apply(entity);
+                }
+            }
+        }
 
-        for mesh in &self.scene.meshes {
-            for vertex in &mesh.vertices {
-                vertices.push(vertex)
+        // Static entities are built once, and reused until invalidated. Changing the entity count
+        // may change their indices.
+        let static_valid = match &self.static_batch {
+            Some(batch) => {
+                batch.entity_count == self.scene.entities.len()
+                    && batch.debug_shapes == debug_shapes
+                    && batch.by_mesh.len() == self.scene.meshes.len()
             }
+            None => false,
+        };
+
+        // Instances are ordered by mesh, then by entity.
+        self.entity_buckets.sync(&self.scene.entities, self.scene.meshes.len());
+
+        let mut by_mesh = vec![Vec::new(); self.scene.meshes.len()];
+        let mut by_mesh_static = vec![Vec::new(); self.scene.meshes.len()];
+        let mut by_mesh_impostor = vec![by_mesh.clone(); Impostor::ALL.len()];
+        let mut by_mesh_individual = vec![by_mesh.clone(); RenderLayer::ALL.len()];
+
+        for (mesh_i, bucket) in self.entity_buckets.by_mesh.iter().enumerate() {
+            for &i_ent in bucket {
+                if hidden.get(i_ent) == Some(&true) {
+                    continue;
+                }
 
-            for index in &mesh.indices {
-                indices.push(index);
+                let entity = &self.scene.entities[i_ent];
+                let group_impostor = group_impostors.get(i_ent).copied().flatten();
+                if entity.layer != RenderLayer::World || entity.index_range.is_some() {
+                    by_mesh_individual[entity.layer.index()][mesh_i].push(i_ent);
+                } else if let Some(kind) = group_impostor.or(self.scene.meshes[mesh_i].impostor) {
+                    by_mesh_impostor[kind.index()][mesh_i].push(i_ent);
+                } else if !self.scene.entities[i_ent].is_static {
+                    by_mesh[mesh_i].push(i_ent);
+                } else if !static_valid {
+                    by_mesh_static[mesh_i].push(i_ent);
+                }
             }
         }
-        // Convert the vertex and index data to u8 buffers.
-        let mut vertex_data = Vec::new();
-        for vertex in vertices {
-            for byte in vertex.to_bytes() {
-                vertex_data.push(byte);
+
+        // Order each mesh's instances by sort key. The sort is stable, so entities with equal keys
+        // stay in entity order.
+        if self.scene.entities.iter().any(|e| e.sort_key != 0) {
+            let sort_key = |i_ent: &usize| self.scene.entities[*i_ent].sort_key;
+
+            for entities in by_mesh
+                .iter_mut()
+                .chain(&mut by_mesh_static)
+                .chain(by_mesh_impostor.iter_mut().flatten())
+                .chain(by_mesh_individual.iter_mut().flatten())
+            {
+                entities.sort_by_key(sort_key);
             }
         }
 
-        let mut index_data = Vec::new();
-        for index in indices {
-            let bytes = index.to_ne_bytes();
-            index_data.push(bytes[0]);
-            index_data.push(bytes[1]);
-            index_data.push(bytes[2]);
-            index_data.push(bytes[3]);
+        let mut inputs = InstanceInputs {
+            entities: &self.scene.entities,
+            grouped: &grouped,
+            meshes: &self.scene.meshes,
+            materials: &self.scene.materials,
+            by_mesh: &by_mesh_static,
+            debug_shapes,
+            prev_model_mats: self.taa.as_ref().map(|t| t.prev_model_mats.as_slice()),
+        };
+
+        if !static_valid {
+            self.static_batch = Some(StaticBatch {
+                instances: parallel::build_instances(&inputs, self.settings.render_threads),
+                by_mesh: by_mesh_static.clone(),
+                entity_count: self.scene.entities.len(),
+                debug_shapes,
+            });
         }
+        let static_batch = self.static_batch.as_mut().unwrap();
 
-        // We can't update using a queue due to buffer size mismatches.
-        let vertex_buf = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Vertex buffer"),
-            contents: &vertex_data,
-            usage: BufferUsages::VERTEX,
-        });
+        inputs.by_mesh = &by_mesh;
+        let dynamic = parallel::build_instances(&inputs, self.settings.render_threads);
 
-        let index_buf = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Index buffer"),
-            contents: &index_data,
-            usage: BufferUsages::INDEX,
+        // Interleave static and dynamic instances, so each mesh's are contiguous.
+        let instance_count =
+            (static_batch.instances.data.len() + dynamic.data.len()) / INSTANCE_SIZE;
+
+        let mut mesh_mappings = Vec::with_capacity(by_mesh.len());
+        let mut entity_instances = vec![None; self.scene.entities.len()];
+        let mut instance_meshes = Vec::with_capacity(instance_count);
+        let mut instance_data = Vec::with_capacity(instance_count * INSTANCE_SIZE);
+
+        // Used for TAA velocity. `prev_models` is in instance order; `model_mats` is in entity
+        // order, for use in the next build.
+        let mut prev_models = Vec::new();
+        let mut model_mats = Vec::new();
+
+        if self.taa.is_some() {
+            prev_models.reserve(instance_count);
+            model_mats = vec![Mat4::new_identity(); self.scene.entities.len()];
+        }
+
+        let mut static_i = 0;
+        let mut dynamic_i = 0;
+
+        for (mesh_i, (entities_static, entities)) in
+            static_batch.by_mesh.iter().zip(&by_mesh).enumerate()
+        {
+            mesh_mappings.push((
+                instance_meshes.len() as u32,
+                (entities_static.len() + entities.len()) as u32,
+            ));
+
+            for (i, chunk, entities) in [
+                (static_i, &static_batch.instances, entities_static),
+                (dynamic_i, &dynamic, entities),
+            ] {
+                for &i_ent in entities {
+                    entity_instances[i_ent] = Some(instance_meshes.len());
+                    instance_meshes.push(mesh_i as u32);
+                }
+
+                let range = i..i + entities.len();
+                instance_data.extend_from_slice(
+                    &chunk.data[range.start * INSTANCE_SIZE..range.end * INSTANCE_SIZE],
+                );
+                if self.taa.is_some() {
+                    prev_models.extend_from_slice(&chunk.prev_models[range]);
+                }
+            }
+
+            static_i += entities_static.len();
+            dynamic_i += entities.len();
+        }
+
+        let static_mats = &static_batch.instances.model_mats;
+        for (i_ent, mat) in static_mats.iter().chain(&dynamic.model_mats) {
+            model_mats[*i_ent] = mat.clone();
+        }
+        let mut motion = static_batch.instances.motion || dynamic.motion;
+
+        let mut debug_lines = static_batch.instances.debug_lines.clone();
+        debug_lines.vertices.extend_from_slice(&dynamic.debug_lines.vertices);
+
+        // Impostors follow the regular instances, so they aren't culled, or drawn to shadow maps.
+        let mut impostor_draws = Vec::new();
+
+        for kind in Impostor::ALL {
+            inputs.by_mesh = &by_mesh_impostor[kind.index()];
+            let chunk = parallel::build_instances(&inputs, self.settings.render_threads);
+
+            let mut instance_i = instance_data.len() / INSTANCE_SIZE;
+            for (mesh_i, entities) in inputs.by_mesh.iter().enumerate() {
+                if entities.is_empty() {
+                    continue;
+                }
+
+                impostor_draws.push(ImpostorDraw {
+                    kind,
+                    mesh: mesh_i,
+                    instance_start: instance_i as u32,
+                    instance_count: entities.len() as u32,
+                });
+
+                for &i_ent in entities {
+                    entity_instances[i_ent] = Some(instance_i);
+                    instance_i += 1;
+                }
+            }
+
+            instance_data.extend_from_slice(&chunk.data);
+            prev_models.extend(chunk.prev_models);
+            for (i_ent, mat) in chunk.model_mats {
+                model_mats[i_ent] = mat;
+            }
+            motion |= chunk.motion;
+            debug_lines.vertices.extend_from_slice(&chunk.debug_lines.vertices);
+        }
+
+        // Entities drawn individually follow these: those in other layers, and those drawing part
+        // of their mesh.
+        let mut layer_draws: [Vec<SubRangeDraw>; 3] = Default::default();
+
+        for layer in RenderLayer::ALL {
+            inputs.by_mesh = &by_mesh_individual[layer.index()];
+            let chunk = parallel::build_instances(&inputs, self.settings.render_threads);
+
+            let mut instance_i = instance_data.len() / INSTANCE_SIZE;
+            let mut draws = Vec::new();
+            for (mesh_i, entities) in inputs.by_mesh.iter().enumerate() {
+                for &i_ent in entities {
+                    let entity = &self.scene.entities[i_ent];
+                    let (index_start, index_count) = entity.index_range.unwrap_or((0, u32::MAX));
+
+                    draws.push((
+                        entity.sort_key,
+                        SubRangeDraw {
+                            mesh: mesh_i,
+                            instance: instance_i as u32,
+                            index_start,
+                            index_count,
+                        },
+                    ));
+
+                    entity_instances[i_ent] = Some(instance_i);
+                    instance_i += 1;
+                }
+            }
+
+            // These are drawn individually, so we can order them by sort key across meshes.
+            draws.sort_by_key(|(sort_key, _)| *sort_key);
+            layer_draws[layer.index()] = draws.into_iter().map(|(_, draw)| draw).collect();
+
+            instance_data.extend_from_slice(&chunk.data);
+            prev_models.extend(chunk.prev_models);
+            for (i_ent, mat) in chunk.model_mats {
+                model_mats[i_ent] = mat;
+            }
+            motion |= chunk.motion;
+            debug_lines.vertices.extend_from_slice(&chunk.debug_lines.vertices);
+        }
+
+        // Static entities don't move after this build.
+        if self.taa.is_some() {
+            static_batch.instances.prev_models =
+                static_batch.instances.model_mats.iter().map(|(_, m)| m.clone()).collect();
+            static_batch.instances.motion = false;
+        }
+
+        // If the instances don't fit in the device's limits, keep drawing the previous ones.
+        self.instance_limit_error = self
+            .check_instance_limits(device, instance_data.len() / INSTANCE_SIZE)
+            .err();
+        self.update_buffer_errors();
+        if self.instance_limit_error.is_some() {
+            return;
+        }
+
+        if let Some(pre_upload) = self.scene.pre_upload {
+            let mut packed = PackedInstances::new(&mut instance_data, &entity_instances);
+            pre_upload(&mut packed, &self.scene);
+        }
+
+        self.buffer_pool.write(
+            device,
+            queue,
+            &mut self.instance_buf,
+            &instance_data,
+            INSTANCE_BUF_USAGE,
+            "Instance buffer",
+        );
+
+        self.instance_count = instance_data.len() / INSTANCE_SIZE;
+        self.mesh_mappings = mesh_mappings;
+        self.entity_instances = entity_instances;
+        self.impostors.draws = impostor_draws;
+        self.layer_draws = layer_draws;
+        self.entity_debug_lines = debug_lines;
+        self.entity_debug_shapes = debug_shapes;
+
+        if let Some(culling) = &mut self.culling {
+            culling.update_instances(
+                device,
+                &instance_meshes,
+                &culling::mesh_bounds(&mut self.scene),
+            );
+        }
+
+        if let Some(taa) = &mut self.taa {
+            let mut prev_data = Vec::with_capacity(prev_models.len() * MAT4_SIZE);
+            for mat in &prev_models {
+                prev_data.extend_from_slice(&mat.to_bytes());
+            }
+            // Storage bindings can't be empty.
+            if prev_data.is_empty() {
+                prev_data.extend_from_slice(&Mat4::new_identity().to_bytes());
+            }
+
+            let replaced = self.buffer_pool.write(
+                device,
+                queue,
+                &mut self.prev_models_buf,
+                &prev_data,
+                BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                "Previous model matrix buffer",
+            );
+
+            if replaced {
+                self.bind_groups.instance_data = create_instance_data_bindgroup(
+                    device,
+                    &self.bind_groups.layout_instance_data,
+                    &self.prev_models_buf,
+                    &self.palette_buf,
+                    &self.materials,
+                );
+            }
+
+            taa.prev_model_mats = model_mats;
+            taa.instances_fresh = true;
+            taa.instance_motion = motion;
+            taa.moved_entities = None;
+        }
+    }
+
+    /// Find the nearest entity whose bounding sphere a ray hits, on the GPU. See `gpu_pick.rs`.
+    pub(crate) fn gpu_pick(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        origin: Vec3,
+        dir: Vec3,
+    ) -> Option<GpuHit> {
+        if dir.magnitude_squared() == 0. {
+            return None;
+        }
+
+        let instance_count = self.instance_count;
+
+        let mut instance_meshes = vec![0; instance_count];
+        let impostor_ranges = self
+            .impostors
+            .draws
+            .iter()
+            .map(|d| (d.mesh, (d.instance_start, d.instance_count)));
+        let individual_ranges = self
+            .layer_draws
+            .iter()
+            .flatten()
+            .map(|d| (d.mesh, (d.instance, 1)));
+        for (mesh_i, (start, count)) in self
+            .mesh_mappings
+            .iter()
+            .copied()
+            .enumerate()
+            .chain(impostor_ranges)
+            .chain(individual_ranges)
+        {
+            let range = start as usize..(start + count) as usize;
+            instance_meshes[range].fill(mesh_i as u32);
+        }
+
+        let mut instance_entities = vec![PICK_NONE; instance_count];
+        // Hidden entities have no instance.
+        let entities = self.entity_instances.iter().zip(&self.scene.entities);
+        for (i_ent, (instance, entity)) in entities.enumerate() {
+            if let Some(i) = instance {
+                if entity.pickable && entity.scale != 0. {
+                    instance_entities[*i] = i_ent as u32;
+                }
+            }
+        }
+
+        let picker = self.gpu_picker.get_or_insert_with(|| GpuPicker::new(device));
+        let (instance, distance) = picker.pick(
+            device,
+            queue,
+            &self.instance_buf,
+            &instance_meshes,
+            &instance_entities,
+            &culling::mesh_bounds(&mut self.scene),
+            origin,
+            dir.to_normalized(),
+        )?;
+
+        Some(GpuHit {
+            entity: instance_entities[instance as usize] as usize,
+            distance,
+        })
+    }
+
+    /// Write instances for entities whose transform or appearance changed, without rebuilding the
+    /// instance buffer. Falls back to rebuilding if this isn't possible, eg if an entity was
+    /// added, if debug shapes, which are built with instances, are in use, or if
+    /// `Scene::pre_upload` is set.
+    ///
+    /// With TAA, we also write the changed entities' previous transforms, and reset those of
+    /// entities that moved in the previous update, so only these entities have velocity.
+    pub(crate) fn update_entity_instances(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        entities: &[usize],
+    ) {
+        self.request_redraw(RedrawRegion::All);
+
+        // After a full rebuild with motion, we don't know which entities moved.
+        let taa_untracked = self.taa.as_ref().is_some_and(|taa| {
+            taa.prev_model_mats.len() != self.scene.entities.len()
+                || (taa.instance_motion && taa.moved_entities.is_none())
         });
 
-        self.vertex_buf = vertex_buf;
-        self.index_buf = index_buf;
-    }
+        if taa_untracked
+            || !self.entity_debug_lines.vertices.is_empty()
+            || self.scene.pre_upload.is_some()
+        {
+            self.setup_entities(device, queue);
+            return;
+        }
+
+        if let Some(taa) = &mut self.taa {
+            for i in taa.moved_entities.take().unwrap_or_default() {
+                if let (false, Some(Some(instance_i))) =
+                    (entities.contains(&i), self.entity_instances.get(i))
+                {
+                    let offset = (instance_i * MAT4_SIZE) as u64;
+                    let data = taa.prev_model_mats[i].to_bytes();
+                    queue.write_buffer(&self.prev_models_buf, offset, &data);
+                }
+            }
+        }
+
+        let mut moved = Vec::new();
+
+        for &i in entities {
+            // The static batch would otherwise overwrite this on the next rebuild.
+            if self.scene.entities.get(i).map(|e| e.is_static) == Some(true) {
+                self.static_batch = None;
+            }
+
+            let (Some(Some(instance_i)), Some(entity)) =
+                (self.entity_instances.get(i), self.scene.entity_in_world(i))
+            else {
+                self.setup_entities(device, queue);
+                return;
+            };
+
+            let offset = (instance_i * INSTANCE_SIZE) as u64;
+            let mut instance = Instance::from_entity(&entity);
+            if let Some(mesh) = self.scene.meshes.get(entity.mesh) {
+                instance.apply_material(mesh, &self.scene.materials);
+            }
+            queue.write_buffer(&self.instance_buf, offset, &instance.to_bytes());
+
+            if let Some(taa) = &mut self.taa {
+                let model_mat = instance.model_mat();
+                if taa.prev_model_mats[i].data != model_mat.data {
+                    moved.push(i);
+                }
+
+                let prev = mem::replace(&mut taa.prev_model_mats[i], model_mat);
+                let offset = (instance_i * MAT4_SIZE) as u64;
+                queue.write_buffer(&self.prev_models_buf, offset, &prev.to_bytes());
+            }
+        }
+
+        if let Some(taa) = &mut self.taa {
+            taa.instances_fresh = true;
+            taa.instance_motion = !moved.is_empty();
+            taa.moved_entities = Some(moved);
+        }
+    }
+
+    /// Build bind groups for the scene's shader extension, and recreate the main shader and its
+    /// pipelines with it.
+    pub(crate) fn setup_extension(&mut self, device: &Device, queue: &Queue) {
+        let max_groups =
+            (device.limits().max_bind_groups).saturating_sub(EXTENSION_GROUP_START) as usize;
+
+        self.extension = ExtensionState::new(
+            device,
+            queue,
+            self.scene.shader_extension.as_ref(),
+            max_groups,
+        );
+        self.update_shader_errors();
+
+        self.rebuild_pipelines(device);
+    }
+
+    /// Report errors from building the shader extension and compute passes to the application.
+    fn update_shader_errors(&mut self) {
+        self.scene.shader_errors = self.extension.error.iter().cloned().collect();
+
+        #[cfg(feature = "compute")]
+        self.scene
+            .shader_errors
+            .extend(self.compute_errors.iter().cloned());
+    }
+
+    /// An error if `instance_count` instances need larger buffers than the device allows: the
+    /// instance buffer, and with TAA, a storage binding of their previous model matrices.
+    fn check_instance_limits(&self, device: &Device, instance_count: usize) -> Result<(), String> {
+        let max_buffer_size = device.limits().max_buffer_size;
+        limits::check_fits("instances", instance_count, 0, INSTANCE_SIZE, max_buffer_size)?;
+
+        if self.taa.is_some() {
+            let max_storage = limits::max_storage_size(device);
+            limits::check_fits("instances", instance_count, 0, MAT4_SIZE, max_storage)?;
+        }
+        Ok(())
+    }
+
+    /// Report errors from uploading instances and lights to the application.
+    fn update_buffer_errors(&mut self) {
+        self.scene.buffer_errors = [&self.instance_limit_error, &self.light_limit_error]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+    }
+
+    /// `Scene::buffer_errors`, as a `Result`, for callers that can return one, eg headless
+    /// rendering.
+    pub(crate) fn check_buffer_errors(&self) -> Result<(), String> {
+        if self.scene.buffer_errors.is_empty() {
+            return Ok(());
+        }
+        Err(self.scene.buffer_errors.join("; "))
+    }
+
+    /// The number of user compute passes; GPU timing queries each.
+    fn num_compute_passes(&self) -> usize {
+        #[cfg(feature = "compute")]
+        return self.scene.compute_passes.len();
+        #[cfg(not(feature = "compute"))]
+        0
+    }
+
+    /// Recreate the main shader and pipeline cache, eg after the extension or shadow maps change.
+    fn rebuild_pipelines(&mut self, device: &Device) {
+        self.bundles.invalidate();
+        self.pipelines = create_pipeline_cache(
+            device,
+            self.color_format,
+            self.depth_format,
+            [
+                &self.bind_groups.layout_cam,
+                &self.bind_groups.layout_lighting,
+                &self.bind_groups.layout_instance_data,
+                &self.shadows.layout,
+            ],
+            &self.extension,
+        );
+    }
+
+    /// Apply changed graphics settings, recreating only the resources they affect. The caller
+    /// reconfigures the surface if `present_mode` changed.
+    pub(crate) fn apply_settings(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        surface_cfg: &SurfaceConfiguration,
+        settings: GraphicsSettings,
+    ) {
+        let prev = std::mem::replace(&mut self.settings, settings.clone());
+        self.bundles.invalidate();
+
+        if settings.gpu_timing != prev.gpu_timing {
+            self.gpu_timer = None;
+            if settings.gpu_timing && device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+                self.gpu_timer = Some(GpuTimer::new(device, self.num_compute_passes()));
+            }
+        }
+
+        if settings.material_sampler != prev.material_sampler {
+            self.update_materials(device, queue);
+        }
+
+        let shadows_changed = settings.max_shadow_lights != prev.max_shadow_lights
+            || settings.shadow_resolution != prev.shadow_resolution;
+
+        if shadows_changed {
+            self.shadows = ShadowState::new(
+                device,
+                settings.max_shadow_lights,
+                settings.shadow_resolution,
+            );
+            // The main pipeline layout includes the shadow bind group's.
+            self.rebuild_pipelines(device);
+            self.ground = GroundRenderer::new(
+                device,
+                surface_cfg,
+                self.depth_format,
+                [
+                    &self.bind_groups.layout_cam,
+                    &self.bind_groups.layout_lighting,
+                    &self.shadows.layout,
+                ],
+            );
+        }
+
+        let probes_changed = settings.max_env_probes != prev.max_env_probes;
+        let clusters_changed = settings.clustered_lighting != prev.clustered_lighting;
+
+        if probes_changed {
+            self.probes = ProbeState::new(device, self.color_format, settings.max_env_probes);
+        }
+        if clusters_changed {
+            self.clusters =
+                ClusterState::new(device, &self.lighting_buf, settings.clustered_lighting);
+        }
+        if probes_changed || clusters_changed {
+            (self.bind_groups.lighting, self.bind_groups.lighting_capture) =
+                create_lighting_bindgroups(
+                    device,
+                    &self.bind_groups.layout_lighting,
+                    &self.lighting_buf,
+                    &self.clusters,
+                    &self.color_buf,
+                    &self.probes,
+                );
+        }
+
+        let targets = settings_targets(&settings);
+        let targets_changed = targets != settings_targets(&prev);
+
+        if targets_changed {
+            self.taa = (targets == MainTargets::Taa).then(|| TaaState::new(device, surface_cfg));
+
+            // The TAA portion of the camera uniform is zero when TAA is disabled.
+            if self.taa.is_none() {
+                queue.write_buffer(&self.camera_buf, CAMERA_SIZE as u64, &[0; TAA_CAMERA_SIZE]);
+            }
+        }
+
+        // The lighting pass shares the main shader, and the shadow bind group layout.
+        if targets_changed || shadows_changed {
+            self.deferred = (targets == MainTargets::GBuffer).then(|| {
+                DeferredState::new(
+                    device,
+                    surface_cfg,
+                    self.pipelines.shader(),
+                    [
+                        &self.bind_groups.layout_cam,
+                        &self.bind_groups.layout_lighting,
+                        &self.shadows.layout,
+                    ],
+                    &self.depth_texture.depth_view(),
+                )
+            });
+        }
+
+        // Instances include previous transforms with TAA, and are assigned to culling buffers.
+        if targets_changed || settings.occlusion_culling != prev.occlusion_culling {
+            self.culling = create_culling(device, surface_cfg, &settings);
+
+            self.static_batch = None;
+            self.setup_entities(device, queue);
+            self.update_raw_instances(device);
+        }
+    }
+
+    /// Build pipelines and user buffers for the scene's compute passes.
+    #[cfg(feature = "compute")]
+    pub(crate) fn setup_compute(&mut self, device: &Device) {
+        self.compute_errors.clear();
+        self.compute_pipelines = Vec::new();
+
+        for pass in &self.scene.compute_passes {
+            match ComputePipelineData::new(device, pass) {
+                Ok(data) => self.compute_pipelines.push(Some(data)),
+                Err(e) => {
+                    self.compute_errors.push(e);
+                    self.compute_pipelines.push(None);
+                }
+            }
+        }
+        self.update_shader_errors();
+
+        if self.gpu_timer.is_some() {
+            self.gpu_timer = Some(GpuTimer::new(device, self.scene.compute_passes.len()));
+        }
+    }
+
+    /// Encode the scene's compute passes for a given stage into the frame's encoder.
+    #[cfg(feature = "compute")]
+    fn encode_compute(&self, stage: ComputeStage, ctx: &PassContext, encoder: &mut CommandEncoder) {
+        compute::encode_passes(
+            self.scene.compute_passes.iter().zip(&self.compute_pipelines),
+            stage,
+            ctx,
+            encoder,
+            &self.mesh_buffers(),
+            self.compute_time,
+            self.gpu_timer.as_ref(),
+        );
+    }
+
+    /// The buffers entities' meshes and instances are drawn from.
+    fn mesh_buffers(&self) -> MeshBuffers<'_> {
+        MeshBuffers {
+            vertex_buf: &self.vertex_buf,
+            index_buf: &self.index_buf,
+            instance_buf: &self.instance_buf,
+            mesh_ranges: &self.mesh_ranges,
+            mesh_mappings: &self.mesh_mappings,
+            meshes: &self.scene.meshes,
+        }
+    }
+
+    pub(crate) fn update_camera(&mut self, queue: &Queue) {
+        self.request_redraw(RedrawRegion::All);
+        queue.write_buffer(&self.camera_buf, 0, &self.scene.camera.to_bytes());
+    }
+
+    pub(crate) fn update_color(&mut self, queue: &Queue) {
+        let data = self
+            .scene
+            .color
+            .to_bytes(!self.color_format.is_srgb(), self.scene.toon.cel_bands);
+        queue.write_buffer(&self.color_buf, 0, &data);
+    }
+
+    /// Render part of the scene next frame, with `RedrawMode::OnChange`. Requests are combined.
+    pub(crate) fn request_redraw(&mut self, region: RedrawRegion) {
+        self.redraw = Some(match self.redraw {
+            Some(prev) => prev.union(region),
+            None => region,
+        });
+    }
+
+    /// With `RedrawMode::Idle`, whether to run another frame without waiting for input: the scene
+    /// was rendered this frame, so may still be changing, eg from an animation; changes are
+    /// pending, or camera controls are held.
+    pub(crate) fn frame_needed(&self) -> bool {
+        self.scene_rendered || self.redraw.is_some() || self.inputs_commanded.inputs_present()
+    }
+
+    /// The part of the scene to render this frame: all of it with `RedrawMode::Always`, or what's
+    /// changed with `OnChange`, if anything. Creates the scene cache if required.
+    fn take_redraw(
+        &mut self,
+        device: &Device,
+        width: u32,
+        height: u32,
+        viewport: (f32, f32, f32, f32),
+    ) -> Option<RedrawRegion> {
+        if self.scene.redraw_mode == RedrawMode::Always {
+            self.scene_cache = None;
+            self.redraw = None;
+            return Some(RedrawRegion::All);
+        }
+
+        let size = (width.max(1), height.max(1));
+        if self.scene_cache.as_ref().map(|c| (c.width, c.height)) != Some(size) {
+            self.scene_cache = Some(SceneCache::new(
+                device,
+                self.color_format,
+                self.depth_format,
+                width,
+                height,
+            ));
+        }
+
+        let cache = self.scene_cache.as_mut().unwrap();
+        if cache.viewport != viewport {
+            cache.viewport = viewport;
+            self.redraw = Some(RedrawRegion::All);
+        }
+
+        self.redraw.take()
+    }
+
+    /// If part of the scene can be redrawn with the main pass alone, over the previous frame.
+    /// Other passes, eg the TAA resolve and deferred lighting, cover the whole viewport.
+    fn partial_redraw_supported(&self) -> bool {
+        self.taa.is_none()
+            && self.deferred.is_none()
+            && self.scene.anaglyph.is_none()
+            && self.scene.extra_views.is_empty()
+            && self.scene.inset.is_none()
+            && self.scene.background_gradient.is_none()
+            && !self.scene.toon.outlines
+            && !self.draws_layer(RenderLayer::Background)
+            && !self.draws_layer(RenderLayer::Overlay)
+    }
+
+    fn clear_color(&self) -> wgpu::Color {
+        self.scene
+            .color
+            .clear_color(self.scene.background_color, !self.color_format.is_srgb())
+    }
+
+    /// The camera, lighting, and shadow bind groups, for the ground plane.
+    fn ground_bind_groups(&self) -> [&BindGroup; 3] {
+        [
+            &self.bind_groups.cam,
+            &self.bind_groups.lighting,
+            &self.shadows.bind_group,
+        ]
+    }
+
+    /// How the main color target is loaded: cleared to the background color, or loaded if the
+    /// background gradient was drawn first.
+    fn color_load_op(&self) -> wgpu::LoadOp<wgpu::Color> {
+        if self.scene.background_gradient.is_some() || self.draws_layer(RenderLayer::Background) {
+            wgpu::LoadOp::Load
+        } else {
+            wgpu::LoadOp::Clear(self.clear_color())
+        }
+    }
+
+    /// The main pipelines; these have a velocity target if TAA is enabled, or write to the
+    /// G-buffer with deferred shading. They must be prepared; see `prepare_pipelines`.
+    fn mesh_pipelines(&self) -> MeshPipelines<'_> {
+        self.pipelines.meshes(self.layer_key(RenderLayer::World))
+    }
+
+    /// The pipeline key for meshes in a render layer. The background and overlay layers are
+    /// drawn to the main pass's color and velocity targets with TAA, or to the output otherwise.
+    fn layer_key(&self, layer: RenderLayer) -> PipelineKey {
+        let targets = match layer {
+            RenderLayer::World => self.main_targets(),
+            _ if self.taa.is_some() => MainTargets::Taa,
+            _ => MainTargets::Color,
+        };
+
+        PipelineKey::new(targets).with_depth_test(self.scene.layers.get(layer).depth_test)
+    }
+
+    /// If a render layer has entities drawn individually; see `layer_draws`.
+    fn draws_layer(&self, layer: RenderLayer) -> bool {
+        !self.layer_draws[layer.index()].is_empty()
+    }
+
+    /// Depth is cleared before a layer if its settings ask, or if no previous layer was drawn.
+    fn layer_depth_load_op(&self, layer: RenderLayer) -> wgpu::LoadOp<f32> {
+        let first = match layer {
+            RenderLayer::Background => true,
+            RenderLayer::World => !self.draws_layer(RenderLayer::Background),
+            RenderLayer::Overlay => false,
+        };
+
+        if first || self.scene.layers.get(layer).clear_depth {
+            wgpu::LoadOp::Clear(1.0)
+        } else {
+            wgpu::LoadOp::Load
+        }
+    }
+
+    /// Draw entities in the background or overlay render layer, in their own pass. See the
+    /// `layers` module.
+    fn encode_layer(
+        &self,
+        encoder: &mut CommandEncoder,
+        layer: RenderLayer,
+        output_view: &TextureView,
+        viewport: (f32, f32, f32, f32),
+        color_load: wgpu::LoadOp<wgpu::Color>,
+    ) {
+        if !self.draws_layer(layer) {
+            return;
+        }
+
+        let color_attachment = |view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: color_load,
+                    store: StoreOp::Store,
+                },
+            })
+        };
+
+        // With TAA, layers are resolved along with the scene.
+        let color_attachments = match &self.taa {
+            Some(taa) => vec![
+                color_attachment(&taa.color.view),
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &taa.velocity.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: StoreOp::Store,
+                    },
+                }),
+            ],
+            None => vec![color_attachment(output_view)],
+        };
+
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Layer render pass"),
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: self.layer_depth_load_op(layer),
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        let (x, y, width, height) = viewport;
+        rpass.set_viewport(x, y, width, height, 0., 1.);
+
+        rpass.set_bind_group(0, &self.bind_groups.cam, &[]);
+        rpass.set_bind_group(1, &self.bind_groups.lighting, &[]);
+        rpass.set_bind_group(2, &self.bind_groups.instance_data, &[]);
+        rpass.set_bind_group(3, &self.shadows.bind_group, &[]);
+        self.extension.set_bind_groups(&mut rpass);
+
+        sub_range::draw(
+            &mut rpass,
+            self.pipelines.meshes(self.layer_key(layer)),
+            &self.layer_draws[layer.index()],
+            &self.mesh_buffers(),
+        );
+    }
+
+    /// Create the main pass pipelines this frame needs, if they don't exist yet. Render passes
+    /// borrow pipelines, so this must run before they're encoded.
+    fn prepare_pipelines(&mut self, device: &Device) {
+        let targets = self.main_targets();
+        for layer in RenderLayer::ALL {
+            if layer == RenderLayer::World || self.draws_layer(layer) {
+                let key = self.layer_key(layer);
+                self.pipelines.prepare_meshes(device, key, &self.scene.meshes);
+            }
+        }
+
+        if !self.impostors.draws.is_empty() {
+            self.pipelines.prepare(device, PipelineKey::impostor(targets));
+        }
+    }
+
+    /// The color targets of the main pass.
+    fn main_targets(&self) -> MainTargets {
+        if self.taa.is_some() {
+            MainTargets::Taa
+        } else if self.deferred.is_some() {
+            MainTargets::GBuffer
+        } else {
+            MainTargets::Color
+        }
+    }
+
+    /// Write the active palette. If it's grown, we recreate its buffer.
+    pub(crate) fn update_palette(&mut self, device: &Device, queue: &Queue) {
+        let data = self.scene.palette_bytes();
+
+        if data.len() as u64 <= self.palette_buf.size() {
+            queue.write_buffer(&self.palette_buf, 0, &data);
+            return;
+        }
+
+        self.palette_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Palette buffer"),
+            contents: &data,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+
+        self.rebind_instance_data(device);
+    }
+
+    /// Pack and upload material textures; see `Scene::materials`. Instances are rebuilt if any
+    /// material sets shinyness, which is applied to them.
+    pub(crate) fn update_materials(&mut self, device: &Device, queue: &Queue) {
+        self.materials = MaterialTextures::new(
+            device,
+            queue,
+            &self.scene.materials,
+            &self.settings.material_sampler,
+        );
+        self.rebind_instance_data(device);
+
+        if self.scene.materials.iter().any(|m| m.shinyness.is_some()) {
+            self.static_batch = None;
+            self.setup_entities(device, queue);
+        }
+    }
+
+    /// Rewrite the texture layers of changed materials; see `EngineUpdates::material_images`.
+    /// Falls back to repacking all materials if a layer can't be rewritten, eg since an image grew.
+    pub(crate) fn update_material_images(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        materials: &[usize],
+    ) {
+        if self.materials.stale {
+            // Not uploaded yet; this happens before the next frame.
+            return;
+        }
+
+        for &i in materials {
+            let Some(material) = self.scene.materials.get(i) else {
+                continue;
+            };
+
+            if !self.materials.write_layer(queue, material, i) {
+                self.update_materials(device, queue);
+                return;
+            }
+        }
+    }
+
+    /// Recreate instance data bind groups, after the palette buffer or material textures are.
+    fn rebind_instance_data(&mut self, device: &Device) {
+        self.bundles.invalidate();
+        self.bind_groups.instance_data = create_instance_data_bindgroup(
+            device,
+            &self.bind_groups.layout_instance_data,
+            &self.prev_models_buf,
+            &self.palette_buf,
+            &self.materials,
+        );
+        self.raw_instances.rebind(
+            device,
+            &self.bind_groups.layout_instance_data,
+            &self.palette_buf,
+            &self.materials,
+        );
+    }
+
+    /// Upload raw instances; see `Scene::set_instances_raw`.
+    pub(crate) fn update_raw_instances(&mut self, device: &Device) {
+        self.raw_instances.update(
+            device,
+            &self.scene,
+            self.taa.is_some(),
+            &self.bind_groups.layout_instance_data,
+            &self.palette_buf,
+            &self.materials,
+        );
+    }
+
+    /// Write only the projection-view matrix, eg after the projection changes.
+    pub(crate) fn update_camera_projection(&mut self, queue: &Queue) {
+        self.request_redraw(RedrawRegion::All);
+        let cam = &self.scene.camera;
+        let proj_view = cam.proj_mat.clone() * cam.view_mat();
+        queue.write_buffer(&self.camera_buf, 0, &proj_view.to_bytes());
+    }
+
+    /// Write only the given point lights. The number of lights must be unchanged.
+    pub(crate) fn update_lights(&mut self, queue: &Queue, lights: &[usize]) {
+        self.request_redraw(RedrawRegion::All);
+        for &i in lights {
+            if let Some(bytes) = self.scene.lighting.light_bytes(i) {
+                let offset = (LIGHTING_SIZE_FIXED + i * POINT_LIGHT_SIZE) as u64;
+                // The buffer doesn't hold lights added since the last full upload.
+                if offset + POINT_LIGHT_SIZE as u64 <= self.lighting_buf.size() {
+                    queue.write_buffer(&self.lighting_buf, offset, &bytes);
+                }
+            }
+        }
+    }
+
+    /// Upload all lights, including those from area light proxies. The buffer grows as needed.
+    pub(crate) fn update_lighting(&mut self, device: &Device, queue: &Queue) {
+        self.request_redraw(RedrawRegion::All);
+
+        // The shader reads all lights from one storage binding. If they don't fit, keep drawing
+        // the previous ones.
+        self.light_limit_error = limits::check_fits(
+            "point lights",
+            self.scene.lighting.point_lights.len() + self.area_lights.len(),
+            LIGHTING_SIZE_FIXED,
+            POINT_LIGHT_SIZE,
+            limits::max_storage_size(device),
+        )
+        .err();
+        self.update_buffer_errors();
+        if self.light_limit_error.is_some() {
+            return;
+        }
+
+        let lighting = self
+            .scene
+            .lighting
+            .to_bytes_with(&self.area_lights, self.scene.scale_factor());
+
+        let replaced = self.buffer_pool.write(
+            device,
+            queue,
+            &mut self.lighting_buf,
+            &lighting,
+            BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            "Lighting buffer",
+        );
+
+        // Clusters, and the lighting bind groups, reference the buffer.
+        if replaced {
+            self.bundles.invalidate();
+            self.clusters = ClusterState::new(
+                device,
+                &self.lighting_buf,
+                self.settings.clustered_lighting,
+            );
+            (self.bind_groups.lighting, self.bind_groups.lighting_capture) =
+                create_lighting_bindgroups(
+                    device,
+                    &self.bind_groups.layout_lighting,
+                    &self.lighting_buf,
+                    &self.clusters,
+                    &self.color_buf,
+                    &self.probes,
+                );
+        }
+    }
+
+    /// Record draw calls for each range of meshes into a render bundle, using a thread per range.
+    /// These are cached in `bundles`; see the `parallel` module.
+    fn encode_bundles(&self, device: &Device, ranges: &[Range<usize>]) -> Vec<RenderBundle> {
+        let color_formats = self.main_targets().formats(self.color_format);
+
+        let (instance_buf, indirect_buf) = match self.culling.as_ref().filter(|c| c.active()) {
+            Some(culling) => (&culling.culled_buf, Some(&culling.indirect_buf)),
+            None => (&self.instance_buf, None),
+        };
+
+        let mut bind_groups = vec![
+            &self.bind_groups.cam,
+            &self.bind_groups.lighting,
+            &self.bind_groups.instance_data,
+            &self.shadows.bind_group,
+        ];
+        bind_groups.extend(&self.extension.bind_groups);
+
+        let inputs = DrawInputs {
+            pipelines: self.mesh_pipelines(),
+            meshes: &self.scene.meshes,
+            bind_groups: &bind_groups,
+            color_formats: &color_formats,
+            depth_format: self.depth_format,
+            vertex_buf: &self.vertex_buf,
+            index_buf: &self.index_buf,
+            instance_buf,
+            indirect_buf,
+            mesh_ranges: &self.mesh_ranges,
+            mesh_mappings: &self.mesh_mappings,
+        };
+
+        parallel::map_ranges(ranges, |range| inputs.encode(device, range))
+    }
+
+    fn setup_render_pass<'a>(
+        &mut self,
+        device: &Device,
+        encoder: &'a mut CommandEncoder,
+        output_view: &TextureView,
+        viewport: (f32, f32, f32, f32),
+        region: Option<(u32, u32, u32, u32)>,
+    ) -> RenderPass<'a> {
+        let (x, y, eff_width, eff_height) = viewport;
+
+        // A partial redraw keeps the previous frame outside the region. It's cleared beforehand.
+        let (color_load, depth_load, stencil_load) = match region {
+            Some(_) => (wgpu::LoadOp::Load, wgpu::LoadOp::Load, wgpu::LoadOp::Load),
+            None => (
+                self.color_load_op(),
+                self.layer_depth_load_op(RenderLayer::World),
+                wgpu::LoadOp::Clear(0),
+            ),
+        };
+        // The stencil, if present, is cleared here; other passes leave it unchanged.
+        let stencil_ops = self
+            .depth_format
+            .has_stencil_aspect()
+            .then_some(wgpu::Operations {
+                load: stencil_load,
+                store: StoreOp::Store,
+            });
+
+        // With TAA, we render to an offscreen texture, along with velocity, and resolve to the
+        // output in a separate pass.
+        let color_view = match &self.taa {
+            Some(taa) => &taa.color.view,
+            None => output_view,
+        };
+
+        let mut color_attachments = match &self.deferred {
+            Some(deferred) => deferred.attachments(),
+            None => vec![Some(wgpu::RenderPassColorAttachment {
+                view: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: color_load,
+                    store: StoreOp::Store,
+                },
+            })],
+        };
+
+        if let Some(taa) = &self.taa {
+            color_attachments.push(Some(wgpu::RenderPassColorAttachment {
+                view: &taa.velocity.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: StoreOp::Store,
+                },
+            }));
+        }
+
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Render pass"),
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: depth_load,
+                    store: StoreOp::Store,
+                }),
+                stencil_ops,
+            }),
+            timestamp_writes: self.gpu_timer.as_ref().map(|t| t.main_writes()),
+            occlusion_query_set: None,
+        });
+
+        // Adjust the portion of the 3D rendering to take up the space not taken up by the UI.
+        rpass.set_viewport(x, y, eff_width, eff_height, 0., 1.);
+
+        if let Some((x, y, width, height)) = region {
+            rpass.set_scissor_rect(x, y, width, height);
+        }
+
+        // Taken, so we can record bundles while borrowing self.
+        let mut bundles = mem::take(&mut self.bundles);
+
+        let pipelines = self.mesh_pipelines();
+        rpass.set_pipeline(pipelines.get(FaceCulling::Back));
+
+        rpass.set_bind_group(0, &self.bind_groups.cam, &[]);
+        rpass.set_bind_group(1, &self.bind_groups.lighting, &[]);
+        rpass.set_bind_group(2, &self.bind_groups.instance_data, &[]);
+        rpass.set_bind_group(3, &self.shadows.bind_group, &[]);
+        self.extension.set_bind_groups(&mut rpass);
+
+        // These may briefly differ in length after meshes change, until entities are rebuilt.
+        let num_meshes = self.mesh_ranges.len().min(self.mesh_mappings.len());
+        let ranges = parallel::partition(num_meshes, self.settings.render_threads);
+
+        if ranges.len() > 1 {
+            // Encode draw calls for each range of meshes on its own thread, if they've changed
+            // since the last frame. Executing bundles resets the pass's bind groups, so we set
+            // them again for impostors and lines.
+            let key = BundleKey {
+                ranges: ranges.clone(),
+                pipeline_key: self.layer_key(RenderLayer::World),
+                culled: self.culling.as_ref().is_some_and(|c| c.active()),
+            };
+            rpass.execute_bundles(
+                bundles
+                    .get_or_record(key, || self.encode_bundles(device, &ranges))
+                    .iter(),
+            );
+
+            rpass.set_bind_group(0, &self.bind_groups.cam, &[]);
+            rpass.set_bind_group(1, &self.bind_groups.lighting, &[]);
+            rpass.set_bind_group(2, &self.bind_groups.instance_data, &[]);
+            rpass.set_bind_group(3, &self.shadows.bind_group, &[]);
+            self.extension.set_bind_groups(&mut rpass);
+        } else if let Some(culling) = self.culling.as_ref().filter(|c| c.active()) {
+            // Instance counts are written by the culling pass.
+            rpass.set_vertex_buffer(0, self.vertex_buf.slice(..));
+            rpass.set_vertex_buffer(1, culling.culled_buf.slice(..));
+            rpass.set_index_buffer(self.index_buf.slice(..), wgpu::IndexFormat::Uint32);
+
+            let mut current = FaceCulling::Back;
+            for i in 0..num_meshes {
+                let face_culling = mesh_culling(&self.scene.meshes, i);
+                if let Some(pipeline) = pipelines.switch(&mut current, face_culling) {
+                    rpass.set_pipeline(pipeline);
+                }
+                rpass.draw_indexed_indirect(&culling.indirect_buf, (i * DRAW_ARGS_SIZE) as u64);
+            }
+        } else {
+            rpass.set_vertex_buffer(0, self.vertex_buf.slice(..));
+            rpass.set_vertex_buffer(1, self.instance_buf.slice(..));
+            rpass.set_index_buffer(self.index_buf.slice(..), wgpu::IndexFormat::Uint32);
+
+            let mut current = FaceCulling::Back;
+            for (i, (range, (instance_start_this_mesh, instance_count_this_mesh))) in
+                self.mesh_ranges.iter().zip(&self.mesh_mappings).enumerate()
+            {
+                let face_culling = mesh_culling(&self.scene.meshes, i);
+                if let Some(pipeline) = pipelines.switch(&mut current, face_culling) {
+                    rpass.set_pipeline(pipeline);
+                }
+                rpass.draw_indexed(
+                    range.index_start..range.index_start + range.index_count,
+                    range.vertex_start,
+                    *instance_start_this_mesh..instance_start_this_mesh + instance_count_this_mesh,
+                );
+            }
+        }
+
+        sub_range::draw(
+            &mut rpass,
+            pipelines,
+            &self.layer_draws[RenderLayer::World.index()],
+            &self.mesh_buffers(),
+        );
+
+        self.raw_instances.draw(
+            &mut rpass,
+            pipelines,
+            &self.bind_groups.instance_data,
+            &self.mesh_buffers(),
+        );
+
+        self.impostors.draw(
+            &mut rpass,
+            &self.pipelines,
+            self.main_targets(),
+            &self.instance_buf,
+        );
+
+        // With deferred shading, these are drawn after lighting; see `setup_overlay_pass`.
+        if self.deferred.is_none() {
+            self.ground.draw(&mut rpass, self.taa.is_some(), self.ground_bind_groups());
+            self.lines.draw(&mut rpass, self.taa.is_some());
+            self.sdf.draw(&mut rpass, self.taa.is_some());
+        }
+
+        self.bundles = bundles;
+        rpass
+    }
+
+    /// With deferred shading, the main pass only writes to the G-buffer, so we draw lines and SDF
+    /// elements over the lit result in this pass, using the main pass's depth.
+    fn setup_overlay_pass<'a>(
+        &self,
+        encoder: &'a mut CommandEncoder,
+        output_view: &TextureView,
+        viewport: (f32, f32, f32, f32),
+    ) -> RenderPass<'a> {
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Overlay render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        let (x, y, width, height) = viewport;
+        rpass.set_viewport(x, y, width, height, 0., 1.);
+
+        self.ground.draw(&mut rpass, false, self.ground_bind_groups());
+
+        rpass.set_bind_group(0, &self.bind_groups.cam, &[]);
+        self.lines.draw(&mut rpass, false);
+        self.sdf.draw(&mut rpass, false);
+
+        rpass
+    }
+
+    /// The GUI has its own render pass, drawn over the 3D one. This lets us time them separately.
+    fn setup_gui_pass<'a>(
+        &self,
+        encoder: &'a mut CommandEncoder,
+        output_view: &TextureView,
+    ) -> RenderPass<'a> {
+        encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("GUI render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: self.gpu_timer.as_ref().map(|t| t.gui_writes()),
+            occlusion_query_set: None,
+        })
+    }
+
+    /// Encode everything drawn in the 3D viewport: compute passes that run before rendering,
+    /// shadows, the main pass, and post-processing, to `ctx`'s output texture. Used for windowed
+    /// and headless rendering.
+    pub(crate) fn encode_scene(&mut self, ctx: &PassContext, encoder: &mut CommandEncoder) {
+        let PassContext {
+            device,
+            queue,
+            output_texture,
+            width,
+            height,
+            viewport,
+            dt: dt_secs,
+        } = *ctx;
+
+        self.advance_upload(device, encoder, false);
+
+        // Entity debug shapes are built with instances.
+        if self.scene.debug.shapes != self.entity_debug_shapes {
+            self.setup_entities(device, queue);
+        }
+
+        if self.extension.stale {
+            self.setup_extension(device, queue);
+        }
+        self.extension.write_uniforms(queue, self.scene.shader_extension.as_ref());
+
+        self.compute_time += dt_secs;
+
+        let scene = &mut self.scene;
+        if scene
+            .timeline
+            .update(dt_secs, &mut scene.camera, &mut scene.lighting.point_lights)
+        {
+            self.update_camera(queue);
+            self.update_lighting(device, queue);
+        }
+
+        let scene = &mut self.scene;
+        if light_path::update(
+            &mut scene.light_paths,
+            &mut scene.lighting.point_lights,
+            dt_secs,
+        ) {
+            self.update_lighting(device, queue);
+        }
+
+        // Area light proxies follow their entities, which may move at any time.
+        if !self.scene.area_lights.is_empty() || !self.area_lights.is_empty() {
+            let lights = area_light::lights(&mut self.scene);
+            if !area_light::same(&lights, &self.area_lights) {
+                self.area_lights = lights;
+                self.update_lighting(device, queue);
+            }
+        }
+
+        // Rotation pauses while the user moves the camera.
+        let inputs = &self.inputs_commanded;
+        let user_input = inputs.inputs_present() || inputs.free_look || inputs.pan;
+        if let Some(turntable) = &mut self.scene.turntable {
+            if turntable.update(&mut self.scene.camera, dt_secs, user_input) {
+                self.update_camera(queue);
+            }
+        }
+
+        let scene = &mut self.scene;
+        let changed = scene.transitions.update(dt_secs, &mut scene.entities);
+        if !changed.is_empty() {
+            self.update_entity_instances(device, queue, &changed);
+        }
+
+        // The clear color is read each frame; this only matters with `RedrawMode::OnChange`.
+        let scene = &mut self.scene;
+        scene
+            .transitions
+            .update_background(dt_secs, &mut scene.background_color);
+        if self.scene.background_color != self.background_color {
+            self.background_color = self.scene.background_color;
+            self.request_redraw(RedrawRegion::All);
+        }
+
+        let scalar_colors = (
+            self.scene.scalar_colormap,
+            self.scene.scalar_range,
+            self.scene.color.input_space,
+        );
+        if scalar_colors != self.scalar_colors {
+            self.scalar_colors = scalar_colors;
+            if self.scene.entities.iter().any(|e| e.scalar.is_some()) {
+                self.static_batch = None;
+                self.setup_entities(device, queue);
+            }
+        }
+
+        // Compute passes that produce data for rendering, eg instance transforms.
+        #[cfg(feature = "compute")]
+        self.encode_compute(ComputeStage::PreRender, ctx, encoder);
+
+        // The HUD is drawn over the output, so it's updated even if the scene isn't rendered.
+        if self.hud.stale {
+            self.hud.update_images(device, queue, &self.scene.hud);
+            self.hud.update(device, queue, &self.scene.hud);
+        }
+        self.hud.update_params(queue, width, height);
+
+        let region = self.take_redraw(device, width, height, viewport);
+        self.scene_rendered = region.is_some();
+
+        let Some(region) = region else {
+            // Nothing changed; show the previous frame.
+            if let Some(cache) = &self.scene_cache {
+                cache.encode_blit(encoder, output_texture);
+            }
+            return;
+        };
+
+        let (_, _, eff_width, eff_height) = viewport;
+
+        if let Some(taa) = &self.taa {
+            // Entities haven't changed since the last frame, so they're no longer moving. Rebuild
+            // the instances so their previous transforms match the current ones.
+            if !taa.instances_fresh && taa.instance_motion {
+                self.setup_entities(device, queue);
+            }
+        }
+
+        // TAA jitters the camera each frame, in addition to the camera's own jitter.
+        let cam_data = match &mut self.taa {
+            Some(taa) => {
+                taa.instances_fresh = false;
+                taa.camera_bytes(&self.scene.camera, eff_width, eff_height)
+            }
+            None => taa::camera_bytes_no_taa(&self.scene.camera, eff_width, eff_height),
+        };
+        queue.write_buffer(&self.camera_buf, CAMERA_SIZE as u64, &cam_data);
+
+        // The slice plane may be dragged, or changed by the application, at any time.
+        queue.write_buffer(
+            &self.camera_buf,
+            (CAMERA_SIZE + TAA_CAMERA_SIZE) as u64,
+            &clip_plane_bytes(self.scene.slice_plane.as_ref()),
+        );
+        queue.write_buffer(
+            &self.camera_buf,
+            (CAMERA_SIZE + TAA_CAMERA_SIZE + CLIP_PLANE_SIZE) as u64,
+            &camera::time_bytes(self.compute_time, dt_secs),
+        );
+
+        // Taken, so we can pass the mesh buffers while borrowing self.
+        if let Some(mut culling) = self.culling.take() {
+            if culling.active() {
+                culling.encode_cull(ctx, encoder, &self.scene.camera, &self.mesh_buffers());
+            }
+            self.culling = Some(culling);
+        }
+
+        self.clusters.encode(queue, encoder, &self.scene.camera);
+
+        let buffers = self.mesh_buffers();
+        let raw_buffers = MeshBuffers {
+            instance_buf: &self.raw_instances.buf,
+            mesh_mappings: &self.raw_instances.mesh_mappings,
+            ..buffers
+        };
+        self.shadows.encode(
+            queue,
+            encoder,
+            &self.scene.lighting.point_lights,
+            self.scene.camera.far,
+            &[
+                (buffers, &self.layer_draws[RenderLayer::World.index()]),
+                (raw_buffers, &[]),
+            ],
+        );
+
+        // Probes are lit using the shadow maps, so we capture them after rendering those.
+        if self.probes.stale {
+            // Probes are captured without TAA or deferred shading.
+            let key = PipelineKey::new(MainTargets::Color);
+            self.pipelines.prepare_meshes(device, key, &self.scene.meshes);
+
+            let mut bind_groups = vec![
+                &self.bind_groups.lighting_capture,
+                &self.bind_groups.instance_data,
+                &self.shadows.bind_group,
+            ];
+            bind_groups.extend(&self.extension.bind_groups);
+
+            let inputs = CaptureInputs {
+                pipelines: self.pipelines.meshes(PipelineKey::new(MainTargets::Color)),
+                meshes: &self.scene.meshes,
+                layout_cam: &self.bind_groups.layout_cam,
+                bind_groups: &bind_groups,
+                vertex_buf: &self.vertex_buf,
+                index_buf: &self.index_buf,
+                instance_buf: &self.instance_buf,
+                mesh_ranges: &self.mesh_ranges,
+                mesh_mappings: &self.mesh_mappings,
+                clear_color: self.clear_color(),
+                far: self.scene.camera.far,
+            };
+            self.probes
+                .capture(device, queue, encoder, &self.scene.env_probes, &inputs);
+        }
+
+        let mut lines = self.entity_debug_lines.clone();
+        lines
+            .vertices
+            .extend_from_slice(&self.scene.debug_draw.lines().vertices);
+        lines.light_gizmos(
+            &self.scene.lighting.point_lights,
+            &self.scene.debug,
+            self.shadows.max_lights,
+            self.scene.camera.far,
+        );
+        self.lines.update(device, &lines);
+
+        if !self.scene.labels.is_empty() {
+            let sdf = &mut self.sdf;
+            let labels = labels::update(&mut self.scene, (eff_width, eff_height), |text| {
+                sdf.text_size(text)
+            });
+
+            let mut elements = self.scene.sdf_elements.clone();
+            elements.extend(labels);
+            self.sdf.update(device, queue, &elements);
+            self.sdf.labels = true;
+        } else if self.sdf.stale || self.sdf.labels {
+            self.sdf.update(device, queue, &self.scene.sdf_elements);
+            self.sdf.labels = false;
+        }
+        self.sdf.update_params(
+            queue,
+            (eff_width, eff_height),
+            self.scene.camera.orientation.rotate_vec(RIGHT_VEC),
+            self.scene.camera.orientation.rotate_vec(UP_VEC),
+        );
+
+        if self.materials.stale {
+            self.update_materials(device, queue);
+        }
+
+        // With `RedrawMode::OnChange`, we render to the cache, and copy it to the output.
+        let Some(cache) = self.scene_cache.take() else {
+            self.encode_views(ctx, encoder);
+            return;
+        };
+
+        let cache_ctx = PassContext {
+            output_texture: &cache.view,
+            ..*ctx
+        };
+
+        match region {
+            RedrawRegion::Rect {
+                x,
+                y,
+                width: rect_width,
+                height: rect_height,
+            } if self.partial_redraw_supported() => {
+                if let Some(rect) = redraw::clip_rect((x, y, rect_width, rect_height), viewport) {
+                    cache.encode_clear(encoder, &self.depth_texture.view, rect, self.clear_color());
+                    self.encode_main_passes(&cache_ctx, encoder, Some(rect));
+                }
+            }
+            _ => self.encode_views(&cache_ctx, encoder),
+        }
+
+        cache.encode_blit(encoder, output_texture);
+        self.scene_cache = Some(cache);
+    }
+
+    /// Encode the main passes to `ctx`'s output texture, or once for each eye with anaglyph
+    /// stereo, followed by extra views, and the inset.
+    fn encode_views(&mut self, ctx: &PassContext, encoder: &mut CommandEncoder) {
+        match self.scene.anaglyph.clone() {
+            Some(anaglyph) => self.encode_anaglyph(ctx, encoder, &anaglyph),
+            None => self.encode_main_passes(ctx, encoder, None),
+        }
+
+        let mut views = self.scene.extra_views.clone();
+        views.extend(self.scene.inset.as_ref().and_then(|inset| inset.view(ctx.viewport)));
+
+        if !views.is_empty() {
+            self.encode_extra_views(ctx, encoder, &views);
+        }
+    }
+
+    /// Show `Scene::debug_view` over the 3D viewport, and render it for saving, if requested. Run
+    /// this after encoding the scene, before the HUD and GUI.
+    fn encode_debug_view(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        output_texture: &TextureView,
+        viewport: (f32, f32, f32, f32),
+    ) {
+        let save_path = self
+            .scene
+            .debug_view
+            .as_mut()
+            .and_then(|view| view.save_path.take());
+
+        let Some(view) = &self.scene.debug_view else {
+            return;
+        };
+        if !view.show && save_path.is_none() {
+            return;
+        }
+
+        let depth_view = self.depth_texture.depth_view();
+        let shadow_size = (self.shadows.resolution(), self.shadows.resolution());
+        let gbuffer = |i| self.deferred.as_ref().map(|d| DebugSource::color(d.gbuffer(i)));
+
+        let source = match view.target {
+            DebugTarget::Depth => Some(DebugSource {
+                view: &depth_view,
+                size: (self.depth_texture.texture.width(), self.depth_texture.texture.height()),
+                depth: true,
+            }),
+            DebugTarget::ShadowMap { light, face } => {
+                self.shadows.face_view(light, face).map(|view| DebugSource {
+                    view,
+                    size: shadow_size,
+                    depth: true,
+                })
+            }
+            DebugTarget::Velocity => self.taa.as_ref().map(|taa| DebugSource::color(&taa.velocity)),
+            DebugTarget::GBufferAlbedo => gbuffer(0),
+            DebugTarget::GBufferNormal => gbuffer(1),
+            DebugTarget::GBufferMaterial => gbuffer(2),
+        };
+
+        let Some(source) = source else {
+            if let Some(path) = save_path {
+                let target = view.target;
+                println!("Unable to save the debug view to {path:?}: {target:?} isn't available");
+            }
+            return;
+        };
+
+        if self.debug_view.as_ref().map(|r| r.format) != Some(self.color_format) {
+            self.debug_view = Some(DebugViewRenderer::new(device, self.color_format));
+        }
+        let renderer = self.debug_view.as_mut().unwrap();
+        let camera = &self.scene.camera;
+
+        if view.show {
+            renderer.encode_display(
+                device,
+                queue,
+                encoder,
+                output_texture,
+                viewport,
+                view,
+                &source,
+                camera,
+            );
+        }
+        if let Some(path) = save_path {
+            renderer.encode_save(device, queue, encoder, view, &source, camera, path);
+        }
+    }
+
+    /// Render the scene once for each eye, and composite the views to `ctx`'s output texture. See
+    /// `anaglyph.rs`.
+    fn encode_anaglyph(
+        &mut self,
+        ctx: &PassContext,
+        encoder: &mut CommandEncoder,
+        anaglyph: &Anaglyph,
+    ) {
+        let PassContext {
+            device,
+            queue,
+            output_texture,
+            width,
+            height,
+            viewport,
+            ..
+        } = *ctx;
+        let size = (width.max(1), height.max(1));
+        if self.anaglyph.as_ref().map(|a| (a.width, a.height)) != Some(size) {
+            self.anaglyph = Some(AnaglyphRenderer::new(device, self.color_format, width, height));
+        }
+        // Taken, so we can render to its views while borrowing `self` mutably.
+        let renderer = self.anaglyph.take().unwrap();
+
+        for (left, view) in [(true, &renderer.left), (false, &renderer.right)] {
+            // Passes that use the camera, eg deferred lighting and outlines, use the eye's.
+            let eye_camera = anaglyph.eye_camera(&self.scene.camera, left);
+            let camera = mem::replace(&mut self.scene.camera, eye_camera);
+            queue.write_buffer(&self.camera_buf, 0, &self.scene.camera.to_bytes());
+
+            let eye_ctx = PassContext {
+                output_texture: view,
+                ..*ctx
+            };
+            self.encode_main_passes(&eye_ctx, encoder, None);
+            self.scene.camera = camera;
+
+            // Buffer writes take effect at the next submission, so each eye is submitted
+            // separately.
+            let eye_encoder = mem::replace(
+                encoder,
+                device.create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("Render encoder"),
+                }),
+            );
+            queue.submit(Some(eye_encoder.finish()));
+        }
+
+        // The camera buffer is only written when the camera changes.
+        queue.write_buffer(&self.camera_buf, 0, &self.scene.camera.to_bytes());
+
+        renderer.encode(encoder, output_texture, viewport);
+        self.anaglyph = Some(renderer);
+    }
+
+    /// Render the scene from each view's camera, and composite it over its rectangle of
+    /// `ctx`'s output texture. See `views.rs`.
+    fn encode_extra_views(
+        &mut self,
+        ctx: &PassContext,
+        encoder: &mut CommandEncoder,
+        views: &[ExtraView],
+    ) {
+        let PassContext {
+            device,
+            queue,
+            output_texture,
+            width,
+            height,
+            viewport,
+            ..
+        } = *ctx;
+
+        let size = (width.max(1), height.max(1));
+        if self.views.as_ref().map(|r| (r.width, r.height)) != Some(size) {
+            self.views = Some(ViewRenderer::new(device, self.color_format, width, height));
+        }
+        // Taken, so we can render to its view while borrowing `self` mutably.
+        let renderer = self.views.take().unwrap();
+
+        // TAA history and the occlusion culling pyramid are from the main camera, and the GPU
+        // timer measures the main pass.
+        let taa = self.taa.take();
+        let culling = self.culling.take();
+        let gpu_timer = self.gpu_timer.take();
+
+        for view in views {
+            let Some(rect) = redraw::clip_rect(view.rect, viewport) else {
+                continue;
+            };
+
+            // Buffer writes take effect at the next submission, so submit the previous view's
+            // passes before writing this one's camera.
+            let prev_encoder = mem::replace(
+                encoder,
+                device.create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("Render encoder"),
+                }),
+            );
+            queue.submit(Some(prev_encoder.finish()));
+
+            let (x, y, rect_width, rect_height) = rect;
+            let view_viewport = (x as f32, y as f32, rect_width as f32, rect_height as f32);
+
+            let view_camera = view.camera_for(rect);
+            queue.write_buffer(&self.camera_buf, 0, &view_camera.to_bytes());
+            queue.write_buffer(
+                &self.camera_buf,
+                CAMERA_SIZE as u64,
+                &taa::camera_bytes_no_taa(&view_camera, view_viewport.2, view_viewport.3),
+            );
+            self.sdf.update_params(
+                queue,
+                (view_viewport.2, view_viewport.3),
+                view_camera.orientation.rotate_vec(RIGHT_VEC),
+                view_camera.orientation.rotate_vec(UP_VEC),
+            );
+            self.clusters.encode(queue, encoder, &view_camera);
+
+            let camera = mem::replace(&mut self.scene.camera, view_camera);
+            let background_color = self.scene.background_color;
+            let background_gradient = self.scene.background_gradient.clone();
+            if let Some(color) = view.background_color {
+                self.scene.background_color = color;
+                self.scene.background_gradient = None;
+            }
+
+            let mut hidden_layers = Vec::new();
+            for layer in [RenderLayer::Background, RenderLayer::Overlay] {
+                if !view.draws_layer(layer) {
+                    let draws = mem::take(&mut self.layer_draws[layer.index()]);
+                    hidden_layers.push((layer, draws));
+                }
+            }
+
+            let view_ctx = PassContext {
+                output_texture: &renderer.view,
+                viewport: view_viewport,
+                ..*ctx
+            };
+            self.encode_main_passes(&view_ctx, encoder, None);
+
+            for (layer, draws) in hidden_layers {
+                self.layer_draws[layer.index()] = draws;
+            }
+            self.scene.background_gradient = background_gradient;
+            self.scene.background_color = background_color;
+            self.scene.camera = camera;
+
+            renderer.encode(encoder, output_texture, rect);
+        }
+
+        self.gpu_timer = gpu_timer;
+        self.culling = culling;
+        self.taa = taa;
+
+        let view_encoder = mem::replace(
+            encoder,
+            device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Render encoder"),
+            }),
+        );
+        queue.submit(Some(view_encoder.finish()));
+
+        // The camera buffer is only written when the camera changes. The rest of the uniform, and
+        // the SDF and cluster parameters, are written each frame the scene is rendered.
+        queue.write_buffer(&self.camera_buf, 0, &self.scene.camera.to_bytes());
+
+        self.views = Some(renderer);
+    }
+
+    /// Encode the main pass, and the passes that follow it, eg deferred lighting, outlines, and
+    /// the TAA resolve. If `region` is set, only that part of the main pass is drawn, over the
+    /// previous frame; see `partial_redraw_supported`.
+    fn encode_main_passes(
+        &mut self,
+        ctx: &PassContext,
+        encoder: &mut CommandEncoder,
+        region: Option<(u32, u32, u32, u32)>,
+    ) {
+        let PassContext {
+            device,
+            queue,
+            output_texture,
+            width,
+            height,
+            viewport,
+            ..
+        } = *ctx;
+        let (_, _, eff_width, eff_height) = viewport;
+
+        self.prepare_pipelines(device);
+
+        if let Some(gradient) = &self.scene.background_gradient {
+            // Deferred lighting writes to the output directly, and discards background pixels.
+            let target = match (&self.taa, &self.deferred) {
+                (Some(taa), None) => &taa.color.view,
+                _ => output_texture,
+            };
+
+            self.background.encode(
+                &PassContext {
+                    output_texture: target,
+                    ..*ctx
+                },
+                encoder,
+                gradient,
+                &self.scene.color,
+                !self.color_format.is_srgb(),
+            );
+        }
+
+        let background_load = match self.scene.background_gradient {
+            Some(_) => wgpu::LoadOp::Load,
+            None => wgpu::LoadOp::Clear(self.clear_color()),
+        };
+        self.encode_layer(
+            encoder,
+            RenderLayer::Background,
+            output_texture,
+            viewport,
+            background_load,
+        );
+
+        self.ground.update(
+            queue,
+            self.scene.ground.as_ref(),
+            &self.scene.color,
+            !self.color_format.is_srgb(),
+        );
 
-    /// Currently, sets up entities (And the associated instance buf), but doesn't change
-    /// meshes, lights, or the camera. The vertex and index buffers aren't changed; only the instances.
-    pub(crate) fn setup_entities(&mut self, device: &Device) {
-        let mut instances = Vec::new();
-
-        let mut mesh_mappings = Vec::new();
-
-        let mut vertex_start_this_mesh = 0;
-        let mut instance_start_this_mesh = 0;
-
-        for (i, mesh) in self.scene.meshes.iter().enumerate() {
-            let mut instance_count_this_mesh = 0;
-            for entity in self.scene.entities.iter().filter(|e| e.mesh == i) {
-                instances.push(Instance {
-                    // todo: entity into method?
-                    position: entity.position,
-                    orientation: entity.orientation,
-                    scale: entity.scale,
-                    color: Vec3::new(entity.color.0, entity.color.1, entity.color.2),
-                    opacity: entity.opacity,
-                    shinyness: entity.shinyness,
-                });
-                instance_count_this_mesh += 1;
-            }
+        let rpass = self.setup_render_pass(device, encoder, output_texture, viewport, region);
+        drop(rpass); // Ends the render pass.
 
-            mesh_mappings.push((
-                vertex_start_this_mesh,
-                instance_start_this_mesh,
-                instance_count_this_mesh,
-            ));
+        if let Some(deferred) = &self.deferred {
+            deferred.update_params(queue, &self.scene.camera, viewport);
+            deferred.encode_lighting(
+                encoder,
+                output_texture,
+                [
+                    &self.bind_groups.cam,
+                    &self.bind_groups.lighting,
+                    &self.shadows.bind_group,
+                ],
+                viewport,
+                self.color_load_op(),
+            );
 
-            vertex_start_this_mesh += mesh.vertices.len() as i32;
-            instance_start_this_mesh += instance_count_this_mesh;
+            let rpass = self.setup_overlay_pass(encoder, output_texture, viewport);
+            drop(rpass);
         }
 
-        // todo: Helper fn that takes a `ToBytes` trait we haven't made?
-        let mut instance_data = Vec::new();
-        for instance in &instances {
-            for byte in instance.to_bytes() {
-                instance_data.push(byte);
-            }
+        if let Some(culling) = self.culling.as_mut().filter(|c| c.active()) {
+            culling.encode_pyramid(
+                device,
+                encoder,
+                &self.depth_texture.depth_view(),
+                width,
+                height,
+                viewport,
+            );
         }
 
-        // We can't update using a queue due to buffer size mismatches.
-        let instance_buf = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Instance buffer"),
-            contents: &instance_data,
-            usage: BufferUsages::VERTEX,
-        });
-
-        self.instance_buf = instance_buf;
-        self.mesh_mappings = mesh_mappings;
-    }
+        if self.scene.toon.outlines {
+            // With TAA, outlines are resolved along with the scene.
+            let target = match &self.taa {
+                Some(taa) => &taa.color.view,
+                None => output_texture,
+            };
+
+            let c = self.scene.toon.outline_color;
+            let encode_srgb = !self.color_format.is_srgb();
+            let [r, g, b] = self.scene.color.unlit_color([c[0], c[1], c[2]], encode_srgb);
+
+            self.toon.encode(
+                &PassContext {
+                    output_texture: target,
+                    ..*ctx
+                },
+                encoder,
+                &self.depth_texture.depth_view(),
+                &self.scene.toon,
+                [r, g, b, c[3]],
+                &self.scene.camera,
+            );
+        }
 
-    pub(crate) fn update_camera(&mut self, queue: &Queue) {
-        queue.write_buffer(&self.camera_buf, 0, &self.scene.camera.to_bytes());
-    }
+        // After outlines and the culling pyramid, which use the world's depth.
+        self.encode_layer(
+            encoder,
+            RenderLayer::Overlay,
+            output_texture,
+            viewport,
+            wgpu::LoadOp::Load,
+        );
 
-    pub(crate) fn update_lighting(&mut self, queue: &Queue) {
-        queue.write_buffer(&self.lighting_buf, 0, &self.scene.lighting.to_bytes());
+        if let Some(taa) = &mut self.taa {
+            let uv_scale = (eff_width / width as f32, eff_height / height as f32);
+            taa.encode_resolve(device, queue, encoder, output_texture, uv_scale);
+        }
     }
 
-    fn setup_render_pass<'a>(
+    /// Render a frame without a window or GUI, to `output_texture`, eg for tests. The 3D viewport
+    /// fills the output, and the HUD is drawn over it.
+    pub(crate) fn render_headless(
         &mut self,
-        ui_size: f32,
-        encoder: &'a mut CommandEncoder,
-        output_view: &TextureView,
+        device: &Device,
+        queue: &Queue,
+        output_texture: &TextureView,
         width: u32,
         height: u32,
-        ui_settings: &UiSettings,
-    ) -> RenderPass<'a> {
-        // Adjust the viewport size for 3D, based on how much size the UI is taking up.
-        let (x, y, mut eff_width, mut eff_height) = match ui_settings.layout {
-            UiLayout::Left => (ui_size, 0., width as f32 - ui_size, height as f32),
-            UiLayout::Right => (0., 0., width as f32 - ui_size, height as f32),
-            UiLayout::Top => (0., ui_size, width as f32, height as f32 - ui_size),
-            UiLayout::Bottom => (0., 0., width as f32, height as f32 - ui_size),
-        };
+        dt_secs: f32,
+    ) {
+        self.scene.update_measurements();
+        self.scene.draw_slice_plane();
+        self.scene.draw_upload_placeholders();
 
-        // println!("X: {x}, Y: {y} w: {eff_width} h: {eff_height} UI size: {ui_size}");
-
-        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
-            label: Some("Render pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: output_view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color {
-                        r: self.scene.background_color.0 as f64,
-                        g: self.scene.background_color.1 as f64,
-                        b: self.scene.background_color.2 as f64,
-                        a: 1.0,
-                    }),
-                    store: StoreOp::Store,
-                },
-            })],
-            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
-                view: &self.depth_texture.view,
-                depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
-                    store: StoreOp::Store,
-                }),
-                stencil_ops: None,
-            }),
-            timestamp_writes: None,
-            occlusion_query_set: None,
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Headless render encoder"),
         });
 
-        // Adjust the portion of the 3D rendering to take up the space not taken up by the UI.
-        rpass.set_viewport(x, y, eff_width, eff_height, 0., 1.);
+        let viewport = (0., 0., width as f32, height as f32);
+        let ctx = PassContext {
+            device,
+            queue,
+            output_texture,
+            width,
+            height,
+            viewport,
+            dt: dt_secs,
+        };
+        self.encode_scene(&ctx, &mut encoder);
+        self.encode_debug_view(device, queue, &mut encoder, output_texture, viewport);
 
-        rpass.set_pipeline(&self.pipeline);
+        let mut rpass = self.setup_gui_pass(&mut encoder, output_texture);
+        self.hud.draw(&mut rpass);
+        drop(rpass);
 
-        rpass.set_bind_group(0, &self.bind_groups.cam, &[]);
-        rpass.set_bind_group(1, &self.bind_groups.lighting, &[]);
+        #[cfg(feature = "compute")]
+        self.encode_compute(ComputeStage::PostRender, &ctx, &mut encoder);
 
-        rpass.set_vertex_buffer(0, self.vertex_buf.slice(..));
-        rpass.set_vertex_buffer(1, self.instance_buf.slice(..));
-        rpass.set_index_buffer(self.index_buf.slice(..), wgpu::IndexFormat::Uint32);
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.resolve(&mut encoder);
+        }
 
-        let mut start_ind = 0;
-        for (i, mesh) in self.scene.meshes.iter().enumerate() {
-            let (vertex_start_this_mesh, instance_start_this_mesh, instance_count_this_mesh) =
-                self.mesh_mappings[i];
+        queue.submit(Some(encoder.finish()));
 
-            rpass.draw_indexed(
-                start_ind..start_ind + mesh.indices.len() as u32,
-                vertex_start_this_mesh,
-                instance_start_this_mesh..instance_start_this_mesh + instance_count_this_mesh,
-            );
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.map();
+            timer.read(device, queue, &mut self.scene.frame_stats);
+        }
 
-            start_ind += mesh.indices.len() as u32;
+        if let Some(renderer) = &mut self.debug_view {
+            renderer.finish_save(device, queue);
         }
 
-        rpass
+        self.scene.debug_draw.clear();
+        self.scene.measure.completed.clear();
+        if let Some(plane) = &mut self.scene.slice_plane {
+            plane.moved = false;
+        }
     }
 
-    /// The entry point to 3D and GUI rendering.
+    /// The entry point to 3D and GUI rendering. The GUI parameters are only present with the `gui`
+    /// feature.
     /// Note:  `resize_required`, the return, is to handle changes in GUI size.
-    pub(crate) fn render<T>(
+    pub(crate) fn render<#[cfg(feature = "gui")] T>(
         &mut self,
-        gui: &mut GuiState,
+        #[cfg(feature = "gui")] gui: &mut GuiState,
+        #[cfg(feature = "gui")] window: &Window,
+        #[cfg(feature = "gui")] gui_handler: impl FnMut(
+            &mut T,
+            &Context,
+            &mut Scene,
+        ) -> EngineUpdates,
+        #[cfg(feature = "gui")] user_state: &mut T,
         surface_texture: SurfaceTexture,
         output_texture: &TextureView,
         device: &Device,
@@ -368,9 +2941,6 @@ impl GraphicsState {
         height: u32,
         ui_settings: &mut UiSettings,
         input_settings: &InputSettings,
-        gui_handler: impl FnMut(&mut T, &Context, &mut Scene) -> EngineUpdates,
-        user_state: &mut T,
-        layout: UiLayout,
     ) -> bool {
         static mut i: usize = 0; // todo temp
         unsafe {
@@ -382,30 +2952,43 @@ impl GraphicsState {
         // Note that camera settings adjusted by the application code are handled in
         // `update_camera`.
 
-        match input_settings.initial_controls {
-            ControlScheme::FreeCamera => {
-                if self.inputs_commanded.inputs_present() {
-                    let dt_secs = dt.as_secs() as f32 + dt.subsec_micros() as f32 / 1_000_000.;
-
-                    let cam_changed = input::adjust_camera(
-                        &mut self.scene.camera,
-                        &self.inputs_commanded,
-                        &input_settings,
-                        dt_secs,
-                    );
-
-                    if cam_changed {
-                        queue.write_buffer(&self.camera_buf, 0, &self.scene.camera.to_bytes());
-                    }
-
-                    // Reset the mouse inputs; keyboard inputs are reset by their release event.
-                    self.inputs_commanded.mouse_delta_x = 0.;
-                    self.inputs_commanded.mouse_delta_y = 0.;
-                }
+        if self.inputs_commanded.inputs_present() {
+            let dt_secs = dt.as_secs() as f32 + dt.subsec_micros() as f32 / 1_000_000.;
+            let scale = self.scene.scale_factor();
+
+            let cam_changed = match input_settings.initial_controls {
+                ControlScheme::FreeCamera => input::adjust_camera(
+                    &mut self.scene.camera,
+                    &self.inputs_commanded,
+                    &input_settings,
+                    scale,
+                    dt_secs,
+                ),
+                ControlScheme::Arc => input::adjust_camera_arc(
+                    &mut self.scene.camera,
+                    &mut self.scene.arc_center,
+                    &self.inputs_commanded,
+                    &input_settings,
+                    dt_secs,
+                ),
+                _ => false,
+            };
+
+            if cam_changed {
+                self.update_camera(queue);
             }
-            _ => (),
+
+            // Reset the mouse inputs; keyboard inputs are reset by their release event.
+            self.inputs_commanded.mouse_delta_x = 0.;
+            self.inputs_commanded.mouse_delta_y = 0.;
+            self.inputs_commanded.scroll = 0.;
         }
 
+        // Measurements are drawn using debug lines and text, which are painted with the GUI.
+        self.scene.update_measurements();
+        self.scene.draw_slice_plane();
+        self.scene.draw_upload_placeholders();
+
         // We create a CommandEncoder to create the actual commands to send to the
         // gpu. Most modern graphics frameworks expect commands to be stored in a command buffer
         // before being sent to the gpu. The encoder builds a command buffer that we can then
@@ -414,9 +2997,12 @@ impl GraphicsState {
             label: Some("Render encoder"),
         });
 
+        #[cfg(feature = "gui")]
         let mut updates_gui = Default::default();
 
+        #[cfg(feature = "gui")]
         let (gui_full_output, tris, screen_descriptor, resize_required) = gui.render_gui_pre_rpass(
+            window,
             self,
             user_state,
             device,
@@ -426,31 +3012,60 @@ impl GraphicsState {
             width,
             height,
             &mut updates_gui,
-            layout,
+            ui_settings,
         );
 
-        // todo: This rpass code does not contribute to the performance problem.
+        #[cfg(feature = "gui")]
+        let gui_size = gui.size;
+        // Without the GUI, the 3D viewport uses the whole window.
+        #[cfg(not(feature = "gui"))]
+        let (gui_size, resize_required) = (0., false);
 
-        let mut rpass = self.setup_render_pass(
-            gui.size,
-            &mut encoder,
+        let dt_secs = dt.as_secs() as f32 + dt.subsec_micros() as f32 / 1_000_000.;
+        let viewport = viewport_3d(gui_size, width, height, ui_settings);
+        let ctx = PassContext {
+            device,
+            queue,
             output_texture,
             width,
             height,
-            ui_settings,
-        );
+            viewport,
+            dt: dt_secs,
+        };
+        self.encode_scene(&ctx, &mut encoder);
+        self.encode_debug_view(device, queue, &mut encoder, output_texture, viewport);
 
-        let mut rpass = rpass.forget_lifetime();
+        let mut rpass = self
+            .setup_gui_pass(&mut encoder, output_texture)
+            .forget_lifetime();
 
+        // The HUD is drawn under the GUI, after TAA resolves the 3D pass. Letterbox bars are drawn
+        // first, so the HUD may cover them.
+        let area = area_3d(gui_size, width, height, ui_settings.layout);
+        self.letterbox
+            .draw(&mut rpass, area, viewport, width, height);
+        self.hud.draw(&mut rpass);
+
+        #[cfg(feature = "gui")]
         gui.egui_renderer
             .render(&mut rpass, &tris, &screen_descriptor);
-        drop(rpass); // Ends the render pass.
+        drop(rpass);
 
-        for x in &gui_full_output.textures_delta.free {
-            gui.egui_renderer.free_texture(x)
+        #[cfg(feature = "compute")]
+        self.encode_compute(ComputeStage::PostRender, &ctx, &mut encoder);
+
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.resolve(&mut encoder);
         }
 
-        process_engine_updates(&updates_gui, self, device, queue);
+        #[cfg(feature = "gui")]
+        {
+            for x in &gui_full_output.textures_delta.free {
+                gui.egui_renderer.free_texture(x)
+            }
+
+            process_engine_updates(&updates_gui, self, device, queue);
+        }
 
         unsafe {
             // if i % 100 == 0 {
@@ -464,98 +3079,303 @@ impl GraphicsState {
         // todo: This queue line is likely the problem! Is your queue just getting bigger??
         queue.submit(Some(encoder.finish()));
 
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.map();
+            timer.read(device, queue, &mut self.scene.frame_stats);
+        }
+
+        if let Some(renderer) = &mut self.debug_view {
+            renderer.finish_save(device, queue);
+        }
+
         unsafe {
             // if i % 100 == 0 {
             // println!("C: {:?}", start_time.elapsed().as_micros());
             // }
         }
 
+        #[cfg(feature = "remote")]
+        if self.capture_requested {
+            self.captured = Some(headless::read_texture(
+                device,
+                queue,
+                &surface_texture.texture,
+            ));
+        }
+
         surface_texture.present();
 
+        self.scene.debug_draw.clear();
+        self.scene.measure.completed.clear();
+        if let Some(plane) = &mut self.scene.slice_plane {
+            plane.moved = false;
+        }
+
         resize_required
     }
 }
 
-/// Create render pipelines.
-fn create_render_pipeline(
+/// Find the portion of the window available for 3D rendering, based on how much size the UI is
+/// taking up. Returns (x, y, width, height), in pixels.
+fn area_3d(ui_size: f32, width: u32, height: u32, layout: UiLayout) -> (f32, f32, f32, f32) {
+    match layout {
+        UiLayout::Left => (ui_size, 0., width as f32 - ui_size, height as f32),
+        UiLayout::Right => (0., 0., width as f32 - ui_size, height as f32),
+        UiLayout::Top => (0., ui_size, width as f32, height as f32 - ui_size),
+        UiLayout::Bottom => (0., 0., width as f32, height as f32 - ui_size),
+    }
+}
+
+/// Find the portion of the window used for 3D rendering: the space the UI leaves, fit to
+/// `UiSettings::viewport_aspect` if set. Returns (x, y, width, height), in pixels.
+pub(crate) fn viewport_3d(
+    ui_size: f32,
+    width: u32,
+    height: u32,
+    ui_settings: &UiSettings,
+) -> (f32, f32, f32, f32) {
+    let area = area_3d(ui_size, width, height, ui_settings.layout);
+
+    match ui_settings.viewport_aspect {
+        Some(aspect) => letterbox::fit_aspect(area, aspect),
+        None => area,
+    }
+}
+
+/// The color targets of the main pass, for these settings. TAA takes precedence over deferred
+/// shading.
+fn settings_targets(settings: &GraphicsSettings) -> MainTargets {
+    if settings.taa {
+        MainTargets::Taa
+    } else if settings.deferred {
+        MainTargets::GBuffer
+    } else {
+        MainTargets::Color
+    }
+}
+
+/// Create occlusion culling state, if enabled and supported. Indirect draws with a non-zero first
+/// instance require `INDIRECT_FIRST_INSTANCE`. Culled instances are compacted, so they don't line
+/// up with the TAA previous model matrices.
+fn create_culling(
     device: &Device,
-    layout: &wgpu::PipelineLayout,
-    shader: wgpu::ShaderModule,
-    config: &SurfaceConfiguration,
-) -> RenderPipeline {
-    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("Render pipeline"),
-        layout: Some(layout),
-        vertex: VertexState {
-            module: &shader,
-            entry_point: Some("vs_main"),
-            compilation_options: Default::default(),
-            buffers: &[Vertex::desc(), Instance::desc()],
-        },
-        // fragment: Some(FragmentState {
-        //     module: &shader,
-        //     entry_point: "fs_main",
-        //     compilation_options: Default::default(),
-        //     targets: &[Some(config.format.into())],
-        // }),
-        fragment: Some(FragmentState {
-            module: &shader,
-            entry_point: Some("fs_main"),
-            compilation_options: Default::default(),
-            // This configures with alpha blending. (?)
-            targets: &[Some(wgpu::ColorTargetState {
-                format: config.format, // Ensure this is a format with alpha (e.g., `wgpu::TextureFormat::Rgba8Unorm`)
-                blend: Some(wgpu::BlendState {
-                    color: wgpu::BlendComponent {
-                        src_factor: wgpu::BlendFactor::SrcAlpha,
-                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                        operation: wgpu::BlendOperation::Add,
-                    },
-                    alpha: wgpu::BlendComponent {
-                        src_factor: wgpu::BlendFactor::One,
-                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                        operation: wgpu::BlendOperation::Add,
-                    },
-                }),
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
-        }),
-        primitive: wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::TriangleList,
-            strip_index_format: None,
-            front_face: wgpu::FrontFace::Ccw,
-            cull_mode: Some(wgpu::Face::Back),
-            unclipped_depth: false,
-            polygon_mode: wgpu::PolygonMode::Fill,
-            conservative: false,
-        },
-
-        depth_stencil: Some(wgpu::DepthStencilState {
-            format: DEPTH_FORMAT,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
-            stencil: wgpu::StencilState::default(),
-            bias: wgpu::DepthBiasState::default(),
-        }),
-        multisample: wgpu::MultisampleState::default(),
-        // If the pipeline will be used with a multiview render pass, this
-        // indicates how many array layers the attachments will have.
-        multiview: None,
-        cache: None,
-    })
+    surface_cfg: &SurfaceConfiguration,
+    settings: &GraphicsSettings,
+) -> Option<CullState> {
+    let supported = device
+        .features()
+        .contains(wgpu::Features::INDIRECT_FIRST_INSTANCE);
+
+    if settings.occlusion_culling && !settings.taa && supported {
+        Some(CullState::new(device, surface_cfg.width, surface_cfg.height))
+    } else {
+        None
+    }
+}
+
+/// Create the main shader, with the extension's WGSL appended, and a cache for its pipelines. The
+/// pipeline layout is the engine's bind groups, followed by the extension's.
+fn create_pipeline_cache(
+    device: &Device,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    engine_layouts: [&BindGroupLayout; 4],
+    extension: &ExtensionState,
+) -> PipelineCache {
+    // The extension's directives were checked when it was built.
+    let source = engine_wgsl(&format!("{}\n{}", include_str!("shader.wgsl"), extension.wgsl));
+
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Graphics shader"),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+
+    let mut layouts = engine_layouts.to_vec();
+    layouts.extend(&extension.layouts);
+
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Render pipeline layout"),
+        bind_group_layouts: &layouts,
+        push_constant_ranges: &[],
+    });
+
+    PipelineCache::new(shader, layout, color_format, depth_format)
+}
+
+/// The culling mode of a mesh. Meshes and their GPU ranges may briefly differ in length after
+/// meshes change, so this defaults if the mesh is missing.
+pub(crate) fn mesh_culling(meshes: &[Mesh], i: usize) -> FaceCulling {
+    meshes.get(i).map(|m| m.culling).unwrap_or_default()
+}
+
+/// Create a larger copy of a buffer, with room to grow further. The copy is submitted
+/// immediately, so writes queued after this apply to the new buffer.
+fn grow_buffer(
+    device: &Device,
+    queue: &Queue,
+    buf: &Buffer,
+    min_size: u64,
+    label: &str,
+    usage: BufferUsages,
+) -> Buffer {
+    let result = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: min_size.max(buf.size() + buf.size() / 2),
+        usage,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("Buffer grow encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buf, 0, &result, 0, buf.size());
+    queue.submit(Some(encoder.finish()));
+
+    result
 }
 
 pub(crate) struct BindGroupData {
     pub layout_cam: BindGroupLayout,
     pub cam: BindGroup,
     pub layout_lighting: BindGroupLayout,
+    /// Lighting, and environment probes.
     pub lighting: BindGroup,
+    /// Used when capturing environment probes; binds a placeholder in place of the probe maps.
+    pub lighting_capture: BindGroup,
     /// We use this for GUI.
     pub layout_texture: BindGroupLayout,
     // pub texture: BindGroup,
+    pub layout_instance_data: BindGroupLayout,
+    /// Previous model matrices, the palette, and material textures. Recreated when any of these
+    /// are.
+    pub instance_data: BindGroup,
+}
+
+/// The buffers and textures the main bind groups are created from.
+struct BindGroupResources<'a> {
+    cam_buf: &'a Buffer,
+    lighting_buf: &'a Buffer,
+    clusters: &'a ClusterState,
+    prev_models_buf: &'a Buffer,
+    palette_buf: &'a Buffer,
+    materials: &'a MaterialTextures,
+    color_buf: &'a Buffer,
+    probes: &'a ProbeState,
+}
+
+/// The previous model matrix and palette buffers, and material textures, are recreated when
+/// entities, the palette, or materials change, so we create their bind group separately from the
+/// others.
+pub(crate) fn create_instance_data_bindgroup(
+    device: &Device,
+    layout: &BindGroupLayout,
+    prev_models_buf: &Buffer,
+    palette_buf: &Buffer,
+    materials: &MaterialTextures,
+) -> BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: prev_models_buf.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: palette_buf.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&materials.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&materials.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: wgpu::BindingResource::TextureView(&materials.normal_view),
+            },
+        ],
+        label: Some("Instance data bind group"),
+    })
+}
+
+/// Create the lighting bind group, and the one used when capturing probes. These are recreated
+/// when probes or clusters are, eg after changing graphics settings.
+fn create_lighting_bindgroups(
+    device: &Device,
+    layout: &BindGroupLayout,
+    lighting_buf: &Buffer,
+    clusters: &ClusterState,
+    color_buf: &Buffer,
+    probes: &ProbeState,
+) -> (BindGroup, BindGroup) {
+    let create_lighting = |probe_view, cluster_params: &Buffer, label| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: lighting_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: probes.probes_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: probes.mats_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(probe_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&probes.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: color_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: cluster_params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: clusters.lists_buf.as_entire_binding(),
+                },
+            ],
+            label: Some(label),
+        })
+    };
+
+    let lighting = create_lighting(&probes.view, &clusters.params_buf, "Lighting bind group");
+    // We can't sample the probe maps while rendering to them. Captures use their own cameras,
+    // which don't match the light clusters.
+    let lighting_capture = create_lighting(
+        &probes.placeholder_view,
+        &clusters.params_disabled_buf,
+        "Lighting capture bind group",
+    );
+
+    (lighting, lighting_capture)
 }
 
-fn create_bindgroups(device: &Device, cam_buf: &Buffer, lighting_buf: &Buffer) -> BindGroupData {
+fn create_bindgroups(device: &Device, resources: &BindGroupResources) -> BindGroupData {
+    let BindGroupResources {
+        cam_buf,
+        lighting_buf,
+        clusters,
+        prev_models_buf,
+        palette_buf,
+        materials,
+        color_buf,
+        probes,
+    } = *resources;
+
     // We only need vertex, not fragment info in the camera uniform.
     let layout_cam = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
         entries: &[wgpu::BindGroupLayoutEntry {
@@ -583,27 +3403,98 @@ fn create_bindgroups(device: &Device, cam_buf: &Buffer, lighting_buf: &Buffer) -
     });
 
     let layout_lighting = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        entries: &[wgpu::BindGroupLayoutEntry {
-            binding: 0,
-            visibility: ShaderStages::FRAGMENT,
-            ty: BindingType::Buffer {
-                ty: BufferBindingType::Storage { read_only: true }, // todo read-only?
-                has_dynamic_offset: false,
-                min_binding_size: None,
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true }, // todo read-only?
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
             },
-            count: None,
-        }],
+            // Environment probes
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            // Color settings
+            wgpu::BindGroupLayoutEntry {
+                binding: 5,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Light clusters
+            wgpu::BindGroupLayoutEntry {
+                binding: 6,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 7,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
         label: Some("Lighting bind group layout"),
     });
 
-    let lighting = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        layout: &layout_lighting,
-        entries: &[wgpu::BindGroupEntry {
-            binding: 0,
-            resource: lighting_buf.as_entire_binding(),
-        }],
-        label: Some("Lighting bind group"),
-    });
+    let (lighting, lighting_capture) = create_lighting_bindgroups(
+        device,
+        &layout_lighting,
+        lighting_buf,
+        clusters,
+        color_buf,
+        probes,
+    );
 
     // todo: Don't create these (diffuse tex view, sampler every time. Pass as args.
     // We don't need to configure the texture view much, so let's
@@ -664,12 +3555,79 @@ fn create_bindgroups(device: &Device, cam_buf: &Buffer, lighting_buf: &Buffer) -
     //         label: Some("Texture bind group"),
     //     });
 
+    let layout_instance_data = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Palette
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Material textures
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            // Material normal maps. Bindings 4 through 8 are used by the deferred lighting pass's
+            // G-buffer, which replaces this group there.
+            wgpu::BindGroupLayoutEntry {
+                binding: 9,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+        ],
+        label: Some("Instance data bind group layout"),
+    });
+
+    let instance_data = create_instance_data_bindgroup(
+        device,
+        &layout_instance_data,
+        prev_models_buf,
+        palette_buf,
+        materials,
+    );
+
     BindGroupData {
         layout_cam,
         cam,
         layout_lighting,
         lighting,
+        lighting_capture,
         layout_texture,
         // texture
+        layout_instance_data,
+        instance_data,
     }
 }
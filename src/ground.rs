@@ -0,0 +1,258 @@
+//! An optional ground plane that receives shadows, so objects appear grounded without modeling
+//! an environment. Set `Scene::ground` to enable it. The plane is transparent, or a flat color,
+//! and darkened where shadow-casting lights are blocked; it isn't otherwise lit, and doesn't cast
+//! shadows or occlude anything.
+//!
+//! Shadows come from the shadow maps, so only lights with `casts_shadow` darken it.
+
+use lin_alg::f32::Vec3;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferUsages, Device,
+    FragmentState, PipelineLayout, Queue, RenderPass, RenderPipeline, ShaderModule, ShaderStages,
+    SurfaceConfiguration, TextureFormat, VertexState,
+};
+
+use crate::{
+    shader_interface::engine_wgsl,
+    taa::VELOCITY_FORMAT,
+    types::{ColorSettings, F32_SIZE, VEC4_SIZE},
+};
+
+/// Center and half size (vec4), color and opacity (vec4), shadow opacity (padded to vec4).
+const GROUND_PARAMS_SIZE: usize = 3 * VEC4_SIZE;
+
+#[derive(Clone, Debug, PartialEq)]
+/// A horizontal square that receives shadows. See the `ground` module.
+pub struct GroundPlane {
+    /// The center of the plane, in world space. Usually at, or just below, the bottom of the
+    /// scene's geometry.
+    pub center: Vec3,
+    /// Half the width of the plane. Its edges fade out, so this should extend past shadows.
+    pub half_size: f32,
+    /// The plane's color away from shadows. Interpreted according to
+    /// `ColorSettings::input_space`, as with entity colors.
+    pub color: (f32, f32, f32),
+    /// The opacity of `color`. 0 shows only shadows.
+    pub opacity: f32,
+    /// How dark full shadows are, from 0 to 1.
+    pub shadow_opacity: f32,
+}
+
+impl Default for GroundPlane {
+    fn default() -> Self {
+        Self {
+            center: Vec3::new_zero(),
+            half_size: 10.,
+            color: (0.5, 0.5, 0.5),
+            opacity: 0.,
+            shadow_opacity: 0.5,
+        }
+    }
+}
+
+impl GroundPlane {
+    fn to_bytes(&self, color: &ColorSettings, encode_srgb: bool) -> [u8; GROUND_PARAMS_SIZE] {
+        let mut result = [0; GROUND_PARAMS_SIZE];
+
+        let [r, g, b] = color.unlit_color([self.color.0, self.color.1, self.color.2], encode_srgb);
+
+        let values = [
+            self.center.x,
+            self.center.y,
+            self.center.z,
+            self.half_size,
+            r,
+            g,
+            b,
+            self.opacity.clamp(0., 1.),
+            self.shadow_opacity.clamp(0., 1.),
+        ];
+        for (i, v) in values.iter().enumerate() {
+            result[i * F32_SIZE..(i + 1) * F32_SIZE].clone_from_slice(&v.to_ne_bytes());
+        }
+
+        result
+    }
+}
+
+/// The ground plane pipelines, and its parameters.
+pub(crate) struct GroundRenderer {
+    pipeline: RenderPipeline,
+    /// Used when TAA is enabled; this has an additional velocity target.
+    pipeline_taa: RenderPipeline,
+    params_buf: Buffer,
+    bind_group: BindGroup,
+    /// If the scene has a ground plane this frame.
+    enabled: bool,
+}
+
+impl GroundRenderer {
+    /// `layouts` are the camera, lighting, and shadow bind group layouts. Recreate this when the
+    /// shadow layout changes.
+    pub fn new(
+        device: &Device,
+        surface_cfg: &SurfaceConfiguration,
+        depth_format: TextureFormat,
+        layouts: [&BindGroupLayout; 3],
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Ground shader"),
+            source: wgpu::ShaderSource::Wgsl(engine_wgsl(include_str!("ground.wgsl")).into()),
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX_FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("Ground bind group layout"),
+        });
+
+        let [layout_cam, layout_lighting, layout_shadows] = layouts;
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Ground pipeline layout"),
+            bind_group_layouts: &[layout_cam, layout_lighting, layout_shadows, &layout],
+            push_constant_ranges: &[],
+        });
+
+        let params_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Ground params buffer"),
+            contents: &[0; GROUND_PARAMS_SIZE],
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params_buf.as_entire_binding(),
+            }],
+            label: Some("Ground bind group"),
+        });
+
+        Self {
+            pipeline: create_pipeline(
+                device,
+                &pipeline_layout,
+                &shader,
+                surface_cfg,
+                depth_format,
+                false,
+            ),
+            pipeline_taa: create_pipeline(
+                device,
+                &pipeline_layout,
+                &shader,
+                surface_cfg,
+                depth_format,
+                true,
+            ),
+            params_buf,
+            bind_group,
+            enabled: false,
+        }
+    }
+
+    /// Upload this frame's ground plane, if any.
+    pub fn update(
+        &mut self,
+        queue: &Queue,
+        ground: Option<&GroundPlane>,
+        color: &ColorSettings,
+        encode_srgb: bool,
+    ) {
+        self.enabled = ground.is_some();
+
+        if let Some(ground) = ground {
+            queue.write_buffer(&self.params_buf, 0, &ground.to_bytes(color, encode_srgb));
+        }
+    }
+
+    /// Draw the plane in the main render pass, or the overlay pass. `bind_groups` are the
+    /// camera, lighting, and shadow bind groups; this sets groups 0 to 3, replacing the
+    /// main pass's.
+    pub fn draw(&self, rpass: &mut RenderPass, taa: bool, bind_groups: [&BindGroup; 3]) {
+        if !self.enabled {
+            return;
+        }
+
+        if taa {
+            rpass.set_pipeline(&self.pipeline_taa);
+        } else {
+            rpass.set_pipeline(&self.pipeline);
+        }
+
+        let [cam, lighting, shadows] = bind_groups;
+
+        rpass.set_bind_group(0, cam, &[]);
+        rpass.set_bind_group(1, lighting, &[]);
+        rpass.set_bind_group(2, shadows, &[]);
+        rpass.set_bind_group(3, &self.bind_group, &[]);
+        rpass.draw(0..6, 0..1);
+    }
+}
+
+fn create_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    config: &SurfaceConfiguration,
+    depth_format: TextureFormat,
+    taa: bool,
+) -> RenderPipeline {
+    let color_target = Some(wgpu::ColorTargetState {
+        format: config.format,
+        blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+        write_mask: wgpu::ColorWrites::ALL,
+    });
+
+    let velocity_target = Some(wgpu::ColorTargetState {
+        format: VELOCITY_FORMAT,
+        blend: None,
+        write_mask: wgpu::ColorWrites::ALL,
+    });
+
+    let (fs_entry_point, targets) = if taa {
+        ("fs_main_taa", vec![color_target, velocity_target])
+    } else {
+        ("fs_main", vec![color_target])
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Ground pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: Some(fs_entry_point),
+            compilation_options: Default::default(),
+            targets: &targets,
+        }),
+        // Visible from below too, eg when orbiting under the scene.
+        primitive: wgpu::PrimitiveState::default(),
+        // The plane is hidden by geometry in front of it, but doesn't occlude anything itself.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
@@ -0,0 +1,130 @@
+// The ground plane: a horizontal square that's transparent, or a flat color, and darkened where
+// shadowed. Its color is premultiplied by alpha. The camera, lighting, and shadow bindings match
+// the main shader's.
+
+#include "camera"
+#include "lighting"
+#include "shadow_sample"
+
+struct GroundParams {
+    // The plane's center, and half its width in w.
+    center: vec4<f32>,
+    // Output color, and opacity away from shadows.
+    color: vec4<f32>,
+    // The opacity of full shadow.
+    shadow_opacity: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var<storage> lighting: Lighting;
+
+@group(2) @binding(0)
+var<uniform> shadow_params: ShadowParams;
+@group(2) @binding(1)
+var<storage> shadow_mats: array<mat4x4<f32>>;
+@group(2) @binding(2)
+var shadow_maps: texture_depth_2d_array;
+@group(2) @binding(3)
+var shadow_sampler: sampler_comparison;
+
+@group(3) @binding(0)
+var<uniform> params: GroundParams;
+
+struct VertexOut {
+    @builtin(position) clip_posit: vec4<f32>,
+    // From -1 to 1 across the plane.
+    @location(0) uv: vec2<f32>,
+    @location(1) world_posit: vec3<f32>,
+    @location(2) curr_clip: vec4<f32>,
+    @location(3) prev_clip: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> VertexOut {
+    // Two triangles.
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1., -1.),
+        vec2<f32>(1., -1.),
+        vec2<f32>(1., 1.),
+        vec2<f32>(-1., -1.),
+        vec2<f32>(1., 1.),
+        vec2<f32>(-1., 1.),
+    );
+    var uv = corners[i];
+
+    var world_posit = params.center.xyz + vec3<f32>(uv.x, 0., uv.y) * params.center.w;
+    var posit = vec4<f32>(world_posit, 1.);
+    var curr_clip = camera.proj_view * posit;
+
+    var result: VertexOut;
+    // Jitter to match meshes when using TAA, so depth testing is consistent.
+    result.clip_posit = curr_clip + vec4<f32>(camera.jitter.xy * curr_clip.w, 0., 0.);
+    result.uv = uv;
+    result.world_posit = world_posit;
+    result.curr_clip = curr_clip;
+    result.prev_clip = camera.prev_proj_view * posit;
+
+    return result;
+}
+
+/// Premultiplied color: black shadow over the plane's color.
+fn ground_color(vertex: VertexOut) -> vec4<f32> {
+    // The fraction of diffuse light from shadow-casting lights that's blocked here.
+    var unshadowed = 0.;
+    var lit = 0.;
+
+    for (var i = 0; i < lighting.lights_len; i++) {
+        var light = lighting.point_lights[i];
+        if (light.shadow_i < 0) {
+            continue;
+        }
+
+        var light_to_vert_diff = vertex.world_posit - light.position.xyz;
+        var falloff_diff = light_to_vert_diff / lighting.falloff_scale;
+
+        var intensity = max(-normalize(light_to_vert_diff).y, 0.) * light.diffuse_intensity
+            / dot(falloff_diff, falloff_diff);
+
+        unshadowed += intensity;
+        lit += intensity * shadow_factor(light.shadow_i, light_to_vert_diff, vertex.world_posit);
+    }
+
+    var shadow = 0.;
+    if (unshadowed > 0.) {
+        shadow = (1. - lit / unshadowed) * params.shadow_opacity;
+    }
+
+    // Fade out near the edges, so the plane's extent isn't visible.
+    var edge = max(abs(vertex.uv.x), abs(vertex.uv.y));
+    var fade = 1. - smoothstep(0.8, 1., edge);
+
+    var base_alpha = params.color.a * fade;
+    shadow *= fade;
+
+    return vec4<f32>(
+        params.color.rgb * base_alpha * (1. - shadow),
+        shadow + base_alpha * (1. - shadow),
+    );
+}
+
+@fragment
+fn fs_main(vertex: VertexOut) -> @location(0) vec4<f32> {
+    return ground_color(vertex);
+}
+
+struct FragOutTaa {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec2<f32>,
+}
+
+@fragment
+fn fs_main_taa(vertex: VertexOut) -> FragOutTaa {
+    var result: FragOutTaa;
+    result.color = ground_color(vertex);
+    result.velocity = vertex.curr_clip.xy / vertex.curr_clip.w - vertex.prev_clip.xy / vertex.prev_clip.w;
+
+    return result;
+}
@@ -1,39 +1,43 @@
 //! GUI code for EGUI, to run on the WGPU painter.
 //! See [this unofficial example](https://github.com/kaphula/winit-egui-wgpu-template/tree/master/src)
 //! https://github.com/rust-windowing/winit/issues/3626
+//!
+//! This requires the `gui` feature. Without it, the whole window is used for the 3D viewport.
 
-use std::sync::Arc;
+use std::time::Duration;
 
-use egui::{ClippedPrimitive, Context, FullOutput};
+use egui::{ClippedPrimitive, Context, FullOutput, ViewportId};
 use egui_wgpu::{Renderer, ScreenDescriptor};
 use egui_winit;
 use wgpu::{self, CommandEncoder, Device, Queue, TextureFormat};
 use winit::window::Window;
 
 use crate::{
-    graphics::GraphicsState,
-    system::DEPTH_FORMAT,
+    graphics::{self, GraphicsState},
     types::{EngineUpdates, Scene},
-    UiLayout,
+    UiLayout, UiSettings,
 };
 
 /// State related to the GUI.
 pub(crate) struct GuiState {
     pub egui_state: egui_winit::State,
     pub egui_renderer: Renderer,
-    /// Used to disable inputs while the mouse is in the GUI section.
-    pub mouse_in_gui: bool,
     /// We store this, so we know if we need to perform a resize if it changes.
     pub size: f32,
 }
 
 impl GuiState {
-    pub fn new(window: Arc<Window>, device: &Device, texture_format: TextureFormat) -> Self {
+    pub fn new(
+        window: &Window,
+        device: &Device,
+        texture_format: TextureFormat,
+        depth_format: TextureFormat,
+    ) -> Self {
         let egui_context = Context::default();
         let egui_state = egui_winit::State::new(
             egui_context,
             egui::viewport::ViewportId::ROOT,
-            &window,
+            window,
             Some(window.scale_factor() as f32),
             None,
             None,
@@ -42,7 +46,7 @@ impl GuiState {
         let egui_renderer = Renderer::new(
             device,
             texture_format,
-            Some(DEPTH_FORMAT),
+            Some(depth_format),
             1,     // todo
             false, // todo: Dithering?
         );
@@ -50,7 +54,6 @@ impl GuiState {
         Self {
             egui_state,
             egui_renderer,
-            mouse_in_gui: false,
             size: 0.,
         }
     }
@@ -58,6 +61,7 @@ impl GuiState {
     /// This function contains code specific to rendering the GUI prior to the render pass.
     pub(crate) fn render_gui_pre_rpass<T>(
         &mut self,
+        window: &Window,
         graphics: &mut GraphicsState,
         user_state: &mut T,
         device: &Device,
@@ -67,11 +71,11 @@ impl GuiState {
         width: u32,
         height: u32,
         updates_gui: &mut EngineUpdates,
-        layout: UiLayout,
+        ui_settings: &UiSettings,
     ) -> (FullOutput, Vec<ClippedPrimitive>, ScreenDescriptor, bool) {
         let screen_descriptor = ScreenDescriptor {
             size_in_pixels: [width, height],
-            pixels_per_point: graphics.window.scale_factor() as f32,
+            pixels_per_point: window.scale_factor() as f32,
         };
 
         self.egui_state
@@ -80,11 +84,11 @@ impl GuiState {
 
         let mut resize_required = false;
 
-        let raw_input = self.egui_state.take_egui_input(&graphics.window);
+        let raw_input = self.egui_state.take_egui_input(window);
         let full_output = self.egui_state.egui_ctx().run(raw_input, |ui| {
             *updates_gui = gui_handler(user_state, self.egui_state.egui_ctx(), &mut graphics.scene);
 
-            let mut new_size = match layout {
+            let mut new_size = match ui_settings.layout {
                 UiLayout::Left | UiLayout::Right => ui.used_size().x,
                 _ => ui.used_size().y,
             };
@@ -98,10 +102,23 @@ impl GuiState {
                 resize_required = true;
                 self.size = new_size;
             }
+
+            // We paint debug text after measuring the GUI, so it doesn't affect the GUI size.
+            graphics.scene.debug_draw.paint_text(
+                ui,
+                &graphics.scene.camera,
+                graphics::viewport_3d(self.size, width, height, ui_settings),
+                screen_descriptor.pixels_per_point,
+            );
         });
 
         self.egui_state
-            .handle_platform_output(&graphics.window, full_output.platform_output.clone()); // todo: Is this clone OK?
+            .handle_platform_output(window, full_output.platform_output.clone()); // todo: Is this clone OK?
+
+        graphics.gui_repaint_delay = full_output
+            .viewport_output
+            .get(&ViewportId::ROOT)
+            .map_or(Duration::MAX, |v| v.repaint_delay);
 
         let tris = self.egui_state.egui_ctx().tessellate(
             full_output.shapes.clone(), // todo: Is the clone OK?
@@ -0,0 +1,235 @@
+//! Rendering without a window, to an image, eg for automated tests of rendering output, or
+//! generating images from a scene. This uses the same render path as the windowed engine,
+//! without the GUI; the 3D viewport fills the image. See the `snapshot` module for comparing
+//! output against reference images.
+
+use std::sync::mpsc;
+
+use image::RgbaImage;
+use wgpu::{
+    Backends, BufferUsages, CommandEncoderDescriptor, Device, Extent3d, Instance,
+    InstanceDescriptor, MapMode, Queue, SurfaceConfiguration, TextureFormat, TextureUsages,
+    TextureView, COPY_BYTES_PER_ROW_ALIGNMENT,
+};
+
+use crate::{
+    graphics::GraphicsState,
+    system::{process_engine_updates, setup_async},
+    types::{EngineUpdates, GraphicsSettings, Scene},
+};
+
+/// Output is read back in this format, so it matches `RgbaImage`.
+const HEADLESS_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+/// Renders a scene to images, without a window. Create one with a scene, modify it with
+/// `scene_mut` and `update` as from a handler, and call `render` for each frame.
+pub struct HeadlessRenderer {
+    device: Device,
+    queue: Queue,
+    graphics: GraphicsState,
+    target: wgpu::Texture,
+    target_view: TextureView,
+    /// Describes the render target; used when applying graphics settings.
+    surface_cfg: SurfaceConfiguration,
+    width: u32,
+    height: u32,
+}
+
+impl HeadlessRenderer {
+    /// Set up a renderer for images of `width` and `height` pixels. Returns an error if no
+    /// suitable GPU adapter is available, eg on a CI machine without one; tests may skip in that
+    /// case. Also returns an error if the scene doesn't fit in the device's buffer limits; see
+    /// `Scene::buffer_errors`.
+    pub fn new(
+        mut scene: Scene,
+        graphics_settings: &GraphicsSettings,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, String> {
+        if width == 0 || height == 0 {
+            return Err("Headless render size must be nonzero".to_owned());
+        }
+
+        // We use the same backends as windowed rendering.
+        let instance = Instance::new(InstanceDescriptor {
+            backends: Backends::VULKAN,
+            ..Default::default()
+        });
+
+        let (_adapter, device, queue) =
+            pollster::block_on(setup_async(&instance, None, graphics_settings))?;
+
+        // There's no surface; this describes the render target.
+        let surface_cfg = SurfaceConfiguration {
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            format: HEADLESS_FORMAT,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: Vec::new(),
+        };
+
+        scene.camera.aspect = width as f32 / height as f32;
+        let graphics = GraphicsState::new(&device, &queue, &surface_cfg, scene, graphics_settings);
+        graphics.check_buffer_errors()?;
+
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Headless render target"),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HEADLESS_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let target_view = target.create_view(&Default::default());
+
+        Ok(Self {
+            device,
+            queue,
+            graphics,
+            target,
+            target_view,
+            surface_cfg,
+            width,
+            height,
+        })
+    }
+
+    pub fn scene(&self) -> &Scene {
+        &self.graphics.scene
+    }
+
+    /// Modify the scene. As with handlers, use `update` afterwards to apply changes that require
+    /// `EngineUpdates`.
+    pub fn scene_mut(&mut self) -> &mut Scene {
+        &mut self.graphics.scene
+    }
+
+    /// Apply changes made to the scene, as returned from a handler.
+    pub fn update(&mut self, updates: &EngineUpdates) {
+        process_engine_updates(updates, &mut self.graphics, &self.device, &self.queue);
+
+        // There's no surface to present to, so `present_mode` has no effect.
+        if let Some(settings) = self.graphics.pending_settings.take() {
+            self.graphics
+                .apply_settings(&self.device, &self.queue, &self.surface_cfg, settings);
+        }
+    }
+
+    /// Render a frame, advancing time by `dt` seconds, eg for compute passes and the timeline,
+    /// and read it back. This blocks until the GPU is done. Returns an error if the scene no
+    /// longer fits in the device's buffer limits; see `Scene::buffer_errors`.
+    pub fn render(&mut self, dt: f32) -> Result<RgbaImage, String> {
+        // As in a window, `load_events` covers loads finished since the previous frame.
+        self.graphics.scene.load_events.clear();
+        let updates = self.graphics.loader.poll(&mut self.graphics.scene);
+        self.update(&updates);
+
+        self.graphics.render_headless(
+            &self.device,
+            &self.queue,
+            &self.target_view,
+            self.width,
+            self.height,
+            dt,
+        );
+        self.graphics.check_buffer_errors()?;
+
+        self.read_target()
+    }
+
+    fn read_target(&self) -> Result<RgbaImage, String> {
+        read_texture(&self.device, &self.queue, &self.target)
+    }
+}
+
+/// Copy a texture to the CPU, eg a render target, or the window's surface texture. It must have
+/// `COPY_SRC` usage, and an 8-bit RGBA or BGRA format. This blocks until the GPU is done.
+pub(crate) fn read_texture(
+    device: &Device,
+    queue: &Queue,
+    texture: &wgpu::Texture,
+) -> Result<RgbaImage, String> {
+    let (width, height) = (texture.width(), texture.height());
+
+    let bgra = match texture.format() {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+        format => return Err(format!("Unable to read textures of format {format:?}")),
+    };
+    if !texture.usage().contains(TextureUsages::COPY_SRC) {
+        return Err("The texture doesn't support copying".to_owned());
+    }
+
+    // Rows of buffer copies must be aligned.
+    let row_len = width * 4;
+    let row_len_padded =
+        row_len.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT) * COPY_BYTES_PER_ROW_ALIGNMENT;
+
+    let buf = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback buffer"),
+        size: (row_len_padded * height) as u64,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("Readback encoder"),
+    });
+
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buf,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(row_len_padded),
+                rows_per_image: Some(height),
+            },
+        },
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+
+    queue.submit(Some(encoder.finish()));
+
+    let slice = buf.slice(..);
+    let (tx, rx) = mpsc::channel();
+    slice.map_async(MapMode::Read, move |result| {
+        let _ = tx.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+
+    rx.recv()
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Unable to read the rendered image: {e}"))?;
+
+    let mut pixels = Vec::with_capacity((row_len * height) as usize);
+    {
+        let data = slice.get_mapped_range();
+        for row in data.chunks_exact(row_len_padded as usize) {
+            pixels.extend_from_slice(&row[..row_len as usize]);
+        }
+    }
+    buf.unmap();
+
+    if bgra {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+
+    RgbaImage::from_raw(width, height, pixels)
+        .ok_or_else(|| "Rendered image has the wrong size".to_owned())
+}
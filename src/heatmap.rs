@@ -0,0 +1,230 @@
+//! Heatmaps: 2D arrays of values, eg from simulations or measurements, shown as a colored
+//! surface. Values are converted to colors with a colormap, and uploaded as a material;
+//! `Heatmap::mesh` creates a grid surface with a vertex per value, whose texture coordinates
+//! sample that value's texel.
+//!
+//! Add the material to `Scene::materials`, and use it with an entity of the mesh. When values
+//! change, eg each frame, call `Scene::set_heatmap`; this only rewrites the material's texture
+//! layer. Material layers share a size, so other, larger materials cause the heatmap to be
+//! stretched to theirs, with linear filtering.
+
+use core::f32::consts::TAU;
+
+use lin_alg::f32::Vec3;
+
+use crate::{
+    material::{Material, MaterialImage},
+    meshes::NormalMode,
+    types::{EngineUpdates, Mesh, Scene, Vertex},
+};
+
+/// sRGB colors, evenly spaced from low to high values.
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [71, 44, 122],
+    [59, 81, 139],
+    [44, 113, 142],
+    [33, 144, 141],
+    [39, 173, 129],
+    [92, 200, 99],
+    [170, 220, 50],
+    [253, 231, 37],
+];
+
+const MAGMA: [[u8; 3]; 9] = [
+    [0, 0, 4],
+    [28, 16, 68],
+    [79, 18, 123],
+    [129, 37, 129],
+    [181, 54, 122],
+    [229, 80, 100],
+    [251, 135, 97],
+    [254, 194, 135],
+    [252, 253, 191],
+];
+
+const COOLWARM: [[u8; 3]; 9] = [
+    [59, 76, 192],
+    [98, 130, 234],
+    [141, 176, 254],
+    [184, 208, 249],
+    [221, 220, 220],
+    [245, 196, 173],
+    [244, 154, 123],
+    [222, 96, 77],
+    [180, 4, 38],
+];
+
+const GRAYSCALE: [[u8; 3]; 2] = [[0, 0, 0], [255, 255, 255]];
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+/// How values are converted to colors.
+pub enum Colormap {
+    /// Perceptually uniform, from dark purple to yellow.
+    #[default]
+    Viridis,
+    /// Perceptually uniform, from black to pale yellow.
+    Magma,
+    /// Diverging, from blue through gray to red, eg for values centered on 0.
+    Coolwarm,
+    Grayscale,
+}
+
+impl Colormap {
+    /// The sRGB color of `t`, from 0 to 1; values outside are clamped.
+    pub fn color(self, t: f32) -> [u8; 3] {
+        let stops: &[[u8; 3]] = match self {
+            Self::Viridis => &VIRIDIS,
+            Self::Magma => &MAGMA,
+            Self::Coolwarm => &COOLWARM,
+            Self::Grayscale => &GRAYSCALE,
+        };
+
+        // NaN maps to the low end.
+        let t = if t.is_nan() { 0. } else { t.clamp(0., 1.) };
+
+        let pos = t * (stops.len() - 1) as f32;
+        let i = (pos as usize).min(stops.len() - 2);
+        let amount = pos - i as f32;
+
+        let (a, b) = (stops[i], stops[i + 1]);
+        [0, 1, 2].map(|c| (a[c] as f32 + (b[c] as f32 - a[c] as f32) * amount).round() as u8)
+    }
+}
+
+#[derive(Clone, Debug)]
+/// A grid of values, shown as colors. See the `heatmap` module.
+pub struct Heatmap {
+    /// The number of columns, along X.
+    pub width: usize,
+    /// The number of rows, along Z.
+    pub height: usize,
+    /// Row by row, starting with the row at -Z, each from -X to +X. Length is `width * height`.
+    pub values: Vec<f32>,
+    pub colormap: Colormap,
+    /// The values mapped to the colormap's low and high ends; values outside are clamped. If
+    /// `None`, the data's minimum and maximum are used.
+    pub range: Option<(f32, f32)>,
+}
+
+impl Heatmap {
+    pub fn new(width: usize, height: usize, values: Vec<f32>, colormap: Colormap) -> Self {
+        Self {
+            width,
+            height,
+            values,
+            colormap,
+            range: None,
+        }
+    }
+
+    /// The range values are mapped over; see `range`.
+    pub fn value_range(&self) -> (f32, f32) {
+        if let Some(range) = self.range {
+            return range;
+        }
+
+        let finite = self.values.iter().filter(|v| v.is_finite());
+        let min = finite.clone().fold(f32::INFINITY, |a, &b| a.min(b));
+        let max = finite.fold(f32::NEG_INFINITY, |a, &b| a.max(b));
+
+        if min > max {
+            (0., 1.)
+        } else {
+            (min, max)
+        }
+    }
+
+    /// Values, scaled from 0 to 1 over `value_range`. Missing values are 0.
+    fn normalized(&self) -> Vec<f32> {
+        let (min, max) = self.value_range();
+        let span = if max > min { max - min } else { 1. };
+
+        (0..self.width * self.height)
+            .map(|i| {
+                let v = self.values.get(i).copied().unwrap_or(min);
+                ((v - min) / span).clamp(0., 1.)
+            })
+            .collect()
+    }
+
+    /// A texture of the values' colors, with a texel per value.
+    pub fn material(&self) -> Material {
+        let mut data = Vec::with_capacity(self.width * self.height * 4);
+        for t in self.normalized() {
+            let [r, g, b] = self.colormap.color(t);
+            data.extend_from_slice(&[r, g, b, 255]);
+        }
+
+        Material::new(MaterialImage::Rgba8 {
+            width: self.width as u32,
+            height: self.height as u32,
+            data,
+        })
+    }
+
+    /// A grid surface with a vertex per value, `spacing` apart, centered on the origin. Each
+    /// vertex is raised by its value, scaled from 0 to `height_scale` over `value_range`; use 0
+    /// for a flat surface. Heights are fixed when the mesh is created; use `Scene::replace_mesh`
+    /// if they should follow changing values.
+    pub fn mesh(&self, spacing: f32, height_scale: f32) -> Mesh {
+        let (w, h) = (self.width.max(1), self.height.max(1));
+        let heights = self.normalized();
+
+        let mut vertices = Vec::with_capacity(w * h);
+        for row in 0..h {
+            for col in 0..w {
+                let height = heights.get(row * w + col).copied().unwrap_or(0.) * height_scale;
+                let position = [
+                    (col as f32 - (w - 1) as f32 / 2.) * spacing,
+                    height,
+                    (row as f32 - (h - 1) as f32 / 2.) * spacing,
+                ];
+
+                let mut vertex = Vertex::new(position, Vec3::new(0., 1., 0.));
+                // At texel centers, so each vertex's color is exactly its value's.
+                vertex.tex_coords = [(col as f32 + 0.5) / w as f32, (row as f32 + 0.5) / h as f32];
+                vertices.push(vertex);
+            }
+        }
+
+        let mut indices = Vec::with_capacity((w - 1) * (h - 1) * 6);
+        for row in 0..h - 1 {
+            for col in 0..w - 1 {
+                let i = row * w + col;
+                indices.extend_from_slice(&[i, i + 1, i + w, i + 1, i + w + 1, i + w]);
+            }
+        }
+
+        let mut result = Mesh {
+            vertices,
+            indices,
+            material: None,
+            impostor: None,
+            culling: Default::default(),
+        };
+
+        if height_scale != 0. {
+            result.generate_normals(NormalMode::Smooth {
+                angle_threshold: TAU / 2.,
+            });
+        }
+
+        result
+    }
+}
+
+impl Scene {
+    /// Replace a material with a heatmap's colors, eg after its values change. Only this
+    /// material's texture layer is rewritten, so this is suitable for use every frame, as long as
+    /// the heatmap's dimensions don't change. Return the result from your handler, or combine it
+    /// with other updates. Panics if `material` is out of bounds.
+    pub fn set_heatmap(&mut self, material: usize, heatmap: &Heatmap) -> EngineUpdates {
+        self.materials[material] = heatmap.material();
+
+        EngineUpdates {
+            material_images: vec![material],
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,531 @@
+//! A lightweight screen-space HUD, for images, text, and rectangles positioned in normalized
+//! screen coordinates, eg crosshairs, compass bars, and watermarks. This is drawn over the 3D
+//! scene, after anti-aliasing, and under the GUI, but doesn't use egui; it's a single pipeline,
+//! with a draw call per run of elements using the same image.
+//!
+//! Text uses the same SDF glyph atlas as `SdfElement`.
+
+use std::ops::Range;
+
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferUsages, Device,
+    FragmentState, Queue, RenderPass, RenderPipeline, Sampler, ShaderStages, SurfaceConfiguration,
+    TextureFormat, VertexState,
+};
+
+use crate::{
+    sdf::GlyphAtlas,
+    texture::{self, Texture},
+    types::{F32_SIZE, VEC4_SIZE},
+};
+
+/// position (vec2), offset (vec2), uv (vec2), color (vec4), kind (f32)
+const HUD_VERTEX_SIZE: usize = 6 * F32_SIZE + VEC4_SIZE + F32_SIZE;
+/// Aspect ratio, padded to a vec4.
+const HUD_PARAMS_SIZE: usize = VEC4_SIZE;
+
+const CORNERS: [(f32, f32); 6] = [(0., 0.), (1., 0.), (1., 1.), (0., 0.), (1., 1.), (0., 1.)];
+
+#[derive(Clone, Debug)]
+pub enum HudContent {
+    /// Index into `Hud::images`.
+    Image(usize),
+    /// Use `\n` to start a new line.
+    Text(String),
+    /// A solid rectangle, in the element's color.
+    Rect,
+}
+
+impl HudContent {
+    /// Matches the `kind` field in the shader.
+    fn index(&self) -> f32 {
+        match self {
+            Self::Image(_) => 0.,
+            Self::Text(_) => 1.,
+            Self::Rect => 2.,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HudElement {
+    pub content: HudContent,
+    /// In normalized screen coordinates: 0 to 1, from the window's top left.
+    pub position: (f32, f32),
+    /// Width and height, as fractions of the window's height, so squares stay square regardless
+    /// of the window's shape. Only the height is used for text.
+    pub size: (f32, f32),
+    /// The point on the element placed at `position`, from (0, 0) at its top left, to (1, 1) at
+    /// its bottom right. Eg (1, 1) for a watermark in the bottom right corner.
+    pub pivot: (f32, f32),
+    /// Multiplies image colors.
+    pub color: (f32, f32, f32),
+    pub opacity: f32,
+    /// The region of the image drawn, as (min u, min v, max u, max v). Values outside 0 to 1
+    /// repeat the image, eg to scroll a compass bar.
+    pub uv_rect: (f32, f32, f32, f32),
+}
+
+impl HudElement {
+    pub fn new(content: HudContent, position: (f32, f32), size: (f32, f32)) -> Self {
+        Self {
+            content,
+            position,
+            size,
+            pivot: (0.5, 0.5),
+            color: (1., 1., 1.),
+            opacity: 1.,
+            uv_rect: (0., 0., 1., 1.),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// An image used by HUD elements.
+pub struct HudImage {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA, with 8 bits per channel, row by row from the top left.
+    pub data: Vec<u8>,
+}
+
+impl HudImage {
+    /// Load from an image file's contents, eg PNG.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, image::ImageError> {
+        let (width, height, data) = texture::decode_rgba8(bytes)?;
+
+        Ok(Self {
+            width,
+            height,
+            data,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// Set `EngineUpdates::hud` after changing elements, and `EngineUpdates::hud_images` after
+/// changing images.
+pub struct Hud {
+    /// Drawn in order, so later elements are on top.
+    pub elements: Vec<HudElement>,
+    pub images: Vec<HudImage>,
+}
+
+pub(crate) struct HudRenderer {
+    pipeline: RenderPipeline,
+    layout_image: BindGroupLayout,
+    sampler: Sampler,
+    /// One for each of `Hud::images`.
+    image_bind_groups: Vec<BindGroup>,
+    /// Used in place of an image by elements that aren't images; the shader doesn't sample it.
+    placeholder_bind_group: BindGroup,
+    atlas: GlyphAtlas,
+    params_buf: Buffer,
+    /// The atlas and params.
+    bind_group: BindGroup,
+    buf: Buffer,
+    /// Vertex ranges, and the image each uses.
+    draws: Vec<(Option<usize>, Range<u32>)>,
+    /// If set, images and vertices are rebuilt before the next frame.
+    pub stale: bool,
+}
+
+impl HudRenderer {
+    pub fn new(
+        device: &Device,
+        surface_cfg: &SurfaceConfiguration,
+        depth_format: TextureFormat,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("HUD shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("hud.wgsl").into()),
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+
+        let layout_image = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(0), sampler_entry(1)],
+            label: Some("HUD image bind group layout"),
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0),
+                sampler_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("HUD bind group layout"),
+        });
+
+        // Repeating allows scrolling images using UVs.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("HUD sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let atlas = GlyphAtlas::new(device);
+
+        let params_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("HUD params buffer"),
+            contents: &[0; HUD_PARAMS_SIZE],
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&atlas.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: params_buf.as_entire_binding(),
+                },
+            ],
+            label: Some("HUD bind group"),
+        });
+
+        let placeholder_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout_image,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&atlas.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("HUD placeholder bind group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("HUD pipeline layout"),
+            bind_group_layouts: &[&layout_image, &layout],
+            push_constant_ranges: &[],
+        });
+
+        let buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("HUD vertex buffer"),
+            contents: &[],
+            usage: BufferUsages::VERTEX,
+        });
+
+        Self {
+            pipeline: create_pipeline(device, &pipeline_layout, &shader, surface_cfg, depth_format),
+            layout_image,
+            sampler,
+            image_bind_groups: Vec::new(),
+            placeholder_bind_group,
+            atlas,
+            params_buf,
+            bind_group,
+            buf,
+            draws: Vec::new(),
+            stale: true,
+        }
+    }
+
+    /// Upload the HUD's images.
+    pub fn update_images(&mut self, device: &Device, queue: &Queue, hud: &Hud) {
+        self.image_bind_groups = hud
+            .images
+            .iter()
+            .map(|img| image_bind_group(device, queue, &self.layout_image, &self.sampler, img))
+            .collect();
+    }
+
+    /// Rebuild vertices for the HUD's elements.
+    pub fn update(&mut self, device: &Device, queue: &Queue, hud: &Hud) {
+        self.stale = false;
+        self.draws.clear();
+
+        let mut data = Vec::new();
+
+        for element in &hud.elements {
+            let start = (data.len() / HUD_VERTEX_SIZE) as u32;
+
+            let image = match element.content {
+                HudContent::Image(i) if i < hud.images.len() => Some(i),
+                HudContent::Image(_) => continue,
+                _ => None,
+            };
+
+            match &element.content {
+                HudContent::Text(text) => {
+                    let h = element.size.1;
+                    let (quads, (width, height)) = self.atlas.layout(text);
+
+                    // Glyphs are centered on the origin, with Y up. Our offsets are from the
+                    // element's top left, with Y down.
+                    let center = (width / 2., height / 2.);
+                    for quad in quads {
+                        push_quad(
+                            &mut data,
+                            element,
+                            ((center.0 + quad.min.0) * h, (center.1 - quad.max.1) * h),
+                            ((center.0 + quad.max.0) * h, (center.1 - quad.min.1) * h),
+                            (quad.uv_min.0, quad.uv_max.1),
+                            (quad.uv_max.0, quad.uv_min.1),
+                            (width * h, height * h),
+                        );
+                    }
+                }
+                _ => {
+                    let (u_min, v_min, u_max, v_max) = element.uv_rect;
+                    push_quad(
+                        &mut data,
+                        element,
+                        (0., 0.),
+                        element.size,
+                        (u_min, v_min),
+                        (u_max, v_max),
+                        element.size,
+                    );
+                }
+            }
+
+            let end = (data.len() / HUD_VERTEX_SIZE) as u32;
+
+            // Combine consecutive elements using the same image into one draw.
+            match self.draws.last_mut() {
+                Some((img, range)) if *img == image && range.end == start => range.end = end,
+                _ => self.draws.push((image, start..end)),
+            }
+        }
+
+        self.atlas.upload(queue);
+
+        if data.is_empty() {
+            self.draws.clear();
+            return;
+        }
+
+        // We can't update using a queue due to buffer size mismatches.
+        self.buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("HUD vertex buffer"),
+            contents: &data,
+            usage: BufferUsages::VERTEX,
+        });
+    }
+
+    /// Update the window size, in pixels. Run this each frame.
+    pub fn update_params(&self, queue: &Queue, width: u32, height: u32) {
+        let aspect = width as f32 / height.max(1) as f32;
+
+        let mut data = Vec::with_capacity(HUD_PARAMS_SIZE);
+        for v in [aspect, 0., 0., 0.] {
+            data.extend_from_slice(&v.to_ne_bytes());
+        }
+
+        queue.write_buffer(&self.params_buf, 0, &data);
+    }
+
+    /// Draw the HUD. This should be in a pass after the 3D one, targeting the surface.
+    pub fn draw(&self, rpass: &mut RenderPass) {
+        if self.draws.is_empty() {
+            return;
+        }
+
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(1, &self.bind_group, &[]);
+        rpass.set_vertex_buffer(0, self.buf.slice(..));
+
+        for (image, range) in &self.draws {
+            let bind_group = match image.and_then(|i| self.image_bind_groups.get(i)) {
+                Some(b) => b,
+                None => &self.placeholder_bind_group,
+            };
+
+            rpass.set_bind_group(0, bind_group, &[]);
+            rpass.draw(range.clone(), 0..1);
+        }
+    }
+}
+
+/// Append a quad's vertices. `min` and `max` are relative to the element's top left, as
+/// fractions of the window's height, and `uv_min` and `uv_max` their texture coordinates.
+/// `bounds` is the element's size, used to apply its pivot.
+fn push_quad(
+    data: &mut Vec<u8>,
+    element: &HudElement,
+    min: (f32, f32),
+    max: (f32, f32),
+    uv_min: (f32, f32),
+    uv_max: (f32, f32),
+    bounds: (f32, f32),
+) {
+    for (x, y) in CORNERS {
+        let offset = (
+            min.0 + (max.0 - min.0) * x - element.pivot.0 * bounds.0,
+            min.1 + (max.1 - min.1) * y - element.pivot.1 * bounds.1,
+        );
+        let uv = (
+            uv_min.0 + (uv_max.0 - uv_min.0) * x,
+            uv_min.1 + (uv_max.1 - uv_min.1) * y,
+        );
+
+        for v in [
+            element.position.0,
+            element.position.1,
+            offset.0,
+            offset.1,
+            uv.0,
+            uv.1,
+            element.color.0,
+            element.color.1,
+            element.color.2,
+            element.opacity,
+            element.content.index(),
+        ] {
+            data.extend_from_slice(&v.to_ne_bytes());
+        }
+    }
+}
+
+fn image_bind_group(
+    device: &Device,
+    queue: &Queue,
+    layout: &BindGroupLayout,
+    sampler: &Sampler,
+    image: &HudImage,
+) -> BindGroup {
+    let img = image::RgbaImage::from_raw(image.width, image.height, image.data.clone())
+        .map(image::DynamicImage::ImageRgba8)
+        .unwrap_or_else(|| image::DynamicImage::new_rgba8(1, 1));
+
+    let texture = Texture::from_image(device, queue, &img, Some("HUD image"), false);
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+        label: Some("HUD image bind group"),
+    })
+}
+
+pub(crate) fn vertex_desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+    wgpu::VertexBufferLayout {
+        array_stride: HUD_VERTEX_SIZE as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &[
+            // Position
+            wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float32x2,
+            },
+            // Offset from the position
+            wgpu::VertexAttribute {
+                offset: (2 * F32_SIZE) as wgpu::BufferAddress,
+                shader_location: 1,
+                format: wgpu::VertexFormat::Float32x2,
+            },
+            // UV
+            wgpu::VertexAttribute {
+                offset: (4 * F32_SIZE) as wgpu::BufferAddress,
+                shader_location: 2,
+                format: wgpu::VertexFormat::Float32x2,
+            },
+            // Color
+            wgpu::VertexAttribute {
+                offset: (6 * F32_SIZE) as wgpu::BufferAddress,
+                shader_location: 3,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+            // Kind
+            wgpu::VertexAttribute {
+                offset: (6 * F32_SIZE + VEC4_SIZE) as wgpu::BufferAddress,
+                shader_location: 4,
+                format: wgpu::VertexFormat::Float32,
+            },
+        ],
+    }
+}
+
+fn create_pipeline(
+    device: &Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    config: &SurfaceConfiguration,
+    depth_format: TextureFormat,
+) -> RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("HUD pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[vertex_desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: Some("fs_main"),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: config.format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        // The GUI pass has a depth attachment; the HUD ignores it.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
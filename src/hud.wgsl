@@ -0,0 +1,73 @@
+// The screen-space HUD: images, SDF text, and rectangles, drawn over the 3D scene.
+
+struct HudParams {
+    // Window width / height; only x is used.
+    aspect: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var image: texture_2d<f32>;
+@group(0) @binding(1)
+var image_sampler: sampler;
+
+@group(1) @binding(0)
+var atlas: texture_2d<f32>;
+@group(1) @binding(1)
+var atlas_sampler: sampler;
+@group(1) @binding(2)
+var<uniform> params: HudParams;
+
+// Element kinds
+const IMAGE: u32 = 0u;
+const TEXT: u32 = 1u;
+
+struct VertexIn {
+    // Normalized screen coordinates: 0 to 1, from the window's top left.
+    @location(0) position: vec2<f32>,
+    // As fractions of the window's height. Y is down.
+    @location(1) offset: vec2<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) kind: f32,
+}
+
+struct VertexOut {
+    @builtin(position) clip_posit: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) @interpolate(flat) kind: u32,
+}
+
+@vertex
+fn vs_main(vertex_in: VertexIn) -> VertexOut {
+    var screen = vertex_in.position
+        + vec2<f32>(vertex_in.offset.x / params.aspect.x, vertex_in.offset.y);
+
+    var result: VertexOut;
+    result.clip_posit = vec4<f32>(screen.x * 2. - 1., 1. - screen.y * 2., 0., 1.);
+    result.uv = vertex_in.uv;
+    result.color = vertex_in.color;
+    result.kind = u32(vertex_in.kind);
+
+    return result;
+}
+
+@fragment
+fn fs_main(vertex: VertexOut) -> @location(0) vec4<f32> {
+    // Sampling and derivatives require uniform control flow, so we sample both textures before
+    // choosing one.
+    var image_color = textureSample(image, image_sampler, vertex.uv);
+
+    // Glyph distances are 0.5 at the edge, and higher inside.
+    var glyph_dist = textureSample(atlas, atlas_sampler, vertex.uv).r - 0.5;
+    var glyph_width = max(fwidth(glyph_dist), 0.0001);
+
+    if (vertex.kind == IMAGE) {
+        return image_color * vertex.color;
+    }
+    if (vertex.kind == TEXT) {
+        var alpha = clamp(0.5 + glyph_dist / glyph_width, 0., 1.);
+        return vec4<f32>(vertex.color.rgb, vertex.color.a * alpha);
+    }
+    return vertex.color;
+}
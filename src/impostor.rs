@@ -0,0 +1,183 @@
+//! Impostors: camera-facing quads, ray traced as spheres or cylinders in the fragment shader, with
+//! correct depth output. For scenes with many spheres or cylinders, eg atoms and bonds in large
+//! molecules, these are much cheaper than meshes, and are smooth at any distance.
+//!
+//! An impostor's size comes from its mesh's bounds, so switching an entity between mesh and
+//! impostor rendering doesn't change its size. Impostors don't cast shadows, and aren't occlusion
+//! culled.
+
+use lin_alg::f32::Vec3;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    Buffer, BufferUsages, Device, RenderPass,
+};
+
+use crate::{
+    pipeline_cache::{MainTargets, PipelineCache, PipelineKey},
+    types::{Scene, F32_SIZE, VEC3_SIZE, VEC4_SIZE},
+};
+
+/// corner (vec2), center (vec3), params (vec4).
+const IMPOSTOR_VERTEX_SIZE: usize = 2 * F32_SIZE + VEC3_SIZE + VEC4_SIZE;
+
+/// Each impostor is a quad, drawn as 2 triangles.
+const VERTS_PER_IMPOSTOR: u32 = 6;
+const CORNERS: [(f32, f32); 6] = [
+    (-1., -1.),
+    (1., -1.),
+    (1., 1.),
+    (-1., -1.),
+    (1., 1.),
+    (-1., 1.),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Render entities as impostors instead of as triangles. Set on a mesh, or on an entity group
+/// to override its members' meshes' setting.
+pub enum Impostor {
+    /// A sphere, with the radius of the largest half-extent of the mesh's bounding box.
+    Sphere,
+    /// A capped cylinder along the mesh's local Y axis, eg for bonds. Its length is the height of
+    /// the mesh's bounding box, and its radius the larger of its X and Z half-extents.
+    Cylinder,
+}
+
+impl Impostor {
+    pub(crate) const ALL: [Self; 2] = [Self::Sphere, Self::Cylinder];
+
+    /// Matches the `kind` field in the shader.
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Self::Sphere => 0,
+            Self::Cylinder => 1,
+        }
+    }
+}
+
+/// Instances to draw as one kind of impostor, for one mesh.
+pub(crate) struct ImpostorDraw {
+    pub kind: Impostor,
+    pub mesh: usize,
+    pub instance_start: u32,
+    pub instance_count: u32,
+}
+
+pub(crate) fn vertex_desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+    wgpu::VertexBufferLayout {
+        array_stride: IMPOSTOR_VERTEX_SIZE as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &[
+            // Quad corner
+            wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float32x2,
+            },
+            // Center
+            wgpu::VertexAttribute {
+                offset: (2 * F32_SIZE) as wgpu::BufferAddress,
+                shader_location: 1,
+                format: wgpu::VertexFormat::Float32x3,
+            },
+            // Radius, half length, and kind
+            wgpu::VertexAttribute {
+                offset: (2 * F32_SIZE + VEC3_SIZE) as wgpu::BufferAddress,
+                shader_location: 2,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+        ],
+    }
+}
+
+pub(crate) struct ImpostorRenderer {
+    /// A quad for each kind of impostor, for each mesh, sized from the mesh's bounds.
+    vertex_buf: Buffer,
+    pub draws: Vec<ImpostorDraw>,
+}
+
+impl ImpostorRenderer {
+    pub fn new(device: &Device) -> Self {
+        let vertex_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Impostor vertex buffer"),
+            contents: &[], // Populated in `update_vertices`.
+            usage: BufferUsages::VERTEX,
+        });
+
+        Self {
+            vertex_buf,
+            draws: Vec::new(),
+        }
+    }
+
+    /// Rebuild quads from mesh bounds. Run this when meshes change.
+    pub fn update_vertices(&mut self, device: &Device, scene: &mut Scene) {
+        let vertex_count = scene.meshes.len() * Impostor::ALL.len() * CORNERS.len();
+        let mut data = Vec::with_capacity(vertex_count * IMPOSTOR_VERTEX_SIZE);
+
+        for mesh_i in 0..scene.meshes.len() {
+            // Empty meshes get empty quads.
+            let (center, half) = match scene.mesh_bounds(mesh_i) {
+                Some(b) => (b.center(), (b.max - b.min) * 0.5),
+                None => (Vec3::new_zero(), Vec3::new_zero()),
+            };
+
+            for kind in Impostor::ALL {
+                let (radius, half_len) = match kind {
+                    Impostor::Sphere => (half.x.max(half.y).max(half.z), 0.),
+                    Impostor::Cylinder => (half.x.max(half.z), half.y),
+                };
+
+                for (x, y) in CORNERS {
+                    for v in [x, y, center.x, center.y, center.z] {
+                        data.extend_from_slice(&v.to_ne_bytes());
+                    }
+                    for v in [radius, half_len, kind.index() as f32, 0.] {
+                        data.extend_from_slice(&v.to_ne_bytes());
+                    }
+                }
+            }
+        }
+
+        self.vertex_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Impostor vertex buffer"),
+            contents: &data,
+            usage: BufferUsages::VERTEX,
+        });
+    }
+
+    /// Draw impostors in the main pass. Its bind groups must be set, and the impostor pipeline
+    /// for `targets` prepared.
+    pub fn draw(
+        &self,
+        rpass: &mut RenderPass,
+        pipelines: &PipelineCache,
+        targets: MainTargets,
+        instance_buf: &Buffer,
+    ) {
+        if self.draws.is_empty() {
+            return;
+        }
+
+        let num_meshes = self.vertex_buf.size() as usize
+            / (IMPOSTOR_VERTEX_SIZE * CORNERS.len() * Impostor::ALL.len());
+
+        rpass.set_pipeline(pipelines.get(PipelineKey::impostor(targets)));
+        rpass.set_vertex_buffer(0, self.vertex_buf.slice(..));
+        rpass.set_vertex_buffer(1, instance_buf.slice(..));
+
+        for draw in &self.draws {
+            // Meshes may briefly differ from the quads after they change.
+            if draw.mesh >= num_meshes {
+                continue;
+            }
+
+            let start =
+                (draw.mesh * Impostor::ALL.len() + draw.kind.index()) as u32 * VERTS_PER_IMPOSTOR;
+
+            rpass.draw(
+                start..start + VERTS_PER_IMPOSTOR,
+                draw.instance_start..draw.instance_start + draw.instance_count,
+            );
+        }
+    }
+}
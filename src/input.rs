@@ -1,9 +1,8 @@
 //! Handles keyboard and mouse input, eg for moving the camera.
 
-use egui::Key;
 use lin_alg::f32::{Quaternion, Vec3};
 // todo: remove Winit from this module if you can, and make it agnostic?
-use winit::event::{DeviceEvent, ElementState};
+use winit::event::{DeviceEvent, ElementState, MouseButton, MouseScrollDelta};
 use winit::{
     keyboard::{KeyCode, PhysicalKey::Code},
     platform::scancode::PhysicalKeyExtScancode,
@@ -18,6 +17,64 @@ use crate::{
 const MOUSE_0_ID: u32 = 0;
 const MOUSE_1_ID: u32 = 1;
 
+// Scroll events from touchpads are in pixels, vice lines of a mouse wheel.
+const PIXELS_PER_SCROLL_LINE: f32 = 20.;
+// With `Arc` controls, the fraction of the distance to the center each line of scrolling zooms.
+const ARC_ZOOM_PER_LINE: f32 = 0.1;
+// With `Arc` controls, the fraction of the distance to the center each pixel of panning moves.
+const ARC_PAN_PER_PIXEL: f32 = 0.0015;
+
+#[derive(Clone, Debug, PartialEq)]
+/// Keys and mouse buttons used by the `FreeCamera` and `Arc` controls. Keys are physical, ie by
+/// position on a US layout, regardless of the keyboard's layout. Set these in
+/// `InputSettings::key_bindings`.
+pub struct KeyBindings {
+    pub fwd: KeyCode,
+    pub back: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+    pub up: KeyCode,
+    pub down: KeyCode,
+    pub roll_ccw: KeyCode,
+    pub roll_cw: KeyCode,
+    /// Held to multiply the move speed by `InputSettings::run_factor`.
+    pub run: KeyCode,
+    /// Held to rotate the camera by moving the mouse. With `Arc` controls, this orbits.
+    pub free_look: MouseButton,
+    /// Held to pan the camera by moving the mouse, with `Arc` controls.
+    pub pan: MouseButton,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            fwd: KeyCode::KeyW,
+            back: KeyCode::KeyS,
+            left: KeyCode::KeyA,
+            right: KeyCode::KeyD,
+            up: KeyCode::Space,
+            down: KeyCode::KeyC,
+            roll_ccw: KeyCode::KeyQ,
+            roll_cw: KeyCode::KeyE,
+            run: KeyCode::ShiftLeft,
+            free_look: MouseButton::Left,
+            pan: MouseButton::Middle,
+        }
+    }
+}
+
+/// The ID of a mouse button in device events, which don't use `MouseButton`.
+fn button_id(button: MouseButton) -> u32 {
+    match button {
+        MouseButton::Left => MOUSE_0_ID,
+        MouseButton::Right => MOUSE_1_ID,
+        MouseButton::Middle => 2,
+        MouseButton::Back => 3,
+        MouseButton::Forward => 4,
+        MouseButton::Other(id) => id as u32,
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct InputsCommanded {
     pub fwd: bool,
@@ -32,13 +89,16 @@ pub struct InputsCommanded {
     pub mouse_delta_y: f32,
     pub run: bool,
     pub free_look: bool,
+    pub pan: bool,
+    /// In lines; positive away from the user.
+    pub scroll: f32,
 }
 
 impl InputsCommanded {
     /// Return true if there are any inputs.
     pub fn inputs_present(&self) -> bool {
         const EPS: f32 = 0.00001;
-        // Note; We don't include `run` or `free_look` here, since it's a modifier.
+        // Note; We don't include `run`, `free_look`, or `pan` here, since they're modifiers.
         self.fwd
             || self.back
             || self.left
@@ -49,112 +109,96 @@ impl InputsCommanded {
             || self.roll_cw
             || self.mouse_delta_x.abs() > EPS
             || self.mouse_delta_y.abs() > EPS
+            || self.scroll.abs() > EPS
     }
 }
 
-/// Modifies the commanded inputs in place; triggered by a single input event.
-/// dt is in seconds.
-/// pub(crate) fn handle_event(event: DeviceEvent, cam: &mut Camera, input_settings: &InputSettings, dt: f32) {
-pub(crate) fn add_input_cmd(event: DeviceEvent, inputs: &mut InputsCommanded) {
+/// Modifies the commanded inputs in place; triggered by a single input event. Events used here are
+/// still passed to the application's event handler, eg so a click can both start free looking, and
+/// select an entity.
+pub(crate) fn add_input_cmd(
+    event: DeviceEvent,
+    inputs: &mut InputsCommanded,
+    bindings: &KeyBindings,
+) {
     match event {
         DeviceEvent::Key(key) => {
-            if key.state == ElementState::Pressed {
-                // todo: Map to PhysicalKey directly without the scancode part.
-                match key.physical_key {
-                    Code(key) => match key {
-                        KeyCode::KeyW => {
-                            inputs.fwd = true;
-                        }
-                        KeyCode::KeyS => {
-                            inputs.back = true;
-                        }
-                        KeyCode::KeyA => {
-                            inputs.left = true;
-                        }
-                        KeyCode::KeyD => {
-                            inputs.right = true;
-                        }
-                        KeyCode::Space => {
-                            inputs.up = true;
-                        }
-                        KeyCode::KeyC => {
-                            inputs.down = true;
-                        }
-                        KeyCode::KeyQ => {
-                            inputs.roll_ccw = true;
-                        }
-                        KeyCode::KeyE => {
-                            inputs.roll_cw = true;
-                        }
-                        KeyCode::ShiftLeft => {
-                            inputs.run = true;
-                        }
-                        _ => (),
-                    },
-                    _ => (),
-                }
-            } else if key.state == ElementState::Released {
-                // todo: DRY
-                match key.physical_key {
-                    Code(key) => match key {
-                        KeyCode::KeyW => {
-                            inputs.fwd = false;
-                        }
-                        KeyCode::KeyS => {
-                            inputs.back = false;
-                        }
-                        KeyCode::KeyA => {
-                            inputs.left = false;
-                        }
-                        KeyCode::KeyD => {
-                            inputs.right = false;
-                        }
-                        KeyCode::Space => {
-                            inputs.up = false;
-                        }
-                        KeyCode::KeyC => {
-                            inputs.down = false;
-                        }
-                        KeyCode::KeyQ => {
-                            inputs.roll_ccw = false;
-                        }
-                        KeyCode::KeyE => {
-                            inputs.roll_cw = false;
-                        }
-                        KeyCode::ShiftLeft => {
-                            inputs.run = false;
-                        }
-                        _ => (),
-                    },
-                    _ => (),
-                }
+            let pressed = key.state == ElementState::Pressed;
+
+            // todo: Map to PhysicalKey directly without the scancode part.
+            let Code(key) = key.physical_key else {
+                return;
+            };
+
+            let actions = [
+                (bindings.fwd, &mut inputs.fwd),
+                (bindings.back, &mut inputs.back),
+                (bindings.left, &mut inputs.left),
+                (bindings.right, &mut inputs.right),
+                (bindings.up, &mut inputs.up),
+                (bindings.down, &mut inputs.down),
+                (bindings.roll_ccw, &mut inputs.roll_ccw),
+                (bindings.roll_cw, &mut inputs.roll_cw),
+                (bindings.run, &mut inputs.run),
+            ];
+
+            if let Some((_, input)) = actions.into_iter().find(|(code, _)| *code == key) {
+                *input = pressed;
             }
         }
-        DeviceEvent::Button { button, state } => {
-            if button == MOUSE_0_ID {
-                inputs.free_look = match state {
-                    ElementState::Pressed => true,
-                    ElementState::Released => false,
-                }
-            }
+        DeviceEvent::Button { button, state } if button == button_id(bindings.free_look) => {
+            inputs.free_look = match state {
+                ElementState::Pressed => true,
+                ElementState::Released => false,
+            };
+        }
+        DeviceEvent::MouseMotion { delta } if inputs.free_look => {
+            inputs.mouse_delta_x += delta.0 as f32;
+            inputs.mouse_delta_y += delta.1 as f32;
+        }
+        _ => (),
+    }
+}
+
+/// As `add_input_cmd`, for the `Arc` controls: the free look and pan buttons, mouse motion while
+/// either is held, and scrolling. Keys aren't used.
+pub(crate) fn add_input_cmd_arc(
+    event: DeviceEvent,
+    inputs: &mut InputsCommanded,
+    bindings: &KeyBindings,
+) {
+    match event {
+        DeviceEvent::Button { button, state } if button == button_id(bindings.free_look) => {
+            inputs.free_look = state == ElementState::Pressed;
+        }
+        DeviceEvent::Button { button, state } if button == button_id(bindings.pan) => {
+            inputs.pan = state == ElementState::Pressed;
         }
-        DeviceEvent::MouseMotion { delta } => {
+        DeviceEvent::MouseMotion { delta } if inputs.free_look || inputs.pan => {
             inputs.mouse_delta_x += delta.0 as f32;
             inputs.mouse_delta_y += delta.1 as f32;
         }
+        DeviceEvent::MouseWheel { delta } => {
+            inputs.scroll += match delta {
+                MouseScrollDelta::LineDelta(_, y) => y,
+                MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / PIXELS_PER_SCROLL_LINE,
+            };
+        }
         _ => (),
     }
 }
 
 /// Adjust the camera orientation and position. Return if there was a change, so we know to update the buffer.
+/// `scale` is the scene's scale factor; movement is proportional to it.
 /// todo: copyied from `peptide`'s Bevy interface.
 pub fn adjust_camera(
     cam: &mut Camera,
     inputs: &InputsCommanded,
     input_settings: &InputSettings,
+    scale: f32,
     dt: f32,
 ) -> bool {
-    let mut move_amt: f32 = input_settings.move_sens * dt;
+    let mut move_amt: f32 = input_settings.move_sens * scale * dt;
     let rotate_amt: f32 = input_settings.rotate_sens * dt;
     let mut rotate_key_amt: f32 = input_settings.rotate_key_sens * dt;
 
@@ -231,3 +275,66 @@ pub fn adjust_camera(
 
     cam_moved || cam_rotated
 }
+
+/// Adjust the camera using the `Arc` controls, orbiting, or panning with, `center`. Returns true
+/// if there was a change. Orbiting is around the up axis, and the camera's right axis, stopping
+/// short of looking straight up or down.
+pub(crate) fn adjust_camera_arc(
+    cam: &mut Camera,
+    center: &mut Vec3,
+    inputs: &InputsCommanded,
+    input_settings: &InputSettings,
+    dt: f32,
+) -> bool {
+    const EPS: f32 = 0.00001;
+    // The most the camera's forward vector can point along the up axis, when orbiting.
+    const MAX_PITCH_DOT: f32 = 0.99;
+
+    let rotate_amt = input_settings.rotate_sens * dt;
+    let (dx, dy) = (inputs.mouse_delta_x, inputs.mouse_delta_y);
+    let dragged = dx.abs() > EPS || dy.abs() > EPS;
+
+    let mut changed = false;
+
+    if inputs.free_look && dragged {
+        let right = cam.orientation.rotate_vec(RIGHT_VEC);
+
+        let mut rotation = Quaternion::from_axis_angle(UP_VEC, dx * rotate_amt);
+
+        let pitch = Quaternion::from_axis_angle(right, dy * rotate_amt);
+        let fwd = (pitch * cam.orientation).rotate_vec(FWD_VEC);
+        if fwd.dot(UP_VEC).abs() < MAX_PITCH_DOT {
+            rotation = rotation * pitch;
+        }
+
+        cam.position = *center + rotation.rotate_vec(cam.position - *center);
+        cam.orientation = rotation * cam.orientation;
+        changed = true;
+    } else if inputs.pan && dragged {
+        // Move a constant fraction of the view per pixel, regardless of the distance.
+        let amt = (cam.position - *center).magnitude() * ARC_PAN_PER_PIXEL;
+        let right = cam.orientation.rotate_vec(RIGHT_VEC);
+        let up = cam.orientation.rotate_vec(UP_VEC);
+
+        let offset = up * dy * amt - right * dx * amt;
+        cam.position += offset;
+        *center += offset;
+        changed = true;
+    }
+
+    if inputs.scroll.abs() > EPS {
+        let factor = (1. - ARC_ZOOM_PER_LINE).powf(inputs.scroll);
+
+        match cam.ortho_height {
+            // Moving an orthographic camera doesn't change the view's size.
+            Some(height) => {
+                cam.ortho_height = Some(height * factor);
+                cam.update_proj_mat();
+            }
+            None => cam.position = *center + (cam.position - *center) * factor,
+        }
+        changed = true;
+    }
+
+    changed
+}
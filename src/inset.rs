@@ -0,0 +1,124 @@
+//! An inset view, eg an overhead minimap, drawn in a corner of the 3D viewport. When
+//! `Scene::inset` is set, after the main passes, we render the scene again from the inset's
+//! camera, with its own background, and composite the result over the inset's rectangle. It's
+//! drawn like the views in `Scene::extra_views`, after them; see the `views` module.
+//!
+//! As with the main camera, clustered lighting and deferred shading assume a perspective
+//! projection, so point lights may be lit inaccurately in an orthographic inset. With
+//! `RedrawMode::OnChange`, set `EngineUpdates::redraw` after changing the inset.
+
+use lin_alg::f32::{Quaternion, Vec3};
+
+use crate::{
+    camera::Camera,
+    graphics::{RIGHT_VEC, UP_VEC},
+    views::ExtraView,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+/// The corner of the 3D viewport an inset is drawn in.
+pub enum InsetCorner {
+    TopLeft,
+    #[default]
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(Clone, Debug)]
+pub struct Inset {
+    /// Its aspect ratio is set from the inset's size when rendering. See `Inset::overhead` for an
+    /// orthographic camera looking down.
+    pub camera: Camera,
+    pub corner: InsetCorner,
+    /// Width and height, in pixels. This is clipped to the 3D viewport.
+    pub size: (u32, u32),
+    /// The distance from the edges of the 3D viewport, in pixels.
+    pub margin: u32,
+    /// Interpreted as `Scene::background_color`. The scene's background gradient isn't drawn in
+    /// the inset.
+    pub background_color: (f32, f32, f32),
+    /// Draw entities in the background layer. See the `layers` module.
+    pub background_layer: bool,
+    /// Draw entities in the overlay layer, eg gizmos, which are often unwanted on a map.
+    pub overlay_layer: bool,
+}
+
+impl Default for Inset {
+    fn default() -> Self {
+        Self::overhead(Vec3::new_zero(), 20.)
+    }
+}
+
+impl Inset {
+    /// An orthographic camera above `center`, looking down, showing `view_height` world units
+    /// vertically. +Z is up in the inset.
+    pub fn overhead(center: Vec3, view_height: f32) -> Self {
+        let mut camera = Camera {
+            position: center + UP_VEC * view_height,
+            orientation: Quaternion::from_axis_angle(RIGHT_VEC, std::f32::consts::TAU / 4.),
+            ortho_height: Some(view_height),
+            aspect: 1.,
+            near: 0.1,
+            far: view_height * 2.,
+            ..Default::default()
+        };
+        camera.update_proj_mat();
+
+        Self {
+            camera,
+            corner: InsetCorner::default(),
+            size: (256, 256),
+            margin: 16,
+            background_color: (0.1, 0.1, 0.1),
+            background_layer: false,
+            overlay_layer: false,
+        }
+    }
+
+    /// The inset as a view, for a 3D viewport of (x, y, width, height).
+    pub(crate) fn view(&self, viewport: (f32, f32, f32, f32)) -> Option<ExtraView> {
+        Some(ExtraView {
+            camera: self.camera.clone(),
+            rect: corner_rect(self.corner, self.size, self.margin, viewport)?,
+            background_color: Some(self.background_color),
+            background_layer: self.background_layer,
+            overlay_layer: self.overlay_layer,
+        })
+    }
+}
+
+/// A rectangle of `size` in a corner of a 3D viewport of (x, y, width, height), `margin` pixels
+/// from its edges, as (x, y, width, height) in pixels. `None` if it doesn't fit. Also used for
+/// debug views.
+pub(crate) fn corner_rect(
+    corner: InsetCorner,
+    size: (u32, u32),
+    margin: u32,
+    viewport: (f32, f32, f32, f32),
+) -> Option<(u32, u32, u32, u32)> {
+    let (vp_x, vp_y, vp_width, vp_height) = viewport;
+
+    let max_width = (vp_width as u32).saturating_sub(margin * 2);
+    let max_height = (vp_height as u32).saturating_sub(margin * 2);
+
+    let width = size.0.min(max_width);
+    let height = size.1.min(max_height);
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let left = vp_x as u32 + margin;
+    let top = vp_y as u32 + margin;
+    let right = (vp_x + vp_width) as u32 - margin - width;
+    let bottom = (vp_y + vp_height) as u32 - margin - height;
+
+    let (x, y) = match corner {
+        InsetCorner::TopLeft => (left, top),
+        InsetCorner::TopRight => (right, top),
+        InsetCorner::BottomLeft => (left, bottom),
+        InsetCorner::BottomRight => (right, bottom),
+    };
+
+    Some((x, y, width, height))
+}
@@ -0,0 +1,76 @@
+// Instance data: the per-instance vertex attributes, and the layout of the instance buffer, for
+// compute passes binding it with `ComputeBinding::Instances`.
+
+// These are matrix columns; we can't pass matrices directly for vertex attributes.
+struct InstanceIn {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    // The w components are the lighting factors; see `instance_lighting_factors`.
+    @location(9) normal_matrix_0: vec4<f32>,
+    @location(10) normal_matrix_1: vec4<f32>,
+    @location(11) normal_matrix_2: vec4<f32>,
+    @location(12) color: vec4<f32>, // Len 4; includes alpha.
+    // Shinyness and reflectivity, then palette and material indices, as raw bits. Read them with
+    // `instance_material` and `instance_indices`.
+    @location(13) props: vec4<u32>,
+    // Texture coordinate offset (xy), and scale (zw).
+    @location(14) uv_transform: vec4<f32>,
+    // Multiplies the material texture.
+    @location(15) texture_tint: vec3<f32>,
+}
+
+// The model matrix includes translation, rotation, and scale.
+fn instance_model(instance: InstanceIn) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+}
+
+// The normal matrix includes rotation only.
+fn instance_normal(instance: InstanceIn) -> mat3x3<f32> {
+    return mat3x3<f32>(
+        instance.normal_matrix_0.xyz,
+        instance.normal_matrix_1.xyz,
+        instance.normal_matrix_2.xyz,
+    );
+}
+
+// Multipliers of ambient, diffuse, and specular lighting. These pad the normal matrix columns, so
+// instances fit in WebGPU's limit of 16 vertex attributes.
+fn instance_lighting_factors(instance: InstanceIn) -> vec3<f32> {
+    return vec3<f32>(
+        instance.normal_matrix_0.w,
+        instance.normal_matrix_1.w,
+        instance.normal_matrix_2.w,
+    );
+}
+
+// Shinyness, and reflectivity.
+fn instance_material(instance: InstanceIn) -> vec2<f32> {
+    return bitcast<vec2<f32>>(instance.props.xy);
+}
+
+// Palette index, and material index. The palette index is -1 if the instance uses its own color,
+// and the material index is -1 if it's untextured.
+fn instance_indices(instance: InstanceIn) -> vec2<i32> {
+    return bitcast<vec2<i32>>(instance.props.zw);
+}
+
+// Offsets in the instance buffer, in f32s, when bound as `array<f32>`. Matrices are column-major.
+// Normal matrix columns are padded to 4 elements; each padding element is a lighting factor, so
+// the ambient, diffuse, and specular factors are at `INSTANCE_LIGHTING_FACTORS`, plus 0, 4, and 8.
+// Indices are i32s; read them with `bitcast<i32>`.
+const INSTANCE_STRIDE: u32 = 43u;
+const INSTANCE_MODEL: u32 = 0u;
+const INSTANCE_NORMAL: u32 = 16u;
+const INSTANCE_LIGHTING_FACTORS: u32 = 19u;
+const INSTANCE_COLOR: u32 = 28u;
+const INSTANCE_MATERIAL: u32 = 32u;
+const INSTANCE_INDICES: u32 = 34u;
+const INSTANCE_UV_TRANSFORM: u32 = 36u;
+const INSTANCE_TEXTURE_TINT: u32 = 40u;
@@ -0,0 +1,177 @@
+//! Text labels attached to entities, eg atom names, or annotations on parts of a model. Attach
+//! them with `Scene::attach_label`. Each frame the scene is rendered, the engine places labels at
+//! their entities' positions, including group transforms, and draws them as SDF text; see the `sdf`
+//! module. Labels of entities hidden by a group aren't drawn.
+//!
+//! Labels with `LabelOptions::declutter` set are hidden where they'd overlap others on screen.
+//! Labels with higher priorities are placed first, then earlier labels. `Label::visible` reports
+//! the result. With `RedrawMode::OnChange`, set `EngineUpdates::redraw` after changing labels.
+
+use std::cmp::Reverse;
+
+use lin_alg::f32::Vec3;
+
+use crate::{
+    camera,
+    sdf::{SdfAnchor, SdfElement, SdfShape},
+    types::Scene,
+};
+
+#[derive(Clone, Debug)]
+pub struct LabelOptions {
+    /// Text height: in pixels if `fixed_size` is set, and world units otherwise.
+    pub size: f32,
+    pub color: (f32, f32, f32),
+    pub opacity: f32,
+    /// Draw the label over geometry in front of it, vice hiding it behind that geometry.
+    pub on_top: bool,
+    /// Keep the label the same size on screen, vice shrinking it with distance.
+    pub fixed_size: bool,
+    /// Hide this label where it overlaps others placed before it.
+    pub declutter: bool,
+    /// Labels with higher priorities are placed before others when decluttering.
+    pub priority: i32,
+}
+
+impl Default for LabelOptions {
+    fn default() -> Self {
+        Self {
+            size: 16.,
+            color: (1., 1., 1.),
+            opacity: 1.,
+            on_top: true,
+            fixed_size: true,
+            declutter: true,
+            priority: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Label {
+    /// Index into `Scene::entities`. Labels of entities that don't exist aren't drawn.
+    pub entity: usize,
+    /// Use `\n` to start a new line.
+    pub text: String,
+    /// From the entity's position to the label's center, in world units. This isn't rotated
+    /// with the entity, eg so labels offset upwards stay above it.
+    pub offset: Vec3,
+    pub options: LabelOptions,
+    /// Set by the engine each frame the scene is rendered: false if the label is hidden by
+    /// decluttering, is off screen, or its entity is hidden.
+    pub visible: bool,
+}
+
+impl Scene {
+    /// Attach a text label to an entity, and return its index in `labels`.
+    pub fn attach_label(
+        &mut self,
+        entity: usize,
+        text: &str,
+        offset: Vec3,
+        options: LabelOptions,
+    ) -> usize {
+        self.labels.push(Label {
+            entity,
+            text: text.to_owned(),
+            offset,
+            options,
+            visible: false,
+        });
+        self.labels.len() - 1
+    }
+
+    /// Remove all labels attached to an entity.
+    pub fn detach_labels(&mut self, entity: usize) {
+        self.labels.retain(|label| label.entity != entity);
+    }
+}
+
+/// A screen rectangle, in pixels: min x, min y, max x, max y.
+type Rect = (f32, f32, f32, f32);
+
+fn overlaps(a: &Rect, b: &Rect) -> bool {
+    a.0 < b.2 && b.0 < a.2 && a.1 < b.3 && b.1 < a.3
+}
+
+/// Place the scene's labels for this frame, setting their visibility, and return SDF elements to
+/// draw them. `viewport` is the 3D viewport's width and height, in pixels. `text_size` returns the
+/// size of a text block, in units of its line height.
+pub(crate) fn update(
+    scene: &mut Scene,
+    viewport: (f32, f32),
+    mut text_size: impl FnMut(&str) -> (f32, f32),
+) -> Vec<SdfElement> {
+    let camera = &scene.camera;
+    let proj_view = camera.proj_mat.clone() * camera.view_mat();
+    // Converts world units at W = 1 to pixels, for labels that shrink with distance.
+    let px_per_unit = camera.proj_mat.data[5] * viewport.1 / 2.;
+
+    let mut order: Vec<usize> = (0..scene.labels.len()).collect();
+    order.sort_by_key(|&i| Reverse(scene.labels[i].options.priority));
+
+    let mut placed: Vec<Rect> = Vec::new();
+    let mut result = Vec::new();
+
+    for i in order {
+        let label = &scene.labels[i];
+
+        let anchor = scene
+            .entity_in_world(label.entity)
+            .map(|entity| entity.position + label.offset);
+        let projected = anchor.and_then(|anchor| Some((anchor, camera::project(&proj_view, anchor)?)));
+
+        let Some((anchor, (ndc_x, ndc_y, w))) = projected else {
+            scene.labels[i].visible = false;
+            continue;
+        };
+
+        let options = &label.options;
+        let height_px = if options.fixed_size {
+            options.size
+        } else {
+            options.size * px_per_unit / w
+        };
+
+        let (width, height) = text_size(&label.text);
+        let (half_w, half_h) = (width * height_px / 2., height * height_px / 2.);
+        let center = (
+            (ndc_x * 0.5 + 0.5) * viewport.0,
+            (0.5 - ndc_y * 0.5) * viewport.1,
+        );
+        let rect = (
+            center.0 - half_w,
+            center.1 - half_h,
+            center.0 + half_w,
+            center.1 + half_h,
+        );
+
+        let on_screen = overlaps(&rect, &(0., 0., viewport.0, viewport.1));
+        let visible =
+            on_screen && !(options.declutter && placed.iter().any(|r| overlaps(r, &rect)));
+
+        if visible {
+            placed.push(rect);
+
+            let anchor = if options.fixed_size {
+                SdfAnchor::WorldFixedSize(anchor)
+            } else {
+                SdfAnchor::World(anchor)
+            };
+
+            let mut element = SdfElement::new(
+                SdfShape::Text(label.text.clone()),
+                anchor,
+                options.size,
+                options.color,
+            );
+            element.opacity = options.opacity;
+            element.on_top = options.on_top;
+            result.push(element);
+        }
+
+        scene.labels[i].visible = visible;
+    }
+
+    result
+}
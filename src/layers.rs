@@ -0,0 +1,77 @@
+//! Render layers: ordered groups of drawing, each in its own pass, with its own depth settings.
+//! Entities are assigned to a layer with `Entity::layer`. In order:
+//!
+//! - Background: `Scene::background_gradient`, then entities in this layer, eg a sky dome or
+//!   distant terrain. By default, the world layer clears depth, so these are always behind it.
+//! - World: the main pass. Entities, impostors, the ground plane, debug lines, and SDF elements.
+//! - Overlay: entities drawn over the world, eg gizmos and manipulators. By default this clears
+//!   depth, so they're never hidden by the world, but still occlude each other.
+//! - HUD: `Scene::hud`, and the GUI. These are 2D, and drawn last, without depth.
+//!
+//! Entities in the background and overlay layers are drawn individually, so these layers are
+//! intended for small numbers of entities. They aren't occlusion culled, drawn as impostors, or
+//! drawn into environment probes, and don't cast shadows.
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+/// The 3D layer an entity is drawn in. See the `layers` module.
+pub enum RenderLayer {
+    Background,
+    #[default]
+    World,
+    Overlay,
+}
+
+impl RenderLayer {
+    /// In the order they're drawn.
+    pub const ALL: [Self; 3] = [Self::Background, Self::World, Self::Overlay];
+
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Depth settings for a render layer.
+pub struct LayerSettings {
+    /// Clear depth before drawing this layer, so it's drawn over previous layers. The first layer
+    /// drawn always clears depth.
+    pub clear_depth: bool,
+    /// Test and write depth. If false, meshes in this layer are drawn in order, each over the
+    /// previous ones; this doesn't apply to impostors, lines, or SDF elements.
+    pub depth_test: bool,
+}
+
+impl Default for LayerSettings {
+    fn default() -> Self {
+        Self {
+            clear_depth: true,
+            depth_test: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// Settings for each 3D render layer; see `Scene::layers`.
+pub struct RenderLayers {
+    pub background: LayerSettings,
+    pub world: LayerSettings,
+    pub overlay: LayerSettings,
+}
+
+impl RenderLayers {
+    pub fn get(&self, layer: RenderLayer) -> &LayerSettings {
+        match layer {
+            RenderLayer::Background => &self.background,
+            RenderLayer::World => &self.world,
+            RenderLayer::Overlay => &self.overlay,
+        }
+    }
+
+    pub fn get_mut(&mut self, layer: RenderLayer) -> &mut LayerSettings {
+        match layer {
+            RenderLayer::Background => &mut self.background,
+            RenderLayer::World => &mut self.world,
+            RenderLayer::Overlay => &mut self.overlay,
+        }
+    }
+}
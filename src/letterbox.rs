@@ -0,0 +1,93 @@
+//! Locking the 3D viewport to a fixed aspect ratio, with `UiSettings::viewport_aspect`, eg so
+//! recordings and figures keep the same framing regardless of window size. The viewport is
+//! centered in the space the GUI leaves, and the rest is filled with black bars, drawn before the
+//! HUD and GUI.
+
+use wgpu::{Device, RenderPass, RenderPipeline, TextureFormat};
+
+use crate::redraw;
+
+/// The largest rectangle with this aspect ratio (width / height) that fits in `area`, centered in
+/// it. Both are (x, y, width, height), in pixels. The offsets are whole pixels.
+pub(crate) fn fit_aspect(area: (f32, f32, f32, f32), aspect: f32) -> (f32, f32, f32, f32) {
+    let (x, y, width, height) = area;
+    if width <= 0. || height <= 0. || aspect <= 0. {
+        return area;
+    }
+
+    if width / height > aspect {
+        let fit_width = (height * aspect).round();
+        (x + ((width - fit_width) / 2.).floor(), y, fit_width, height)
+    } else {
+        let fit_height = (width / aspect).round();
+        (
+            x,
+            y + ((height - fit_height) / 2.).floor(),
+            width,
+            fit_height,
+        )
+    }
+}
+
+/// The parts of `area` outside `viewport`, ie the bars, as (x, y, width, height) in pixels.
+fn bars(area: (f32, f32, f32, f32), viewport: (f32, f32, f32, f32)) -> Vec<(u32, u32, u32, u32)> {
+    let (x, y, width, height) = area;
+    let (vp_x, vp_y, vp_width, vp_height) = viewport;
+
+    let rects = if vp_width < width {
+        [
+            (x, y, vp_x - x, height),
+            (vp_x + vp_width, y, x + width - vp_x - vp_width, height),
+        ]
+    } else {
+        [
+            (x, y, width, vp_y - y),
+            (x, vp_y + vp_height, width, y + height - vp_y - vp_height),
+        ]
+    };
+
+    rects
+        .into_iter()
+        .map(|(x, y, w, h)| (x as u32, y as u32, w as u32, h as u32))
+        .filter(|(_, _, w, h)| *w > 0 && *h > 0)
+        .collect()
+}
+
+pub(crate) struct LetterboxRenderer {
+    pipeline: RenderPipeline,
+}
+
+impl LetterboxRenderer {
+    /// `format` is that of the output, and `depth_format` that of the main depth buffer.
+    pub fn new(device: &Device, format: TextureFormat, depth_format: TextureFormat) -> Self {
+        Self {
+            pipeline: redraw::create_fill_pipeline(device, format, depth_format),
+        }
+    }
+
+    /// Draw bars over the parts of `area` outside `viewport`, if any. `area` is the part of the
+    /// window not used by the GUI. The pass's scissor is reset to the output, which is `width` by
+    /// `height`.
+    pub fn draw(
+        &self,
+        rpass: &mut RenderPass,
+        area: (f32, f32, f32, f32),
+        viewport: (f32, f32, f32, f32),
+        width: u32,
+        height: u32,
+    ) {
+        if area == viewport {
+            return;
+        }
+
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_blend_constant(wgpu::Color::BLACK);
+
+        for (x, y, bar_width, bar_height) in bars(area, viewport) {
+            rpass.set_scissor_rect(x, y, bar_width, bar_height);
+            rpass.draw(0..3, 0..1);
+        }
+
+        rpass.set_scissor_rect(0, 0, width, height);
+    }
+}
@@ -1,27 +1,150 @@
 #![allow(mixed_script_confusables)] // Theta in meshes
 
+mod anaglyph;
+mod animation;
+mod area_light;
+mod background;
+mod binary;
+mod buffer_pool;
 mod camera;
+#[cfg(feature = "capi")]
+mod capi;
+mod cluster;
+mod collision;
+pub mod color;
+mod compressed;
+#[cfg(feature = "compute")]
+mod compute;
+mod culling;
+mod debug;
+mod debug_view;
+mod deferred;
+mod engine;
+mod entity_buckets;
+mod extension;
+mod fixed_step;
+mod fnv;
+mod gpu_pick;
 mod graphics;
+mod ground;
+#[cfg(feature = "gui")]
 mod gui;
+mod headless;
+mod heatmap;
+mod hud;
+mod impostor;
 mod input;
+mod inset;
+mod labels;
+mod layers;
+mod letterbox;
+mod lifecycle;
+mod light_path;
+mod limits;
 pub mod lighting;
+mod loader;
+mod material;
+pub mod math;
+mod measure;
+mod mesh_cache;
 mod meshes;
+mod pacing;
+mod packed;
+mod parallel;
+mod pass;
+mod patch;
+mod pipeline_cache;
+mod probe;
+mod raw_instances;
+mod raycast;
+mod redraw;
+#[cfg(feature = "remote")]
+mod remote;
+mod sdf;
+mod shader_interface;
+mod shadow;
+mod shortcut;
+mod slice;
+pub mod snapshot;
+mod stats;
+mod sub_range;
 mod system;
+mod taa;
 mod texture;
+mod timing;
+mod toon;
+mod transition;
+mod turntable;
+mod two_d;
 mod types;
+mod upload;
+mod views;
 mod window;
 
+pub use anaglyph::Anaglyph;
+#[cfg(feature = "gui")]
+pub use animation::timeline_ui;
+pub use animation::{CameraKeyframe, LightKeyframe, Timeline};
+pub use area_light::AreaLight;
+pub use background::BackgroundGradient;
 pub use camera::Camera;
-pub use input::InputsCommanded;
+pub use collision::{Aabb, SpatialCache};
+pub use compressed::{BcFormat, CompressedImage};
+#[cfg(feature = "compute")]
+pub use compute::{ComputeBinding, ComputePass, ComputeStage, DEFORM_WORKGROUP_SIZE};
+pub use debug::{DebugDraw, DebugSettings, DebugShapes};
+pub use debug_view::{DebugTarget, DebugView};
+pub use engine::{Engine, EngineBuilder};
+pub use extension::{ExtensionBindGroup, ExtensionBinding, ShaderExtension};
+pub use gpu_pick::GpuHit;
+pub use ground::GroundPlane;
+pub use headless::HeadlessRenderer;
+pub use heatmap::{Colormap, Heatmap};
+pub use hud::{Hud, HudContent, HudElement, HudImage};
+pub use impostor::Impostor;
+pub use input::{InputsCommanded, KeyBindings};
+pub use inset::{Inset, InsetCorner};
+pub use labels::{Label, LabelOptions};
+pub use layers::{LayerSettings, RenderLayer, RenderLayers};
+pub use lifecycle::{LifecycleEvent, WindowState};
+pub use light_path::{LightPath, PathShape};
+pub use material::{Material, MaterialImage, SamplerSettings, TextureAddress, TextureFilter};
 pub use lighting::{LightType, Lighting, PointLight};
-pub use system::run;
+pub use loader::{AssetId, AssetRequest, LoadEvent};
+pub use measure::{MeasureKind, MeasurePoint, MeasureTool, Measurement};
+pub use meshes::{NormalMode, UvProjection};
+pub use packed::PackedInstances;
+pub use patch::{PatchOp, ScenePatch, PATCH_VERSION};
+pub use probe::EnvProbe;
+pub use raw_instances::InstanceRaw;
+pub use raycast::Hit;
+pub use redraw::{RedrawMode, RedrawRegion};
+pub use sdf::{SdfAnchor, SdfElement, SdfShape};
+pub use shader_interface::{
+    preprocess_wgsl, CAMERA_WGSL, INSTANCE_WGSL, LIGHTING_WGSL, SHADER_INTERFACE_VERSION,
+};
+pub use shortcut::{KeyChord, Modifiers, Shortcuts};
+pub use slice::SlicePlane;
+pub use stats::SceneStats;
+pub use system::{run, GuiContext};
+pub use timing::FrameStats;
+pub use toon::ToonSettings;
+pub use transition::Transitions;
+pub use turntable::Turntable;
 pub use types::{
-    ControlScheme, EngineUpdates, Entity, InputSettings, Mesh, Scene, UiLayout, UiSettings, Vertex,
+    ColorSettings, ColorSpace, ControlScheme, DepthFormat, EngineUpdates, Entity, EntityGroup,
+    FaceCulling, GraphicsSettings, InputSettings, Instance, LightingFactors, Mesh, Palette,
+    PresentMode, RenderProps, Scene, Transform, UiLayout, UiSettings, Units, Vertex, INSTANCE_SIZE,
 };
+pub use views::ExtraView;
+// Re-export the math library, so applications use the same version as the engine. See the `math`
+// module for its types, and operations it doesn't provide.
+pub use lin_alg;
 // Re-export winit DeviceEvents for use in the API; this prevents the calling
 // lib from needing to use winit as a dependency directly.
 // todo: the equiv for mouse events too. And in the future, Gamepad events.
 pub use winit::{
     self,
-    event::{self, DeviceEvent, ElementState},
+    event::{self, DeviceEvent, ElementState, MouseButton},
+    keyboard::KeyCode,
 };
@@ -0,0 +1,61 @@
+//! The window's lifecycle state, eg whether it's focused or minimized, so applications can react,
+//! eg by pausing a simulation while the window is in the background. The engine keeps
+//! `Scene::window_state` current, and lists the changes since the previous frame in its `events`.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LifecycleEvent {
+    /// The window gained (true) or lost (false) keyboard focus.
+    Focused(bool),
+    /// The window became hidden (true), eg minimized, or fully covered by other windows, or
+    /// visible again (false). Not all platforms report this.
+    Occluded(bool),
+    /// The window moved. The new position of its top left corner, in pixels.
+    Moved(i32, i32),
+    /// The window's new size, in pixels. This is 0 by 0 when minimized on some platforms.
+    Resized(u32, u32),
+}
+
+#[derive(Clone, Debug)]
+/// See the `lifecycle` module. Set by the engine.
+pub struct WindowState {
+    pub focused: bool,
+    pub occluded: bool,
+    /// The position of the window's top left corner, in pixels.
+    pub position: (i32, i32),
+    /// The size of the window's drawable area, in pixels.
+    pub size: (u32, u32),
+    /// Changes since the previous frame, in order. These are cleared after each frame, so each
+    /// handler sees them once.
+    pub events: Vec<LifecycleEvent>,
+}
+
+impl Default for WindowState {
+    fn default() -> Self {
+        Self {
+            focused: true,
+            occluded: false,
+            position: (0, 0),
+            size: (0, 0),
+            events: Vec::new(),
+        }
+    }
+}
+
+impl WindowState {
+    /// False if the window is hidden, or minimized; rendering to it has no visible effect.
+    pub fn visible(&self) -> bool {
+        !self.occluded && self.size.0 > 0 && self.size.1 > 0
+    }
+
+    /// Record a change, updating the state.
+    pub(crate) fn push(&mut self, event: LifecycleEvent) {
+        match event {
+            LifecycleEvent::Focused(focused) => self.focused = focused,
+            LifecycleEvent::Occluded(occluded) => self.occluded = occluded,
+            LifecycleEvent::Moved(x, y) => self.position = (x, y),
+            LifecycleEvent::Resized(width, height) => self.size = (width, height),
+        }
+
+        self.events.push(event);
+    }
+}
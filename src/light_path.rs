@@ -0,0 +1,143 @@
+//! Moving point lights along paths, eg for lighting demos, or to show dynamic shadows. Add paths
+//! to `Scene::light_paths`; each frame, the engine advances them, and sets their lights' positions.
+//! Paths are applied after the timeline, so they take precedence over its light keyframes.
+
+use core::f32::consts::TAU;
+
+use lin_alg::f32::{Quaternion, Vec3};
+
+use crate::{graphics::UP_VEC, lighting::PointLight};
+
+#[derive(Clone, Debug)]
+pub enum PathShape {
+    /// A circle around `center`, in the plane perpendicular to `axis`. Positive speeds are
+    /// counter-clockwise, looking down the axis.
+    Circle {
+        center: Vec3,
+        axis: Vec3,
+        radius: f32,
+    },
+    /// A closed Catmull-Rom spline through these points, in order, returning from the last to the
+    /// first. Each segment between points takes the same time, regardless of its length.
+    Spline(Vec<Vec3>),
+}
+
+#[derive(Clone, Debug)]
+/// Moves a point light along a path. One trip around the path is `TAU` radians, for both circles
+/// and splines.
+pub struct LightPath {
+    /// Index in `Lighting::point_lights`. Paths referencing lights that don't exist are ignored.
+    pub light: usize,
+    pub shape: PathShape,
+    /// In radians per second. Negative values move the other way.
+    pub speed: f32,
+    /// The position along the path, in radians, from 0 to `TAU`. Advanced by the engine.
+    pub angle: f32,
+}
+
+impl LightPath {
+    /// Circle `center` around the up axis.
+    pub fn circle(light: usize, center: Vec3, radius: f32, speed: f32) -> Self {
+        Self {
+            light,
+            shape: PathShape::Circle {
+                center,
+                axis: UP_VEC,
+                radius,
+            },
+            speed,
+            angle: 0.,
+        }
+    }
+
+    /// Loop through `waypoints`.
+    pub fn spline(light: usize, waypoints: Vec<Vec3>, speed: f32) -> Self {
+        Self {
+            light,
+            shape: PathShape::Spline(waypoints),
+            speed,
+            angle: 0.,
+        }
+    }
+
+    /// The position at the current angle; `None` for a spline without points.
+    pub fn position(&self) -> Option<Vec3> {
+        match &self.shape {
+            PathShape::Circle {
+                center,
+                axis,
+                radius,
+            } => {
+                if axis.magnitude_squared() == 0. {
+                    return Some(*center);
+                }
+                let axis = axis.to_normalized();
+
+                // Any direction perpendicular to the axis; this is where the circle starts.
+                let reference = if axis.x.abs() < 0.9 {
+                    Vec3::new(1., 0., 0.)
+                } else {
+                    Vec3::new(0., 1., 0.)
+                };
+                let start = axis.cross(reference).to_normalized() * *radius;
+
+                Some(*center + Quaternion::from_axis_angle(axis, self.angle).rotate_vec(start))
+            }
+            PathShape::Spline(points) => spline_point(points, self.angle / TAU),
+        }
+    }
+
+    /// Advance by `dt` seconds.
+    fn advance(&mut self, dt: f32) {
+        self.angle = (self.angle + self.speed * dt).rem_euclid(TAU);
+    }
+}
+
+/// A point on a closed, uniform Catmull-Rom spline through `points`. `t` is from 0 to 1 around
+/// the loop.
+fn spline_point(points: &[Vec3], t: f32) -> Option<Vec3> {
+    let n = points.len();
+    if n == 0 {
+        return None;
+    }
+
+    let t = t.rem_euclid(1.) * n as f32;
+    let i = (t as usize).min(n - 1);
+    let f = t - i as f32;
+
+    let p0 = points[(i + n - 1) % n];
+    let p1 = points[i];
+    let p2 = points[(i + 1) % n];
+    let p3 = points[(i + 2) % n];
+
+    let f2 = f * f;
+    let f3 = f2 * f;
+
+    Some(
+        (p1 * 2.
+            + (p2 - p0) * f
+            + (p0 * 2. - p1 * 5. + p2 * 4. - p3) * f2
+            + (p1 * 3. - p0 - p2 * 3. + p3) * f3)
+            * 0.5,
+    )
+}
+
+/// Advance each path by `dt` seconds, and move its light. Returns true if any light moved, in
+/// which case lighting must be updated.
+pub(crate) fn update(paths: &mut [LightPath], lights: &mut [PointLight], dt: f32) -> bool {
+    let mut moved = false;
+
+    for path in paths {
+        path.advance(dt);
+
+        let Some(light) = lights.get_mut(path.light) else {
+            continue;
+        };
+        if let Some(position) = path.position() {
+            light.position = position;
+            moved = true;
+        }
+    }
+
+    moved
+}
@@ -4,11 +4,14 @@ use crate::types::{F32_SIZE, VEC3_UNIFORM_SIZE};
 
 // The extra 4 is due to uniform (and storage) buffers needing ton be a multiple of 16 in size.
 // This is for the non-array portion of the lighting uniform.
-// The extra 12 is for padding.
-pub const LIGHTING_SIZE_FIXED: usize = VEC3_UNIFORM_SIZE + F32_SIZE + 4 + 8;
+// The extra 4 is for padding.
+pub const LIGHTING_SIZE_FIXED: usize = VEC3_UNIFORM_SIZE + 2 * F32_SIZE + 4 + 4;
 
-// The extra 8 here for the same reason.
-pub const POINT_LIGHT_SIZE: usize = 3 * VEC3_UNIFORM_SIZE + 2 * F32_SIZE + 8;
+// The extra 4 here for the same reason.
+pub const POINT_LIGHT_SIZE: usize = 3 * VEC3_UNIFORM_SIZE + 3 * F32_SIZE + 4;
+
+// The location of the shadow map index in the point light data.
+const SHADOW_I_START: usize = 3 * VEC3_UNIFORM_SIZE + 2 * F32_SIZE;
 
 // Note: These array-to-bytes functions may have broader use than in this lighting module.
 
@@ -46,14 +49,22 @@ impl Default for Lighting {
                 specular_color: [1., 1., 1., 0.5],
                 diffuse_intensity: 100.,
                 specular_intensity: 100.,
+                casts_shadow: false,
             }],
         }
     }
 }
 
 impl Lighting {
-    /// We use a vec due to the dynamic size of `point_lights`.
-    pub fn to_bytes(&self) -> Vec<u8> {
+    /// We use a vec due to the dynamic size of `point_lights`. Distances are divided by
+    /// `falloff_scale` before applying inverse-square falloff; this is the scene's scale factor.
+    pub fn to_bytes(&self, falloff_scale: f32) -> Vec<u8> {
+        self.to_bytes_with(&[], falloff_scale)
+    }
+
+    /// As `to_bytes`, with `extra` lights after `point_lights`, eg from area light proxies. These
+    /// don't cast shadows.
+    pub(crate) fn to_bytes_with(&self, extra: &[PointLight], falloff_scale: f32) -> Vec<u8> {
         let mut result = Vec::new();
 
         let mut buf_fixed_size = [0; LIGHTING_SIZE_FIXED];
@@ -65,24 +76,60 @@ impl Lighting {
 
         // We pass size manually, due to trouble getting the array len in the shader.
         buf_fixed_size[VEC3_UNIFORM_SIZE + F32_SIZE..VEC3_UNIFORM_SIZE + F32_SIZE + 4]
-            .clone_from_slice(&(self.point_lights.len() as i32).to_le_bytes());
+            .clone_from_slice(&((self.point_lights.len() + extra.len()) as i32).to_le_bytes());
+
+        buf_fixed_size[VEC3_UNIFORM_SIZE + F32_SIZE + 4..VEC3_UNIFORM_SIZE + 2 * F32_SIZE + 4]
+            .clone_from_slice(&falloff_scale.to_ne_bytes());
 
         // Pad to a multiple of 16.
-        buf_fixed_size[VEC3_UNIFORM_SIZE + F32_SIZE + 4..LIGHTING_SIZE_FIXED]
-            .clone_from_slice(&[0; 8]);
+        buf_fixed_size[VEC3_UNIFORM_SIZE + 2 * F32_SIZE + 4..LIGHTING_SIZE_FIXED]
+            .clone_from_slice(&[0; 4]);
 
         for byte in buf_fixed_size.into_iter() {
             result.push(byte);
         }
 
+        // Shadow maps are assigned to shadow-casting lights in order. The shader ignores indices
+        // past the number of shadow maps.
+        let mut shadow_i: i32 = 0;
+
         for light in &self.point_lights {
-            for byte in light.to_bytes().into_iter() {
+            let mut light_bytes = light.to_bytes();
+
+            if light.casts_shadow {
+                light_bytes[SHADOW_I_START..SHADOW_I_START + 4]
+                    .clone_from_slice(&shadow_i.to_ne_bytes());
+                shadow_i += 1;
+            }
+
+            for byte in light_bytes.into_iter() {
                 result.push(byte)
             }
         }
 
+        for light in extra {
+            result.extend_from_slice(&light.to_bytes());
+        }
+
         result
     }
+
+    /// A single light's data, as serialized by `to_bytes`. Returns `None` if out of bounds.
+    pub fn light_bytes(&self, i: usize) -> Option<[u8; POINT_LIGHT_SIZE]> {
+        let light = self.point_lights.get(i)?;
+        let mut result = light.to_bytes();
+
+        if light.casts_shadow {
+            let shadow_i = self.point_lights[..i]
+                .iter()
+                .filter(|l| l.casts_shadow)
+                .count() as i32;
+
+            result[SHADOW_I_START..SHADOW_I_START + 4].clone_from_slice(&shadow_i.to_ne_bytes());
+        }
+
+        Some(result)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -97,12 +144,16 @@ pub struct PointLight {
     // A point light source
     pub type_: LightType,
     pub position: Vec3,
+    /// As with entity colors, these are interpreted according to `ColorSettings::input_space`.
     pub diffuse_color: [f32; 4],
     pub specular_color: [f32; 4],
     pub diffuse_intensity: f32,
     pub specular_intensity: f32,
+    /// If true, this light casts shadows, using a cube shadow map. The number of shadow-casting
+    /// lights is limited by `GraphicsSettings::max_shadow_lights`; additional ones don't cast
+    /// shadows.
+    pub casts_shadow: bool,
     // todo: FOV, and direction?
-    // shadow_map
 }
 
 impl PointLight {
@@ -125,7 +176,10 @@ impl PointLight {
         result[3 * VEC3_UNIFORM_SIZE + F32_SIZE..3 * VEC3_UNIFORM_SIZE + 2 * F32_SIZE]
             .clone_from_slice(&self.specular_intensity.to_ne_bytes());
 
-        result[3 * VEC3_UNIFORM_SIZE + 2 * F32_SIZE..POINT_LIGHT_SIZE].clone_from_slice(&[0; 8]);
+        // The shadow map index is set by `Lighting::to_bytes`; -1 means no shadow.
+        result[SHADOW_I_START..SHADOW_I_START + 4].clone_from_slice(&(-1_i32).to_ne_bytes());
+
+        result[SHADOW_I_START + 4..POINT_LIGHT_SIZE].clone_from_slice(&[0; 4]);
 
         result
     }
@@ -0,0 +1,24 @@
+// The lighting storage buffer, bound at `@group(1) @binding(0)` in the main shader. Written
+// from `Lighting::to_bytes`.
+
+struct PointLight {
+    position: vec4<f32>,
+    diffuse_color: vec4<f32>,
+    specular_color: vec4<f32>,
+    diffuse_intensity: f32,
+    specular_intensity: f32,
+    // Index into the shadow maps; -1 if this light doesn't cast shadows.
+    shadow_i: i32,
+}
+
+// Note: Don't us vec3 in uniforms due to alignment issues.
+struct Lighting {
+    ambient_color: vec4<f32>,
+    ambient_intensity: f32,
+    // We use this as a workaround for array len not working.
+    lights_len: i32,
+    // Distances are divided by this for falloff, so lights reach as far at any scene scale.
+    falloff_scale: f32,
+    point_lights: array<PointLight>
+}
+
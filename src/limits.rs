@@ -0,0 +1,117 @@
+//! Device limits on buffer sizes. wgpu's default limits allow buffers of 256 MiB, and storage
+//! bindings of 128 MiB, which large scenes can exceed, eg with millions of instances. We request
+//! the device with limits that fit `GraphicsSettings::buffer_size_hint`, up to what the GPU
+//! supports, and check buffers against the device's limits, vice failing validation:
+//!
+//! - Instances must fit in the instance buffer, and, with TAA, their previous model matrices in a
+//!   storage binding. Point lights must fit in a storage binding. If they don't, they aren't
+//!   uploaded, so the previous instances or lights are drawn, and the error is reported in
+//!   `Scene::buffer_errors`.
+//! - GPU picking binds the instance buffer as storage in ranges that each fit a binding.
+//! - Occlusion culling is skipped while the instance buffer is larger than a storage binding, and
+//!   culling dispatches are split to stay under the workgroup count limit.
+//! - Compute passes binding the instance or vertex buffer see as much of it as fits in a storage
+//!   binding.
+
+use std::ops::Range;
+
+use wgpu::{Adapter, Buffer, BufferBinding, Device, Limits};
+
+/// The limits to request: wgpu's defaults, raised to fit buffers of `buffer_size_hint` bytes, up
+/// to the adapter's. We also request all available bind groups, for shader extensions.
+pub(crate) fn required_limits(adapter: &Adapter, buffer_size_hint: u64) -> Limits {
+    let supported = adapter.limits();
+    let defaults = Limits::default();
+
+    let max_buffer_size = buffer_size_hint
+        .min(supported.max_buffer_size)
+        .max(defaults.max_buffer_size);
+
+    let max_storage_binding = buffer_size_hint
+        .min(supported.max_storage_buffer_binding_size as u64)
+        .max(defaults.max_storage_buffer_binding_size as u64);
+
+    Limits {
+        max_bind_groups: supported.max_bind_groups,
+        max_buffer_size,
+        max_storage_buffer_binding_size: max_storage_binding as u32,
+        ..defaults
+    }
+}
+
+/// The largest storage binding, in bytes.
+pub(crate) fn max_storage_size(device: &Device) -> u64 {
+    let limits = device.limits();
+    (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size)
+}
+
+/// The number of items of `item_size` bytes that fit in `max_size` bytes, after a header of
+/// `header_size`.
+fn items_that_fit(max_size: u64, header_size: usize, item_size: usize) -> usize {
+    (max_size.saturating_sub(header_size as u64) / item_size as u64) as usize
+}
+
+/// An error if `count` items of `item_size` bytes, after a header of `header_size`, don't fit in
+/// `max_size` bytes. `name` describes the items, eg "point lights".
+pub(crate) fn check_fits(
+    name: &str,
+    count: usize,
+    header_size: usize,
+    item_size: usize,
+    max_size: u64,
+) -> Result<(), String> {
+    let max = items_that_fit(max_size, header_size, item_size);
+    if count > max {
+        return Err(format!(
+            "{count} {name} don't fit in the device's buffer limits, which allow {max}. Raise \
+             `GraphicsSettings::buffer_size_hint`, up to what the GPU supports."
+        ));
+    }
+    Ok(())
+}
+
+/// Split `count` items of `item_size` bytes into ranges that each fit in a storage binding, and
+/// start at an offset storage bindings may start at.
+pub(crate) fn binding_ranges(device: &Device, count: usize, item_size: usize) -> Vec<Range<usize>> {
+    let alignment = device.limits().min_storage_buffer_offset_alignment as usize;
+
+    // Ranges start at multiples of this many items: the fewest whose size is a multiple of the
+    // alignment.
+    let mut step = alignment;
+    while !step.is_multiple_of(item_size) {
+        step += alignment;
+    }
+    let step = step / item_size;
+
+    let per_range = items_that_fit(max_storage_size(device), 0, item_size) / step * step;
+    let per_range = per_range.max(step);
+
+    (0..count)
+        .step_by(per_range)
+        .map(|start| start..(start + per_range).min(count))
+        .collect()
+}
+
+/// A storage binding of `range` of `buf`, in bytes.
+pub(crate) fn storage_range(buf: &Buffer, range: Range<u64>) -> wgpu::BindingResource<'_> {
+    wgpu::BindingResource::Buffer(BufferBinding {
+        buffer: buf,
+        offset: range.start,
+        size: wgpu::BufferSize::new(range.end - range.start),
+    })
+}
+
+/// A storage binding of `buf`, truncated to the storage binding limit if it's larger.
+pub(crate) fn storage_binding<'a>(device: &Device, buf: &'a Buffer) -> wgpu::BindingResource<'a> {
+    let max = max_storage_size(device);
+    if buf.size() <= max {
+        return buf.as_entire_binding();
+    }
+
+    // Binding sizes must be a multiple of 4.
+    wgpu::BindingResource::Buffer(BufferBinding {
+        buffer: buf,
+        offset: 0,
+        size: wgpu::BufferSize::new(max / 4 * 4),
+    })
+}
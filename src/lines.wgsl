@@ -0,0 +1,52 @@
+// Debug lines, eg light gizmos. These are drawn in the main render pass, after meshes.
+
+#include "camera"
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct VertexIn {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+struct VertexOut {
+    @builtin(position) clip_posit: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) curr_clip: vec4<f32>,
+    @location(2) prev_clip: vec4<f32>,
+}
+
+@vertex
+fn vs_main(vertex_in: VertexIn) -> VertexOut {
+    var posit = vec4<f32>(vertex_in.position, 1.);
+    var curr_clip = camera.proj_view * posit;
+
+    var result: VertexOut;
+    // Jitter to match meshes when using TAA, so depth testing is consistent.
+    result.clip_posit = curr_clip + vec4<f32>(camera.jitter.xy * curr_clip.w, 0., 0.);
+    result.color = vertex_in.color;
+    result.curr_clip = curr_clip;
+    result.prev_clip = camera.prev_proj_view * posit;
+
+    return result;
+}
+
+@fragment
+fn fs_main(vertex: VertexOut) -> @location(0) vec4<f32> {
+    return vertex.color;
+}
+
+struct FragOutTaa {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec2<f32>,
+}
+
+@fragment
+fn fs_main_taa(vertex: VertexOut) -> FragOutTaa {
+    var result: FragOutTaa;
+    result.color = vertex.color;
+    result.velocity = vertex.curr_clip.xy / vertex.curr_clip.w - vertex.prev_clip.xy / vertex.prev_clip.w;
+
+    return result;
+}
@@ -0,0 +1,239 @@
+//! Asynchronous asset loading. Files are read and parsed on worker threads, so large meshes and
+//! images don't freeze the window. Start loads with `EngineUpdates::load_assets`; the engine adds
+//! finished meshes and materials to the scene on the main thread, and reports progress, finished
+//! assets, and failures in `Scene::load_events`.
+
+use std::{
+    fs::File,
+    io::Read,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+use crate::{
+    material::{Material, MaterialImage},
+    meshes::NormalMode,
+    types::{EngineUpdates, Mesh, Scene},
+};
+
+/// We read files in chunks of this size, reporting progress after each.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// The portion of progress attributed to reading the file; the remainder is parsing.
+const READ_PROGRESS_PORTION: f32 = 0.9;
+
+/// Identifies a load request. Chosen by the application, and included in the request's events.
+pub type AssetId = usize;
+
+/// Reported in `Scene::load_events`, for the frame after it occurs.
+#[derive(Clone, Debug)]
+pub enum LoadEvent {
+    /// `fraction` is from 0 to 1.
+    Progress {
+        id: AssetId,
+        fraction: f32,
+    },
+    /// The mesh has been added to the end of `Scene::meshes`, at `mesh_i`.
+    MeshLoaded {
+        id: AssetId,
+        mesh_i: usize,
+    },
+    /// The material has been added to the end of `Scene::materials`, at `material_i`.
+    MaterialLoaded {
+        id: AssetId,
+        material_i: usize,
+    },
+    /// The image is decoded, and ready for use by the application.
+    ImageLoaded {
+        id: AssetId,
+        image: image::DynamicImage,
+    },
+    /// The file couldn't be read or parsed, or its worker thread panicked.
+    Failed {
+        id: AssetId,
+        error: String,
+    },
+}
+
+/// Sent from worker threads.
+enum Msg {
+    Progress(AssetId, f32),
+    Mesh(AssetId, Mesh),
+    Material(AssetId, Material),
+    Image(AssetId, image::DynamicImage),
+    Failed(AssetId, String),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum AssetKind {
+    /// If normals are set, we generate them instead of using the file's.
+    MeshObj(Option<NormalMode>),
+    Material,
+    Image,
+}
+
+/// A file to load in the background. Add these to `EngineUpdates::load_assets`.
+#[derive(Clone, Debug)]
+pub struct AssetRequest {
+    pub id: AssetId,
+    pub path: PathBuf,
+    kind: AssetKind,
+}
+
+impl AssetRequest {
+    /// Load a mesh from an obj file.
+    pub fn obj(id: AssetId, path: impl AsRef<Path>) -> Self {
+        Self::new(id, path, AssetKind::MeshObj(None))
+    }
+
+    /// Load a mesh from an obj file, generating its normals. Normals in the file are optional.
+    pub fn obj_with_normals(id: AssetId, path: impl AsRef<Path>, normals: NormalMode) -> Self {
+        Self::new(id, path, AssetKind::MeshObj(Some(normals)))
+    }
+
+    /// Load a material texture. KTX2 and DDS files are loaded as compressed textures; other
+    /// formats are loaded with the `image` crate. See `Material`.
+    pub fn material(id: AssetId, path: impl AsRef<Path>) -> Self {
+        Self::new(id, path, AssetKind::Material)
+    }
+
+    /// Load an image, eg a texture. Any format supported by the `image` crate may be used.
+    pub fn image(id: AssetId, path: impl AsRef<Path>) -> Self {
+        Self::new(id, path, AssetKind::Image)
+    }
+
+    fn new(id: AssetId, path: impl AsRef<Path>, kind: AssetKind) -> Self {
+        Self {
+            id,
+            path: path.as_ref().to_owned(),
+            kind,
+        }
+    }
+}
+
+/// Runs loads on worker threads, and passes their results to the render thread.
+pub(crate) struct AssetLoader {
+    tx: Sender<Msg>,
+    rx: Receiver<Msg>,
+    pending: usize,
+}
+
+impl Default for AssetLoader {
+    fn default() -> Self {
+        let (tx, rx) = mpsc::channel();
+
+        Self { tx, rx, pending: 0 }
+    }
+}
+
+impl AssetLoader {
+    /// Process messages from worker threads. Finished meshes and materials are appended to
+    /// `scene.meshes` and `scene.materials`, and events are added to `scene.load_events`. Returns
+    /// the updates these require.
+    pub fn poll(&mut self, scene: &mut Scene) -> EngineUpdates {
+        let mut result = EngineUpdates::default();
+
+        while let Ok(msg) = self.rx.try_recv() {
+            let event = match msg {
+                Msg::Progress(id, fraction) => LoadEvent::Progress { id, fraction },
+                Msg::Mesh(id, mesh) => {
+                    self.pending -= 1;
+
+                    scene.meshes.push(mesh);
+                    result.meshes = true;
+
+                    LoadEvent::MeshLoaded {
+                        id,
+                        mesh_i: scene.meshes.len() - 1,
+                    }
+                }
+                Msg::Material(id, material) => {
+                    self.pending -= 1;
+
+                    scene.materials.push(material);
+                    result.materials = true;
+
+                    LoadEvent::MaterialLoaded {
+                        id,
+                        material_i: scene.materials.len() - 1,
+                    }
+                }
+                Msg::Image(id, image) => {
+                    self.pending -= 1;
+                    LoadEvent::ImageLoaded { id, image }
+                }
+                Msg::Failed(id, error) => {
+                    self.pending -= 1;
+                    LoadEvent::Failed { id, error }
+                }
+            };
+
+            scene.load_events.push(event);
+        }
+
+        scene.loads_pending = self.pending;
+
+        result
+    }
+
+    pub fn start(&mut self, request: &AssetRequest) {
+        self.pending += 1;
+
+        let AssetRequest { id, path, kind } = request.clone();
+        let tx = self.tx.clone();
+
+        thread::spawn(move || {
+            // A panic, eg in a decoder, still reports a failure, so the load doesn't stay pending.
+            let msg = match panic::catch_unwind(AssertUnwindSafe(|| load(&path, kind, id, &tx))) {
+                Ok(Ok(msg)) => msg,
+                Ok(Err(e)) => Msg::Failed(id, format!("{}: {e}", path.display())),
+                Err(_) => Msg::Failed(id, format!("{}: the loader panicked", path.display())),
+            };
+            // The engine may have exited; there's nobody to notify in that case.
+            let _ = tx.send(msg);
+        });
+    }
+}
+
+/// Runs on a worker thread. Reads the file in chunks, reporting progress, then parses it.
+fn load(path: &Path, kind: AssetKind, id: AssetId, tx: &Sender<Msg>) -> Result<Msg, String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let len = file.metadata().map(|m| m.len() as usize).unwrap_or(0);
+
+    let mut data = Vec::with_capacity(len);
+    let mut chunk = vec![0; CHUNK_SIZE];
+
+    loop {
+        let n = file.read(&mut chunk).map_err(|e| e.to_string())?;
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&chunk[..n]);
+
+        if len > 0 {
+            let fraction = data.len() as f32 / len as f32 * READ_PROGRESS_PORTION;
+            let _ = tx.send(Msg::Progress(id, fraction.min(READ_PROGRESS_PORTION)));
+        }
+    }
+
+    let result = match kind {
+        AssetKind::MeshObj(None) => Msg::Mesh(id, Mesh::from_obj_bytes(&data)?),
+        AssetKind::MeshObj(Some(normals)) => {
+            Msg::Mesh(id, Mesh::from_obj_bytes_with_normals(&data, normals)?)
+        }
+        AssetKind::Material => {
+            let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+            Msg::Material(id, Material::new(MaterialImage::decode(&data, ext)?))
+        }
+        AssetKind::Image => Msg::Image(
+            id,
+            image::load_from_memory(&data).map_err(|e| e.to_string())?,
+        ),
+    };
+
+    let _ = tx.send(Msg::Progress(id, 1.));
+
+    Ok(result)
+}
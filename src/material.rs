@@ -0,0 +1,630 @@
+//! Textured materials for entities. Many entities may each use a different small texture, eg
+//! icons or labels; instead of a bind group and draw call per texture, we pack material textures
+//! into layers of a single texture array. Each instance stores its layer, so entities sharing a
+//! mesh are still drawn together, with one draw call.
+//!
+//! Layers share a size: the largest material texture's, limited by the device. Smaller textures
+//! are stretched to fit, so UVs from 0 to 1 cover the whole texture regardless.
+//!
+//! If all materials are block-compressed, with the same format and size, and the device supports
+//! it, we upload them without decompressing, with their mip chains. Otherwise, we decompress them.
+//!
+//! Normal maps are packed the same way, into a second texture array, sized to the largest normal
+//! map. They're always decompressed. Materials without one use a flat layer.
+//!
+//! All layers share one sampler, configured by `GraphicsSettings::material_sampler`.
+
+use std::{fs, path::Path};
+
+use image::{imageops::FilterType, RgbaImage};
+use wgpu::{
+    AddressMode, Device, Extent3d, Features, FilterMode, Queue, Sampler, SamplerDescriptor,
+    Texture, TextureDescriptor, TextureFormat, TextureView,
+};
+
+use crate::{compressed::CompressedImage, texture};
+
+/// The RGBA of a flat normal map layer: a normal of +Z in tangent space.
+const FLAT_NORMAL: [u8; 4] = [128, 128, 255, 255];
+
+#[derive(Clone, Debug)]
+/// The texture of a material, or its normal map.
+pub enum MaterialImage {
+    /// RGBA, with 8 bits per channel, row by row from the top left. Colors are sRGB-encoded;
+    /// normal map components aren't.
+    Rgba8 {
+        width: u32,
+        height: u32,
+        data: Vec<u8>,
+    },
+    /// Block-compressed, eg from a KTX2 or DDS file.
+    Compressed(CompressedImage),
+}
+
+impl MaterialImage {
+    /// Decode an image file's contents. `extension` selects the format: KTX2 and DDS files are
+    /// kept compressed; others are decoded with the `image` crate, eg PNG.
+    pub(crate) fn decode(bytes: &[u8], extension: &str) -> Result<Self, String> {
+        Ok(match extension.to_lowercase().as_str() {
+            "ktx2" => Self::Compressed(CompressedImage::from_ktx2(bytes)?),
+            "dds" => Self::Compressed(CompressedImage::from_dds(bytes)?),
+            _ => {
+                let (width, height, data) =
+                    texture::decode_rgba8(bytes).map_err(|e| e.to_string())?;
+
+                Self::Rgba8 {
+                    width,
+                    height,
+                    data,
+                }
+            }
+        })
+    }
+
+    /// Load from an image file, with its format from its extension; see `decode`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+
+        Self::decode(&bytes, ext)
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        match self {
+            Self::Rgba8 { width, height, .. } => (*width, *height),
+            Self::Compressed(img) => (img.width, img.height),
+        }
+    }
+
+    /// RGBA, scaled to a layer's size. If `data` doesn't match the dimensions, we use `fill`.
+    fn layer_data(&self, width: u32, height: u32, fill: [u8; 4]) -> Vec<u8> {
+        let (w, h) = self.dimensions();
+        let data = match self {
+            Self::Rgba8 { data, .. } => data.clone(),
+            Self::Compressed(img) => img.decompress(),
+        };
+
+        let Some(img) = RgbaImage::from_raw(w, h, data) else {
+            return fill.repeat((width * height) as usize);
+        };
+
+        if img.dimensions() == (width, height) {
+            return img.into_raw();
+        }
+
+        image::imageops::resize(&img, width, height, FilterType::Triangle).into_raw()
+    }
+}
+
+#[derive(Clone, Debug)]
+/// A texture entities can reference by index; see `Entity::material`, and `Mesh::material`. Its
+/// colors multiply the entity's color, using the mesh's texture coordinates.
+pub struct Material {
+    /// The albedo map.
+    pub image: MaterialImage,
+    /// Perturbs surface normals, in tangent space, eg for bumps and grooves without extra
+    /// geometry. Green points up in the image, ie towards lower V. The blue component is ignored;
+    /// it's reconstructed from red and green, so two-channel BC5 maps work. This requires vertex
+    /// tangents; see `Mesh::generate_tangents`.
+    pub normal_map: Option<MaterialImage>,
+    /// If set, this replaces the shinyness of entities using this material, from 0 (rough) to 1
+    /// (smooth). See `Entity::shinyness`.
+    pub shinyness: Option<f32>,
+}
+
+impl Material {
+    /// A material with only an albedo map.
+    pub fn new(image: MaterialImage) -> Self {
+        Self {
+            image,
+            normal_map: None,
+            shinyness: None,
+        }
+    }
+
+    /// Load from an image file, eg PNG, KTX2, or DDS, with its format from its extension.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        Ok(Self::new(MaterialImage::from_file(path)?))
+    }
+
+    /// Load from an image file's contents, eg PNG.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, image::ImageError> {
+        let (width, height, data) = texture::decode_rgba8(bytes)?;
+
+        Ok(Self::new(MaterialImage::Rgba8 {
+            width,
+            height,
+            data,
+        }))
+    }
+
+    /// Load from a KTX2 file's contents, using BC1, BC3, BC5, or BC7 compression.
+    pub fn from_ktx2(bytes: &[u8]) -> Result<Self, String> {
+        Ok(Self::new(MaterialImage::Compressed(
+            CompressedImage::from_ktx2(bytes)?,
+        )))
+    }
+
+    /// Load from a DDS file's contents, using BC1, BC3, BC5, or BC7 compression.
+    pub fn from_dds(bytes: &[u8]) -> Result<Self, String> {
+        Ok(Self::new(MaterialImage::Compressed(
+            CompressedImage::from_dds(bytes)?,
+        )))
+    }
+
+    /// Add a normal map, loaded from an image file; see `normal_map`.
+    pub fn with_normal_map(mut self, path: impl AsRef<Path>) -> Result<Self, String> {
+        self.normal_map = Some(MaterialImage::from_file(path)?);
+        Ok(self)
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    /// RGBA, scaled to a layer's size. If `data` doesn't match the dimensions, we use white.
+    fn layer_data(&self, width: u32, height: u32) -> Vec<u8> {
+        self.image.layer_data(width, height, [255; 4])
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+/// How texels are blended when sampling a texture.
+pub enum TextureFilter {
+    /// Use the nearest texel, eg for pixel art.
+    Nearest,
+    /// Blend between neighboring texels.
+    #[default]
+    Linear,
+}
+
+impl TextureFilter {
+    fn filter_mode(self) -> FilterMode {
+        match self {
+            Self::Nearest => FilterMode::Nearest,
+            Self::Linear => FilterMode::Linear,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+/// How texture coordinates outside 0 to 1 are handled.
+pub enum TextureAddress {
+    /// Tile the texture.
+    #[default]
+    Repeat,
+    /// Tile the texture, flipping every other tile.
+    MirrorRepeat,
+    /// Use the color at the nearest edge.
+    ClampToEdge,
+    /// Transparent outside the texture. This requires the `ADDRESS_MODE_CLAMP_TO_BORDER`
+    /// feature; if the GPU doesn't support it, we use `ClampToEdge`.
+    ClampToBorder,
+}
+
+impl TextureAddress {
+    fn address_mode(self, features: Features) -> AddressMode {
+        match self {
+            Self::Repeat => AddressMode::Repeat,
+            Self::MirrorRepeat => AddressMode::MirrorRepeat,
+            Self::ClampToEdge => AddressMode::ClampToEdge,
+            Self::ClampToBorder => {
+                if features.contains(Features::ADDRESS_MODE_CLAMP_TO_BORDER) {
+                    AddressMode::ClampToBorder
+                } else {
+                    AddressMode::ClampToEdge
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// How material textures are sampled; see `GraphicsSettings::material_sampler`.
+pub struct SamplerSettings {
+    /// Filtering when a texel covers more than one pixel, eg up close.
+    pub mag_filter: TextureFilter,
+    /// Filtering when a pixel covers more than one texel, eg far away.
+    pub min_filter: TextureFilter,
+    /// Filtering between mip levels. Only compressed materials with mip chains have more than one.
+    pub mipmap_filter: TextureFilter,
+    pub address_u: TextureAddress,
+    pub address_v: TextureAddress,
+    /// The maximum anisotropy, from 1 (disabled) to 16, eg 8. Higher values keep textures
+    /// sharp when viewed at glancing angles, at some cost. This requires all filters to be
+    /// `Linear`, and is ignored otherwise, or if the GPU doesn't support it.
+    pub anisotropy: u16,
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            mag_filter: TextureFilter::Linear,
+            min_filter: TextureFilter::Linear,
+            mipmap_filter: TextureFilter::Linear,
+            address_u: TextureAddress::Repeat,
+            address_v: TextureAddress::Repeat,
+            anisotropy: 1,
+        }
+    }
+}
+
+impl SamplerSettings {
+    /// Replaces settings the device doesn't support, or that are invalid together.
+    fn descriptor(&self, features: Features) -> SamplerDescriptor<'static> {
+        let address_mode_u = self.address_u.address_mode(features);
+        let address_mode_v = self.address_v.address_mode(features);
+
+        let border_color = if address_mode_u == AddressMode::ClampToBorder
+            || address_mode_v == AddressMode::ClampToBorder
+        {
+            Some(wgpu::SamplerBorderColor::TransparentBlack)
+        } else {
+            None
+        };
+
+        let filters = [self.mag_filter, self.min_filter, self.mipmap_filter];
+        let anisotropy_clamp = if filters.iter().all(|f| *f == TextureFilter::Linear) {
+            self.anisotropy.clamp(1, 16)
+        } else {
+            1
+        };
+
+        SamplerDescriptor {
+            label: Some("Material sampler"),
+            address_mode_u,
+            address_mode_v,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: self.mag_filter.filter_mode(),
+            min_filter: self.min_filter.filter_mode(),
+            mipmap_filter: self.mipmap_filter.filter_mode(),
+            anisotropy_clamp,
+            border_color,
+            ..Default::default()
+        }
+    }
+}
+
+/// Material textures, packed into one texture array, and normal maps, packed into another.
+pub(crate) struct MaterialTextures {
+    texture: Texture,
+    pub view: TextureView,
+    pub normal_view: TextureView,
+    pub sampler: Sampler,
+    /// Set if `Scene::materials` haven't been uploaded yet.
+    pub stale: bool,
+}
+
+impl MaterialTextures {
+    /// A single blank layer, used until materials are uploaded.
+    pub fn placeholder(device: &Device, sampler: &SamplerSettings) -> Self {
+        let (_, normal_view) = create_array(device, (1, 1, 1), TextureFormat::Rgba8Unorm, 1);
+
+        let mut result = Self::create(
+            device,
+            (1, 1, 1),
+            TextureFormat::Rgba8UnormSrgb,
+            1,
+            normal_view,
+            sampler,
+        );
+        result.stale = true;
+        result
+    }
+
+    /// Pack and upload `materials`. Materials past the device's layer limit, usually 256, aren't
+    /// uploaded; entities using them sample the last layer.
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        materials: &[Material],
+        sampler: &SamplerSettings,
+    ) -> Self {
+        let limits = device.limits();
+        let max_dim = limits.max_texture_dimension_2d;
+
+        let max_layers = limits.max_texture_array_layers as usize;
+        let materials = &materials[..materials.len().min(max_layers)];
+
+        if let Some(result) = Self::new_compressed(device, queue, materials, sampler) {
+            return result;
+        }
+
+        let width = materials
+            .iter()
+            .map(|m| m.dimensions().0)
+            .max()
+            .unwrap_or(1);
+        let height = materials
+            .iter()
+            .map(|m| m.dimensions().1)
+            .max()
+            .unwrap_or(1);
+        let (width, height) = (width.clamp(1, max_dim), height.clamp(1, max_dim));
+
+        // A blank layer if there are no materials; the shader doesn't sample it.
+        let layers = materials.len().max(1) as u32;
+        let result = Self::create(
+            device,
+            (width, height, layers),
+            TextureFormat::Rgba8UnormSrgb,
+            1,
+            create_normal_maps(device, queue, materials),
+            sampler,
+        );
+
+        for (i, material) in materials.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &result.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: i as u32,
+                    },
+                },
+                &material.layer_data(width, height),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * width),
+                    rows_per_image: Some(height),
+                },
+                Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        result
+    }
+
+    /// Upload materials without decompressing them, if they're all compressed with the same format
+    /// and size, and the device supports it. Returns `None` otherwise.
+    fn new_compressed(
+        device: &Device,
+        queue: &Queue,
+        materials: &[Material],
+        sampler: &SamplerSettings,
+    ) -> Option<Self> {
+        let images: Vec<_> = materials
+            .iter()
+            .map(|m| match &m.image {
+                MaterialImage::Compressed(img) => Some(img),
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+
+        let first = images.first()?;
+        let (width, height) = (first.width, first.height);
+
+        if !device.features().contains(Features::TEXTURE_COMPRESSION_BC)
+            || width % 4 != 0
+            || height % 4 != 0
+            || width.max(height) > device.limits().max_texture_dimension_2d
+            || images.iter().any(|img| {
+                (img.format, img.srgb, img.width, img.height)
+                    != (first.format, first.srgb, width, height)
+            })
+        {
+            return None;
+        }
+
+        // Files may include more levels than are valid for their size.
+        let max_levels = u32::BITS - width.max(height).leading_zeros();
+        let mip_levels = images
+            .iter()
+            .map(|img| img.levels.len() as u32)
+            .min()?
+            .min(max_levels);
+
+        let result = Self::create(
+            device,
+            (width, height, images.len() as u32),
+            first.format.texture_format(first.srgb),
+            mip_levels,
+            create_normal_maps(device, queue, materials),
+            sampler,
+        );
+
+        let block_size = first.format.block_size() as u32;
+
+        for (i, img) in images.iter().enumerate() {
+            for level in 0..mip_levels {
+                let (blocks_x, blocks_y) = img.level_blocks(level);
+
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        aspect: wgpu::TextureAspect::All,
+                        texture: &result.texture,
+                        mip_level: level,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: i as u32,
+                        },
+                    },
+                    &img.levels[level as usize],
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(blocks_x * block_size),
+                        rows_per_image: Some(blocks_y),
+                    },
+                    // Levels smaller than a block are padded to one.
+                    Extent3d {
+                        width: blocks_x * 4,
+                        height: blocks_y * 4,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+
+        Some(result)
+    }
+
+    /// Rewrite a material's layer in place, eg after its image changes each frame. Returns false
+    /// if it can't be, since the layer doesn't exist, the textures are compressed, or the image
+    /// is larger than a layer; repack all materials with `new` in that case.
+    pub fn write_layer(&self, queue: &Queue, material: &Material, i: usize) -> bool {
+        let size = self.texture.size();
+        let (width, height) = material.dimensions();
+
+        if i >= size.depth_or_array_layers as usize
+            || self.texture.format() != TextureFormat::Rgba8UnormSrgb
+            || width > size.width
+            || height > size.height
+        {
+            return false;
+        }
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: i as u32,
+                },
+            },
+            &material.layer_data(size.width, size.height),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * size.width),
+                rows_per_image: Some(size.height),
+            },
+            Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        true
+    }
+
+    /// `size` is width, height, and layers.
+    fn create(
+        device: &Device,
+        size: (u32, u32, u32),
+        format: TextureFormat,
+        mip_levels: u32,
+        normal_view: TextureView,
+        sampler: &SamplerSettings,
+    ) -> Self {
+        let (texture, view) = create_array(device, size, format, mip_levels);
+        let sampler = device.create_sampler(&sampler.descriptor(device.features()));
+
+        Self {
+            texture,
+            view,
+            normal_view,
+            sampler,
+            stale: false,
+        }
+    }
+}
+
+/// A texture array, and a view of all its layers. `size` is width, height, and layers.
+fn create_array(
+    device: &Device,
+    size: (u32, u32, u32),
+    format: TextureFormat,
+    mip_levels: u32,
+) -> (Texture, TextureView) {
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some("Material textures"),
+        size: Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: size.2,
+        },
+        mip_level_count: mip_levels,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("Material texture view"),
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    });
+
+    (texture, view)
+}
+
+/// Pack and upload normal maps, with a layer per material. Layers are sized to the largest normal
+/// map; if there are none, they're a single texel.
+fn create_normal_maps(device: &Device, queue: &Queue, materials: &[Material]) -> TextureView {
+    let max_dim = device.limits().max_texture_dimension_2d;
+
+    let sizes: Vec<_> = materials
+        .iter()
+        .filter_map(|m| m.normal_map.as_ref().map(|n| n.dimensions()))
+        .collect();
+    let width = sizes
+        .iter()
+        .map(|s| s.0)
+        .max()
+        .unwrap_or(1)
+        .clamp(1, max_dim);
+    let height = sizes
+        .iter()
+        .map(|s| s.1)
+        .max()
+        .unwrap_or(1)
+        .clamp(1, max_dim);
+
+    let layers = materials.len().max(1) as u32;
+    let (texture, view) = create_array(
+        device,
+        (width, height, layers),
+        TextureFormat::Rgba8Unorm,
+        1,
+    );
+
+    for (i, material) in materials.iter().enumerate() {
+        let data = match &material.normal_map {
+            Some(map) => map.layer_data(width, height, FLAT_NORMAL),
+            None => FLAT_NORMAL.repeat((width * height) as usize),
+        };
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: i as u32,
+                },
+            },
+            &data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
+            },
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    view
+}
@@ -0,0 +1,202 @@
+//! The math types used throughout the engine's API: vectors, quaternions, and matrices, from the
+//! `lin_alg` crate. Use these, or `graphics::lin_alg`, vice depending on `lin_alg` directly, so
+//! your types always match the engine's, even if your own `lin_alg` dependency's version differs.
+//!
+//! Operations `lin_alg` doesn't provide are here as functions. Matrices are column-major.
+
+pub use lin_alg::f32::{Mat3, Mat4, Quaternion, Vec3, Vec4};
+
+use crate::graphics::{FWD_VEC, RIGHT_VEC};
+
+const EPS: f32 = 0.000_001;
+
+/// Transform a point by a matrix, eg a model matrix, including translation. For projection
+/// matrices, the result is divided by W.
+pub fn transform_point(mat: &Mat4, point: Vec3) -> Vec3 {
+    let d = &mat.data;
+    let row = |i: usize| d[i] * point.x + d[4 + i] * point.y + d[8 + i] * point.z + d[12 + i];
+
+    let w = row(3);
+    let w = if w.abs() > f32::EPSILON { w } else { 1. };
+
+    Vec3::new(row(0) / w, row(1) / w, row(2) / w)
+}
+
+/// Transform a direction by a matrix, ignoring translation.
+pub fn transform_vec(mat: &Mat4, v: Vec3) -> Vec3 {
+    let d = &mat.data;
+    let row = |i: usize| d[i] * v.x + d[4 + i] * v.y + d[8 + i] * v.z;
+
+    Vec3::new(row(0), row(1), row(2))
+}
+
+/// Split a transform matrix into translation, rotation, and scale along each axis, such that it's
+/// `translation * rotation * scale`, as with `Entity` model matrices. Matrices with shear, or
+/// a projection, can't be represented this way; their rotation is approximate. A reflection is
+/// represented by negating the X scale. If any scale is zero, the rotation is the identity.
+pub fn decompose(mat: &Mat4) -> (Vec3, Quaternion, Vec3) {
+    let d = &mat.data;
+
+    let translation = Vec3::new(d[12], d[13], d[14]);
+
+    let cols = [
+        Vec3::new(d[0], d[1], d[2]),
+        Vec3::new(d[4], d[5], d[6]),
+        Vec3::new(d[8], d[9], d[10]),
+    ];
+    let mut scale = Vec3::new(
+        cols[0].magnitude(),
+        cols[1].magnitude(),
+        cols[2].magnitude(),
+    );
+
+    // A rotation can't reflect, so a negative determinant means a negative scale.
+    if cols[0].dot(cols[1].cross(cols[2])) < 0. {
+        scale.x = -scale.x;
+    }
+
+    if scale.x.abs() < EPS || scale.y.abs() < EPS || scale.z.abs() < EPS {
+        return (translation, Quaternion::new_identity(), scale);
+    }
+
+    let (x, y, z) = (cols[0] / scale.x, cols[1] / scale.y, cols[2] / scale.z);
+    let rotation = from_rotation_mat(&[x.x, x.y, x.z, y.x, y.y, y.z, z.x, z.y, z.z]);
+
+    (translation, rotation, scale)
+}
+
+/// The rotation of a 3x3 rotation matrix, eg from `Quaternion::to_matrix3`. The matrix shouldn't
+/// include scale; see `decompose` for matrices that do.
+pub fn mat3_to_quaternion(mat: &Mat3) -> Quaternion {
+    from_rotation_mat(&mat.data)
+}
+
+/// The axis, normalized, and angle in radians, from 0 to TAU/2, of a rotation; the inverse of
+/// `Quaternion::from_axis_angle`. For the identity, the axis is +X.
+pub fn to_axis_angle(rotation: Quaternion) -> (Vec3, f32) {
+    let mut q = rotation.to_normalized();
+    // Use the shorter rotation; a quaternion and its negative are the same.
+    if q.w < 0. {
+        q = Quaternion::new(-q.w, -q.x, -q.y, -q.z);
+    }
+
+    let angle = 2. * q.w.min(1.).acos();
+    let sin_half = (1. - q.w * q.w).max(0.).sqrt();
+    if sin_half < EPS {
+        return (RIGHT_VEC, 0.);
+    }
+
+    (
+        Vec3::new(q.x / sin_half, q.y / sin_half, q.z / sin_half),
+        angle,
+    )
+}
+
+/// Whether two rotations have identical components. `Quaternion` doesn't implement `PartialEq`.
+/// `q` and `-q` represent the same rotation, but compare unequal.
+pub fn quat_eq(a: Quaternion, b: Quaternion) -> bool {
+    (a.w, a.x, a.y, a.z) == (b.w, b.x, b.y, b.z)
+}
+
+/// Spherical linear interpolation between two rotations, along the shorter path, at a constant
+/// angular speed. `t` is from 0, for `a`, to 1, for `b`.
+pub fn slerp(a: Quaternion, b: Quaternion, t: f32) -> Quaternion {
+    let mut dot = a.w * b.w + a.x * b.x + a.y * b.y + a.z * b.z;
+
+    // A quaternion and its negative are the same rotation; negating one takes the shorter path.
+    let b = if dot < 0. {
+        dot = -dot;
+        Quaternion::new(-b.w, -b.x, -b.y, -b.z)
+    } else {
+        b
+    };
+
+    // When they're nearly the same, linear interpolation is accurate, and avoids dividing by a
+    // small sine.
+    let (weight_a, weight_b) = if dot > 0.9995 {
+        (1. - t, t)
+    } else {
+        let angle = dot.acos();
+        let sin = angle.sin();
+        (((1. - t) * angle).sin() / sin, (t * angle).sin() / sin)
+    };
+
+    Quaternion::new(
+        a.w * weight_a + b.w * weight_b,
+        a.x * weight_a + b.x * weight_b,
+        a.y * weight_a + b.y * weight_b,
+        a.z * weight_a + b.z * weight_b,
+    )
+    .to_normalized()
+}
+
+/// A rotation that points the forward axis (+Z) along `dir`, with the up axis (+Y) as close to
+/// `up` as possible, eg to face a camera or entity towards a point. Neither needs to be
+/// normalized. If `dir` is parallel to `up`, another up vector is used, and if it's zero, this is
+/// the identity.
+pub fn from_vec_direction(dir: Vec3, up: Vec3) -> Quaternion {
+    if dir.magnitude_squared() < EPS {
+        return Quaternion::new_identity();
+    }
+    let fwd = dir.to_normalized();
+
+    let mut right = up.cross(fwd);
+    if right.magnitude_squared() < EPS {
+        let alt_up = if fwd.z.abs() < 0.9 {
+            FWD_VEC
+        } else {
+            RIGHT_VEC
+        };
+        right = alt_up.cross(fwd);
+    }
+    let right = right.to_normalized();
+    let up = fwd.cross(right);
+
+    from_rotation_mat(&[
+        right.x, right.y, right.z, up.x, up.y, up.z, fwd.x, fwd.y, fwd.z,
+    ])
+}
+
+/// The rotation of an orthonormal, column-major 3x3 matrix.
+fn from_rotation_mat(m: &[f32; 9]) -> Quaternion {
+    // The element at a row and column.
+    let e = |row: usize, col: usize| m[col * 3 + row];
+    let trace = e(0, 0) + e(1, 1) + e(2, 2);
+
+    // Divide by the largest component, for precision.
+    let result = if trace > 0. {
+        let s = (trace + 1.).sqrt() * 2.;
+        Quaternion::new(
+            s / 4.,
+            (e(2, 1) - e(1, 2)) / s,
+            (e(0, 2) - e(2, 0)) / s,
+            (e(1, 0) - e(0, 1)) / s,
+        )
+    } else if e(0, 0) > e(1, 1) && e(0, 0) > e(2, 2) {
+        let s = (1. + e(0, 0) - e(1, 1) - e(2, 2)).sqrt() * 2.;
+        Quaternion::new(
+            (e(2, 1) - e(1, 2)) / s,
+            s / 4.,
+            (e(0, 1) + e(1, 0)) / s,
+            (e(0, 2) + e(2, 0)) / s,
+        )
+    } else if e(1, 1) > e(2, 2) {
+        let s = (1. + e(1, 1) - e(0, 0) - e(2, 2)).sqrt() * 2.;
+        Quaternion::new(
+            (e(0, 2) - e(2, 0)) / s,
+            (e(0, 1) + e(1, 0)) / s,
+            s / 4.,
+            (e(1, 2) + e(2, 1)) / s,
+        )
+    } else {
+        let s = (1. + e(2, 2) - e(0, 0) - e(1, 1)).sqrt() * 2.;
+        Quaternion::new(
+            (e(1, 0) - e(0, 1)) / s,
+            (e(0, 2) + e(2, 0)) / s,
+            (e(1, 2) + e(2, 1)) / s,
+            s / 4.,
+        )
+    };
+
+    result.to_normalized()
+}
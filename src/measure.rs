@@ -0,0 +1,314 @@
+//! World-space measurements of distances, angles, and dihedral angles, eg between atoms in a
+//! molecule, or features of a CAD model. Each measurement is defined by 2 to 4 points, which are
+//! either fixed positions, or entities, which the measurement follows as they move.
+//!
+//! Measurements are added by the application, or interactively: while `MeasureTool::picking` is
+//! set, left clicks in the 3D viewport pick points using `Scene::raycast`. The engine draws each
+//! measurement's connecting lines and arcs, and a label with its value, each frame, and updates
+//! its `value`, so the application can read results.
+
+use lin_alg::f32::Vec3;
+
+use crate::{
+    camera::Camera,
+    graphics::{FWD_VEC, RIGHT_VEC, UP_VEC},
+    types::Scene,
+};
+
+/// The radius of the marker drawn at each point, relative to the scene's scale factor.
+const MARKER_SIZE: f32 = 0.1;
+
+/// The radius of angle arcs, relative to the shortest arm.
+const ARC_RATIO: f32 = 0.3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum MeasureKind {
+    /// The distance between 2 points.
+    #[default]
+    Distance,
+    /// The angle at the second of 3 points.
+    Angle,
+    /// The torsion angle of 4 points, around the axis between the second and third, from -π to
+    /// π. This is the angle between the planes of the first 3, and last 3 points.
+    Dihedral,
+}
+
+impl MeasureKind {
+    /// The number of points this kind of measurement uses.
+    pub fn num_points(self) -> usize {
+        match self {
+            Self::Distance => 2,
+            Self::Angle => 3,
+            Self::Dihedral => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MeasurePoint {
+    /// A fixed position, in world space.
+    Position(Vec3),
+    /// The position of an entity, by index in `Scene::entities`, with group transforms applied.
+    Entity(usize),
+}
+
+#[derive(Clone, Debug)]
+pub struct Measurement {
+    pub kind: MeasureKind,
+    /// `kind.num_points()` points.
+    pub points: Vec<MeasurePoint>,
+    /// The distance, in scene units, or angle, in radians. Updated by the engine each frame.
+    /// `None` if the points are invalid, eg an entity was removed, or points coincide.
+    pub value: Option<f32>,
+}
+
+impl Measurement {
+    pub fn new(kind: MeasureKind, points: Vec<MeasurePoint>) -> Self {
+        Self {
+            kind,
+            points,
+            value: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Measurements, and settings for picking them interactively. Changes take effect on the next
+/// frame, without setting `EngineUpdates`.
+pub struct MeasureTool {
+    pub measurements: Vec<Measurement>,
+    /// If set, left clicks in the 3D viewport pick points for a measurement of this kind. Once
+    /// enough are picked, it's added to `measurements`.
+    pub picking: Option<MeasureKind>,
+    /// If true, picked points are the entity clicked, and follow it. Otherwise, they're the
+    /// position on its surface that was clicked.
+    pub snap_to_entities: bool,
+    /// Points picked so far for the next measurement.
+    pub pending: Vec<MeasurePoint>,
+    /// Indices into `measurements` added by picking since the last frame, eg to respond to them
+    /// from the render handler. Cleared after each frame.
+    pub completed: Vec<usize>,
+    /// RGBA, from 0 to 1. Used for lines and labels.
+    pub color: [f32; 4],
+}
+
+impl Default for MeasureTool {
+    fn default() -> Self {
+        Self {
+            measurements: Vec::new(),
+            picking: None,
+            snap_to_entities: true,
+            pending: Vec::new(),
+            completed: Vec::new(),
+            color: [1., 0.9, 0.3, 1.],
+        }
+    }
+}
+
+/// The component of `v` perpendicular to the unit vector `axis`.
+fn reject(v: Vec3, axis: Vec3) -> Vec3 {
+    v - axis * v.dot(axis)
+}
+
+/// A unit vector halfway between the directions of nonzero vectors `a` and `b`.
+fn bisector(a: Vec3, b: Vec3) -> Vec3 {
+    let a = a.to_normalized();
+    let sum = a + b.to_normalized();
+
+    // Opposite directions have no bisector; we label along `a` instead.
+    if sum.magnitude() < 1e-6 {
+        a
+    } else {
+        sum.to_normalized()
+    }
+}
+
+/// Compute a measurement's value from its points' positions.
+fn measure(kind: MeasureKind, p: &[Vec3]) -> Option<f32> {
+    match kind {
+        MeasureKind::Distance => Some((p[1] - p[0]).magnitude()),
+        MeasureKind::Angle => {
+            let (a, b) = (p[0] - p[1], p[2] - p[1]);
+            let denom = a.magnitude() * b.magnitude();
+            if denom == 0. {
+                return None;
+            }
+            Some((a.dot(b) / denom).clamp(-1., 1.).acos())
+        }
+        MeasureKind::Dihedral => {
+            let axis = p[2] - p[1];
+            if axis.magnitude() == 0. {
+                return None;
+            }
+            let axis = axis.to_normalized();
+
+            // The first and last arms, projected onto the plane normal to the axis.
+            let v = reject(p[0] - p[1], axis);
+            let w = reject(p[3] - p[2], axis);
+            if v.magnitude() == 0. || w.magnitude() == 0. {
+                return None;
+            }
+
+            Some(axis.cross(v).dot(w).atan2(v.dot(w)))
+        }
+    }
+}
+
+/// The origin and direction of the ray from the camera through a point on the 3D viewport.
+/// `viewport` is (x, y, width, height), and `cursor` is the position in the window, in pixels.
+/// Returns `None` if the point is outside the viewport.
+pub(crate) fn cursor_ray(
+    camera: &Camera,
+    viewport: (f32, f32, f32, f32),
+    cursor: (f32, f32),
+) -> Option<(Vec3, Vec3)> {
+    let (x, y, width, height) = viewport;
+    if width <= 0. || height <= 0. {
+        return None;
+    }
+
+    let ndc_x = (cursor.0 - x) / width * 2. - 1.;
+    let ndc_y = 1. - (cursor.1 - y) / height * 2.;
+    if ndc_x.abs() > 1. || ndc_y.abs() > 1. {
+        return None;
+    }
+
+    // Orthographic rays are parallel, from points across the view.
+    if let Some(height) = camera.ortho_height {
+        let width = height * camera.aspect;
+        let offset = RIGHT_VEC * (ndc_x * width / 2.) + UP_VEC * (ndc_y * height / 2.);
+        let origin = camera.position + camera.orientation.rotate_vec(offset);
+        return Some((origin, camera.orientation.rotate_vec(FWD_VEC)));
+    }
+
+    let tan_y = (camera.fov_y / 2.).tan();
+    let tan_x = tan_y * camera.aspect;
+
+    let dir = RIGHT_VEC * (ndc_x * tan_x) + UP_VEC * (ndc_y * tan_y) + FWD_VEC;
+    Some((camera.position, camera.orientation.rotate_vec(dir).to_normalized()))
+}
+
+impl Scene {
+    fn measure_point_posit(&self, point: &MeasurePoint) -> Option<Vec3> {
+        match point {
+            MeasurePoint::Position(p) => Some(*p),
+            MeasurePoint::Entity(i) => self.entity_in_world(*i).map(|e| e.position),
+        }
+    }
+
+    /// Pick a point under the cursor for the measurement in progress, if picking. See
+    /// `cursor_ray` for the arguments.
+    pub(crate) fn pick_measure_point(
+        &mut self,
+        viewport: (f32, f32, f32, f32),
+        cursor: (f32, f32),
+    ) {
+        let Some(kind) = self.measure.picking else {
+            return;
+        };
+        let Some((origin, dir)) = cursor_ray(&self.camera, viewport, cursor) else {
+            return;
+        };
+        let Some(hit) = self.raycast(origin, dir) else {
+            return;
+        };
+
+        let point = if self.measure.snap_to_entities {
+            MeasurePoint::Entity(hit.entity)
+        } else {
+            MeasurePoint::Position(hit.position)
+        };
+
+        let tool = &mut self.measure;
+        tool.pending.push(point);
+
+        if tool.pending.len() >= kind.num_points() {
+            let points = tool.pending.drain(..).take(kind.num_points()).collect();
+            tool.measurements.push(Measurement::new(kind, points));
+            tool.completed.push(tool.measurements.len() - 1);
+        }
+    }
+
+    /// Update measurement values, and draw measurements and pending points using `debug_draw`.
+    /// Run this each frame, before rendering.
+    pub(crate) fn update_measurements(&mut self) {
+        let color = self.measure.color;
+        let marker_radius = MARKER_SIZE * self.scale_factor();
+
+        let pending: Vec<Vec3> = self
+            .measure
+            .pending
+            .iter()
+            .filter_map(|p| self.measure_point_posit(p))
+            .collect();
+
+        for (i, p) in pending.iter().enumerate() {
+            self.debug_draw.sphere(*p, marker_radius, color);
+            if i > 0 {
+                self.debug_draw.line(pending[i - 1], *p, color);
+            }
+        }
+
+        for i in 0..self.measure.measurements.len() {
+            let m = &self.measure.measurements[i];
+
+            let posits: Option<Vec<Vec3>> = m
+                .points
+                .iter()
+                .map(|p| self.measure_point_posit(p))
+                .collect();
+
+            let (kind, posits) = match posits {
+                Some(p) if p.len() == m.kind.num_points() => (m.kind, p),
+                _ => {
+                    self.measure.measurements[i].value = None;
+                    continue;
+                }
+            };
+
+            let value = measure(kind, &posits);
+            self.measure.measurements[i].value = value;
+
+            let Some(value) = value else {
+                continue;
+            };
+
+            for (j, p) in posits.iter().enumerate() {
+                self.debug_draw.sphere(*p, marker_radius, color);
+                if j > 0 {
+                    self.debug_draw.line(posits[j - 1], *p, color);
+                }
+            }
+
+            let label_posit = match kind {
+                MeasureKind::Distance => (posits[0] + posits[1]) * 0.5,
+                MeasureKind::Angle => {
+                    let (a, b) = (posits[0] - posits[1], posits[2] - posits[1]);
+                    let radius = a.magnitude().min(b.magnitude()) * ARC_RATIO;
+                    self.debug_draw.arc(posits[1], a, b, radius, color);
+
+                    posits[1] + bisector(a, b) * radius * 1.5
+                }
+                MeasureKind::Dihedral => {
+                    // `measure` returned a value, so the axis and arms are nonzero.
+                    let axis = (posits[2] - posits[1]).to_normalized();
+                    let v = reject(posits[0] - posits[1], axis);
+                    let w = reject(posits[3] - posits[2], axis);
+
+                    let center = (posits[1] + posits[2]) * 0.5;
+                    let radius = v.magnitude().min(w.magnitude()) * ARC_RATIO;
+                    self.debug_draw.arc(center, v, w, radius, color);
+
+                    center + bisector(v, w) * radius * 1.5
+                }
+            };
+
+            let label = match kind {
+                MeasureKind::Distance => format!("{value:.2} {}", self.units.symbol()),
+                _ => format!("{:.1}°", value.to_degrees()),
+            };
+
+            self.debug_draw.text_color(label_posit, &label, color);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! A cache of meshes uploaded to the GPU, keyed by their contents. Meshes with identical contents
+//! (eg several copies of a unit sphere) share a single range of the vertex and index buffers, and
+//! updating meshes only re-uploads if there's content not already on the GPU. Meshes whose
+//! vertices change, but not their vertex count or indices, (eg cloth, or morphing surfaces) can be
+//! updated in place, without recreating the buffers. Replaced meshes are written over their
+//! previous range if they fit, or appended to the buffers otherwise.
+//!
+//! Entries are looked up by a hash of their contents, and compared with them, so meshes whose
+//! hashes collide are stored separately. This keeps a CPU copy of the data of each cached mesh.
+
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+};
+
+use crate::{
+    fnv::FnvHasher,
+    types::{Mesh, VERTEX_SIZE},
+};
+
+/// Indices are uploaded as `u32`.
+pub(crate) const INDEX_SIZE: usize = 4;
+
+/// Vertex and index data, as uploaded to the GPU.
+pub(crate) type MeshData = (Vec<u8>, Vec<u8>);
+
+#[derive(Clone, Copy, Debug)]
+/// The location of a mesh in the vertex and index buffers.
+pub(crate) struct MeshRange {
+    /// Added to each index; this is the base vertex when drawing.
+    pub vertex_start: i32,
+    pub vertex_count: u32,
+    pub index_start: u32,
+    pub index_count: u32,
+}
+
+struct CacheEntry {
+    range: MeshRange,
+    /// The mesh's contents, to tell apart meshes whose hashes collide.
+    data: MeshData,
+    vertex_count: usize,
+    /// The space allocated for this entry, which may be larger than its mesh after a replacement.
+    vertex_capacity: u32,
+    index_capacity: u32,
+    /// The number of scene meshes using this entry. Entries with no references stay on the GPU
+    /// until the next time we rebuild the buffers.
+    ref_count: usize,
+}
+
+#[derive(Default)]
+pub(crate) struct MeshCache {
+    entries: HashMap<u64, CacheEntry>,
+    /// The key of each scene mesh's entry, as of the last update.
+    mesh_keys: Vec<u64>,
+    /// The number of vertices and indices allocated in the buffers, including unused ranges of
+    /// replaced meshes. Replaced meshes that don't fit their range are appended after these.
+    vertex_end: u32,
+    index_end: u32,
+}
+
+/// Data to write for a replaced mesh.
+pub(crate) struct MeshWrite {
+    pub range: MeshRange,
+    /// Byte offsets into the vertex and index buffers.
+    pub vertex_offset: u64,
+    pub index_offset: u64,
+    pub vertex_data: Vec<u8>,
+    pub index_data: Vec<u8>,
+    /// The sizes, in bytes, the vertex and index buffers must be at least.
+    pub vertex_buf_size: u64,
+    pub index_buf_size: u64,
+}
+
+/// Hash the data we upload to the GPU for a mesh.
+pub(crate) fn hash_mesh(mesh: &Mesh) -> u64 {
+    let mut hasher = FnvHasher::default();
+
+    for vertex in &mesh.vertices {
+        vertex.to_bytes().hash(&mut hasher);
+    }
+    mesh.indices.hash(&mut hasher);
+
+    hasher.finish()
+}
+
+/// The data we upload to the GPU for a mesh.
+fn mesh_data(mesh: &Mesh) -> MeshData {
+    let mut vertex_data = Vec::with_capacity(mesh.vertices.len() * VERTEX_SIZE);
+    for vertex in &mesh.vertices {
+        vertex_data.extend_from_slice(&vertex.to_bytes());
+    }
+
+    let mut index_data = Vec::with_capacity(mesh.indices.len() * INDEX_SIZE);
+    for index in &mesh.indices {
+        index_data.extend_from_slice(&(*index as u32).to_ne_bytes());
+    }
+
+    (vertex_data, index_data)
+}
+
+impl MeshCache {
+    /// The key of the entry for a mesh with `data`, or if there isn't one, a vacant key to insert
+    /// it at. Keys start at the mesh's hash; meshes whose hashes collide use the following keys.
+    fn key(&self, mesh: &Mesh, data: &MeshData) -> u64 {
+        let mut key = hash_mesh(mesh);
+
+        while let Some(entry) = self.entries.get(&key) {
+            if entry.data == *data {
+                break;
+            }
+            key = key.wrapping_add(1);
+        }
+
+        key
+    }
+
+    /// Update reference counts for a new set of meshes. Returns the range for each mesh, and if
+    /// any meshes aren't already uploaded, the vertex and index data to rebuild the buffers with.
+    /// The rebuilt buffers contain only meshes in use.
+    pub fn update(&mut self, meshes: &[Mesh]) -> (Vec<MeshRange>, Option<MeshData>) {
+        let data: Vec<MeshData> = meshes.iter().map(mesh_data).collect();
+        let keys: Vec<u64> = meshes
+            .iter()
+            .zip(&data)
+            .map(|(mesh, data)| self.key(mesh, data))
+            .collect();
+
+        for entry in self.entries.values_mut() {
+            entry.ref_count = 0;
+        }
+
+        let mut all_cached = true;
+        for key in &keys {
+            match self.entries.get_mut(key) {
+                Some(entry) => entry.ref_count += 1,
+                None => all_cached = false,
+            }
+        }
+
+        if all_cached {
+            let ranges = keys.iter().map(|k| self.entries[k].range).collect();
+            self.mesh_keys = keys;
+            return (ranges, None);
+        }
+
+        // Rebuild, with each unique mesh in use stored once.
+        self.entries.clear();
+
+        let mut vertex_data = Vec::new();
+        let mut index_data = Vec::new();
+        let mut vertex_start = 0;
+        let mut index_start = 0;
+
+        let mut ranges = Vec::with_capacity(meshes.len());
+        let mut keys = Vec::with_capacity(meshes.len());
+
+        for (mesh, data) in meshes.iter().zip(data) {
+            let key = self.key(mesh, &data);
+            keys.push(key);
+
+            if let Some(entry) = self.entries.get_mut(&key) {
+                entry.ref_count += 1;
+                ranges.push(entry.range);
+                continue;
+            }
+
+            vertex_data.extend_from_slice(&data.0);
+            index_data.extend_from_slice(&data.1);
+
+            let range = MeshRange {
+                vertex_start,
+                vertex_count: mesh.vertices.len() as u32,
+                index_start,
+                index_count: mesh.indices.len() as u32,
+            };
+
+            self.entries.insert(
+                key,
+                CacheEntry {
+                    range,
+                    data,
+                    vertex_count: mesh.vertices.len(),
+                    vertex_capacity: range.vertex_count,
+                    index_capacity: range.index_count,
+                    ref_count: 1,
+                },
+            );
+            ranges.push(range);
+
+            vertex_start += mesh.vertices.len() as i32;
+            index_start += mesh.indices.len() as u32;
+        }
+
+        self.mesh_keys = keys;
+        self.vertex_end = vertex_start as u32;
+        self.index_end = index_start;
+
+        (ranges, Some((vertex_data, index_data)))
+    }
+
+    /// Update the cache for a mesh replaced with different contents, which may have different
+    /// vertex and index counts. We write it over its previous range if it fits, and after all
+    /// others otherwise. Returns `None` if its range is shared with other meshes, or if its new
+    /// contents match another mesh's; rebuild the buffers using `update` in that case.
+    pub fn replace(&mut self, meshes: &[Mesh], mesh_i: usize) -> Option<MeshWrite> {
+        let mesh = meshes.get(mesh_i)?;
+        let key_prev = *self.mesh_keys.get(mesh_i)?;
+
+        if self.entries.get(&key_prev)?.ref_count != 1 {
+            return None;
+        }
+
+        let data = mesh_data(mesh);
+        let key = self.key(mesh, &data);
+        if key != key_prev && self.entries.contains_key(&key) {
+            return None;
+        }
+
+        let mut entry = self.entries.remove(&key_prev)?;
+
+        let vertex_count = mesh.vertices.len() as u32;
+        let index_count = mesh.indices.len() as u32;
+
+        if vertex_count > entry.vertex_capacity || index_count > entry.index_capacity {
+            // The previous range stays unused until the next rebuild.
+            entry.range.vertex_start = self.vertex_end as i32;
+            entry.range.index_start = self.index_end;
+            entry.vertex_capacity = vertex_count;
+            entry.index_capacity = index_count;
+
+            self.vertex_end += vertex_count;
+            self.index_end += index_count;
+        }
+
+        entry.range.vertex_count = vertex_count;
+        entry.range.index_count = index_count;
+        entry.vertex_count = mesh.vertices.len();
+        entry.data = data.clone();
+
+        let range = entry.range;
+        self.entries.insert(key, entry);
+        self.mesh_keys[mesh_i] = key;
+
+        let (vertex_data, index_data) = data;
+
+        Some(MeshWrite {
+            range,
+            vertex_offset: (range.vertex_start as usize * VERTEX_SIZE) as u64,
+            index_offset: (range.index_start as usize * INDEX_SIZE) as u64,
+            vertex_data,
+            index_data,
+            vertex_buf_size: (self.vertex_end as usize * VERTEX_SIZE) as u64,
+            index_buf_size: (self.index_end as usize * INDEX_SIZE) as u64,
+        })
+    }
+
+    /// Update the cache for a mesh whose vertices changed in place. Returns the offset into the
+    /// vertex buffer, and the data to write there. Returns `None` if the mesh can't be updated
+    /// in place, eg because its vertex count changed, or its range is shared with other meshes;
+    /// rebuild the buffers using `update` in that case.
+    pub fn update_vertices(&mut self, meshes: &[Mesh], mesh_i: usize) -> Option<(u64, Vec<u8>)> {
+        let mesh = meshes.get(mesh_i)?;
+        let key_prev = *self.mesh_keys.get(mesh_i)?;
+
+        let entry = self.entries.get(&key_prev)?;
+        if entry.ref_count != 1 || entry.vertex_count != mesh.vertices.len() {
+            return None;
+        }
+
+        let data = mesh_data(mesh);
+        let key = self.key(mesh, &data);
+        if key != key_prev {
+            // If the new contents match another mesh, rebuild so they share a range.
+            if self.entries.contains_key(&key) {
+                return None;
+            }
+            let entry = self.entries.remove(&key_prev)?;
+            self.entries.insert(key, entry);
+            self.mesh_keys[mesh_i] = key;
+        }
+
+        let entry = self.entries.get_mut(&key)?;
+        entry.data = data;
+
+        Some((
+            (entry.range.vertex_start as usize * VERTEX_SIZE) as u64,
+            entry.data.0.clone(),
+        ))
+    }
+}
@@ -1,6 +1,7 @@
 //! This module generates meshes
 
 use std::{
+    collections::HashMap,
     f32::consts::TAU,
     fs::File,
     io::{BufReader, Read},
@@ -9,10 +10,55 @@ use std::{
 use lin_alg::f32::Vec3;
 
 use crate::{
+    collision::Aabb,
     graphics::UP_VEC,
     types::{Mesh, Vertex},
 };
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// How to generate vertex normals from a mesh's triangles.
+pub enum NormalMode {
+    /// Each triangle uses its face normal, so all edges are hard. Vertices are duplicated for each
+    /// triangle.
+    Flat,
+    /// Normals are averaged across the triangles around each vertex, except across edges where
+    /// faces meet at an angle greater than `angle_threshold`, in radians; those edges stay hard,
+    /// eg the edges of a box. Use `TAU / 2.` to smooth all edges.
+    Smooth { angle_threshold: f32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// How to project vertex positions to texture coordinates. Projections are centered on the mesh's
+/// bounding box, around the Y axis.
+pub enum UvProjection {
+    /// U is longitude, and V latitude, from the top.
+    Spherical,
+    /// U is the angle around the Y axis, and V height, from the top. Faces along the axis, eg
+    /// caps, are projected from above.
+    Cylindrical,
+    /// Each vertex is projected along the axis closest to its normal, and scaled to the bounding
+    /// box.
+    Box,
+}
+
+/// Identifies a vertex's position, texture coordinates, and normal exactly, by their bits.
+type VertexKey = ([u32; 3], [u32; 2], [u32; 3]);
+
+/// Identifies a vertex position exactly, so vertices duplicated at the same position are
+/// treated as connected.
+fn position_key(vertex: &Vertex) -> [u32; 3] {
+    vertex.position.map(f32::to_bits)
+}
+
+/// Normalize a vector, or use `fallback` if it's zero, eg for degenerate triangles.
+fn normalized_or(v: Vec3, fallback: Vec3) -> Vec3 {
+    if v.magnitude_squared() > 0. {
+        v.to_normalized()
+    } else {
+        fallback
+    }
+}
+
 /// Rotate a 2d vector counter-clockwise a given angle.
 fn rotate_vec_2d(vec: [f32; 2], θ: f32) -> [f32; 2] {
     // Self-contained 2d rotation matrix (col-maj)
@@ -57,9 +103,9 @@ impl Mesh {
     /// with the convention of Z-up used elsewhere.
     ///
     /// Points are (x, y, z), with Z being the vertical component. Their indices correspond to how they're
-    /// tied together in a mesh.
+    /// tied together in a mesh. Normals are generated using `normals`.
     // pub fn new_surface(grid: &Vec<Vec<f32>>, start: f32, step: f32, two_sided: bool) -> Self {
-    pub fn new_surface(points: &Vec<Vec<Vec3>>, two_sided: bool) -> Self {
+    pub fn new_surface(points: &Vec<Vec<Vec3>>, two_sided: bool, normals: NormalMode) -> Self {
         let mut vertices = Vec::new();
         let mut indices = Vec::new();
 
@@ -109,26 +155,20 @@ impl Mesh {
             // x += step;
         }
 
-        // Now that we've populated our vertices, update their normals.
-        for i in 0..indices.len() / 3 {
-            let tri_start_i = i * 3;
-            // Find the vertices that make up each triangle.
-            let vert0 = vertices[indices[tri_start_i]];
-            let vert1 = vertices[indices[tri_start_i + 1]];
-            let vert2 = vertices[indices[tri_start_i + 2]];
-
-            // Convert from arrays to Vec3.
-            let v0 = Vec3::new(vert0.position[0], vert0.position[1], vert0.position[2]);
-            let v1 = Vec3::new(vert1.position[0], vert1.position[1], vert1.position[2]);
-            let v2 = Vec3::new(vert2.position[0], vert2.position[1], vert2.position[2]);
-
-            let norm = (v2 - v0).to_normalized().cross((v1 - v0).to_normalized());
-
-            // todo: DRY on this indexing.
-            vertices[indices[tri_start_i]].normal = norm;
-            vertices[indices[tri_start_i + 1]].normal = norm;
-            vertices[indices[tri_start_i + 1]].normal = norm;
-        }
+        // Now that we've populated our vertices, update their normals. This may add vertices.
+        let mut mesh = Self {
+            vertices,
+            indices,
+            material: None,
+            impostor: None,
+            culling: Default::default(),
+        };
+        mesh.generate_normals(normals);
+        let Self {
+            mut vertices,
+            mut indices,
+            ..
+        } = mesh;
 
         // If dual-sided, We need to replicate vertices, since the normal will be opposite.
         // Then, update the index buffer with these new vertices, using the opposite triangle order.
@@ -156,7 +196,9 @@ impl Mesh {
         Self {
             vertices,
             indices,
-            material: 0,
+            material: None,
+            impostor: None,
+            culling: Default::default(),
         }
     }
 
@@ -251,14 +293,19 @@ impl Mesh {
             indices.append(&mut vec![f[0], f[1], f[2], f[0], f[2], f[3]]);
         }
 
-        Self {
+        let mut result = Self {
             vertices,
             indices,
             // vertex_buffer: Vec<usize>,
             // index_buffer: Vec<usize>,
             // num_elements: u32,
-            material: 0,
-        }
+            material: None,
+            impostor: None,
+            culling: Default::default(),
+        };
+
+        result.generate_uvs(UvProjection::Spherical);
+        result
     }
 
     /// Create a box (rectangular prism) mesh.
@@ -336,11 +383,16 @@ impl Mesh {
             ]);
         }
 
-        Self {
+        let mut result = Self {
             vertices,
             indices,
-            material: 0,
-        }
+            material: None,
+            impostor: None,
+            culling: Default::default(),
+        };
+
+        result.generate_uvs(UvProjection::Box);
+        result
     }
 
     /// Create a tetrahedron mesh
@@ -397,7 +449,9 @@ impl Mesh {
             // vertex_buffer: Vec<usize>,
             // index_buffer: Vec<usize>,
             // num_elements: u32,
-            material: 0,
+            material: None,
+            impostor: None,
+            culling: Default::default(),
         }
     }
 
@@ -468,11 +522,16 @@ impl Mesh {
             i_vertex += 1;
         }
 
-        Self {
+        let mut result = Self {
             vertices,
             indices,
-            material: 0,
-        }
+            material: None,
+            impostor: None,
+            culling: Default::default(),
+        };
+
+        result.generate_uvs(UvProjection::Cylindrical);
+        result
     }
 
     pub fn new_pyramid(len: f32, radius: f32, num_sides: usize) -> Self {
@@ -537,7 +596,9 @@ impl Mesh {
         Self {
             vertices,
             indices,
-            material: 0,
+            material: None,
+            impostor: None,
+            culling: Default::default(),
         }
     }
 
@@ -572,7 +633,9 @@ impl Mesh {
         Self {
             vertices,
             indices,
-            material: 0,
+            material: None,
+            impostor: None,
+            culling: Default::default(),
         }
     }
 
@@ -586,7 +649,24 @@ impl Mesh {
 
         reader.read_to_end(&mut file_buf).unwrap();
 
-        let data = obj::ObjData::load_buf(&file_buf[..]).unwrap();
+        Self::from_obj_bytes(&file_buf).unwrap()
+    }
+
+    /// Load a mesh from the contents of an obj file. Returns an error description if the data
+    /// is invalid, or is missing normals.
+    pub fn from_obj_bytes(bytes: &[u8]) -> Result<Self, String> {
+        Self::parse_obj(bytes, None)
+    }
+
+    /// Load a mesh from the contents of an obj file, generating normals instead of using the
+    /// file's. Normals in the file are optional.
+    pub fn from_obj_bytes_with_normals(bytes: &[u8], normals: NormalMode) -> Result<Self, String> {
+        Self::parse_obj(bytes, Some(normals))
+    }
+
+    /// If `normals` is set, we generate them, instead of requiring them in the file.
+    fn parse_obj(bytes: &[u8], normals: Option<NormalMode>) -> Result<Self, String> {
+        let data = obj::ObjData::load_buf(bytes).map_err(|e| format!("{e:?}"))?;
         let mut vertices = Vec::new();
 
         for object in data.objects {
@@ -599,7 +679,11 @@ impl Mesh {
                             let obj::IndexTuple(position_id, _texture_id, normal_id) =
                                 poly.0[index];
 
-                            let n = data.normal[normal_id.unwrap()];
+                            let n = match (normal_id, normals) {
+                                (_, Some(_)) => [0.; 3], // Generated below.
+                                (Some(id), None) => data.normal[id],
+                                (None, None) => return Err("Missing vertex normal".to_owned()),
+                            };
 
                             vertices.push(Vertex::new(
                                 data.position[position_id],
@@ -614,10 +698,229 @@ impl Mesh {
         // todo: Is this right?
         let indices = (0..vertices.len()).collect();
 
-        Self {
+        let mut result = Self {
             vertices,
             indices,
-            material: 0,
+            material: None,
+            impostor: None,
+            culling: Default::default(),
+        };
+
+        if let Some(mode) = normals {
+            result.generate_normals(mode);
+        }
+
+        Ok(result)
+    }
+
+    /// Replace vertex normals with ones generated from the triangles. Vertices at the same position
+    /// are treated as connected, eg for imported meshes with vertices duplicated per triangle.
+    /// Vertices are rebuilt, so indices into them change; their other attributes are kept.
+    pub fn generate_normals(&mut self, mode: NormalMode) {
+        let num_tris = self.indices.len() / 3;
+        let indices = &self.indices[..num_tris * 3];
+
+        let position = |i: usize| {
+            let p = self.vertices[i].position;
+            Vec3::new(p[0], p[1], p[2])
+        };
+
+        // Not normalized, so larger triangles contribute more to smoothed normals.
+        let face_normals: Vec<Vec3> = indices
+            .chunks_exact(3)
+            .map(|tri| {
+                let v0 = position(tri[0]);
+                (position(tri[2]) - v0).cross(position(tri[1]) - v0)
+            })
+            .collect();
+
+        let cos_threshold = match mode {
+            NormalMode::Flat => None,
+            NormalMode::Smooth { angle_threshold } => Some(angle_threshold.cos()),
+        };
+
+        // Triangles touching each position.
+        let mut position_tris: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+        if cos_threshold.is_some() {
+            for (i, &index) in indices.iter().enumerate() {
+                let key = position_key(&self.vertices[index]);
+                position_tris.entry(key).or_default().push(i / 3);
+            }
+        }
+
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+        let mut new_indices = Vec::with_capacity(indices.len());
+
+        // Vertices with the same position, texture coordinates, and normal are shared.
+        let mut shared: HashMap<VertexKey, usize> = HashMap::new();
+
+        for (i, &index) in indices.iter().enumerate() {
+            let source = self.vertices[index];
+            let face = normalized_or(face_normals[i / 3], Vec3::new(0., 1., 0.));
+
+            let Some(cos_threshold) = cos_threshold else {
+                vertices.push(Vertex { normal: face, ..source });
+                new_indices.push(vertices.len() - 1);
+                continue;
+            };
+
+            let key = position_key(&source);
+
+            let mut sum = Vec3::new_zero();
+            for &tri in &position_tris[&key] {
+                let other = face_normals[tri];
+                if normalized_or(other, face).dot(face) >= cos_threshold {
+                    sum += other;
+                }
+            }
+            let normal = normalized_or(sum, face);
+
+            let shared_key = (
+                key,
+                source.tex_coords.map(f32::to_bits),
+                [normal.x, normal.y, normal.z].map(f32::to_bits),
+            );
+
+            let vertex_i = *shared.entry(shared_key).or_insert_with(|| {
+                vertices.push(Vertex { normal, ..source });
+                vertices.len() - 1
+            });
+            new_indices.push(vertex_i);
+        }
+
+        self.vertices = vertices;
+        self.indices = new_indices;
+    }
+
+    /// Set vertex tangents and bitangents from texture coordinates and normals, for normal maps;
+    /// see `Material::normal_map`. Call this after setting both, eg with `generate_uvs`. Each
+    /// vertex's are averaged over the triangles using it, so vertices on texture seams should
+    /// be separate.
+    pub fn generate_tangents(&mut self) {
+        let mut tangents = vec![Vec3::new_zero(); self.vertices.len()];
+        let mut bitangents = vec![Vec3::new_zero(); self.vertices.len()];
+
+        let position = |i: usize| {
+            let p = self.vertices[i].position;
+            Vec3::new(p[0], p[1], p[2])
+        };
+
+        for tri in self.indices.chunks_exact(3) {
+            let [uv0, uv1, uv2] = [0, 1, 2].map(|i| self.vertices[tri[i]].tex_coords);
+            let edge_1 = position(tri[1]) - position(tri[0]);
+            let edge_2 = position(tri[2]) - position(tri[0]);
+            let (du_1, dv_1) = (uv1[0] - uv0[0], uv1[1] - uv0[1]);
+            let (du_2, dv_2) = (uv2[0] - uv0[0], uv2[1] - uv0[1]);
+
+            let det = du_1 * dv_2 - du_2 * dv_1;
+            if det.abs() < f32::EPSILON {
+                continue;
+            }
+
+            // Not normalized, so larger triangles contribute more.
+            let tangent = (edge_1 * dv_2 - edge_2 * dv_1) / det;
+            let bitangent = (edge_2 * du_1 - edge_1 * du_2) / det;
+
+            for &i in tri {
+                tangents[i] += tangent;
+                bitangents[i] += bitangent;
+            }
+        }
+
+        for (i, vertex) in self.vertices.iter_mut().enumerate() {
+            let n = vertex.normal;
+            let fallback = normalized_or(n.cross(UP_VEC), Vec3::new(1., 0., 0.));
+
+            // Make the tangent perpendicular to the normal. The bitangent follows from both, with
+            // its sign kept, eg for mirrored texture coordinates.
+            let t = normalized_or(tangents[i] - n * n.dot(tangents[i]), fallback);
+            let mut b = n.cross(t);
+            if b.dot(bitangents[i]) < 0. {
+                b = -b;
+            }
+
+            vertex.tangent = [t.x, t.y, t.z];
+            vertex.bitangent = [b.x, b.y, b.z];
+        }
+    }
+
+    /// Set texture coordinates by projecting vertex positions, eg so textures can be applied to
+    /// primitives. For spherical and cylindrical projections, vertices on the seam, where U wraps
+    /// from 1 to 0, are duplicated, so indices may change.
+    pub fn generate_uvs(&mut self, projection: UvProjection) {
+        let bounds = Aabb::from_mesh(self);
+        let center = bounds.center();
+        let extent = bounds.max - bounds.min;
+
+        // Fractions of the bounding box, from 0 to 1, for each axis.
+        let fraction = |v: f32, min: f32, len: f32| if len > 0. { (v - min) / len } else { 0.5 };
+
+        for vertex in &mut self.vertices {
+            let p = Vec3::new(vertex.position[0], vertex.position[1], vertex.position[2]);
+            let fx = fraction(p.x, bounds.min.x, extent.x);
+            let fy = fraction(p.y, bounds.min.y, extent.y);
+            let fz = fraction(p.z, bounds.min.z, extent.z);
+
+            let dir = p - center;
+            let angle_u = dir.z.atan2(dir.x) / TAU + 0.5;
+            let n = vertex.normal;
+
+            vertex.tex_coords = match projection {
+                UvProjection::Spherical => {
+                    let dir = normalized_or(dir, UP_VEC);
+                    [angle_u, dir.y.clamp(-1., 1.).acos() / (TAU / 2.)]
+                }
+                UvProjection::Cylindrical => {
+                    if n.y.abs() > n.x.abs().max(n.z.abs()) {
+                        [fx, fz]
+                    } else {
+                        [angle_u, 1. - fy]
+                    }
+                }
+                UvProjection::Box => {
+                    let (ax, ay, az) = (n.x.abs(), n.y.abs(), n.z.abs());
+                    if ax >= ay && ax >= az {
+                        [fz, 1. - fy]
+                    } else if ay >= az {
+                        [fx, fz]
+                    } else {
+                        [fx, 1. - fy]
+                    }
+                }
+            };
+        }
+
+        if projection != UvProjection::Box {
+            self.split_uv_seam();
+        }
+    }
+
+    /// For triangles that cross the seam of a wrapping projection, use copies of their vertices
+    /// with low U values, offset by 1, so they don't interpolate across the whole texture.
+    fn split_uv_seam(&mut self) {
+        let mut copies: HashMap<usize, usize> = HashMap::new();
+
+        for tri in self.indices.chunks_exact_mut(3) {
+            let u = [tri[0], tri[1], tri[2]].map(|i| self.vertices[i].tex_coords[0]);
+            let u_min = u.iter().copied().fold(f32::MAX, f32::min);
+            let u_max = u.iter().copied().fold(f32::MIN, f32::max);
+
+            if u_max - u_min <= 0.5 {
+                continue;
+            }
+
+            for index in tri {
+                if self.vertices[*index].tex_coords[0] >= 0.5 {
+                    continue;
+                }
+
+                *index = *copies.entry(*index).or_insert_with(|| {
+                    let mut vertex = self.vertices[*index];
+                    vertex.tex_coords[0] += 1.;
+                    self.vertices.push(vertex);
+                    self.vertices.len() - 1
+                });
+            }
         }
     }
 }
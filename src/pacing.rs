@@ -0,0 +1,90 @@
+//! Frame pacing, for when presenting without vsync. Rendering as fast as possible wastes power,
+//! and gives uneven frame times, since most frames are never displayed. When
+//! `GraphicsSettings::frame_pacing` is set, we instead schedule each redraw one refresh interval
+//! of the window's current monitor after the previous, and let the event loop sleep in between.
+//!
+//! The monitor's refresh rate is re-queried when the window moves, eg to a different monitor, and
+//! is available to applications as `FrameStats::display_hz`, eg to pick a fixed simulation step.
+
+use std::time::{Duration, Instant};
+
+use winit::window::Window;
+
+#[derive(Default)]
+pub(crate) struct FramePacer {
+    /// Time between refreshes of the window's monitor. `None` if the platform doesn't report it.
+    interval: Option<Duration>,
+    /// When the most recently scheduled frame is due.
+    next_frame: Option<Instant>,
+    /// True if a frame is scheduled, and its redraw hasn't been requested yet.
+    pending: bool,
+}
+
+impl FramePacer {
+    /// Query the refresh rate of the monitor `window` is on. Returns it in Hz, if known.
+    pub fn update_monitor(&mut self, window: &Window) -> Option<f32> {
+        let millihertz = window
+            .current_monitor()
+            .and_then(|m| m.refresh_rate_millihertz())
+            .filter(|&mhz| mhz > 0);
+
+        self.interval = millihertz.map(|mhz| Duration::from_secs_f64(1_000. / mhz as f64));
+        millihertz.map(|mhz| mhz as f32 / 1_000.)
+    }
+
+    /// Schedule the frame after one that just started rendering at `frame_start`. Returns false if
+    /// the refresh rate is unknown, in which case the next frame should be requested immediately.
+    pub fn schedule(&mut self, frame_start: Instant) -> bool {
+        let Some(interval) = self.interval else {
+            self.clear();
+            return false;
+        };
+
+        // Keep the same phase as previous frames, so frame times don't drift from the display's.
+        // If we've fallen behind, eg from a slow frame, skip the missed intervals.
+        let mut next = match self.next_frame {
+            Some(prev) if frame_start < prev + interval * 2 => prev + interval,
+            _ => frame_start + interval,
+        };
+        let now = Instant::now();
+        if next < now {
+            let missed = (now - next).as_secs_f64() / interval.as_secs_f64();
+            next += interval.mul_f64(missed.ceil());
+        }
+
+        self.next_frame = Some(next);
+        self.pending = true;
+        true
+    }
+
+    /// Schedule the next frame for `time`, eg when the GUI next needs repainting.
+    pub fn schedule_at(&mut self, time: Instant) {
+        self.next_frame = Some(time);
+        self.pending = true;
+    }
+
+    /// Stop pacing, eg when vsync is enabled.
+    pub fn clear(&mut self) {
+        self.next_frame = None;
+        self.pending = false;
+    }
+
+    /// True if the scheduled frame is due, in which case its redraw should be requested. Only
+    /// returns true once per frame.
+    pub fn frame_due(&mut self) -> bool {
+        let due = self.pending && self.next_frame.is_some_and(|next| next <= Instant::now());
+        if due {
+            self.pending = false;
+        }
+        due
+    }
+
+    /// When the event loop should wake, to request the scheduled frame. `None` if there isn't one.
+    pub fn wait_until(&self) -> Option<Instant> {
+        if self.pending {
+            self.next_frame
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Access to the packed instance array before it's uploaded to the GPU, for `Scene::pre_upload`.
+//! This lets applications apply bulk changes to instances in one place, eg wrapping positions
+//! into a periodic box, without changing entities.
+//!
+//! Changes only affect rendering, and things read from the GPU instance buffer, eg occlusion
+//! culling and `EngineUpdates::gpu_pick`; `Scene::raycast` and other CPU queries use entities.
+//! TAA velocity is computed from entities, so instances moved here may blur for a frame.
+
+use lin_alg::f32::{Mat4, Vec3};
+
+use crate::types::{
+    Instance, F32_SIZE, INSTANCE_COLOR_START, INSTANCE_SIZE, INSTANCE_UV_START, MAT4_SIZE,
+};
+
+/// The instance array, in the layout uploaded to the GPU: `INSTANCE_SIZE` bytes per instance,
+/// starting with the model matrix, in column-major order. Instances of each mesh are contiguous.
+pub struct PackedInstances<'a> {
+    data: &'a mut [u8],
+    /// The instance of each entity; `None` if hidden.
+    entity_instances: &'a [Option<usize>],
+}
+
+impl<'a> PackedInstances<'a> {
+    pub(crate) fn new(data: &'a mut [u8], entity_instances: &'a [Option<usize>]) -> Self {
+        Self {
+            data,
+            entity_instances,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len() / INSTANCE_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The index of an entity's instance. `None` if the entity is hidden, or doesn't exist.
+    pub fn entity_instance(&self, entity: usize) -> Option<usize> {
+        self.entity_instances.get(entity).copied().flatten()
+    }
+
+    /// The raw bytes of all instances, eg for bulk changes not covered by other methods.
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        self.data
+    }
+
+    fn f32(&self, i: usize, offset: usize) -> f32 {
+        let start = i * INSTANCE_SIZE + offset;
+        f32::from_ne_bytes(self.data[start..start + F32_SIZE].try_into().unwrap())
+    }
+
+    fn set_f32(&mut self, i: usize, offset: usize, v: f32) {
+        let start = i * INSTANCE_SIZE + offset;
+        self.data[start..start + F32_SIZE].clone_from_slice(&v.to_ne_bytes());
+    }
+
+    /// Replace an instance. Panics if `i` is out of bounds.
+    pub fn set(&mut self, i: usize, instance: &Instance) {
+        self.data[i * INSTANCE_SIZE..(i + 1) * INSTANCE_SIZE]
+            .clone_from_slice(&instance.to_bytes());
+    }
+
+    /// An instance's model matrix. Panics if `i` is out of bounds.
+    pub fn model_mat(&self, i: usize) -> Mat4 {
+        let mut data = [0.; 16];
+        for (j, v) in data.iter_mut().enumerate() {
+            *v = self.f32(i, j * F32_SIZE);
+        }
+        Mat4 { data }
+    }
+
+    /// Set an instance's model matrix. This doesn't update the normal matrix used for lighting,
+    /// so only translation and uniform scale should change; use `set` to change orientation.
+    pub fn set_model_mat(&mut self, i: usize, mat: &Mat4) {
+        self.data[i * INSTANCE_SIZE..i * INSTANCE_SIZE + MAT4_SIZE]
+            .clone_from_slice(&mat.to_bytes());
+    }
+
+    /// An instance's position: the translation of its model matrix.
+    pub fn position(&self, i: usize) -> Vec3 {
+        Vec3::new(
+            self.f32(i, 12 * F32_SIZE),
+            self.f32(i, 13 * F32_SIZE),
+            self.f32(i, 14 * F32_SIZE),
+        )
+    }
+
+    pub fn set_position(&mut self, i: usize, position: Vec3) {
+        self.set_f32(i, 12 * F32_SIZE, position.x);
+        self.set_f32(i, 13 * F32_SIZE, position.y);
+        self.set_f32(i, 14 * F32_SIZE, position.z);
+    }
+
+    /// An instance's color, and opacity.
+    pub fn color(&self, i: usize) -> (Vec3, f32) {
+        let color = Vec3::new(
+            self.f32(i, INSTANCE_COLOR_START),
+            self.f32(i, INSTANCE_COLOR_START + F32_SIZE),
+            self.f32(i, INSTANCE_COLOR_START + 2 * F32_SIZE),
+        );
+        (color, self.f32(i, INSTANCE_COLOR_START + 3 * F32_SIZE))
+    }
+
+    pub fn set_color(&mut self, i: usize, color: Vec3, opacity: f32) {
+        self.set_f32(i, INSTANCE_COLOR_START, color.x);
+        self.set_f32(i, INSTANCE_COLOR_START + F32_SIZE, color.y);
+        self.set_f32(i, INSTANCE_COLOR_START + 2 * F32_SIZE, color.z);
+        self.set_f32(i, INSTANCE_COLOR_START + 3 * F32_SIZE, opacity);
+    }
+
+    /// An instance's texture coordinate offset, and scale. See `Entity::uv_offset`.
+    pub fn uv_transform(&self, i: usize) -> ((f32, f32), (f32, f32)) {
+        (
+            (
+                self.f32(i, INSTANCE_UV_START),
+                self.f32(i, INSTANCE_UV_START + F32_SIZE),
+            ),
+            (
+                self.f32(i, INSTANCE_UV_START + 2 * F32_SIZE),
+                self.f32(i, INSTANCE_UV_START + 3 * F32_SIZE),
+            ),
+        )
+    }
+
+    pub fn set_uv_transform(&mut self, i: usize, offset: (f32, f32), scale: (f32, f32)) {
+        self.set_f32(i, INSTANCE_UV_START, offset.0);
+        self.set_f32(i, INSTANCE_UV_START + F32_SIZE, offset.1);
+        self.set_f32(i, INSTANCE_UV_START + 2 * F32_SIZE, scale.0);
+        self.set_f32(i, INSTANCE_UV_START + 3 * F32_SIZE, scale.1);
+    }
+}
@@ -0,0 +1,275 @@
+//! Splitting per-frame CPU work across threads, for scenes with many meshes. Meshes are
+//! partitioned into contiguous ranges; each thread builds instances for, and encodes draw calls
+//! for, one range. Results are combined in mesh order, so the output matches what a single
+//! thread would produce.
+//!
+//! Draw calls are recorded into render bundles, which wgpu allows encoding in parallel; these are
+//! executed in the main render pass. Bundles are kept across frames, and only re-recorded when
+//! the instances, meshes, pipelines, or bind groups they reference change.
+
+use std::{ops::Range, thread};
+
+use lin_alg::f32::Mat4;
+use wgpu::{
+    BindGroup, Buffer, Device, RenderBundle, RenderBundleDepthStencil, RenderBundleDescriptor,
+    RenderBundleEncoderDescriptor, TextureFormat,
+};
+
+use crate::{
+    culling::DRAW_ARGS_SIZE,
+    debug::{DebugShapes, Lines},
+    graphics::mesh_culling,
+    material::Material,
+    mesh_cache::MeshRange,
+    pipeline_cache::{MeshPipelines, PipelineKey},
+    types::{Entity, FaceCulling, Instance, Mesh},
+};
+
+/// We don't split work into ranges smaller than this; below it, the cost of spawning threads
+/// outweighs the savings.
+const MIN_MESHES_PER_THREAD: usize = 64;
+
+/// Split `len` items into up to `threads` contiguous ranges of similar size. Always returns at
+/// least one range.
+pub(crate) fn partition(len: usize, threads: usize) -> Vec<Range<usize>> {
+    let count = threads.min(len / MIN_MESHES_PER_THREAD).max(1);
+
+    (0..count)
+        .map(|i| len * i / count..len * (i + 1) / count)
+        .collect()
+}
+
+/// Run `f` on each range, with one thread per range. The first range runs on the calling
+/// thread. Results are in range order.
+pub(crate) fn map_ranges<T: Send>(
+    ranges: &[Range<usize>],
+    f: impl Fn(Range<usize>) -> T + Sync,
+) -> Vec<T> {
+    if ranges.len() <= 1 {
+        return ranges.iter().cloned().map(&f).collect();
+    }
+
+    thread::scope(|s| {
+        let f = &f;
+        let handles: Vec<_> = ranges[1..]
+            .iter()
+            .cloned()
+            .map(|range| s.spawn(move || f(range)))
+            .collect();
+
+        let mut result = vec![f(ranges[0].clone())];
+        for handle in handles {
+            result.push(handle.join().expect("Render thread panicked"));
+        }
+        result
+    })
+}
+
+/// Data shared by threads building instances.
+pub(crate) struct InstanceInputs<'a> {
+    pub entities: &'a [Entity],
+    /// Entities with group transforms applied; empty if there are no groups.
+    pub grouped: &'a [Option<Entity>],
+    pub meshes: &'a [Mesh],
+    pub materials: &'a [Material],
+    /// Indices of visible entities using each mesh.
+    pub by_mesh: &'a [Vec<usize>],
+    pub debug_shapes: DebugShapes,
+    /// Each entity's model matrix from the last build; present if TAA is enabled.
+    pub prev_model_mats: Option<&'a [Mat4]>,
+}
+
+/// Instances built for a range of meshes.
+#[derive(Default)]
+pub(crate) struct InstanceChunk {
+    /// Serialized instances, in mesh, then entity order.
+    pub data: Vec<u8>,
+    pub debug_lines: Lines,
+    /// Used for TAA velocity. In instance order.
+    pub prev_models: Vec<Mat4>,
+    /// Used for TAA velocity. (entity index, model matrix)
+    pub model_mats: Vec<(usize, Mat4)>,
+    /// If any instance moved since the last build. Only set if TAA is enabled.
+    pub motion: bool,
+}
+
+impl InstanceInputs<'_> {
+    /// Build instances for the entities using a range of meshes.
+    pub fn build(&self, meshes: Range<usize>) -> InstanceChunk {
+        let mut result = InstanceChunk::default();
+
+        for mesh_i in meshes {
+            for &i_ent in &self.by_mesh[mesh_i] {
+                let entity = match self.grouped.get(i_ent) {
+                    Some(Some(e)) => e,
+                    _ => &self.entities[i_ent],
+                };
+
+                let shapes = self.debug_shapes.combine(entity.debug);
+                if shapes.any() {
+                    result
+                        .debug_lines
+                        .entity_shapes(entity, &self.meshes[mesh_i], shapes);
+                }
+
+                let mut instance = Instance::from_entity(entity);
+                instance.apply_material(&self.meshes[mesh_i], self.materials);
+
+                if let Some(prev_model_mats) = self.prev_model_mats {
+                    let model_mat = instance.model_mat();
+
+                    // If the entity count changed, we can't match entities to their previous
+                    // transforms, so we treat them as stationary.
+                    let prev = if prev_model_mats.len() == self.entities.len() {
+                        prev_model_mats[i_ent].clone()
+                    } else {
+                        model_mat.clone()
+                    };
+
+                    result.motion |= prev.data != model_mat.data;
+                    result.prev_models.push(prev);
+                    result.model_mats.push((i_ent, model_mat));
+                }
+
+                result.data.extend_from_slice(&instance.to_bytes());
+            }
+        }
+
+        result
+    }
+}
+
+/// Build instances for all meshes, splitting them across up to `threads` threads.
+pub(crate) fn build_instances(inputs: &InstanceInputs, threads: usize) -> InstanceChunk {
+    let ranges = partition(inputs.by_mesh.len(), threads);
+    let mut chunks = map_ranges(&ranges, |range| inputs.build(range)).into_iter();
+
+    let mut result = chunks.next().unwrap_or_default();
+    for chunk in chunks {
+        result.data.extend_from_slice(&chunk.data);
+        result
+            .debug_lines
+            .vertices
+            .extend_from_slice(&chunk.debug_lines.vertices);
+        result.prev_models.extend(chunk.prev_models);
+        result.model_mats.extend(chunk.model_mats);
+        result.motion |= chunk.motion;
+    }
+
+    result
+}
+
+/// What a set of render bundles was recorded for. Bundles recorded for one key can't be executed
+/// in a pass set up for another.
+#[derive(Clone, PartialEq)]
+pub(crate) struct BundleKey {
+    pub ranges: Vec<Range<usize>>,
+    pub pipeline_key: PipelineKey,
+    /// If the bundles draw occlusion-culled instances, using indirect draws.
+    pub culled: bool,
+}
+
+/// Render bundles recorded by `DrawInputs::encode`, reused across frames. Extra views may render
+/// without culling or TAA, so we keep a set of bundles for each key drawn with.
+#[derive(Default)]
+pub(crate) struct BundleCache {
+    entries: Vec<(BundleKey, Vec<RenderBundle>)>,
+}
+
+impl BundleCache {
+    /// Discard all bundles. Call this when anything they reference is replaced or changes
+    /// size, eg buffers, bind groups, pipelines, or instance counts.
+    pub fn invalidate(&mut self) {
+        self.entries.clear();
+    }
+
+    /// The bundles recorded for `key`, recording them with `record` if there are none.
+    pub fn get_or_record(
+        &mut self,
+        key: BundleKey,
+        record: impl FnOnce() -> Vec<RenderBundle>,
+    ) -> &[RenderBundle] {
+        let i = match self.entries.iter().position(|(k, _)| *k == key) {
+            Some(i) => i,
+            None => {
+                self.entries.push((key, record()));
+                self.entries.len() - 1
+            }
+        };
+
+        &self.entries[i].1
+    }
+}
+
+/// Data shared by threads encoding draw calls.
+pub(crate) struct DrawInputs<'a> {
+    pub pipelines: MeshPipelines<'a>,
+    /// For each mesh's culling mode.
+    pub meshes: &'a [Mesh],
+    /// In order of bind group index.
+    pub bind_groups: &'a [&'a BindGroup],
+    pub color_formats: &'a [Option<TextureFormat>],
+    pub depth_format: TextureFormat,
+    pub vertex_buf: &'a Buffer,
+    pub index_buf: &'a Buffer,
+    pub instance_buf: &'a Buffer,
+    /// Present if occlusion culling is enabled; indirect draw arguments for each mesh.
+    pub indirect_buf: Option<&'a Buffer>,
+    pub mesh_ranges: &'a [MeshRange],
+    /// Instance start and count, for each mesh.
+    pub mesh_mappings: &'a [(u32, u32)],
+}
+
+impl DrawInputs<'_> {
+    /// Record draw calls for a range of meshes into a render bundle.
+    pub fn encode(&self, device: &Device, range: Range<usize>) -> RenderBundle {
+        let mut encoder = device.create_render_bundle_encoder(&RenderBundleEncoderDescriptor {
+            label: Some("Mesh render bundle"),
+            color_formats: self.color_formats,
+            depth_stencil: Some(RenderBundleDepthStencil {
+                format: self.depth_format,
+                depth_read_only: false,
+                stencil_read_only: true,
+            }),
+            sample_count: 1,
+            multiview: None,
+        });
+
+        let mut current = FaceCulling::Back;
+        encoder.set_pipeline(self.pipelines.get(current));
+        for (i, bind_group) in self.bind_groups.iter().enumerate() {
+            encoder.set_bind_group(i as u32, *bind_group, &[]);
+        }
+
+        encoder.set_vertex_buffer(0, self.vertex_buf.slice(..));
+        encoder.set_vertex_buffer(1, self.instance_buf.slice(..));
+        encoder.set_index_buffer(self.index_buf.slice(..), wgpu::IndexFormat::Uint32);
+
+        for i in range {
+            let culling = mesh_culling(self.meshes, i);
+            if let Some(pipeline) = self.pipelines.switch(&mut current, culling) {
+                encoder.set_pipeline(pipeline);
+            }
+
+            match self.indirect_buf {
+                Some(indirect_buf) => {
+                    encoder.draw_indexed_indirect(indirect_buf, (i * DRAW_ARGS_SIZE) as u64);
+                }
+                None => {
+                    let mesh_range = &self.mesh_ranges[i];
+                    let (instance_start, instance_count) = self.mesh_mappings[i];
+
+                    encoder.draw_indexed(
+                        mesh_range.index_start..mesh_range.index_start + mesh_range.index_count,
+                        mesh_range.vertex_start,
+                        instance_start..instance_start + instance_count,
+                    );
+                }
+            }
+        }
+
+        encoder.finish(&RenderBundleDescriptor {
+            label: Some("Mesh render bundle"),
+        })
+    }
+}
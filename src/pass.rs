@@ -0,0 +1,36 @@
+//! Inputs shared by the functions that encode a frame's passes. `PassContext` is what the frame is
+//! encoded with; `MeshBuffers` is the engine's geometry, for passes that draw or read it. Both are
+//! cheap to copy.
+
+use wgpu::{Buffer, Device, Queue, TextureView};
+
+use crate::{mesh_cache::MeshRange, types::Mesh};
+
+/// The device and queue, the texture being rendered to, and the frame's timing.
+#[derive(Clone, Copy)]
+pub(crate) struct PassContext<'a> {
+    pub device: &'a Device,
+    pub queue: &'a Queue,
+    pub output_texture: &'a TextureView,
+    /// The size of `output_texture`, in pixels.
+    pub width: u32,
+    pub height: u32,
+    /// The portion of `output_texture` used for 3D rendering: x, y, width, and height, in pixels.
+    pub viewport: (f32, f32, f32, f32),
+    /// The time since the previous frame, in seconds.
+    pub dt: f32,
+}
+
+/// The vertex, index, and instance buffers meshes are drawn from.
+#[derive(Clone, Copy)]
+pub(crate) struct MeshBuffers<'a> {
+    pub vertex_buf: &'a Buffer,
+    pub index_buf: &'a Buffer,
+    pub instance_buf: &'a Buffer,
+    /// The location of each mesh in `vertex_buf` and `index_buf`.
+    pub mesh_ranges: &'a [MeshRange],
+    /// The start and count of each mesh's instances in `instance_buf`.
+    pub mesh_mappings: &'a [(u32, u32)],
+    /// Used to select pipelines by face culling.
+    pub meshes: &'a [Mesh],
+}
@@ -0,0 +1,765 @@
+//! Changing a running scene from outside the application, eg from a remote controller over a
+//! socket, or a scripting layer. A `ScenePatch` is a list of operations on entities, point lights,
+//! and the camera. It has a compact binary encoding, with `to_bytes` and `from_bytes`, so it can be
+//! sent between processes. Apply received patches with `Scene::apply_patch` in a handler, and
+//! return the updates it produces.
+//!
+//! Entities and lights are identified by index. Removing one moves the last one into its place, as
+//! with `Vec::swap_remove`; later operations in the patch see the new indices. Entity handles
+//! (`Scene::entity_handles`) move with their entities. Groups, labels, transitions, light paths,
+//! and timeline keyframes referencing moved indices aren't updated.
+//!
+//! The encoding is little-endian: a version byte (`PATCH_VERSION`), the operation count as a `u32`,
+//! then each operation, as a tag byte followed by its fields.
+
+use lin_alg::f32::{Quaternion, Vec3};
+
+use crate::{
+    binary::{Reader, Writer},
+    layers::RenderLayer,
+    lighting::{LightType, PointLight},
+    types::{EngineUpdates, Entity, LightingFactors, RenderProps, Scene, Transform},
+};
+
+/// The version of the binary encoding. Patches with a different version can't be decoded.
+pub const PATCH_VERSION: u8 = 3;
+
+#[derive(Clone, Debug)]
+pub enum PatchOp {
+    /// Add an entity to the end of `Scene::entities`.
+    AddEntity(Transform, RenderProps),
+    /// Remove an entity. The last entity takes its index.
+    RemoveEntity(usize),
+    /// Set an entity's position, orientation, and scale.
+    SetTransform(usize, Transform),
+    /// Set an entity's appearance. Its debug, static, and picking settings are kept.
+    SetProps(usize, RenderProps),
+    /// Add a light to the end of `Lighting::point_lights`.
+    AddLight(PointLight),
+    /// Remove a point light. The last light takes its index.
+    RemoveLight(usize),
+    /// Replace a point light.
+    SetLight(usize, PointLight),
+    SetCameraView {
+        position: Vec3,
+        orientation: Quaternion,
+    },
+    /// Set the camera's vertical field of view, in radians, and its near and far planes.
+    SetCameraProjection { fov_y: f32, near: f32, far: f32 },
+}
+
+#[derive(Clone, Debug, Default)]
+/// Operations to apply to a scene, in order. See the `patch` module.
+pub struct ScenePatch {
+    pub ops: Vec<PatchOp>,
+}
+
+impl ScenePatch {
+    pub fn new(ops: Vec<PatchOp>) -> Self {
+        Self { ops }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer(vec![PATCH_VERSION]);
+        w.u32(self.ops.len() as u32);
+
+        for op in &self.ops {
+            match op {
+                PatchOp::AddEntity(transform, props) => {
+                    w.u8(0);
+                    w.transform(transform);
+                    w.props(props);
+                }
+                PatchOp::RemoveEntity(i) => {
+                    w.u8(1);
+                    w.u32(*i as u32);
+                }
+                PatchOp::SetTransform(i, transform) => {
+                    w.u8(2);
+                    w.u32(*i as u32);
+                    w.transform(transform);
+                }
+                PatchOp::SetProps(i, props) => {
+                    w.u8(3);
+                    w.u32(*i as u32);
+                    w.props(props);
+                }
+                PatchOp::AddLight(light) => {
+                    w.u8(4);
+                    w.light(light);
+                }
+                PatchOp::RemoveLight(i) => {
+                    w.u8(5);
+                    w.u32(*i as u32);
+                }
+                PatchOp::SetLight(i, light) => {
+                    w.u8(6);
+                    w.u32(*i as u32);
+                    w.light(light);
+                }
+                PatchOp::SetCameraView {
+                    position,
+                    orientation,
+                } => {
+                    w.u8(7);
+                    w.vec3(*position);
+                    w.quaternion(*orientation);
+                }
+                PatchOp::SetCameraProjection { fov_y, near, far } => {
+                    w.u8(8);
+                    w.f32(*fov_y);
+                    w.f32(*near);
+                    w.f32(*far);
+                }
+            }
+        }
+
+        w.0
+    }
+
+    /// Decode a patch from `to_bytes`. Returns an error if the data is truncated, has trailing
+    /// bytes, or was encoded with a different `PATCH_VERSION`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut r = Reader::new(bytes, "Patch");
+
+        let version = r.u8()?;
+        if version != PATCH_VERSION {
+            return Err(format!("Patch version {version}; expected {PATCH_VERSION}"));
+        }
+
+        let count = r.u32()?;
+        let mut ops = Vec::new();
+
+        for _ in 0..count {
+            let op = match r.u8()? {
+                0 => PatchOp::AddEntity(r.transform()?, r.props()?),
+                1 => PatchOp::RemoveEntity(r.index()?),
+                2 => PatchOp::SetTransform(r.index()?, r.transform()?),
+                3 => PatchOp::SetProps(r.index()?, r.props()?),
+                4 => PatchOp::AddLight(r.light()?),
+                5 => PatchOp::RemoveLight(r.index()?),
+                6 => PatchOp::SetLight(r.index()?, r.light()?),
+                7 => PatchOp::SetCameraView {
+                    position: r.vec3()?,
+                    orientation: r.quaternion()?,
+                },
+                8 => PatchOp::SetCameraProjection {
+                    fov_y: r.f32()?,
+                    near: r.f32()?,
+                    far: r.f32()?,
+                },
+                tag => return Err(format!("Unknown patch operation {tag}")),
+            };
+            ops.push(op);
+        }
+
+        if r.remaining() != 0 {
+            return Err(format!("{} trailing bytes in patch", r.remaining()));
+        }
+
+        Ok(Self { ops })
+    }
+}
+
+impl Writer {
+    fn transform(&mut self, v: &Transform) {
+        self.vec3(v.position);
+        self.quaternion(v.orientation);
+        self.f32(v.scale);
+    }
+
+    fn props(&mut self, v: &RenderProps) {
+        self.u32(v.mesh as u32);
+        for c in [
+            v.color.0,
+            v.color.1,
+            v.color.2,
+            v.opacity,
+            v.shinyness,
+            v.reflectivity,
+        ] {
+            self.f32(c);
+        }
+        self.bool(v.scalar.is_some());
+        if let Some(scalar) = v.scalar {
+            self.f32(scalar);
+        }
+        self.option_u32(v.palette_i.map(|i| i as u32));
+        self.option_u32(v.material.map(|i| i as u32));
+        for c in [
+            v.uv_offset.0,
+            v.uv_offset.1,
+            v.uv_scale.0,
+            v.uv_scale.1,
+            v.texture_tint.0,
+            v.texture_tint.1,
+            v.texture_tint.2,
+        ] {
+            self.f32(c);
+        }
+
+        let factors = &v.lighting_factors;
+        for c in [factors.ambient, factors.diffuse, factors.specular] {
+            self.f32(c);
+        }
+
+        self.bool(v.index_range.is_some());
+        if let Some((start, count)) = v.index_range {
+            self.u32(start);
+            self.u32(count);
+        }
+        self.u8(v.layer.index() as u8);
+        self.i32(v.sort_key);
+    }
+
+    fn light(&mut self, v: &PointLight) {
+        match v.type_ {
+            LightType::Omnidirectional => self.u8(0),
+            LightType::Directional(dir) => {
+                self.u8(1);
+                self.vec3(dir);
+            }
+            LightType::Diffuse => self.u8(2),
+        }
+        self.vec3(v.position);
+        for c in v.diffuse_color.iter().chain(&v.specular_color) {
+            self.f32(*c);
+        }
+        self.f32(v.diffuse_intensity);
+        self.f32(v.specular_intensity);
+        self.bool(v.casts_shadow);
+    }
+}
+
+impl Reader<'_> {
+    fn index(&mut self) -> Result<usize, String> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn transform(&mut self) -> Result<Transform, String> {
+        Ok(Transform {
+            position: self.vec3()?,
+            orientation: self.quaternion()?,
+            scale: self.f32()?,
+        })
+    }
+
+    fn props(&mut self) -> Result<RenderProps, String> {
+        Ok(RenderProps {
+            mesh: self.index()?,
+            color: (self.f32()?, self.f32()?, self.f32()?),
+            opacity: self.f32()?,
+            shinyness: self.f32()?,
+            reflectivity: self.f32()?,
+            scalar: if self.bool()? {
+                Some(self.f32()?)
+            } else {
+                None
+            },
+            palette_i: self.option_u32()?.map(|i| i as usize),
+            material: self.option_u32()?.map(|i| i as usize),
+            uv_offset: (self.f32()?, self.f32()?),
+            uv_scale: (self.f32()?, self.f32()?),
+            texture_tint: (self.f32()?, self.f32()?, self.f32()?),
+            lighting_factors: LightingFactors {
+                ambient: self.f32()?,
+                diffuse: self.f32()?,
+                specular: self.f32()?,
+            },
+            index_range: if self.bool()? {
+                Some((self.u32()?, self.u32()?))
+            } else {
+                None
+            },
+            layer: match RenderLayer::ALL.get(self.u8()? as usize) {
+                Some(layer) => *layer,
+                None => return Err("Invalid render layer".to_owned()),
+            },
+            sort_key: self.i32()?,
+        })
+    }
+
+    fn light(&mut self) -> Result<PointLight, String> {
+        let type_ = match self.u8()? {
+            0 => LightType::Omnidirectional,
+            1 => LightType::Directional(self.vec3()?),
+            2 => LightType::Diffuse,
+            tag => return Err(format!("Unknown light type {tag}")),
+        };
+
+        Ok(PointLight {
+            type_,
+            position: self.vec3()?,
+            diffuse_color: [self.f32()?, self.f32()?, self.f32()?, self.f32()?],
+            specular_color: [self.f32()?, self.f32()?, self.f32()?, self.f32()?],
+            diffuse_intensity: self.f32()?,
+            specular_intensity: self.f32()?,
+            casts_shadow: self.bool()?,
+        })
+    }
+}
+
+/// An entity from a transform and appearance, keeping settings patches don't cover from `prev`.
+fn patched_entity(transform: &Transform, props: &RenderProps, prev: Option<&Entity>) -> Entity {
+    let mut result = Entity::new(
+        props.mesh,
+        transform.position,
+        transform.orientation,
+        transform.scale,
+        props.color,
+        props.shinyness,
+    );
+
+    result.opacity = props.opacity;
+    result.reflectivity = props.reflectivity;
+    result.palette_i = props.palette_i;
+    result.material = props.material;
+    result.scalar = props.scalar;
+    result.uv_offset = props.uv_offset;
+    result.uv_scale = props.uv_scale;
+    result.texture_tint = props.texture_tint;
+    result.lighting_factors = props.lighting_factors;
+    result.index_range = props.index_range;
+    result.layer = props.layer;
+    result.sort_key = props.sort_key;
+
+    if let Some(prev) = prev {
+        result.debug = prev.debug;
+        result.is_static = prev.is_static;
+        result.pickable = prev.pickable;
+    }
+
+    result
+}
+
+fn check_index(op_i: usize, kind: &str, i: usize, count: usize) -> Result<(), String> {
+    if i >= count {
+        return Err(format!("Operation {op_i}: {kind} {i} doesn't exist"));
+    }
+    Ok(())
+}
+
+impl Scene {
+    /// Apply a patch, eg received from a remote controller, and return the updates it requires;
+    /// return them from your handler. The patch is checked first, so if any operation references
+    /// an entity, light, mesh, material, or palette color that doesn't exist, or an index range
+    /// past the end of its mesh, nothing is applied, and an error is returned.
+    pub fn apply_patch(&mut self, patch: &ScenePatch) -> Result<EngineUpdates, String> {
+        self.check_patch(patch)?;
+
+        let mut result = EngineUpdates::default();
+
+        for op in &patch.ops {
+            match op {
+                PatchOp::AddEntity(transform, props) => {
+                    self.entities.push(patched_entity(transform, props, None));
+                    result.entities = true;
+                }
+                PatchOp::RemoveEntity(i) => {
+                    // Keep handles matched to entities, for `sync_entities`. If they already
+                    // don't match, eg after adding an entity, which has no handle, they're
+                    // discarded, so the next sync rebuilds entities.
+                    if self.entity_handles.len() == self.entities.len() {
+                        self.entity_handles.swap_remove(*i);
+                    } else {
+                        self.entity_handles.clear();
+                    }
+                    self.entities.swap_remove(*i);
+                    // Static entities' indices may have changed.
+                    result.entities = true;
+                    result.static_entities = true;
+                }
+                PatchOp::SetTransform(i, transform) => {
+                    let entity = &mut self.entities[*i];
+                    entity.position = transform.position;
+                    entity.orientation = transform.orientation;
+                    entity.scale = transform.scale;
+
+                    if entity.is_static {
+                        result.static_entities = true;
+                    } else {
+                        result.changed_entities.push(*i);
+                    }
+                }
+                PatchOp::SetProps(i, props) => {
+                    let entity = &mut self.entities[*i];
+                    let transform = Transform {
+                        position: entity.position,
+                        orientation: entity.orientation,
+                        scale: entity.scale,
+                    };
+                    let updated = patched_entity(&transform, props, Some(entity));
+
+                    // As in `sync_entities`: these change how, or in what order, it's drawn.
+                    if updated.mesh != entity.mesh
+                        || updated.index_range != entity.index_range
+                        || updated.layer != entity.layer
+                        || updated.sort_key != entity.sort_key
+                    {
+                        result.entities = true;
+                    }
+                    if entity.is_static {
+                        result.static_entities = true;
+                    } else {
+                        result.changed_entities.push(*i);
+                    }
+
+                    *entity = updated;
+                }
+                PatchOp::AddLight(light) => {
+                    self.lighting.point_lights.push(light.clone());
+                    result.lighting = true;
+                }
+                PatchOp::RemoveLight(i) => {
+                    self.lighting.point_lights.swap_remove(*i);
+                    result.lighting = true;
+                }
+                PatchOp::SetLight(i, light) => {
+                    let prev = &mut self.lighting.point_lights[*i];
+                    // Shadow maps are assigned when lighting is written.
+                    if light.casts_shadow != prev.casts_shadow {
+                        result.lighting = true;
+                    }
+                    *prev = light.clone();
+                    result.changed_lights.push(*i);
+                }
+                PatchOp::SetCameraView {
+                    position,
+                    orientation,
+                } => {
+                    self.camera.position = *position;
+                    self.camera.orientation = *orientation;
+                    result.camera_view = true;
+                }
+                PatchOp::SetCameraProjection { fov_y, near, far } => {
+                    self.camera.fov_y = *fov_y;
+                    self.camera.near = *near;
+                    self.camera.far = *far;
+                    result.camera_projection = true;
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Check that each operation's indices are valid, accounting for earlier additions and
+    /// removals.
+    fn check_patch(&self, patch: &ScenePatch) -> Result<(), String> {
+        let mut num_entities = self.entities.len();
+        let mut num_lights = self.lighting.point_lights.len();
+
+        for (op_i, op) in patch.ops.iter().enumerate() {
+            match op {
+                PatchOp::AddEntity(_, props) => {
+                    self.check_props(op_i, props)?;
+                    num_entities += 1;
+                }
+                PatchOp::RemoveEntity(i) => {
+                    check_index(op_i, "entity", *i, num_entities)?;
+                    num_entities -= 1;
+                }
+                PatchOp::SetTransform(i, _) => check_index(op_i, "entity", *i, num_entities)?,
+                PatchOp::SetProps(i, props) => {
+                    check_index(op_i, "entity", *i, num_entities)?;
+                    self.check_props(op_i, props)?;
+                }
+                PatchOp::AddLight(_) => num_lights += 1,
+                PatchOp::RemoveLight(i) => {
+                    check_index(op_i, "light", *i, num_lights)?;
+                    num_lights -= 1;
+                }
+                PatchOp::SetLight(i, _) => check_index(op_i, "light", *i, num_lights)?,
+                PatchOp::SetCameraView { .. } | PatchOp::SetCameraProjection { .. } => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that an entity's appearance references a mesh, material, and palette color that
+    /// exist, and that its index range is within its mesh.
+    fn check_props(&self, op_i: usize, props: &RenderProps) -> Result<(), String> {
+        check_index(op_i, "mesh", props.mesh, self.meshes.len())?;
+
+        if let Some(i) = props.material {
+            check_index(op_i, "material", i, self.materials.len())?;
+        }
+        if let Some(i) = props.palette_i {
+            let num_colors = self
+                .palettes
+                .get(self.active_palette)
+                .map_or(0, |p| p.colors.len());
+            check_index(op_i, "palette color", i, num_colors)?;
+        }
+
+        if let Some((start, count)) = props.index_range {
+            let num_indices = self.meshes[props.mesh].indices.len();
+            if start as usize + count as usize > num_indices {
+                return Err(format!(
+                    "Operation {op_i}: index range {start}, {count} is past the end of mesh {}, \
+                     with {num_indices} indices",
+                    props.mesh
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        material::{Material, MaterialImage},
+        types::{Mesh, Palette},
+    };
+
+    fn light() -> PointLight {
+        PointLight {
+            type_: LightType::Directional(Vec3::new(0., -1., 0.)),
+            position: Vec3::new(1., 2., 3.),
+            diffuse_color: [1., 0.5, 0.25, 1.],
+            specular_color: [1., 1., 1., 0.5],
+            diffuse_intensity: 10.,
+            specular_intensity: 5.,
+            casts_shadow: true,
+        }
+    }
+
+    fn transform() -> Transform {
+        Transform {
+            position: Vec3::new(1., 2., 3.),
+            orientation: Quaternion::new(0., 1., 0., 0.),
+            scale: 2.,
+        }
+    }
+
+    /// A patch with each operation, and optional fields both set and unset.
+    fn patch() -> ScenePatch {
+        let mut props = RenderProps {
+            color: (1., 0.5, 0.25),
+            scalar: Some(0.5),
+            palette_i: Some(1),
+            material: Some(0),
+            index_range: Some((3, 6)),
+            layer: RenderLayer::Overlay,
+            sort_key: -4,
+            ..Default::default()
+        };
+        props.lighting_factors.specular = 0.;
+
+        ScenePatch::new(vec![
+            PatchOp::AddEntity(transform(), props),
+            PatchOp::AddEntity(transform(), RenderProps::default()),
+            PatchOp::SetTransform(0, transform()),
+            PatchOp::SetProps(1, props),
+            PatchOp::RemoveEntity(0),
+            PatchOp::AddLight(light()),
+            PatchOp::SetLight(0, light()),
+            PatchOp::RemoveLight(0),
+            PatchOp::SetCameraView {
+                position: Vec3::new(0., 1., 2.),
+                orientation: Quaternion::new_identity(),
+            },
+            PatchOp::SetCameraProjection {
+                fov_y: 1.,
+                near: 0.1,
+                far: 100.,
+            },
+        ])
+    }
+
+    /// A scene the patch from `patch` applies to.
+    fn scene() -> Scene {
+        let mut scene = Scene {
+            meshes: vec![Mesh::new_box(1., 1., 1.)],
+            materials: vec![Material::new(MaterialImage::Rgba8 {
+                width: 1,
+                height: 1,
+                data: vec![255; 4],
+            })],
+            palettes: vec![Palette::new("Test", vec![[1.; 4]; 2])],
+            ..Default::default()
+        };
+        scene.lighting.point_lights.clear();
+        scene
+    }
+
+    #[test]
+    fn patch_round_trip() {
+        let bytes = patch().to_bytes();
+        let decoded = ScenePatch::from_bytes(&bytes).unwrap();
+
+        assert_eq!(decoded.to_bytes(), bytes);
+        assert_eq!(decoded.ops.len(), patch().ops.len());
+
+        let PatchOp::AddEntity(transform, props) = &decoded.ops[0] else {
+            panic!("Expected an added entity");
+        };
+        assert_eq!(transform.scale, 2.);
+        assert_eq!(props.scalar, Some(0.5));
+        assert_eq!(props.index_range, Some((3, 6)));
+        assert_eq!(props.layer, RenderLayer::Overlay);
+        assert_eq!(props.sort_key, -4);
+
+        let PatchOp::AddLight(light) = &decoded.ops[5] else {
+            panic!("Expected an added light");
+        };
+        assert!(matches!(light.type_, LightType::Directional(d) if d.y == -1.));
+        assert!(light.casts_shadow);
+    }
+
+    #[test]
+    fn truncated_patches() {
+        let bytes = patch().to_bytes();
+
+        for len in 0..bytes.len() {
+            assert!(
+                ScenePatch::from_bytes(&bytes[..len]).is_err(),
+                "Length {len}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_patches() {
+        let bytes = patch().to_bytes();
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            ScenePatch::from_bytes(&trailing).unwrap_err(),
+            "1 trailing bytes in patch"
+        );
+
+        let mut version = bytes.clone();
+        version[0] = PATCH_VERSION + 1;
+        assert!(ScenePatch::from_bytes(&version).is_err());
+
+        // An operation count larger than the data.
+        let mut count = bytes.clone();
+        count[1..5].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(ScenePatch::from_bytes(&count).is_err());
+
+        let mut tag = bytes.clone();
+        tag[5] = 200;
+        assert_eq!(
+            ScenePatch::from_bytes(&tag).unwrap_err(),
+            "Unknown patch operation 200"
+        );
+
+        let mut w = Writer(vec![PATCH_VERSION]);
+        w.u32(1);
+        w.u8(4);
+        w.u8(9);
+        assert_eq!(
+            ScenePatch::from_bytes(&w.0).unwrap_err(),
+            "Unknown light type 9"
+        );
+    }
+
+    #[test]
+    fn corrupt_patches_dont_panic() {
+        let bytes = patch().to_bytes();
+
+        for i in 0..bytes.len() {
+            let mut corrupt = bytes.clone();
+            corrupt[i] ^= 0xff;
+
+            if let Ok(patch) = ScenePatch::from_bytes(&corrupt) {
+                let _ = scene().apply_patch(&patch);
+            }
+        }
+    }
+
+    #[test]
+    fn apply_patch() {
+        let mut scene = scene();
+        let updates = scene.apply_patch(&patch()).unwrap();
+
+        // The second entity took the first's index when it was removed.
+        assert_eq!(scene.entities.len(), 1);
+        assert_eq!(scene.entities[0].sort_key, -4);
+        assert!(scene.lighting.point_lights.is_empty());
+        assert_eq!(scene.camera.far, 100.);
+        assert!(updates.entities && updates.lighting);
+        assert!(updates.camera_view && updates.camera_projection);
+    }
+
+    #[test]
+    fn check_patch_indices() {
+        let mut scene = scene();
+
+        // Indices account for earlier additions and removals.
+        let added = ScenePatch::new(vec![
+            PatchOp::AddEntity(transform(), RenderProps::default()),
+            PatchOp::SetTransform(0, transform()),
+        ]);
+        assert!(scene.check_patch(&added).is_ok());
+
+        let removed = ScenePatch::new(vec![
+            PatchOp::AddLight(light()),
+            PatchOp::RemoveLight(0),
+            PatchOp::SetLight(0, light()),
+        ]);
+        assert_eq!(
+            scene.check_patch(&removed).unwrap_err(),
+            "Operation 2: light 0 doesn't exist"
+        );
+
+        // Nothing is applied if any operation is invalid.
+        let invalid = ScenePatch::new(vec![
+            PatchOp::AddEntity(transform(), RenderProps::default()),
+            PatchOp::RemoveEntity(1),
+        ]);
+        assert!(scene.apply_patch(&invalid).is_err());
+        assert!(scene.entities.is_empty());
+    }
+
+    #[test]
+    fn check_patch_props() {
+        let scene = scene();
+        let check = |props: RenderProps| {
+            let patch = ScenePatch::new(vec![PatchOp::AddEntity(transform(), props)]);
+            scene.check_patch(&patch)
+        };
+
+        assert!(check(RenderProps::default()).is_ok());
+
+        let mesh = RenderProps {
+            mesh: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            check(mesh).unwrap_err(),
+            "Operation 0: mesh 1 doesn't exist"
+        );
+
+        let material = RenderProps {
+            material: Some(1),
+            ..Default::default()
+        };
+        assert!(check(material).is_err());
+
+        let palette = RenderProps {
+            palette_i: Some(2),
+            ..Default::default()
+        };
+        assert!(check(palette).is_err());
+
+        let num_indices = scene.meshes[0].indices.len() as u32;
+        let in_range = RenderProps {
+            index_range: Some((num_indices - 3, 3)),
+            ..Default::default()
+        };
+        assert!(check(in_range).is_ok());
+
+        let past_end = RenderProps {
+            index_range: Some((num_indices - 3, 6)),
+            ..Default::default()
+        };
+        assert!(check(past_end).is_err());
+    }
+}
@@ -0,0 +1,323 @@
+//! Render pipelines for the main pass, created on demand. Each combination of features, eg color
+//! targets and face culling, is a separate pipeline, identified by a `PipelineKey` bitset. We
+//! create each the first time it's needed, and reuse it after, so adding features doesn't
+//! multiply the pipelines built at startup.
+//!
+//! Render passes borrow pipelines while they're encoded, so pipelines must be prepared before a
+//! pass begins, eg with `PipelineCache::prepare_meshes`; `get` only looks them up.
+
+use std::collections::HashMap;
+
+use wgpu::{
+    Device, FragmentState, PipelineLayout, RenderPipeline, ShaderModule, TextureFormat,
+    VertexState,
+};
+
+use crate::{
+    deferred::GBUFFER_FORMATS,
+    impostor,
+    taa::VELOCITY_FORMAT,
+    types::{FaceCulling, Instance, Mesh, Vertex},
+};
+
+#[derive(Clone, Copy, PartialEq)]
+/// The color targets of the main pass, and the pipelines drawn in it.
+pub(crate) enum MainTargets {
+    /// The surface's color.
+    Color,
+    /// Color, and velocity, for temporal anti-aliasing.
+    Taa,
+    /// The G-buffer, for deferred shading.
+    GBuffer,
+}
+
+impl MainTargets {
+    pub fn formats(self, color_format: TextureFormat) -> Vec<Option<TextureFormat>> {
+        match self {
+            Self::Color => vec![Some(color_format)],
+            Self::Taa => vec![Some(color_format), Some(VELOCITY_FORMAT)],
+            Self::GBuffer => GBUFFER_FORMATS.iter().map(|f| Some(*f)).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// The features of a main pass pipeline, as a bitset. With no flags set, a pipeline draws meshes
+/// to the surface's color, culling back faces.
+pub(crate) struct PipelineKey(u32);
+
+impl PipelineKey {
+    /// Color and velocity targets, for TAA.
+    pub const TAA: Self = Self(1);
+    /// G-buffer targets, for deferred shading.
+    pub const GBUFFER: Self = Self(1 << 1);
+    /// Draws impostor quads instead of meshes.
+    pub const IMPOSTOR: Self = Self(1 << 2);
+    /// Culls front faces, instead of back faces.
+    pub const CULL_FRONT: Self = Self(1 << 3);
+    /// Culls no faces.
+    pub const CULL_NONE: Self = Self(1 << 4);
+    /// Draws without testing or writing depth; see `LayerSettings::depth_test`.
+    pub const NO_DEPTH_TEST: Self = Self(1 << 5);
+
+    const CULLING: Self = Self(Self::CULL_FRONT.0 | Self::CULL_NONE.0);
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn new(targets: MainTargets) -> Self {
+        match targets {
+            MainTargets::Color => Self(0),
+            MainTargets::Taa => Self::TAA,
+            MainTargets::GBuffer => Self::GBUFFER,
+        }
+    }
+
+    /// The pipeline for impostors, with these targets. Impostor quads always face the camera, so
+    /// they're never culled.
+    pub fn impostor(targets: MainTargets) -> Self {
+        Self::new(targets)
+            .union(Self::IMPOSTOR)
+            .with_culling(FaceCulling::None)
+    }
+
+    /// This key, with its culling replaced.
+    pub fn with_culling(self, culling: FaceCulling) -> Self {
+        let result = Self(self.0 & !Self::CULLING.0);
+
+        match culling {
+            FaceCulling::Back => result,
+            FaceCulling::Front => result.union(Self::CULL_FRONT),
+            FaceCulling::None => result.union(Self::CULL_NONE),
+        }
+    }
+
+    /// This key, with depth testing enabled or disabled.
+    pub fn with_depth_test(self, depth_test: bool) -> Self {
+        if depth_test {
+            Self(self.0 & !Self::NO_DEPTH_TEST.0)
+        } else {
+            self.union(Self::NO_DEPTH_TEST)
+        }
+    }
+
+    fn targets(self) -> MainTargets {
+        if self.contains(Self::TAA) {
+            MainTargets::Taa
+        } else if self.contains(Self::GBUFFER) {
+            MainTargets::GBuffer
+        } else {
+            MainTargets::Color
+        }
+    }
+
+    fn culling(self) -> FaceCulling {
+        if self.contains(Self::CULL_NONE) {
+            FaceCulling::None
+        } else if self.contains(Self::CULL_FRONT) {
+            FaceCulling::Front
+        } else {
+            FaceCulling::Back
+        }
+    }
+}
+
+/// Main pass pipelines, by feature. These share the main shader and pipeline layout.
+pub(crate) struct PipelineCache {
+    shader: ShaderModule,
+    layout: PipelineLayout,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    pipelines: HashMap<PipelineKey, RenderPipeline>,
+}
+
+impl PipelineCache {
+    pub fn new(
+        shader: ShaderModule,
+        layout: PipelineLayout,
+        color_format: TextureFormat,
+        depth_format: TextureFormat,
+    ) -> Self {
+        Self {
+            shader,
+            layout,
+            color_format,
+            depth_format,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    pub fn shader(&self) -> &ShaderModule {
+        &self.shader
+    }
+
+    /// Create the pipeline for `key`, if it doesn't exist.
+    pub fn prepare(&mut self, device: &Device, key: PipelineKey) {
+        self.pipelines.entry(key).or_insert_with(|| {
+            create_render_pipeline(
+                device,
+                &self.layout,
+                &self.shader,
+                self.color_format,
+                self.depth_format,
+                key,
+            )
+        });
+    }
+
+    /// Create pipelines for drawing `meshes` with `key`'s targets and depth testing: one for each
+    /// culling mode they use, and back face culling, which passes start with.
+    pub fn prepare_meshes(&mut self, device: &Device, key: PipelineKey, meshes: &[Mesh]) {
+        let key = key.with_culling(FaceCulling::Back);
+
+        self.prepare(device, key);
+        for culling in [FaceCulling::Front, FaceCulling::None] {
+            if meshes.iter().any(|m| m.culling == culling) {
+                self.prepare(device, key.with_culling(culling));
+            }
+        }
+    }
+
+    /// A prepared pipeline. Panics if it wasn't prepared.
+    pub fn get(&self, key: PipelineKey) -> &RenderPipeline {
+        self.pipelines
+            .get(&key)
+            .expect("Render pipeline used before it was prepared")
+    }
+
+    /// Pipelines for drawing meshes with `key`'s targets and depth testing.
+    pub fn meshes(&self, key: PipelineKey) -> MeshPipelines<'_> {
+        MeshPipelines { cache: self, key }
+    }
+}
+
+#[derive(Clone, Copy)]
+/// Mesh pipelines for a set of targets, for each face culling mode. Meshes are drawn in order,
+/// switching pipelines only when culling differs from the previous mesh's.
+pub(crate) struct MeshPipelines<'a> {
+    cache: &'a PipelineCache,
+    key: PipelineKey,
+}
+
+impl<'a> MeshPipelines<'a> {
+    pub fn get(&self, culling: FaceCulling) -> &'a RenderPipeline {
+        self.cache.get(self.key.with_culling(culling))
+    }
+
+    /// The pipeline to set for a mesh's culling, if it differs from `current`, the one last set.
+    pub fn switch(
+        &self,
+        current: &mut FaceCulling,
+        culling: FaceCulling,
+    ) -> Option<&'a RenderPipeline> {
+        if culling == *current {
+            return None;
+        }
+
+        *current = culling;
+        Some(self.get(culling))
+    }
+}
+
+/// Create the render pipeline for a combination of features.
+fn create_render_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    color_format: TextureFormat,
+    depth_format: TextureFormat,
+    key: PipelineKey,
+) -> RenderPipeline {
+    let color_target = Some(wgpu::ColorTargetState {
+        format: color_format, // Ensure this is a format with alpha (e.g., `wgpu::TextureFormat::Rgba8Unorm`)
+        blend: Some(wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+        }),
+        write_mask: wgpu::ColorWrites::ALL,
+    });
+
+    let velocity_target = Some(wgpu::ColorTargetState {
+        format: VELOCITY_FORMAT,
+        blend: None,
+        write_mask: wgpu::ColorWrites::ALL,
+    });
+
+    // G-buffer targets aren't blended.
+    let gbuffer_targets = GBUFFER_FORMATS.iter().map(|f| Some((*f).into())).collect();
+
+    let impostor = key.contains(PipelineKey::IMPOSTOR);
+    let depth_test = !key.contains(PipelineKey::NO_DEPTH_TEST);
+
+    let (fs_entry_point, targets) = match (key.targets(), impostor) {
+        (MainTargets::Color, false) => ("fs_main", vec![color_target]),
+        (MainTargets::Taa, false) => ("fs_main_taa", vec![color_target, velocity_target]),
+        (MainTargets::GBuffer, false) => ("fs_gbuffer", gbuffer_targets),
+        (MainTargets::Color, true) => ("fs_impostor", vec![color_target]),
+        (MainTargets::Taa, true) => ("fs_impostor_taa", vec![color_target, velocity_target]),
+        (MainTargets::GBuffer, true) => ("fs_impostor_gbuffer", gbuffer_targets),
+    };
+
+    let (vs_entry_point, vertex_desc) = if impostor {
+        ("vs_impostor", impostor::vertex_desc())
+    } else {
+        ("vs_main", Vertex::desc())
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: Some(vs_entry_point),
+            compilation_options: Default::default(),
+            buffers: &[vertex_desc, Instance::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: Some(fs_entry_point),
+            compilation_options: Default::default(),
+            // This configures with alpha blending. (?)
+            targets: &targets,
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: key.culling().face(),
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: depth_test,
+            depth_compare: if depth_test {
+                wgpu::CompareFunction::Less
+            } else {
+                wgpu::CompareFunction::Always
+            },
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        // If the pipeline will be used with a multiview render pass, this
+        // indicates how many array layers the attachments will have.
+        multiview: None,
+        cache: None,
+    })
+}
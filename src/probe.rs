@@ -0,0 +1,319 @@
+//! Environment probes: cube maps of the scene, rendered from a point on demand, that reflective
+//! entities near that point sample. These give local reflections, eg for shiny objects in an
+//! enclosed room, without rendering reflections each frame.
+//!
+//! As with shadow maps, we store each probe's faces as layers of a single texture array, and the
+//! main shader projects into them using the matrices we render them with. Faces are rendered with
+//! the main pipeline, so they include lighting and shadows, but not reflections, impostors, or
+//! debug lines.
+
+use core::f32::consts::TAU;
+
+use lin_alg::f32::Vec3;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, Buffer, BufferUsages, CommandEncoder, Device, Queue,
+    RenderPassDepthStencilAttachment, RenderPassDescriptor, Sampler, StoreOp, TextureFormat,
+    TextureView,
+};
+
+use crate::{
+    camera::{Camera, TIME_SIZE},
+    graphics::mesh_culling,
+    mesh_cache::MeshRange,
+    pipeline_cache::MeshPipelines,
+    shadow::{face_orientations, FACES_PER_LIGHT},
+    slice::CLIP_PLANE_SIZE,
+    system::DEPTH_FORMAT,
+    taa::TAA_CAMERA_SIZE,
+    types::{FaceCulling, Mesh, F32_SIZE, MAT4_SIZE, VEC4_SIZE},
+};
+
+/// Width and height of each cube face, in pixels.
+pub const PROBE_SIZE: u32 = 256;
+
+/// Near plane of the cube face projections.
+const PROBE_NEAR: f32 = 0.1;
+
+/// The probe count, padded to a vec4. Each probe's position and radius follow it.
+const PROBES_HEADER_SIZE: usize = VEC4_SIZE;
+
+#[derive(Clone, Debug)]
+/// A point to capture the scene from, for reflections. Reflective entities within `radius` of a
+/// probe reflect its capture; where probes overlap, they use the nearest one.
+pub struct EnvProbe {
+    pub position: Vec3,
+    pub radius: f32,
+}
+
+impl EnvProbe {
+    pub fn new(position: Vec3, radius: f32) -> Self {
+        Self { position, radius }
+    }
+}
+
+/// The scene, and the resources it's drawn with, for capturing probes.
+pub(crate) struct CaptureInputs<'a> {
+    pub pipelines: MeshPipelines<'a>,
+    /// For each mesh's culling mode.
+    pub meshes: &'a [Mesh],
+    pub layout_cam: &'a BindGroupLayout,
+    /// Bind groups 1 onward of the main pipeline, including extension groups.
+    pub bind_groups: &'a [&'a BindGroup],
+    pub vertex_buf: &'a Buffer,
+    pub index_buf: &'a Buffer,
+    pub instance_buf: &'a Buffer,
+    pub mesh_ranges: &'a [MeshRange],
+    pub mesh_mappings: &'a [(u32, u32)],
+    pub clear_color: wgpu::Color,
+    pub far: f32,
+}
+
+/// Probe textures, and data the main shader uses to sample them.
+pub(crate) struct ProbeState {
+    /// The maximum number of probes. If 0, we don't render reflections.
+    pub max_probes: usize,
+    /// One view per cube face, for rendering.
+    face_views: Vec<TextureView>,
+    /// Shared by all faces, since we render them one at a time.
+    depth_view: TextureView,
+    /// All faces, for sampling in the main shader.
+    pub view: TextureView,
+    /// Bound in place of `view` while capturing, since we can't sample the texture we render to.
+    pub placeholder_view: TextureView,
+    pub sampler: Sampler,
+    /// The probe count, and each probe's position and radius.
+    pub probes_buf: Buffer,
+    /// Projection-view matrices of each face.
+    pub mats_buf: Buffer,
+    /// Set to capture probes in the next frame. We capture probes in the initial scene.
+    pub stale: bool,
+}
+
+impl ProbeState {
+    pub fn new(device: &Device, color_format: TextureFormat, max_probes: usize) -> Self {
+        // We always create at least one cube map, so the main pipeline's bindings are valid.
+        let num_layers = (max_probes.max(1) * FACES_PER_LIGHT) as u32;
+
+        let create_texture = |label, size, layers| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: layers,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: color_format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+        };
+
+        let texture = create_texture("Env probe texture", PROBE_SIZE, num_layers);
+        let placeholder = create_texture("Env probe placeholder texture", 1, 1);
+
+        let face_views = (0..num_layers)
+            .map(|i| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Env probe face view"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: i,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let array_view = |texture: &wgpu::Texture| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("Env probe view"),
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                ..Default::default()
+            })
+        };
+
+        let view = array_view(&texture);
+        let placeholder_view = array_view(&placeholder);
+
+        let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Env probe depth texture"),
+            size: wgpu::Extent3d {
+                width: PROBE_SIZE,
+                height: PROBE_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let depth_view = depth_texture.create_view(&Default::default());
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Env probe sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        // Until captured, there are no probes.
+        let probes_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Env probe buffer"),
+            contents: &vec![0; PROBES_HEADER_SIZE + max_probes.max(1) * VEC4_SIZE],
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+
+        let mats_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Env probe matrix buffer"),
+            size: (num_layers as usize * MAT4_SIZE) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            max_probes,
+            face_views,
+            depth_view,
+            view,
+            placeholder_view,
+            sampler,
+            probes_buf,
+            mats_buf,
+            stale: true,
+        }
+    }
+
+    /// Render the scene into each probe's cube faces, up to the maximum count. Run this before the
+    /// main render pass.
+    pub fn capture(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        probes: &[EnvProbe],
+        inputs: &CaptureInputs,
+    ) {
+        self.stale = false;
+
+        let probes = &probes[..probes.len().min(self.max_probes)];
+
+        let mut probes_data = vec![0; PROBES_HEADER_SIZE];
+        probes_data[0..F32_SIZE].clone_from_slice(&(probes.len() as u32).to_ne_bytes());
+        for probe in probes {
+            let mut posit = probe.position.to_bytes_uniform();
+            posit[3 * F32_SIZE..VEC4_SIZE].clone_from_slice(&probe.radius.to_ne_bytes());
+            probes_data.extend_from_slice(&posit);
+        }
+        queue.write_buffer(&self.probes_buf, 0, &probes_data);
+
+        if probes.is_empty() {
+            return;
+        }
+
+        let orientations = face_orientations();
+        let mut mats_data = Vec::with_capacity(probes.len() * FACES_PER_LIGHT * MAT4_SIZE);
+
+        // Face cameras have no jitter, or previous transforms, so their TAA portion is zero.
+        let mut cam_bind_groups = Vec::with_capacity(probes.len() * FACES_PER_LIGHT);
+
+        for probe in probes {
+            for orientation in &orientations {
+                let mut cam = Camera {
+                    fov_y: TAU / 4.,
+                    aspect: 1.,
+                    near: PROBE_NEAR,
+                    far: inputs.far,
+                    position: probe.position,
+                    orientation: *orientation,
+                    ..Default::default()
+                };
+                cam.update_proj_mat();
+
+                let proj_view = cam.proj_mat.clone() * cam.view_mat();
+                mats_data.extend_from_slice(&proj_view.to_bytes());
+
+                // Probes capture the scene unclipped, at time 0.
+                let mut cam_data = cam.to_bytes().to_vec();
+                cam_data.extend_from_slice(&[0; TAA_CAMERA_SIZE]);
+                cam_data.extend_from_slice(&[0; CLIP_PLANE_SIZE]);
+                cam_data.extend_from_slice(&[0; TIME_SIZE]);
+
+                let cam_buf = device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some("Env probe camera buffer"),
+                    contents: &cam_data,
+                    usage: BufferUsages::UNIFORM,
+                });
+
+                cam_bind_groups.push(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: inputs.layout_cam,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: cam_buf.as_entire_binding(),
+                    }],
+                    label: Some("Env probe camera bind group"),
+                }));
+            }
+        }
+
+        queue.write_buffer(&self.mats_buf, 0, &mats_data);
+
+        for (i_layer, cam_bind_group) in cam_bind_groups.iter().enumerate() {
+            let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Env probe render pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.face_views[i_layer],
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(inputs.clear_color),
+                        store: StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: &self.depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            let mut current = FaceCulling::Back;
+            rpass.set_pipeline(inputs.pipelines.get(current));
+            rpass.set_bind_group(0, cam_bind_group, &[]);
+            for (i, bind_group) in inputs.bind_groups.iter().enumerate() {
+                rpass.set_bind_group(i as u32 + 1, *bind_group, &[]);
+            }
+
+            rpass.set_vertex_buffer(0, inputs.vertex_buf.slice(..));
+            rpass.set_vertex_buffer(1, inputs.instance_buf.slice(..));
+            rpass.set_index_buffer(inputs.index_buf.slice(..), wgpu::IndexFormat::Uint32);
+
+            for (i, (range, (instance_start, instance_count))) in
+                inputs.mesh_ranges.iter().zip(inputs.mesh_mappings).enumerate()
+            {
+                let culling = mesh_culling(inputs.meshes, i);
+                if let Some(pipeline) = inputs.pipelines.switch(&mut current, culling) {
+                    rpass.set_pipeline(pipeline);
+                }
+                rpass.draw_indexed(
+                    range.index_start..range.index_start + range.index_count,
+                    range.vertex_start,
+                    *instance_start..instance_start + instance_count,
+                );
+            }
+        }
+    }
+}
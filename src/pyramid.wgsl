@@ -0,0 +1,46 @@
+// Builds a depth pyramid for occlusion culling. `copy_depth` copies the depth buffer to the first
+// level; `downsample` builds each following level from the one before it. Each texel holds the
+// farthest depth of the texels it covers.
+
+@group(0) @binding(0)
+var depth_in: texture_depth_2d;
+@group(0) @binding(1)
+var mip_in: texture_2d<f32>;
+@group(0) @binding(2)
+var mip_out: texture_storage_2d<r32float, write>;
+
+@compute
+@workgroup_size(8, 8)
+fn copy_depth(@builtin(global_invocation_id) id: vec3<u32>) {
+    let dims = textureDimensions(mip_out);
+    if (id.x >= dims.x || id.y >= dims.y) {
+        return;
+    }
+
+    let depth = textureLoad(depth_in, vec2<i32>(id.xy), 0);
+    textureStore(mip_out, vec2<i32>(id.xy), vec4<f32>(depth, 0., 0., 0.));
+}
+
+@compute
+@workgroup_size(8, 8)
+fn downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let dims = textureDimensions(mip_out);
+    if (id.x >= dims.x || id.y >= dims.y) {
+        return;
+    }
+
+    // If the previous level has an odd size, the last row or column also covers its last texel.
+    let dims_in = textureDimensions(mip_in);
+    let extra_x = select(1u, 2u, (dims_in.x & 1u) == 1u && id.x == dims.x - 1u);
+    let extra_y = select(1u, 2u, (dims_in.y & 1u) == 1u && id.y == dims.y - 1u);
+
+    var depth = 0.;
+    for (var y = 0u; y <= extra_y; y++) {
+        for (var x = 0u; x <= extra_x; x++) {
+            let coord = min(id.xy * 2u + vec2<u32>(x, y), dims_in - 1u);
+            depth = max(depth, textureLoad(mip_in, vec2<i32>(coord), 0).r);
+        }
+    }
+
+    textureStore(mip_out, vec2<i32>(id.xy), vec4<f32>(depth, 0., 0., 0.));
+}
@@ -0,0 +1,207 @@
+//! Instances uploaded directly from packed arrays, eg from a simulation, bypassing entities. These
+//! are stored in their own instance buffer, so updating them doesn't rebuild entity instances.
+//!
+//! Raw instances are drawn in the main pass, and cast shadows. They aren't occlusion culled, or
+//! drawn into environment probes, and are treated as stationary by TAA.
+
+use lin_alg::f32::{Mat4, Quaternion, Vec3};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, Buffer, BufferUsages, Device, RenderPass,
+};
+
+use crate::{
+    graphics::{create_instance_data_bindgroup, mesh_culling},
+    material::MaterialTextures,
+    pass::MeshBuffers,
+    pipeline_cache::MeshPipelines,
+    types::{FaceCulling, Instance, Scene, INSTANCE_SIZE, MAT4_SIZE},
+};
+
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+/// A packed instance, for `Scene::set_instances_raw`.
+pub struct InstanceRaw {
+    pub position: [f32; 3],
+    /// A unit quaternion: w, x, y, z.
+    pub orientation: [f32; 4],
+    pub scale: f32,
+    /// RGBA.
+    pub color: [f32; 4],
+    pub shinyness: f32,
+}
+
+impl InstanceRaw {
+    fn to_instance(self) -> Instance {
+        let [w, x, y, z] = self.orientation;
+        let [r, g, b, a] = self.color;
+
+        Instance {
+            position: Vec3::new(self.position[0], self.position[1], self.position[2]),
+            orientation: Quaternion::new(w, x, y, z),
+            scale: self.scale,
+            color: Vec3::new(r, g, b),
+            opacity: a,
+            shinyness: self.shinyness,
+            reflectivity: 0.,
+            palette_i: None,
+            material: None,
+            lighting_factors: Default::default(),
+            uv_offset: (0., 0.),
+            uv_scale: (1., 1.),
+            texture_tint: Vec3::new(1., 1., 1.),
+        }
+    }
+}
+
+pub(crate) struct RawInstanceState {
+    pub buf: Buffer,
+    /// Instance start and count, for each mesh.
+    pub mesh_mappings: Vec<(u32, u32)>,
+    /// Previous model matrices for TAA. These match the current ones, so only camera motion
+    /// contributes to raw instances' velocity. `None` if TAA is disabled.
+    prev_models_buf: Option<Buffer>,
+    /// Replaces the main instance data bind group when drawing raw instances, if TAA is enabled.
+    bind_group: Option<BindGroup>,
+}
+
+impl RawInstanceState {
+    pub fn new(device: &Device) -> Self {
+        let buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Raw instance buffer"),
+            contents: &[], // Populated in `update`.
+            usage: BufferUsages::VERTEX,
+        });
+
+        Self {
+            buf,
+            mesh_mappings: Vec::new(),
+            prev_models_buf: None,
+            bind_group: None,
+        }
+    }
+
+    /// Serialize and upload `Scene::raw_instances`.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        scene: &Scene,
+        taa: bool,
+        layout: &BindGroupLayout,
+        palette_buf: &Buffer,
+        materials: &MaterialTextures,
+    ) {
+        let count = scene.raw_instances.iter().map(|m| m.len()).sum::<usize>();
+
+        let mut data = Vec::with_capacity(count * INSTANCE_SIZE);
+        self.mesh_mappings = Vec::with_capacity(scene.raw_instances.len());
+
+        for instances in &scene.raw_instances {
+            let start = (data.len() / INSTANCE_SIZE) as u32;
+            for instance in instances {
+                data.extend_from_slice(&instance.to_instance().to_bytes());
+            }
+            self.mesh_mappings.push((start, instances.len() as u32));
+        }
+
+        self.buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Raw instance buffer"),
+            contents: &data,
+            usage: BufferUsages::VERTEX,
+        });
+
+        self.prev_models_buf = None;
+        self.bind_group = None;
+
+        if taa {
+            // Model matrices lead each serialized instance. Storage bindings can't be empty.
+            let mut prev_data = Vec::with_capacity(count.max(1) * MAT4_SIZE);
+            for instance in data.chunks_exact(INSTANCE_SIZE) {
+                prev_data.extend_from_slice(&instance[..MAT4_SIZE]);
+            }
+            if prev_data.is_empty() {
+                prev_data.extend_from_slice(&Mat4::new_identity().to_bytes());
+            }
+
+            self.prev_models_buf = Some(device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("Raw previous model matrix buffer"),
+                contents: &prev_data,
+                usage: BufferUsages::STORAGE,
+            }));
+
+            self.rebind(device, layout, palette_buf, materials);
+        }
+    }
+
+    /// Recreate the instance data bind group, eg after the palette buffer is recreated.
+    pub fn rebind(
+        &mut self,
+        device: &Device,
+        layout: &BindGroupLayout,
+        palette_buf: &Buffer,
+        materials: &MaterialTextures,
+    ) {
+        if let Some(prev_models_buf) = &self.prev_models_buf {
+            self.bind_group = Some(create_instance_data_bindgroup(
+                device,
+                layout,
+                prev_models_buf,
+                palette_buf,
+                materials,
+            ));
+        }
+    }
+
+    /// Draw raw instances in the main pass. Its bind groups must be set; we replace the instance
+    /// data group, and restore it after. The instances are drawn from our buffer, instead of
+    /// `buffers`' instance buffer.
+    pub fn draw(
+        &self,
+        rpass: &mut RenderPass,
+        pipelines: MeshPipelines,
+        instance_data: &BindGroup,
+        buffers: &MeshBuffers,
+    ) {
+        let MeshBuffers {
+            vertex_buf,
+            index_buf,
+            mesh_ranges,
+            meshes,
+            ..
+        } = *buffers;
+
+        if self.mesh_mappings.iter().all(|(_, count)| *count == 0) {
+            return;
+        }
+
+        let mut current = FaceCulling::Back;
+        rpass.set_pipeline(pipelines.get(current));
+        if let Some(bind_group) = &self.bind_group {
+            rpass.set_bind_group(2, bind_group, &[]);
+        }
+
+        rpass.set_vertex_buffer(0, vertex_buf.slice(..));
+        rpass.set_vertex_buffer(1, self.buf.slice(..));
+        rpass.set_index_buffer(index_buf.slice(..), wgpu::IndexFormat::Uint32);
+
+        for (i, (range, (instance_start, instance_count))) in
+            mesh_ranges.iter().zip(&self.mesh_mappings).enumerate()
+        {
+            if *instance_count == 0 {
+                continue;
+            }
+
+            if let Some(pipeline) = pipelines.switch(&mut current, mesh_culling(meshes, i)) {
+                rpass.set_pipeline(pipeline);
+            }
+
+            rpass.draw_indexed(
+                range.index_start..range.index_start + range.index_count,
+                range.vertex_start,
+                *instance_start..instance_start + instance_count,
+            );
+        }
+
+        rpass.set_bind_group(2, instance_data, &[]);
+    }
+}
@@ -0,0 +1,641 @@
+//! Ray casting against entities, at triangle precision, eg for picking and measurement. We build a
+//! bounding volume hierarchy (BVH) for each mesh the first time a ray is tested against it, and
+//! transform rays into each entity's local space, so entities sharing a mesh share its BVH.
+//!
+//! BVHs are rebuilt when meshes change via `EngineUpdates::mesh_vertices` or
+//! `EngineUpdates::replaced_meshes`. After `EngineUpdates::meshes`, BVHs of meshes whose
+//! geometry didn't change are kept, even if they moved to a different index.
+//!
+//! Building BVHs for large models may take a while. To skip this at startup, save them with
+//! `Scene::serialize_bvhs`, eg to a file next to the model, and load them with `Scene::load_bvhs`.
+//! Loaded BVHs are matched to meshes by a hash of their positions and indices, so stale data is
+//! ignored.
+
+use std::{hash::Hasher, ops::Range};
+
+use lin_alg::f32::Vec3;
+
+use crate::{
+    binary::{Reader, Writer},
+    collision::Aabb,
+    fnv::FnvHasher,
+    types::{Mesh, Scene},
+};
+
+/// The maximum number of triangles in a leaf node.
+const LEAF_SIZE: usize = 4;
+
+/// Identifies serialized BVHs.
+const BVH_MAGIC: &[u8; 4] = b"GBVH";
+/// Increment this when the serialized format, or how BVHs are built, changes.
+const BVH_VERSION: u32 = 1;
+
+/// Bounds, start, and count.
+const NODE_SIZE: usize = 6 * 4 + 2 * 4;
+
+#[derive(Clone, Debug)]
+/// The closest intersection of a ray with an entity.
+pub struct Hit {
+    /// Index into `Scene::entities`.
+    pub entity: usize,
+    /// Distance from the ray's origin, in world units.
+    pub distance: f32,
+    /// World-space position of the intersection.
+    pub position: Vec3,
+    /// World-space normal of the triangle hit, facing the ray's origin.
+    pub normal: Vec3,
+    /// Index of the triangle in the mesh; its vertex indices start at `3 * triangle` in
+    /// `Mesh::indices`.
+    pub triangle: usize,
+}
+
+/// Slab test. Returns the distance along the ray at which it enters the box, if it hits before
+/// `t_max`. `dir_inv` is the reciprocal of each component of the ray's direction.
+fn intersect_aabb(aabb: &Aabb, origin: Vec3, dir_inv: Vec3, t_max: f32) -> Option<f32> {
+    let tx1 = (aabb.min.x - origin.x) * dir_inv.x;
+    let tx2 = (aabb.max.x - origin.x) * dir_inv.x;
+    let ty1 = (aabb.min.y - origin.y) * dir_inv.y;
+    let ty2 = (aabb.max.y - origin.y) * dir_inv.y;
+    let tz1 = (aabb.min.z - origin.z) * dir_inv.z;
+    let tz2 = (aabb.max.z - origin.z) * dir_inv.z;
+
+    let t_enter = tx1.min(tx2).max(ty1.min(ty2)).max(tz1.min(tz2));
+    let t_exit = tx1.max(tx2).min(ty1.max(ty2)).min(tz1.max(tz2));
+
+    if t_exit >= t_enter.max(0.) && t_enter < t_max {
+        Some(t_enter)
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Debug)]
+struct BvhNode {
+    bounds: Aabb,
+    /// For leaves, the index of the first triangle in `Bvh::tris`. For interior nodes, the index
+    /// of the left child; the right child follows it.
+    start: usize,
+    /// The number of triangles, for leaves; 0 for interior nodes.
+    count: usize,
+}
+
+#[derive(Clone, Debug)]
+/// A BVH over a mesh's triangles, in the mesh's local space.
+pub(crate) struct Bvh {
+    nodes: Vec<BvhNode>,
+    /// Triangle indices, ordered so each leaf's triangles are contiguous.
+    tris: Vec<usize>,
+    /// The `geometry_hash` of the mesh this was built from.
+    pub geometry_hash: u64,
+}
+
+/// A hash of a mesh's vertex positions and indices: the data its BVH depends on. This is
+/// FNV-1a, so it's stable between builds, and platforms, for matching serialized BVHs.
+pub(crate) fn geometry_hash(mesh: &Mesh) -> u64 {
+    let mut hasher = FnvHasher::default();
+    let mut add = |word: u32| hasher.write(&word.to_le_bytes());
+
+    add(mesh.vertices.len() as u32);
+    for vertex in &mesh.vertices {
+        for v in vertex.position {
+            add(v.to_bits());
+        }
+    }
+    for &i in &mesh.indices {
+        add(i as u32);
+    }
+
+    hasher.finish()
+}
+
+fn vertex_posit(mesh: &Mesh, i: usize) -> Vec3 {
+    let p = mesh.vertices[mesh.indices[i]].position;
+    Vec3::new(p[0], p[1], p[2])
+}
+
+fn tri_verts(mesh: &Mesh, tri: usize) -> (Vec3, Vec3, Vec3) {
+    (
+        vertex_posit(mesh, tri * 3),
+        vertex_posit(mesh, tri * 3 + 1),
+        vertex_posit(mesh, tri * 3 + 2),
+    )
+}
+
+fn axis(v: Vec3, axis: usize) -> f32 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+impl Bvh {
+    fn new(mesh: &Mesh) -> Self {
+        // Skip triangles that reference vertices out of bounds.
+        let tris: Vec<usize> = (0..mesh.indices.len() / 3)
+            .filter(|t| (0..3).all(|i| mesh.indices[t * 3 + i] < mesh.vertices.len()))
+            .collect();
+
+        let centroids: Vec<Vec3> = (0..mesh.indices.len() / 3)
+            .map(|t| {
+                if tris.binary_search(&t).is_err() {
+                    return Vec3::new_zero();
+                }
+                let (a, b, c) = tri_verts(mesh, t);
+                (a + b + c) * (1. / 3.)
+            })
+            .collect();
+
+        let mut result = Self {
+            nodes: Vec::new(),
+            tris,
+            geometry_hash: geometry_hash(mesh),
+        };
+
+        let count = result.tris.len();
+        result.nodes.push(BvhNode {
+            bounds: Aabb::empty(),
+            start: 0,
+            count,
+        });
+        result.subdivide(0, mesh, &centroids);
+
+        result
+    }
+
+    fn subdivide(&mut self, node_i: usize, mesh: &Mesh, centroids: &[Vec3]) {
+        let (start, count) = (self.nodes[node_i].start, self.nodes[node_i].count);
+
+        let mut bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for &tri in &self.tris[start..start + count] {
+            let (a, b, c) = tri_verts(mesh, tri);
+            bounds.grow(a);
+            bounds.grow(b);
+            bounds.grow(c);
+            centroid_bounds.grow(centroids[tri]);
+        }
+        self.nodes[node_i].bounds = bounds;
+
+        if count <= LEAF_SIZE {
+            return;
+        }
+
+        // Split at the median along the longest axis of the centroids' bounds.
+        let extent = centroid_bounds.max - centroid_bounds.min;
+        let split_axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+
+        self.tris[start..start + count].sort_unstable_by(|a, b| {
+            axis(centroids[*a], split_axis).total_cmp(&axis(centroids[*b], split_axis))
+        });
+
+        let left_count = count / 2;
+        let left_i = self.nodes.len();
+
+        self.nodes.push(BvhNode {
+            bounds: Aabb::empty(),
+            start,
+            count: left_count,
+        });
+        self.nodes.push(BvhNode {
+            bounds: Aabb::empty(),
+            start: start + left_count,
+            count: count - left_count,
+        });
+
+        self.nodes[node_i].start = left_i;
+        self.nodes[node_i].count = 0;
+
+        self.subdivide(left_i, mesh, centroids);
+        self.subdivide(left_i + 1, mesh, centroids);
+    }
+
+    fn write(&self, w: &mut Writer) {
+        w.u64(self.geometry_hash);
+        w.u32(self.nodes.len() as u32);
+        w.u32(self.tris.len() as u32);
+
+        for node in &self.nodes {
+            w.vec3(node.bounds.min);
+            w.vec3(node.bounds.max);
+            w.u32(node.start as u32);
+            w.u32(node.count as u32);
+        }
+        for &tri in &self.tris {
+            w.u32(tri as u32);
+        }
+    }
+
+    /// Read a BVH written by `write`. Node and triangle indices are checked, so corrupt data
+    /// can't cause a panic, or an endless traversal.
+    fn read(reader: &mut Reader) -> Result<Self, String> {
+        let geometry_hash = reader.u64()?;
+        let node_count = reader.u32()? as usize;
+        let tri_count = reader.u32()? as usize;
+
+        // Check the length before allocating.
+        if reader.remaining() < node_count * NODE_SIZE + tri_count * 4 {
+            return Err("BVH data ended unexpectedly".to_owned());
+        }
+
+        let mut nodes = Vec::with_capacity(node_count);
+        for node_i in 0..node_count {
+            let bounds = Aabb::new(reader.vec3()?, reader.vec3()?);
+            let start = reader.u32()? as usize;
+            let count = reader.u32()? as usize;
+
+            // Children are always after their parent. A BVH without triangles has a single,
+            // empty node, which isn't traversed.
+            let valid = if tri_count == 0 {
+                true
+            } else if count == 0 {
+                start > node_i && start + 1 < node_count
+            } else {
+                start + count <= tri_count
+            };
+            if !valid {
+                return Err("Invalid BVH node".to_owned());
+            }
+
+            nodes.push(BvhNode {
+                bounds,
+                start,
+                count,
+            });
+        }
+
+        if nodes.is_empty() {
+            return Err("BVH has no nodes".to_owned());
+        }
+
+        let mut tris = Vec::with_capacity(tri_count);
+        for _ in 0..tri_count {
+            tris.push(reader.u32()? as usize);
+        }
+
+        Ok(Self {
+            nodes,
+            tris,
+            geometry_hash,
+        })
+    }
+
+    /// If this BVH's triangles are in bounds for a mesh. `geometry_hash` matching makes this
+    /// true, unless the data is corrupt.
+    fn fits(&self, mesh: &Mesh) -> bool {
+        self.tris.iter().all(|t| {
+            t * 3 + 2 < mesh.indices.len()
+                && (0..3).all(|i| mesh.indices[t * 3 + i] < mesh.vertices.len())
+        })
+    }
+
+    /// Find the closest triangle hit by a ray, in the mesh's local space, among those in `tris`.
+    /// Returns the distance along the ray, in units of `dir`'s length, and the triangle index.
+    fn intersect(
+        &self,
+        mesh: &Mesh,
+        tris: Range<usize>,
+        origin: Vec3,
+        dir: Vec3,
+    ) -> Option<(f32, usize)> {
+        // An empty root would otherwise be treated as an interior node.
+        if self.tris.is_empty() {
+            return None;
+        }
+
+        let dir_inv = Vec3::new(1. / dir.x, 1. / dir.y, 1. / dir.z);
+
+        let mut closest: Option<(f32, usize)> = None;
+        let mut stack = vec![0];
+
+        while let Some(node_i) = stack.pop() {
+            let node = &self.nodes[node_i];
+            let t_max = closest.map(|c| c.0).unwrap_or(f32::MAX);
+
+            if intersect_aabb(&node.bounds, origin, dir_inv, t_max).is_none() {
+                continue;
+            }
+
+            if node.count == 0 {
+                stack.push(node.start);
+                stack.push(node.start + 1);
+                continue;
+            }
+
+            for &tri in &self.tris[node.start..node.start + node.count] {
+                if !tris.contains(&tri) {
+                    continue;
+                }
+
+                let (a, b, c) = tri_verts(mesh, tri);
+                if let Some(t) = intersect_tri(origin, dir, a, b, c) {
+                    if t < t_max && closest.map(|c| t < c.0).unwrap_or(true) {
+                        closest = Some((t, tri));
+                    }
+                }
+            }
+        }
+
+        closest
+    }
+}
+
+/// Möller–Trumbore ray-triangle intersection. Triangles are hit from either side. Returns the
+/// distance along the ray, in units of `dir`'s length.
+fn intersect_tri(origin: Vec3, dir: Vec3, a: Vec3, b: Vec3, c: Vec3) -> Option<f32> {
+    const EPS: f32 = 1e-7;
+
+    let edge_ab = b - a;
+    let edge_ac = c - a;
+
+    let p = dir.cross(edge_ac);
+    let det = edge_ab.dot(p);
+    if det.abs() < EPS {
+        return None; // Parallel to the triangle.
+    }
+    let det_inv = 1. / det;
+
+    let s = origin - a;
+    let u = s.dot(p) * det_inv;
+    if !(0. ..=1.).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(edge_ab);
+    let v = dir.dot(q) * det_inv;
+    if v < 0. || u + v > 1. {
+        return None;
+    }
+
+    let t = edge_ac.dot(q) * det_inv;
+    if t > EPS {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+impl Scene {
+    /// Build BVHs for all meshes that don't have one, and serialize them, eg to save next to a
+    /// large model. Load them with `load_bvhs`.
+    pub fn serialize_bvhs(&mut self) -> Vec<u8> {
+        self.spatial_cache.resize(self.meshes.len());
+
+        let mut w = Writer::default();
+        w.bytes(BVH_MAGIC);
+        w.u32(BVH_VERSION);
+        w.u32(self.meshes.len() as u32);
+
+        for (mesh, bvh) in self.meshes.iter().zip(&mut self.spatial_cache.bvhs) {
+            bvh.get_or_insert_with(|| Bvh::new(mesh)).write(&mut w);
+        }
+
+        w.0
+    }
+
+    /// Load BVHs serialized by `serialize_bvhs`, so they don't need to be built. Each is used for
+    /// meshes with the same positions and indices as the mesh it was built from; others are
+    /// ignored. Returns the number of meshes that received a BVH. Call this after adding meshes
+    /// to the scene.
+    pub fn load_bvhs(&mut self, data: &[u8]) -> Result<usize, String> {
+        let mut reader = Reader::new(data, "BVH data");
+
+        if reader.bytes(BVH_MAGIC.len())? != BVH_MAGIC {
+            return Err("Not BVH data".to_owned());
+        }
+        let version = reader.u32()?;
+        if version != BVH_VERSION {
+            return Err(format!(
+                "BVH data is version {version}; expected {BVH_VERSION}"
+            ));
+        }
+
+        let count = reader.u32()?;
+        let mut bvhs = Vec::new();
+        for _ in 0..count {
+            bvhs.push(Bvh::read(&mut reader)?);
+        }
+
+        self.spatial_cache.resize(self.meshes.len());
+
+        let mut loaded = 0;
+        for (mesh, slot) in self.meshes.iter().zip(&mut self.spatial_cache.bvhs) {
+            if slot.is_some() {
+                continue;
+            }
+
+            let hash = geometry_hash(mesh);
+            if let Some(bvh) = bvhs
+                .iter()
+                .find(|b| b.geometry_hash == hash && b.fits(mesh))
+            {
+                *slot = Some(bvh.clone());
+                loaded += 1;
+            }
+        }
+
+        Ok(loaded)
+    }
+
+    /// Find the closest entity a ray hits, and where. Hidden entities (via their group), and
+    /// those with `pickable` false, are skipped; group transforms are applied.
+    pub fn raycast(&mut self, origin: Vec3, dir: Vec3) -> Option<Hit> {
+        if dir.magnitude_squared() == 0. {
+            return None;
+        }
+        let dir = dir.to_normalized();
+
+        self.spatial_cache.resize(self.meshes.len());
+
+        let mut closest: Option<Hit> = None;
+
+        for i_ent in 0..self.entities.len() {
+            let Some(entity) = self.entity_in_world(i_ent) else {
+                continue;
+            };
+            let Some(mesh) = self.meshes.get(entity.mesh) else {
+                continue;
+            };
+            if entity.scale == 0. || !entity.pickable {
+                continue;
+            }
+
+            let bvh = self.spatial_cache.bvhs[entity.mesh].get_or_insert_with(|| Bvh::new(mesh));
+
+            // Transform the ray into the entity's local space. We don't normalize the local
+            // direction, so distances along it match those in world space.
+            let orientation_inv = entity.orientation.inverse();
+            let local_origin =
+                orientation_inv.rotate_vec(origin - entity.position) * (1. / entity.scale);
+            let local_dir = orientation_inv.rotate_vec(dir) * (1. / entity.scale);
+
+            // Entities drawing part of their mesh only hit triangles in that part.
+            let tris = match entity.index_range {
+                Some((start, count)) => start as usize / 3..(start as usize + count as usize) / 3,
+                None => 0..usize::MAX,
+            };
+
+            let Some((distance, triangle)) = bvh.intersect(mesh, tris, local_origin, local_dir)
+            else {
+                continue;
+            };
+
+            if closest
+                .as_ref()
+                .map(|c| distance >= c.distance)
+                .unwrap_or(false)
+            {
+                continue;
+            }
+
+            let (a, b, c) = tri_verts(mesh, triangle);
+            let mut normal = entity
+                .orientation
+                .rotate_vec((b - a).cross(c - a))
+                .to_normalized();
+            if normal.dot(dir) > 0. {
+                normal *= -1.;
+            }
+
+            closest = Some(Hit {
+                entity: i_ent,
+                distance,
+                position: origin + dir * distance,
+                normal,
+                triangle,
+            });
+        }
+
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lin_alg::f32::Quaternion;
+
+    use super::*;
+    use crate::types::Entity;
+
+    fn scene() -> Scene {
+        Scene {
+            meshes: vec![Mesh::new_box(1., 2., 3.), Mesh::new_sphere(1., 12, 12)],
+            entities: vec![Entity::new(
+                0,
+                Vec3::new(0., 0., 5.),
+                Quaternion::new_identity(),
+                1.,
+                (1., 1., 1.),
+                0.,
+            )],
+            ..Default::default()
+        }
+    }
+
+    fn bvh_data() -> Vec<u8> {
+        scene().serialize_bvhs()
+    }
+
+    #[test]
+    fn bvh_round_trip() {
+        let data = bvh_data();
+
+        let mut loaded = scene();
+        assert_eq!(loaded.load_bvhs(&data), Ok(2));
+        // Loaded BVHs are kept, so serializing them again gives the same data.
+        assert_eq!(loaded.serialize_bvhs(), data);
+
+        let hit = loaded
+            .raycast(Vec3::new_zero(), Vec3::new(0., 0., 1.))
+            .unwrap();
+        assert_eq!(hit.entity, 0);
+        // The box is 3 long along Z.
+        assert!((hit.distance - 3.5).abs() < 0.0001);
+    }
+
+    #[test]
+    fn bvhs_for_other_meshes_are_ignored() {
+        let mut other = Scene {
+            meshes: vec![Mesh::new_box(1., 1., 1.)],
+            ..Default::default()
+        };
+
+        assert_eq!(other.load_bvhs(&bvh_data()), Ok(0));
+        assert!(other.spatial_cache.bvhs[0].is_none());
+    }
+
+    #[test]
+    fn truncated_bvhs() {
+        let data = bvh_data();
+
+        for len in 0..data.len() {
+            assert!(scene().load_bvhs(&data[..len]).is_err(), "Length {len}");
+        }
+    }
+
+    #[test]
+    fn invalid_bvh_header() {
+        let mut data = bvh_data();
+        data[0] = b'X';
+        assert_eq!(scene().load_bvhs(&data), Err("Not BVH data".to_owned()));
+
+        let mut data = bvh_data();
+        data[4..8].copy_from_slice(&(BVH_VERSION + 1).to_le_bytes());
+        assert!(scene().load_bvhs(&data).is_err());
+
+        // A BVH count larger than the data.
+        let mut data = bvh_data();
+        data[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(scene().load_bvhs(&data).is_err());
+    }
+
+    #[test]
+    fn invalid_bvh_nodes() {
+        let bvh = Bvh::new(&Mesh::new_box(1., 1., 1.));
+        let read = |bvh: &Bvh| {
+            let mut w = Writer::default();
+            bvh.write(&mut w);
+            Bvh::read(&mut Reader::new(&w.0, "BVH data"))
+        };
+        assert!(read(&bvh).is_ok());
+
+        // An interior node whose children are before it would make traversal loop.
+        let mut cyclic = bvh.clone();
+        let interior = cyclic.nodes.iter().position(|n| n.count == 0).unwrap();
+        cyclic.nodes[interior].start = interior;
+        assert_eq!(read(&cyclic).unwrap_err(), "Invalid BVH node");
+
+        let mut out_of_range = bvh.clone();
+        let leaf = out_of_range.nodes.iter().position(|n| n.count > 0).unwrap();
+        out_of_range.nodes[leaf].start = bvh.tris.len();
+        assert_eq!(read(&out_of_range).unwrap_err(), "Invalid BVH node");
+
+        // Counts larger than the data are rejected before allocating.
+        let mut w = Writer::default();
+        w.u64(0);
+        w.u32(u32::MAX);
+        w.u32(u32::MAX);
+        let result = Bvh::read(&mut Reader::new(&w.0, "BVH data"));
+        assert_eq!(result.unwrap_err(), "BVH data ended unexpectedly");
+    }
+
+    #[test]
+    fn corrupt_bvhs_dont_panic() {
+        let data = bvh_data();
+
+        for i in 0..data.len() {
+            let mut corrupt = data.clone();
+            corrupt[i] ^= 0xff;
+
+            let mut scene = scene();
+            let _ = scene.load_bvhs(&corrupt);
+            scene.raycast(Vec3::new_zero(), Vec3::new(0., 0., 1.));
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! Redrawing the scene only when it changes, for applications that display rarely-changing
+//! content, eg a molecule or CAD model the user inspects, and want minimal GPU work per frame.
+//!
+//! With `RedrawMode::OnChange`, the 3D viewport is rendered to a persistent texture, and copied to
+//! the output each frame, under the HUD and GUI. The scene is rendered again only when it changes:
+//!
+//! - Updates from `EngineUpdates`, eg to entities, meshes, the camera, or lighting, and changes
+//!   made by the engine, eg by camera controls, timelines, light paths, transitions, or the
+//!   turntable, redraw the whole viewport. So do changes to `Scene::background_color`.
+//! - `EngineUpdates::redraw` requests a redraw explicitly; use this after changing fields read each
+//!   frame, eg `Scene::slice_plane`, or `debug_draw`. A `RedrawRegion::Rect` redraws only part of
+//!   the viewport, over the previous frame's color and depth.
+//!
+//! `RedrawMode::Idle` goes further: the engine doesn't run frames at all while nothing changes, so
+//! the CPU and GPU are idle too. A frame runs after window input, when the GUI requests a repaint,
+//! eg for an animation, or to blink a text cursor, and after device events the camera controls or
+//! event handler act on. While the scene changes each frame, eg from the render handler's updates,
+//! a timeline, or held camera keys, frames keep running; once a frame renders nothing, the engine
+//! waits again. Since the render handler only runs during frames, use it for animations that were
+//! started by input or a previous frame. With the `remote` feature's server running, frames run
+//! at least every 100ms, to apply its requests.
+//!
+//! Partial redraws only apply to the main pass. If other passes cover the viewport, ie with TAA,
+//! deferred shading, anaglyph stereo, outlines, a background gradient, or entities in the
+//! background or overlay layers, the whole viewport is redrawn instead. TAA doesn't converge
+//! while the scene isn't redrawn.
+
+use wgpu::{
+    BindGroup, BindingType, CommandEncoder, Device, FragmentState, RenderPassDescriptor,
+    RenderPipeline, ShaderStages, StoreOp, TextureFormat, TextureUsages, TextureView, VertexState,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+/// When the scene is rendered. See the `redraw` module.
+pub enum RedrawMode {
+    /// Render the scene each frame.
+    #[default]
+    Always,
+    /// Render the scene when it changes, and reuse the previous frame otherwise.
+    OnChange,
+    /// As `OnChange`, and additionally, only run frames when something may have changed.
+    Idle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Part of the scene to redraw, with `RedrawMode::OnChange`.
+pub enum RedrawRegion {
+    All,
+    /// In pixels, from the top left of the window, like the 3D viewport. This is clipped to the
+    /// viewport.
+    Rect {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+}
+
+impl RedrawRegion {
+    /// The smallest region containing both.
+    pub(crate) fn union(self, other: Self) -> Self {
+        match (self, other) {
+            (
+                Self::Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                },
+                Self::Rect {
+                    x: x2,
+                    y: y2,
+                    width: width2,
+                    height: height2,
+                },
+            ) => {
+                let left = x.min(x2);
+                let top = y.min(y2);
+                Self::Rect {
+                    x: left,
+                    y: top,
+                    width: x.saturating_add(width).max(x2.saturating_add(width2)) - left,
+                    height: y.saturating_add(height).max(y2.saturating_add(height2)) - top,
+                }
+            }
+            _ => Self::All,
+        }
+    }
+}
+
+/// Clip a rectangle, as (x, y, width, height) in pixels, to the viewport; `None` if they don't
+/// overlap.
+pub(crate) fn clip_rect(
+    rect: (u32, u32, u32, u32),
+    viewport: (f32, f32, f32, f32),
+) -> Option<(u32, u32, u32, u32)> {
+    let (x, y, width, height) = rect;
+    let (vp_x, vp_y, vp_width, vp_height) = viewport;
+
+    let left = x.max(vp_x as u32);
+    let top = y.max(vp_y as u32);
+    let right = x.saturating_add(width).min((vp_x + vp_width) as u32);
+    let bottom = y.saturating_add(height).min((vp_y + vp_height) as u32);
+
+    if right <= left || bottom <= top {
+        return None;
+    }
+
+    Some((left, top, right - left, bottom - top))
+}
+
+/// A pipeline that fills the render target with the blend constant, and the far plane's depth,
+/// within the scissor rect. We use this to clear regions, since `LoadOp::Clear` ignores the
+/// scissor.
+pub(crate) fn create_fill_pipeline(
+    device: &Device,
+    format: TextureFormat,
+    depth_format: TextureFormat,
+) -> RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Fill shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("redraw.wgsl").into()),
+    });
+
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Fill pipeline layout"),
+        bind_group_layouts: &[],
+        push_constant_ranges: &[],
+    });
+
+    let constant = wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Constant,
+        dst_factor: wgpu::BlendFactor::Zero,
+        operation: wgpu::BlendOperation::Add,
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Fill pipeline"),
+        layout: Some(&layout),
+        vertex: VertexState {
+            module: &shader,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[],
+        },
+        fragment: Some(FragmentState {
+            module: &shader,
+            entry_point: Some("fs_clear"),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState {
+                    color: constant,
+                    alpha: constant,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// The rendered scene, and passes to copy it to the output, and to clear regions of it.
+pub(crate) struct SceneCache {
+    pub width: u32,
+    pub height: u32,
+    pub view: TextureView,
+    /// The 3D viewport the cache was rendered with; if it changes, eg when the GUI is resized, the
+    /// scene is redrawn.
+    pub viewport: (f32, f32, f32, f32),
+    blit_pipeline: RenderPipeline,
+    bind_group: BindGroup,
+    clear_pipeline: RenderPipeline,
+}
+
+impl SceneCache {
+    /// `format` is that of the output, and `depth_format` that of the main depth buffer; `width`
+    /// and `height` are their size.
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        depth_format: TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let width = width.max(1);
+        let height = height.max(1);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Redraw shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("redraw.wgsl").into()),
+        });
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Scene cache texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&Default::default());
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+            label: Some("Scene cache bind group layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            }],
+            label: Some("Scene cache bind group"),
+        });
+
+        let blit_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Scene blit pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let blit_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Scene blit pipeline"),
+            layout: Some(&blit_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: Some("fs_blit"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let clear_pipeline = create_fill_pipeline(device, format, depth_format);
+
+        Self {
+            width,
+            height,
+            view,
+            viewport: (0., 0., 0., 0.),
+            blit_pipeline,
+            bind_group,
+            clear_pipeline,
+        }
+    }
+
+    /// Clear a rectangle of the cache and depth texture, before redrawing it. `LoadOp::Clear`
+    /// ignores the scissor, so we draw the clear color instead. `rect` is (x, y, width, height), in
+    /// pixels, and must be within the cache.
+    pub fn encode_clear(
+        &self,
+        encoder: &mut CommandEncoder,
+        depth_view: &TextureView,
+        rect: (u32, u32, u32, u32),
+        color: wgpu::Color,
+    ) {
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Region clear render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        let (x, y, width, height) = rect;
+        rpass.set_scissor_rect(x, y, width, height);
+
+        rpass.set_pipeline(&self.clear_pipeline);
+        rpass.set_blend_constant(color);
+        rpass.draw(0..3, 0..1);
+    }
+
+    /// Copy the cache to `target`, which must be the same size.
+    pub fn encode_blit(&self, encoder: &mut CommandEncoder, target: &TextureView) {
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Scene blit render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Every pixel is overwritten.
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        rpass.set_pipeline(&self.blit_pipeline);
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
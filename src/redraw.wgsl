@@ -0,0 +1,33 @@
+// Copies the cached scene to the output, and clears regions of it before a partial redraw. Also
+// copies extra views; see `views.rs`.
+
+@group(0) @binding(0)
+var scene_tex: texture_2d<f32>;
+
+struct VertexOut {
+    @builtin(position) posit: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> VertexOut {
+    // A single triangle that covers the target. It's at the far plane, so clearing writes a depth
+    // of 1.
+    var uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+
+    var result: VertexOut;
+    result.posit = vec4<f32>(uv * vec2<f32>(2., -2.) + vec2<f32>(-1., 1.), 1., 1.);
+
+    return result;
+}
+
+@fragment
+fn fs_blit(in: VertexOut) -> @location(0) vec4<f32> {
+    // The cache is the same size as the output, so pixels correspond.
+    return textureLoad(scene_tex, vec2<i32>(in.posit.xy), 0);
+}
+
+@fragment
+fn fs_clear(in: VertexOut) -> @location(0) vec4<f32> {
+    // Multiplied by the blend constant, which is set to the clear color.
+    return vec4<f32>(1.);
+}
@@ -0,0 +1,239 @@
+//! A remote control server, which lets other processes drive the application, eg a notebook, a
+//! test harness, or a web front end, by sending `ScenePatch`es, and requesting screenshots and
+//! statistics. This requires the `remote` feature; start it by setting `UiSettings::remote_addr`.
+//!
+//! This uses plain TCP. Messages in both directions are a little-endian `u32` length, followed by
+//! that many bytes: a tag byte, then its payload. Each request gets one reply, in order.
+//!
+//! Requests:
+//! - 0: Apply a patch, encoded with `ScenePatch::to_bytes`. Replies with `Ok`, or an error.
+//! - 1: A screenshot of the next frame, including the GUI. Replies with a PNG image.
+//! - 2: Frame and scene statistics. Replies with text, formatted as for display.
+//!
+//! Replies: 0, `Ok`, without a payload; 1, an error, as UTF-8 text; 2, a PNG image; 3, UTF-8 text.
+//!
+//! Requests are applied on the render thread at the start of each frame, after the render
+//! handler, so the application sees patched entities on the next frame. If no frame runs for a
+//! while, eg as the window is minimized, requests are replied to with an error; patches are still
+//! applied once frames resume. Connections beyond `MAX_CONNECTIONS` are closed. There's no
+//! authentication, so listen on a local address, eg "127.0.0.1:7878", unless the network is
+//! trusted. WebSocket clients, eg browsers, can connect through a proxy, such as websockify.
+
+use std::{
+    io::{self, Cursor, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use image::{ImageFormat, RgbaImage};
+
+use crate::{
+    patch::ScenePatch,
+    types::{EngineUpdates, Scene},
+};
+
+/// Messages larger than this, in bytes, close the connection.
+const MAX_MESSAGE_SIZE: usize = 64 * 1_024 * 1_024;
+
+/// Each connection has a thread, and buffers a message at a time.
+const MAX_CONNECTIONS: usize = 8;
+
+/// How long a connection waits for the render thread to handle its request.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const REQUEST_PATCH: u8 = 0;
+const REQUEST_SCREENSHOT: u8 = 1;
+const REQUEST_STATS: u8 = 2;
+
+enum Request {
+    Patch(ScenePatch),
+    Screenshot,
+    Stats,
+}
+
+enum Reply {
+    Ok,
+    Error(String),
+    /// PNG-encoded.
+    Image(Vec<u8>),
+    Text(String),
+}
+
+impl Reply {
+    fn to_bytes(&self) -> Vec<u8> {
+        let (tag, payload) = match self {
+            Self::Ok => (0, &[][..]),
+            Self::Error(msg) => (1, msg.as_bytes()),
+            Self::Image(png) => (2, &png[..]),
+            Self::Text(text) => (3, text.as_bytes()),
+        };
+
+        let mut result = Vec::with_capacity(5 + payload.len());
+        result.extend_from_slice(&(payload.len() as u32 + 1).to_le_bytes());
+        result.push(tag);
+        result.extend_from_slice(payload);
+        result
+    }
+}
+
+/// A request received from a connection, and where to send its reply.
+struct Message {
+    request: Request,
+    reply: Sender<Reply>,
+}
+
+/// Listens for connections on background threads, and passes their requests to the render thread.
+pub(crate) struct RemoteServer {
+    messages: Receiver<Message>,
+    /// Replies to screenshot requests, sent once the next frame is captured.
+    screenshots: Vec<Sender<Reply>>,
+}
+
+impl RemoteServer {
+    /// Listen on `addr`, eg "127.0.0.1:7878".
+    pub fn start(addr: &str) -> Result<Self, String> {
+        let listener =
+            TcpListener::bind(addr).map_err(|e| format!("Unable to listen on {addr}: {e}"))?;
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            let connections = Arc::new(AtomicUsize::new(0));
+
+            for stream in listener.incoming().flatten() {
+                if connections.load(Ordering::Acquire) >= MAX_CONNECTIONS {
+                    continue; // Dropping the stream closes it.
+                }
+                connections.fetch_add(1, Ordering::AcqRel);
+
+                let tx = tx.clone();
+                let connections = Arc::clone(&connections);
+                thread::spawn(move || {
+                    // The connection closed, or sent invalid data.
+                    let _ = handle_connection(stream, tx);
+                    connections.fetch_sub(1, Ordering::AcqRel);
+                });
+            }
+        });
+
+        Ok(Self {
+            messages: rx,
+            screenshots: Vec::new(),
+        })
+    }
+
+    /// Handle requests received since the last frame. Returns the updates from each patch applied;
+    /// process these in order.
+    pub fn poll(&mut self, scene: &mut Scene) -> Vec<EngineUpdates> {
+        let mut result = Vec::new();
+
+        while let Ok(Message { request, reply }) = self.messages.try_recv() {
+            match request {
+                Request::Patch(patch) => match scene.apply_patch(&patch) {
+                    Ok(updates) => {
+                        result.push(updates);
+                        let _ = reply.send(Reply::Ok);
+                    }
+                    Err(e) => {
+                        let _ = reply.send(Reply::Error(e));
+                    }
+                },
+                Request::Screenshot => self.screenshots.push(reply),
+                Request::Stats => {
+                    let text = format!("{:#?}\n{:?}", scene.frame_stats, scene.stats());
+                    let _ = reply.send(Reply::Text(text));
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Whether a screenshot of the next frame is needed.
+    pub fn screenshot_pending(&self) -> bool {
+        !self.screenshots.is_empty()
+    }
+
+    /// Reply to screenshot requests with an error, eg if the frame couldn't be rendered.
+    pub fn fail_screenshots(&mut self, error: &str) {
+        for sender in self.screenshots.drain(..) {
+            let _ = sender.send(Reply::Error(error.to_owned()));
+        }
+    }
+
+    /// Send a captured frame to each connection waiting for one.
+    pub fn send_screenshot(&mut self, image: Result<RgbaImage, String>) {
+        let reply = image.and_then(|image| {
+            let mut png = Vec::new();
+            image
+                .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                .map_err(|e| format!("Unable to encode the screenshot: {e}"))?;
+            Ok(png)
+        });
+
+        for sender in self.screenshots.drain(..) {
+            let _ = sender.send(match &reply {
+                Ok(png) => Reply::Image(png.clone()),
+                Err(e) => Reply::Error(e.clone()),
+            });
+        }
+    }
+}
+
+/// Read requests from a connection until it closes, passing each to the render thread, and
+/// writing its reply.
+fn handle_connection(mut stream: TcpStream, messages: Sender<Message>) -> io::Result<()> {
+    loop {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len)?;
+
+        let len = u32::from_le_bytes(len) as usize;
+        if len == 0 || len > MAX_MESSAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid message size",
+            ));
+        }
+
+        // Read incrementally, so we only allocate for data actually received.
+        let mut data = Vec::new();
+        (&mut stream).take(len as u64).read_to_end(&mut data)?;
+        if data.len() < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let request = match data[0] {
+            REQUEST_PATCH => ScenePatch::from_bytes(&data[1..]).map(Request::Patch),
+            REQUEST_SCREENSHOT => Ok(Request::Screenshot),
+            REQUEST_STATS => Ok(Request::Stats),
+            tag => Err(format!("Unknown request {tag}")),
+        };
+
+        let reply = match request {
+            Ok(request) => {
+                let (tx, rx) = mpsc::channel();
+                if messages.send(Message { request, reply: tx }).is_err() {
+                    // The engine has stopped.
+                    return Ok(());
+                }
+                match rx.recv_timeout(REPLY_TIMEOUT) {
+                    Ok(reply) => reply,
+                    Err(RecvTimeoutError::Timeout) => Reply::Error(
+                        "No frame ran in time, eg as the window is minimized".to_owned(),
+                    ),
+                    Err(RecvTimeoutError::Disconnected) => {
+                        Reply::Error("The engine stopped".to_owned())
+                    }
+                }
+            }
+            Err(e) => Reply::Error(e),
+        };
+
+        stream.write_all(&reply.to_bytes())?;
+    }
+}
@@ -0,0 +1,725 @@
+//! Signed distance field (SDF) rendering of text and simple shapes, eg for labels and HUD
+//! markers. These are drawn as quads in the main render pass; the fragment shader anti-aliases
+//! them using the distance to their edge, so they're crisp at any size.
+//!
+//! Glyphs are rendered from font outlines into an SDF atlas as they're first used. Shapes are
+//! evaluated analytically in the shader.
+//!
+//! https://steamcdn-a.akamaihd.net/apps/valve/2007/SIGGRAPH2007_AlphaTestedMagnification.pdf
+
+use std::collections::HashMap;
+
+use ab_glyph::{Font, FontVec, ScaleFont};
+use lin_alg::f32::Vec3;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferUsages, Device,
+    FragmentState, Queue, RenderPass, RenderPipeline, ShaderStages, SurfaceConfiguration,
+    TextureFormat, VertexState,
+};
+
+use crate::{
+    shader_interface::engine_wgsl,
+    taa::VELOCITY_FORMAT,
+    types::{F32_SIZE, VEC3_SIZE, VEC4_SIZE},
+};
+
+/// Glyphs are rasterized at this size, in pixels per em.
+const GLYPH_PX: f32 = 40.;
+/// The distance range encoded around glyph edges, in atlas pixels. This also pads each glyph's
+/// cell, so neighboring glyphs don't bleed into each other.
+const SPREAD: usize = 6;
+/// The size of each glyph's cell in the atlas, in pixels.
+const CELL_SIZE: usize = 64;
+/// The atlas has this many cells per side; it holds up to this squared glyphs.
+const ATLAS_CELLS: usize = 16;
+const ATLAS_SIZE: usize = CELL_SIZE * ATLAS_CELLS;
+
+/// anchor (vec3), offset (vec2), uv (vec2), color (vec4), params (vec4)
+const SDF_VERTEX_SIZE: usize = VEC3_SIZE + 4 * F32_SIZE + 2 * VEC4_SIZE;
+/// Viewport size (padded to vec4), camera right (vec4), camera up (vec4).
+const SDF_PARAMS_SIZE: usize = 3 * VEC4_SIZE;
+
+/// Each element is drawn as one or more quads, of 2 triangles each.
+const CORNERS: [(f32, f32); 6] = [
+    (-1., -1.),
+    (1., -1.),
+    (1., 1.),
+    (-1., -1.),
+    (1., 1.),
+    (-1., 1.),
+];
+
+#[derive(Clone, Debug)]
+pub enum SdfShape {
+    /// Centered on the anchor. Use `\n` to start a new line.
+    Text(String),
+    Circle,
+    /// The inner field is the ring's thickness, as a fraction of its radius.
+    Ring(f32),
+    Square,
+    /// The inner field is the thickness of each bar, as a fraction of the cross's size.
+    Cross(f32),
+}
+
+impl SdfShape {
+    /// Matches the `kind` field in the shader. Text is 0.
+    fn index(&self) -> f32 {
+        match self {
+            Self::Text(_) => 0.,
+            Self::Circle => 1.,
+            Self::Ring(_) => 2.,
+            Self::Square => 3.,
+            Self::Cross(_) => 4.,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SdfAnchor {
+    /// A position in world space. The element faces the camera, and its size is in world units,
+    /// so it shrinks with distance.
+    World(Vec3),
+    /// A position in world space. The element's size is in pixels, so it stays the same size on
+    /// screen, eg for labels.
+    WorldFixedSize(Vec3),
+    /// A position on the 3D viewport, in pixels from its top left. Its size is in pixels, eg for
+    /// HUD markers.
+    Screen(f32, f32),
+}
+
+impl SdfAnchor {
+    /// Matches the `mode` field in the shader.
+    fn index(&self) -> f32 {
+        match self {
+            Self::World(_) => 0.,
+            Self::WorldFixedSize(_) => 1.,
+            Self::Screen(_, _) => 2.,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Text, or a shape, rendered using signed distance fields. Set `EngineUpdates::sdf_elements`
+/// after changing these.
+pub struct SdfElement {
+    pub shape: SdfShape,
+    pub anchor: SdfAnchor,
+    /// Text height, or shape diameter. In world units for `SdfAnchor::World`, and pixels
+    /// otherwise.
+    pub size: f32,
+    pub color: (f32, f32, f32),
+    pub opacity: f32,
+    /// If true, this is drawn over geometry in front of it. Screen-anchored elements always are.
+    pub on_top: bool,
+}
+
+impl SdfElement {
+    pub fn new(shape: SdfShape, anchor: SdfAnchor, size: f32, color: (f32, f32, f32)) -> Self {
+        Self {
+            shape,
+            anchor,
+            size,
+            color,
+            opacity: 1.,
+            on_top: false,
+        }
+    }
+}
+
+/// A glyph's location in the atlas.
+#[derive(Clone, Copy)]
+struct GlyphCell {
+    /// Cell index, from the top left, row-major.
+    cell: usize,
+    /// The cell's bottom left corner, relative to the pen position on the baseline, in pixels at
+    /// `GLYPH_PX`, Y up.
+    origin: (f32, f32),
+}
+
+/// A glyph's quad, from `GlyphAtlas::layout`.
+pub(crate) struct GlyphQuad {
+    /// Relative to the center of the text, in units of its height. Y is up.
+    pub min: (f32, f32),
+    pub max: (f32, f32),
+    /// Atlas texture coordinates of `min` and `max`.
+    pub uv_min: (f32, f32),
+    pub uv_max: (f32, f32),
+}
+
+/// Glyph SDFs, generated on demand, and the texture they're uploaded to. Used by SDF elements,
+/// and the HUD.
+pub(crate) struct GlyphAtlas {
+    font: Option<FontVec>,
+    /// `None` for glyphs with no outline, eg spaces.
+    glyphs: HashMap<char, Option<GlyphCell>>,
+    /// Cells rendered since the last upload: (cell index, data).
+    pending: Vec<(usize, Vec<u8>)>,
+    texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+impl GlyphAtlas {
+    pub fn new(device: &Device) -> Self {
+        // We use the GUI's default proportional font.
+        let font = FontVec::try_from_vec(epaint_default_fonts::UBUNTU_LIGHT.to_vec()).ok();
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Glyph atlas"),
+            size: wgpu::Extent3d {
+                width: ATLAS_SIZE as u32,
+                height: ATLAS_SIZE as u32,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            font,
+            glyphs: HashMap::new(),
+            pending: Vec::new(),
+            texture,
+            view,
+        }
+    }
+
+    /// Lay out text, centered on the origin, rendering any new glyphs. Returns a quad for each
+    /// glyph, and the (width, height) of the text block, in units of text height. Use `\n` to
+    /// start a new line.
+    pub fn layout(&mut self, text: &str) -> (Vec<GlyphQuad>, (f32, f32)) {
+        let Some(font) = &self.font else {
+            return (Vec::new(), (0., 0.));
+        };
+        let font = font.as_scaled(GLYPH_PX);
+
+        let line_height = font.ascent() - font.descent() + font.line_gap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        // The baseline of the first line, such that the block is vertically centered.
+        let height = line_height * (lines.len().max(1) - 1) as f32;
+        let first_baseline = height / 2. - (font.ascent() + font.descent()) / 2.;
+
+        // (char, pen x, baseline), in pixels at `GLYPH_PX`.
+        let mut placed = Vec::new();
+        let mut max_width: f32 = 0.;
+
+        for (i, line) in lines.iter().enumerate() {
+            let width: f32 = line.chars().map(|c| font.h_advance(font.glyph_id(c))).sum();
+            let baseline = first_baseline - line_height * i as f32;
+            max_width = max_width.max(width);
+
+            let mut pen_x = -width / 2.;
+            for c in line.chars() {
+                placed.push((c, pen_x, baseline));
+                pen_x += font.h_advance(font.glyph_id(c));
+            }
+        }
+
+        let size = CELL_SIZE as f32 / GLYPH_PX;
+        let uv_size = CELL_SIZE as f32 / ATLAS_SIZE as f32;
+
+        let mut quads = Vec::with_capacity(placed.len());
+        for (c, pen_x, baseline) in placed {
+            let Some(glyph) = self.glyph(c) else {
+                continue;
+            };
+
+            let x = (pen_x + glyph.origin.0) / GLYPH_PX;
+            let y = (baseline + glyph.origin.1) / GLYPH_PX;
+
+            let u = ((glyph.cell % ATLAS_CELLS) * CELL_SIZE) as f32 / ATLAS_SIZE as f32;
+            let v = ((glyph.cell / ATLAS_CELLS) * CELL_SIZE) as f32 / ATLAS_SIZE as f32;
+
+            // Texture V increases downward.
+            quads.push(GlyphQuad {
+                min: (x, y),
+                max: (x + size, y + size),
+                uv_min: (u, v + uv_size),
+                uv_max: (u + uv_size, v),
+            });
+        }
+
+        // The font's scale is the height of its ascent and descent.
+        (quads, (max_width / GLYPH_PX, 1. + height / GLYPH_PX))
+    }
+
+    /// Write glyphs rendered since the last upload to the atlas texture.
+    pub fn upload(&mut self, queue: &Queue) {
+        for (cell, cell_data) in self.pending.drain(..) {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &self.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: ((cell % ATLAS_CELLS) * CELL_SIZE) as u32,
+                        y: ((cell / ATLAS_CELLS) * CELL_SIZE) as u32,
+                        z: 0,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &cell_data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(CELL_SIZE as u32),
+                    rows_per_image: Some(CELL_SIZE as u32),
+                },
+                wgpu::Extent3d {
+                    width: CELL_SIZE as u32,
+                    height: CELL_SIZE as u32,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+
+    /// Find a glyph's cell, rendering it if it's not in the atlas yet. Returns `None` if it has
+    /// no outline, or the atlas is full.
+    fn glyph(&mut self, c: char) -> Option<GlyphCell> {
+        if let Some(glyph) = self.glyphs.get(&c) {
+            return *glyph;
+        }
+
+        let font = self.font.as_ref()?;
+        let cell = self.glyphs.values().flatten().count();
+        if cell >= ATLAS_CELLS * ATLAS_CELLS {
+            return None;
+        }
+
+        let outlined = font.outline_glyph(font.as_scaled(GLYPH_PX).scaled_glyph(c));
+        let result = outlined.map(|outlined| {
+            let bounds = outlined.px_bounds();
+
+            // Coverage of each pixel in the cell. Glyphs larger than the cell are clipped.
+            let mut inside = vec![false; CELL_SIZE * CELL_SIZE];
+            outlined.draw(|x, y, coverage| {
+                let (x, y) = (x as usize + SPREAD, y as usize + SPREAD);
+                if x < CELL_SIZE && y < CELL_SIZE {
+                    inside[y * CELL_SIZE + x] = coverage >= 0.5;
+                }
+            });
+
+            self.pending.push((cell, distance_field(&inside)));
+
+            GlyphCell {
+                cell,
+                origin: (
+                    bounds.min.x - SPREAD as f32,
+                    -(bounds.min.y - SPREAD as f32) - CELL_SIZE as f32,
+                ),
+            }
+        });
+
+        self.glyphs.insert(c, result);
+        result
+    }
+}
+
+/// Convert a coverage bitmap of one cell to a distance field. 0.5 is on the edge; higher values
+/// are inside. This is brute force, but cells are small, and glyphs are only rendered once.
+fn distance_field(inside: &[bool]) -> Vec<u8> {
+    let spread = SPREAD as isize;
+    let mut result = vec![0; CELL_SIZE * CELL_SIZE];
+
+    for y in 0..CELL_SIZE as isize {
+        for x in 0..CELL_SIZE as isize {
+            let this = inside[y as usize * CELL_SIZE + x as usize];
+
+            // Distance to the nearest pixel on the other side of the edge.
+            let mut nearest_sq = (spread * spread) as f32;
+            for dy in -spread..=spread {
+                for dx in -spread..=spread {
+                    let (x2, y2) = (x + dx, y + dy);
+                    if x2 < 0 || y2 < 0 || x2 >= CELL_SIZE as isize || y2 >= CELL_SIZE as isize {
+                        continue;
+                    }
+                    if inside[y2 as usize * CELL_SIZE + x2 as usize] != this {
+                        nearest_sq = nearest_sq.min((dx * dx + dy * dy) as f32);
+                    }
+                }
+            }
+
+            // The edge is between pixel centers.
+            let dist = nearest_sq.sqrt() - 0.5;
+            let signed = if this { dist } else { -dist };
+
+            let v = 0.5 + signed / (2. * SPREAD as f32);
+            result[y as usize * CELL_SIZE + x as usize] = (v.clamp(0., 1.) * 255.) as u8;
+        }
+    }
+
+    result
+}
+
+/// Append a quad's vertices. `min` and `max` are offsets from the anchor, and `uv_min` and
+/// `uv_max` their texture coordinates, or local coordinates for shapes.
+fn push_quad(
+    data: &mut Vec<u8>,
+    element: &SdfElement,
+    min: (f32, f32),
+    max: (f32, f32),
+    uv_min: (f32, f32),
+    uv_max: (f32, f32),
+) {
+    let anchor = match element.anchor {
+        SdfAnchor::World(p) | SdfAnchor::WorldFixedSize(p) => p,
+        SdfAnchor::Screen(x, y) => Vec3::new(x, y, 0.),
+    };
+
+    let shape_param = match element.shape {
+        SdfShape::Ring(v) | SdfShape::Cross(v) => v,
+        _ => 0.,
+    };
+
+    let on_top = if element.on_top { 1. } else { 0. };
+
+    for (x, y) in CORNERS {
+        let t = ((x + 1.) / 2., (y + 1.) / 2.);
+        let offset = (min.0 + (max.0 - min.0) * t.0, min.1 + (max.1 - min.1) * t.1);
+        let uv = (
+            uv_min.0 + (uv_max.0 - uv_min.0) * t.0,
+            uv_min.1 + (uv_max.1 - uv_min.1) * t.1,
+        );
+
+        data.extend_from_slice(&anchor.to_bytes_vertex());
+        for v in [offset.0, offset.1, uv.0, uv.1] {
+            data.extend_from_slice(&v.to_ne_bytes());
+        }
+        for v in [
+            element.color.0,
+            element.color.1,
+            element.color.2,
+            element.opacity,
+        ] {
+            data.extend_from_slice(&v.to_ne_bytes());
+        }
+        for v in [
+            element.anchor.index(),
+            element.shape.index(),
+            shape_param,
+            on_top,
+        ] {
+            data.extend_from_slice(&v.to_ne_bytes());
+        }
+    }
+}
+
+pub(crate) fn vertex_desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+    wgpu::VertexBufferLayout {
+        array_stride: SDF_VERTEX_SIZE as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &[
+            // Anchor
+            wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float32x3,
+            },
+            // Offset from the anchor
+            wgpu::VertexAttribute {
+                offset: VEC3_SIZE as wgpu::BufferAddress,
+                shader_location: 1,
+                format: wgpu::VertexFormat::Float32x2,
+            },
+            // Atlas UV, or local coordinates for shapes
+            wgpu::VertexAttribute {
+                offset: (VEC3_SIZE + 2 * F32_SIZE) as wgpu::BufferAddress,
+                shader_location: 2,
+                format: wgpu::VertexFormat::Float32x2,
+            },
+            // Color
+            wgpu::VertexAttribute {
+                offset: (VEC3_SIZE + 4 * F32_SIZE) as wgpu::BufferAddress,
+                shader_location: 3,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+            // Anchor mode, shape kind, shape parameter, and on top
+            wgpu::VertexAttribute {
+                offset: (VEC3_SIZE + 4 * F32_SIZE + VEC4_SIZE) as wgpu::BufferAddress,
+                shader_location: 4,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+        ],
+    }
+}
+
+/// The SDF pipelines, glyph atlas, and vertex buffer for the scene's SDF elements.
+pub(crate) struct SdfRenderer {
+    pipeline: RenderPipeline,
+    /// Used when TAA is enabled; this has an additional velocity target.
+    pipeline_taa: RenderPipeline,
+    atlas: GlyphAtlas,
+    params_buf: Buffer,
+    bind_group: BindGroup,
+    buf: Buffer,
+    num_vertices: u32,
+    /// If set, vertices are rebuilt before the next frame, eg after elements change.
+    pub stale: bool,
+    /// True if the vertices include entity labels. These are rebuilt each frame the scene is
+    /// rendered, since they follow their entities.
+    pub labels: bool,
+}
+
+impl SdfRenderer {
+    pub fn new(
+        device: &Device,
+        surface_cfg: &SurfaceConfiguration,
+        depth_format: TextureFormat,
+        layout_cam: &BindGroupLayout,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("SDF shader"),
+            source: wgpu::ShaderSource::Wgsl(engine_wgsl(include_str!("sdf.wgsl")).into()),
+        });
+
+        let atlas = GlyphAtlas::new(device);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Glyph atlas sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let params_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("SDF params buffer"),
+            contents: &[0; SDF_PARAMS_SIZE],
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("SDF bind group layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&atlas.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("SDF bind group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SDF pipeline layout"),
+            bind_group_layouts: &[layout_cam, &layout],
+            push_constant_ranges: &[],
+        });
+
+        let buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("SDF vertex buffer"),
+            contents: &[],
+            usage: BufferUsages::VERTEX,
+        });
+
+        Self {
+            pipeline: create_pipeline(
+                device,
+                &pipeline_layout,
+                &shader,
+                surface_cfg,
+                depth_format,
+                false,
+            ),
+            pipeline_taa: create_pipeline(
+                device,
+                &pipeline_layout,
+                &shader,
+                surface_cfg,
+                depth_format,
+                true,
+            ),
+            atlas,
+            params_buf,
+            bind_group,
+            buf,
+            num_vertices: 0,
+            stale: true,
+            labels: false,
+        }
+    }
+
+    /// Rebuild vertices for the scene's elements, rendering any new glyphs to the atlas.
+    pub fn update(&mut self, device: &Device, queue: &Queue, elements: &[SdfElement]) {
+        self.stale = false;
+
+        let mut data = Vec::new();
+        for element in elements {
+            match &element.shape {
+                SdfShape::Text(text) => self.push_text(&mut data, element, text),
+                _ => {
+                    let r = element.size / 2.;
+                    push_quad(&mut data, element, (-r, -r), (r, r), (-1., -1.), (1., 1.));
+                }
+            }
+        }
+
+        self.atlas.upload(queue);
+
+        self.num_vertices = (data.len() / SDF_VERTEX_SIZE) as u32;
+        if data.is_empty() {
+            return;
+        }
+
+        // We can't update using a queue due to buffer size mismatches.
+        self.buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("SDF vertex buffer"),
+            contents: &data,
+            usage: BufferUsages::VERTEX,
+        });
+    }
+
+    /// The size of a block of text, in units of its line height.
+    pub fn text_size(&mut self, text: &str) -> (f32, f32) {
+        self.atlas.layout(text).1
+    }
+
+    /// Lay out text, centered on its anchor, and append a quad for each glyph.
+    fn push_text(&mut self, data: &mut Vec<u8>, element: &SdfElement, text: &str) {
+        let (quads, _) = self.atlas.layout(text);
+
+        for quad in quads {
+            push_quad(
+                data,
+                element,
+                (quad.min.0 * element.size, quad.min.1 * element.size),
+                (quad.max.0 * element.size, quad.max.1 * element.size),
+                quad.uv_min,
+                quad.uv_max,
+            );
+        }
+    }
+
+    /// Update the viewport size, in pixels, and camera orientation used to place elements. Run
+    /// this each frame.
+    pub fn update_params(&self, queue: &Queue, viewport: (f32, f32), right: Vec3, up: Vec3) {
+        let mut data = Vec::with_capacity(SDF_PARAMS_SIZE);
+        for v in [viewport.0, viewport.1, 0., 0.] {
+            data.extend_from_slice(&v.to_ne_bytes());
+        }
+        data.extend_from_slice(&right.to_bytes_uniform());
+        data.extend_from_slice(&up.to_bytes_uniform());
+
+        queue.write_buffer(&self.params_buf, 0, &data);
+    }
+
+    /// Draw elements in the main render pass. The camera bind group must be set.
+    pub fn draw(&self, rpass: &mut RenderPass, taa: bool) {
+        if self.num_vertices == 0 {
+            return;
+        }
+
+        if taa {
+            rpass.set_pipeline(&self.pipeline_taa);
+        } else {
+            rpass.set_pipeline(&self.pipeline);
+        }
+
+        rpass.set_bind_group(1, &self.bind_group, &[]);
+        rpass.set_vertex_buffer(0, self.buf.slice(..));
+        rpass.draw(0..self.num_vertices, 0..1);
+    }
+}
+
+fn create_pipeline(
+    device: &Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    config: &SurfaceConfiguration,
+    depth_format: TextureFormat,
+    taa: bool,
+) -> RenderPipeline {
+    let color_target = Some(wgpu::ColorTargetState {
+        format: config.format,
+        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+        write_mask: wgpu::ColorWrites::ALL,
+    });
+
+    let velocity_target = Some(wgpu::ColorTargetState {
+        format: VELOCITY_FORMAT,
+        blend: None,
+        write_mask: wgpu::ColorWrites::ALL,
+    });
+
+    let (fs_entry_point, targets) = if taa {
+        ("fs_main_taa", vec![color_target, velocity_target])
+    } else {
+        ("fs_main", vec![color_target])
+    };
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("SDF pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[vertex_desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: Some(fs_entry_point),
+            compilation_options: Default::default(),
+            targets: &targets,
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        // Like lines, elements are hidden by geometry in front of them unless on top, but don't
+        // occlude anything themselves.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
@@ -0,0 +1,182 @@
+// Text and shapes rendered using signed distance fields. These are drawn in the main render pass,
+// after meshes and lines. Glyph distances are sampled from an atlas; shape distances are computed
+// here. Either way, we anti-alias using the distance's screen-space derivative.
+
+#include "camera"
+
+struct SdfParams {
+    // The 3D viewport's width and height, in pixels; only x and y are used.
+    viewport: vec4<f32>,
+    // The camera's right and up vectors, in world space. Used to face elements toward it.
+    right: vec4<f32>,
+    up: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var<uniform> params: SdfParams;
+@group(1) @binding(1)
+var atlas: texture_2d<f32>;
+@group(1) @binding(2)
+var atlas_sampler: sampler;
+
+// Anchor modes
+const WORLD: u32 = 0u;
+const WORLD_FIXED_SIZE: u32 = 1u;
+const SCREEN: u32 = 2u;
+
+// Shape kinds
+const TEXT: u32 = 0u;
+const CIRCLE: u32 = 1u;
+const RING: u32 = 2u;
+const SQUARE: u32 = 3u;
+const CROSS: u32 = 4u;
+
+struct VertexIn {
+    // In world space, or in pixels from the viewport's top left for screen anchors.
+    @location(0) anchor: vec3<f32>,
+    // In world units for world anchors, and pixels otherwise. Y is up.
+    @location(1) offset: vec2<f32>,
+    // Atlas UV for glyphs, or -1 to 1 across the quad for shapes.
+    @location(2) uv: vec2<f32>,
+    @location(3) color: vec4<f32>,
+    // Anchor mode, shape kind, shape parameter, and on top.
+    @location(4) params: vec4<f32>,
+}
+
+struct VertexOut {
+    @builtin(position) clip_posit: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) @interpolate(flat) kind: u32,
+    @location(3) shape_param: f32,
+    @location(4) curr_clip: vec4<f32>,
+    @location(5) prev_clip: vec4<f32>,
+}
+
+// Pixels to clip space offsets, for a point with a given w.
+fn pixels_to_clip(offset: vec2<f32>, w: f32) -> vec2<f32> {
+    return offset * 2. / params.viewport.xy * w;
+}
+
+@vertex
+fn vs_main(vertex_in: VertexIn) -> VertexOut {
+    var mode = u32(vertex_in.params.x);
+
+    var curr_clip: vec4<f32>;
+    var prev_clip: vec4<f32>;
+
+    if (mode == WORLD) {
+        var posit = vec4<f32>(
+            vertex_in.anchor
+                + params.right.xyz * vertex_in.offset.x
+                + params.up.xyz * vertex_in.offset.y,
+            1.,
+        );
+        curr_clip = camera.proj_view * posit;
+        prev_clip = camera.prev_proj_view * posit;
+    } else if (mode == WORLD_FIXED_SIZE) {
+        var posit = vec4<f32>(vertex_in.anchor, 1.);
+        curr_clip = camera.proj_view * posit;
+        prev_clip = camera.prev_proj_view * posit;
+
+        curr_clip += vec4<f32>(pixels_to_clip(vertex_in.offset, curr_clip.w), 0., 0.);
+        prev_clip += vec4<f32>(pixels_to_clip(vertex_in.offset, prev_clip.w), 0., 0.);
+    } else {
+        var ndc = vec2<f32>(
+            vertex_in.anchor.x / params.viewport.x * 2. - 1.,
+            1. - vertex_in.anchor.y / params.viewport.y * 2.,
+        );
+        curr_clip = vec4<f32>(ndc + pixels_to_clip(vertex_in.offset, 1.), 0., 1.);
+        prev_clip = curr_clip;
+    }
+
+    var result: VertexOut;
+    result.clip_posit = curr_clip;
+
+    // Elements on top are at the near plane, so they pass the depth test.
+    if (vertex_in.params.w > 0.5) {
+        result.clip_posit.z = 0.;
+    }
+
+    result.uv = vertex_in.uv;
+    result.color = vertex_in.color;
+    result.kind = u32(vertex_in.params.y);
+    result.shape_param = vertex_in.params.z;
+    result.curr_clip = curr_clip;
+    result.prev_clip = prev_clip;
+
+    return result;
+}
+
+fn sd_box(p: vec2<f32>, half_size: vec2<f32>) -> f32 {
+    var d = abs(p) - half_size;
+    return length(max(d, vec2<f32>(0.))) + min(max(d.x, d.y), 0.);
+}
+
+// Coverage of the element at this fragment, from 0 to 1.
+fn coverage(vertex: VertexOut) -> f32 {
+    // Sampling and derivatives require uniform control flow, so we compute both distances
+    // before choosing one. Glyph distances are 0.5 at the edge, and higher inside; we flip them
+    // to match shapes, which are negative inside.
+    var glyph_dist = 0.5 - textureSample(atlas, atlas_sampler, vertex.uv).r;
+
+    var p = vertex.uv;
+    var t = vertex.shape_param;
+    var shape_dist = 0.;
+
+    switch vertex.kind {
+        case CIRCLE: {
+            shape_dist = length(p) - 1.;
+        }
+        case RING: {
+            shape_dist = abs(length(p) - 1. + t / 2.) - t / 2.;
+        }
+        case SQUARE: {
+            shape_dist = sd_box(p, vec2<f32>(1.));
+        }
+        case CROSS: {
+            shape_dist = min(sd_box(p, vec2<f32>(1., t)), sd_box(p, vec2<f32>(t, 1.)));
+        }
+        default: {}
+    }
+
+    var glyph_width = fwidth(glyph_dist);
+    var shape_width = fwidth(shape_dist);
+
+    if (vertex.kind == TEXT) {
+        return clamp(0.5 - glyph_dist / max(glyph_width, 0.0001), 0., 1.);
+    }
+    return clamp(0.5 - shape_dist / max(shape_width, 0.0001), 0., 1.);
+}
+
+@fragment
+fn fs_main(vertex: VertexOut) -> @location(0) vec4<f32> {
+    var alpha = coverage(vertex);
+    if (alpha <= 0.) {
+        discard;
+    }
+
+    return vec4<f32>(vertex.color.rgb, vertex.color.a * alpha);
+}
+
+struct FragOutTaa {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec2<f32>,
+}
+
+@fragment
+fn fs_main_taa(vertex: VertexOut) -> FragOutTaa {
+    var alpha = coverage(vertex);
+    if (alpha <= 0.) {
+        discard;
+    }
+
+    var result: FragOutTaa;
+    result.color = vec4<f32>(vertex.color.rgb, vertex.color.a * alpha);
+    result.velocity = vertex.curr_clip.xy / vertex.curr_clip.w - vertex.prev_clip.xy / vertex.prev_clip.w;
+
+    return result;
+}
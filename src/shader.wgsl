@@ -1,26 +1,8 @@
 // Reference: https://www.w3.org/TR/WGSL
 
-struct Camera {
-    proj_view: mat4x4<f32>,
-    position: vec4<f32>,
-}
-
-struct PointLight {
-    position: vec4<f32>,
-    diffuse_color: vec4<f32>,
-    specular_color: vec4<f32>,
-    diffuse_intensity: f32,
-    specular_intensity: f32,
-}
-
-// Note: Don't us vec3 in uniforms due to alignment issues.
-struct Lighting {
-    ambient_color: vec4<f32>,
-    ambient_intensity: f32,
-    // We use this as a workaround for array len not working.
-    lights_len: i32,
-    point_lights: array<PointLight>
-}
+#include "camera"
+#include "lighting"
+#include "shadow_sample"
 
 @group(0) @binding(0)
 var<uniform> camera: Camera;
@@ -30,25 +12,125 @@ var<uniform> camera: Camera;
 // this is due to the dynamic-sized point light array.
 var<storage> lighting: Lighting;
 
+struct EnvProbes {
+    num_probes: u32,
+    // Each probe's position, and its radius in w.
+    probes: array<vec4<f32>>,
+}
+
+@group(1) @binding(1)
+var<storage> env_probes: EnvProbes;
+@group(1) @binding(2)
+// Projection-view matrices for each cube face; 6 per probe.
+var<storage> probe_mats: array<mat4x4<f32>>;
+@group(1) @binding(3)
+// Cube faces; 6 layers per probe, in the same order as shadow maps.
+var probe_maps: texture_2d_array<f32>;
+@group(1) @binding(4)
+var probe_sampler: sampler;
+
+struct ColorSettings {
+    exposure: f32,
+    gamma: f32,
+    // If nonzero, entity and light colors are sRGB, and we convert them to linear.
+    srgb_input: u32,
+    // If nonzero, the surface format isn't sRGB, so we encode output here.
+    encode_srgb: u32,
+    // If nonzero, lighting is quantized into this many bands, for cel shading.
+    cel_bands: u32,
+}
+
+@group(1) @binding(5)
+var<uniform> color_settings: ColorSettings;
+
+struct ClusterParams {
+    view: mat4x4<f32>,
+    tan_x: f32,
+    tan_y: f32,
+    near: f32,
+    far: f32,
+    // The size of the grid of clusters. 0 if clustered lighting is disabled.
+    grid_x: u32,
+    grid_y: u32,
+    grid_z: u32,
+    max_lights: u32,
+}
+
+@group(1) @binding(6)
+var<uniform> cluster_params: ClusterParams;
+@group(1) @binding(7)
+// For each cluster, its light count, followed by up to `max_lights` light indices.
+var<storage> cluster_lights: array<u32>;
+
+@group(2) @binding(0)
+// Each instance's model matrix from the previous frame, indexed by instance index. Used to
+// compute velocity for temporal anti-aliasing.
+var<storage> prev_models: array<mat4x4<f32>>;
+
+@group(2) @binding(1)
+// The active palette's colors, for instances with a palette index.
+var<storage> palette: array<vec4<f32>>;
+@group(2) @binding(2)
+// Material textures; one layer per material.
+var material_maps: texture_2d_array<f32>;
+@group(2) @binding(3)
+var material_sampler: sampler;
+@group(2) @binding(9)
+// Material normal maps, in tangent space; one layer per material. Layers of materials without one
+// are flat.
+var normal_maps: texture_2d_array<f32>;
+
+@group(3) @binding(0)
+var<uniform> shadow_params: ShadowParams;
+@group(3) @binding(1)
+// Projection-view matrices for each cube face; 6 per shadow map.
+var<storage> shadow_mats: array<mat4x4<f32>>;
+@group(3) @binding(2)
+// Cube faces; 6 layers per shadow map, in the order +X, -X, +Y, -Y, +Z, -Z.
+var shadow_maps: texture_depth_2d_array;
+@group(3) @binding(3)
+var shadow_sampler: sampler_comparison;
+
+struct DeferredParams {
+    // The camera's basis. Right and up are scaled to the edges of the view at unit distance.
+    right: vec4<f32>,
+    up: vec4<f32>,
+    forward: vec4<f32>,
+    // x, y, width, and height of the 3D viewport, in pixels.
+    viewport: vec4<f32>,
+    near: f32,
+    far: f32,
+}
+
+// Used by the deferred lighting pass, in place of instance data; see `GBufferOut`. These follow
+// the instance data bindings, since they share the group.
+@group(2) @binding(4)
+var gbuffer_albedo: texture_2d<f32>;
+@group(2) @binding(5)
+var gbuffer_normal: texture_2d<f32>;
+@group(2) @binding(6)
+var gbuffer_material: texture_2d<f32>;
+@group(2) @binding(7)
+var gbuffer_depth: texture_depth_2d;
+@group(2) @binding(8)
+var<uniform> deferred_params: DeferredParams;
+
 struct VertexIn {
     @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>, // unused
+    @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
     @location(3) tangent: vec3<f32>,
     @location(4) bitangent: vec3<f32>,
 }
 
-// These are matrix columns; we can't pass matrices directly for vertex attributes.
-struct InstanceIn {
-    @location(5) model_matrix_0: vec4<f32>,
-    @location(6) model_matrix_1: vec4<f32>,
-    @location(7) model_matrix_2: vec4<f32>,
-    @location(8) model_matrix_3: vec4<f32>,
-    @location(9) normal_matrix_0: vec3<f32>,
-    @location(10) normal_matrix_1: vec3<f32>,
-    @location(11) normal_matrix_2: vec3<f32>,
-    @location(12) color: vec4<f32>, // Len 4; includes alpha.
-    @location(13) shinyness: f32,
+#include "instance"
+
+fn instance_color(instance: InstanceIn) -> vec4<f32> {
+    var palette_i = instance_indices(instance).x;
+    if (palette_i >= 0) {
+        return palette[palette_i];
+    }
+    return instance.color;
 }
 
 struct VertexOut {
@@ -58,30 +140,28 @@ struct VertexOut {
     @location(2) color: vec4<f32>,
     @location(3) shinyness: f32,
     @location(4) world_posit: vec3<f32>, // todo: Experimenting
-//        @location(1) tangent_position: vec3<f32>,
-//        @location(2) tangent_light_position: vec3<f32>,
-//        @location(3) tangent_view_position: vec3<f32>,
+    // Unjittered clip positions for this frame and the previous one; used for TAA velocity.
+    @location(5) curr_clip: vec4<f32>,
+    @location(6) prev_clip: vec4<f32>,
+    @location(7) reflectivity: f32,
+    @location(8) lighting_factors: vec3<f32>,
+    // The material texture layer; -1 if none.
+    @location(9) @interpolate(flat) material_i: i32,
+    // Multiplies the material texture.
+    @location(10) texture_tint: vec3<f32>,
+    // In world space, for normal mapping. Not normalized; these are 0 if the mesh has no tangents.
+    @location(11) tangent: vec3<f32>,
+    @location(12) bitangent: vec3<f32>,
 }
 
 @vertex
 fn vs_main(
     vertex_in: VertexIn,
     instance: InstanceIn,
+    @builtin(instance_index) instance_i: u32,
 ) -> VertexOut {
-    // The model matrix includes translation, rotation, and scale.
-    var model_mat = mat4x4<f32>(
-        instance.model_matrix_0,
-        instance.model_matrix_1,
-        instance.model_matrix_2,
-        instance.model_matrix_3,
-    );
-
-    // The normal matrix includes rotation only.
-    var normal_mat = mat3x3<f32>(
-        instance.normal_matrix_0,
-        instance.normal_matrix_1,
-        instance.normal_matrix_2,
-    );
+    var model_mat = instance_model(instance);
+    var normal_mat = instance_normal(instance);
 
     // "the transpose of the inverse of the upper-left 3x3 part of the model matrix"
 //    var model_mat_3 = mat3x3<f32>(
@@ -99,34 +179,32 @@ fn vs_main(
 //    var normal_mat = inverse(transpose(model_mat_3));
 
 
-    // todo: Is this right?
-    // We use the tangent matrix, and tangent out values for normal mapping.
-    // This is currently unimplemented.
     var world_normal = normalize(normal_mat * vertex_in.normal);
-    var world_tangent = normalize(normal_mat * vertex_in.tangent);
-    var world_bitangent = normalize(normal_mat * vertex_in.bitangent);
-
-// Construct the tangent matrix
-    var tangent_mat = transpose(mat3x3<f32>(
-        world_tangent,
-        world_bitangent,
-        world_normal,
-    ));
 
     // Pad the model position with 1., for use with the 4x4 transform mats.
     var world_posit = model_mat * vec4<f32>(vertex_in.position, 1.0);
 
     var result: VertexOut;
 
-    result.clip_posit = camera.proj_view * world_posit;
+    var curr_clip = camera.proj_view * world_posit;
+    var prev_clip = camera.prev_proj_view * prev_models[instance_i] * vec4<f32>(vertex_in.position, 1.0);
 
-//    result.tangent_position = tangent_mat * world_posit.xyz;
-//    result.tangent_view_position = tangent_mat * camera.position.xyz;
-//    result.tangent_light_position = tangent_matrix * light.position;
-    result.normal = world_normal;
+    result.clip_posit = curr_clip + vec4<f32>(camera.jitter.xy * curr_clip.w, 0., 0.);
+    result.curr_clip = curr_clip;
+    result.prev_clip = prev_clip;
 
-    result.color = instance.color;
-    result.shinyness = instance.shinyness;
+    result.normal = world_normal;
+    result.tangent = normal_mat * vertex_in.tangent;
+    result.bitangent = normal_mat * vertex_in.bitangent;
+
+    result.color = instance_color(instance);
+    var material = instance_material(instance);
+    result.shinyness = material.x;
+    result.reflectivity = material.y;
+    result.lighting_factors = instance_lighting_factors(instance);
+    result.tex_coords = vertex_in.tex_coords * instance.uv_transform.zw + instance.uv_transform.xy;
+    result.material_i = instance_indices(instance).y;
+    result.texture_tint = instance.texture_tint;
     result.world_posit = world_posit.xyz;
 
     return result;
@@ -135,16 +213,432 @@ fn vs_main(
 /// Fragment shader, which is mostly lighting calculations.
 @fragment
 fn fs_main(vertex: VertexOut) -> @location(0) vec4<f32> {
+    return shade(vertex);
+}
+
+// Surface properties for deferred shading; lit in `fs_deferred`.
+struct GBufferOut {
+    // Linear, with alpha.
+    @location(0) albedo: vec4<f32>,
+    // World space normal, and shinyness.
+    @location(1) normal: vec4<f32>,
+    // Lighting factors, and reflectivity.
+    @location(2) material: vec4<f32>,
+}
+
+fn gbuffer(surface: Surface) -> GBufferOut {
+    var result: GBufferOut;
+    result.albedo = surface.base_color;
+    result.normal = vec4<f32>(surface.normal, surface.shinyness);
+    result.material = vec4<f32>(surface.lighting_factors, surface.reflectivity);
+
+    return result;
+}
+
+/// Fragment shader used with deferred shading; writes surface properties instead of lighting.
+@fragment
+fn fs_gbuffer(vertex: VertexOut) -> GBufferOut {
+    return gbuffer(vertex_surface(vertex));
+}
+
+struct FragOutTaa {
+    @location(0) color: vec4<f32>,
+    // Screen-space motion since the previous frame, in NDC.
+    @location(1) velocity: vec2<f32>,
+}
+
+/// Fragment shader used with temporal anti-aliasing; also outputs velocity.
+@fragment
+fn fs_main_taa(vertex: VertexOut) -> FragOutTaa {
+    var result: FragOutTaa;
+    result.color = shade(vertex);
+    result.velocity = vertex.curr_clip.xy / vertex.curr_clip.w - vertex.prev_clip.xy / vertex.prev_clip.w;
+
+    return result;
+}
+
+// Impostors are camera-facing quads, ray traced as spheres or cylinders in the fragment shader.
+// They use the same instances as meshes, and the mesh's bounds for their size.
+struct ImpostorIn {
+    // A corner of the quad, from -1 to 1.
+    @location(0) corner: vec2<f32>,
+    // In the mesh's local space.
+    @location(1) center: vec3<f32>,
+    // Radius, half length (cylinders only), and kind: 0 for spheres, and 1 for cylinders.
+    @location(2) params: vec4<f32>,
+}
+
+struct ImpostorOut {
+    @builtin(position) clip_posit: vec4<f32>,
+    // On the quad.
+    @location(0) world_posit: vec3<f32>,
+    @location(1) center: vec3<f32>,
+    // A cylinder's axis, from its center to one end. 0 for spheres.
+    @location(2) axis: vec3<f32>,
+    @location(3) radius: f32,
+    @location(4) @interpolate(flat) kind: u32,
+    @location(5) color: vec4<f32>,
+    @location(6) shinyness: f32,
+    // The center's motion since the previous frame, reversed; used for TAA velocity.
+    @location(7) prev_offset: vec3<f32>,
+    @location(8) reflectivity: f32,
+    @location(9) lighting_factors: vec3<f32>,
+}
+
+@vertex
+fn vs_impostor(
+    impostor: ImpostorIn,
+    instance: InstanceIn,
+    @builtin(instance_index) instance_i: u32,
+) -> ImpostorOut {
+    var model_mat = instance_model(instance);
+
+    // Entities are uniformly scaled.
+    var scale = length(instance.model_matrix_0.xyz);
+
+    var result: ImpostorOut;
+
+    result.center = (model_mat * vec4<f32>(impostor.center, 1.)).xyz;
+    result.radius = impostor.params.x * scale;
+    result.kind = u32(impostor.params.z);
+    result.axis = (model_mat * vec4<f32>(0., impostor.params.y, 0., 0.)).xyz;
+    result.color = instance_color(instance);
+    var material = instance_material(instance);
+    result.shinyness = material.x;
+    result.reflectivity = material.y;
+    result.lighting_factors = instance_lighting_factors(instance);
+
+    var to_cam = camera.position.xyz - result.center;
+    var dist = length(to_cam);
+    var view_dir = to_cam / dist;
+
+    // The quad is perpendicular to the view direction. For cylinders, one side follows the
+    // axis's projection.
+    var axis_proj = result.axis - view_dir * dot(result.axis, view_dir);
+    var dir_a = normalize(axis_proj);
+    if (length(axis_proj) < 0.000001) {
+        var up = select(vec3<f32>(0., 1., 0.), vec3<f32>(1., 0., 0.), abs(view_dir.y) > 0.99);
+        dir_a = normalize(cross(up, view_dir));
+    }
+    var dir_b = cross(view_dir, dir_a);
+
+    // Parts of the shape nearer the camera than the center project farther out; scale by the
+    // worst case, using its bounding sphere.
+    var bound = sqrt(result.radius * result.radius + dot(result.axis, result.axis));
+    var persp = dist / max(dist - bound, 0.0001);
+
+    var extent_a = (length(axis_proj) + result.radius) * persp;
+    var extent_b = result.radius * persp;
+
+    result.world_posit = result.center
+        + dir_a * impostor.corner.x * extent_a
+        + dir_b * impostor.corner.y * extent_b;
+
+    var clip = camera.proj_view * vec4<f32>(result.world_posit, 1.);
+    result.clip_posit = clip + vec4<f32>(camera.jitter.xy * clip.w, 0., 0.);
+
+    var prev_center = (prev_models[instance_i] * vec4<f32>(impostor.center, 1.)).xyz;
+    result.prev_offset = prev_center - result.center;
+
+    return result;
+}
+
+/// Ray-sphere intersection. Returns the normal, and the distance along the ray; the distance is
+/// negative on a miss.
+fn intersect_sphere(
+    ray_origin: vec3<f32>,
+    ray_dir: vec3<f32>,
+    center: vec3<f32>,
+    radius: f32,
+) -> vec4<f32> {
+    var oc = ray_origin - center;
+    var b = dot(oc, ray_dir);
+    var h = b * b - dot(oc, oc) + radius * radius;
+    if (h < 0.) {
+        return vec4<f32>(0., 0., 0., -1.);
+    }
+
+    var t = -b - sqrt(h);
+    return vec4<f32>((oc + ray_dir * t) / radius, t);
+}
+
+/// Ray intersection with a capped cylinder between `a` and `b`. Returns the normal, and the
+/// distance along the ray; the distance is negative on a miss.
+/// https://iquilezles.org/articles/intersectors/
+fn intersect_cylinder(
+    ray_origin: vec3<f32>,
+    ray_dir: vec3<f32>,
+    a: vec3<f32>,
+    b: vec3<f32>,
+    radius: f32,
+) -> vec4<f32> {
+    var ba = b - a;
+    var oc = ray_origin - a;
+    var baba = dot(ba, ba);
+    var bard = dot(ba, ray_dir);
+    var baoc = dot(ba, oc);
+
+    var k2 = baba - bard * bard;
+    var k1 = baba * dot(oc, ray_dir) - baoc * bard;
+    var k0 = baba * dot(oc, oc) - baoc * baoc - radius * radius * baba;
+
+    var h = k1 * k1 - k2 * k0;
+    if (h < 0.) {
+        return vec4<f32>(0., 0., 0., -1.);
+    }
+    h = sqrt(h);
+
+    // The side.
+    var t = (-k1 - h) / k2;
+    var y = baoc + t * bard;
+    if (y > 0. && y < baba) {
+        return vec4<f32>((oc + ray_dir * t - ba * y / baba) / radius, t);
+    }
+
+    // The caps.
+    t = (select(baba, 0., y < 0.) - baoc) / bard;
+    if (abs(k1 + k2 * t) < h) {
+        return vec4<f32>(ba * sign(y) / sqrt(baba), t);
+    }
+
+    return vec4<f32>(0., 0., 0., -1.);
+}
+
+struct ImpostorFrag {
+    surface: Surface,
+    depth: f32,
+    // Unjittered clip positions for this frame and the previous one; used for TAA velocity.
+    curr_clip: vec4<f32>,
+    prev_clip: vec4<f32>,
+    // False if the ray misses the shape.
+    hit: bool,
+}
+
+fn trace_impostor(impostor: ImpostorOut) -> ImpostorFrag {
+    var ray_origin = camera.position.xyz;
+    var ray_dir = normalize(impostor.world_posit - ray_origin);
+
+    var hit: vec4<f32>;
+    if (impostor.kind == 0u) {
+        hit = intersect_sphere(ray_origin, ray_dir, impostor.center, impostor.radius);
+    } else {
+        hit = intersect_cylinder(
+            ray_origin,
+            ray_dir,
+            impostor.center - impostor.axis,
+            impostor.center + impostor.axis,
+            impostor.radius,
+        );
+    }
+
+    var result: ImpostorFrag;
+    result.hit = hit.w > 0.;
+
+    var world_posit = ray_origin + ray_dir * hit.w;
+
+    var vertex: VertexOut;
+    vertex.normal = hit.xyz;
+    vertex.color = impostor.color;
+    vertex.shinyness = impostor.shinyness;
+    vertex.reflectivity = impostor.reflectivity;
+    vertex.lighting_factors = impostor.lighting_factors;
+    // Impostors have no texture coordinates.
+    vertex.material_i = -1;
+    vertex.world_posit = world_posit;
+
+    result.surface = vertex_surface(vertex);
+    result.curr_clip = camera.proj_view * vec4<f32>(world_posit, 1.);
+    result.prev_clip = camera.prev_proj_view * vec4<f32>(world_posit + impostor.prev_offset, 1.);
+    result.depth = result.curr_clip.z / result.curr_clip.w;
+
+    return result;
+}
+
+struct ImpostorFragOut {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+@fragment
+fn fs_impostor(impostor: ImpostorOut) -> ImpostorFragOut {
+    var frag = trace_impostor(impostor);
+    if (!frag.hit) {
+        discard;
+    }
+
+    var result: ImpostorFragOut;
+    result.color = light_surface(frag.surface);
+    result.depth = frag.depth;
+
+    return result;
+}
+
+struct ImpostorFragOutTaa {
+    @location(0) color: vec4<f32>,
+    @location(1) velocity: vec2<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+@fragment
+fn fs_impostor_taa(impostor: ImpostorOut) -> ImpostorFragOutTaa {
+    var frag = trace_impostor(impostor);
+    if (!frag.hit) {
+        discard;
+    }
+
+    var result: ImpostorFragOutTaa;
+    result.color = light_surface(frag.surface);
+    result.depth = frag.depth;
+    result.velocity = frag.curr_clip.xy / frag.curr_clip.w - frag.prev_clip.xy / frag.prev_clip.w;
+
+    return result;
+}
+
+struct ImpostorGBufferOut {
+    @location(0) albedo: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+    @location(2) material: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+@fragment
+fn fs_impostor_gbuffer(impostor: ImpostorOut) -> ImpostorGBufferOut {
+    var frag = trace_impostor(impostor);
+    if (!frag.hit) {
+        discard;
+    }
+
+    var props = gbuffer(frag.surface);
+
+    var result: ImpostorGBufferOut;
+    result.albedo = props.albedo;
+    result.normal = props.normal;
+    result.material = props.material;
+    result.depth = frag.depth;
+
+    return result;
+}
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) i: u32) -> @builtin(position) vec4<f32> {
+    // A single triangle that covers the viewport.
+    var uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+    return vec4<f32>(uv * vec2<f32>(2., -2.) + vec2<f32>(-1., 1.), 0., 1.);
+}
+
+/// The deferred lighting pass: lights each pixel of the G-buffer once.
+@fragment
+fn fs_deferred(@builtin(position) frag_posit: vec4<f32>) -> @location(0) vec4<f32> {
+    var pixel = vec2<i32>(frag_posit.xy);
+
+    var depth = textureLoad(gbuffer_depth, pixel, 0);
+    // Nothing was drawn here; keep the clear color.
+    if (depth >= 1.) {
+        discard;
+    }
+
+    var albedo = textureLoad(gbuffer_albedo, pixel, 0);
+    var normal = textureLoad(gbuffer_normal, pixel, 0);
+    var material = textureLoad(gbuffer_material, pixel, 0);
+
+    // Reconstruct the position along the view ray through this pixel, from its distance from the
+    // camera plane.
+    var p = deferred_params;
+    var ndc = (frag_posit.xy - p.viewport.xy) / p.viewport.zw * vec2<f32>(2., -2.)
+        + vec2<f32>(-1., 1.);
+    var view_z = p.near * p.far / (p.far - depth * (p.far - p.near));
+    var ray = p.forward.xyz + p.right.xyz * ndc.x + p.up.xyz * ndc.y;
+
+    var surface: Surface;
+    surface.world_posit = camera.position.xyz + ray * view_z;
+    surface.normal = normalize(normal.xyz);
+    surface.base_color = vec4<f32>(albedo.rgb, 1.);
+    surface.shinyness = normal.w;
+    surface.reflectivity = material.w;
+    surface.lighting_factors = material.xyz;
+
+    return light_surface(surface);
+}
+
+// Inputs to lighting, from a mesh or impostor fragment, or from the G-buffer.
+struct Surface {
+    world_posit: vec3<f32>,
+    normal: vec3<f32>,
+    // Linear, with alpha, including the material texture.
+    base_color: vec4<f32>,
+    shinyness: f32,
+    reflectivity: f32,
+    lighting_factors: vec3<f32>,
+}
+
+fn vertex_surface(vertex: VertexOut) -> Surface {
+    if (dot(camera.clip_plane.xyz, vertex.world_posit) + camera.clip_plane.w > 0.) {
+        discard;
+    }
+
+    // Derivatives for selecting material mip levels. These must be taken outside of the branch
+    // below, since it isn't uniform.
+    var uv_dx = dpdx(vertex.tex_coords);
+    var uv_dy = dpdy(vertex.tex_coords);
+
+    var base_color = vec4<f32>(input_color(vertex.color.rgb), vertex.color.a);
+    var normal = vertex.normal;
+    if (vertex.material_i >= 0) {
+        // Sampled as linear, since material textures are sRGB-encoded.
+        base_color *= textureSampleGrad(
+            material_maps,
+            material_sampler,
+            vertex.tex_coords,
+            vertex.material_i,
+            uv_dx,
+            uv_dy,
+        ) * vec4<f32>(input_color(vertex.texture_tint), 1.);
+
+        // Z is reconstructed, for two-channel maps. Green points towards lower V, and the
+        // bitangent towards higher. Meshes without tangents, and flat layers, leave the normal
+        // unchanged, or nearly so.
+        var mapped = textureSampleGrad(
+            normal_maps,
+            material_sampler,
+            vertex.tex_coords,
+            vertex.material_i,
+            uv_dx,
+            uv_dy,
+        ).xy * 2. - 1.;
+        var mapped_z = sqrt(max(1. - dot(mapped, mapped), 0.));
+        normal = normalize(
+            vertex.tangent * mapped.x - vertex.bitangent * mapped.y + vertex.normal * mapped_z
+        );
+    }
+
+    var result: Surface;
+    result.world_posit = vertex.world_posit;
+    result.normal = normal;
+    result.base_color = base_color;
+    result.shinyness = vertex.shinyness;
+    result.reflectivity = vertex.reflectivity;
+    result.lighting_factors = vertex.lighting_factors;
+
+    // Defined by the application's shader extension, or appended as a no-op; see `extension.rs`.
+    return extend_surface(result, vertex);
+}
+
+fn shade(vertex: VertexOut) -> vec4<f32> {
+    return light_surface(vertex_surface(vertex));
+}
+
+fn light_surface(surface: Surface) -> vec4<f32> {
     // Ambient lighting
     // todo: Don't multiply ambient for every fragment; do it on the CPU.
-    var ambient = lighting.ambient_color * lighting.ambient_intensity;
+    var ambient = vec4<f32>(input_color(lighting.ambient_color.rgb), lighting.ambient_color.a)
+        * lighting.ambient_intensity;
 
     // todo: Pass from CPU
     var fog_color = vec3<f32>(1., 1., 1.);
     // todo: Pass from CPU
     var fog_thickness = 0.001;
 
-    var view_diff = camera.position.xyz - vertex.world_posit.xyz;
+    var view_diff = camera.position.xyz - surface.world_posit.xyz;
     var view_dir = normalize(view_diff);
 
     // todo: Color the fog.
@@ -162,27 +656,37 @@ fn fs_main(vertex: VertexOut) -> @location(0) vec4<f32> {
     var specular = vec4<f32>(0., 0., 0., 0.);
 
 
+    // With clustered lighting, we only loop over lights near this position.
+    var cluster = light_cluster(surface.world_posit);
+    var num_lights = cluster_light_count(cluster);
+
     // todo: arrayLength on this variable is not working. Use size passed from CPU in the
     // todo meanwhile.
 //    for (var i=0; i < arrayLength(lighting.point_lights); i++) {
-    for (var i=0; i < lighting.lights_len; i++) {
-        var light = lighting.point_lights[i];
+    for (var j = 0u; j < num_lights; j++) {
+        var light = lighting.point_lights[cluster_light(cluster, j)];
+        var diffuse_color = vec4<f32>(input_color(light.diffuse_color.rgb), light.diffuse_color.a);
+        var specular_color =
+            vec4<f32>(input_color(light.specular_color.rgb), light.specular_color.a);
 
         // Direction from light to the vertex; we use this to calculate attentiation,
         // and diffuse-lighting cosine loss.
 
-        var light_to_vert_diff =  vertex.world_posit.xyz - light.position.xyz;
+        var light_to_vert_diff =  surface.world_posit.xyz - light.position.xyz;
 
         var light_to_vert_dir = normalize(light_to_vert_diff);
 
+        var shadow = shadow_factor(light.shadow_i, light_to_vert_diff, surface.world_posit);
+
         // This expr applies the inverse square to find falloff with distance.
         // Note that we use the word "attenuation" in perhaps the inverse of how we usually use it; 1.0
         // is full intensity here.
-        var dist_attenuation = 1. / (pow(light_to_vert_diff.x, 2.) + pow(light_to_vert_diff.y, 2.) + pow(light_to_vert_diff.z, 2.));
+        var falloff_diff = light_to_vert_diff / lighting.falloff_scale;
+        var dist_attenuation = 1. / (pow(falloff_diff.x, 2.) + pow(falloff_diff.y, 2.) + pow(falloff_diff.z, 2.));
 
         // Diffuse lighting. This is essentially cosine los.
-        var diffuse_attenuation = max(dot(vertex.normal, -light_to_vert_dir), 0.);
-        diffuse += light.diffuse_color * diffuse_attenuation * light.diffuse_intensity * dist_attenuation;
+        var diffuse_attenuation = max(dot(surface.normal, -light_to_vert_dir), 0.) * shadow;
+        diffuse += diffuse_color * diffuse_attenuation * light.diffuse_intensity * dist_attenuation;
 
         // Specular lighting.
         var specular_this_light = vec4<f32>(0., 0., 0., 0.);
@@ -191,11 +695,11 @@ fn fs_main(vertex: VertexOut) -> @location(0) vec4<f32> {
 //          // Blinn half vector
             var half_dir = normalize(view_dir + light_to_vert_dir);
 
-            var specular_coeff = pow(max(dot(vertex.normal, half_dir), 0.), vertex.shinyness);
+            var specular_coeff = pow(max(dot(surface.normal, half_dir), 0.), surface.shinyness);
 
-            specular_this_light = light.specular_color * specular_coeff * light.specular_intensity * dist_attenuation;
+            specular_this_light = specular_color * specular_coeff * light.specular_intensity * dist_attenuation * shadow;
 
-            specular += specular_this_light * clamp(dot(vertex.normal, light_to_vert_dir), 0.0, 1.0);
+            specular += specular_this_light * clamp(dot(surface.normal, light_to_vert_dir), 0.0, 1.0);
         }
     }
 
@@ -203,8 +707,142 @@ fn fs_main(vertex: VertexOut) -> @location(0) vec4<f32> {
 //    var result = (ambient + diffuse + specular) * vertex.color;
 
     // Process alpha separately.
-    var lightingColor = ambient.rgb + diffuse.rgb + specular.rgb;
-    var result = vec4<f32>(lightingColor * vertex.color.rgb, vertex.color.a);
+    var factors = surface.lighting_factors;
+    var lightingColor =
+        ambient.rgb * factors.x + diffuse.rgb * factors.y + specular.rgb * factors.z;
+    var base_color = surface.base_color;
+
+    var lit = output_color(cel_quantize(lightingColor) * base_color.rgb);
+
+    // Probes store output colors, so we blend them after converting ours.
+    if (surface.reflectivity > 0.) {
+        var reflect_dir = reflect(-view_dir, surface.normal);
+        var reflection = env_reflection(surface.world_posit, reflect_dir);
+        // Alpha is 0 if no probe is in range.
+        lit = mix(lit, reflection.rgb, surface.reflectivity * reflection.a);
+    }
+
+    var result = vec4<f32>(lit, base_color.a);
 
     return result;
-}
\ No newline at end of file
+}
+
+/// The index of the light cluster containing a position; -1 if clustered lighting is disabled, or
+/// the position is outside the view.
+fn light_cluster(world_posit: vec3<f32>) -> i32 {
+    var p = cluster_params;
+    if (p.grid_x == 0u) {
+        return -1;
+    }
+
+    var clip = camera.proj_view * vec4<f32>(world_posit, 1.);
+    var ndc = clip.xy / clip.w;
+    if (clip.w <= 0. || abs(ndc.x) > 1. || abs(ndc.y) > 1.) {
+        return -1;
+    }
+
+    // Slices are spaced exponentially by depth; see `cluster.wgsl`.
+    var tile = vec2<u32>((ndc * 0.5 + 0.5) * vec2<f32>(f32(p.grid_x), f32(p.grid_y)));
+    var slice = u32(max(log(clip.w / p.near) / log(p.far / p.near), 0.) * f32(p.grid_z));
+
+    var x = min(tile.x, p.grid_x - 1u);
+    var y = min(tile.y, p.grid_y - 1u);
+    var z = min(slice, p.grid_z - 1u);
+
+    return i32((z * p.grid_y + y) * p.grid_x + x);
+}
+
+/// The number of lights affecting a cluster; all lights if `cluster` is -1.
+fn cluster_light_count(cluster: i32) -> u32 {
+    if (cluster < 0) {
+        return u32(lighting.lights_len);
+    }
+    return cluster_lights[u32(cluster) * (cluster_params.max_lights + 1u)];
+}
+
+/// The index of a cluster's `j`th light.
+fn cluster_light(cluster: i32, j: u32) -> u32 {
+    if (cluster < 0) {
+        return j;
+    }
+    return cluster_lights[u32(cluster) * (cluster_params.max_lights + 1u) + 1u + j];
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    return select(
+        pow((color + 0.055) / 1.055, vec3<f32>(2.4)),
+        color / 12.92,
+        color <= vec3<f32>(0.04045),
+    );
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    return select(
+        1.055 * pow(color, vec3<f32>(1. / 2.4)) - 0.055,
+        color * 12.92,
+        color <= vec3<f32>(0.0031308),
+    );
+}
+
+/// Quantize lighting into bands of brightness, for cel shading. We scale all channels by the same
+/// amount, preserving the light's hue.
+fn cel_quantize(lighting: vec3<f32>) -> vec3<f32> {
+    var brightness = max(max(lighting.r, lighting.g), lighting.b);
+    if (color_settings.cel_bands == 0u || brightness <= 0.) {
+        return lighting;
+    }
+
+    var bands = f32(color_settings.cel_bands);
+    return lighting * (ceil(brightness * bands) / bands / brightness);
+}
+
+/// Convert an entity or light color to linear, for lighting.
+fn input_color(color: vec3<f32>) -> vec3<f32> {
+    if (color_settings.srgb_input != 0u) {
+        return srgb_to_linear(color);
+    }
+    return color;
+}
+
+/// Apply exposure and gamma to a lit color, and encode it for the surface.
+fn output_color(color: vec3<f32>) -> vec3<f32> {
+    var exposed = max(color * color_settings.exposure, vec3<f32>(0.));
+    var result = pow(exposed, vec3<f32>(1. / color_settings.gamma));
+
+    if (color_settings.encode_srgb != 0u) {
+        return linear_to_srgb(clamp(result, vec3<f32>(0.), vec3<f32>(1.)));
+    }
+    return result;
+}
+
+/// The color of the environment in a direction, from the nearest probe in range of a position.
+/// Alpha is 1 if a probe is in range, and 0 otherwise.
+fn env_reflection(world_posit: vec3<f32>, dir: vec3<f32>) -> vec4<f32> {
+    var nearest = -1;
+    var nearest_dist = 0.;
+
+    for (var i = 0u; i < env_probes.num_probes; i++) {
+        var probe = env_probes.probes[i];
+        var dist = distance(world_posit, probe.xyz);
+
+        if (dist <= probe.w && (nearest < 0 || dist < nearest_dist)) {
+            nearest = i32(i);
+            nearest_dist = dist;
+        }
+    }
+
+    if (nearest < 0) {
+        return vec4<f32>(0.);
+    }
+
+    // We treat the environment as infinitely distant, so we look up the direction from the
+    // probe's position.
+    var layer = u32(nearest) * 6u + cube_face(dir);
+    var probe_posit = env_probes.probes[nearest].xyz;
+
+    var clip = probe_mats[layer] * vec4<f32>(probe_posit + dir, 1.);
+    var uv = clip.xy / clip.w * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5);
+
+    var color = textureSampleLevel(probe_maps, probe_sampler, uv, layer, 0.);
+    return vec4<f32>(color.rgb, 1.);
+}
@@ -0,0 +1,95 @@
+//! The engine's WGSL interface: the layouts of buffers shared with user shaders, as WGSL chunks
+//! that shader extensions and compute passes can include, instead of copying them. These are
+//! updated along with the buffers, so shaders that include them stay compatible.
+//!
+//! Shaders support two directives, each on its own line:
+//!
+//! - `#include "name"` inserts a chunk: `camera`, `lighting`, or `instance`; see the constants
+//!   below. Each chunk is inserted once; later includes of it are ignored. Shader extensions are
+//!   appended to the main shader, which includes all of them. The engine's shaders also share
+//!   `shadow_sample`, for sampling shadow maps.
+//! - `#interface_version N` declares the interface version the shader was written for. If this
+//!   isn't `SHADER_INTERFACE_VERSION`, the shader isn't built, and the error is reported in
+//!   `Scene::shader_errors`. Shaders without it are built regardless.
+//!
+//! The version is incremented when a chunk changes in a way that may break shaders using it, eg
+//! a field is removed or moved. Adding fields to the end of a struct doesn't change it.
+//!
+//! The main shader's bind groups are: 0, the camera; 1, lighting, environment probes, color
+//! settings, and clustered lighting; 2, instance data, ie previous model matrices, the palette,
+//! and material textures; 3, shadow maps. Extensions' groups start at 4.
+
+/// The current interface version. See the module documentation.
+pub const SHADER_INTERFACE_VERSION: u32 = 2;
+
+/// The `Camera` uniform struct.
+pub const CAMERA_WGSL: &str = include_str!("camera.wgsl");
+
+/// The `PointLight` and `Lighting` structs, for the lighting storage buffer.
+pub const LIGHTING_WGSL: &str = include_str!("lighting.wgsl");
+
+/// The `InstanceIn` vertex attributes, functions to decode its packed fields, and instance buffer
+/// offsets for compute passes.
+pub const INSTANCE_WGSL: &str = include_str!("instance.wgsl");
+
+/// The `ShadowParams` struct, and `shadow_factor`, for the engine's shaders that sample shadow
+/// maps. These declare the shadow bindings themselves, since their bind groups differ.
+pub(crate) const SHADOW_SAMPLE_WGSL: &str = include_str!("shadow_sample.wgsl");
+
+fn chunk(name: &str) -> Option<&'static str> {
+    match name {
+        "camera" => Some(CAMERA_WGSL),
+        "lighting" => Some(LIGHTING_WGSL),
+        "instance" => Some(INSTANCE_WGSL),
+        "shadow_sample" => Some(SHADOW_SAMPLE_WGSL),
+        _ => None,
+    }
+}
+
+/// Resolve `#include` and `#interface_version` directives in WGSL source. See the module
+/// documentation. This is run on shaders before building them; it's exposed so applications can
+/// check their shaders, eg in tests.
+pub fn preprocess_wgsl(source: &str) -> Result<String, String> {
+    let mut result = String::with_capacity(source.len());
+    let mut included = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let trimmed = line.trim();
+
+        if let Some(name) = trimmed.strip_prefix("#include") {
+            let name = name.trim().trim_matches('"');
+            let Some(wgsl) = chunk(name) else {
+                return Err(format!("Line {}: unknown shader chunk \"{name}\"", i + 1));
+            };
+
+            if !included.contains(&name) {
+                included.push(name);
+                result.push_str(wgsl);
+            }
+        } else if let Some(version) = trimmed.strip_prefix("#interface_version") {
+            let version: u32 = version
+                .trim()
+                .parse()
+                .map_err(|_| format!("Line {}: invalid interface version", i + 1))?;
+
+            if version != SHADER_INTERFACE_VERSION {
+                return Err(format!(
+                    "Written for shader interface version {version}; the engine's is \
+                     {SHADER_INTERFACE_VERSION}"
+                ));
+            }
+        } else if trimmed.starts_with('#') {
+            return Err(format!("Line {}: unknown directive \"{trimmed}\"", i + 1));
+        } else {
+            result.push_str(line);
+            result.push('\n');
+        }
+    }
+
+    Ok(result)
+}
+
+/// Preprocess one of the engine's shaders. These only include known chunks, so this can't fail.
+pub(crate) fn engine_wgsl(source: &str) -> String {
+    preprocess_wgsl(source).unwrap()
+}
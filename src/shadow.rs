@@ -0,0 +1,421 @@
+//! Omnidirectional shadows for point lights. For each shadow-casting light, we render the distance
+//! from the light to the nearest surface into the 6 faces of a cube map. Cube maps are stored
+//! as layers of a single depth texture array; 6 layers per light. The main fragment shader selects
+//! the face from the direction to the light, and compares its distance to the stored one.
+//!
+//! We store faces as an array instead of using cube textures, so the shader can project into each
+//! face using the same matrices we render the faces with.
+
+use core::f32::consts::TAU;
+
+use lin_alg::f32::Quaternion;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferUsages,
+    CommandEncoder, Device, FragmentState, Queue, RenderPassDepthStencilAttachment,
+    RenderPassDescriptor, RenderPipeline, ShaderStages, StoreOp, TextureView, VertexState,
+};
+
+use crate::{
+    camera::Camera,
+    graphics::{FWD_VEC, RIGHT_VEC, UP_VEC},
+    lighting::PointLight,
+    pass::MeshBuffers,
+    sub_range::SubRangeDraw,
+    system::DEPTH_FORMAT,
+    types::{Instance, Vertex, F32_SIZE, MAT4_SIZE, VEC4_SIZE},
+};
+
+/// Width and height of each cube face, in pixels, by default; see
+/// `GraphicsSettings::shadow_resolution`.
+pub const SHADOW_MAP_SIZE: u32 = 1_024;
+
+/// Near plane of the cube face projections.
+const SHADOW_NEAR: f32 = 0.1;
+
+/// Subtracted from the (normalized) distance when comparing, to prevent self-shadowing.
+const SHADOW_BIAS: f32 = 0.002;
+
+pub(crate) const FACES_PER_LIGHT: usize = 6;
+
+/// Projection-view matrix, light position, and far distance.
+const FACE_SIZE: usize = MAT4_SIZE + VEC4_SIZE + VEC4_SIZE;
+
+/// We index face uniforms with dynamic offsets; these must be aligned to this. This is the default
+/// value of `min_uniform_buffer_offset_alignment`.
+const FACE_STRIDE: usize = 256;
+
+const SHADOW_PARAMS_SIZE: usize = 4 * F32_SIZE;
+
+/// Cube face view directions, in the order the shader expects: +X, -X, +Y, -Y, +Z, -Z.
+pub(crate) fn face_orientations() -> [Quaternion; FACES_PER_LIGHT] {
+    [
+        Quaternion::from_unit_vecs(FWD_VEC, RIGHT_VEC),
+        Quaternion::from_unit_vecs(FWD_VEC, RIGHT_VEC * -1.),
+        Quaternion::from_unit_vecs(FWD_VEC, UP_VEC),
+        Quaternion::from_unit_vecs(FWD_VEC, UP_VEC * -1.),
+        Quaternion::new_identity(),
+        // `from_unit_vecs` is undefined for opposite vectors.
+        Quaternion::from_axis_angle(UP_VEC, TAU / 2.),
+    ]
+}
+
+/// Shadow map textures, and the pipeline that renders them.
+pub(crate) struct ShadowState {
+    /// The maximum number of shadow-casting lights. If 0, we don't render shadows.
+    pub max_lights: usize,
+    /// One view per cube face, for rendering.
+    face_views: Vec<TextureView>,
+    /// Width and height of each face, in pixels.
+    resolution: u32,
+    pipeline: RenderPipeline,
+    face_buf: Buffer,
+    face_bind_group: BindGroup,
+    /// Projection-view matrices of each face; used by the main shader for lookups.
+    mats_buf: Buffer,
+    params_buf: Buffer,
+    /// Bound to the main render pipeline, for sampling the shadow maps.
+    pub layout: BindGroupLayout,
+    pub bind_group: BindGroup,
+}
+
+impl ShadowState {
+    pub fn new(device: &Device, max_lights: usize, resolution: u32) -> Self {
+        // We always create at least one cube map, so the main pipeline's bindings are valid.
+        let num_layers = (max_lights.max(1) * FACES_PER_LIGHT) as u32;
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow map texture"),
+            size: wgpu::Extent3d {
+                width: resolution.max(1),
+                height: resolution.max(1),
+                depth_or_array_layers: num_layers,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let face_views = (0..num_layers)
+            .map(|i| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Shadow map face view"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: i,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Shadow map view"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        // Linear filtering with a comparison sampler gives us some smoothing at shadow edges.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow map sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let face_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow face buffer"),
+            size: (num_layers as usize * FACE_STRIDE) as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mats_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow matrix buffer"),
+            size: (num_layers as usize * MAT4_SIZE) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let params_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Shadow params buffer"),
+            contents: &[0; SHADOW_PARAMS_SIZE],
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let layout_face = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(FACE_SIZE as u64),
+                },
+                count: None,
+            }],
+            label: Some("Shadow face bind group layout"),
+        });
+
+        let face_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout_face,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &face_buf,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(FACE_SIZE as u64),
+                }),
+            }],
+            label: Some("Shadow face bind group"),
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
+            label: Some("Shadow bind group layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: mats_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("Shadow bind group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shadow.wgsl").into()),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow pipeline layout"),
+            bind_group_layouts: &[&layout_face],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[Vertex::desc(), Instance::desc()],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[],
+            }),
+            // We don't cull, so open meshes still cast shadows.
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            max_lights,
+            face_views,
+            resolution: resolution.max(1),
+            pipeline,
+            face_buf,
+            face_bind_group,
+            mats_buf,
+            params_buf,
+            layout,
+            bind_group,
+        }
+    }
+
+    /// The view of one cube face of a shadow-casting light's shadow map, by index among
+    /// shadow-casting lights, for debug views. `None` if there's no such light.
+    pub fn face_view(&self, light: usize, face: usize) -> Option<&TextureView> {
+        if light >= self.max_lights || face >= FACES_PER_LIGHT {
+            return None;
+        }
+        self.face_views.get(light * FACES_PER_LIGHT + face)
+    }
+
+    /// Width and height of each cube face, in pixels.
+    pub fn resolution(&self) -> u32 {
+        self.resolution
+    }
+
+    /// Render shadow maps for shadow-casting lights, up to the maximum count. `far` is the
+    /// maximum distance from a light that casts shadows. Each of `instance_sets` draws its
+    /// buffers' instances, and entities drawing part of their mesh from them. Run this before the
+    /// main render pass.
+    pub fn encode(
+        &self,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        lights: &[PointLight],
+        far: f32,
+        instance_sets: &[(MeshBuffers, &[SubRangeDraw])],
+    ) {
+        let shadow_lights: Vec<&PointLight> = lights
+            .iter()
+            .filter(|l| l.casts_shadow)
+            .take(self.max_lights)
+            .collect();
+
+        let mut params = [0; SHADOW_PARAMS_SIZE];
+        params[0..4].clone_from_slice(&(shadow_lights.len() as u32).to_ne_bytes());
+        params[F32_SIZE..2 * F32_SIZE].clone_from_slice(&far.to_ne_bytes());
+        params[2 * F32_SIZE..3 * F32_SIZE].clone_from_slice(&SHADOW_BIAS.to_ne_bytes());
+        queue.write_buffer(&self.params_buf, 0, &params);
+
+        if shadow_lights.is_empty() {
+            return;
+        }
+
+        let orientations = face_orientations();
+
+        let mut face_data = vec![0; shadow_lights.len() * FACES_PER_LIGHT * FACE_STRIDE];
+        let mut mats_data = Vec::with_capacity(shadow_lights.len() * FACES_PER_LIGHT * MAT4_SIZE);
+
+        for (i_light, light) in shadow_lights.iter().enumerate() {
+            for (i_face, orientation) in orientations.iter().enumerate() {
+                let mut cam = Camera {
+                    fov_y: TAU / 4.,
+                    aspect: 1.,
+                    near: SHADOW_NEAR,
+                    far,
+                    position: light.position,
+                    orientation: *orientation,
+                    ..Default::default()
+                };
+                cam.update_proj_mat();
+
+                let proj_view = cam.proj_mat.clone() * cam.view_mat();
+                let proj_view_bytes = proj_view.to_bytes();
+
+                let start = (i_light * FACES_PER_LIGHT + i_face) * FACE_STRIDE;
+                let face = &mut face_data[start..start + FACE_SIZE];
+
+                face[0..MAT4_SIZE].clone_from_slice(&proj_view_bytes);
+                face[MAT4_SIZE..MAT4_SIZE + VEC4_SIZE]
+                    .clone_from_slice(&light.position.to_bytes_uniform());
+                face[MAT4_SIZE + VEC4_SIZE..MAT4_SIZE + VEC4_SIZE + F32_SIZE]
+                    .clone_from_slice(&far.to_ne_bytes());
+
+                mats_data.extend_from_slice(&proj_view_bytes);
+            }
+        }
+
+        queue.write_buffer(&self.face_buf, 0, &face_data);
+        queue.write_buffer(&self.mats_buf, 0, &mats_data);
+
+        for i_layer in 0..shadow_lights.len() * FACES_PER_LIGHT {
+            let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Shadow render pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: &self.face_views[i_layer],
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, &self.face_bind_group, &[(i_layer * FACE_STRIDE) as u32]);
+
+            for (buffers, sub_ranges) in instance_sets {
+                rpass.set_vertex_buffer(0, buffers.vertex_buf.slice(..));
+                rpass.set_vertex_buffer(1, buffers.instance_buf.slice(..));
+                rpass.set_index_buffer(buffers.index_buf.slice(..), wgpu::IndexFormat::Uint32);
+
+                for (range, (instance_start, instance_count)) in
+                    buffers.mesh_ranges.iter().zip(buffers.mesh_mappings)
+                {
+                    rpass.draw_indexed(
+                        range.index_start..range.index_start + range.index_count,
+                        range.vertex_start,
+                        *instance_start..instance_start + instance_count,
+                    );
+                }
+
+                for draw in *sub_ranges {
+                    if let Some((start, end, base_vertex)) = draw.indices(buffers.mesh_ranges) {
+                        let instances = draw.instance..draw.instance + 1;
+                        rpass.draw_indexed(start..end, base_vertex, instances);
+                    }
+                }
+            }
+        }
+    }
+}
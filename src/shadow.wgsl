@@ -0,0 +1,52 @@
+// Renders the distance from a point light to the nearest surface, for one cube map face.
+
+struct Face {
+    proj_view: mat4x4<f32>,
+    light_position: vec4<f32>,
+    // Only x is used.
+    far: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> face: Face;
+
+struct VertexIn {
+    @location(0) position: vec3<f32>,
+}
+
+struct InstanceIn {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+}
+
+struct VertexOut {
+    @builtin(position) clip_posit: vec4<f32>,
+    @location(0) world_posit: vec3<f32>,
+}
+
+@vertex
+fn vs_main(vertex_in: VertexIn, instance: InstanceIn) -> VertexOut {
+    var model_mat = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+
+    var world_posit = model_mat * vec4<f32>(vertex_in.position, 1.0);
+
+    var result: VertexOut;
+    result.clip_posit = face.proj_view * world_posit;
+    result.world_posit = world_posit.xyz;
+
+    return result;
+}
+
+// We store linear distance, normalized to the far distance, instead of projected depth. This
+// lets the main shader compare distances directly.
+@fragment
+fn fs_main(vertex: VertexOut) -> @builtin(frag_depth) f32 {
+    return length(vertex.world_posit - face.light_position.xyz) / face.far.x;
+}
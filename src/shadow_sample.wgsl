@@ -0,0 +1,40 @@
+// Shadow map sampling, shared by the main and ground plane shaders. Shaders including this
+// declare the shadow bindings: `shadow_params`, `shadow_mats`, `shadow_maps`, and
+// `shadow_sampler`; see `shadow.rs`.
+
+struct ShadowParams {
+    num_maps: u32,
+    // Distances in the shadow maps are normalized to this.
+    far: f32,
+    bias: f32,
+}
+
+/// The cube face a direction from the cube's center points into.
+fn cube_face(dir: vec3<f32>) -> u32 {
+    var dir_abs = abs(dir);
+
+    if (dir_abs.x >= dir_abs.y && dir_abs.x >= dir_abs.z) {
+        return select(1u, 0u, dir.x > 0.);
+    }
+    if (dir_abs.y >= dir_abs.z) {
+        return select(3u, 2u, dir.y > 0.);
+    }
+    return select(5u, 4u, dir.z > 0.);
+}
+
+/// 0 if the position is fully shadowed from the light, and 1 if it's fully lit.
+/// `light_to_vert_diff` is from the light to the position.
+fn shadow_factor(shadow_i: i32, light_to_vert_diff: vec3<f32>, world_posit: vec3<f32>) -> f32 {
+    if (shadow_i < 0 || u32(shadow_i) >= shadow_params.num_maps) {
+        return 1.;
+    }
+
+    var layer = u32(shadow_i) * 6u + cube_face(light_to_vert_diff);
+
+    var clip = shadow_mats[layer] * vec4<f32>(world_posit, 1.);
+    var uv = clip.xy / clip.w * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5);
+
+    var dist = length(light_to_vert_diff) / shadow_params.far - shadow_params.bias;
+
+    return textureSampleCompareLevel(shadow_maps, shadow_sampler, uv, layer, dist);
+}
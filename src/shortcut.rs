@@ -0,0 +1,159 @@
+//! Keyboard shortcuts: key chords, eg Ctrl+S or F11, that the application registers with a tag.
+//! The engine tracks modifier keys, and matches chords against key presses in the window.
+//! Handlers check for triggered tags with `Shortcuts::triggered`, eg:
+//!
+//! `scene.shortcuts.register(KeyChord::new(KeyCode::KeyS).ctrl(), "save");`
+//!
+//! and, in the render handler, `if scene.shortcuts.triggered("save") { ... }`.
+//!
+//! Shortcuts don't trigger while the GUI uses the keyboard, eg when typing in a text field. Key
+//! presses that trigger a shortcut aren't passed to camera controls, or the event handler.
+
+use winit::{
+    event::{ElementState, RawKeyEvent},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+/// Modifier keys held down. Left and right keys are equivalent.
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    /// The Windows key, or Command on Mac.
+    pub logo: bool,
+}
+
+impl Modifiers {
+    fn from_winit(state: ModifiersState) -> Self {
+        Self {
+            ctrl: state.control_key(),
+            shift: state.shift_key(),
+            alt: state.alt_key(),
+            logo: state.super_key(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// A key, and the modifiers held with it. A chord matches only if exactly these modifiers are
+/// held, so Ctrl+S doesn't trigger on Ctrl+Shift+S. Keys are physical, ie by position on a US
+/// layout, so chords work the same with other layouts.
+pub struct KeyChord {
+    pub key: KeyCode,
+    pub modifiers: Modifiers,
+}
+
+impl KeyChord {
+    /// A key, without modifiers. Add them with eg `.ctrl()`.
+    pub fn new(key: KeyCode) -> Self {
+        Self {
+            key,
+            modifiers: Default::default(),
+        }
+    }
+
+    pub fn ctrl(mut self) -> Self {
+        self.modifiers.ctrl = true;
+        self
+    }
+
+    pub fn shift(mut self) -> Self {
+        self.modifiers.shift = true;
+        self
+    }
+
+    pub fn alt(mut self) -> Self {
+        self.modifiers.alt = true;
+        self
+    }
+
+    pub fn logo(mut self) -> Self {
+        self.modifiers.logo = true;
+        self
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// Registered shortcuts, the modifiers currently held, and tags triggered this frame.
+pub struct Shortcuts {
+    /// Chords, and the tag each triggers.
+    bindings: Vec<(KeyChord, String)>,
+    modifiers: Modifiers,
+    /// Tags of chords pressed since the last frame.
+    triggered: Vec<String>,
+}
+
+impl Shortcuts {
+    /// Trigger `tag` when `chord` is pressed. A chord may trigger several tags, and a tag may be
+    /// triggered by several chords, eg for alternate bindings.
+    pub fn register(&mut self, chord: KeyChord, tag: &str) {
+        if !self.bindings.iter().any(|(c, t)| *c == chord && t == tag) {
+            self.bindings.push((chord, tag.to_owned()));
+        }
+    }
+
+    /// Remove all chords that trigger `tag`.
+    pub fn unregister(&mut self, tag: &str) {
+        self.bindings.retain(|(_, t)| t != tag);
+    }
+
+    /// Chords that trigger `tag`, eg to show them in a menu.
+    pub fn chords(&self, tag: &str) -> Vec<KeyChord> {
+        self.bindings
+            .iter()
+            .filter(|(_, t)| t == tag)
+            .map(|(c, _)| *c)
+            .collect()
+    }
+
+    /// True if a chord that triggers `tag` was pressed since the last frame. This is true for all
+    /// handlers run in the frame.
+    pub fn triggered(&self, tag: &str) -> bool {
+        self.triggered.iter().any(|t| t == tag)
+    }
+
+    /// The modifier keys currently held.
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    pub(crate) fn set_modifiers(&mut self, state: ModifiersState) {
+        self.modifiers = Modifiers::from_winit(state);
+    }
+
+    /// Trigger the tags of chords matching a key press. Returns true if any match, in which case
+    /// the press is consumed.
+    pub(crate) fn handle_key(&mut self, event: &RawKeyEvent) -> bool {
+        if event.state != ElementState::Pressed {
+            return false;
+        }
+        let PhysicalKey::Code(key) = event.physical_key else {
+            return false;
+        };
+
+        let chord = KeyChord {
+            key,
+            modifiers: self.modifiers,
+        };
+
+        let mut matched = false;
+        for (c, tag) in &self.bindings {
+            if *c != chord {
+                continue;
+            }
+
+            matched = true;
+            if !self.triggered.contains(tag) {
+                self.triggered.push(tag.clone());
+            }
+        }
+
+        matched
+    }
+
+    /// Run at the end of each frame, after all handlers.
+    pub(crate) fn clear_triggered(&mut self) {
+        self.triggered.clear();
+    }
+}
@@ -0,0 +1,257 @@
+//! A slice plane, eg for cutting into meshes or volume data. When `Scene::slice_plane` is set,
+//! geometry on the side its normal points to is hidden, and the plane is drawn as a square with
+//! an arrow along its normal. Dragging either with the left mouse button moves the plane along
+//! its normal.
+//!
+//! The application can slice its own data with the plane's equation: check `SlicePlane::moved`
+//! from the render handler, or set `SlicePlane::on_change`. Clipping applies to the main pass,
+//! but not shadow maps, so clipped geometry still casts shadows.
+
+use lin_alg::f32::Vec3;
+
+use crate::{
+    graphics::{RIGHT_VEC, UP_VEC},
+    measure::cursor_ray,
+    types::{EngineUpdates, Scene, F32_SIZE, VEC4_SIZE},
+};
+
+/// The size of the clip plane in the camera uniform, which follows its TAA portion.
+pub(crate) const CLIP_PLANE_SIZE: usize = VEC4_SIZE;
+
+/// The length of the normal arrow, relative to the plane's half size.
+const ARROW_RATIO: f32 = 0.5;
+
+/// How close to the normal arrow a click grabs it, relative to the plane's half size.
+const GRAB_RADIUS_RATIO: f32 = 0.05;
+
+#[derive(Clone, Debug)]
+pub struct SlicePlane {
+    /// A point on the plane, in world space. This is the center of the square drawn.
+    pub position: Vec3,
+    /// The plane's normal; geometry on the side it points to is clipped. Needn't be normalized.
+    pub normal: Vec3,
+    /// Half the width of the square drawn, in world units.
+    pub half_size: f32,
+    /// If true, geometry in front of the plane is hidden. Otherwise, the plane is only a widget.
+    pub clip: bool,
+    /// If true, the plane is drawn, and can be dragged with the mouse.
+    pub draggable: bool,
+    /// RGBA, from 0 to 1.
+    pub color: [f32; 4],
+    /// Set when the plane is dragged, and cleared after each frame, eg to re-slice data from the
+    /// render handler.
+    pub moved: bool,
+    /// Called whenever the plane is dragged, with its new position. Its updates are applied
+    /// immediately.
+    pub on_change: Option<fn(&SlicePlane, &mut Scene) -> EngineUpdates>,
+    /// The position, and distance along the normal under the cursor, when a drag started.
+    drag_start: Option<(Vec3, f32)>,
+}
+
+impl SlicePlane {
+    pub fn new(position: Vec3, normal: Vec3, half_size: f32) -> Self {
+        Self {
+            position,
+            normal,
+            half_size,
+            clip: true,
+            draggable: true,
+            color: [0.3, 0.8, 1., 1.],
+            moved: false,
+            on_change: None,
+            drag_start: None,
+        }
+    }
+
+    /// The plane as (a, b, c, d), where a point is in front of it if `a*x + b*y + c*z + d > 0`;
+    /// (a, b, c) is the unit normal. All zero if the normal is.
+    pub fn equation(&self) -> [f32; 4] {
+        if self.normal.magnitude_squared() == 0. {
+            return [0.; 4];
+        }
+
+        let n = self.normal.to_normalized();
+        [n.x, n.y, n.z, -n.dot(self.position)]
+    }
+
+    /// The signed distance from a point to the plane; positive in front of it.
+    pub fn distance(&self, point: Vec3) -> f32 {
+        let [a, b, c, d] = self.equation();
+        a * point.x + b * point.y + c * point.z + d
+    }
+
+    /// If the plane is being dragged.
+    pub fn dragging(&self) -> bool {
+        self.drag_start.is_some()
+    }
+
+    /// Unit vectors spanning the plane.
+    fn basis(&self) -> (Vec3, Vec3) {
+        let n = self.normal.to_normalized();
+        let reference = if n.dot(UP_VEC).abs() > 0.9 {
+            RIGHT_VEC
+        } else {
+            UP_VEC
+        };
+
+        let u = n.cross(reference).to_normalized();
+        (u, n.cross(u))
+    }
+
+    /// The distance along the normal, from `base`, of the point on the normal's axis through
+    /// `base` nearest a ray. `None` if the ray is nearly parallel to the normal.
+    fn axis_param(&self, base: Vec3, origin: Vec3, dir: Vec3) -> Option<f32> {
+        let n = self.normal.to_normalized();
+        let w = base - origin;
+
+        let b = n.dot(dir);
+        let denom = 1. - b * b;
+        if denom < 1e-4 {
+            return None;
+        }
+
+        Some((b * dir.dot(w) - n.dot(w)) / denom)
+    }
+
+    /// If a ray hits the square, or passes near the normal arrow.
+    fn grabbed(&self, origin: Vec3, dir: Vec3) -> bool {
+        let n = self.normal.to_normalized();
+
+        let denom = n.dot(dir);
+        if denom.abs() > 1e-6 {
+            let t = n.dot(self.position - origin) / denom;
+            let offset = origin + dir * t - self.position;
+            let (u, v) = self.basis();
+
+            if t > 0.
+                && offset.dot(u).abs() <= self.half_size
+                && offset.dot(v).abs() <= self.half_size
+            {
+                return true;
+            }
+        }
+
+        let Some(s) = self.axis_param(self.position, origin, dir) else {
+            return false;
+        };
+        if s < 0. || s > self.half_size * ARROW_RATIO {
+            return false;
+        }
+
+        // The distance from the ray to the nearest point on the arrow.
+        let on_axis = self.position + n * s;
+        let to_axis = on_axis - origin;
+        let t = to_axis.dot(dir);
+
+        t > 0. && (to_axis - dir * t).magnitude() <= self.half_size * GRAB_RADIUS_RATIO
+    }
+}
+
+/// The clip plane portion of the camera uniform; all zero, which clips nothing, unless a slice
+/// plane is clipping.
+pub(crate) fn clip_plane_bytes(plane: Option<&SlicePlane>) -> [u8; CLIP_PLANE_SIZE] {
+    let mut result = [0; CLIP_PLANE_SIZE];
+
+    if let Some(plane) = plane.filter(|p| p.clip) {
+        for (i, v) in plane.equation().iter().enumerate() {
+            result[i * F32_SIZE..(i + 1) * F32_SIZE].clone_from_slice(&v.to_ne_bytes());
+        }
+    }
+
+    result
+}
+
+impl Scene {
+    /// Start dragging the slice plane, if the cursor is over it. Returns true if a drag started.
+    /// See `cursor_ray` for the arguments.
+    pub(crate) fn grab_slice_plane(
+        &mut self,
+        viewport: (f32, f32, f32, f32),
+        cursor: (f32, f32),
+    ) -> bool {
+        let Some((origin, dir)) = cursor_ray(&self.camera, viewport, cursor) else {
+            return false;
+        };
+        let Some(plane) = self.slice_plane.as_mut() else {
+            return false;
+        };
+
+        if !plane.draggable || plane.normal.magnitude_squared() == 0. || !plane.grabbed(origin, dir)
+        {
+            return false;
+        }
+
+        let Some(s) = plane.axis_param(plane.position, origin, dir) else {
+            return false;
+        };
+        plane.drag_start = Some((plane.position, s));
+
+        true
+    }
+
+    /// Move the slice plane along its normal to follow the cursor, if dragging. Returns the
+    /// updates from its `on_change` callback.
+    pub(crate) fn drag_slice_plane(
+        &mut self,
+        viewport: (f32, f32, f32, f32),
+        cursor: (f32, f32),
+    ) -> EngineUpdates {
+        let Some((origin, dir)) = cursor_ray(&self.camera, viewport, cursor) else {
+            return Default::default();
+        };
+        let Some(plane) = self.slice_plane.as_mut() else {
+            return Default::default();
+        };
+        let Some((start_posit, start_s)) = plane.drag_start else {
+            return Default::default();
+        };
+
+        // Measured from the start position, so the plane doesn't drift along the drag.
+        let Some(s) = plane.axis_param(start_posit, origin, dir) else {
+            return Default::default();
+        };
+
+        plane.position = start_posit + plane.normal.to_normalized() * (s - start_s);
+        plane.moved = true;
+
+        match plane.on_change {
+            Some(on_change) => {
+                let plane = plane.clone();
+                on_change(&plane, self)
+            }
+            None => Default::default(),
+        }
+    }
+
+    /// Stop dragging the slice plane.
+    pub(crate) fn release_slice_plane(&mut self) {
+        if let Some(plane) = self.slice_plane.as_mut() {
+            plane.drag_start = None;
+        }
+    }
+
+    /// Draw the slice plane's square and normal arrow using `debug_draw`. Run this each frame,
+    /// before rendering.
+    pub(crate) fn draw_slice_plane(&mut self) {
+        let Some(plane) = &self.slice_plane else {
+            return;
+        };
+        if !plane.draggable || plane.normal.magnitude_squared() == 0. {
+            return;
+        }
+
+        let (u, v) = plane.basis();
+        let (u, v) = (u * plane.half_size, v * plane.half_size);
+        let p = plane.position;
+        let corners = [p - u - v, p + u - v, p + u + v, p - u + v];
+
+        let color = plane.color;
+        let arrow_end = p + plane.normal.to_normalized() * plane.half_size * ARROW_RATIO;
+
+        for i in 0..4 {
+            self.debug_draw
+                .line(corners[i], corners[(i + 1) % 4], color);
+        }
+        self.debug_draw.arrow(p, arrow_end, color);
+    }
+}
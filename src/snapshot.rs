@@ -0,0 +1,287 @@
+//! Comparing rendered images against reference ("golden") images, for regression tests of
+//! rendering, eg after shader or layout refactors. Render with `HeadlessRenderer`, then check the
+//! result with `check_snapshot`.
+//!
+//! Images are compared per pixel, using a perceptual color distance, so small differences in
+//! rasterization and precision between GPUs and drivers don't cause failures. This is based on
+//! the YIQ color difference metric of Kotsarenko and Ramos, as used by Pixelmatch.
+//!
+//! Reference images are created, or replaced, by running checks with the `UPDATE_SNAPSHOTS`
+//! environment variable set. Review them before committing. Otherwise, a missing reference image
+//! fails the check, so a test can't pass without one.
+
+use std::{env, path::Path};
+
+use image::{Rgba, RgbaImage};
+
+/// The largest possible YIQ distance, squared, between 2 colors.
+const MAX_DELTA: f32 = 35_215.;
+
+/// Set this environment variable to replace reference images with the current output.
+pub const UPDATE_VAR: &str = "UPDATE_SNAPSHOTS";
+
+#[derive(Clone, Copy, Debug)]
+/// How different images may be, and still match.
+pub struct DiffTolerance {
+    /// The perceptual color distance, from 0 to 1, above which pixels are considered different.
+    pub threshold: f32,
+    /// The fraction of pixels, from 0 to 1, that may differ, eg along edges, where rasterization
+    /// varies between GPUs.
+    pub max_diff_fraction: f32,
+}
+
+impl Default for DiffTolerance {
+    fn default() -> Self {
+        Self {
+            threshold: 0.1,
+            max_diff_fraction: 0.002,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// The result of comparing 2 images.
+pub struct ImageDiff {
+    pub num_different: usize,
+    /// `num_different`, as a fraction of all pixels.
+    pub diff_fraction: f32,
+    /// The largest perceptual color distance of any pixel, from 0 to 1.
+    pub max_delta: f32,
+    /// Different pixels in red, over a faded copy of the expected image.
+    pub image: RgbaImage,
+}
+
+impl ImageDiff {
+    pub fn passes(&self, tolerance: &DiffTolerance) -> bool {
+        self.diff_fraction <= tolerance.max_diff_fraction
+    }
+}
+
+/// Convert a color, blended over white, to YIQ.
+fn to_yiq(px: &Rgba<u8>) -> (f32, f32, f32) {
+    let a = px[3] as f32 / 255.;
+    let blend = |c: u8| 255. + (c as f32 - 255.) * a;
+    let (r, g, b) = (blend(px[0]), blend(px[1]), blend(px[2]));
+
+    (
+        r * 0.2989 + g * 0.5866 + b * 0.1145,
+        r * 0.5960 - g * 0.2742 - b * 0.3218,
+        r * 0.2115 - g * 0.5226 + b * 0.3111,
+    )
+}
+
+/// The perceptual distance between 2 colors, from 0 to 1.
+fn color_delta(a: &Rgba<u8>, b: &Rgba<u8>) -> f32 {
+    if a == b {
+        return 0.;
+    }
+
+    let (y_a, i_a, q_a) = to_yiq(a);
+    let (y_b, i_b, q_b) = to_yiq(b);
+    let (y, i, q) = (y_a - y_b, i_a - i_b, q_a - q_b);
+
+    ((0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q) / MAX_DELTA).sqrt()
+}
+
+/// Compare images pixel by pixel. Returns an error if their sizes differ.
+pub fn compare_images(
+    expected: &RgbaImage,
+    actual: &RgbaImage,
+    tolerance: &DiffTolerance,
+) -> Result<ImageDiff, String> {
+    if expected.dimensions() != actual.dimensions() {
+        return Err(format!(
+            "Image sizes differ: expected {:?}, got {:?}",
+            expected.dimensions(),
+            actual.dimensions()
+        ));
+    }
+
+    let mut image = RgbaImage::new(expected.width(), expected.height());
+    let mut num_different = 0;
+    let mut max_delta: f32 = 0.;
+
+    for ((px_expected, px_actual), px_diff) in expected
+        .pixels()
+        .zip(actual.pixels())
+        .zip(image.pixels_mut())
+    {
+        let delta = color_delta(px_expected, px_actual);
+        max_delta = max_delta.max(delta);
+
+        *px_diff = if delta > tolerance.threshold {
+            num_different += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            // Fade matching pixels, so differences stand out.
+            let (y, _, _) = to_yiq(px_expected);
+            let v = (255. - (255. - y) * 0.1) as u8;
+            Rgba([v, v, v, 255])
+        };
+    }
+
+    let num_pixels = (expected.width() * expected.height()).max(1) as f32;
+
+    Ok(ImageDiff {
+        num_different,
+        diff_fraction: num_different as f32 / num_pixels,
+        max_delta,
+        image,
+    })
+}
+
+/// Compare an image against the reference image at `path`, eg `tests/snapshots/box.png`. If
+/// `UPDATE_SNAPSHOTS` is set, the image is saved as the reference instead. Returns an error if the
+/// reference doesn't exist. On failure, the image and a diff are saved next to the reference, with
+/// `.actual.png` and `.diff.png` extensions, and an error describes the difference.
+pub fn check_snapshot(
+    actual: &RgbaImage,
+    path: &Path,
+    tolerance: &DiffTolerance,
+) -> Result<(), String> {
+    let save = |image: &RgbaImage, path: &Path| {
+        image
+            .save(path)
+            .map_err(|e| format!("Unable to save {}: {e}", path.display()))
+    };
+
+    if env::var_os(UPDATE_VAR).is_some() {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Unable to create {}: {e}", dir.display()))?;
+        }
+        println!("Saving reference image: {}", path.display());
+        return save(actual, path);
+    }
+
+    if !path.exists() {
+        return Err(format!(
+            "Missing reference image {}. Set {UPDATE_VAR} to create it.",
+            path.display()
+        ));
+    }
+
+    let expected = image::open(path)
+        .map_err(|e| format!("Unable to load {}: {e}", path.display()))?
+        .into_rgba8();
+
+    let diff = compare_images(&expected, actual, tolerance)?;
+    if diff.passes(tolerance) {
+        return Ok(());
+    }
+
+    let actual_path = path.with_extension("actual.png");
+    let diff_path = path.with_extension("diff.png");
+    save(actual, &actual_path)?;
+    save(&diff.image, &diff_path)?;
+
+    Err(format!(
+        "{} differs from the reference: {} pixels ({:.3}%) differ, by up to {:.3}. \
+         See {} and {}. Set {UPDATE_VAR} to accept the change.",
+        path.display(),
+        diff.num_different,
+        diff.diff_fraction * 100.,
+        diff.max_delta,
+        actual_path.display(),
+        diff_path.display(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    fn image(px: Rgba<u8>) -> RgbaImage {
+        RgbaImage::from_pixel(10, 10, px)
+    }
+
+    #[test]
+    fn color_deltas() {
+        assert_eq!(color_delta(&WHITE, &WHITE), 0.);
+
+        let extremes = color_delta(&BLACK, &WHITE);
+        assert!(extremes > 0.9 && extremes <= 1., "{extremes}");
+        assert_eq!(color_delta(&WHITE, &BLACK), extremes);
+
+        // Transparent pixels are blended over white.
+        assert!(color_delta(&Rgba([0, 0, 0, 0]), &WHITE) < 0.0001);
+        assert!(color_delta(&Rgba([0, 0, 0, 128]), &WHITE) > 0.4);
+
+        // Differences in brightness count for more than the same differences in hue.
+        let gray = Rgba([128, 128, 128, 255]);
+        let brighter = color_delta(&gray, &Rgba([148, 148, 148, 255]));
+        let bluer = color_delta(&gray, &Rgba([128, 128, 148, 255]));
+        assert!(brighter > bluer);
+
+        // Rounding differences are well under the default threshold.
+        let threshold = DiffTolerance::default().threshold;
+        assert!(color_delta(&gray, &Rgba([129, 127, 128, 255])) < threshold / 10.);
+    }
+
+    #[test]
+    fn compare_thresholds() {
+        let expected = image(Rgba([100, 150, 200, 255]));
+        let tolerance = DiffTolerance::default();
+
+        let same = compare_images(&expected, &expected, &tolerance).unwrap();
+        assert_eq!(same.num_different, 0);
+        assert_eq!(same.max_delta, 0.);
+        assert!(same.passes(&tolerance));
+
+        // A small color change is within the threshold, so no pixels differ.
+        let mut close = expected.clone();
+        close.put_pixel(3, 4, Rgba([102, 150, 198, 255]));
+        let diff = compare_images(&expected, &close, &tolerance).unwrap();
+        assert_eq!(diff.num_different, 0);
+        assert!(diff.max_delta > 0. && diff.max_delta <= tolerance.threshold);
+
+        let mut one_off = expected.clone();
+        one_off.put_pixel(3, 4, WHITE);
+        let diff = compare_images(&expected, &one_off, &tolerance).unwrap();
+        assert_eq!(diff.num_different, 1);
+        assert_eq!(diff.diff_fraction, 0.01);
+        assert_eq!(*diff.image.get_pixel(3, 4), Rgba([255, 0, 0, 255]));
+        assert_ne!(*diff.image.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+
+        // 1% of pixels differ; more than the default allows.
+        assert!(!diff.passes(&tolerance));
+        let lenient = DiffTolerance {
+            max_diff_fraction: 0.01,
+            ..tolerance
+        };
+        assert!(diff.passes(&lenient));
+
+        let small = RgbaImage::new(5, 10);
+        assert!(compare_images(&expected, &small, &tolerance).is_err());
+    }
+
+    #[test]
+    fn check_against_reference() {
+        // This would save the references instead of checking them.
+        if env::var_os(UPDATE_VAR).is_some() {
+            return;
+        }
+
+        let dir = env::temp_dir().join(format!("graphics_snapshot_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("reference.png");
+        let tolerance = DiffTolerance::default();
+
+        let err = check_snapshot(&image(BLACK), &path, &tolerance).unwrap_err();
+        assert!(err.starts_with("Missing reference image"), "{err}");
+
+        image(BLACK).save(&path).unwrap();
+        assert!(check_snapshot(&image(BLACK), &path, &tolerance).is_ok());
+
+        // On failure, the image and a diff are saved next to the reference.
+        assert!(check_snapshot(&image(WHITE), &path, &tolerance).is_err());
+        let actual = image::open(path.with_extension("actual.png")).unwrap();
+        assert_eq!(actual.into_rgba8(), image(WHITE));
+        assert!(path.with_extension("diff.png").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+//! Summary statistics about a scene's contents, eg for displaying dataset information, or catching
+//! unintended duplication of meshes or entities.
+
+use std::{collections::HashSet, fmt};
+
+use crate::{
+    mesh_cache::{hash_mesh, INDEX_SIZE},
+    types::{Scene, INSTANCE_SIZE, VERTEX_SIZE},
+};
+
+#[derive(Clone, Default)]
+/// Counts of a scene's meshes, entities, and lights. Its `Debug` output is formatted for display,
+/// eg `println!("{stats:?}")`.
+pub struct SceneStats {
+    pub mesh_count: usize,
+    /// Meshes whose contents are identical to an earlier mesh's. These share GPU memory, but
+    /// may indicate unintended duplication.
+    pub duplicate_meshes: usize,
+    /// Totals across all meshes, including duplicates.
+    pub vertex_count: usize,
+    pub index_count: usize,
+    pub entity_count: usize,
+    /// The number of entities referencing each mesh. Indices correspond to `Scene::meshes`.
+    pub entities_per_mesh: Vec<usize>,
+    pub raw_instance_count: usize,
+    pub light_count: usize,
+    /// Approximate GPU memory used by mesh and instance data, in bytes. This doesn't include
+    /// textures, eg shadow maps and probes, or smaller buffers, eg the camera and lighting.
+    pub gpu_memory: usize,
+}
+
+impl fmt::Debug for SceneStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Scene stats:")?;
+        writeln!(
+            f,
+            "  Meshes: {} ({} duplicate)",
+            self.mesh_count, self.duplicate_meshes
+        )?;
+        writeln!(f, "  Vertices: {}", self.vertex_count)?;
+        writeln!(f, "  Indices: {}", self.index_count)?;
+        writeln!(f, "  Entities: {}", self.entity_count)?;
+        writeln!(f, "  Raw instances: {}", self.raw_instance_count)?;
+        writeln!(f, "  Lights: {}", self.light_count)?;
+        writeln!(f, "  GPU memory: ~{}", format_bytes(self.gpu_memory))?;
+
+        writeln!(f, "  Entities per mesh:")?;
+        for (i, count) in self.entities_per_mesh.iter().enumerate() {
+            writeln!(f, "    {i}: {count}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Format a size in bytes using binary units, eg "1.5 MiB".
+fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1_024. && unit < UNITS.len() - 1 {
+        size /= 1_024.;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+impl Scene {
+    /// Count meshes, vertices, entities, and lights, and estimate GPU memory use. This hashes each
+    /// mesh's contents to find duplicates, so avoid calling it every frame for large scenes.
+    pub fn stats(&self) -> SceneStats {
+        let mut result = SceneStats {
+            mesh_count: self.meshes.len(),
+            entity_count: self.entities.len(),
+            entities_per_mesh: vec![0; self.meshes.len()],
+            light_count: self.lighting.point_lights.len(),
+            ..Default::default()
+        };
+
+        // Meshes with identical contents are uploaded once; see `mesh_cache`.
+        let mut hashes = HashSet::new();
+        for mesh in &self.meshes {
+            result.vertex_count += mesh.vertices.len();
+            result.index_count += mesh.indices.len();
+
+            if hashes.insert(hash_mesh(mesh)) {
+                result.gpu_memory +=
+                    mesh.vertices.len() * VERTEX_SIZE + mesh.indices.len() * INDEX_SIZE;
+            } else {
+                result.duplicate_meshes += 1;
+            }
+        }
+
+        for entity in &self.entities {
+            if let Some(count) = result.entities_per_mesh.get_mut(entity.mesh) {
+                *count += 1;
+            }
+        }
+
+        result.raw_instance_count = self.raw_instances.iter().map(|m| m.len()).sum();
+
+        result.gpu_memory += (result.entity_count + result.raw_instance_count) * INSTANCE_SIZE;
+
+        result
+    }
+}
@@ -0,0 +1,81 @@
+//! Drawing part of a mesh per entity, using `Entity::index_range`. This allows showing or hiding
+//! sections of a large combined mesh, eg city tiles or protein chains, without splitting it into
+//! separate meshes.
+//!
+//! Entities with an index range are drawn individually, after the regular instances. They cast
+//! shadows, but aren't occlusion culled, drawn as impostors, or drawn into environment probes.
+
+use wgpu::RenderPass;
+
+use crate::{
+    graphics::mesh_culling, mesh_cache::MeshRange, pass::MeshBuffers,
+    pipeline_cache::MeshPipelines, types::FaceCulling,
+};
+
+#[derive(Clone, Copy, Debug)]
+/// An entity drawing part of its mesh.
+pub(crate) struct SubRangeDraw {
+    pub mesh: usize,
+    /// The entity's index in the instance buffer.
+    pub instance: u32,
+    /// From `Entity::index_range`, relative to the mesh's first index.
+    pub index_start: u32,
+    pub index_count: u32,
+}
+
+impl SubRangeDraw {
+    /// The indices to draw, clamped to the mesh's; `None` if the mesh is missing, or there are
+    /// none.
+    pub fn indices(&self, mesh_ranges: &[MeshRange]) -> Option<(u32, u32, i32)> {
+        let range = mesh_ranges.get(self.mesh)?;
+
+        let start = self.index_start.min(range.index_count);
+        let count = self.index_count.min(range.index_count - start);
+        if count == 0 {
+            return None;
+        }
+
+        let start = range.index_start + start;
+        Some((start, start + count, range.vertex_start))
+    }
+}
+
+/// Draw entities' index ranges in the main pass. The main pass's bind groups must be set.
+pub(crate) fn draw(
+    rpass: &mut RenderPass,
+    pipelines: MeshPipelines,
+    draws: &[SubRangeDraw],
+    buffers: &MeshBuffers,
+) {
+    if draws.is_empty() {
+        return;
+    }
+
+    let MeshBuffers {
+        vertex_buf,
+        index_buf,
+        instance_buf,
+        mesh_ranges,
+        meshes,
+        ..
+    } = *buffers;
+
+    let mut current = FaceCulling::Back;
+    rpass.set_pipeline(pipelines.get(current));
+
+    rpass.set_vertex_buffer(0, vertex_buf.slice(..));
+    rpass.set_vertex_buffer(1, instance_buf.slice(..));
+    rpass.set_index_buffer(index_buf.slice(..), wgpu::IndexFormat::Uint32);
+
+    for draw in draws {
+        let Some((start, end, base_vertex)) = draw.indices(mesh_ranges) else {
+            continue;
+        };
+
+        if let Some(pipeline) = pipelines.switch(&mut current, mesh_culling(meshes, draw.mesh)) {
+            rpass.set_pipeline(pipeline);
+        }
+
+        rpass.draw_indexed(start..end, base_vertex, draw.instance..draw.instance + 1);
+    }
+}
@@ -22,16 +22,33 @@ use winit::{
     window::{Icon, Window},
 };
 
+#[cfg(feature = "gui")]
+use crate::gui::GuiState;
+#[cfg(feature = "remote")]
+use crate::remote::RemoteServer;
 use crate::{
-    graphics::GraphicsState,
-    gui::GuiState,
+    fixed_step::FixedUpdate,
+    graphics::{viewport_3d, GraphicsState},
+    limits,
+    pacing::FramePacer,
+    redraw::RedrawRegion,
     texture::Texture,
-    types::{EngineUpdates, InputSettings, Scene, UiLayout, UiSettings},
+    types::{EngineUpdates, GraphicsSettings, InputSettings, Scene, UiSettings},
 };
 
 pub const COLOR_FORMAT: TextureFormat = TextureFormat::Bgra8UnormSrgb;
+/// The format of shadow maps, and environment probe captures. The main depth buffer's is set by
+/// `GraphicsSettings::depth_format`.
 pub const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
 
+/// The context passed to the GUI handler.
+#[cfg(feature = "gui")]
+pub type GuiContext = egui::Context;
+
+/// Without the `gui` feature, there's no GUI context, so the GUI handler is never called.
+#[cfg(not(feature = "gui"))]
+pub enum GuiContext {}
+
 /// This struct contains state related to the 3D graphics. It is mostly constructed of types
 /// that are required by  the WGPU renderer.
 pub(crate) struct RenderState {
@@ -43,25 +60,46 @@ pub(crate) struct RenderState {
     pub surface_cfg: SurfaceConfiguration,
 }
 
+/// The settings the event loop starts with.
+pub(crate) struct Settings {
+    pub input: InputSettings,
+    pub ui: UiSettings,
+    pub graphics: GraphicsSettings,
+}
+
 pub struct State<T: 'static, FRender, FEvent, FGui>
 where
     FRender: FnMut(&mut T, &mut Scene, f32) -> EngineUpdates + 'static,
     FEvent: FnMut(&mut T, DeviceEvent, &mut Scene, f32) -> EngineUpdates + 'static,
-    FGui: FnMut(&mut T, &egui::Context, &mut Scene) -> EngineUpdates + 'static,
+    FGui: FnMut(&mut T, &GuiContext, &mut Scene) -> EngineUpdates + 'static,
 {
     pub instance: Instance,
-    /// `render` and `graphics`, and `gui` are only None at init; they require the `Window` event loop
-    /// to be run.
+    /// `window`, `render` and `graphics`, and `gui` are only None at init; they require the
+    /// `Window` event loop to be run.
+    pub window: Option<Arc<Window>>,
     pub render: Option<RenderState>,
     pub graphics: Option<GraphicsState>,
+    #[cfg(feature = "gui")]
     pub gui: Option<GuiState>,
+    /// Used to disable inputs while the mouse is in the GUI section.
+    pub mouse_in_gui: bool,
+    /// The cursor's position in the window, in pixels. Used to pick measurement points.
+    pub cursor: (f32, f32),
     pub user_state: T,
     pub render_handler: FRender,
     pub event_handler: FEvent,
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
     pub gui_handler: FGui,
+    /// Set by `EngineBuilder::on_fixed_update`.
+    pub fixed_update: Option<FixedUpdate<T>>,
     pub input_settings: InputSettings,
     pub ui_settings: UiSettings,
+    pub graphics_settings: GraphicsSettings,
     pub scene: Scene,
+    /// Present if `UiSettings::remote_addr` is set, and the server started.
+    #[cfg(feature = "remote")]
+    pub remote: Option<RemoteServer>,
+    pub pacer: FramePacer,
     pub last_render_time: Instant,
     pub dt: Duration,
 }
@@ -70,14 +108,13 @@ impl<T: 'static, FRender, FEvent, FGui> State<T, FRender, FEvent, FGui>
 where
     FRender: FnMut(&mut T, &mut Scene, f32) -> EngineUpdates + 'static,
     FEvent: FnMut(&mut T, DeviceEvent, &mut Scene, f32) -> EngineUpdates + 'static,
-    FGui: FnMut(&mut T, &egui::Context, &mut Scene) -> EngineUpdates + 'static,
+    FGui: FnMut(&mut T, &GuiContext, &mut Scene) -> EngineUpdates + 'static,
 {
     /// This constructor sets up the basics required for Winit's events loop. We initialize the important
     /// parts later, once the window has been set up.
     pub(crate) fn new(
         scene: Scene,
-        input_settings: InputSettings,
-        ui_settings: UiSettings,
+        settings: Settings,
         user_state: T,
         render_handler: FRender,
         event_handler: FEvent,
@@ -86,6 +123,19 @@ where
         let last_render_time = Instant::now();
         let dt = Duration::new(0, 0);
 
+        let Settings {
+            input: input_settings,
+            ui: ui_settings,
+            graphics: graphics_settings,
+        } = settings;
+
+        #[cfg(feature = "remote")]
+        let remote = ui_settings.remote_addr.as_ref().and_then(|addr| {
+            RemoteServer::start(addr)
+                .inspect_err(|e| println!("Error starting the remote control server: {e}"))
+                .ok()
+        });
+
         // The instance is a handle to our GPU. Its main purpose is to create Adapters and Surfaces.
         let instance = Instance::new(InstanceDescriptor {
             backends: Backends::VULKAN,
@@ -94,16 +144,25 @@ where
 
         Self {
             instance,
+            window: None,
             render: None,
             graphics: None,
+            #[cfg(feature = "gui")]
             gui: None,
+            mouse_in_gui: false,
+            cursor: (0., 0.),
             user_state,
             render_handler,
             event_handler,
             gui_handler,
+            fixed_update: None,
             input_settings,
             ui_settings,
+            graphics_settings,
             scene,
+            #[cfg(feature = "remote")]
+            remote,
+            pacer: Default::default(),
             last_render_time,
             dt,
         }
@@ -118,25 +177,49 @@ where
 
         let surface = self.instance.create_surface(window.clone()).unwrap();
 
-        let (adapter, device, queue) = pollster::block_on(setup_async(&self.instance, &surface));
+        let (adapter, device, queue) = pollster::block_on(setup_async(
+            &self.instance,
+            Some(&surface),
+            &self.graphics_settings,
+        ))
+        .unwrap();
 
         // The surface is the part of the window that we draw to. We need it to draw directly to the
         // screen. Our window needs to implement raw-window-handle (opens new window)'s
         // HasRawWindowHandle trait to create a surface.
 
+        // Prefer our default format, then any sRGB format. If the surface supports neither, the
+        // main shader encodes its output as sRGB, so colors are consistent either way.
+        let capabilities = surface.get_capabilities(&adapter);
+        let formats = capabilities.formats;
+        let format = if formats.contains(&COLOR_FORMAT) {
+            COLOR_FORMAT
+        } else {
+            formats
+                .iter()
+                .copied()
+                .find(|f| f.is_srgb())
+                .or(formats.first().copied())
+                .unwrap_or(COLOR_FORMAT)
+        };
+
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT;
+        // Remote screenshots are copied from the surface texture, if the surface supports it.
+        #[cfg(feature = "remote")]
+        let usage = match self.remote {
+            Some(_) => usage | (capabilities.usages & wgpu::TextureUsages::COPY_SRC),
+            None => usage,
+        };
+
         // https://docs.rs/wgpu/latest/wgpu/type.SurfaceConfiguration.html
         let surface_cfg = SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            // format: surface.get_supported_formats(&adapter)[0],
-            format: COLOR_FORMAT,
+            usage,
+            format,
             width: size.width,
             height: size.height,
             // https://docs.rs/wgpu/latest/wgpu/enum.PresentMode.html
-            // Note that `Fifo` locks FPS to the speed of the monitor.
-            present_mode: wgpu::PresentMode::Fifo,
-            // todo: Allow config from user.
-            // present_mode: wgpu::PresentMode::Immediate,
-            // present_mode: wgpu::PresentMode::Mailbox,
+            // Note that `Fifo` (Vsync) locks FPS to the speed of the monitor.
+            present_mode: self.graphics_settings.present_mode.to_wgpu(),
             desired_maximum_frame_latency: 2, // Default
             alpha_mode: wgpu::CompositeAlphaMode::Auto,
             view_formats: Vec::new(),
@@ -144,6 +227,7 @@ where
 
         surface.configure(&device, &surface_cfg);
 
+        #[cfg(feature = "gui")]
         let texture_format = surface_cfg.format;
 
         let render = RenderState {
@@ -155,29 +239,62 @@ where
             surface_cfg,
         };
 
-        let graphics = GraphicsState::new(
+        let mut graphics = GraphicsState::new(
             &render.device,
+            &render.queue,
             &render.surface_cfg,
             self.scene.clone(), // todo: Now we have two scene states... not good.
             // input_settings,
             // ui_settings,
-            window.clone(),
+            &self.graphics_settings,
         );
 
-        self.gui = Some(GuiState::new(window, &render.device, texture_format));
+        let window_state = &mut graphics.scene.window_state;
+        window_state.size = (size.width, size.height);
+        if let Ok(position) = window.outer_position() {
+            window_state.position = (position.x, position.y);
+        }
+        graphics.scene.frame_stats.display_hz = self.pacer.update_monitor(&window);
+
+        // todo: Logical (scaling by device?) vs physical pixels
+        // let window_size = winit::dpi::LogicalSize::new(scene.window_size.0, scene.window_size.1);
+        window.set_title(&self.scene.window_title);
+
+        #[cfg(feature = "gui")]
+        {
+            self.gui = Some(GuiState::new(
+                &window,
+                &render.device,
+                texture_format,
+                graphics.depth_format,
+            ));
+        }
 
+        self.window = Some(window);
         self.render = Some(render);
         self.graphics = Some(graphics);
     }
 
+    /// The size of the GUI panel, in pixels, along the layout's axis. This is 0 without the `gui`
+    /// feature.
+    pub(crate) fn gui_size(&self) -> f32 {
+        #[cfg(feature = "gui")]
+        if let Some(gui) = &self.gui {
+            return gui.size;
+        }
+
+        0.
+    }
+
     pub(crate) fn resize(&mut self, new_size: PhysicalSize<u32>) {
         if self.render.is_none() || self.graphics.is_none() {
             return;
         }
 
+        let gui_size = self.gui_size();
+
         let mut sys = &mut self.render.as_mut().unwrap();
         let mut graphics = &mut self.graphics.as_mut().unwrap();
-        let mut gui = &mut self.gui.as_mut().unwrap();
 
         if new_size.width > 0 && new_size.height > 0 {
             sys.size = new_size;
@@ -185,21 +302,30 @@ where
             sys.surface_cfg.height = new_size.height;
             sys.surface.configure(&sys.device, &sys.surface_cfg);
 
-            let (eff_width, eff_height) = match self.ui_settings.layout {
-                UiLayout::Left | UiLayout::Right => (
-                    sys.surface_cfg.width as f32 - gui.size,
-                    sys.surface_cfg.height as f32,
-                ),
-                _ => (
-                    sys.surface_cfg.width as f32,
-                    sys.surface_cfg.height as f32 - gui.size,
-                ),
-            };
+            let (_, _, eff_width, eff_height) = viewport_3d(
+                gui_size,
+                sys.surface_cfg.width,
+                sys.surface_cfg.height,
+                &self.ui_settings,
+            );
 
             graphics.scene.camera.aspect = eff_width / eff_height;
 
-            graphics.depth_texture =
-                Texture::create_depth_texture(&sys.device, &sys.surface_cfg, "Depth texture");
+            graphics.depth_texture = Texture::create_depth_texture(
+                &sys.device,
+                &sys.surface_cfg,
+                graphics.depth_format,
+                "Depth texture",
+            );
+
+            if let Some(taa) = &mut graphics.taa {
+                taa.resize(&sys.device, &sys.surface_cfg);
+            }
+
+            if let Some(deferred) = &mut graphics.deferred {
+                let depth_view = graphics.depth_texture.depth_view();
+                deferred.resize(&sys.device, &sys.surface_cfg, &depth_view);
+            }
 
             graphics.scene.camera.update_proj_mat();
 
@@ -209,6 +335,14 @@ where
             //     .write_buffer(&graphics.camera_buf, 0, &self.scene.camera.to_bytes());
         }
     }
+
+    /// Run the Winit event loop, until the window is closed.
+    pub(crate) fn run_event_loop(&mut self) {
+        let event_loop = EventLoop::new().unwrap();
+        event_loop.set_control_flow(ControlFlow::Poll);
+
+        event_loop.run_app(self).expect("Failed to run app");
+    }
 }
 
 /// This is the entry point to the renderer. It's called by the application to initialize the event
@@ -217,10 +351,20 @@ where
 ///
 /// `user_state` is arbitrary application state, to maintain ownership of.
 /// `render_handler` allows application code to run each frame.
-/// `event_handler` allows application code to handle device events, such as user input.
-/// `gui_handler` is where the EGUI code is written to describe the UI.
+/// `event_handler` allows application code to handle device events, such as user input. Events
+/// are first offered to the GUI, then registered shortcuts (`Scene::shortcuts`); the handler
+/// receives those neither consumes. Camera controls also see these, before the handler.
+/// `gui_handler` is where the EGUI code is written to describe the UI. Without the `gui` feature,
+/// there's no GUI, the 3D viewport uses the whole window, and this is never called; pass eg
+/// `|_, _, _| Default::default()`.
+///
+/// Returns the user state once the event loop ends, ie when the window is closed, or a handler sets
+/// `EngineUpdates::exit`. Use this for cleanup, eg saving the state.
+///
+/// This uses the default `GraphicsSettings`. `Engine::builder` configures the same settings and
+/// handlers by name, with defaults for those not set, and also sets graphics settings, eg to enable
+/// TAA or GPU timing.
 pub fn run<T: 'static, FRender, FEvent, FGui>(
-
     user_state: T,
     scene: Scene,
     input_settings: InputSettings,
@@ -228,34 +372,41 @@ pub fn run<T: 'static, FRender, FEvent, FGui>(
     render_handler: FRender,
     event_handler: FEvent,
     gui_handler: FGui,
-) where
+) -> T
+where
     FRender: FnMut(&mut T, &mut Scene, f32) -> EngineUpdates + 'static,
     FEvent: FnMut(&mut T, DeviceEvent, &mut Scene, f32) -> EngineUpdates + 'static,
-    FGui: FnMut(&mut T, &egui::Context, &mut Scene) -> EngineUpdates + 'static,
+    FGui: FnMut(&mut T, &GuiContext, &mut Scene) -> EngineUpdates + 'static,
 {
     let (_frame_count, _accum_time) = (0, 0.0);
 
+    let settings = Settings {
+        input: input_settings,
+        ui: ui_settings,
+        graphics: Default::default(),
+    };
+
     let mut state: State<T, FRender, FEvent, FGui> = State::new(
         scene,
-        input_settings,
-        ui_settings,
+        settings,
         user_state,
         render_handler,
         event_handler,
         gui_handler,
     );
 
-    let event_loop = EventLoop::new().unwrap();
-    event_loop.set_control_flow(ControlFlow::Poll);
+    state.run_event_loop();
 
-    event_loop.run_app(&mut state).expect("Failed to run app");
+    state.user_state
 }
 
 /// Quarantine for the Async part of the API
-async fn setup_async(
+/// Request an adapter and device. `surface` is `None` when rendering headless.
+pub(crate) async fn setup_async(
     instance: &Instance,
-    surface: &Surface<'static>,
-) -> (Adapter, Device, Queue) {
+    surface: Option<&Surface<'static>>,
+    graphics_settings: &GraphicsSettings,
+) -> Result<(Adapter, Device, Queue), String> {
     // The adapter is a handle to our actual graphics card. You can use this to get
     // information about the graphics card such as its name and what backend the
     // adapter uses. We use this to create our Device and Queue.
@@ -263,20 +414,33 @@ async fn setup_async(
         .request_adapter(&wgpu::RequestAdapterOptions {
             // `Default` prefers low power when on battery, high performance when on mains.
             power_preference: PowerPreference::default(),
-            compatible_surface: Some(surface),
+            compatible_surface: surface,
             force_fallback_adapter: false,
         })
         .await
-        .unwrap();
+        .ok_or("Unable to find a suitable GPU adapter")?;
+
+    // Only request optional features the adapter supports. We request those used by any graphics
+    // setting, since settings may be changed while running. Compressed material textures are
+    // decompressed if `TEXTURE_COMPRESSION_BC` isn't supported.
+    let required_features = adapter.features()
+        & (Features::TIMESTAMP_QUERY
+            | Features::INDIRECT_FIRST_INSTANCE
+            | Features::TEXTURE_COMPRESSION_BC
+            | Features::ADDRESS_MODE_CLAMP_TO_BORDER
+            | Features::DEPTH32FLOAT_STENCIL8);
 
     let (device, queue) = adapter
         .request_device(
             &wgpu::DeviceDescriptor {
                 label: None,
                 // https://docs.rs/wgpu/latest/wgpu/struct.Features.html
-                required_features: Features::empty(),
+                required_features,
                 // https://docs.rs/wgpu/latest/wgpu/struct.Limits.html
-                required_limits: Default::default(),
+                required_limits: limits::required_limits(
+                    &adapter,
+                    graphics_settings.buffer_size_hint,
+                ),
                 memory_hints: Default::default(),
             },
             std::env::var("WGPU_TRACE")
@@ -285,9 +449,9 @@ async fn setup_async(
                 .map(std::path::Path::new),
         )
         .await
-        .expect("Unable to find a suitable GPU adapter. :(");
+        .map_err(|e| format!("Unable to create a GPU device: {e}"))?;
 
-    (adapter, device, queue)
+    Ok((adapter, device, queue))
 }
 
 /// Process engine updates from render, GUI, or events.
@@ -297,22 +461,142 @@ pub(crate) fn process_engine_updates(
     device: &Device,
     queue: &Queue,
 ) {
+    // Debug shapes of static entities depend on their meshes.
+    if engine_updates.static_entities
+        || engine_updates.meshes
+        || !engine_updates.replaced_meshes.is_empty()
+    {
+        g_state.static_batch = None;
+    }
+
+    // Mesh bounds are cached here, and used when updating vertices.
     if engine_updates.meshes {
-        g_state.setup_vertices_indices(device);
-        g_state.setup_entities(device);
+        g_state.scene.spatial_cache.retain_unchanged(&g_state.scene.meshes);
+        g_state.setup_vertices_indices(device, queue);
+        g_state.setup_entities(device, queue);
+    } else {
+        if !engine_updates.replaced_meshes.is_empty() {
+            g_state.scene.spatial_cache.invalidate(&engine_updates.replaced_meshes);
+            g_state.replace_meshes(device, queue, &engine_updates.replaced_meshes);
+        }
+        if !engine_updates.mesh_vertices.is_empty() {
+            g_state.scene.spatial_cache.invalidate(&engine_updates.mesh_vertices);
+            g_state
+                .update_mesh_vertices(device, queue, &engine_updates.mesh_vertices)
+                .unwrap_or_else(|e| println!("Error updating mesh vertices: {e}"));
+        }
+    }
+
+    if engine_updates.entities || engine_updates.static_entities {
+        g_state.setup_entities(device, queue);
+    } else if !engine_updates.changed_entities.is_empty() {
+        g_state.update_entity_instances(device, queue, &engine_updates.changed_entities);
+    }
+
+    if engine_updates.raw_instances {
+        g_state.update_raw_instances(device);
     }
 
-    if engine_updates.entities {
-        g_state.setup_entities(device);
+    if engine_updates.camera_projection {
+        g_state.scene.camera.update_proj_mat();
     }
 
-    if engine_updates.camera {
+    if engine_updates.camera || engine_updates.camera_view {
         // Entities have been updated in the scene; update the buffer.
         g_state.update_camera(queue);
+    } else if engine_updates.camera_projection {
+        g_state.update_camera_projection(queue);
     }
 
     if engine_updates.lighting {
         // Entities have been updated in the scene; update the buffer.
-        g_state.update_lighting(queue);
+        g_state.update_lighting(device, queue);
+    } else if !engine_updates.changed_lights.is_empty() {
+        g_state.update_lights(queue, &engine_updates.changed_lights);
+    }
+
+    if engine_updates.color {
+        g_state.update_color(queue);
+    }
+
+    if engine_updates.palette {
+        g_state.update_palette(device, queue);
+    }
+
+    if engine_updates.materials {
+        g_state.update_materials(device, queue);
+    } else if !engine_updates.material_images.is_empty() {
+        g_state.update_material_images(device, queue, &engine_updates.material_images);
+    }
+
+    #[cfg(feature = "compute")]
+    if engine_updates.compute {
+        g_state.setup_compute(device);
+        g_state.request_redraw(RedrawRegion::All);
+    }
+
+    if engine_updates.shader_extension {
+        g_state.setup_extension(device, queue);
+    }
+
+    if engine_updates.sdf_elements {
+        g_state.sdf.update(device, queue, &g_state.scene.sdf_elements);
+    }
+
+    if engine_updates.hud_images {
+        g_state.hud.update_images(device, queue, &g_state.scene.hud);
+    }
+    if engine_updates.hud {
+        g_state.hud.update(device, queue, &g_state.scene.hud);
+    }
+
+    if engine_updates.env_probes {
+        g_state.probes.stale = true;
+    }
+
+    if engine_updates.stop_turntable {
+        g_state.scene.turntable = None;
+    }
+    if let Some(turntable) = &engine_updates.turntable {
+        g_state.scene.turntable = Some(turntable.clone());
+    }
+
+    // With `RedrawMode::OnChange`, camera, lighting, and entity updates request a redraw when
+    // applied.
+    if engine_updates.meshes
+        || !engine_updates.mesh_vertices.is_empty()
+        || !engine_updates.replaced_meshes.is_empty()
+        || engine_updates.raw_instances
+        || engine_updates.color
+        || engine_updates.palette
+        || engine_updates.materials
+        || !engine_updates.material_images.is_empty()
+        || engine_updates.shader_extension
+        || engine_updates.sdf_elements
+        || engine_updates.graphics_settings.is_some()
+    {
+        g_state.request_redraw(RedrawRegion::All);
+    }
+    if let Some(region) = engine_updates.redraw {
+        g_state.request_redraw(region);
+    }
+
+    // These are applied by the caller, since some settings affect the surface.
+    if let Some(settings) = &engine_updates.graphics_settings {
+        g_state.pending_settings = Some(settings.clone());
+    }
+
+    for request in &engine_updates.load_assets {
+        g_state.loader.start(request);
+    }
+
+    // The caller owns the event loop, so exits from it.
+    if engine_updates.exit {
+        g_state.exit_requested = true;
+    }
+
+    // After entity and mesh updates, so the pick uses the current instances.
+    if let Some((origin, dir)) = engine_updates.gpu_pick {
+        g_state.scene.gpu_pick_hit = g_state.gpu_pick(device, queue, origin, dir);
     }
 }
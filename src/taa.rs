@@ -0,0 +1,342 @@
+//! Temporal anti-aliasing (TAA). We jitter the camera projection by a sub-pixel amount each frame,
+//! render color and per-pixel velocity to offscreen textures, then blend the result with the
+//! reprojected history of previous frames. This is an alternative to MSAA that scales better to
+//! heavy scenes, at the cost of some softness, and ghosting on fast motion.
+//!
+//! Velocity accounts for both camera motion, and entity motion; for the latter, we keep each
+//! instance's model matrix from the previous frame in a storage buffer.
+
+use lin_alg::f32::Mat4;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferUsages, CommandEncoder, Device,
+    FragmentState, Queue, RenderPassDescriptor, RenderPipeline, ShaderStages, StoreOp,
+    SurfaceConfiguration, TextureFormat, TextureView, VertexState,
+};
+
+use crate::{
+    camera::Camera,
+    texture::Texture,
+    types::{F32_SIZE, MAT4_SIZE, VEC4_SIZE},
+};
+
+/// The portion of the camera uniform used for TAA: the previous frame's projection-view matrix,
+/// and this frame's jitter. This follows the `Camera::to_bytes` data in the buffer.
+pub const TAA_CAMERA_SIZE: usize = MAT4_SIZE + VEC4_SIZE;
+
+const TAA_PARAMS_SIZE: usize = 4 * F32_SIZE;
+
+pub const VELOCITY_FORMAT: TextureFormat = TextureFormat::Rg16Float;
+
+/// Weight of the current frame when blending with history. Lower values are smoother, but ghost
+/// more.
+const BLEND: f32 = 0.1;
+
+/// We cycle through this many jitter positions.
+const NUM_JITTER_SAMPLES: u32 = 8;
+
+/// `Camera::jitter`, converted from pixels to NDC, for a viewport `width` by `height` pixels. NDC
+/// spans 2 units across the viewport, with +y up.
+fn camera_jitter(camera: &Camera, width: f32, height: f32) -> (f32, f32) {
+    let (x, y) = camera.jitter;
+    (x * 2. / width, -y * 2. / height)
+}
+
+/// Serialize the TAA portion of the camera uniform. `jitter` is in NDC.
+fn to_bytes(prev_proj_view: &Mat4, jitter: (f32, f32)) -> [u8; TAA_CAMERA_SIZE] {
+    let mut result = [0; TAA_CAMERA_SIZE];
+
+    result[0..MAT4_SIZE].clone_from_slice(&prev_proj_view.to_bytes());
+    result[MAT4_SIZE..MAT4_SIZE + F32_SIZE].clone_from_slice(&jitter.0.to_ne_bytes());
+    result[MAT4_SIZE + F32_SIZE..MAT4_SIZE + 2 * F32_SIZE]
+        .clone_from_slice(&jitter.1.to_ne_bytes());
+
+    result
+}
+
+/// The TAA portion of the camera uniform when TAA is disabled: only `Camera::jitter`. Velocity
+/// isn't used, so the previous projection-view matrix is the current one.
+pub(crate) fn camera_bytes_no_taa(
+    camera: &Camera,
+    width: f32,
+    height: f32,
+) -> [u8; TAA_CAMERA_SIZE] {
+    let proj_view = camera.proj_mat.clone() * camera.view_mat();
+    to_bytes(&proj_view, camera_jitter(camera, width, height))
+}
+
+/// Element `i` of the Halton sequence with a given base; a well-distributed value from 0 to 1.
+fn halton(mut i: u32, base: u32) -> f32 {
+    let mut f = 1.;
+    let mut result = 0.;
+
+    while i > 0 {
+        f /= base as f32;
+        result += f * (i % base) as f32;
+        i /= base;
+    }
+
+    result
+}
+
+/// Textures, pipelines, and per-frame state used by TAA.
+pub(crate) struct TaaState {
+    pub color: Texture,
+    pub velocity: Texture,
+    history: [Texture; 2],
+    /// Index of the history texture read from this frame; we write to the other one.
+    history_i: usize,
+    pipeline_resolve: RenderPipeline,
+    layout_resolve: BindGroupLayout,
+    params_buf: Buffer,
+    frame_i: u32,
+    /// Set when history is invalid, eg after a resize.
+    reset: bool,
+    /// Unjittered projection-view matrix from the previous frame.
+    prev_proj_view: Option<Mat4>,
+    /// Model matrices from the previous instance buffer build, indexed by entity.
+    pub prev_model_mats: Vec<Mat4>,
+    /// Set when the instance buffer is rebuilt; cleared once a frame has rendered with it.
+    pub instances_fresh: bool,
+    /// Set if any entity moved between the last two instance buffer builds.
+    pub instance_motion: bool,
+    /// Entities that moved in the last partial instance update, so whose previous transforms
+    /// differ from their current ones. `None` after a full rebuild, which doesn't track them.
+    pub moved_entities: Option<Vec<usize>>,
+}
+
+impl TaaState {
+    pub fn new(device: &Device, surface_cfg: &SurfaceConfiguration) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("TAA shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("taa.wgsl").into()),
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+
+        let layout_resolve = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                texture_entry(2),
+                texture_entry(3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("TAA bind group layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("TAA pipeline layout"),
+            bind_group_layouts: &[&layout_resolve],
+            push_constant_ranges: &[],
+        });
+
+        let color_target = Some(wgpu::ColorTargetState {
+            format: surface_cfg.format,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        });
+
+        let pipeline_resolve = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("TAA pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                // The surface, and the next frame's history.
+                targets: &[color_target.clone(), color_target],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let params_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("TAA params buffer"),
+            contents: &[0; TAA_PARAMS_SIZE],
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let (color, velocity, history) = create_textures(device, surface_cfg);
+
+        Self {
+            color,
+            velocity,
+            history,
+            history_i: 0,
+            pipeline_resolve,
+            layout_resolve,
+            params_buf,
+            frame_i: 0,
+            reset: true,
+            prev_proj_view: None,
+            prev_model_mats: Vec::new(),
+            instances_fresh: false,
+            instance_motion: false,
+            moved_entities: None,
+        }
+    }
+
+    /// Recreate textures to match a new surface size. This invalidates history.
+    pub fn resize(&mut self, device: &Device, surface_cfg: &SurfaceConfiguration) {
+        (self.color, self.velocity, self.history) = create_textures(device, surface_cfg);
+        self.reset = true;
+    }
+
+    /// Serialize the TAA portion of the camera uniform for this frame, including
+    /// `Camera::jitter`. `width` and `height` are the size of the 3D viewport, in pixels. Run this
+    /// once per frame.
+    pub fn camera_bytes(
+        &mut self,
+        camera: &Camera,
+        width: f32,
+        height: f32,
+    ) -> [u8; TAA_CAMERA_SIZE] {
+        let proj_view = camera.proj_mat.clone() * camera.view_mat();
+        let prev_proj_view = self
+            .prev_proj_view
+            .replace(proj_view.clone())
+            .unwrap_or(proj_view);
+
+        // Offset by up to half a pixel in each direction. NDC spans 2 units across the viewport.
+        let sample_i = self.frame_i % NUM_JITTER_SAMPLES + 1;
+        let (camera_x, camera_y) = camera_jitter(camera, width, height);
+        let jitter_x = (halton(sample_i, 2) - 0.5) * 2. / width + camera_x;
+        let jitter_y = (halton(sample_i, 3) - 0.5) * 2. / height + camera_y;
+
+        to_bytes(&prev_proj_view, (jitter_x, jitter_y))
+    }
+
+    /// Blend the current frame with history, writing the result to `output_view`. `uv_scale` is
+    /// the size of the 3D viewport, relative to the whole surface.
+    pub fn encode_resolve(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        output_view: &TextureView,
+        uv_scale: (f32, f32),
+    ) {
+        let reset: f32 = if self.reset { 1. } else { 0. };
+
+        let mut params = [0; TAA_PARAMS_SIZE];
+        params[0..F32_SIZE].clone_from_slice(&uv_scale.0.to_ne_bytes());
+        params[F32_SIZE..2 * F32_SIZE].clone_from_slice(&uv_scale.1.to_ne_bytes());
+        params[2 * F32_SIZE..3 * F32_SIZE].clone_from_slice(&BLEND.to_ne_bytes());
+        params[3 * F32_SIZE..4 * F32_SIZE].clone_from_slice(&reset.to_ne_bytes());
+
+        queue.write_buffer(&self.params_buf, 0, &params);
+
+        let history_read = &self.history[self.history_i];
+        let history_write = &self.history[1 - self.history_i];
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.layout_resolve,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&self.color.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&self.velocity.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&history_read.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&history_read.sampler),
+                },
+            ],
+            label: Some("TAA bind group"),
+        });
+
+        let ops = wgpu::Operations {
+            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            store: StoreOp::Store,
+        };
+
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("TAA render pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view: output_view,
+                    resolve_target: None,
+                    ops,
+                }),
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &history_write.view,
+                    resolve_target: None,
+                    ops,
+                }),
+            ],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        rpass.set_pipeline(&self.pipeline_resolve);
+        rpass.set_bind_group(0, &bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+        drop(rpass);
+
+        self.history_i = 1 - self.history_i;
+        self.frame_i = self.frame_i.wrapping_add(1);
+        self.reset = false;
+    }
+}
+
+/// Create the color, velocity, and history textures.
+fn create_textures(
+    device: &Device,
+    surface_cfg: &SurfaceConfiguration,
+) -> (Texture, Texture, [Texture; 2]) {
+    let format = surface_cfg.format;
+
+    (
+        Texture::create_render_target(device, surface_cfg, format, "TAA color texture"),
+        Texture::create_render_target(device, surface_cfg, VELOCITY_FORMAT, "Velocity texture"),
+        [
+            Texture::create_render_target(device, surface_cfg, format, "TAA history texture 0"),
+            Texture::create_render_target(device, surface_cfg, format, "TAA history texture 1"),
+        ],
+    )
+}
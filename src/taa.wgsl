@@ -0,0 +1,84 @@
+// Temporal anti-aliasing resolve. Blends the current (jittered) frame with the previous
+// frames' result, reprojected using per-pixel velocity.
+
+struct TaaParams {
+    // Converts velocity from viewport NDC to full-texture UV, since the 3D viewport may not
+    // take up the whole window.
+    uv_scale: vec2<f32>,
+    // Weight of the current frame, from 0 to 1.
+    blend: f32,
+    // 1 if history is invalid, eg after a resize.
+    reset: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: TaaParams;
+@group(0) @binding(1)
+var color_tex: texture_2d<f32>;
+@group(0) @binding(2)
+var velocity_tex: texture_2d<f32>;
+@group(0) @binding(3)
+var history_tex: texture_2d<f32>;
+@group(0) @binding(4)
+var history_sampler: sampler;
+
+struct VertexOut {
+    @builtin(position) posit: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+struct FragOut {
+    @location(0) color: vec4<f32>,
+    // Written to the next frame's history texture.
+    @location(1) history: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> VertexOut {
+    // A single triangle that covers the screen.
+    var uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+
+    var result: VertexOut;
+    result.posit = vec4<f32>(uv * vec2<f32>(2., -2.) + vec2<f32>(-1., 1.), 0., 1.);
+    result.uv = uv;
+
+    return result;
+}
+
+@fragment
+fn fs_main(vertex: VertexOut) -> FragOut {
+    var dims = vec2<i32>(textureDimensions(color_tex));
+    var pixel = vec2<i32>(vertex.posit.xy);
+
+    var current = textureLoad(color_tex, pixel, 0);
+
+    // Clamp history to the current frame's 3x3 neighborhood, to reduce ghosting.
+    var color_min = current;
+    var color_max = current;
+    for (var x = -1; x <= 1; x++) {
+        for (var y = -1; y <= 1; y++) {
+            var neighbor_px = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0, 0), dims - 1);
+            var neighbor = textureLoad(color_tex, neighbor_px, 0);
+            color_min = min(color_min, neighbor);
+            color_max = max(color_max, neighbor);
+        }
+    }
+
+    // Velocity is in NDC; convert to UV, where Y points down.
+    var velocity = textureLoad(velocity_tex, pixel, 0).xy * vec2<f32>(0.5, -0.5) * params.uv_scale;
+    var prev_uv = vertex.uv - velocity;
+
+    var history = textureSampleLevel(history_tex, history_sampler, prev_uv, 0.);
+    history = clamp(history, color_min, color_max);
+
+    var blend = params.blend;
+    if (params.reset > 0.5 || any(prev_uv < vec2<f32>(0., 0.)) || any(prev_uv > vec2<f32>(1., 1.))) {
+        blend = 1.;
+    }
+
+    var result: FragOut;
+    result.color = mix(history, current, blend);
+    result.history = result.color;
+
+    return result;
+}
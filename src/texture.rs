@@ -1,8 +1,6 @@
 use image::GenericImageView;
 use wgpu::{Device, Queue, TextureDescriptor, TextureFormat};
 
-use crate::system::{COLOR_FORMAT, DEPTH_FORMAT};
-
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
@@ -10,10 +8,11 @@ pub struct Texture {
 }
 
 impl Texture {
-    /// Create a 2d texture for the depth stencil.
+    /// Create a 2d texture for the depth stencil. `format` may include a stencil component.
     pub fn create_depth_texture(
         device: &Device,
         config: &wgpu::SurfaceConfiguration,
+        format: TextureFormat,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
@@ -28,7 +27,7 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: DEPTH_FORMAT,
+            format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
         };
@@ -55,6 +54,58 @@ impl Texture {
         }
     }
 
+    /// A view of a depth texture's depth only, for sampling it in later passes. Views of textures
+    /// with a stencil component can't be sampled as depth otherwise.
+    pub fn depth_view(&self) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        })
+    }
+
+    /// Create a 2d texture the size of the surface, that we render to, then sample from in
+    /// a later pass. Eg for post-processing.
+    pub fn create_render_target(
+        device: &Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: TextureFormat,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
     #[allow(dead_code)]
     pub fn from_bytes(
         device: &Device,
@@ -68,7 +119,6 @@ impl Texture {
         Self::from_image(device, queue, &img, Some(label), is_normal_map)
     }
 
-    #[allow(dead_code)]
     pub fn from_image(
         device: &Device,
         queue: &Queue,
@@ -134,3 +184,10 @@ impl Texture {
         }
     }
 }
+
+/// Decode an image file's contents, eg PNG, to RGBA with 8 bits per channel. Returns the width,
+/// height, and data, row by row from the top left.
+pub(crate) fn decode_rgba8(bytes: &[u8]) -> Result<(u32, u32, Vec<u8>), image::ImageError> {
+    let img = image::load_from_memory(bytes)?.to_rgba8();
+    Ok((img.width(), img.height(), img.into_raw()))
+}
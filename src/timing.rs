@@ -0,0 +1,204 @@
+//! Frame statistics, and GPU timing using timestamp queries. The latter lets us measure the
+//! compute, main (3D), and GUI passes separately. Timestamp queries require the
+//! `TIMESTAMP_QUERY` feature, which we request if supported by the adapter.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, Device, MapMode, QuerySet,
+    QuerySetDescriptor, QueryType, Queue, RenderPassTimestampWrites,
+};
+#[cfg(feature = "compute")]
+use wgpu::ComputePassTimestampWrites;
+
+/// Size of a single timestamp, in bytes.
+const TIMESTAMP_SIZE: u64 = 8;
+
+// Query indices. Compute passes start after these, with a start and end query each.
+const MAIN_START: u32 = 0;
+const GUI_START: u32 = 2;
+const COMPUTE_START: u32 = 4;
+
+#[derive(Clone, Debug, Default)]
+/// Timing information about recent frames. This is updated by the engine each frame, and is
+/// available to the application through `Scene::frame_stats`.
+pub struct FrameStats {
+    /// CPU time elapsed since the previous frame.
+    pub frame_time: Duration,
+    /// GPU time spent in all compute passes. `None` if GPU timing is disabled or unsupported.
+    /// GPU timings lag a frame or two behind, since we read them back without stalling.
+    pub gpu_compute: Option<Duration>,
+    /// GPU time spent in the main 3D render pass.
+    pub gpu_main: Option<Duration>,
+    /// GPU time spent in the GUI render pass.
+    pub gpu_gui: Option<Duration>,
+    /// The refresh rate of the window's monitor, in Hz, eg for choosing a fixed simulation step.
+    /// `None` if the platform doesn't report it. Updated when the window moves.
+    pub display_hz: Option<f32>,
+    /// With a fixed-timestep update handler, the fraction of a step accumulated but not yet run,
+    /// from 0 to 1, eg to interpolate between simulation states when rendering. See the
+    /// `fixed_step` module.
+    pub fixed_step_alpha: f32,
+}
+
+/// Timestamp queries, and the buffers we resolve and read them back with.
+pub(crate) struct GpuTimer {
+    query_set: QuerySet,
+    resolve_buf: Buffer,
+    readback_buf: Buffer,
+    num_queries: u32,
+    /// Set by the map callback, once the readback buffer is available to read.
+    ready: Arc<AtomicBool>,
+    /// True while the readback buffer is mapped, or a map is pending. We don't copy into it then.
+    pending: bool,
+    /// True if we copied to the readback buffer this frame, and need to map it after submitting.
+    copied: bool,
+}
+
+impl GpuTimer {
+    pub fn new(device: &Device, num_compute_passes: usize) -> Self {
+        let num_queries = COMPUTE_START + 2 * num_compute_passes as u32;
+
+        let query_set = device.create_query_set(&QuerySetDescriptor {
+            label: Some("Timestamp query set"),
+            ty: QueryType::Timestamp,
+            count: num_queries,
+        });
+
+        let size = num_queries as u64 * TIMESTAMP_SIZE;
+
+        let resolve_buf = device.create_buffer(&BufferDescriptor {
+            label: Some("Timestamp resolve buffer"),
+            size,
+            usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let readback_buf = device.create_buffer(&BufferDescriptor {
+            label: Some("Timestamp readback buffer"),
+            size,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            query_set,
+            resolve_buf,
+            readback_buf,
+            num_queries,
+            ready: Arc::new(AtomicBool::new(false)),
+            pending: false,
+            copied: false,
+        }
+    }
+
+    pub fn main_writes(&self) -> RenderPassTimestampWrites<'_> {
+        RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(MAIN_START),
+            end_of_pass_write_index: Some(MAIN_START + 1),
+        }
+    }
+
+    pub fn gui_writes(&self) -> RenderPassTimestampWrites<'_> {
+        RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(GUI_START),
+            end_of_pass_write_index: Some(GUI_START + 1),
+        }
+    }
+
+    /// `i` is the index of the pass in `Scene::compute_passes`.
+    #[cfg(feature = "compute")]
+    pub fn compute_writes(&self, i: usize) -> ComputePassTimestampWrites<'_> {
+        let start = COMPUTE_START + 2 * i as u32;
+
+        ComputePassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(start),
+            end_of_pass_write_index: Some(start + 1),
+        }
+    }
+
+    /// Resolve this frame's queries, and copy them to the readback buffer if it's not in use.
+    /// Run this after all passes are encoded.
+    pub fn resolve(&mut self, encoder: &mut CommandEncoder) {
+        if self.pending {
+            return;
+        }
+
+        encoder.resolve_query_set(&self.query_set, 0..self.num_queries, &self.resolve_buf, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buf,
+            0,
+            &self.readback_buf,
+            0,
+            self.resolve_buf.size(),
+        );
+
+        self.copied = true;
+    }
+
+    /// Start mapping the readback buffer. Run this after submitting the encoder.
+    pub fn map(&mut self) {
+        if !self.copied {
+            return;
+        }
+
+        let ready = self.ready.clone();
+        self.readback_buf
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                if result.is_ok() {
+                    ready.store(true, Ordering::Release);
+                }
+            });
+
+        self.copied = false;
+        self.pending = true;
+    }
+
+    /// If a readback has completed, update GPU timings in `stats`. This doesn't block.
+    pub fn read(&mut self, device: &Device, queue: &Queue, stats: &mut FrameStats) {
+        device.poll(wgpu::Maintain::Poll);
+
+        if !self.ready.swap(false, Ordering::Acquire) {
+            return;
+        }
+
+        {
+            let data = self.readback_buf.slice(..).get_mapped_range();
+
+            let timestamps: Vec<u64> = data
+                .chunks_exact(TIMESTAMP_SIZE as usize)
+                .map(|c| u64::from_ne_bytes(c.try_into().unwrap()))
+                .collect();
+
+            // Nanoseconds per timestamp tick.
+            let period = queue.get_timestamp_period() as f64;
+            let elapsed = |start: u32| {
+                let ticks =
+                    timestamps[start as usize + 1].saturating_sub(timestamps[start as usize]);
+                Duration::from_nanos((ticks as f64 * period) as u64)
+            };
+
+            stats.gpu_main = Some(elapsed(MAIN_START));
+            stats.gpu_gui = Some(elapsed(GUI_START));
+            stats.gpu_compute = Some(
+                (COMPUTE_START..self.num_queries)
+                    .step_by(2)
+                    .map(elapsed)
+                    .sum(),
+            );
+        }
+
+        self.readback_buf.unmap();
+        self.pending = false;
+    }
+}
@@ -0,0 +1,227 @@
+//! Stylized, toon-like rendering, eg for schematic or diagram-style figures: outlines at
+//! silhouettes and creases, and cel shading that quantizes lighting into bands.
+//!
+//! Outlines are drawn in a post pass over the 3D viewport, detected from the depth buffer: a large
+//! change in depth between neighboring pixels is a silhouette, and a change in slope is a crease.
+//! With TAA, we draw them before the resolve, so they're anti-aliased along with the scene.
+
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferUsages, CommandEncoder, Device,
+    FragmentState, RenderPassDescriptor, RenderPipeline, ShaderStages, StoreOp,
+    SurfaceConfiguration, TextureView, VertexState,
+};
+
+use crate::{
+    camera::Camera,
+    pass::PassContext,
+    types::{F32_SIZE, VEC4_SIZE},
+};
+
+/// Outline color; width, thresholds, and the near plane; the far plane, padded.
+const TOON_PARAMS_SIZE: usize = 3 * VEC4_SIZE;
+
+#[derive(Clone, Debug)]
+/// Outline and cel shading settings. Outlines apply from the next frame; set
+/// `EngineUpdates::color` after changing `cel_bands`.
+pub struct ToonSettings {
+    /// Draw outlines at silhouettes and creases.
+    pub outlines: bool,
+    /// RGBA. Interpreted according to `ColorSettings::input_space`.
+    pub outline_color: [f32; 4],
+    /// The distance, in pixels, we compare depth across. Larger values result in thicker
+    /// outlines.
+    pub outline_width: f32,
+    /// The relative change in depth between neighboring pixels, eg 0.1 for 10%, above which we
+    /// draw a silhouette.
+    pub depth_threshold: f32,
+    /// The relative change in depth slope between neighboring pixels above which we draw a
+    /// crease. Lower values outline shallower creases.
+    pub crease_threshold: f32,
+    /// If nonzero, lighting of meshes is quantized into this many bands of brightness.
+    pub cel_bands: u32,
+}
+
+impl Default for ToonSettings {
+    fn default() -> Self {
+        Self {
+            outlines: false,
+            outline_color: [0., 0., 0., 1.],
+            outline_width: 1.,
+            depth_threshold: 0.1,
+            crease_threshold: 0.02,
+            cel_bands: 0,
+        }
+    }
+}
+
+impl ToonSettings {
+    /// `outline_color` is the color to draw outlines, converted to the surface's encoding.
+    fn to_bytes(&self, outline_color: [f32; 4], camera: &Camera) -> [u8; TOON_PARAMS_SIZE] {
+        let mut result = [0; TOON_PARAMS_SIZE];
+
+        let values = [
+            outline_color[0],
+            outline_color[1],
+            outline_color[2],
+            outline_color[3],
+            self.outline_width,
+            self.depth_threshold,
+            self.crease_threshold,
+            camera.near,
+            camera.far,
+        ];
+
+        for (i, v) in values.iter().enumerate() {
+            result[i * F32_SIZE..(i + 1) * F32_SIZE].clone_from_slice(&v.to_ne_bytes());
+        }
+
+        result
+    }
+}
+
+/// The outline post pass.
+pub(crate) struct ToonRenderer {
+    pipeline: RenderPipeline,
+    layout: BindGroupLayout,
+    params_buf: Buffer,
+}
+
+impl ToonRenderer {
+    pub fn new(device: &Device, surface_cfg: &SurfaceConfiguration) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Toon outline shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("toon.wgsl").into()),
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Toon outline bind group layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Toon outline pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Toon outline pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_cfg.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let params_buf = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Toon outline params buffer"),
+            contents: &[0; TOON_PARAMS_SIZE],
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        Self {
+            pipeline,
+            layout,
+            params_buf,
+        }
+    }
+
+    /// Draw outlines over the 3D viewport of `ctx`'s output texture, using the main pass's depth.
+    /// `outline_color` is converted to the surface's encoding.
+    pub fn encode(
+        &self,
+        ctx: &PassContext,
+        encoder: &mut CommandEncoder,
+        depth_view: &TextureView,
+        settings: &ToonSettings,
+        outline_color: [f32; 4],
+        camera: &Camera,
+    ) {
+        let PassContext {
+            device,
+            queue,
+            output_texture,
+            viewport,
+            ..
+        } = *ctx;
+
+        let params = settings.to_bytes(outline_color, camera);
+        queue.write_buffer(&self.params_buf, 0, &params);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(depth_view),
+                },
+            ],
+            label: Some("Toon outline bind group"),
+        });
+
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Toon outline render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output_texture,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        let (x, y, width, height) = viewport;
+        rpass.set_viewport(x, y, width, height, 0., 1.);
+
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
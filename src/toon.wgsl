@@ -0,0 +1,74 @@
+// Toon outlines: a post pass that detects silhouettes and creases from the depth buffer, and
+// blends an outline color over them.
+
+struct ToonParams {
+    // Converted to the surface's encoding.
+    color: vec4<f32>,
+    // In pixels.
+    width: f32,
+    depth_threshold: f32,
+    crease_threshold: f32,
+    near: f32,
+    far: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: ToonParams;
+@group(0) @binding(1)
+var depth_tex: texture_depth_2d;
+
+struct VertexOut {
+    @builtin(position) posit: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> VertexOut {
+    // A single triangle that covers the viewport.
+    var uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+
+    var result: VertexOut;
+    result.posit = vec4<f32>(uv * vec2<f32>(2., -2.) + vec2<f32>(-1., 1.), 0., 1.);
+
+    return result;
+}
+
+// The distance from the camera plane, from a depth buffer value.
+fn linear_depth(pixel: vec2<i32>) -> f32 {
+    var dims = vec2<i32>(textureDimensions(depth_tex));
+    var depth = textureLoad(depth_tex, clamp(pixel, vec2<i32>(0, 0), dims - 1), 0);
+
+    return params.near * params.far / (params.far - depth * (params.far - params.near));
+}
+
+@fragment
+fn fs_main(vertex: VertexOut) -> @location(0) vec4<f32> {
+    var pixel = vec2<i32>(vertex.posit.xy);
+    var offset = i32(max(round(params.width), 1.));
+
+    var center = linear_depth(pixel);
+    var left = linear_depth(pixel - vec2<i32>(offset, 0));
+    var right = linear_depth(pixel + vec2<i32>(offset, 0));
+    var up = linear_depth(pixel - vec2<i32>(0, offset));
+    var down = linear_depth(pixel + vec2<i32>(0, offset));
+
+    // Silhouettes: a large relative change in depth to any neighbor.
+    var depth_diff = max(
+        max(abs(left - center), abs(right - center)),
+        max(abs(up - center), abs(down - center)),
+    ) / center;
+
+    // Creases: a change in slope. Inverse depth varies linearly across the screen over a flat
+    // surface, so its second difference is zero there, regardless of viewing angle.
+    var inv_center = 1. / center;
+    var crease = max(
+        abs(1. / left + 1. / right - 2. * inv_center),
+        abs(1. / up + 1. / down - 2. * inv_center),
+    ) / inv_center;
+
+    var edge = max(
+        smoothstep(params.depth_threshold, params.depth_threshold * 1.5, depth_diff),
+        smoothstep(params.crease_threshold, params.crease_threshold * 1.5, crease),
+    );
+
+    return vec4<f32>(params.color.rgb, params.color.a * edge);
+}
@@ -0,0 +1,215 @@
+//! Transitions of entity color, scale, and opacity, eg to fade a highlight in and out. The
+//! application starts one with `Scene::animate_color`, `animate_scale`, or `animate_opacity`; the
+//! engine interpolates it each frame, and writes the entity's instance, so no per-frame
+//! bookkeeping is needed.
+//!
+//! Starting a transition of a property that's already transitioning replaces it, starting from the
+//! current value, so a fade can be reversed part way. Transitions write to the entity's fields, so
+//! setting those fields directly, or with `Scene::sync_entities`, while a transition runs is
+//! overridden on the next frame.
+//!
+//! `Scene::animate_background` transitions the background color the same way, eg for a day and
+//! night cycle.
+
+use crate::types::{Entity, Scene};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A property, and its value.
+enum Value {
+    Color((f32, f32, f32)),
+    Scale(f32),
+    Opacity(f32),
+}
+
+impl Value {
+    fn of(&self, entity: &Entity) -> Self {
+        match self {
+            Self::Color(_) => Self::Color(entity.color),
+            Self::Scale(_) => Self::Scale(entity.scale),
+            Self::Opacity(_) => Self::Opacity(entity.opacity),
+        }
+    }
+
+    fn apply(&self, entity: &mut Entity) {
+        match *self {
+            Self::Color(v) => entity.color = v,
+            Self::Scale(v) => entity.scale = v,
+            Self::Opacity(v) => entity.opacity = v,
+        }
+    }
+
+    fn lerp(&self, target: &Self, amount: f32) -> Self {
+        let lerp = |a: f32, b: f32| a + (b - a) * amount;
+
+        match (*self, *target) {
+            (Self::Color(a), Self::Color(b)) => {
+                Self::Color((lerp(a.0, b.0), lerp(a.1, b.1), lerp(a.2, b.2)))
+            }
+            (Self::Scale(a), Self::Scale(b)) => Self::Scale(lerp(a, b)),
+            (Self::Opacity(a), Self::Opacity(b)) => Self::Opacity(lerp(a, b)),
+            _ => *target,
+        }
+    }
+
+    fn same_property(&self, other: &Self) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Transition {
+    entity: usize,
+    start: Value,
+    target: Value,
+    /// In seconds.
+    duration: f32,
+    elapsed: f32,
+}
+
+#[derive(Clone, Debug)]
+/// A transition of `Scene::background_color`.
+struct BackgroundTransition {
+    start: (f32, f32, f32),
+    target: (f32, f32, f32),
+    /// In seconds.
+    duration: f32,
+    elapsed: f32,
+}
+
+/// Progress through a transition, from 0 to 1, eased in and out.
+fn ease(elapsed: f32, duration: f32) -> f32 {
+    let x = if duration > 0. {
+        (elapsed / duration).min(1.)
+    } else {
+        1.
+    };
+
+    x * x * (3. - 2. * x)
+}
+
+#[derive(Clone, Debug, Default)]
+/// Transitions in progress. See `Scene::animate_color`.
+pub struct Transitions {
+    active: Vec<Transition>,
+    background: Option<BackgroundTransition>,
+}
+
+impl Transitions {
+    /// If any of an entity's properties are transitioning.
+    pub fn is_active(&self, entity: usize) -> bool {
+        self.active.iter().any(|t| t.entity == entity)
+    }
+
+    /// Stop an entity's transitions, leaving its properties at their current values.
+    pub fn stop(&mut self, entity: usize) {
+        self.active.retain(|t| t.entity != entity);
+    }
+
+    /// Stop the background color's transition, leaving it at its current value.
+    pub fn stop_background(&mut self) {
+        self.background = None;
+    }
+
+    /// Stop all transitions, eg after replacing the scene's entities.
+    pub fn clear(&mut self) {
+        self.active.clear();
+        self.background = None;
+    }
+
+    /// Advance transitions by `dt` seconds, and set entities' properties. Returns the indices of
+    /// entities changed, whose instances must be updated. Transitions of entities that no
+    /// longer exist are dropped.
+    pub(crate) fn update(&mut self, dt: f32, entities: &mut [Entity]) -> Vec<usize> {
+        let mut changed = Vec::new();
+
+        self.active.retain_mut(|t| {
+            let Some(entity) = entities.get_mut(t.entity) else {
+                return false;
+            };
+
+            t.elapsed += dt;
+
+            t.start
+                .lerp(&t.target, ease(t.elapsed, t.duration))
+                .apply(entity);
+            if !changed.contains(&t.entity) {
+                changed.push(t.entity);
+            }
+
+            t.elapsed < t.duration
+        });
+
+        changed
+    }
+
+    /// Advance the background color's transition by `dt` seconds, if there is one, and set
+    /// `color`.
+    pub(crate) fn update_background(&mut self, dt: f32, color: &mut (f32, f32, f32)) {
+        let Some(t) = &mut self.background else {
+            return;
+        };
+
+        t.elapsed += dt;
+
+        let amount = ease(t.elapsed, t.duration);
+        let lerp = |a: f32, b: f32| a + (b - a) * amount;
+        *color = (
+            lerp(t.start.0, t.target.0),
+            lerp(t.start.1, t.target.1),
+            lerp(t.start.2, t.target.2),
+        );
+
+        if t.elapsed >= t.duration {
+            self.background = None;
+        }
+    }
+
+    fn start(&mut self, entities: &[Entity], entity: usize, target: Value, duration: f32) {
+        let Some(ent) = entities.get(entity) else {
+            return;
+        };
+
+        self.active
+            .retain(|t| t.entity != entity || !t.target.same_property(&target));
+
+        self.active.push(Transition {
+            entity,
+            start: target.of(ent),
+            target,
+            duration: duration.max(0.),
+            elapsed: 0.,
+        });
+    }
+}
+
+impl Scene {
+    /// Transition an entity's color to `target` over `duration` seconds. `entity` is an index
+    /// into `entities`, eg from `Hit::entity`. Entities using a palette color aren't affected.
+    pub fn animate_color(&mut self, entity: usize, target: (f32, f32, f32), duration: f32) {
+        self.transitions
+            .start(&self.entities, entity, Value::Color(target), duration);
+    }
+
+    /// Transition an entity's scale to `target` over `duration` seconds.
+    pub fn animate_scale(&mut self, entity: usize, target: f32, duration: f32) {
+        self.transitions
+            .start(&self.entities, entity, Value::Scale(target), duration);
+    }
+
+    /// Transition an entity's opacity to `target` over `duration` seconds.
+    pub fn animate_opacity(&mut self, entity: usize, target: f32, duration: f32) {
+        self.transitions
+            .start(&self.entities, entity, Value::Opacity(target), duration);
+    }
+
+    /// Transition `background_color` to `target` over `duration` seconds, replacing any background
+    /// transition in progress.
+    pub fn animate_background(&mut self, target: (f32, f32, f32), duration: f32) {
+        self.transitions.background = Some(BackgroundTransition {
+            start: self.background_color,
+            target,
+            duration: duration.max(0.),
+            elapsed: 0.,
+        });
+    }
+}
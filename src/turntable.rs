@@ -0,0 +1,63 @@
+//! Turntable auto-rotation: the camera orbits a point at a constant speed, eg for unattended demo
+//! displays, or recording turntable videos with `HeadlessRenderer`. Start and stop it with
+//! `EngineUpdates::turntable` and `EngineUpdates::stop_turntable`.
+//!
+//! Rotation pauses while the user moves the camera with the built-in controls, and resumes from
+//! wherever they left it, after `Turntable::resume_delay`.
+
+use core::f32::consts::TAU;
+
+use lin_alg::f32::{Quaternion, Vec3};
+
+use crate::{camera::Camera, graphics::UP_VEC};
+
+#[derive(Clone, Debug)]
+pub struct Turntable {
+    /// The point the camera orbits, in world space.
+    pub center: Vec3,
+    /// The axis the camera orbits around, through `center`. Defaults to up.
+    pub axis: Vec3,
+    /// In radians per second. Positive values are counter-clockwise, looking down the axis.
+    pub speed: f32,
+    /// Seconds without user input before rotation resumes.
+    pub resume_delay: f32,
+    /// Seconds since user input last moved the camera.
+    idle: f32,
+}
+
+impl Turntable {
+    /// Orbit `center` around the up axis, once every 20 seconds.
+    pub fn new(center: Vec3) -> Self {
+        let resume_delay = 3.;
+
+        Self {
+            center,
+            axis: UP_VEC,
+            speed: TAU / 20.,
+            resume_delay,
+            // Start rotating immediately.
+            idle: resume_delay,
+        }
+    }
+
+    /// Rotate the camera by `dt` seconds of motion, unless paused by user input. Returns true if
+    /// the camera moved.
+    pub(crate) fn update(&mut self, camera: &mut Camera, dt: f32, user_input: bool) -> bool {
+        if user_input {
+            self.idle = 0.;
+            return false;
+        }
+
+        self.idle += dt;
+        if self.idle < self.resume_delay || self.axis.magnitude_squared() == 0. {
+            return false;
+        }
+
+        let rotation = Quaternion::from_axis_angle(self.axis.to_normalized(), self.speed * dt);
+
+        camera.position = self.center + rotation.rotate_vec(camera.position - self.center);
+        camera.orientation = rotation * camera.orientation;
+
+        true
+    }
+}
@@ -0,0 +1,359 @@
+//! A 2D mode, for simple plots, diagrams, or node editors, without a separate 2D engine. This uses
+//! the 3D renderer, with an orthographic camera looking along +Z, and flat lighting. X is right,
+//! Y is up, and Z orders layers. Shapes are meshes in the XY plane, drawn by entities as usual.
+//!
+//! Eg: set `Scene::camera` to `Camera::new_2d`, `Scene::lighting` to `Lighting::new_2d`, add
+//! meshes from `Mesh::new_rect` and similar, and entities with `Entity::new_2d`. Use
+//! `ControlScheme::None`, since free camera controls rotate the camera out of the plane.
+//!
+//! Layers are depth tested, so translucent shapes only blend with shapes drawn before them, ie
+//! those of earlier meshes.
+
+use core::f32::consts::TAU;
+
+use lin_alg::f32::{Quaternion, Vec3};
+
+use crate::{
+    camera::Camera,
+    graphics::{FWD_VEC, RIGHT_VEC, UP_VEC},
+    lighting::Lighting,
+    meshes::UvProjection,
+    types::{Entity, FaceCulling, Mesh, Vertex},
+};
+
+/// The Z distance between adjacent layers.
+const LAYER_SPACING: f32 = 0.01;
+
+/// Layers from `-MAX_LAYER` to `MAX_LAYER` are between the 2D camera's near and far planes.
+const MAX_LAYER: i32 = 9_999;
+
+/// The 2D camera's Z position; it sees layers on both sides of the origin.
+const CAMERA_Z: f32 = -(MAX_LAYER + 1) as f32 * LAYER_SPACING;
+
+/// Joins sharper than this are clipped, so their miters don't extend far past the stroke.
+const MAX_MITER: f32 = 4.;
+
+impl Camera {
+    /// An orthographic camera for 2D, looking at the XY plane, centered on the origin.
+    /// `view_height` is in world units; use the 3D viewport's height in pixels, for one unit per
+    /// pixel. Move it by changing `position`'s X and Y.
+    pub fn new_2d(view_height: f32) -> Self {
+        let mut result = Self {
+            position: Vec3::new(0., 0., CAMERA_Z),
+            near: 0.,
+            far: -2. * CAMERA_Z,
+            ortho_height: Some(view_height),
+            ..Default::default()
+        };
+
+        result.update_proj_mat();
+        result
+    }
+
+    /// The position in the XY plane under a pixel of the 3D viewport, with an orthographic camera.
+    /// `viewport_size` is the viewport's width and height, and `pixel` is relative to its top
+    /// left, in pixels. Returns `None` with a perspective camera.
+    pub fn pixel_to_world(
+        &self,
+        viewport_size: (f32, f32),
+        pixel: (f32, f32),
+    ) -> Option<(f32, f32)> {
+        let height = self.ortho_height?;
+        let width = height * self.aspect;
+
+        let ndc_x = pixel.0 / viewport_size.0 * 2. - 1.;
+        let ndc_y = 1. - pixel.1 / viewport_size.1 * 2.;
+
+        let offset = RIGHT_VEC * (ndc_x * width / 2.) + UP_VEC * (ndc_y * height / 2.);
+        let posit = self.position + self.orientation.rotate_vec(offset);
+
+        Some((posit.x, posit.y))
+    }
+
+    /// The pixel of the 3D viewport at a position in the XY plane, relative to the viewport's top
+    /// left; the inverse of `pixel_to_world`. Returns `None` with a perspective camera.
+    pub fn world_to_pixel(
+        &self,
+        viewport_size: (f32, f32),
+        posit: (f32, f32),
+    ) -> Option<(f32, f32)> {
+        let height = self.ortho_height?;
+        let width = height * self.aspect;
+
+        let diff = Vec3::new(posit.0, posit.1, self.position.z) - self.position;
+        let ndc_x = diff.dot(self.orientation.rotate_vec(RIGHT_VEC)) / (width / 2.);
+        let ndc_y = diff.dot(self.orientation.rotate_vec(UP_VEC)) / (height / 2.);
+
+        Some((
+            (ndc_x + 1.) / 2. * viewport_size.0,
+            (1. - ndc_y) / 2. * viewport_size.1,
+        ))
+    }
+}
+
+impl Lighting {
+    /// Full-intensity white ambient light, and no point lights, so shapes show their colors
+    /// unshaded.
+    pub fn new_2d() -> Self {
+        Self {
+            ambient_color: [1., 1., 1., 1.],
+            ambient_intensity: 1.,
+            point_lights: Vec::new(),
+        }
+    }
+}
+
+impl Entity {
+    /// An entity in the XY plane, at `posit`, on a layer. Higher layers are drawn over lower ones.
+    pub fn new_2d(mesh: usize, posit: (f32, f32), layer: i32, color: (f32, f32, f32)) -> Self {
+        let mut result = Self::new(
+            mesh,
+            Vec3::new(posit.0, posit.1, 0.),
+            Quaternion::new_identity(),
+            1.,
+            color,
+            0.,
+        );
+
+        result.set_layer(layer);
+        result
+    }
+
+    /// Move this entity to a layer, from -9,999 to 9,999. Higher layers are drawn over lower ones.
+    pub fn set_layer(&mut self, layer: i32) {
+        self.position.z = -(layer.clamp(-MAX_LAYER, MAX_LAYER) as f32) * LAYER_SPACING;
+    }
+
+    /// Rotate this entity in the XY plane, counter-clockwise, in radians.
+    pub fn set_rotation_2d(&mut self, angle: f32) {
+        self.orientation = Quaternion::from_axis_angle(FWD_VEC, angle);
+    }
+}
+
+impl Mesh {
+    /// A filled rectangle in the XY plane, centered on the origin.
+    pub fn new_rect(width: f32, height: f32) -> Self {
+        let (x, y) = (width / 2., height / 2.);
+        flat_mesh(
+            &[[-x, -y], [x, -y], [x, y], [-x, y]],
+            vec![0, 1, 2, 0, 2, 3],
+        )
+    }
+
+    /// A rectangle's outline in the XY plane, centered on the origin. The stroke is centered on
+    /// the edges.
+    pub fn new_rect_stroke(width: f32, height: f32, thickness: f32) -> Self {
+        let (x, y) = (width / 2., height / 2.);
+        Self::new_polyline(&[(-x, -y), (x, -y), (x, y), (-x, y)], thickness, true)
+    }
+
+    /// A filled circle in the XY plane, centered on the origin.
+    pub fn new_circle(radius: f32, num_segments: usize) -> Self {
+        let points = circle_points(radius, num_segments);
+
+        // A fan around the center, which is the last vertex.
+        let center = points.len();
+        let mut indices = Vec::with_capacity(points.len() * 3);
+        for i in 0..points.len() {
+            indices.extend_from_slice(&[center, i, (i + 1) % points.len()]);
+        }
+
+        let mut vertices = points;
+        vertices.push([0., 0.]);
+        flat_mesh(&vertices, indices)
+    }
+
+    /// A circle's outline in the XY plane, centered on the origin. The stroke is centered on
+    /// `radius`.
+    pub fn new_circle_stroke(radius: f32, thickness: f32, num_segments: usize) -> Self {
+        let points: Vec<_> = circle_points(radius, num_segments)
+            .iter()
+            .map(|p| (p[0], p[1]))
+            .collect();
+
+        Self::new_polyline(&points, thickness, true)
+    }
+
+    /// A filled polygon in the XY plane, from its outline, in either winding order. It may be
+    /// concave, but its edges shouldn't cross.
+    pub fn new_polygon(points: &[(f32, f32)]) -> Self {
+        let points: Vec<_> = points.iter().map(|p| [p.0, p.1]).collect();
+        let indices = triangulate(&points);
+        flat_mesh(&points, indices)
+    }
+
+    /// A line through points in the XY plane, `thickness` wide, with mitered joins. If `closed`,
+    /// the last point connects to the first, eg for a polygon's outline.
+    pub fn new_polyline(points: &[(f32, f32)], thickness: f32, closed: bool) -> Self {
+        let points: Vec<_> = points.iter().map(|p| [p.0, p.1]).collect();
+        let n = points.len();
+        if n < 2 {
+            return flat_mesh(&[], Vec::new());
+        }
+
+        let half = thickness / 2.;
+
+        // The left-hand normal of the segment from point `i` to the next.
+        let segment_normal = |i: usize| {
+            let (a, b) = (points[i], points[(i + 1) % n]);
+            let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+            let len = (dx * dx + dy * dy).sqrt().max(f32::EPSILON);
+            [-dy / len, dx / len]
+        };
+
+        // Each point has a vertex on either side of the line.
+        let mut vertices = Vec::with_capacity(n * 2);
+        for (i, point) in points.iter().enumerate() {
+            let prev = if i > 0 || closed {
+                Some(segment_normal((i + n - 1) % n))
+            } else {
+                None
+            };
+            let next = if i < n - 1 || closed {
+                Some(segment_normal(i))
+            } else {
+                None
+            };
+
+            let offset = match (prev, next) {
+                (Some(a), Some(b)) => miter(a, b, half),
+                (Some(a), None) | (None, Some(a)) => [a[0] * half, a[1] * half],
+                (None, None) => unreachable!(),
+            };
+
+            vertices.push([point[0] + offset[0], point[1] + offset[1]]);
+            vertices.push([point[0] - offset[0], point[1] - offset[1]]);
+        }
+
+        let num_segments = if closed { n } else { n - 1 };
+        let mut indices = Vec::with_capacity(num_segments * 6);
+        for i in 0..num_segments {
+            let (a, b) = (i * 2, (i + 1) % n * 2);
+            indices.extend_from_slice(&[a, a + 1, b + 1, a, b + 1, b]);
+        }
+
+        flat_mesh(&vertices, indices)
+    }
+}
+
+/// Build a mesh in the XY plane, facing the 2D camera. Faces aren't culled, so triangles may use
+/// either winding.
+fn flat_mesh(points: &[[f32; 2]], indices: Vec<usize>) -> Mesh {
+    let normal = FWD_VEC * -1.;
+
+    let vertices = points
+        .iter()
+        .map(|p| Vertex::new([p[0], p[1], 0.], normal))
+        .collect();
+
+    let mut result = Mesh {
+        vertices,
+        indices,
+        material: None,
+        impostor: None,
+        culling: FaceCulling::None,
+    };
+
+    result.generate_uvs(UvProjection::Box);
+    result
+}
+
+/// Points around a circle, counter-clockwise from +X.
+fn circle_points(radius: f32, num_segments: usize) -> Vec<[f32; 2]> {
+    let num_segments = num_segments.max(3);
+
+    (0..num_segments)
+        .map(|i| {
+            let θ = i as f32 / num_segments as f32 * TAU;
+            [radius * θ.cos(), radius * θ.sin()]
+        })
+        .collect()
+}
+
+/// The offset from a join to the stroke's left edge, where segments with normals `a` and `b`
+/// meet.
+fn miter(a: [f32; 2], b: [f32; 2], half: f32) -> [f32; 2] {
+    let sum = [a[0] + b[0], a[1] + b[1]];
+    let len = (sum[0] * sum[0] + sum[1] * sum[1]).sqrt();
+
+    // The segments double back on each other.
+    if len < f32::EPSILON {
+        return [a[0] * half, a[1] * half];
+    }
+
+    let dir = [sum[0] / len, sum[1] / len];
+    let scale = (half / (dir[0] * a[0] + dir[1] * a[1])).min(half * MAX_MITER);
+
+    [dir[0] * scale, dir[1] * scale]
+}
+
+/// Twice the signed area of a triangle; positive if counter-clockwise.
+fn cross(a: [f32; 2], b: [f32; 2], c: [f32; 2]) -> f32 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+/// Triangulate a simple polygon by ear clipping, returning triangle indices. Triangles are
+/// counter-clockwise. If the polygon's edges cross, some of it may be missing.
+fn triangulate(points: &[[f32; 2]]) -> Vec<usize> {
+    let n = points.len();
+    if n < 3 {
+        return Vec::new();
+    }
+
+    // Work counter-clockwise, so ears are convex corners.
+    let area: f32 = (0..n)
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % n]);
+            a[0] * b[1] - b[0] * a[1]
+        })
+        .sum();
+
+    let mut remaining: Vec<usize> = if area >= 0. {
+        (0..n).collect()
+    } else {
+        (0..n).rev().collect()
+    };
+
+    let mut result = Vec::with_capacity((n - 2) * 3);
+    let mut i = 0;
+    // Corners checked since the last ear was clipped; if we've checked them all, there are none.
+    let mut checked = 0;
+
+    while remaining.len() > 3 && checked < remaining.len() {
+        let len = remaining.len();
+        let (ia, ib, ic) = (
+            remaining[(i + len - 1) % len],
+            remaining[i],
+            remaining[(i + 1) % len],
+        );
+        let (a, b, c) = (points[ia], points[ib], points[ic]);
+
+        let is_ear = cross(a, b, c) > 0.
+            && !remaining.iter().any(|&j| {
+                let p = points[j];
+                j != ia
+                    && j != ib
+                    && j != ic
+                    && cross(a, b, p) >= 0.
+                    && cross(b, c, p) >= 0.
+                    && cross(c, a, p) >= 0.
+            });
+
+        if is_ear {
+            result.extend_from_slice(&[ia, ib, ic]);
+            remaining.remove(i);
+            checked = 0;
+        } else {
+            i += 1;
+            checked += 1;
+        }
+
+        i %= remaining.len();
+    }
+
+    if remaining.len() == 3 {
+        result.extend_from_slice(&remaining);
+    }
+
+    result
+}
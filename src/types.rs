@@ -1,8 +1,52 @@
 //! https://sotrh.github.io/learn-wgpu/beginner/tutorial9-models/#rendering-a-mesh
 
+use std::collections::HashMap;
+
 use lin_alg::f32::{Mat4, Quaternion, Vec3};
 
-use crate::{camera::Camera, lighting::Lighting};
+#[cfg(feature = "compute")]
+use crate::compute::ComputePass;
+use crate::{
+    anaglyph::Anaglyph,
+    area_light::AreaLight,
+    animation::Timeline,
+    background::BackgroundGradient,
+    camera::Camera,
+    collision::SpatialCache,
+    color::{color_from_srgb8, linear_to_srgb, srgb_to_linear},
+    debug::{DebugDraw, DebugSettings, DebugShapes},
+    debug_view::DebugView,
+    extension::ShaderExtension,
+    gpu_pick::GpuHit,
+    ground::GroundPlane,
+    heatmap::Colormap,
+    hud::Hud,
+    impostor::Impostor,
+    input::KeyBindings,
+    inset::Inset,
+    labels::Label,
+    layers::{RenderLayer, RenderLayers},
+    lifecycle::WindowState,
+    light_path::LightPath,
+    lighting::Lighting,
+    loader::{AssetRequest, LoadEvent},
+    material::{Material, SamplerSettings},
+    math,
+    measure::MeasureTool,
+    packed::PackedInstances,
+    probe::EnvProbe,
+    raw_instances::InstanceRaw,
+    redraw::{RedrawMode, RedrawRegion},
+    sdf::SdfElement,
+    shadow::SHADOW_MAP_SIZE,
+    slice::SlicePlane,
+    shortcut::Shortcuts,
+    timing::FrameStats,
+    transition::Transitions,
+    turntable::Turntable,
+    toon::ToonSettings,
+    views::ExtraView,
+};
 
 // These sizes are in bytes. We do this, since that's the data format expected by the shader.
 pub const F32_SIZE: usize = 4;
@@ -11,12 +55,31 @@ pub const VEC3_SIZE: usize = 3 * F32_SIZE;
 pub const VEC4_SIZE: usize = 4 * F32_SIZE;
 pub const VEC3_UNIFORM_SIZE: usize = 4 * F32_SIZE;
 pub const MAT4_SIZE: usize = 16 * F32_SIZE;
-pub const MAT3_SIZE: usize = 9 * F32_SIZE;
 
 pub const VERTEX_SIZE: usize = 14 * F32_SIZE;
 // Note that position, orientation, and scale are combined into a single 4x4 transformation
 // matrix. Note that unlike uniforms, we don't need alignment padding, and can use Vec3 directly.
-pub const INSTANCE_SIZE: usize = MAT4_SIZE + MAT3_SIZE + VEC4_SIZE + F32_SIZE;
+// The normal matrix columns are padded to Vec4s with the lighting factors, so the main pipelines
+// use 16 vertex attributes, WebGPU's default limit: 5 per vertex, and 11 per instance.
+pub const INSTANCE_SIZE: usize =
+    MAT4_SIZE + 3 * VEC4_SIZE + VEC4_SIZE + 4 * F32_SIZE + VEC4_SIZE + VEC3_SIZE;
+
+/// The offset of color and opacity in serialized instances.
+pub(crate) const INSTANCE_COLOR_START: usize = MAT4_SIZE + 3 * VEC4_SIZE;
+
+/// The offset of shinyness in serialized instances. Reflectivity, palette index, and material
+/// index follow it.
+const INSTANCE_PROPS_START: usize = INSTANCE_COLOR_START + VEC4_SIZE;
+
+/// The offset of the texture coordinate offset and scale in serialized instances. The texture
+/// tint follows them.
+pub(crate) const INSTANCE_UV_START: usize = INSTANCE_PROPS_START + VEC4_SIZE;
+
+/// The scene extent the engine's defaults, eg camera speed and clipping planes, are tuned for.
+const REFERENCE_EXTENT: f32 = 10.;
+
+/// Exposure, gamma, if input is sRGB, if we encode output as sRGB, and cel shading bands, padded.
+const COLOR_SETTINGS_SIZE: usize = 8 * F32_SIZE;
 
 #[derive(Clone, Copy, Debug)]
 /// Example attributes: https://github.com/bevyengine/bevy/blob/main/crates/bevy_render/src/mesh/mesh/mod.rs#L56
@@ -128,9 +191,46 @@ pub struct Instance {
     pub color: Vec3,
     pub opacity: f32,
     pub shinyness: f32,
+    pub reflectivity: f32,
+    pub palette_i: Option<usize>,
+    pub material: Option<usize>,
+    pub lighting_factors: LightingFactors,
+    pub uv_offset: (f32, f32),
+    pub uv_scale: (f32, f32),
+    pub texture_tint: Vec3,
 }
 
 impl Instance {
+    pub fn from_entity(entity: &Entity) -> Self {
+        Self {
+            position: entity.position,
+            orientation: entity.orientation,
+            scale: entity.scale,
+            color: Vec3::new(entity.color.0, entity.color.1, entity.color.2),
+            opacity: entity.opacity,
+            shinyness: entity.shinyness,
+            reflectivity: entity.reflectivity,
+            palette_i: entity.palette_i,
+            material: entity.material,
+            lighting_factors: entity.lighting_factors,
+            uv_offset: entity.uv_offset,
+            uv_scale: entity.uv_scale,
+            texture_tint: Vec3::new(
+                entity.texture_tint.0,
+                entity.texture_tint.1,
+                entity.texture_tint.2,
+            ),
+        }
+    }
+
+    /// Use the mesh's material if the entity doesn't set one, and the material's shinyness, if set.
+    pub(crate) fn apply_material(&mut self, mesh: &Mesh, materials: &[Material]) {
+        self.material = self.material.or(mesh.material);
+        if let Some(shinyness) = self.material.and_then(|i| materials.get(i)?.shinyness) {
+            self.shinyness = shinyness;
+        }
+    }
+
     /// Create the vertex buffer memory layout, for our vertexes passed from the
     /// vertex to the fragment shader. Corresponds to `VertexOut` in the shader. Each
     /// item here is for a single vertex. Cannot share locations with `VertexIn`, so
@@ -171,53 +271,83 @@ impl Instance {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
-                // Normal matrix, col 0
+                // Normal matrix, col 0, and the ambient lighting factor
                 wgpu::VertexAttribute {
                     offset: (MAT4_SIZE) as wgpu::BufferAddress,
                     shader_location: 9,
-                    format: wgpu::VertexFormat::Float32x3,
+                    format: wgpu::VertexFormat::Float32x4,
                 },
-                // Normal matrix, col 1
+                // Normal matrix, col 1, and the diffuse lighting factor
                 wgpu::VertexAttribute {
-                    offset: (MAT4_SIZE + VEC3_SIZE) as wgpu::BufferAddress,
+                    offset: (MAT4_SIZE + VEC4_SIZE) as wgpu::BufferAddress,
                     shader_location: 10,
-                    format: wgpu::VertexFormat::Float32x3,
+                    format: wgpu::VertexFormat::Float32x4,
                 },
-                // Normal matrix, col 2
+                // Normal matrix, col 2, and the specular lighting factor
                 wgpu::VertexAttribute {
-                    offset: (MAT4_SIZE + VEC3_SIZE * 2) as wgpu::BufferAddress,
+                    offset: (MAT4_SIZE + VEC4_SIZE * 2) as wgpu::BufferAddress,
                     shader_location: 11,
-                    format: wgpu::VertexFormat::Float32x3,
+                    format: wgpu::VertexFormat::Float32x4,
                 },
-                // model (and vertex) color
+                // model (and vertex) color, and opacity
                 wgpu::VertexAttribute {
-                    offset: (MAT4_SIZE + MAT3_SIZE) as wgpu::BufferAddress,
+                    offset: INSTANCE_COLOR_START as wgpu::BufferAddress,
                     shader_location: 12,
-                    format: wgpu::VertexFormat::Float32x3,
+                    format: wgpu::VertexFormat::Float32x4,
                 },
-                // Shinyness
+                // Shinyness and reflectivity, then palette and material indices; -1 if none.
+                // These mix f32s and i32s, so the shader reads their bits, and casts them.
                 wgpu::VertexAttribute {
-                    offset: (MAT4_SIZE + MAT3_SIZE + VEC4_SIZE) as wgpu::BufferAddress,
+                    offset: INSTANCE_PROPS_START as wgpu::BufferAddress,
                     shader_location: 13,
-                    format: wgpu::VertexFormat::Float32,
+                    format: wgpu::VertexFormat::Uint32x4,
+                },
+                // Texture coordinate offset and scale.
+                wgpu::VertexAttribute {
+                    offset: INSTANCE_UV_START as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // Texture tint.
+                wgpu::VertexAttribute {
+                    offset: (INSTANCE_UV_START + VEC4_SIZE) as wgpu::BufferAddress,
+                    shader_location: 15,
+                    format: wgpu::VertexFormat::Float32x3,
                 },
             ],
         }
     }
 
+    /// The model matrix, which combines position, orientation, and scale.
+    pub fn model_mat(&self) -> Mat4 {
+        Mat4::new_translation(self.position)
+            * self.orientation.to_matrix()
+            * Mat4::new_scaler(self.scale)
+    }
+
     /// Converts to a model matrix
     pub fn to_bytes(&self) -> [u8; INSTANCE_SIZE] {
         let mut result = [0; INSTANCE_SIZE];
 
-        let model_mat = Mat4::new_translation(self.position)
-            * self.orientation.to_matrix()
-            * Mat4::new_scaler(self.scale);
+        let model_mat = self.model_mat();
 
         let normal_mat = self.orientation.to_matrix3();
 
         result[0..MAT4_SIZE].clone_from_slice(&model_mat.to_bytes());
 
-        result[MAT4_SIZE..MAT4_SIZE + MAT3_SIZE].clone_from_slice(&normal_mat.to_bytes());
+        // Each column is padded with a lighting factor.
+        let normal_buf = normal_mat.to_bytes();
+        let factors = [
+            self.lighting_factors.ambient,
+            self.lighting_factors.diffuse,
+            self.lighting_factors.specular,
+        ];
+        for (i, factor) in factors.into_iter().enumerate() {
+            let col = MAT4_SIZE + i * VEC4_SIZE;
+            result[col..col + VEC3_SIZE]
+                .clone_from_slice(&normal_buf[i * VEC3_SIZE..(i + 1) * VEC3_SIZE]);
+            result[col + VEC3_SIZE..col + VEC4_SIZE].clone_from_slice(&factor.to_ne_bytes());
+        }
 
         // todo: fn to convert Vec3 to byte array?
         let mut color_buf = [0; VEC4_SIZE];
@@ -226,26 +356,79 @@ impl Instance {
         color_buf[2 * F32_SIZE..3 * F32_SIZE].clone_from_slice(&self.color.z.to_ne_bytes());
         color_buf[3 * F32_SIZE..4 * F32_SIZE].clone_from_slice(&self.opacity.to_ne_bytes());
 
-        result[MAT4_SIZE + MAT3_SIZE..INSTANCE_SIZE - F32_SIZE].clone_from_slice(&color_buf);
+        result[INSTANCE_COLOR_START..INSTANCE_PROPS_START].clone_from_slice(&color_buf);
         // todo
-        // result[MAT4_SIZE + MAT3_SIZE..INSTANCE_SIZE - F32_SIZE]
+        // result[INSTANCE_COLOR_START..INSTANCE_SIZE - F32_SIZE]
         //     // .clone_from_slice(&self.color.to_bytes_uniform());
         //     .clone_from_slice(&self.color.to_bytes());
 
-        result[INSTANCE_SIZE - F32_SIZE..INSTANCE_SIZE]
-            .clone_from_slice(&self.shinyness.to_ne_bytes());
+        let props = INSTANCE_PROPS_START;
+
+        result[props..props + F32_SIZE].clone_from_slice(&self.shinyness.to_ne_bytes());
+        result[props + F32_SIZE..props + 2 * F32_SIZE]
+            .clone_from_slice(&self.reflectivity.to_ne_bytes());
+
+        let palette_i = self.palette_i.map(|i| i as i32).unwrap_or(-1);
+        result[props + 2 * F32_SIZE..props + 3 * F32_SIZE]
+            .clone_from_slice(&palette_i.to_ne_bytes());
+
+        let material = self.material.map(|i| i as i32).unwrap_or(-1);
+        result[props + 3 * F32_SIZE..props + 4 * F32_SIZE]
+            .clone_from_slice(&material.to_ne_bytes());
+
+        let uv = INSTANCE_UV_START;
+        result[uv..uv + F32_SIZE].clone_from_slice(&self.uv_offset.0.to_ne_bytes());
+        result[uv + F32_SIZE..uv + 2 * F32_SIZE].clone_from_slice(&self.uv_offset.1.to_ne_bytes());
+        result[uv + 2 * F32_SIZE..uv + 3 * F32_SIZE]
+            .clone_from_slice(&self.uv_scale.0.to_ne_bytes());
+        result[uv + 3 * F32_SIZE..uv + VEC4_SIZE].clone_from_slice(&self.uv_scale.1.to_ne_bytes());
+
+        result[uv + VEC4_SIZE..INSTANCE_SIZE]
+            .clone_from_slice(&self.texture_tint.to_bytes_vertex());
 
         result
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+/// Which triangles of a mesh aren't drawn. Triangles whose vertices are counter-clockwise on
+/// screen face the camera.
+pub enum FaceCulling {
+    /// Triangles facing away from the camera are culled.
+    #[default]
+    Back,
+    /// Triangles facing the camera are culled. Use this for meshes with inverted (clockwise)
+    /// winding, eg from some importers, which otherwise render inside-out or vanish.
+    Front,
+    /// All triangles are drawn, eg for open or double-sided meshes.
+    None,
+}
+
+impl FaceCulling {
+    pub(crate) fn face(self) -> Option<wgpu::Face> {
+        match self {
+            Self::Back => Some(wgpu::Face::Back),
+            Self::Front => Some(wgpu::Face::Front),
+            Self::None => None,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Mesh {
     pub vertices: Vec<Vertex>,
     /// These indices are relative to 0 for this mesh. When adding to a global index
     /// buffer, we offset them by previous meshes' vertex counts.
     pub indices: Vec<usize>,
-    pub material: usize,
+    /// The material of entities using this mesh that don't set `Entity::material`; see
+    /// `Scene::materials`. Changes take effect when entities are rebuilt.
+    pub material: Option<usize>,
+    /// If set, entities using this mesh are rendered as impostors, sized from its bounds, instead
+    /// of as triangles.
+    pub impostor: Option<Impostor>,
+    /// This may be changed at any time; it applies from the next frame. Shadows are cast from
+    /// both sides regardless.
+    pub culling: FaceCulling,
 }
 
 /// Represents an entity in the world. This is not fundamental to the WGPU system.
@@ -258,9 +441,60 @@ pub struct Entity {
     /// Rotation, relative to up.
     pub orientation: Quaternion,
     pub scale: f32, // 1.0 is original.
+    /// Interpreted according to `ColorSettings::input_space`. See the `color` module for
+    /// converting from 8-bit sRGB, eg from a color picker.
     pub color: (f32, f32, f32),
+    /// If set, this entity's color comes from this value instead, using `Scene::scalar_colormap`
+    /// over `Scene::scalar_range`, eg to color atoms by charge. As with `color`, this is ignored
+    /// if `palette_i` is set.
+    pub scalar: Option<f32>,
     pub opacity: f32,
     pub shinyness: f32, // 0 to 1.
+    /// How much this entity reflects its surroundings, from 0 to 1. Reflections are sampled from
+    /// the nearest environment probe in range; see `Scene::env_probes`.
+    pub reflectivity: f32,
+    /// If set, this entity's color and opacity come from this index in the active palette,
+    /// instead of `color` and `opacity`; see `Scene::palettes`.
+    pub palette_i: Option<usize>,
+    /// If set, this entity's color is multiplied by this material's texture, using its mesh's
+    /// texture coordinates; see `Scene::materials`. If not, its mesh's material is used, if any.
+    pub material: Option<usize>,
+    /// Applied to its mesh's texture coordinates, as `uv * uv_scale + uv_offset`, eg to show one
+    /// frame of a texture atlas, or to scroll a texture by changing the offset each frame, with
+    /// the default repeating `SamplerSettings`. Defaults to (0, 0), and (1, 1).
+    pub uv_offset: (f32, f32),
+    pub uv_scale: (f32, f32),
+    /// Multiplies this entity's material texture, eg to recolor an atlas frame without another
+    /// material. Unlike `color`, this applies when the color comes from a palette too. It's
+    /// interpreted like `color`. Defaults to white.
+    pub texture_tint: (f32, f32, f32),
+    /// Scales how much this entity is lit by each lighting component.
+    pub lighting_factors: LightingFactors,
+    /// Debug shapes drawn over this entity, in addition to those in `DebugSettings::shapes`.
+    pub debug: DebugShapes,
+    /// Static entities' instances are built once, and reused when entities are rebuilt, which
+    /// reduces CPU time for scenes that are mostly static. Changes to static entities, including
+    /// their groups, don't take effect until `EngineUpdates::static_entities` is set.
+    pub is_static: bool,
+    /// If false, `Scene::raycast` passes through this entity, eg for gizmos or helper geometry
+    /// that overlaps entities the user selects. Defaults to true.
+    pub pickable: bool,
+    /// If set, only this range of its mesh's indices is drawn, as (start, count), eg to show one
+    /// section of a large combined mesh. Both should be multiples of 3. Raycasts only hit
+    /// triangles in the range. Changes take effect when entities are rebuilt, eg with
+    /// `EngineUpdates::entities`. See the `sub_range` module.
+    pub index_range: Option<(u32, u32)>,
+    /// The layer this entity is drawn in, eg `Overlay` for gizmos drawn over the scene. Changes
+    /// take effect when entities are rebuilt. See the `layers` module.
+    pub layer: RenderLayer,
+    /// Controls draw order, eg for overlapping coplanar entities, such as decals or stacked 2D
+    /// shapes, where the last drawn wins. Entities with lower keys are drawn first; those with
+    /// equal keys are drawn in the order they appear in `Scene::entities`. Entities are drawn one
+    /// mesh at a time, so this applies among entities using the same mesh, and across meshes only
+    /// for entities drawn individually, ie those with an `index_range`, or in the background or
+    /// overlay layers. A mesh's static entities are drawn before its others. Changes take effect
+    /// when entities are rebuilt. Defaults to 0.
+    pub sort_key: i32,
 }
 
 impl Entity {
@@ -278,12 +512,192 @@ impl Entity {
             orientation,
             scale,
             color,
+            scalar: None,
             opacity: 1.,
             shinyness,
+            reflectivity: 0.,
+            palette_i: None,
+            material: None,
+            uv_offset: (0., 0.),
+            uv_scale: (1., 1.),
+            texture_tint: (1., 1., 1.),
+            lighting_factors: Default::default(),
+            debug: Default::default(),
+            is_static: false,
+            pickable: true,
+            index_range: None,
+            layer: RenderLayer::World,
+            sort_key: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Multipliers of each lighting component for an entity, eg to dim or highlight it without
+/// changing the scene's lighting. 1 leaves a component unchanged.
+pub struct LightingFactors {
+    pub ambient: f32,
+    pub diffuse: f32,
+    pub specular: f32,
+}
+
+impl Default for LightingFactors {
+    fn default() -> Self {
+        Self {
+            ambient: 1.,
+            diffuse: 1.,
+            specular: 1.,
         }
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+/// The spatial part of an entity; used with `Scene::sync_entities`.
+pub struct Transform {
+    pub position: Vec3,
+    pub orientation: Quaternion,
+    pub scale: f32,
+}
+
+impl Transform {
+    /// From a model matrix, eg a glTF node's transform. Entities only support uniform scale, so
+    /// this uses the average of the matrix's scale along each axis. See `math::decompose`.
+    pub fn from_matrix(mat: &Mat4) -> Self {
+        let (position, orientation, scale) = math::decompose(mat);
+
+        Self {
+            position,
+            orientation,
+            scale: (scale.x + scale.y + scale.z) / 3.,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The appearance of an entity; used with `Scene::sync_entities`. Create this with `new`, or
+/// `Default`, and set fields as required; more may be added.
+#[non_exhaustive]
+pub struct RenderProps {
+    /// Index into `Scene::meshes`.
+    pub mesh: usize,
+    pub color: (f32, f32, f32),
+    /// See `Entity::scalar`.
+    pub scalar: Option<f32>,
+    pub opacity: f32,
+    pub shinyness: f32,
+    pub reflectivity: f32,
+    pub palette_i: Option<usize>,
+    pub material: Option<usize>,
+    /// See `Entity::uv_offset`.
+    pub uv_offset: (f32, f32),
+    pub uv_scale: (f32, f32),
+    pub texture_tint: (f32, f32, f32),
+    pub lighting_factors: LightingFactors,
+    /// See `Entity::index_range`.
+    pub index_range: Option<(u32, u32)>,
+    /// See `Entity::layer`.
+    pub layer: RenderLayer,
+    /// See `Entity::sort_key`.
+    pub sort_key: i32,
+}
+
+impl Default for RenderProps {
+    fn default() -> Self {
+        Self {
+            mesh: 0,
+            color: (1., 1., 1.),
+            scalar: None,
+            opacity: 1.,
+            shinyness: 0.,
+            reflectivity: 0.,
+            palette_i: None,
+            material: None,
+            uv_offset: (0., 0.),
+            uv_scale: (1., 1.),
+            texture_tint: (1., 1., 1.),
+            lighting_factors: Default::default(),
+            index_range: None,
+            layer: RenderLayer::World,
+            sort_key: 0,
+        }
+    }
+}
+
+impl RenderProps {
+    /// An opaque, untextured mesh, with other properties at their defaults.
+    pub fn new(mesh: usize, color: (f32, f32, f32)) -> Self {
+        Self {
+            mesh,
+            color,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// A named set of colors, eg a color scheme for molecules. Entities reference colors by index,
+/// using `Entity::palette_i`, so switching or editing palettes recolors them without rebuilding
+/// instances.
+pub struct Palette {
+    pub name: String,
+    /// RGBA.
+    pub colors: Vec<[f32; 4]>,
+}
+
+impl Palette {
+    pub fn new(name: &str, colors: Vec<[f32; 4]>) -> Self {
+        Self {
+            name: name.to_owned(),
+            colors,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// A named set of entities that can be moved, hidden, or tinted together, eg a chain in a
+/// molecule. Group effects are applied when building instances, so they don't modify the entities
+/// themselves. Set `EngineUpdates::entities` after changing a group.
+pub struct EntityGroup {
+    pub name: String,
+    /// Indices into `Scene::entities`.
+    pub entities: Vec<usize>,
+    /// Applied to member entities after their own position; ie a translation in world space.
+    pub position: Vec3,
+    /// Applied to member entities' positions and orientations, rotating them around the origin.
+    pub orientation: Quaternion,
+    pub visible: bool,
+    /// Multiplies the color of member entities.
+    pub tint: (f32, f32, f32),
+    /// If set, overrides `Mesh::impostor` for member entities.
+    pub impostor: Option<Impostor>,
+}
+
+impl EntityGroup {
+    pub fn new(name: &str, entities: Vec<usize>) -> Self {
+        Self {
+            name: name.to_owned(),
+            entities,
+            position: Vec3::new_zero(),
+            orientation: Quaternion::new_identity(),
+            visible: true,
+            tint: (1., 1., 1.),
+            impostor: None,
+        }
+    }
+
+    /// Apply this group's transform and tint to an entity.
+    pub fn apply(&self, entity: &mut Entity) {
+        entity.position = self.orientation.rotate_vec(entity.position) + self.position;
+        entity.orientation = self.orientation * entity.orientation;
+
+        entity.color = (
+            entity.color.0 * self.tint.0,
+            entity.color.1 * self.tint.1,
+            entity.color.2 * self.tint.2,
+        );
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 /// Default controls. Provides easy defaults. For maximum flexibility, choose `None`,
 /// and implement controls in the `event_handler` function.
@@ -296,9 +710,10 @@ pub enum ControlScheme {
     /// FPS-style camera. Ie, no Z-axis roll, no up/down movement, and can't look up past TAU/4.
     /// todo: Unimplemented
     Fps,
-    /// The mouse rotates the camera around a fixed point.
-    /// todo: inner Vec of the point?
-    /// todo: Unimplemented
+    /// Orbit controls, as in CAD and visualization tools. Dragging with the free look button
+    /// orbits the camera around `Scene::arc_center`, dragging with the pan button moves the camera
+    /// and center together, and scrolling zooms towards the center. Keys are passed to the
+    /// application.
     Arc,
 }
 
@@ -308,15 +723,272 @@ impl Default for ControlScheme {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+/// The length unit of scene coordinates.
+pub enum Units {
+    #[default]
+    Meters,
+    Millimeters,
+    Nanometers,
+    Angstroms,
+}
+
+impl Units {
+    /// The length of one unit, in meters.
+    pub fn to_meters(self) -> f32 {
+        match self {
+            Self::Meters => 1.,
+            Self::Millimeters => 1e-3,
+            Self::Nanometers => 1e-9,
+            Self::Angstroms => 1e-10,
+        }
+    }
+
+    /// The unit's symbol, eg for labels.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Self::Meters => "m",
+            Self::Millimeters => "mm",
+            Self::Nanometers => "nm",
+            Self::Angstroms => "Å",
+        }
+    }
+
+    /// A typical scene extent in this unit, eg a room in meters, or a small molecule in angstroms.
+    fn typical_extent(self) -> f32 {
+        match self {
+            Self::Meters => 10.,
+            Self::Millimeters => 100.,
+            Self::Nanometers => 5.,
+            Self::Angstroms => 20.,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+/// How colors are interpreted.
+pub enum ColorSpace {
+    /// Colors are used in lighting as-is.
+    #[default]
+    Linear,
+    /// Colors are converted from sRGB to linear before lighting. This matches colors picked in
+    /// most image editors and color pickers.
+    Srgb,
+}
+
+#[derive(Clone, Debug)]
+/// Output color controls for lit meshes. Set `EngineUpdates::color` after changing these.
+pub struct ColorSettings {
+    /// Multiplies lit colors.
+    pub exposure: f32,
+    /// Lit colors are raised to the power of 1 / `gamma`, after exposure. Values above 1
+    /// brighten midtones; 1 leaves them unchanged. This is in addition to the surface's sRGB
+    /// encoding.
+    pub gamma: f32,
+    /// How entity, palette, ambient, and light colors are interpreted.
+    pub input_space: ColorSpace,
+}
+
+impl Default for ColorSettings {
+    fn default() -> Self {
+        Self {
+            exposure: 1.,
+            gamma: 1.,
+            input_space: Default::default(),
+        }
+    }
+}
+
+impl ColorSettings {
+    /// `encode_srgb` is set if the surface format isn't sRGB, so we encode output in the shader.
+    /// `cel_bands` is from `ToonSettings`.
+    pub(crate) fn to_bytes(&self, encode_srgb: bool, cel_bands: u32) -> [u8; COLOR_SETTINGS_SIZE] {
+        let mut result = [0; COLOR_SETTINGS_SIZE];
+
+        result[0..F32_SIZE].clone_from_slice(&self.exposure.to_ne_bytes());
+        result[F32_SIZE..2 * F32_SIZE].clone_from_slice(&self.gamma.to_ne_bytes());
+
+        let srgb_input = (self.input_space == ColorSpace::Srgb) as u32;
+        result[2 * F32_SIZE..3 * F32_SIZE].clone_from_slice(&srgb_input.to_ne_bytes());
+        result[3 * F32_SIZE..4 * F32_SIZE].clone_from_slice(&(encode_srgb as u32).to_ne_bytes());
+        result[4 * F32_SIZE..5 * F32_SIZE].clone_from_slice(&cel_bands.to_ne_bytes());
+
+        result
+    }
+
+    /// Convert an unlit color, eg the background, for writing directly to the surface. As with lit
+    /// colors, it's converted from the input space, and encoded if the surface isn't sRGB. It
+    /// isn't affected by exposure or gamma.
+    pub(crate) fn unlit_color(&self, color: [f32; 3], encode_srgb: bool) -> [f32; 3] {
+        let mut c = color;
+
+        if self.input_space == ColorSpace::Srgb {
+            c = c.map(srgb_to_linear);
+        }
+        if encode_srgb {
+            c = c.map(|v| linear_to_srgb(v.clamp(0., 1.)));
+        }
+
+        c
+    }
+
+    /// The clear color for `background`; see `unlit_color`.
+    pub(crate) fn clear_color(
+        &self,
+        background: (f32, f32, f32),
+        encode_srgb: bool,
+    ) -> wgpu::Color {
+        let c = self.unlit_color([background.0, background.1, background.2], encode_srgb);
+
+        wgpu::Color {
+            r: c[0] as f64,
+            g: c[1] as f64,
+            b: c[2] as f64,
+            a: 1.0,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Scene {
     pub meshes: Vec<Mesh>,
     pub entities: Vec<Entity>,
+    /// Instances uploaded directly, bypassing `entities`, for each mesh. Set with
+    /// `set_instances_raw`.
+    pub raw_instances: Vec<Vec<InstanceRaw>>,
+    /// Named sets of entities, that can be transformed, hidden, or tinted together.
+    pub groups: Vec<EntityGroup>,
+    /// Colors entities can reference by index. Set `EngineUpdates::palette` after changing the
+    /// active palette, or its colors.
+    pub palettes: Vec<Palette>,
+    /// Index into `palettes`.
+    pub active_palette: usize,
+    /// Textures entities can reference by index, with `Entity::material`. These are packed into
+    /// one texture array; set `EngineUpdates::materials` after changing them.
+    pub materials: Vec<Material>,
+    /// Converts `Entity::scalar` to colors. Entities are recolored when this, or `scalar_range`,
+    /// changes.
+    pub scalar_colormap: Colormap,
+    /// Scalars from the first value to the second span the colormap; values outside are
+    /// clamped. The first may be larger, to reverse it. Defaults to 0 to 1.
+    pub scalar_range: (f32, f32),
     pub camera: Camera,
+    /// The point the camera orbits with `ControlScheme::Arc`, in world space. Panning moves it with
+    /// the camera. Set it eg to a selected entity's position; the camera isn't turned to face it.
+    pub arc_center: Vec3,
     pub lighting: Lighting,
+    /// Exposure, gamma, and how colors are interpreted.
+    pub color: ColorSettings,
+    /// Outlines and cel shading, for a toon-like style.
+    pub toon: ToonSettings,
+    /// Interpreted according to `color.input_space`, as with entity colors. See the `color`
+    /// module for converting from 8-bit sRGB. This may be changed at any time; use
+    /// `animate_background` to transition it smoothly.
     pub background_color: (f32, f32, f32),
+    /// If set, draw a vertical gradient, with an optional vignette, behind the scene instead of
+    /// `background_color`. Environment probes still capture `background_color`.
+    pub background_gradient: Option<BackgroundGradient>,
+    /// If set, draw a ground plane that receives shadows, but is otherwise transparent or flat.
+    pub ground: Option<GroundPlane>,
+    /// Depth settings for each render layer; see `Entity::layer`, and the `layers` module.
+    pub layers: RenderLayers,
     pub window_title: String,
+    /// The window's initial size. For its current size, and whether it's focused or minimized,
+    /// see `window_state`.
     pub window_size: (f32, f32),
+    /// The window's focus, visibility, position, and size, and changes to them since the previous
+    /// frame, eg to pause a simulation while the window is in the background. Set by the engine.
+    pub window_state: WindowState,
+    /// The length unit of scene coordinates. With `scale_hint`, this scales camera speed, default
+    /// clipping planes, and light falloff; see `scale_factor`.
+    pub units: Units,
+    /// The typical extent of the scene's contents, in `units`, eg 50 for a protein in angstroms.
+    /// If `None`, we use a typical extent for `units`.
+    pub scale_hint: Option<f32>,
+    /// Compute shaders run each frame, before or after the render pass. Requires the `compute`
+    /// feature.
+    #[cfg(feature = "compute")]
+    pub compute_passes: Vec<ComputePass>,
+    /// WGSL and bind groups added to the main shader, eg for custom surface effects that need
+    /// textures or buffers the engine doesn't provide.
+    pub shader_extension: Option<ShaderExtension>,
+    /// Updated by the engine each frame; changes made by the application are ignored.
+    pub frame_stats: FrameStats,
+    /// Debug visualizations, eg light gizmos.
+    pub debug: DebugSettings,
+    /// Lines, spheres, and text drawn for a single frame; cleared after rendering.
+    pub debug_draw: DebugDraw,
+    /// If set, show an intermediate render target, eg the depth buffer, or save it to a file. See
+    /// the `debug_view` module.
+    pub debug_view: Option<DebugView>,
+    /// Text and shapes, eg labels and HUD markers, rendered crisply at any size using signed
+    /// distance fields.
+    pub sdf_elements: Vec<SdfElement>,
+    /// Text labels attached to entities, and placed by the engine each frame. Add them with
+    /// `attach_label`. See the `labels` module.
+    pub labels: Vec<Label>,
+    /// Images, text, and rectangles drawn in screen space over the 3D scene, eg crosshairs.
+    pub hud: Hud,
+    /// Points reflective entities sample their surroundings from. Each is captured on demand, by
+    /// setting `EngineUpdates::env_probes`.
+    pub env_probes: Vec<EnvProbe>,
+    /// Used by spatial queries, eg `raycast` and `overlapping_pairs`.
+    pub spatial_cache: SpatialCache,
+    /// The application's handle for each entity, if using `sync_entities`. Indices correspond to
+    /// `entities`.
+    pub entity_handles: Vec<u64>,
+    /// Keyframe animation of the camera and point lights, played back by the engine. See
+    /// `timeline_ui` for a widget to control it.
+    pub timeline: Timeline,
+    /// Point lights moved along paths by the engine, eg circling the scene. See the `light_path`
+    /// module.
+    pub light_paths: Vec<LightPath>,
+    /// Entities that light their surroundings, eg lamps, using point lights placed by the engine.
+    /// See the `area_light` module.
+    pub area_lights: Vec<AreaLight>,
+    /// Color, scale, and opacity transitions of entities, and of the background color, played back
+    /// by the engine. Start them with eg `animate_color`.
+    pub transitions: Transitions,
+    /// Distance, angle, and dihedral measurements, drawn over the scene.
+    pub measure: MeasureTool,
+    /// If set, clip geometry in front of this plane, and draw it as a draggable widget.
+    pub slice_plane: Option<SlicePlane>,
+    /// The fraction of mesh data uploaded to the GPU, from 0 to 1, while large vertex and index
+    /// buffers are uploaded over several frames; `None` otherwise. Entities' bounding boxes are
+    /// drawn in place of their meshes until the upload completes. Set by the engine.
+    pub upload_progress: Option<f32>,
+    /// Progress, finished assets, and failures of loads started with
+    /// `EngineUpdates::load_assets`, since the previous frame. Set by the engine.
+    pub load_events: Vec<LoadEvent>,
+    /// The number of loads that haven't finished or failed. Set by the engine.
+    pub loads_pending: usize,
+    /// Key chords the application registers, eg Ctrl+S, and those triggered this frame.
+    pub shortcuts: Shortcuts,
+    /// The result of the last `EngineUpdates::gpu_pick`; `None` if its ray hit nothing.
+    pub gpu_pick_hit: Option<GpuHit>,
+    /// Run each time entity instances are built, after entities are processed, and before the
+    /// instances are uploaded, eg to wrap positions into a periodic box. While this is set,
+    /// changing entities rebuilds all instances, so this sees the whole array.
+    pub pre_upload: Option<fn(&mut PackedInstances, &Scene)>,
+    /// If set, the camera orbits a point automatically. Set with `EngineUpdates::turntable`.
+    pub turntable: Option<Turntable>,
+    /// If set, render red/cyan anaglyph stereo, for viewing with 3D glasses.
+    pub anaglyph: Option<Anaglyph>,
+    /// Render the scene again from each of these cameras, to a rectangle of the 3D viewport, eg
+    /// for picture-in-picture, or a rear-view mirror. See the `views` module.
+    pub extra_views: Vec<ExtraView>,
+    /// If set, render the scene again from a second camera, eg an overhead minimap, in a corner
+    /// of the 3D viewport. See the `inset` module.
+    pub inset: Option<Inset>,
+    /// Render the scene each frame, or only when it changes. See the `redraw` module.
+    pub redraw_mode: RedrawMode,
+    /// Errors from the last build of `shader_extension` and `compute_passes`, eg requiring a
+    /// different `SHADER_INTERFACE_VERSION`. Shaders with errors aren't used.
+    pub shader_errors: Vec<String>,
+    /// Errors from the last upload of entities and lights, if they need larger buffers than the
+    /// device allows; see `GraphicsSettings::buffer_size_hint`. Data with errors isn't uploaded,
+    /// so the previous entities or lights are drawn until it fits.
+    pub buffer_errors: Vec<String>,
 }
 
 impl Default for Scene {
@@ -324,12 +996,310 @@ impl Default for Scene {
         Self {
             meshes: Vec::new(),
             entities: Vec::new(),
+            raw_instances: Vec::new(),
+            groups: Vec::new(),
+            palettes: Vec::new(),
+            active_palette: 0,
+            materials: Vec::new(),
+            scalar_colormap: Default::default(),
+            scalar_range: (0., 1.),
             camera: Default::default(),
+            arc_center: Vec3::new_zero(),
             lighting: Default::default(),
+            color: Default::default(),
+            toon: Default::default(),
             // todo: Consider a separate window struct.
             background_color: (0.7, 0.7, 0.7),
+            background_gradient: None,
+            ground: None,
+            layers: Default::default(),
             window_title: "(Window title here)".to_owned(),
             window_size: (900., 600.),
+            window_state: Default::default(),
+            units: Default::default(),
+            scale_hint: None,
+            #[cfg(feature = "compute")]
+            compute_passes: Vec::new(),
+            shader_extension: None,
+            frame_stats: Default::default(),
+            debug: Default::default(),
+            debug_draw: Default::default(),
+            debug_view: None,
+            sdf_elements: Vec::new(),
+            labels: Vec::new(),
+            hud: Default::default(),
+            env_probes: Vec::new(),
+            spatial_cache: Default::default(),
+            entity_handles: Vec::new(),
+            timeline: Default::default(),
+            light_paths: Vec::new(),
+            area_lights: Vec::new(),
+            transitions: Default::default(),
+            measure: Default::default(),
+            slice_plane: None,
+            upload_progress: None,
+            load_events: Vec::new(),
+            loads_pending: 0,
+            shortcuts: Default::default(),
+            gpu_pick_hit: None,
+            pre_upload: None,
+            turntable: None,
+            anaglyph: None,
+            extra_views: Vec::new(),
+            inset: None,
+            redraw_mode: Default::default(),
+            shader_errors: Vec::new(),
+            buffer_errors: Vec::new(),
+        }
+    }
+}
+
+impl Scene {
+    /// How much larger the scene is than the engine's defaults are tuned for; 1 for a 10m scene
+    /// in meters. Camera movement (`InputSettings::move_sens`) and the distance lights reach are
+    /// multiplied by this, as are the camera's clipping planes if left at their defaults.
+    pub fn scale_factor(&self) -> f32 {
+        self.scale_hint.unwrap_or(self.units.typical_extent()) / REFERENCE_EXTENT
+    }
+
+    /// Scale the camera's clipping planes to the scene, if they're at their defaults. We run
+    /// this once, at startup.
+    pub(crate) fn scale_default_camera(&mut self) {
+        let default = Camera::default();
+        if self.camera.near != default.near || self.camera.far != default.far {
+            return;
+        }
+
+        let scale = self.scale_factor();
+        self.camera.near *= scale;
+        self.camera.far *= scale;
+    }
+
+    /// Replace a mesh, eg with a higher resolution version of a preview, keeping entities that
+    /// reference it valid. Only this mesh is re-uploaded; its vertex and index counts may change.
+    /// Return the result from your handler, or combine it with other updates. Panics if `mesh` is
+    /// out of bounds.
+    pub fn replace_mesh(&mut self, mesh: usize, new_mesh: Mesh) -> EngineUpdates {
+        self.meshes[mesh] = new_mesh;
+        // Spatial queries made before the update is applied use the new mesh.
+        self.spatial_cache.invalidate(&[mesh]);
+
+        EngineUpdates {
+            replaced_meshes: vec![mesh],
+            ..Default::default()
+        }
+    }
+
+    /// Replace the raw instances of a mesh, eg with packed positions and colors from a
+    /// simulation. These bypass `entities`, and are uploaded in their own buffer, so this avoids
+    /// building an entity for each, and doesn't rebuild entity instances. Return the result from
+    /// your handler, or combine it with other updates.
+    pub fn set_instances_raw(&mut self, mesh: usize, instances: &[InstanceRaw]) -> EngineUpdates {
+        if self.raw_instances.len() <= mesh {
+            self.raw_instances.resize(mesh + 1, Vec::new());
+        }
+
+        self.raw_instances[mesh].clear();
+        self.raw_instances[mesh].extend_from_slice(instances);
+
+        EngineUpdates {
+            raw_instances: true,
+            ..Default::default()
+        }
+    }
+
+    /// The index of a palette, by name; eg to set `active_palette`.
+    pub fn palette_i(&self, name: &str) -> Option<usize> {
+        self.palettes.iter().position(|p| p.name == name)
+    }
+
+    /// The active palette's colors, for the GPU.
+    pub(crate) fn palette_bytes(&self) -> Vec<u8> {
+        let colors = match self.palettes.get(self.active_palette) {
+            Some(p) => &p.colors[..],
+            None => &[],
+        };
+
+        let mut result = Vec::with_capacity(colors.len().max(1) * VEC4_SIZE);
+        for color in colors {
+            for v in color {
+                result.extend_from_slice(&v.to_ne_bytes());
+            }
+        }
+
+        // Storage bindings can't be empty.
+        if result.is_empty() {
+            result.extend_from_slice(&[0; VEC4_SIZE]);
+        }
+
+        result
+    }
+
+    /// Find a group by name.
+    pub fn group(&self, name: &str) -> Option<&EntityGroup> {
+        self.groups.iter().find(|g| g.name == name)
+    }
+
+    /// Find a group by name, for modification.
+    pub fn group_mut(&mut self, name: &str) -> Option<&mut EntityGroup> {
+        self.groups.iter_mut().find(|g| g.name == name)
+    }
+
+    /// Update entities from an external store, eg an ECS, identifying each by a handle (eg an
+    /// ECS entity's bits). This compares against the current entities, and returns updates
+    /// covering only what changed; return it from your handler, or combine it with other
+    /// updates. If only transforms and colors changed, only those instances are rewritten on the
+    /// GPU; adding or removing handles, or changing meshes, rebuilds all instances.
+    ///
+    /// This manages all of `entities`; entities not in `items` are removed.
+    pub fn sync_entities(
+        &mut self,
+        items: impl IntoIterator<Item = (u64, Transform, RenderProps)>,
+    ) -> EngineUpdates {
+        let mut result = EngineUpdates::default();
+
+        let items: Vec<_> = items.into_iter().collect();
+
+        let same_handles = items.len() == self.entities.len()
+            && self.entity_handles.len() == self.entities.len()
+            && items
+                .iter()
+                .zip(&self.entity_handles)
+                .all(|((handle, _, _), h)| handle == h);
+
+        let make_entity = |transform: &Transform, props: &RenderProps| Entity {
+            mesh: props.mesh,
+            position: transform.position,
+            orientation: transform.orientation,
+            scale: transform.scale,
+            color: props.color,
+            scalar: props.scalar,
+            opacity: props.opacity,
+            shinyness: props.shinyness,
+            reflectivity: props.reflectivity,
+            palette_i: props.palette_i,
+            material: props.material,
+            uv_offset: props.uv_offset,
+            uv_scale: props.uv_scale,
+            texture_tint: props.texture_tint,
+            lighting_factors: props.lighting_factors,
+            debug: Default::default(),
+            is_static: false,
+            pickable: true,
+            index_range: props.index_range,
+            layer: props.layer,
+            sort_key: props.sort_key,
+        };
+
+        if !same_handles {
+            // Keep debug, static, and picking settings for handles we already have.
+            let prev: HashMap<u64, (DebugShapes, bool, bool)> = self
+                .entity_handles
+                .iter()
+                .zip(&self.entities)
+                .map(|(h, e)| (*h, (e.debug, e.is_static, e.pickable)))
+                .collect();
+
+            self.entities = items
+                .iter()
+                .map(|(handle, transform, props)| {
+                    let mut entity = make_entity(transform, props);
+                    if let Some(&(debug, is_static, pickable)) = prev.get(handle) {
+                        (entity.debug, entity.is_static, entity.pickable) =
+                            (debug, is_static, pickable);
+                    }
+                    entity
+                })
+                .collect();
+            self.entity_handles = items.iter().map(|(h, _, _)| *h).collect();
+
+            // Static entities' indices may have changed.
+            result.entities = true;
+            result.static_entities = true;
+            return result;
+        }
+
+        for (i, (_, transform, props)) in items.iter().enumerate() {
+            let entity = &mut self.entities[i];
+            let mut updated = make_entity(transform, props);
+            updated.debug = entity.debug;
+            updated.is_static = entity.is_static;
+            updated.pickable = entity.pickable;
+
+            // Entities drawing part of their mesh, or in other layers, are drawn separately. Sort
+            // keys change instance order.
+            if updated.mesh != entity.mesh
+                || updated.index_range != entity.index_range
+                || updated.layer != entity.layer
+                || updated.sort_key != entity.sort_key
+            {
+                if entity.is_static {
+                    result.static_entities = true;
+                } else {
+                    result.entities = true;
+                }
+            } else if updated.position != entity.position
+                || !math::quat_eq(updated.orientation, entity.orientation)
+                || updated.scale != entity.scale
+                || updated.color != entity.color
+                || updated.scalar != entity.scalar
+                || updated.opacity != entity.opacity
+                || updated.shinyness != entity.shinyness
+                || updated.reflectivity != entity.reflectivity
+                || updated.palette_i != entity.palette_i
+                || updated.material != entity.material
+                || updated.uv_offset != entity.uv_offset
+                || updated.uv_scale != entity.uv_scale
+                || updated.texture_tint != entity.texture_tint
+                || updated.lighting_factors != entity.lighting_factors
+            {
+                result.changed_entities.push(i);
+            }
+
+            *entity = updated;
+        }
+
+        result
+    }
+
+    /// An entity, with transforms of any groups it's in applied. Returns `None` if it's out of
+    /// bounds, or hidden by a group.
+    pub fn entity_in_world(&self, i: usize) -> Option<Entity> {
+        let mut result = self.entities.get(i)?.clone();
+        if let Some(scalar) = result.scalar {
+            result.color = self.scalar_color(scalar);
+        }
+
+        for group in &self.groups {
+            if !group.entities.contains(&i) {
+                continue;
+            }
+            if !group.visible {
+                return None;
+            }
+            group.apply(&mut result);
+        }
+
+        Some(result)
+    }
+
+    /// The color of an entity with `Entity::scalar` set to `scalar`, in `color.input_space`.
+    pub(crate) fn scalar_color(&self, scalar: f32) -> (f32, f32, f32) {
+        let (min, max) = self.scalar_range;
+        let t = if max != min {
+            (scalar - min) / (max - min)
+        } else {
+            0.
+        };
+
+        let srgb = self.scalar_colormap.color(t);
+        match self.color.input_space {
+            ColorSpace::Linear => color_from_srgb8(srgb),
+            ColorSpace::Srgb => (
+                srgb[0] as f32 / 255.,
+                srgb[1] as f32 / 255.,
+                srgb[2] as f32 / 255.,
+            ),
         }
     }
 }
@@ -337,12 +1307,15 @@ impl Default for Scene {
 #[derive(Clone, Debug)]
 /// These sensitivities are in units (position), or radians (orientation) per second.
 pub struct InputSettings {
+    /// Multiplied by `Scene::scale_factor`.
     pub move_sens: f32,
     pub rotate_sens: f32,
     pub rotate_key_sens: f32,
     /// How much the move speed is multiplied when holding the run key.
     pub run_factor: f32,
     pub initial_controls: ControlScheme,
+    /// Keys and mouse buttons for the `FreeCamera` and `Arc` controls.
+    pub key_bindings: KeyBindings,
 }
 
 impl Default for InputSettings {
@@ -353,6 +1326,7 @@ impl Default for InputSettings {
             rotate_sens: 0.45,
             rotate_key_sens: 1.0,
             run_factor: 5.,
+            key_bindings: Default::default(),
         }
     }
 }
@@ -370,6 +1344,13 @@ pub enum UiLayout {
 pub struct UiSettings {
     pub layout: UiLayout,
     pub icon_path: Option<String>,
+    /// If set, lock the 3D viewport to this aspect ratio (width / height), eg 16. / 9., with black
+    /// bars filling the rest of the space the GUI leaves. See the `letterbox` module.
+    pub viewport_aspect: Option<f32>,
+    /// If set, listen for remote control connections on this address, eg "127.0.0.1:7878", when
+    /// the engine starts. Requires the `remote` feature. See the `remote` module.
+    #[cfg(feature = "remote")]
+    pub remote_addr: Option<String>,
 }
 
 impl Default for UiSettings {
@@ -377,6 +1358,147 @@ impl Default for UiSettings {
         Self {
             layout: UiLayout::Left,
             icon_path: None,
+            viewport_aspect: None,
+            #[cfg(feature = "remote")]
+            remote_addr: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+/// How frames are presented to the window.
+pub enum PresentMode {
+    /// Wait for the display's vertical blank, limiting the frame rate to its refresh rate. This
+    /// prevents tearing, and is supported everywhere.
+    #[default]
+    Vsync,
+    /// Present frames as soon as they're rendered, if supported. This reduces latency, but may
+    /// tear. Falls back to `Vsync` if the surface supports neither mailbox nor immediate modes.
+    NoVsync,
+}
+
+impl PresentMode {
+    pub(crate) fn to_wgpu(self) -> wgpu::PresentMode {
+        match self {
+            Self::Vsync => wgpu::PresentMode::Fifo,
+            Self::NoVsync => wgpu::PresentMode::AutoNoVsync,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+/// The precision of the main depth buffer. See `GraphicsSettings::depth_format`.
+pub enum DepthFormat {
+    /// 32-bit float. This is the most precise, eg for large scenes with a close near plane.
+    #[default]
+    Depth32,
+    /// At least 24 bits. This may use less memory and bandwidth on some GPUs.
+    Depth24,
+}
+
+impl DepthFormat {
+    /// The texture format, with a stencil component if `stencil` is set. 32-bit depth with a
+    /// stencil requires `DEPTH32FLOAT_STENCIL8`; without it, we fall back to 24-bit depth.
+    pub(crate) fn to_wgpu(self, stencil: bool, features: wgpu::Features) -> wgpu::TextureFormat {
+        match (self, stencil) {
+            (Self::Depth32, false) => wgpu::TextureFormat::Depth32Float,
+            (Self::Depth24, false) => wgpu::TextureFormat::Depth24Plus,
+            (Self::Depth32, true) if features.contains(wgpu::Features::DEPTH32FLOAT_STENCIL8) => {
+                wgpu::TextureFormat::Depth32FloatStencil8
+            }
+            (_, true) => wgpu::TextureFormat::Depth24PlusStencil8,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Settings related to the renderer itself, vice the scene. These may be changed while running,
+/// using `EngineUpdates::graphics_settings`; only the affected GPU resources are recreated.
+pub struct GraphicsSettings {
+    /// Measure GPU time spent in the compute, main, and GUI passes, using timestamp queries.
+    /// Results are available in `Scene::frame_stats`. This has no effect if the GPU doesn't
+    /// support timestamp queries.
+    pub gpu_timing: bool,
+    /// Use temporal anti-aliasing: jitter the camera each frame, and blend with previous frames.
+    /// This smooths edges with less cost than MSAA for heavy scenes, but may blur or ghost
+    /// during fast motion.
+    pub taa: bool,
+    /// The maximum number of point lights that cast shadows; see `PointLight::casts_shadow`. Each
+    /// uses a cube shadow map, rendered each frame. 0 disables shadows. Most GPUs limit this to 42,
+    /// due to the texture array layer limit.
+    pub max_shadow_lights: usize,
+    /// The number of threads used to build instances and encode draw calls, for scenes with
+    /// thousands of meshes. Meshes are split into a contiguous range per thread. 0 or 1 does all
+    /// of this on the render thread, which is faster for small scenes.
+    pub render_threads: usize,
+    /// Skip drawing instances hidden behind others, or outside the view, using a depth pyramid
+    /// from the previous frame. This reduces GPU time for dense scenes, at the cost of a compute
+    /// pass each frame. This has no effect if TAA is enabled, or if the GPU doesn't support
+    /// `INDIRECT_FIRST_INSTANCE`.
+    pub occlusion_culling: bool,
+    /// The maximum number of environment probes; see `Scene::env_probes`. Each uses a cube map,
+    /// rendered only when captured. 0 disables reflections.
+    pub max_env_probes: usize,
+    /// Filtering, addressing, and anisotropy used when sampling material textures. These apply to
+    /// all materials.
+    pub material_sampler: SamplerSettings,
+    /// Use deferred shading: draw surface properties to offscreen textures, then light each pixel
+    /// once, in a separate pass. This is faster for scenes with many point lights, eg hundreds,
+    /// since lighting cost doesn't scale with overdraw. Translucent entities are drawn opaque. This
+    /// has no effect if TAA is enabled.
+    pub deferred: bool,
+    /// Assign point lights to clusters of the view each frame, so surfaces are only lit by lights
+    /// near them. This is faster for scenes with many point lights, eg more than a few dozen.
+    /// Lights don't affect surfaces where they'd contribute less than 0.5% of full brightness, so
+    /// lights with lower intensities affect fewer clusters.
+    pub clustered_lighting: bool,
+    pub present_mode: PresentMode,
+    /// With `PresentMode::NoVsync`, start each frame one refresh interval of the window's monitor
+    /// after the previous, instead of rendering as fast as possible. This saves power, and keeps
+    /// frame times even, while avoiding vsync's latency. This has no effect with vsync, or if the
+    /// platform doesn't report the monitor's refresh rate. See `FrameStats::display_hz`.
+    pub frame_pacing: bool,
+    /// Width and height of each shadow map cube face, in pixels. Higher values give sharper
+    /// shadows, but use more memory and GPU time.
+    pub shadow_resolution: u32,
+    /// Vertex and index data larger than this, in bytes, is uploaded over several frames, in
+    /// chunks of this size, so loading large meshes doesn't stall rendering. 0 uploads all data at
+    /// once.
+    pub upload_chunk_size: usize,
+    /// The precision of the main depth buffer. This is set when the renderer is created; changes
+    /// while running have no effect.
+    pub depth_format: DepthFormat,
+    /// Add an 8-bit stencil component to the main depth buffer, cleared to 0 with the main pass
+    /// each frame, eg for masking mirrors or outlines. Engine pipelines don't test or write it.
+    /// This is set when the renderer is created.
+    pub stencil: bool,
+    /// The largest buffer the scene is expected to need, in bytes, eg for millions of instances.
+    /// The GPU device is requested with limits that fit this, up to what it supports; data that
+    /// doesn't fit isn't uploaded, and is reported in `Scene::buffer_errors`. 0 uses wgpu's
+    /// default limits. This is set when the renderer is
+    /// created. See the `limits` module.
+    pub buffer_size_hint: u64,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            gpu_timing: false,
+            taa: false,
+            max_shadow_lights: 0,
+            render_threads: 0,
+            occlusion_culling: false,
+            max_env_probes: 0,
+            material_sampler: Default::default(),
+            deferred: false,
+            clustered_lighting: false,
+            present_mode: Default::default(),
+            frame_pacing: false,
+            shadow_resolution: SHADOW_MAP_SIZE,
+            upload_chunk_size: 32 * 1024 * 1024,
+            depth_format: Default::default(),
+            stencil: false,
+            buffer_size_hint: 0,
         }
     }
 }
@@ -386,7 +1508,78 @@ impl Default for UiSettings {
 #[derive(Default)]
 pub struct EngineUpdates {
     pub meshes: bool,
+    /// Indices of entities whose transform or appearance changed, but not their mesh. Only these
+    /// instances are written to the GPU, which is faster than setting `entities` for large scenes.
+    pub changed_entities: Vec<usize>,
+    /// Indices of meshes whose vertices changed, but not their vertex count or indices, eg for
+    /// cloth or morph target animation. These are written to the existing vertex buffer, which is
+    /// much faster than setting `meshes`, and suitable for use every frame. If a mesh's indices
+    /// reference vertices past its end, none are updated, and an error is printed.
+    pub mesh_vertices: Vec<usize>,
+    /// Indices of meshes replaced with different contents, eg a high resolution version of a
+    /// preview; set by `Scene::replace_mesh`. Only these are written to the GPU.
+    pub replaced_meshes: Vec<usize>,
     pub entities: bool,
+    /// Rebuild instances of static entities (`Entity::is_static`), eg after moving one. Setting
+    /// `entities` rebuilds only non-static ones.
+    pub static_entities: bool,
     pub camera: bool,
+    /// Write color settings, eg after changing `Scene::color`, or `Scene::toon`'s cel shading.
+    pub color: bool,
+    /// The camera's position or orientation changed. Equivalent to `camera`.
+    pub camera_view: bool,
+    /// The camera's projection parameters changed, eg `fov_y` or `aspect`. We update its
+    /// projection matrix, and write only the projection-view matrix.
+    pub camera_projection: bool,
     pub lighting: bool,
+    /// Indices of point lights that changed, eg moved or recolored. Only these lights are written
+    /// to the GPU. If lights are added or removed, or `casts_shadow` changes, set `lighting`.
+    pub changed_lights: Vec<usize>,
+    /// Rebuild compute pipelines and their user buffers, eg after changing `Scene::compute_passes`.
+    #[cfg(feature = "compute")]
+    pub compute: bool,
+    /// Rebuild the main shader and the extension's bind groups, eg after changing
+    /// `Scene::shader_extension`. Uniform contents are written each frame without this.
+    pub shader_extension: bool,
+    /// Rebuild SDF text and shapes, eg after changing `Scene::sdf_elements`.
+    pub sdf_elements: bool,
+    /// Rebuild HUD elements, eg after changing `Scene::hud.elements`.
+    pub hud: bool,
+    /// Upload HUD images, eg after changing `Scene::hud.images`.
+    pub hud_images: bool,
+    /// Write the active palette, eg after changing `Scene::active_palette`, or its colors. This
+    /// recolors entities using it, without rebuilding instances.
+    pub palette: bool,
+    /// Pack and upload material textures, eg after changing `Scene::materials`.
+    pub materials: bool,
+    /// Indices of materials whose images changed, but not their sizes, eg a heatmap updated each
+    /// frame; set by `Scene::set_heatmap`. Only these texture layers are rewritten, which is much
+    /// faster than setting `materials`.
+    pub material_images: Vec<usize>,
+    /// Capture environment probes, eg after changing `Scene::env_probes`, or the scene around
+    /// them. This renders the scene 6 times per probe, so isn't done automatically.
+    pub env_probes: bool,
+    /// Upload raw instances, eg after changing `Scene::raw_instances` directly. Set by
+    /// `Scene::set_instances_raw`.
+    pub raw_instances: bool,
+    /// Apply new graphics settings, eg from a settings menu. Only resources the changed settings
+    /// affect are recreated, eg the swap chain for `present_mode`, or shadow maps for
+    /// `shadow_resolution`. This is applied before the next frame is rendered.
+    pub graphics_settings: Option<GraphicsSettings>,
+    /// Find the nearest entity whose bounding sphere a ray hits, on the GPU, eg for picking in
+    /// scenes with millions of instances. The ray is (origin, direction), in world space. The
+    /// result is written to `Scene::gpu_pick_hit`, after other updates are applied.
+    pub gpu_pick: Option<(Vec3, Vec3)>,
+    /// Start orbiting the camera automatically, replacing any turntable in progress.
+    pub turntable: Option<Turntable>,
+    /// Stop orbiting the camera, leaving it where it is.
+    pub stop_turntable: bool,
+    /// Render part or all of the scene, with `RedrawMode::OnChange`, eg after changing
+    /// `Scene::debug_draw`. Other updates here redraw the whole scene.
+    pub redraw: Option<RedrawRegion>,
+    /// Read and parse these files on worker threads. See `Scene::load_events` for results.
+    pub load_assets: Vec<AssetRequest>,
+    /// Close the window, and end the event loop. `run` then returns the user state, eg so the
+    /// application can save it.
+    pub exit: bool,
 }
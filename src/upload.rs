@@ -0,0 +1,230 @@
+//! Streaming uploads of large vertex and index buffers. Creating a buffer with several hundred MB
+//! of data at once stalls the render thread, so when the packed mesh data is larger than
+//! `GraphicsSettings::upload_chunk_size`, we upload it over several frames instead.
+//!
+//! Each frame, the render thread creates staging buffers, mapped for writing, and sends them to a
+//! worker thread, which copies a chunk of the data into each, and unmaps it. The render thread then
+//! encodes copies of the filled chunks into the new buffers. Meshes aren't drawn until the upload
+//! completes; their bounding boxes are drawn in their place, and `Scene::upload_progress` reports
+//! how far along it is.
+//!
+//! Meshes are still hashed and packed on the render thread, since the mesh cache lives there.
+
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+use wgpu::{Buffer, BufferUsages, CommandEncoder, Device, COPY_BUFFER_ALIGNMENT};
+
+use crate::{mesh_cache::MeshRange, types::Scene};
+
+/// The number of staging buffers the worker may be filling at once. More allows it to work ahead,
+/// but uses more memory.
+const MAX_IN_FLIGHT: usize = 2;
+
+/// Bounding boxes drawn in place of meshes, while they're uploading.
+const PLACEHOLDER_COLOR: [f32; 4] = [0.6, 0.6, 0.6, 1.];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Target {
+    Vertex,
+    Index,
+}
+
+/// A staging buffer, and where its data goes.
+struct Chunk {
+    staging: Buffer,
+    target: Target,
+    /// The offset of this chunk's data, in both the source data and the destination buffer.
+    offset: u64,
+}
+
+/// An in-progress upload of the vertex and index buffers.
+pub(crate) struct BufferUpload {
+    /// These replace the current buffers once the upload completes.
+    pub vertex_buf: Buffer,
+    pub index_buf: Buffer,
+    /// The location of each mesh in the new buffers.
+    pub mesh_ranges: Vec<MeshRange>,
+    to_worker: Sender<Chunk>,
+    from_worker: Receiver<Chunk>,
+    /// Bytes of each buffer sent to the worker.
+    vertex_queued: u64,
+    index_queued: u64,
+    /// Bytes copied to the new buffers, of both.
+    copied: u64,
+    chunk_size: u64,
+    in_flight: usize,
+}
+
+impl BufferUpload {
+    /// Start uploading packed vertex and index data, using staging buffers of up to `chunk_size`
+    /// bytes.
+    pub fn new(
+        device: &Device,
+        vertex_data: Vec<u8>,
+        index_data: Vec<u8>,
+        mesh_ranges: Vec<MeshRange>,
+        vertex_usage: BufferUsages,
+        index_usage: BufferUsages,
+        chunk_size: usize,
+    ) -> Self {
+        let vertex_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Vertex buffer"),
+            size: vertex_data.len() as u64,
+            usage: vertex_usage,
+            mapped_at_creation: false,
+        });
+
+        let index_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Index buffer"),
+            size: index_data.len() as u64,
+            usage: index_usage,
+            mapped_at_creation: false,
+        });
+
+        let (to_worker, worker_rx) = mpsc::channel::<Chunk>();
+        let (worker_tx, from_worker) = mpsc::channel();
+
+        // The thread exits once this upload is dropped, along with its sender.
+        thread::spawn(move || {
+            for chunk in worker_rx {
+                let data = match chunk.target {
+                    Target::Vertex => &vertex_data,
+                    Target::Index => &index_data,
+                };
+                let start = chunk.offset as usize;
+                let end = start + chunk.staging.size() as usize;
+
+                chunk
+                    .staging
+                    .slice(..)
+                    .get_mapped_range_mut()
+                    .copy_from_slice(&data[start..end]);
+                chunk.staging.unmap();
+
+                if worker_tx.send(chunk).is_err() {
+                    break;
+                }
+            }
+        });
+
+        // Copies must be aligned.
+        let chunk_size = (chunk_size as u64 / COPY_BUFFER_ALIGNMENT).max(1) * COPY_BUFFER_ALIGNMENT;
+
+        Self {
+            vertex_buf,
+            index_buf,
+            mesh_ranges,
+            to_worker,
+            from_worker,
+            vertex_queued: 0,
+            index_queued: 0,
+            copied: 0,
+            chunk_size,
+            in_flight: 0,
+        }
+    }
+
+    fn total_size(&self) -> u64 {
+        self.vertex_buf.size() + self.index_buf.size()
+    }
+
+    /// The fraction of data uploaded, from 0 to 1.
+    pub fn progress(&self) -> f32 {
+        if self.total_size() == 0 {
+            return 1.;
+        }
+        self.copied as f32 / self.total_size() as f32
+    }
+
+    /// Send empty staging buffers to the worker, up to the in-flight limit.
+    fn queue_chunks(&mut self, device: &Device) {
+        while self.in_flight < MAX_IN_FLIGHT {
+            let (target, offset, remaining) = if self.vertex_queued < self.vertex_buf.size() {
+                let remaining = self.vertex_buf.size() - self.vertex_queued;
+                (Target::Vertex, self.vertex_queued, remaining)
+            } else if self.index_queued < self.index_buf.size() {
+                let remaining = self.index_buf.size() - self.index_queued;
+                (Target::Index, self.index_queued, remaining)
+            } else {
+                return;
+            };
+
+            let size = remaining.min(self.chunk_size);
+
+            let staging = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Upload staging buffer"),
+                size,
+                usage: BufferUsages::MAP_WRITE | BufferUsages::COPY_SRC,
+                mapped_at_creation: true,
+            });
+
+            if self
+                .to_worker
+                .send(Chunk {
+                    staging,
+                    target,
+                    offset,
+                })
+                .is_err()
+            {
+                return;
+            }
+
+            match target {
+                Target::Vertex => self.vertex_queued += size,
+                Target::Index => self.index_queued += size,
+            }
+            self.in_flight += 1;
+        }
+    }
+
+    /// Encode copies of chunks the worker has filled, and queue more. If `block` is true, waits
+    /// for each chunk in flight. Returns true once all data is copied.
+    pub fn advance(&mut self, device: &Device, encoder: &mut CommandEncoder, block: bool) -> bool {
+        self.queue_chunks(device);
+
+        while self.in_flight > 0 {
+            let chunk = if block {
+                self.from_worker.recv().ok()
+            } else {
+                self.from_worker.try_recv().ok()
+            };
+            let Some(chunk) = chunk else {
+                break;
+            };
+
+            let dest = match chunk.target {
+                Target::Vertex => &self.vertex_buf,
+                Target::Index => &self.index_buf,
+            };
+            let size = chunk.staging.size();
+
+            // The staging buffer is kept alive by the encoder until the copy completes.
+            encoder.copy_buffer_to_buffer(&chunk.staging, 0, dest, chunk.offset, size);
+
+            self.copied += size;
+            self.in_flight -= 1;
+        }
+
+        self.copied >= self.total_size()
+    }
+}
+
+impl Scene {
+    /// Draw each entity's bounding box using `debug_draw`, in place of meshes that are still
+    /// uploading. Run this each frame, before rendering.
+    pub(crate) fn draw_upload_placeholders(&mut self) {
+        if self.upload_progress.is_none() {
+            return;
+        }
+
+        for i in 0..self.entities.len() {
+            if let Some(aabb) = self.entity_aabb(i) {
+                self.debug_draw.aabb(aabb.min, aabb.max, PLACEHOLDER_COLOR);
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Additional views of the scene, each from its own camera, eg a picture-in-picture view, or a
+//! rear-view mirror. After the main passes, each of `Scene::extra_views` is rendered, in order, to
+//! its rectangle of the 3D viewport, over the main view. `Scene::inset` is drawn last.
+//!
+//! Views use the same entities, lights, and shadows as the main view. They're rendered without TAA
+//! or occlusion culling. With `RedrawMode::OnChange`, set `EngineUpdates::redraw` after changing
+//! views.
+
+use wgpu::{
+    BindGroup, BindingType, CommandEncoder, Device, FragmentState, RenderPassDescriptor,
+    RenderPipeline, ShaderStages, StoreOp, TextureFormat, TextureUsages, TextureView, VertexState,
+};
+
+use crate::{camera::Camera, layers::RenderLayer};
+
+#[derive(Clone, Debug)]
+pub struct ExtraView {
+    /// Its aspect ratio is set from the view's size when rendering.
+    pub camera: Camera,
+    /// (x, y, width, height), in pixels, from the top left of the window, like the 3D viewport.
+    /// This is clipped to the 3D viewport.
+    pub rect: (u32, u32, u32, u32),
+    /// If set, clear the view to this color, interpreted as `Scene::background_color`, vice
+    /// drawing the scene's background color or gradient.
+    pub background_color: Option<(f32, f32, f32)>,
+    /// Draw entities in the background layer. See the `layers` module.
+    pub background_layer: bool,
+    /// Draw entities in the overlay layer, eg gizmos.
+    pub overlay_layer: bool,
+}
+
+impl ExtraView {
+    pub fn new(camera: Camera, rect: (u32, u32, u32, u32)) -> Self {
+        Self {
+            camera,
+            rect,
+            background_color: None,
+            background_layer: true,
+            overlay_layer: true,
+        }
+    }
+
+    /// If entities in `layer` are drawn in the view.
+    pub(crate) fn draws_layer(&self, layer: RenderLayer) -> bool {
+        match layer {
+            RenderLayer::Background => self.background_layer,
+            RenderLayer::World => true,
+            RenderLayer::Overlay => self.overlay_layer,
+        }
+    }
+
+    /// The camera to render with, with its aspect ratio matching `rect`, as (x, y, width,
+    /// height).
+    pub(crate) fn camera_for(&self, rect: (u32, u32, u32, u32)) -> Camera {
+        let mut result = self.camera.clone();
+        result.aspect = rect.2 as f32 / rect.3 as f32;
+        result.update_proj_mat();
+        result
+    }
+}
+
+/// The texture views are rendered to, and the pass that copies them to the output. Views are
+/// rendered and copied one at a time, so they share the texture.
+pub(crate) struct ViewRenderer {
+    pub width: u32,
+    pub height: u32,
+    /// The same size as the output, since the main pass's depth texture is shared. Only each
+    /// view's rectangle is used.
+    pub view: TextureView,
+    pipeline: RenderPipeline,
+    bind_group: BindGroup,
+}
+
+impl ViewRenderer {
+    /// `format` is that of the main pass's color target; `width` and `height` are its size.
+    pub fn new(device: &Device, format: TextureFormat, width: u32, height: u32) -> Self {
+        let width = width.max(1);
+        let height = height.max(1);
+
+        // The scene cache's blit copies corresponding pixels; we use it within each view's rect.
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("View shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("redraw.wgsl").into()),
+        });
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("View texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&Default::default());
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+            label: Some("View bind group layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            }],
+            label: Some("View bind group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("View pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("View pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: Some("fs_blit"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            width,
+            height,
+            view,
+            pipeline,
+            bind_group,
+        }
+    }
+
+    /// Copy a view's rectangle, as (x, y, width, height) in pixels, to `target`.
+    pub fn encode(
+        &self,
+        encoder: &mut CommandEncoder,
+        target: &TextureView,
+        rect: (u32, u32, u32, u32),
+    ) {
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("View render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        let (x, y, width, height) = rect;
+        rpass.set_scissor_rect(x, y, width, height);
+
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
@@ -1,21 +1,31 @@
 //! Handles window initialization and events, using Winit.
 
+#[cfg(feature = "remote")]
+use std::time::Duration;
 use std::{path::Path, time::Instant};
 
 use image::ImageError;
 use wgpu::TextureViewDescriptor;
 use winit::{
     application::ApplicationHandler,
-    event::{DeviceEvent, DeviceId, WindowEvent},
-    event_loop::ActiveEventLoop,
+    event::{DeviceEvent, DeviceId, ElementState, MouseButton, WindowEvent},
+    event_loop::{ActiveEventLoop, ControlFlow},
     window::{Icon, WindowAttributes, WindowId},
 };
 
 use crate::{
-    system::{process_engine_updates, State},
-    EngineUpdates, Scene, UiLayout,
+    graphics::viewport_3d,
+    lifecycle::LifecycleEvent,
+    redraw::RedrawMode,
+    system::{process_engine_updates, GuiContext, State},
+    EngineUpdates, PresentMode, Scene, UiLayout,
 };
 
+/// With `RedrawMode::Idle` and the remote server running, frames run at least this often, to apply
+/// its requests.
+#[cfg(feature = "remote")]
+const REMOTE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 fn load_icon(path: &Path) -> Result<Icon, ImageError> {
     let (icon_rgba, icon_width, icon_height) = {
         let image = image::open(path)?.into_rgba8();
@@ -30,19 +40,110 @@ impl<T, FRender, FEvent, FGui> State<T, FRender, FEvent, FGui>
 where
     FRender: FnMut(&mut T, &mut Scene, f32) -> EngineUpdates + 'static,
     FEvent: FnMut(&mut T, DeviceEvent, &mut Scene, f32) -> EngineUpdates + 'static,
-    FGui: FnMut(&mut T, &egui::Context, &mut Scene) -> EngineUpdates + 'static,
+    FGui: FnMut(&mut T, &GuiContext, &mut Scene) -> EngineUpdates + 'static,
 {
+    /// Whether the GUI consumes a device event: mouse events while the cursor is over it, and key
+    /// presses while it uses the keyboard, eg for a text field. Key releases always pass through,
+    /// so keys held when the GUI takes focus are released.
+    fn gui_consumes(&self, event: &DeviceEvent) -> bool {
+        #[cfg(feature = "gui")]
+        let wants_keyboard = self
+            .gui
+            .as_ref()
+            .is_some_and(|gui| gui.egui_state.egui_ctx().wants_keyboard_input());
+        #[cfg(not(feature = "gui"))]
+        let wants_keyboard = false;
+
+        match event {
+            DeviceEvent::Key(key) => key.state == ElementState::Pressed && wants_keyboard,
+            DeviceEvent::Button { .. }
+            | DeviceEvent::MouseMotion { .. }
+            | DeviceEvent::MouseWheel { .. } => self.mouse_in_gui,
+            _ => false,
+        }
+    }
+
+    /// Request the frame after one that started at `frame_start`: immediately, or after the
+    /// monitor's next refresh when pacing. With `RedrawMode::Idle`, if the scene isn't changing,
+    /// only when the GUI next needs repainting; input requests frames too.
+    fn schedule_frame(&mut self, frame_start: Instant) {
+        let Some(graphics) = &self.graphics else {
+            return;
+        };
+
+        if graphics.scene.redraw_mode == RedrawMode::Idle && !graphics.frame_needed() {
+            #[cfg(feature = "remote")]
+            let delay = if self.remote.is_some() {
+                graphics.gui_repaint_delay.min(REMOTE_POLL_INTERVAL)
+            } else {
+                graphics.gui_repaint_delay
+            };
+            #[cfg(not(feature = "remote"))]
+            let delay = graphics.gui_repaint_delay;
+
+            self.pacer.clear();
+            if let Some(time) = Instant::now().checked_add(delay) {
+                self.pacer.schedule_at(time);
+            }
+            return;
+        }
+
+        // When pacing, the next redraw is requested in `about_to_wait`, once it's due.
+        let paced = self.graphics_settings.frame_pacing
+            && self.graphics_settings.present_mode == PresentMode::NoVsync;
+        if !paced {
+            self.pacer.clear();
+        }
+        if !paced || !self.pacer.schedule(frame_start) {
+            self.window.as_ref().unwrap().request_redraw();
+        }
+    }
+
+    /// With `RedrawMode::Idle`, run a frame, eg after input. Otherwise, frames already run
+    /// continuously.
+    fn request_idle_frame(&self) {
+        if self
+            .graphics
+            .as_ref()
+            .is_some_and(|g| g.scene.redraw_mode == RedrawMode::Idle)
+        {
+            if let Some(window) = &self.window {
+                window.request_redraw();
+            }
+        }
+    }
+
     fn redraw(&mut self) {
         if self.render.is_none() || self.graphics.is_none() {
             return;
         }
 
-        let sys = &self.render.as_ref().unwrap();
         let graphics = &mut self.graphics.as_mut().unwrap();
 
         let now = Instant::now();
         self.dt = now - self.last_render_time;
         self.last_render_time = now;
+        graphics.scene.frame_stats.frame_time = self.dt;
+
+        // Loads finished since the last frame are added to the scene before any handler runs, so
+        // they all see the same `load_events`.
+        {
+            let sys = self.render.as_ref().unwrap();
+            let updates = graphics.loader.poll(&mut graphics.scene);
+            process_engine_updates(&updates, graphics, &sys.device, &sys.queue);
+        }
+
+        // Fixed steps run first, so the render handler sees the latest simulation state.
+        if let Some(fixed) = &mut self.fixed_update {
+            let sys = self.render.as_ref().unwrap();
+            let step_secs = fixed.step_secs();
+
+            for _ in 0..fixed.advance(self.dt) {
+                let updates = (fixed.handler)(&mut self.user_state, &mut graphics.scene, step_secs);
+                process_engine_updates(&updates, graphics, &sys.device, &sys.queue);
+            }
+            graphics.scene.frame_stats.fixed_step_alpha = fixed.alpha();
+        }
 
         let dt_secs = self.dt.as_secs() as f32 + self.dt.subsec_micros() as f32 / 1_000_000.;
         let updates_render =
@@ -55,6 +156,30 @@ where
             &self.render.as_ref().unwrap().queue,
         );
 
+        #[cfg(feature = "remote")]
+        if let Some(remote) = &mut self.remote {
+            let sys = self.render.as_ref().unwrap();
+            for updates in remote.poll(&mut graphics.scene) {
+                process_engine_updates(&updates, graphics, &sys.device, &sys.queue);
+            }
+            graphics.capture_requested = remote.screenshot_pending();
+        }
+
+        // Settings from any handler; the GUI's are applied on the next frame.
+        if let Some(settings) = graphics.pending_settings.take() {
+            let sys = self.render.as_mut().unwrap();
+
+            if settings.present_mode != self.graphics_settings.present_mode {
+                sys.surface_cfg.present_mode = settings.present_mode.to_wgpu();
+                sys.surface.configure(&sys.device, &sys.surface_cfg);
+            }
+
+            graphics.apply_settings(&sys.device, &sys.queue, &sys.surface_cfg, settings.clone());
+            self.graphics_settings = settings;
+        }
+
+        let sys = &self.render.as_ref().unwrap();
+
         // Note that the GUI handler can also modify entities, but
         // we do that in the `init_graphics` module.
 
@@ -65,10 +190,24 @@ where
                     .texture
                     .create_view(&TextureViewDescriptor::default());
 
-                let layout = self.ui_settings.layout;
-
+                #[cfg(feature = "gui")]
+                let resize_required = graphics.render(
+                    self.gui.as_mut().unwrap(),
+                    self.window.as_ref().unwrap(),
+                    &mut self.gui_handler,
+                    &mut self.user_state,
+                    output_frame,
+                    &surface_texture,
+                    &sys.device,
+                    &sys.queue,
+                    self.dt,
+                    sys.surface_cfg.width,
+                    sys.surface_cfg.height,
+                    &mut self.ui_settings,
+                    &self.input_settings,
+                );
+                #[cfg(not(feature = "gui"))]
                 let resize_required = graphics.render(
-                    &mut self.gui.as_mut().unwrap(),
                     output_frame,
                     &surface_texture,
                     &sys.device,
@@ -78,18 +217,35 @@ where
                     sys.surface_cfg.height,
                     &mut self.ui_settings,
                     &self.input_settings,
-                    &mut self.gui_handler,
-                    &mut self.user_state,
-                    layout,
                 );
 
+                // Shortcuts triggered, and window and load events since the last frame, have been
+                // seen by all handlers.
+                graphics.scene.shortcuts.clear_triggered();
+                graphics.scene.window_state.events.clear();
+                graphics.scene.load_events.clear();
+
+                #[cfg(feature = "remote")]
+                if let (Some(remote), Some(image)) = (&mut self.remote, graphics.captured.take()) {
+                    remote.send_screenshot(image);
+                }
+
                 if resize_required {
                     println!("Resize requested from GUI");
                     self.resize(sys.size);
                 }
             }
             // This occurs when minimized.
-            Err(_e) => (),
+            Err(_e) => {
+                #[cfg(feature = "remote")]
+                if let Some(remote) = &mut self.remote {
+                    remote.fail_screenshots("The frame couldn't be rendered, eg while minimized");
+                }
+
+                graphics.scene.shortcuts.clear_triggered();
+                graphics.scene.window_state.events.clear();
+                graphics.scene.load_events.clear();
+            }
         }
     }
 }
@@ -98,7 +254,7 @@ impl<T, FRender, FEvent, FGui> ApplicationHandler for State<T, FRender, FEvent,
 where
     FRender: FnMut(&mut T, &mut Scene, f32) -> EngineUpdates + 'static,
     FEvent: FnMut(&mut T, DeviceEvent, &mut Scene, f32) -> EngineUpdates + 'static,
-    FGui: FnMut(&mut T, &egui::Context, &mut Scene) -> EngineUpdates + 'static,
+    FGui: FnMut(&mut T, &GuiContext, &mut Scene) -> EngineUpdates + 'static,
 {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         println!("Engine resumed; rebuilding window, render, and graphics state.");
@@ -140,8 +296,9 @@ where
             return;
         }
 
+        let gui_size = self.gui_size();
         let graphics = &mut self.graphics.as_mut().unwrap();
-        let gui = &mut self.gui.as_mut().unwrap();
+        let window = self.window.as_ref().unwrap();
 
         //     if let Some(gui) = self.gui.as_mut() {
         //     if let Some(graphics) = self.graphics.as_mut() {
@@ -150,42 +307,97 @@ where
         //     }
         // }
 
-        let window = &graphics.window;
-        let _ = gui.egui_state.on_window_event(window, &event);
+        #[cfg(feature = "gui")]
+        {
+            let gui = self.gui.as_mut().unwrap();
+            let _ = gui.egui_state.on_window_event(window, &event);
+        }
+
+        // With `RedrawMode::Idle`, any window input may change the GUI or scene.
+        if graphics.scene.redraw_mode == RedrawMode::Idle
+            && !matches!(event, WindowEvent::RedrawRequested)
+        {
+            window.request_redraw();
+        }
 
         match event {
             WindowEvent::RedrawRequested => {
+                let frame_start = Instant::now();
                 self.redraw();
-                self.graphics.as_ref().unwrap().window.request_redraw();
+                self.schedule_frame(frame_start);
             }
             WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = (position.x as f32, position.y as f32);
+
+                if graphics.scene.slice_plane.as_ref().is_some_and(|p| p.dragging()) {
+                    let size = window.inner_size();
+                    let viewport =
+                        viewport_3d(gui_size, size.width, size.height, &self.ui_settings);
+
+                    let updates = graphics.scene.drag_slice_plane(viewport, self.cursor);
+                    let render = self.render.as_ref().unwrap();
+                    process_engine_updates(&updates, graphics, &render.device, &render.queue);
+                }
+
                 let mouse_in_gui = match self.ui_settings.layout {
-                    UiLayout::Left => position.x < gui.size as f64,
+                    UiLayout::Left => position.x < gui_size as f64,
                     UiLayout::Right => {
-                        position.x > window.inner_size().width as f64 - gui.size as f64
+                        position.x > window.inner_size().width as f64 - gui_size as f64
                     }
-                    UiLayout::Top => position.y < gui.size as f64,
+                    UiLayout::Top => position.y < gui_size as f64,
                     UiLayout::Bottom => {
-                        position.y > window.inner_size().height as f64 - gui.size as f64
+                        position.y > window.inner_size().height as f64 - gui_size as f64
                     }
                 };
                 if mouse_in_gui {
-                    gui.mouse_in_gui = true;
+                    self.mouse_in_gui = true;
 
                     // We reset the inputs, since otherwise a held key that
                     // doesn't get the reset command will continue to execute.
                     self.graphics.as_mut().unwrap().inputs_commanded = Default::default();
                 } else {
-                    gui.mouse_in_gui = false;
+                    self.mouse_in_gui = false;
                 }
             }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if !self.mouse_in_gui => {
+                let size = window.inner_size();
+                let viewport =
+                    viewport_3d(gui_size, size.width, size.height, &self.ui_settings);
+
+                // Dragging the slice plane takes precedence over picking, and free look.
+                if graphics.scene.grab_slice_plane(viewport, self.cursor) {
+                    graphics.inputs_commanded.free_look = false;
+                } else {
+                    graphics.scene.pick_measure_point(viewport, self.cursor);
+                }
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } => {
+                graphics.scene.release_slice_plane();
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                graphics.scene.shortcuts.set_modifiers(modifiers.state());
+            }
             WindowEvent::CloseRequested => {
                 event_loop.exit();
             }
             WindowEvent::Resized(physical_size) => {
                 self.resize(physical_size);
+
+                let graphics = self.graphics.as_mut().unwrap();
                 // Prevents inadvertent mouse-click-activated free-look.
-                self.graphics.as_mut().unwrap().inputs_commanded.free_look = false;
+                graphics.inputs_commanded.free_look = false;
+                graphics.scene.window_state.push(LifecycleEvent::Resized(
+                    physical_size.width,
+                    physical_size.height,
+                ));
             }
             // If the window scale changes, update the renderer size, and camera aspect ratio.
             WindowEvent::ScaleFactorChanged {
@@ -195,21 +407,37 @@ where
             } => {
                 // Note: This appears to not come up, nor is it required. (Oct 2024)
                 println!("Scale factor changed");
+                // Eg the window moved to a different monitor.
+                graphics.scene.frame_stats.display_hz = self.pacer.update_monitor(window);
             }
             // If the window is being moved, disable mouse inputs, eg so click+drag
             // doesn't cause a drag when moving the window using the mouse.
-            WindowEvent::Moved(_) => {
-                gui.mouse_in_gui = true;
+            WindowEvent::Moved(position) => {
+                self.mouse_in_gui = true;
                 // Prevents inadvertent mouse-click-activated free-look after moving the window.
-                self.graphics.as_mut().unwrap().inputs_commanded.free_look = false;
+                graphics.inputs_commanded.free_look = false;
+                graphics
+                    .scene
+                    .window_state
+                    .push(LifecycleEvent::Moved(position.x, position.y));
+                // The window may be on a different monitor, with a different refresh rate.
+                graphics.scene.frame_stats.display_hz = self.pacer.update_monitor(window);
             }
-            WindowEvent::Occluded(_) => {
+            WindowEvent::Occluded(occluded) => {
                 // Prevents inadvertent mouse-click-activated free-look after minimizing.
-                self.graphics.as_mut().unwrap().inputs_commanded.free_look = false;
+                graphics.inputs_commanded.free_look = false;
+                graphics
+                    .scene
+                    .window_state
+                    .push(LifecycleEvent::Occluded(occluded));
             }
-            WindowEvent::Focused(_) => {
+            WindowEvent::Focused(focused) => {
                 // Eg clicking the tile bar icon.
-                self.graphics.as_mut().unwrap().inputs_commanded.free_look = false;
+                graphics.inputs_commanded.free_look = false;
+                graphics
+                    .scene
+                    .window_state
+                    .push(LifecycleEvent::Focused(focused));
             }
             WindowEvent::CursorLeft { device_id: _ } => {
                 // todo: Not working?
@@ -229,27 +457,73 @@ where
             return;
         }
 
+        // Events pass through each stage in order, until one consumes them: the GUI, shortcuts,
+        // then the application's event handler. Camera controls see events before the handler,
+        // but don't consume them.
+        if self.gui_consumes(&event) {
+            return;
+        }
+
         let render = &self.render.as_ref().unwrap();
         let graphics = &mut self.graphics.as_mut().unwrap();
-        let gui = &mut self.gui.as_mut().unwrap();
 
-        if !gui.mouse_in_gui {
-            let dt_secs = self.dt.as_secs() as f32 + self.dt.subsec_micros() as f32 / 1_000_000.;
+        if let DeviceEvent::Key(key) = &event {
+            if graphics.scene.shortcuts.handle_key(key) {
+                // Handlers see triggered shortcuts next frame.
+                self.request_idle_frame();
+                return;
+            }
+        }
 
-            let updates_event = (self.event_handler)(
-                &mut self.user_state,
-                event.clone(),
-                &mut graphics.scene,
-                dt_secs,
+        // Mouse input drags the slice plane instead of the camera.
+        let dragging = graphics.scene.slice_plane.as_ref().is_some_and(|p| p.dragging())
+            && matches!(
+                event,
+                DeviceEvent::Button { .. } | DeviceEvent::MouseMotion { .. }
             );
 
-            process_engine_updates(&updates_event, graphics, &render.device, &render.queue);
+        if !dragging {
+            graphics.handle_input(event.clone(), &self.input_settings);
+        }
+
+        let dt_secs = self.dt.as_secs() as f32 + self.dt.subsec_micros() as f32 / 1_000_000.;
 
-            graphics.handle_input(event, &self.input_settings);
+        let updates_event =
+            (self.event_handler)(&mut self.user_state, event, &mut graphics.scene, dt_secs);
+
+        process_engine_updates(&updates_event, graphics, &render.device, &render.queue);
+
+        if graphics.frame_needed() {
+            self.request_idle_frame();
         }
     }
 
-    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {}
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        if self.graphics.as_ref().is_some_and(|g| g.exit_requested) {
+            event_loop.exit();
+        }
+
+        // Sleep until the next scheduled frame, if there is one. With `RedrawMode::Idle`, sleep
+        // until input otherwise.
+        let idle = self
+            .graphics
+            .as_ref()
+            .is_some_and(|g| g.scene.redraw_mode == RedrawMode::Idle);
+
+        let control_flow = if self.pacer.frame_due() {
+            if let Some(window) = &self.window {
+                window.request_redraw();
+            }
+            ControlFlow::Poll
+        } else {
+            match self.pacer.wait_until() {
+                Some(time) => ControlFlow::WaitUntil(time),
+                None if idle => ControlFlow::Wait,
+                None => ControlFlow::Poll,
+            }
+        };
+        event_loop.set_control_flow(control_flow);
+    }
 
     fn exiting(&mut self, _event_loop: &ActiveEventLoop) {}
 }
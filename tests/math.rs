@@ -0,0 +1,200 @@
+//! Tests of the math operations in the `math` module, that `lin_alg` doesn't provide.
+
+use std::f32::consts::TAU;
+
+use graphics::{
+    math::{
+        decompose, from_vec_direction, mat3_to_quaternion, slerp, to_axis_angle, Mat4, Quaternion,
+        Vec3,
+    },
+    Transform,
+};
+
+const EPS: f32 = 0.0001;
+
+/// A rotation by `angle` radians around the Y axis.
+fn rotation_y(angle: f32) -> Quaternion {
+    Quaternion::new((angle / 2.).cos(), 0., (angle / 2.).sin(), 0.)
+}
+
+/// True if the quaternions are the same rotation; a quaternion and its negative are.
+fn same_rotation(a: Quaternion, b: Quaternion) -> bool {
+    let dot = a.w * b.w + a.x * b.x + a.y * b.y + a.z * b.z;
+    (dot.abs() - 1.).abs() < EPS
+}
+
+fn same_vec(a: Vec3, b: Vec3) -> bool {
+    (a - b).magnitude() < EPS
+}
+
+#[test]
+fn slerp_endpoints() {
+    let a = rotation_y(0.3);
+    let b = rotation_y(2.);
+
+    assert!(same_rotation(slerp(a, b, 0.), a));
+    assert!(same_rotation(slerp(a, b, 1.), b));
+}
+
+#[test]
+fn slerp_constant_speed() {
+    let a = Quaternion::new_identity();
+    let b = rotation_y(TAU / 4.);
+
+    assert!(same_rotation(slerp(a, b, 0.5), rotation_y(TAU / 8.)));
+    assert!(same_rotation(slerp(a, b, 0.25), rotation_y(TAU / 16.)));
+}
+
+#[test]
+fn slerp_shorter_path() {
+    let a = Quaternion::new_identity();
+    let b = rotation_y(TAU / 4.);
+    let b_negated = Quaternion::new(-b.w, -b.x, -b.y, -b.z);
+
+    assert!(same_rotation(
+        slerp(a, b_negated, 0.5),
+        rotation_y(TAU / 8.)
+    ));
+}
+
+#[test]
+fn slerp_nearly_equal() {
+    let a = rotation_y(1.);
+    let b = rotation_y(1.0001);
+
+    let result = slerp(a, b, 0.5);
+    assert!((result.magnitude() - 1.).abs() < EPS);
+    assert!(same_rotation(result, a));
+}
+
+#[test]
+fn direction_forward_is_identity() {
+    let result = from_vec_direction(Vec3::new(0., 0., 2.), Vec3::new(0., 1., 0.));
+    assert!(same_rotation(result, Quaternion::new_identity()));
+}
+
+#[test]
+fn direction_points_forward_along_dir() {
+    let up = Vec3::new(0., 1., 0.);
+
+    let result = from_vec_direction(Vec3::new(1., 0., 0.), up);
+    assert!(same_rotation(result, rotation_y(TAU / 4.)));
+
+    let dir = Vec3::new(1., 2., -3.);
+    let result = from_vec_direction(dir, up);
+    assert!(same_vec(
+        result.rotate_vec(Vec3::new(0., 0., 1.)),
+        dir.to_normalized()
+    ));
+
+    // Up stays in the plane of the direction and the requested up vector, and above the horizon.
+    let result_up = result.rotate_vec(up);
+    assert!(result_up.dot(dir.cross(up)).abs() < EPS);
+    assert!(result_up.y > 0.);
+}
+
+#[test]
+fn direction_parallel_to_up() {
+    let up = Vec3::new(0., 1., 0.);
+
+    for dir in [up, up * -1.] {
+        let result = from_vec_direction(dir, up);
+        assert!((result.magnitude() - 1.).abs() < EPS);
+        assert!(same_vec(result.rotate_vec(Vec3::new(0., 0., 1.)), dir));
+    }
+}
+
+/// A model matrix, as `translation * rotation * scale`.
+fn model_mat(translation: Vec3, rotation: Quaternion, scale: Vec3) -> Mat4 {
+    let mut scale_mat = Mat4::new_identity();
+    scale_mat.data[0] = scale.x;
+    scale_mat.data[5] = scale.y;
+    scale_mat.data[10] = scale.z;
+
+    Mat4::new_translation(translation) * rotation.to_matrix() * scale_mat
+}
+
+#[test]
+fn decompose_round_trip() {
+    let translation = Vec3::new(1., -2., 3.);
+    let rotation = from_vec_direction(Vec3::new(1., 2., -3.), Vec3::new(0., 1., 0.));
+    let scale = Vec3::new(2., 0.5, 3.);
+
+    let (t, r, s) = decompose(&model_mat(translation, rotation, scale));
+
+    assert!(same_vec(t, translation));
+    assert!(same_rotation(r, rotation));
+    assert!(same_vec(s, scale));
+}
+
+#[test]
+fn decompose_reflection() {
+    let rotation = rotation_y(1.);
+    let scale = Vec3::new(1., -1., 1.);
+
+    let (_, r, s) = decompose(&model_mat(Vec3::new_zero(), rotation, scale));
+
+    // Reflections are represented with a negative X scale.
+    assert!(s.x < 0. && s.y > 0. && s.z > 0.);
+    let rebuilt = model_mat(Vec3::new_zero(), r, s);
+    let original = model_mat(Vec3::new_zero(), rotation, scale);
+    for (a, b) in rebuilt.data.iter().zip(original.data) {
+        assert!((a - b).abs() < EPS);
+    }
+}
+
+#[test]
+fn decompose_zero_scale() {
+    let (_, r, s) = decompose(&model_mat(
+        Vec3::new_zero(),
+        rotation_y(1.),
+        Vec3::new(1., 0., 1.),
+    ));
+
+    assert!(same_rotation(r, Quaternion::new_identity()));
+    assert!(s.y.abs() < EPS);
+}
+
+#[test]
+fn mat3_rotation() {
+    for rotation in [
+        Quaternion::new_identity(),
+        rotation_y(TAU / 2.),
+        from_vec_direction(Vec3::new(-1., 0.5, -2.), Vec3::new(0., 1., 0.)),
+        from_vec_direction(Vec3::new(0., -1., 0.), Vec3::new(1., 0., 0.)),
+    ] {
+        assert!(same_rotation(
+            mat3_to_quaternion(&rotation.to_matrix3()),
+            rotation
+        ));
+    }
+}
+
+#[test]
+fn axis_angle_round_trip() {
+    let axis = Vec3::new(1., 2., 2.).to_normalized();
+
+    let (result_axis, angle) = to_axis_angle(Quaternion::from_axis_angle(axis, 1.2));
+    assert!(same_vec(result_axis, axis));
+    assert!((angle - 1.2).abs() < EPS);
+
+    // The negated quaternion is the same rotation, returned the shorter way.
+    let (result_axis, angle) = to_axis_angle(Quaternion::from_axis_angle(axis, TAU - 1.2));
+    assert!(same_vec(result_axis, axis * -1.));
+    assert!((angle - 1.2).abs() < EPS);
+
+    let (_, angle) = to_axis_angle(Quaternion::new_identity());
+    assert!(angle.abs() < EPS);
+}
+
+#[test]
+fn transform_uniform_scale() {
+    let rotation = rotation_y(0.5);
+    let mat = model_mat(Vec3::new(4., 5., 6.), rotation, Vec3::new(2., 2., 2.));
+
+    let transform = Transform::from_matrix(&mat);
+
+    assert!(same_vec(transform.position, Vec3::new(4., 5., 6.)));
+    assert!(same_rotation(transform.orientation, rotation));
+    assert!((transform.scale - 2.).abs() < EPS);
+}
@@ -0,0 +1,133 @@
+//! Golden-image tests of rendering. These render scenes headless, and compare the output against
+//! reference images in `tests/snapshots`, eg to catch regressions from shader or layout changes.
+//! They require a GPU adapter, so are ignored by default; run them with
+//! `cargo test --test snapshots -- --ignored`. See the `snapshot` module for how reference images
+//! are created and updated.
+
+use std::path::PathBuf;
+
+use graphics::{
+    math::{Quaternion, Vec3},
+    snapshot::{check_snapshot, DiffTolerance},
+    Entity, GraphicsSettings, HeadlessRenderer, LightType, Lighting, Mesh, PointLight, Scene,
+};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+
+/// The camera is 6 units behind the origin, facing it.
+fn scene(meshes: Vec<Mesh>, entities: Vec<Entity>, lighting: Lighting) -> Scene {
+    let mut result = Scene {
+        meshes,
+        entities,
+        lighting,
+        background_color: (0.1, 0.1, 0.1),
+        ..Default::default()
+    };
+    result.camera.position = Vec3::new(0., 0., -6.);
+
+    result
+}
+
+fn light(position: Vec3, color: [f32; 4], intensity: f32) -> PointLight {
+    PointLight {
+        type_: LightType::Omnidirectional,
+        position,
+        diffuse_color: color,
+        specular_color: color,
+        diffuse_intensity: intensity,
+        specular_intensity: intensity,
+        casts_shadow: false,
+    }
+}
+
+/// Render a scene, and compare it against the reference image named `name`.
+fn check(scene: Scene, name: &str) {
+    let mut renderer = HeadlessRenderer::new(scene, &GraphicsSettings::default(), WIDTH, HEIGHT)
+        .unwrap_or_else(|e| panic!("Unable to render snapshot {name}: {e}"));
+
+    let image = renderer.render(0.).unwrap();
+
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(format!("{name}.png"));
+
+    if let Err(e) = check_snapshot(&image, &path, &DiffTolerance::default()) {
+        panic!("{e}");
+    }
+}
+
+#[test]
+#[ignore = "requires a GPU adapter"]
+fn primitive_meshes() {
+    let meshes = vec![
+        Mesh::new_box(1., 1., 1.),
+        Mesh::new_sphere(0.6, 20, 20),
+        Mesh::new_cylinder(1.2, 0.5, 16),
+        Mesh::new_tetrahedron(1.2),
+        Mesh::new_pyramid(1.2, 0.6, 4),
+        Mesh::new_arrow(1.2, 0.1, 12),
+    ];
+
+    // A 3x2 grid, rotated so each mesh's 3D shape is visible.
+    let orientation = Quaternion::from_axis_angle(Vec3::new(1., 1., 0.).to_normalized(), 0.6);
+    let entities = (0..meshes.len())
+        .map(|i| {
+            let posit = Vec3::new((i % 3) as f32 * 2.5 - 2.5, 1.2 - (i / 3) as f32 * 2.4, 0.);
+            Entity::new(i, posit, orientation, 1., (0.8, 0.8, 0.8), 0.5)
+        })
+        .collect();
+
+    let lighting = Lighting {
+        point_lights: vec![light(Vec3::new(-3., 4., -5.), [1., 1., 1., 0.5], 100.)],
+        ..Default::default()
+    };
+
+    check(scene(meshes, entities, lighting), "primitive_meshes");
+}
+
+#[test]
+#[ignore = "requires a GPU adapter"]
+fn colored_point_lights() {
+    let meshes = vec![Mesh::new_sphere(1.5, 32, 32)];
+    let entities = vec![Entity::new(
+        0,
+        Vec3::new_zero(),
+        Quaternion::new_identity(),
+        1.,
+        (1., 1., 1.),
+        0.8,
+    )];
+
+    let lighting = Lighting {
+        point_lights: vec![
+            light(Vec3::new(-4., 1., -3.), [1., 0.2, 0.2, 0.5], 60.),
+            light(Vec3::new(4., 1., -3.), [0.2, 0.3, 1., 0.5], 60.),
+        ],
+        ..Default::default()
+    };
+
+    check(scene(meshes, entities, lighting), "colored_point_lights");
+}
+
+#[test]
+#[ignore = "requires a GPU adapter"]
+fn ambient_only() {
+    let meshes = vec![Mesh::new_box(2., 2., 2.)];
+    let entities = vec![Entity::new(
+        0,
+        Vec3::new_zero(),
+        Quaternion::from_axis_angle(Vec3::new(0., 1., 0.), 0.5),
+        1.,
+        (0.2, 0.6, 1.),
+        0.,
+    )];
+
+    let lighting = Lighting {
+        ambient_intensity: 0.6,
+        point_lights: Vec::new(),
+        ..Default::default()
+    };
+
+    check(scene(meshes, entities, lighting), "ambient_only");
+}